@@ -0,0 +1,110 @@
+//! # Model A/B Comparisons
+//!
+//! This module renders `nishiogi ab`, which answers the same question with several models
+//! to help users pick a default model for their repository. The agents of the runs share a
+//! [`CommandCache`](crate::tools::CommandCache), so a file read by one model's plan is not
+//! read again for the next, and each model only pays for its own prompts.
+//!
+//! The answers are printed side by side, followed by a table of the latency and tokens of
+//! every model.
+
+use std::time::Duration;
+
+use crate::transcript::columns;
+
+/// One model's run of the question.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelRun {
+    /// The model's ID.
+    pub model: String,
+    /// The answer, or the error that stopped the run.
+    pub answer: Result<String, String>,
+    /// How long the run took.
+    pub latency: Duration,
+    /// Tokens the run used.
+    pub tokens_used: u64,
+    /// Prompt tokens served from the provider's cache.
+    pub cached_tokens: u64,
+}
+
+/// Renders `runs` as columns fitting in `width` characters, followed by their costs.
+pub fn render(runs: &[ModelRun], width: usize) -> String {
+    let separators = 3 * runs.len().saturating_sub(1);
+    let column = (width.saturating_sub(separators) / runs.len().max(1)).max(20);
+
+    let models: Vec<&str> = runs.iter().map(|run| run.model.as_str()).collect();
+    let mut out = columns(&models, column);
+    out.push_str(&vec!["-".repeat(column); runs.len()].join("-+-"));
+    out.push('\n');
+    let answers: Vec<String> = runs
+        .iter()
+        .map(|run| match &run.answer {
+            Ok(answer) => answer.clone(),
+            Err(err) => format!("(failed: {err})"),
+        })
+        .collect();
+    let answers: Vec<&str> = answers.iter().map(String::as_str).collect();
+    out.push_str(&columns(&answers, column));
+
+    let name_width = models
+        .iter()
+        .map(|model| model.len())
+        .max()
+        .unwrap_or(0)
+        .max(5);
+    out.push_str(&format!(
+        "\n{:<name_width$}  {:>9}  {:>8}  {:>8}\n",
+        "Model", "Latency", "Tokens", "Cached"
+    ));
+    for run in runs {
+        out.push_str(&format!(
+            "{:<name_width$}  {:>8.1}s  {:>8}  {:>8}\n",
+            run.model,
+            run.latency.as_secs_f64(),
+            run.tokens_used,
+            run.cached_tokens
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let runs = [
+            ModelRun {
+                model: "gpt-4".to_string(),
+                answer: Ok("The entry point is main in src/main.rs.".to_string()),
+                latency: Duration::from_millis(12_340),
+                tokens_used: 4210,
+                cached_tokens: 1024,
+            },
+            ModelRun {
+                model: "claude-3".to_string(),
+                answer: Err("model claude-3 is not allowed".to_string()),
+                latency: Duration::from_millis(800),
+                tokens_used: 0,
+                cached_tokens: 0,
+            },
+        ];
+        let out = render(&runs, 63);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], format!("{:<30} | claude-3", "gpt-4"));
+        assert_eq!(lines[1], format!("{}-+-{}", "-".repeat(30), "-".repeat(30)));
+        assert_eq!(
+            lines[2],
+            format!(
+                "{:<30} | (failed: model claude-3 is not",
+                "The entry point is main in"
+            )
+        );
+        assert!(out.ends_with(
+            "Model       Latency    Tokens    Cached\n\
+             gpt-4         12.3s      4210      1024\n\
+             claude-3       0.8s         0         0\n"
+        ));
+    }
+}
@@ -5,14 +5,29 @@
 //!
 //! ## Architecture
 //!
-//! The Agent follows a six-step workflow:
+//! The Agent follows a five-step workflow:
 //!
-//! 1. **Intent Extraction**: Analyze user's question to determine what they're asking
-//! 2. **Planning**: Create a plan of action to answer the question
-//! 3. **Command Execution**: Run commands (currently supports `tree` and `show_file`)
-//! 4. **Answer Generation**: Create an answer based on command results
-//! 5. **Review**: Evaluate if the answer adequately addresses the question
-//! 6. **Iteration**: If review is unsuccessful, repeat the process; otherwise return the answer
+//! 1. **Planning**: A single structured call classifies the question (see
+//!    [`crate::intent`]), which selects the answering preset, identifies the paths it
+//!    concerns, and creates a plan of action to answer it
+//! 2. **Command Execution**: Run the planned commands through the [tool
+//!    registry](crate::tools) (`tree`, `show_file`, `search`, `grep`, `coverage`, `blame`,
+//!    `owners`, `licenses`, the build configuration outlines, the `regex_test` and
+//!    `glob_expand` checks, the sandboxed `run` and `run_rust`, and any tool added with
+//!    [`Agent::with_tool`]), then let the planner request follow-up commands based on their
+//!    results. Questions naming a file location such as `src/main.rs:42`
+//!    always get a `blame` step, so answers can explain why the code is the way it is
+//! 3. **Answer Generation**: Create an answer based on command results
+//! 4. **Review**: Evaluate if the answer adequately addresses the question, using a
+//!    configurable [`Reviewer`] strategy
+//! 5. **Iteration**: If review is unsuccessful, repeat the process; otherwise return the answer
+//!
+//! The agent adapts to the [capabilities](crate::provider::Capabilities) of its model,
+//! recorded when it is created: the plan is requested in JSON mode where the provider has
+//! one, and command results are fitted to the model's context window.
+//!
+//! The wall-clock time of every step, and how much of it was spent waiting on the provider,
+//! is available afterwards from [`Agent::latency_report`].
 //!
 //! ## Error Handling
 //!
@@ -20,124 +35,85 @@
 //! allowing for graceful recovery and detailed error reporting.
 
 use std::{
-    error::Error,
-    fmt,
+    collections::HashMap,
+    io::{self, Write},
     path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
+    thread,
+    time::{Duration, Instant},
 };
 
+use async_trait::async_trait;
+use regex::Regex;
+
 use crate::{
-    github_copilot_client::{CopilotClient, CopilotError, Message},
-    show_file::{read_file_content, FileReadError},
-    tree::generate_tree,
+    blame::find_line_references,
+    citation::{anchor_excerpt, anchor_file, anchor_snippets, resolve, Chunk, CITATION_PROMPT},
+    config::repo_root,
+    diff::{render_diff, similarity, unified_diff},
+    elide::{elide, fit_results},
+    follow_up,
+    github_copilot_client::{self, ChatResponse, CopilotError, Message},
+    glossary::Glossary,
+    intent::{Intent, PlanReply, QuestionType, INTENT_PROMPT},
+    memory::Preferences,
+    org_policy,
+    overrides::{ContextOverride, Exclusions},
+    plan::{parse_plan, stages, PlanStep},
+    planner::{ContextMode, PlannerConfig},
+    policy::{tool_name, Permission, Policy},
+    provider::{Capabilities, LlmProvider, ProviderKind},
+    relevance::{keywords, score, select, Candidate},
+    report::{ContextReport, FailureReport, LatencyReport, Step, StepTiming, UnreadableFile},
+    review::{precheck, LlmReviewer, ReviewInput, ReviewModel, Reviewer, Verdict},
+    root_sandbox::RootSandbox,
+    session::{
+        sha256_hex, FileProvenance, Provenance, SessionEntry, SessionRecord, Staleness, ToolCall,
+    },
+    show_file::{reads_range, split_range},
+    style::AnswerStyle,
+    symbols::SymbolIndex,
+    tokens::{count_tokens, prompt_cost},
+    tools::{resolve_path, CommandCache, Tool, ToolContext, ToolOutput, ToolRegistry},
+    unknown::UNKNOWN_PROMPT,
+    usage::{find_usage_examples, usage_subject, MAX_USAGE_EXAMPLES},
 };
 
-const MAX_ITERATIONS: usize = 3;
-
-/// Errors that can occur during agent operations
-#[derive(Debug)]
-pub enum AgentError {
-    // Intent errors
-    IntentExtractionFailed,
-    MalformedIntentResponse,
-    EmptyIntentResponse,
-
-    // Planning errors
-    PlanningFailed,
-    EmptyPlan,
-    InvalidPlanFormat,
-
-    // Command errors
-    UnknownCommand(String), // Keep string for command name
-    PathNotFound(PathBuf),  // Use PathBuf instead of String
-    PathIsDirectory(PathBuf),
-    CommandExecutionFailed,
-
-    // Answer errors
-    AnswerGenerationFailed,
-    EmptyAnswerResponse,
-
-    // Review errors
-    ReviewFailed,
-    NoAnswerToReview,
-    MaxIterationsReached,
-
-    // External errors
-    CopilotError(CopilotError),
-    IoError(std::io::Error),
-
-    // Fallback for truly custom errors
-    Other(String),
-}
-
-impl fmt::Display for AgentError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            // Intent errors
-            AgentError::IntentExtractionFailed => {
-                write!(f, "Failed to extract intent from question")
-            }
-            AgentError::MalformedIntentResponse => {
-                write!(f, "Intent extraction produced malformed response")
-            }
-            AgentError::EmptyIntentResponse => {
-                write!(f, "Intent extraction produced empty response")
-            }
-
-            // Planning errors
-            AgentError::PlanningFailed => write!(f, "Failed to create execution plan"),
-            AgentError::EmptyPlan => write!(f, "Generated plan contains no commands"),
-            AgentError::InvalidPlanFormat => write!(f, "Generated plan has invalid format"),
+pub use crate::error::AgentError;
 
-            // Command errors
-            AgentError::UnknownCommand(cmd) => write!(f, "Unknown command: {cmd}"),
-            AgentError::PathNotFound(path) => write!(f, "Path does not exist: {}", path.display()),
-            AgentError::PathIsDirectory(path) => {
-                write!(f, "Path is a directory: {}", path.display())
-            }
-            AgentError::CommandExecutionFailed => write!(f, "Command execution failed"),
+/// Model used unless another one is requested, with the Copilot provider
+pub const DEFAULT_MODEL: &str = github_copilot_client::DEFAULT_MODEL;
 
-            // Answer errors
-            AgentError::AnswerGenerationFailed => write!(f, "Failed to generate answer"),
-            AgentError::EmptyAnswerResponse => write!(f, "Generated answer is empty"),
+const MAX_ITERATIONS: usize = 3;
 
-            // Review errors
-            AgentError::ReviewFailed => write!(f, "Failed to review answer"),
-            AgentError::NoAnswerToReview => write!(f, "No answer available to review"),
-            AgentError::MaxIterationsReached => {
-                write!(f, "Maximum iterations reached without satisfactory answer")
-            }
+/// Successive answers at least this similar are considered unchanged
+const STALL_SIMILARITY: f64 = 0.95;
 
-            // External errors
-            AgentError::CopilotError(err) => write!(f, "Copilot error: {err}"),
-            AgentError::IoError(err) => write!(f, "I/O error: {err}"),
+/// Tool output beyond this many bytes is cut before it is added to the prompt
+const MAX_TOOL_OUTPUT_BYTES: usize = 64 * 1024;
 
-            // Fallback
-            AgentError::Other(msg) => write!(f, "Other error: {msg}"),
-        }
-    }
-}
+/// Tokens of the context window left for the answer when the style does not limit it
+const ANSWER_RESERVE_TOKENS: usize = 4096;
 
-impl Error for AgentError {}
+/// Lines of each command result kept in a partial answer
+const PARTIAL_RESULT_LINES: usize = 20;
 
-impl From<CopilotError> for AgentError {
-    fn from(error: CopilotError) -> Self {
-        AgentError::CopilotError(error)
-    }
-}
+/// Unchanged lines shown around each change to a watched file
+const CHANGE_CONTEXT_LINES: usize = 2;
 
-impl From<std::io::Error> for AgentError {
-    fn from(error: std::io::Error) -> Self {
-        AgentError::IoError(error)
-    }
-}
+/// Review feedback saying an answer lacks detail that file signatures cannot give
+static MISSING_DETAIL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(details?|detailed|implementation|body|bodies|specifics?|incomplete|insufficient|not enough|vague|superficial|how it works)\b")
+        .expect("Invalid missing detail pattern")
+});
 
 /// Represents the context for an agent session
 #[derive(Default)]
 struct AgentContext {
     /// The original user question
     question: String,
-    /// Commands to execute
-    plan: Vec<String>,
+    /// Commands to execute, with their dependencies
+    plan: Vec<PlanStep>,
     /// Results from executed commands
     command_results: Vec<(String, String)>,
     /// The current generated answer
@@ -146,16 +122,127 @@ struct AgentContext {
     review_result: Option<String>,
     /// Number of iterations
     iterations: usize,
+    /// Files read by the most recent command execution
+    consulted_files: Vec<FileProvenance>,
+    /// Hashes of every prompt sent while answering the question
+    prompt_hashes: Vec<String>,
+    /// Timing and size of every tool executed while answering the question
+    tool_calls: Vec<ToolCall>,
+    /// Every answer reviewed so far, with the files it was based on
+    attempts: Vec<ReviewInput>,
+    /// Size breakdown of each answer prompt, one per generated answer
+    context_reports: Vec<ContextReport>,
+    /// Index of the attempt chosen as the final answer, if not the latest one
+    chosen_attempt: Option<usize>,
+    /// Tokens the provider reported using while answering the question
+    tokens_used: u64,
+    /// Prompt tokens the provider served from its prompt cache while answering the question
+    cached_tokens: u64,
+    /// Whether a review asked for more detail than file signatures give, so planned file
+    /// reads show whole files
+    full_detail: bool,
+    /// The kind of question, choosing the answering preset
+    question_type: QuestionType,
+    /// What the planner read in the question, kept for logging
+    intent: Intent,
+    /// Every chunk shown to the model while answering the question, by ID
+    chunks: HashMap<String, Chunk>,
+    /// The review feedback of each iteration, `None` if the answer passed
+    review_feedback: Vec<Option<String>>,
+    /// Files planned commands could not read
+    unreadable_files: Vec<UnreadableFile>,
+    /// Why no answer passed the review, if the loop gave up
+    failure: Option<FailureReport>,
+    /// Wall-clock time of every step of the loop
+    timings: Vec<StepTiming>,
+    /// Time spent waiting on the provider while answering the question, in milliseconds
+    provider_ms: u64,
+}
+
+/// A question answered earlier in the session, carried over as conversation history
+struct HistoryTurn {
+    /// The question that was asked
+    question: String,
+    /// The answer that was given
+    answer: String,
+    /// Cited files that changed since the answer was given
+    stale_files: Vec<PathBuf>,
+    /// Commands run to answer the question
+    commands: Vec<String>,
+}
+
+/// A workspace member that planned commands are confined to
+struct PackageScope {
+    /// Name of the package
+    name: String,
+    /// Directory of the package; relative plan paths are resolved against it
+    dir: PathBuf,
+}
+
+/// A model with a larger context window, answering the questions whose context does not fit
+struct LargeContextModel {
+    /// Model ID of the larger model
+    model_id: String,
+    /// What the larger model supports
+    capabilities: Capabilities,
+    /// Prompt price in dollars per million tokens, if configured
+    price: Option<f64>,
 }
 
 /// Agent that processes user queries to provide answers based on file system commands
 pub struct Agent {
-    /// Client for GitHub Copilot API access
-    client: CopilotClient,
+    /// Provider answering the prompts
+    client: Box<dyn LlmProvider>,
     /// Model ID to use for AI operations
     model_id: String,
+    /// What the model supports, choosing how replies are requested and how much context fits
+    capabilities: Capabilities,
+    /// Model answering instead when the context does not fit the model's window
+    large_context: Option<LargeContextModel>,
     /// Context for the current session
     context: AgentContext,
+    /// Rules deciding which planned commands may run
+    policy: Policy,
+    /// Earlier questions and answers in this session
+    history: Vec<HistoryTurn>,
+    /// Files to re-read on the next query because they changed since they were cited
+    refresh_files: Vec<PathBuf>,
+    /// Whether to print diagnostic output such as answer diffs between iterations
+    verbose: bool,
+    /// Strategy deciding whether an answer is good enough to return
+    reviewer: Arc<dyn Reviewer>,
+    /// Settings shaping the planning prompt
+    planner: PlannerConfig,
+    /// Package the questions are scoped to, in monorepos
+    scope: Option<PackageScope>,
+    /// Project conventions appended to the system prompt of every step
+    instructions: Option<String>,
+    /// Whether to tell the model how consulted files changed between questions
+    watch: bool,
+    /// Content of each consulted file when it was last shown to the model, in watch mode
+    snapshots: HashMap<PathBuf, String>,
+    /// Diff of the consulted files that changed since the previous answer
+    changes: Option<String>,
+    /// Directory receiving every prompt and response, for debugging
+    dump_dir: Option<PathBuf>,
+    /// Number of model calls dumped so far
+    dumped: usize,
+    /// How long and detailed answers are
+    style: AnswerStyle,
+    /// Project-specific terms, described to the planning step
+    glossary: Glossary,
+    /// The language answers are written in, if the user prefers one
+    language: Option<String>,
+    /// The files the user asks about most, mentioned to the planner
+    favorite_modules: Vec<String>,
+    /// Files or line ranges included in every answer's context, whatever the plan
+    forced_context: Vec<ContextOverride>,
+    /// The commands plans can run
+    tools: ToolRegistry,
+    /// Paths hidden from the tools, whatever the plan
+    excluded: Exclusions,
+    /// The directory tree planned commands may read
+    sandbox: RootSandbox,
 }
 
 impl Agent {
@@ -169,18 +256,7 @@ impl Agent {
     ///
     /// Returns `AgentError::CopilotError` if the Copilot client fails to initialize
     pub async fn new() -> Result<Self, AgentError> {
-        let client = CopilotClient::from_env_with_models("1.0.0".to_string())
-            .await
-            .map_err(AgentError::CopilotError)?;
-
-        // Default model
-        let model_id = "gpt-4".to_string();
-
-        Ok(Self {
-            client,
-            model_id,
-            context: AgentContext::default(),
-        })
+        Self::for_provider(ProviderKind::Copilot, None).await
     }
 
     /// Creates a new Agent with a specified model ID
@@ -195,28 +271,381 @@ impl Agent {
     ///
     /// # Errors
     ///
-    /// Returns `AgentError::CopilotError` if the Copilot client fails to initialize
+    /// Returns `AgentError::CopilotError` if the Copilot client fails to initialize or the
+    /// model is not available
     pub async fn with_model(model_id: String) -> Result<Self, AgentError> {
-        let client = CopilotClient::from_env_with_models("1.0.0".to_string())
-            .await
-            .map_err(AgentError::CopilotError)?;
+        Self::for_provider(ProviderKind::Copilot, Some(model_id)).await
+    }
 
-        Ok(Self {
+    /// Creates a new Agent answering with the given provider
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The provider to connect to with the credentials of the environment
+    /// * `model_id` - The model ID to use, or `None` for the provider's default model
+    ///
+    /// # Returns
+    ///
+    /// A new Agent instance or an error if initialization fails
+    ///
+    /// # Errors
+    ///
+    /// Returns `AgentError::CopilotError` if the provider's client fails to initialize or
+    /// the requested model is not available
+    pub async fn for_provider(
+        kind: ProviderKind,
+        model_id: Option<String>,
+    ) -> Result<Self, AgentError> {
+        let client = kind.connect().await.map_err(AgentError::CopilotError)?;
+        match model_id {
+            Some(model_id) => Self::with_client(client, model_id),
+            None => Ok(Self::from_client(client, kind.default_model().to_string())),
+        }
+    }
+
+    /// Creates a new Agent answering with an already connected provider
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The provider, such as a custom backend
+    /// * `model_id` - The model ID to use
+    ///
+    /// # Returns
+    ///
+    /// A new Agent instance or an error if the model is not available
+    ///
+    /// # Errors
+    ///
+    /// Returns `AgentError::CopilotError` if the provider does not offer the model
+    pub fn with_client(client: Box<dyn LlmProvider>, model_id: String) -> Result<Self, AgentError> {
+        if !client.has_model(&model_id) {
+            return Err(AgentError::CopilotError(CopilotError::InvalidModel(
+                model_id,
+            )));
+        }
+        Ok(Self::from_client(client, model_id))
+    }
+
+    /// Creates a new Agent with default settings around a provider and model
+    fn from_client(client: Box<dyn LlmProvider>, model_id: String) -> Self {
+        Self {
+            capabilities: client.capabilities(&model_id),
+            large_context: None,
             client,
             model_id,
             context: AgentContext::default(),
-        })
+            policy: Policy::default(),
+            history: Vec::new(),
+            refresh_files: Vec::new(),
+            verbose: false,
+            reviewer: Arc::new(LlmReviewer),
+            planner: PlannerConfig::default(),
+            scope: None,
+            instructions: None,
+            watch: false,
+            snapshots: HashMap::new(),
+            changes: None,
+            dump_dir: None,
+            dumped: 0,
+            style: AnswerStyle::default(),
+            glossary: Glossary::default(),
+            language: None,
+            favorite_modules: Vec::new(),
+            forced_context: Vec::new(),
+            tools: ToolRegistry::builtin(),
+            excluded: Exclusions::default(),
+            sandbox: RootSandbox::new(&repo_root()),
+        }
+    }
+
+    /// Sets the tool permission policy used when executing planned commands
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The policy to enforce
+    #[must_use]
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets the strategy used to review generated answers
+    ///
+    /// # Arguments
+    ///
+    /// * `reviewer` - The reviewer to consult after each answer
+    #[must_use]
+    pub fn with_reviewer(mut self, reviewer: Arc<dyn Reviewer>) -> Self {
+        self.reviewer = reviewer;
+        self
+    }
+
+    /// Sets the planner settings, such as directories to avoid reading
+    ///
+    /// # Arguments
+    ///
+    /// * `planner` - The `[planner]` configuration
+    #[must_use]
+    pub fn with_planner(mut self, planner: PlannerConfig) -> Self {
+        self.planner = planner;
+        self
+    }
+
+    /// Scopes questions to a single workspace member of a monorepo
+    ///
+    /// Paths in planned commands are resolved relative to the package directory, so `tree`
+    /// and `show_file` only see that package.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The package name, used in prompts
+    /// * `dir` - The package directory
+    #[must_use]
+    pub fn with_package_scope(mut self, name: String, dir: PathBuf) -> Self {
+        self.scope = Some(PackageScope { name, dir });
+        self
+    }
+
+    /// Confines the paths of planned commands to a directory tree other than the repository
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The directory commands may read below, from `--root`
+    #[must_use]
+    pub fn with_root(mut self, root: &Path) -> Self {
+        self.sandbox = RootSandbox::new(root);
+        self
+    }
+
+    /// Sets project conventions to append to the system prompt of every step
+    ///
+    /// # Arguments
+    ///
+    /// * `instructions` - The contents of the project's convention files
+    #[must_use]
+    pub fn with_instructions(mut self, instructions: Option<String>) -> Self {
+        self.instructions = instructions;
+        self
+    }
+
+    /// Enables diagnostic output such as answer diffs between iterations
+    ///
+    /// # Arguments
+    ///
+    /// * `verbose` - Whether verbose output is enabled
+    #[must_use]
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Keeps track of the consulted files so later questions are told how they changed
+    ///
+    /// # Arguments
+    ///
+    /// * `watch` - Whether to track consulted files between questions
+    #[must_use]
+    pub fn with_watch(mut self, watch: bool) -> Self {
+        self.watch = watch;
+        self
+    }
+
+    /// Sets how long and detailed answers are
+    ///
+    /// # Arguments
+    ///
+    /// * `style` - The answer style
+    #[must_use]
+    pub fn with_style(mut self, style: AnswerStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Sets the project-specific terms described when a question uses them
+    ///
+    /// # Arguments
+    ///
+    /// * `glossary` - The configured and learned terms of the repository
+    #[must_use]
+    pub fn with_glossary(mut self, glossary: Glossary) -> Self {
+        self.glossary = glossary;
+        self
+    }
+
+    /// Sets a model with a larger context window, answering the questions whose context does
+    /// not fit the model's window instead of shortening their command results
+    ///
+    /// The model is ignored with a warning if the provider does not offer it or its window
+    /// is not larger.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_id` - The larger model, from `large_context_model`
+    /// * `price` - Its prompt price in dollars per million tokens, for the cost estimate
+    #[must_use]
+    pub fn with_large_context_model(mut self, model_id: String, price: Option<f64>) -> Self {
+        if !self.client.has_model(&model_id) {
+            eprintln!("Ignoring large-context model {model_id}: the provider does not offer it");
+            return self;
+        }
+        let capabilities = self.client.capabilities(&model_id);
+        if capabilities.max_context <= self.capabilities.max_context {
+            eprintln!(
+                "Ignoring large-context model {model_id}: its context window is not larger than {}'s",
+                self.model_id
+            );
+            return self;
+        }
+        self.large_context = Some(LargeContextModel {
+            model_id,
+            capabilities,
+            price,
+        });
+        self
+    }
+
+    /// Applies the answer language and favorite modules remembered from earlier chats
+    ///
+    /// The remembered style is not applied; pass it to `with_style` unless the user chose
+    /// one.
+    ///
+    /// # Arguments
+    ///
+    /// * `preferences` - The remembered preferences
+    /// * `repo` - The repository the questions are about
+    #[must_use]
+    pub fn with_preferences(mut self, preferences: &Preferences, repo: &Path) -> Self {
+        self.language.clone_from(&preferences.language);
+        self.favorite_modules = preferences.favorite_modules(repo);
+        self
+    }
+
+    /// Includes files or line ranges in the context of every answer, in addition to what the
+    /// plan reads
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - The files and ranges to include, from `--context`
+    #[must_use]
+    pub fn with_context(mut self, context: Vec<ContextOverride>) -> Self {
+        self.forced_context = context;
+        self
+    }
+
+    /// Hides paths from every tool, so answers are built without them
+    ///
+    /// Files included with `with_context` are read even if hidden.
+    ///
+    /// # Arguments
+    ///
+    /// * `excluded` - The globs to hide, from `--without`
+    #[must_use]
+    pub fn with_exclusions(mut self, excluded: Exclusions) -> Self {
+        self.excluded = excluded;
+        self
+    }
+
+    /// Serves file-reading commands from a cache shared with other agents, so commands they
+    /// already ran are not run again
+    ///
+    /// # Arguments
+    ///
+    /// * `cache` - The cache, cloned from the one of the other agents
+    #[must_use]
+    pub fn with_command_cache(mut self, cache: CommandCache) -> Self {
+        self.tools = self.tools.with_cache(cache);
+        self
+    }
+
+    /// Adds a command plans can run, replacing a built-in tool of the same name
+    ///
+    /// The tool is described to the planner unless the policy denies it. Tools unknown to
+    /// the policy are classified as `exec`.
+    ///
+    /// # Arguments
+    ///
+    /// * `tool` - The tool to add
+    #[must_use]
+    pub fn with_tool(mut self, tool: impl Tool + 'static) -> Self {
+        self.tools = self.tools.register(tool);
+        self
+    }
+
+    /// Writes every fully rendered prompt and the model's response to numbered files in
+    /// `dir`, such as `001-prompt.json` and `001-response.json`
+    ///
+    /// Files from an earlier run with the same numbers are overwritten.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The existing directory to write to, or `None` to write nothing
+    #[must_use]
+    pub fn with_prompt_dump(mut self, dir: Option<PathBuf>) -> Self {
+        self.dump_dir = dir;
+        self
+    }
+
+    /// Returns what the model with the given ID supports, as described by the provider
+    ///
+    /// # Arguments
+    ///
+    /// * `model_id` - The model ID, such as one of [`Agent::available_models`]
+    pub fn capabilities(&self, model_id: &str) -> Capabilities {
+        self.client.capabilities(model_id)
+    }
+
+    /// Returns the IDs of the models available to the account, without duplicates
+    pub fn available_models(&self) -> Vec<&str> {
+        let mut ids: Vec<&str> = Vec::new();
+        for model in self.client.models() {
+            if !ids.contains(&model.id.as_str()) {
+                ids.push(&model.id);
+            }
+        }
+        ids
+    }
+
+    /// Continues a previously saved session
+    ///
+    /// Earlier questions and answers become conversation history for the next query. Cited
+    /// files that changed since they were read are flagged as stale in that history, and the
+    /// ones that still exist are re-read on the next query so the answer reflects the
+    /// current code.
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - The session to continue
+    pub fn resume(&mut self, record: &SessionRecord) {
+        let base = record.working_dir.as_deref();
+        for entry in &record.entries {
+            let stale = entry.stale_files(base);
+            for file in &stale {
+                if file.status == Staleness::Modified && !self.refresh_files.contains(&file.path) {
+                    self.refresh_files.push(file.path.clone());
+                }
+            }
+            self.history.push(HistoryTurn {
+                question: entry.question.clone(),
+                answer: entry.answer.clone(),
+                stale_files: stale.into_iter().map(|file| file.path).collect(),
+                commands: entry
+                    .provenance
+                    .tool_calls
+                    .iter()
+                    .map(|call| call.command.clone())
+                    .collect(),
+            });
+        }
     }
 
     /// Process a user query and return an answer
     ///
     /// This method orchestrates the entire agent workflow:
-    /// 1. Understanding the question
-    /// 2. Planning the execution
-    /// 3. Executing commands
-    /// 4. Generating an answer
-    /// 5. Reviewing the answer
-    /// 6. Iterating if necessary
+    /// 1. Classifying the question and planning the execution
+    /// 2. Executing commands
+    /// 3. Generating an answer
+    /// 4. Reviewing the answer
+    /// 5. Iterating if necessary
     ///
     /// # Arguments
     ///
@@ -235,31 +664,93 @@ impl Agent {
         self.context.question = query.to_string();
 
         // Maximum number of iterations to prevent infinite loops
-
+        let mut stalled = false;
         while self.context.iterations < MAX_ITERATIONS {
             self.context.iterations += 1;
 
-            self.understand_question().await?;
+            let started = self.step_started();
             self.plan_execution().await?;
+            let started = self.step_finished(Step::Plan, started);
+            self.rank_reads().await?;
+            let started = self.step_finished(Step::Rank, started);
             self.execute_commands()?;
+            let started = self.step_finished(Step::Commands, started);
+            self.follow_up().await?;
+            let started = self.step_finished(Step::FollowUp, started);
+
+            let previous_answer = self.context.current_answer.clone();
             self.create_answer().await?;
+            let started = self.step_finished(Step::Answer, started);
+            if self.verbose {
+                self.print_iteration_diff(previous_answer.as_deref());
+            }
 
             let review_passed = self.review_answer().await?;
+            self.step_finished(Step::Review, started);
             if review_passed {
-                return Ok(self.context.current_answer.clone().unwrap_or_default());
+                let answer = self.resolve_citations();
+                self.finish_query(&answer);
+                return Ok(answer);
             }
 
-            println!(
-                "Review failed, starting iteration {}",
-                self.context.iterations + 1
-            );
+            // Retrying is pointless if the answer no longer changes between iterations
+            if let (Some(previous), Some(current)) =
+                (&previous_answer, &self.context.current_answer)
+                && similarity(previous, current) >= STALL_SIMILARITY
+            {
+                eprintln!("Answer did not change since the previous iteration, stopping early");
+                stalled = true;
+                break;
+            }
+
+            if self.planner.context == ContextMode::Signatures
+                && !self.context.full_detail
+                && self
+                    .context
+                    .review_result
+                    .as_deref()
+                    .is_some_and(|review| MISSING_DETAIL.is_match(review))
+            {
+                eprintln!("Review asked for more detail; reading whole files from now on");
+                self.context.full_detail = true;
+            }
+
+            if self.context.iterations < MAX_ITERATIONS {
+                eprintln!(
+                    "Review failed, starting iteration {}",
+                    self.context.iterations + 1
+                );
+            }
         }
 
-        // If we've reached the maximum iterations, return the last answer with a note
-        if let Some(answer) = &self.context.current_answer {
-            Ok(format!(
-                "{answer}\n\n(Note: This answer was provided after reaching the maximum number of iteration attempts.)",
-            ))
+        // If we've stopped without a passing review, return the best answer with a note
+        let started = self.step_started();
+        self.select_best_answer().await?;
+        self.step_finished(Step::Select, started);
+        if self.context.current_answer.is_some() {
+            let answer = self.resolve_citations();
+            self.finish_query(&answer);
+            let reason = if stalled {
+                format!(
+                    "iteration {} produced nearly the same answer as the one before, so further attempts were skipped",
+                    self.context.iterations
+                )
+            } else {
+                "reaching the maximum number of iteration attempts".to_string()
+            };
+            let failure = FailureReport::new(
+                reason,
+                &self.context.review_feedback,
+                self.context
+                    .tool_calls
+                    .iter()
+                    .map(|call| call.command.clone())
+                    .collect(),
+                self.context.unreadable_files.clone(),
+            );
+            let answer = format!("{answer}\n\n{failure}");
+            self.context.failure = Some(failure);
+            Ok(answer)
         } else {
             Err(AgentError::Other(
                 "Failed to generate an answer after maximum iterations".to_string(),
@@ -267,64 +758,624 @@ impl Agent {
         }
     }
 
-    /// Extract intent from user's question
-    async fn understand_question(&mut self) -> Result<(), AgentError> {
-        let messages = vec![
-            Message {
-                role: "system".to_string(),
-                content: "You are an assistant that understands user questions about code repositories. Extract the user's intent regarding what files or directories they want to explore.".to_string(),
-            },
-            Message {
-                role: "user".to_string(),
-                content: format!(
-                    "Based on this question: '{}', identify what directories and files the user wants to explore. Respond in this format:\n\n{{\"tree\": [\"path1\", \"path2\"], \"show_file\": [\"file1\", \"file2\"]}}",
-                    self.context.question
-                ),
+    /// Returns when a step starts, and how long the provider had been waited on by then
+    fn step_started(&self) -> (Instant, u64) {
+        (Instant::now(), self.context.provider_ms)
+    }
+
+    /// Records the time of `step`, started at `started`, returning when the next step starts
+    fn step_finished(&mut self, step: Step, started: (Instant, u64)) -> (Instant, u64) {
+        let (start, provider_ms) = started;
+        self.context.timings.push(StepTiming {
+            step,
+            iteration: self.context.iterations,
+            duration_ms: millis(start.elapsed()),
+            provider_ms: self.context.provider_ms - provider_ms,
+        });
+        self.step_started()
+    }
+
+    /// Print how the answer changed since the previous iteration and why it was retried
+    fn print_iteration_diff(&self, previous_answer: Option<&str>) {
+        let (Some(previous), Some(current)) = (previous_answer, &self.context.current_answer)
+        else {
+            return;
+        };
+
+        eprintln!(
+            "=== Answer diff: iteration {} -> {} ===",
+            self.context.iterations - 1,
+            self.context.iterations
+        );
+        if let Some(review) = &self.context.review_result {
+            eprintln!("Review feedback that triggered the retry: {review}");
+        }
+        if previous == current {
+            eprintln!("(answer unchanged)");
+        } else {
+            eprint!("{}", render_diff(previous, current));
+        }
+    }
+
+    /// Make the best of all reviewed answers the current one
+    async fn select_best_answer(&mut self) -> Result<(), AgentError> {
+        if self.context.attempts.len() < 2 {
+            return Ok(());
+        }
+
+        let candidates = self.context.attempts.clone();
+        let reviewer = Arc::clone(&self.reviewer);
+        let best = reviewer.select_best(&candidates, self).await?;
+        if let Some(chosen) = candidates.into_iter().nth(best) {
+            eprintln!(
+                "Selected answer from iteration {} as the best attempt",
+                best + 1
+            );
+            self.context.current_answer = Some(chosen.answer);
+            self.context.consulted_files = chosen.consulted_files;
+            self.context.chosen_attempt = Some(best);
+        }
+        Ok(())
+    }
+
+    /// Returns the current answer with its cited chunk IDs turned into links to their lines
+    fn resolve_citations(&self) -> String {
+        let answer = self.context.current_answer.as_deref().unwrap_or_default();
+        let (answer, unknown) = resolve(answer, &self.context.chunks);
+        if !unknown.is_empty() {
+            eprintln!(
+                "Answer cites chunks that were never shown: {}",
+                unknown.join(", ")
+            );
+        }
+        answer
+    }
+
+    /// Compares the files consulted so far with their current content, so the next query
+    /// includes a diff of what changed instead of re-reading whole files
+    ///
+    /// Only tracks files in watch mode. Returns the files that changed.
+    pub fn refresh_changes(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        let mut diff = String::new();
+        let mut paths: Vec<PathBuf> = self.snapshots.keys().cloned().collect();
+        paths.sort();
+        for path in paths {
+            let current = std::fs::read_to_string(&path).unwrap_or_default();
+            let Some(previous) = self.snapshots.get_mut(&path) else {
+                continue;
+            };
+            if *previous == current {
+                continue;
+            }
+            diff.push_str(&unified_diff(
+                &path.to_string_lossy(),
+                previous,
+                &current,
+                CHANGE_CONTEXT_LINES,
+            ));
+            *previous = current;
+            changed.push(path);
+        }
+        if diff.len() > MAX_TOOL_OUTPUT_BYTES {
+            diff.truncate(diff.floor_char_boundary(MAX_TOOL_OUTPUT_BYTES));
+            diff.push_str("\n[diff truncated; read the files for the rest]");
+        }
+        if !changed.is_empty() {
+            self.changes = Some(match self.changes.take() {
+                Some(earlier) => format!("{earlier}{diff}"),
+                None => diff,
+            });
+        }
+        changed
+    }
+
+    /// Forgets earlier questions and answers, so the next query is answered on its own
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+        self.refresh_files.clear();
+    }
+
+    /// Returns the tokens the provider reported using for the current question
+    pub fn tokens_used(&self) -> u64 {
+        self.context.tokens_used
+    }
+
+    /// Returns the prompt tokens of the current question the provider served from its
+    /// prompt cache
+    pub fn cached_tokens(&self) -> u64 {
+        self.context.cached_tokens
+    }
+
+    /// Record a completed query as history for follow-up questions
+    fn finish_query(&mut self, answer: &str) {
+        self.refresh_files.clear();
+        self.changes = None;
+        if self.watch {
+            for file in &self.context.consulted_files {
+                if let Ok(content) = std::fs::read_to_string(&file.path) {
+                    self.snapshots.insert(file.path.clone(), content);
+                }
+            }
+        }
+        self.history.push(HistoryTurn {
+            question: self.context.question.clone(),
+            answer: answer.to_string(),
+            stale_files: Vec::new(),
+            commands: self
+                .context
+                .tool_calls
+                .iter()
+                .map(|call| call.command.clone())
+                .collect(),
+        });
+    }
+
+    /// Returns the earlier questions and answers of the session, oldest first
+    pub fn history(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.history
+            .iter()
+            .map(|turn| (turn.question.as_str(), turn.answer.as_str()))
+    }
+
+    /// Render earlier questions and the commands run for them, so the planner can build on
+    /// what was already explored
+    fn planner_history_text(&self) -> String {
+        if self.history.is_empty() {
+            return String::new();
+        }
+
+        let mut text =
+            String::from("Earlier questions in this conversation and the commands run for them:\n");
+        for (number, turn) in self.history.iter().enumerate() {
+            text.push_str(&format!("{}. {}\n", number + 1, turn.question));
+            if !turn.commands.is_empty() {
+                text.push_str(&format!("   Commands: {}\n", turn.commands.join(", ")));
+            }
+        }
+        text.push('\n');
+        text
+    }
+
+    /// Render earlier questions and answers for inclusion in a prompt
+    fn history_text(&self) -> String {
+        if self.history.is_empty() {
+            return String::new();
+        }
+
+        let mut text = String::from("Previous questions and answers in this session:\n\n");
+        for turn in &self.history {
+            text.push_str(&format!("Q: {}\nA: {}\n", turn.question, turn.answer));
+            if !turn.stale_files.is_empty() {
+                let files: Vec<String> = turn
+                    .stale_files
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                text.push_str(&format!(
+                    "(Note: these cited files changed after this answer was given, so it may be outdated: {})\n",
+                    files.join(", ")
+                ));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Returns the session entry describing how `answer` to the current question was produced
+    ///
+    /// # Arguments
+    ///
+    /// * `answer` - The final answer returned by `process_query`
+    pub fn session_entry(&self, answer: &str) -> SessionEntry {
+        SessionEntry {
+            question: self.context.question.clone(),
+            answer: answer.to_string(),
+            answered_at: chrono::Utc::now(),
+            provenance: Provenance {
+                model: self.model_id.clone(),
+                files: self.context.consulted_files.clone(),
+                prompt_hashes: self.context.prompt_hashes.clone(),
+                tool_calls: self.context.tool_calls.clone(),
+                pin: None,
             },
-        ];
+            question_embedding: None,
+            truncated: false,
+        }
+    }
 
-        let response = self
-            .client
-            .chat_completion(messages, self.model_id.clone())
-            .await?;
+    /// Suggests follow-up questions to the current question, grounded in the files consulted
+    /// for its answer
+    ///
+    /// # Arguments
+    ///
+    /// * `answer` - The final answer returned by `process_query`
+    pub async fn suggest_follow_ups(&mut self, answer: &str) -> Result<Vec<String>, AgentError> {
+        let question = self.context.question.clone();
+        let files = self.consulted_paths();
+        follow_up::suggest(self, &question, answer, &files).await
+    }
 
-        if let Some(choice) = response.choices.first() {
-            println!("Intent extraction: {}", choice.message.content);
-            // Here you would parse the JSON response, but for simplicity we'll skip that part
-            Ok(())
+    /// Returns the paths of the files consulted for the current question, without
+    /// duplicates
+    pub fn consulted_paths(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = Vec::new();
+        for file in &self.context.consulted_files {
+            if !files.contains(&file.path) {
+                files.push(file.path.clone());
+            }
+        }
+        files
+    }
+
+    /// Returns the latest answer drafted for the current question, if any
+    ///
+    /// Used to keep what was produced so far when the user interrupts `process_query`.
+    pub fn partial_answer(&self) -> Option<&str> {
+        self.context.current_answer.as_deref()
+    }
+
+    /// Returns the latest draft answer, or else an excerpt of each command result gathered
+    /// for the current question, if there is either
+    ///
+    /// Used when `process_query` cannot finish in time, so the user still gets what was
+    /// found so far.
+    pub fn partial_results(&self) -> Option<String> {
+        if let Some(draft) = self.partial_answer() {
+            return Some(draft.to_string());
+        }
+        if self.context.command_results.is_empty() {
+            return None;
+        }
+        let mut text = String::from("No answer was drafted yet. Results gathered so far:\n\n");
+        for (cmd, result) in &self.context.command_results {
+            let lines: Vec<&str> = result.lines().collect();
+            let excerpt = lines[..lines.len().min(PARTIAL_RESULT_LINES)].join("\n");
+            let more = lines.len().saturating_sub(PARTIAL_RESULT_LINES);
+            text.push_str(&format!("## {cmd}\n\n```\n{excerpt}\n```\n"));
+            if more > 0 {
+                text.push_str(&format!("({more} more lines)\n"));
+            }
+            text.push('\n');
+        }
+        Some(text.trim_end().to_string())
+    }
+
+    /// Computes an embedding of `text`, e.g. to recognize previously answered questions
+    ///
+    /// # Errors
+    ///
+    /// Returns `AgentError::CopilotError` if the embeddings request fails, or
+    /// `AgentError::Other` if the response contains no embedding
+    pub async fn embed(&self, text: &str) -> Result<Vec<f64>, AgentError> {
+        let embeddings = self.client.embeddings(vec![text.to_string()]).await?;
+        embeddings
+            .into_iter()
+            .next()
+            .map(|embedding| embedding.embedding)
+            .ok_or_else(|| AgentError::Other("Embeddings response was empty".to_string()))
+    }
+
+    /// Returns why no answer passed the review, if the last query gave up
+    pub fn failure_report(&self) -> Option<&FailureReport> {
+        self.context.failure.as_ref()
+    }
+
+    /// Returns where the time of the last query went, by step of the loop
+    pub fn latency_report(&self) -> Option<LatencyReport> {
+        if self.context.timings.is_empty() {
+            return None;
+        }
+        Some(LatencyReport::new(
+            self.context.timings.clone(),
+            self.context.tool_calls.clone(),
+        ))
+    }
+
+    /// Returns the size breakdown of the prompt that produced the final answer
+    pub fn context_report(&self) -> Option<&ContextReport> {
+        match self.context.chosen_attempt {
+            Some(index) => self.context.context_reports.get(index),
+            None => self.context.context_reports.last(),
+        }
+    }
+
+    /// Send a chat completion request whose leading system messages are its cacheable
+    /// prefix
+    async fn chat(&mut self, messages: Vec<Message>) -> Result<ChatResponse, AgentError> {
+        let system = messages.iter().take_while(|m| m.role == "system").count();
+        self.chat_with_prefix(messages, system, None, false).await
+    }
+
+    /// Send a chat completion request whose reply must be a JSON object
+    ///
+    /// Models with a JSON mode are held to it; the others are asked for JSON in prose, so
+    /// the reply must be read leniently either way.
+    async fn chat_json(&mut self, messages: Vec<Message>) -> Result<ChatResponse, AgentError> {
+        let system = messages.iter().take_while(|m| m.role == "system").count();
+        let json = self.capabilities.json_mode;
+        self.chat_with_prefix(messages, system, None, json).await
+    }
+
+    /// Send a chat completion request, recording a hash of the prompt for provenance and
+    /// the tokens it used
+    ///
+    /// The first `stable_prefix` messages must be identical across the calls of a session,
+    /// so the provider can serve them from its prompt cache. Project conventions, if any,
+    /// are appended to the system messages first. The reply is limited to `max_tokens`
+    /// tokens if given, and requested in the provider's JSON mode if `json` is set.
+    async fn chat_with_prefix(
+        &mut self,
+        mut messages: Vec<Message>,
+        stable_prefix: usize,
+        max_tokens: Option<u32>,
+        json: bool,
+    ) -> Result<ChatResponse, AgentError> {
+        if let Some(instructions) = &self.instructions {
+            for message in messages.iter_mut().filter(|m| m.role == "system") {
+                message.content.push_str(&format!(
+                    "\n\nFollow these project conventions:\n{instructions}"
+                ));
+            }
+        }
+
+        let rendered =
+            serde_json::to_vec(&messages).map_err(|e| AgentError::Other(e.to_string()))?;
+        self.context.prompt_hashes.push(sha256_hex(&rendered));
+
+        let number = self.dump_prompt(&messages, stable_prefix);
+        let started = Instant::now();
+        let response = if json {
+            self.client
+                .chat_json(messages, &self.model_id, stable_prefix, max_tokens)
+                .await
         } else {
-            Err(AgentError::IntentExtractionFailed)
+            self.client
+                .chat(messages, &self.model_id, stable_prefix, max_tokens)
+                .await
+        };
+        self.context.provider_ms += millis(started.elapsed());
+        if let Some(number) = number {
+            self.dump_response(number, &response);
+        }
+        let response = response?;
+        self.context.tokens_used += response.tokens_used();
+        self.context.cached_tokens += response.cached_tokens();
+        Ok(response)
+    }
+
+    /// Writes the prompt of a model call to the dump directory, if any, returning its
+    /// number
+    fn dump_prompt(&mut self, messages: &[Message], stable_prefix: usize) -> Option<usize> {
+        let dir = self.dump_dir.as_ref()?;
+        self.dumped += 1;
+        let prompt = serde_json::json!({
+            "model": self.model_id,
+            "question": self.context.question,
+            "iteration": self.context.iterations,
+            "stable_prefix": stable_prefix,
+            "messages": messages,
+        });
+        let path = dir.join(format!("{:03}-prompt.json", self.dumped));
+        let text = serde_json::to_string_pretty(&prompt).unwrap_or_default();
+        if let Err(err) = std::fs::write(&path, text) {
+            eprintln!("Failed to write {}: {err}", path.display());
         }
+        Some(self.dumped)
     }
 
-    /// Plan what commands to execute based on extracted intent
+    /// Writes the response to model call `number`, or the error it failed with, to the dump
+    /// directory
+    fn dump_response(&self, number: usize, response: &Result<ChatResponse, CopilotError>) {
+        let Some(dir) = &self.dump_dir else {
+            return;
+        };
+        let (path, text) = match response {
+            Ok(response) => (
+                dir.join(format!("{number:03}-response.json")),
+                serde_json::to_string_pretty(response).unwrap_or_default(),
+            ),
+            Err(err) => (
+                dir.join(format!("{number:03}-error.txt")),
+                format!("{err}\n"),
+            ),
+        };
+        if let Err(err) = std::fs::write(&path, text) {
+            eprintln!("Failed to write {}: {err}", path.display());
+        }
+    }
+
+    /// Describes the project terms the question uses, followed by a blank line, or returns
+    /// an empty string if it uses none
+    fn glossary_text(&self) -> String {
+        self.glossary
+            .prompt(&self.context.question)
+            .map(|text| format!("{text}\n"))
+            .unwrap_or_default()
+    }
+
+    /// Tells the planner which files the user included, followed by a blank line, or
+    /// returns an empty string if they included none
+    fn forced_context_text(&self) -> String {
+        if self.forced_context.is_empty() {
+            return String::new();
+        }
+        let included: Vec<String> = self
+            .forced_context
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        format!(
+            "The user already included {} in the context; do not read them again.\n\n",
+            included.join(", ")
+        )
+    }
+
+    /// Returns the system prompt of the planning steps
+    ///
+    /// The initial plan and every follow-up round share it, so the provider can cache it.
+    fn planner_prompt(&self) -> String {
+        let mut system_prompt = self
+            .tools
+            .planner_prompt(|tool| self.policy.permission(tool) != Permission::Deny);
+        system_prompt.push_str("\n\n");
+        if let Some(guidance) = self.planner.guidance() {
+            system_prompt.push_str(&guidance);
+            system_prompt.push(' ');
+        }
+        system_prompt.push_str(&QuestionType::planner_guidance(|tool| {
+            self.policy.permission(tool) != Permission::Deny
+        }));
+        if let Some(scope) = &self.scope {
+            system_prompt.push_str(&format!(
+                " The question concerns the package `{}` in `{}`. All paths in commands are relative to that package directory; use `tree .` for its root.",
+                scope.name,
+                scope.dir.display()
+            ));
+        }
+        let disabled: Vec<&str> = self
+            .tools
+            .names()
+            .into_iter()
+            .filter(|tool| !matches!(*tool, "run" | "run_rust"))
+            .filter(|tool| self.policy.permission(tool) == Permission::Deny)
+            .collect();
+        if !disabled.is_empty() {
+            system_prompt.push_str(&format!(
+                " These commands are disabled; do not use them: {}.",
+                disabled.join(", ")
+            ));
+        }
+        if !self.excluded.is_empty() {
+            system_prompt.push_str(&format!(
+                " The user excluded these paths from this question; do not plan commands reading them: {}.",
+                self.excluded.globs().join(", ")
+            ));
+        }
+        if !self.favorite_modules.is_empty() {
+            system_prompt.push_str(&format!(
+                " The user often asks about these files; consider them when they are relevant: {}.",
+                self.favorite_modules.join(", ")
+            ));
+        }
+        if let Some(examples) = self.planner.examples_prompt() {
+            system_prompt.push_str("\n\n");
+            system_prompt.push_str(&examples);
+        }
+        system_prompt
+    }
+
+    /// Classify the question and plan what commands to execute, in one call
     async fn plan_execution(&mut self) -> Result<(), AgentError> {
         let messages = vec![
             Message {
                 role: "system".to_string(),
-                content: "You are an assistant that plans how to answer questions about code repositories. You can use 'tree' to show directory structure and 'show_file' to display file contents.".to_string(),
+                content: self.planner_prompt(),
             },
             Message {
                 role: "user".to_string(),
                 content: format!(
-                    "Based on this question: '{}', create a plan of what commands to run. Return a JSON array of commands like [\"tree src\", \"show_file src/main.rs\"]",
+                    "{}{}{}Question: '{}'\n\n{INTENT_PROMPT}",
+                    self.planner_history_text(),
+                    self.glossary_text(),
+                    self.forced_context_text(),
                     self.context.question
                 ),
             },
         ];
 
-        let response = self
-            .client
-            .chat_completion(messages, self.model_id.clone())
-            .await?;
+        let response = self.chat_json(messages).await?;
 
         if let Some(choice) = response.choices.first() {
-            println!("Plan: {}", choice.message.content);
+            eprintln!("Plan: {}", choice.message.content);
+
+            // Planning can do without the intent, so an unreadable one is not an error
+            let reply = PlanReply::parse(&choice.message.content);
+            self.context.question_type =
+                QuestionType::classify(reply.intent.as_ref(), &self.context.question);
+            self.context.intent = reply.intent.unwrap_or_else(|| {
+                eprintln!("Could not read the intent; classifying the question by its wording");
+                Intent::default()
+            });
+            eprintln!("Intent: {}", self.context.intent);
+            eprintln!("Question type: {}", self.context.question_type);
+
+            self.context.plan = parse_plan(&reply.plan, &self.tools.names()).map_err(|reason| {
+                AgentError::InvalidPlanFormat {
+                    reason,
+                    text: choice.message.content.clone(),
+                }
+            })?;
+
+            // Pull in history for file locations named in the question
+            let base = self.scope.as_ref().map(|scope| scope.dir.as_path());
+            for reference in find_line_references(&self.context.question) {
+                let command = reference.command();
+                if resolve_path(base, &reference.path.to_string_lossy()).is_file()
+                    && !self.context.plan.iter().any(|step| step.command == command)
+                {
+                    self.context
+                        .plan
+                        .push(PlanStep::new(command.clone(), command));
+                }
+            }
+
+            // Read tests and examples first for "how do I use X" questions
+            if let Some(subject) = usage_subject(&self.context.question) {
+                let root = base.unwrap_or(Path::new("."));
+                let index = symbol_index(root);
+                let mut examples = find_usage_examples(&index, &subject, MAX_USAGE_EXAMPLES);
+                examples.retain(|path| !self.excluded.excludes(path));
+                if !examples.is_empty() {
+                    eprintln!(
+                        "Reading {} tests and examples using {subject}",
+                        examples.len()
+                    );
+                }
+                for (position, path) in examples.iter().enumerate() {
+                    let command = format!("show_file {}", path.display());
+                    let id = format!("usage {}", path.display());
+                    let mut step = PlanStep::new(id.clone(), command.clone());
+                    if let Some(planned) = self
+                        .context
+                        .plan
+                        .iter()
+                        .position(|step| step.command == command)
+                    {
+                        // Keep the steps waiting for the planned read pointing at it
+                        step = self.context.plan.remove(planned);
+                        for other in &mut self.context.plan {
+                            for after in &mut other.after {
+                                if *after == step.id {
+                                    after.clone_from(&id);
+                                }
+                            }
+                        }
+                        step.id = id;
+                    }
+                    self.context.plan.insert(position, step);
+                }
+            }
+
+            // Re-read files that changed since a resumed session cited them
+            for path in &self.refresh_files {
+                let command = format!("show_file {}", path.display());
+                if !self.context.plan.iter().any(|step| step.command == command) {
+                    let id = format!("refresh {}", path.display());
+                    self.context.plan.push(PlanStep::new(id, command));
+                }
+            }
 
-            // Mock command parsing - in a real implementation, parse JSON from response
-            self.context.plan = vec!["tree src".to_string(), "show_file src/main.rs".to_string()];
+            // Disabled tools fail the whole query when run, so their steps are skipped
+            let policy = &self.policy;
+            let denied = drop_denied(&mut self.context.plan, |tool| {
+                policy.permission(tool) == Permission::Deny
+            });
+            for step in denied {
+                eprintln!("Skipping `{}`: the tool is disabled", step.command);
+            }
 
-            if self.context.plan.is_empty() {
+            if self.context.plan.is_empty() && self.forced_context.is_empty() {
                 return Err(AgentError::EmptyPlan);
             }
 
@@ -334,49 +1385,301 @@ impl Agent {
         }
     }
 
+    /// Drop planned file reads beyond the configured limits, keeping the most relevant files
+    ///
+    /// Files re-read because a resumed session cited them, and tests and examples read for
+    /// usage questions, are always kept.
+    async fn rank_reads(&mut self) -> Result<(), AgentError> {
+        let base = self.scope.as_ref().map(|scope| scope.dir.as_path());
+        let reads: Vec<(String, Candidate)> = self
+            .context
+            .plan
+            .iter()
+            .filter(|step| !step.id.starts_with("refresh ") && !step.id.starts_with("usage "))
+            // Reads of line ranges are short already
+            .filter(|step| !reads_range(&step.command))
+            .filter_map(|step| {
+                let path = step.command.strip_prefix("show_file ")?;
+                // Paths outside the sandbox fail when run, and are not worth reading to rank
+                let resolved = resolve_path(base, path);
+                if !self.sandbox.contains(&resolved) {
+                    return None;
+                }
+                // Nor are forbidden and excluded files, whose symbols must not reach the model
+                if org_policy::global().is_forbidden(&resolved) || self.excluded.excludes(&resolved)
+                {
+                    return None;
+                }
+                let mut candidate = Candidate::from_path(&resolved);
+                candidate.path = PathBuf::from(path);
+                Some((step.id.clone(), candidate))
+            })
+            .collect();
+        let tokens: usize = reads.iter().map(|(_, candidate)| candidate.tokens).sum();
+        if reads.len() <= self.planner.max_files && tokens <= self.planner.read_budget {
+            return Ok(());
+        }
+
+        let (ids, candidates): (Vec<String>, Vec<Candidate>) = reads.into_iter().unzip();
+        let question = self.context.question.clone();
+        let scores = score(self, &question, &candidates).await?;
+        let selected = select(
+            &candidates,
+            &scores,
+            self.planner.max_files,
+            self.planner.read_budget,
+        );
+        let dropped: Vec<&String> = ids
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !selected.contains(index))
+            .map(|(_, id)| id)
+            .collect();
+        eprintln!(
+            "Reading the {} most relevant of {} planned files",
+            selected.len(),
+            candidates.len()
+        );
+
+        self.context
+            .plan
+            .retain(|step| !dropped.contains(&&step.id));
+        for step in &mut self.context.plan {
+            step.after.retain(|id| !dropped.contains(&id));
+        }
+        Ok(())
+    }
+
     /// Execute the planned commands
     fn execute_commands(&mut self) -> Result<(), AgentError> {
         self.context.command_results.clear();
+        self.context.consulted_files.clear();
 
-        // Execute each command in the plan
-        for command in &self.context.plan {
-            let cmd_result = if command.starts_with("tree ") {
-                let path = command.strip_prefix("tree ").unwrap_or(".");
-                let path = Path::new(path);
+        // Files the user included explicitly are read even if excluded
+        let unrestricted = ToolContext {
+            base: self.scope.as_ref().map(|scope| scope.dir.as_path()),
+            excluded: &Exclusions::default(),
+            sandbox: None,
+        };
+        for context in &self.forced_context {
+            let command = format!("show_file {}", context.path.display());
+            let output = self.tools.run(&command, &unrestricted)?;
+            let mut text = context.excerpt(&output.text);
+            if text.len() > MAX_TOOL_OUTPUT_BYTES {
+                text.truncate(text.floor_char_boundary(MAX_TOOL_OUTPUT_BYTES));
+                text.push_str("\n[output truncated]");
+            }
+            eprintln!("Including {context} in the context");
+            if let Some(mut file) = output.file {
+                file.range = context.range;
+                self.context.consulted_files.push(file);
+            }
+            self.context.command_results.push((command, text));
+        }
 
-                // Check if path exists
-                if !path.exists() {
-                    return Err(AgentError::PathNotFound(path.to_path_buf()));
+        let plan = self.context.plan.clone();
+        self.run_steps(&plan)
+    }
+
+    /// Let the planner request more commands based on the results gathered so far
+    ///
+    /// Runs at most `follow_up_rounds` rounds, stopping as soon as the planner asks for
+    /// nothing new.
+    async fn follow_up(&mut self) -> Result<(), AgentError> {
+        for round in 1..=self.planner.follow_up_rounds {
+            let mut results = String::new();
+            for (cmd, result) in &self.context.command_results {
+                results.push_str(&format!("## Command: {cmd}\n\n```\n{result}\n```\n\n"));
+            }
+
+            let messages = vec![
+                Message {
+                    role: "system".to_string(),
+                    content: self.planner_prompt(),
+                },
+                Message {
+                    role: "user".to_string(),
+                    content: format!(
+                        "Question: {}\nQuestion type: {}\n\nCommand results so far:\n\n{results}If these results point to files or directories that must be inspected to answer the question, return a JSON array of the additional commands to run, written like the commands of the plan. Return [] if nothing else is needed.",
+                        self.context.question,
+                        self.context.question_type
+                    ),
+                },
+            ];
+
+            let response = self.chat(messages).await?;
+            let Some(choice) = response.choices.first() else {
+                return Err(AgentError::PlanningFailed);
+            };
+            let steps = match parse_plan(&choice.message.content, &self.tools.names()) {
+                Ok(steps) => steps,
+                Err(err) => {
+                    eprintln!("Could not parse follow-up commands ({err}); skipping follow-ups");
+                    return Ok(());
                 }
+            };
+
+            let mut steps = follow_up_steps(round, steps, &self.context.plan);
+            let policy = &self.policy;
+            let denied = drop_denied(&mut steps, |tool| {
+                policy.permission(tool) == Permission::Deny
+            });
+            for step in denied {
+                eprintln!("Skipping `{}`: the tool is disabled", step.command);
+            }
+            if steps.is_empty() {
+                return Ok(());
+            }
+
+            eprintln!(
+                "Follow-up round {round}: {}",
+                steps
+                    .iter()
+                    .map(|step| step.command.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            self.run_steps(&steps)?;
+            self.context.plan.extend(steps);
+        }
+        Ok(())
+    }
 
-                // Directly call the generate_tree function from tree module
-                generate_tree(path, "", None, None)
-            } else if command.starts_with("show_file ") {
-                let path = command.strip_prefix("show_file ").unwrap_or("");
-                let path = Path::new(path);
-
-                // Directly call the read_file_content function from show_file module
-                match read_file_content(path) {
-                    Ok(content) => content,
-                    Err(e) => match e {
-                        FileReadError::NotFound => {
-                            return Err(AgentError::PathNotFound(path.to_path_buf()));
+    /// Returns the command that runs for the planned `command`
+    ///
+    /// In signatures mode, file reads show signatures until a review asks for more detail.
+    fn effective_command(&self, command: &str) -> String {
+        match command.strip_prefix("show_file ") {
+            Some(path)
+                if self.planner.context == ContextMode::Signatures
+                    && !self.context.full_detail
+                    && !reads_range(command) =>
+            {
+                format!("show_signatures {path}")
+            }
+            _ => command.to_string(),
+        }
+    }
+
+    /// Run plan steps and append their results
+    ///
+    /// Steps run stage by stage in dependency order; the steps within a stage run
+    /// concurrently. Results are recorded in plan order.
+    fn run_steps(&mut self, steps: &[PlanStep]) -> Result<(), AgentError> {
+        let stages = stages(steps).map_err(AgentError::InvalidPlan)?;
+
+        // Ask for permissions up front so prompts are not interleaved with execution
+        let mut outputs: Vec<Option<ToolOutput>> = steps.iter().map(|_| None).collect();
+        for (index, step) in steps.iter().enumerate() {
+            if !self.authorize(&step.command)? {
+                // The model is told, so it can answer without the output or plan around it
+                eprintln!("Skipping `{}`: declined", step.command);
+                outputs[index] = Some(ToolOutput {
+                    text: "The user declined to run this command.".to_string(),
+                    file: None,
+                    duration_ms: 0,
+                });
+            }
+        }
+
+        let context = ToolContext {
+            base: self.scope.as_ref().map(|scope| scope.dir.as_path()),
+            excluded: &self.excluded,
+            sandbox: Some(&self.sandbox),
+        };
+        let commands: Vec<String> = steps
+            .iter()
+            .map(|step| self.effective_command(&step.command))
+            .collect();
+        let tools = &self.tools;
+        let context = &context;
+        for stage in stages {
+            let results: Vec<(usize, Result<ToolOutput, AgentError>)> = thread::scope(|scope| {
+                let handles: Vec<_> = stage
+                    .iter()
+                    .filter(|&&index| outputs[index].is_none())
+                    .map(|&index| {
+                        let command = commands[index].as_str();
+                        (index, scope.spawn(move || tools.run(command, context)))
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|(index, handle)| {
+                        let result = handle
+                            .join()
+                            .unwrap_or(Err(AgentError::CommandExecutionFailed));
+                        (index, result)
+                    })
+                    .collect()
+            });
+            for (index, result) in results {
+                let output = match result {
+                    Ok(output) => output,
+                    // A path that cannot be read should not sink the answer; the model is
+                    // told, and the file is listed if no answer passes the review
+                    Err(err) => {
+                        let Some(path) = err.unreadable_path() else {
+                            return Err(err);
+                        };
+                        if !self
+                            .context
+                            .unreadable_files
+                            .iter()
+                            .any(|file| file.path == path)
+                        {
+                            self.context.unreadable_files.push(UnreadableFile {
+                                path: path.to_path_buf(),
+                                error: err.to_string(),
+                            });
                         }
-                        FileReadError::IsDirectory => {
-                            return Err(AgentError::PathIsDirectory(path.to_path_buf()));
+                        ToolOutput {
+                            text: format!("Could not run this command: {err}"),
+                            file: None,
+                            duration_ms: 0,
                         }
-                        FileReadError::Io(io_err) => return Err(AgentError::IoError(io_err)),
-                    },
-                }
-            } else {
-                return Err(AgentError::UnknownCommand(command.clone()));
+                    }
+                };
+                outputs[index] = Some(output);
+            }
+        }
+
+        for (command, output) in commands.iter().zip(outputs) {
+            let Some(output) = output else {
+                continue;
             };
+            let mut cmd_result = output.text;
+
+            let bytes = cmd_result.len();
+            let mut truncated = bytes > MAX_TOOL_OUTPUT_BYTES;
+            if command.starts_with("show_file ")
+                && !reads_range(command)
+                && let Some(elided) = elide(
+                    &cmd_result,
+                    self.planner.file_budget,
+                    &keywords(&self.context.question),
+                )
+            {
+                cmd_result = elided;
+                truncated = cmd_result.len() > MAX_TOOL_OUTPUT_BYTES;
+                eprintln!(
+                    "Elided the middle of {} to fit the file budget",
+                    &command["show_file ".len()..]
+                );
+            }
+            if truncated {
+                cmd_result.truncate(cmd_result.floor_char_boundary(MAX_TOOL_OUTPUT_BYTES));
+                cmd_result.push_str("\n[output truncated]");
+            }
 
             // Truncate output for logging
-            let preview_len = std::cmp::min(100, cmd_result.len());
-            println!(
-                "Command result ({}): {}{}",
+            let preview_len = cmd_result.floor_char_boundary(100);
+            eprintln!(
+                "Command result ({}, {} ms, {} bytes{}): {}{}",
                 command,
+                output.duration_ms,
+                bytes,
+                if truncated { ", truncated" } else { "" },
                 &cmd_result[..preview_len],
                 if cmd_result.len() > preview_len {
                     "..."
@@ -385,6 +1688,15 @@ impl Agent {
                 }
             );
 
+            if let Some(file) = output.file {
+                self.context.consulted_files.push(file);
+            }
+            self.context.tool_calls.push(ToolCall {
+                command: command.clone(),
+                duration_ms: output.duration_ms,
+                bytes,
+                truncated,
+            });
             self.context
                 .command_results
                 .push((command.clone(), cmd_result));
@@ -393,36 +1705,169 @@ impl Agent {
         Ok(())
     }
 
+    /// Check a planned command against the tool policy, asking the user when required
+    ///
+    /// Returns whether the command may run, which is `false` if the user declined it, or
+    /// `AgentError::ToolDenied` if the policy denies the tool outright
+    fn authorize(&self, command: &str) -> Result<bool, AgentError> {
+        match self.policy.permission(tool_name(command)) {
+            Permission::Allow => Ok(true),
+            Permission::Deny => Err(AgentError::ToolDenied(command.to_string())),
+            Permission::Ask => confirm(&format!("Allow command `{command}`?")),
+        }
+    }
+
+    /// Switches to the large-context model if a prompt of `needed` tokens does not fit the
+    /// model's window but fits the larger one, returning the model to switch back to
+    ///
+    /// # Arguments
+    ///
+    /// * `needed` - Tokens of the prompt with the full command results, and of the reply
+    fn upgrade_context(&mut self, needed: usize) -> Option<(String, Capabilities)> {
+        let large = self.large_context.as_ref()?;
+        if needed <= self.capabilities.max_context {
+            return None;
+        }
+        let cost = large
+            .price
+            .map(|price| format!(", about ${:.2} for the prompt", prompt_cost(needed, price)))
+            .unwrap_or_default();
+        eprintln!(
+            "The context needs about {}k tokens, more than the {}k window of {}; answering with {} ({}k window{cost})",
+            needed / 1000,
+            self.capabilities.max_context / 1000,
+            self.model_id,
+            large.model_id,
+            large.capabilities.max_context / 1000
+        );
+        let model_id = std::mem::replace(&mut self.model_id, large.model_id.clone());
+        let capabilities = std::mem::replace(&mut self.capabilities, large.capabilities);
+        Some((model_id, capabilities))
+    }
+
     /// Generate an answer based on command results
     async fn create_answer(&mut self) -> Result<(), AgentError> {
+        let history = self.history_text();
+        // Files read for earlier answers may have been edited since
+        let changes = self
+            .changes
+            .as_ref()
+            .map(|diff| {
+                format!("Changes to files since the previous answer:\n\n```diff\n{diff}```\n\n")
+            })
+            .unwrap_or_default();
+        let mut system_prompt = format!("You are an assistant that analyzes code repositories. Create a helpful response based on executed commands. {} {CITATION_PROMPT} {UNKNOWN_PROMPT}", self.context.question_type.answer_guidance());
+        if let Some(guidance) = self.style.answer_guidance() {
+            system_prompt.push(' ');
+            system_prompt.push_str(guidance);
+        }
+        if let Some(language) = &self.language {
+            system_prompt.push_str(&format!(" Write the answer in {language}."));
+        }
+        let question = self.context.question.clone();
+        let prompt = |results: &str| {
+            format!(
+                "{changes}Question: {question}\n\nCommand results:\n\n{results}\n\nBased on the above information, please provide a comprehensive answer to the question."
+            )
+        };
+
+        // Shorten the results for this prompt only, so a revision after the planner reads
+        // more still starts from the full ones
+        let reserved = self
+            .style
+            .max_tokens()
+            .map_or(ANSWER_RESERVE_TOKENS, |tokens| tokens as usize);
+        let fixed = count_tokens(&system_prompt)
+            + count_tokens(&history)
+            + count_tokens(&prompt(""))
+            + reserved;
+        let needed = fixed
+            + self
+                .context
+                .command_results
+                .iter()
+                .map(|(cmd, result)| count_tokens(cmd) + count_tokens(result))
+                .sum::<usize>();
+        let default_model = self.upgrade_context(needed);
+        let budget = self.capabilities.max_context.saturating_sub(fixed);
+        let mut results = self.context.command_results.clone();
+        let shortened = fit_results(&mut results, budget, &keywords(&question));
+        if !shortened.is_empty() {
+            eprintln!(
+                "Shortened the results of {} to fit the context window of {}",
+                shortened.join(", "),
+                self.model_id
+            );
+        }
+
         // Prepare command results for the prompt
         let mut command_results_text = String::new();
-        for (cmd, result) in &self.context.command_results {
-            command_results_text.push_str(&format!("## Command: {cmd}\n\n```\n{result}\n```\n\n",));
+        let mut sections = Vec::new();
+        for (cmd, result) in &results {
+            // Excerpts of files get IDs for the answer to cite
+            let (result, chunks) = if let Some(arg) = cmd.strip_prefix("show_file ") {
+                match split_range(arg) {
+                    Ok((path, Some(_))) => anchor_excerpt(path, result),
+                    _ => anchor_file(arg, result),
+                }
+            } else if cmd.starts_with("search ") {
+                anchor_snippets(result)
+            } else {
+                (result.clone(), Vec::new())
+            };
+            self.context
+                .chunks
+                .extend(chunks.into_iter().map(|chunk| (chunk.id(), chunk)));
+            let section = format!("## Command: {cmd}\n\n```\n{result}\n```\n\n");
+            command_results_text.push_str(&section);
+            sections.push((cmd.clone(), section));
         }
+        let user_prompt = prompt(&command_results_text);
 
-        let messages = vec![
-            Message {
-                role: "system".to_string(),
-                content: "You are an assistant that analyzes code repositories. Create a helpful response based on executed commands.".to_string(),
-            },
-            Message {
-                role: "user".to_string(),
-                content: format!(
-                    "Question: {}\n\nCommand results:\n\n{}\n\nBased on the above information, please provide a comprehensive answer to the question.",
-                    self.context.question,
-                    command_results_text
-                ),
-            },
+        let mut parts = vec![
+            ("history".to_string(), history.as_str()),
+            ("changes".to_string(), changes.as_str()),
+            ("question".to_string(), self.context.question.as_str()),
         ];
+        parts.extend(
+            sections
+                .iter()
+                .map(|(label, section)| (label.clone(), section.as_str())),
+        );
+        self.context.context_reports.push(ContextReport::new(
+            &format!("{system_prompt}{history}{user_prompt}"),
+            parts,
+        ));
+
+        // Earlier turns stay the same while the answer is revised, so they extend the
+        // cacheable prefix
+        let mut messages = vec![Message {
+            role: "system".to_string(),
+            content: system_prompt,
+        }];
+        if !history.is_empty() {
+            messages.push(Message {
+                role: "user".to_string(),
+                content: history,
+            });
+        }
+        let stable_prefix = messages.len();
+        messages.push(Message {
+            role: "user".to_string(),
+            content: user_prompt,
+        });
 
         let response = self
-            .client
-            .chat_completion(messages, self.model_id.clone())
-            .await?;
+            .chat_with_prefix(messages, stable_prefix, self.style.max_tokens(), false)
+            .await;
+        if let Some((model_id, capabilities)) = default_model {
+            self.model_id = model_id;
+            self.capabilities = capabilities;
+        }
+        let response = response?;
         if let Some(choice) = response.choices.first() {
             self.context.current_answer = Some(choice.message.content.clone());
-            println!("Generated answer: {}", choice.message.content);
+            eprintln!("Generated answer: {}", choice.message.content);
             Ok(())
         } else {
             Err(AgentError::AnswerGenerationFailed)
@@ -435,36 +1880,348 @@ impl Agent {
             return Err(AgentError::NoAnswerToReview);
         };
 
-        let messages = vec![
-            Message {
-                role: "system".to_string(),
-                content: "You are a critical reviewer. Evaluate if the answer adequately addresses the question.".to_string(),
+        let input = ReviewInput {
+            question: self.context.question.clone(),
+            answer: answer.clone(),
+            consulted_files: self.context.consulted_files.clone(),
+        };
+        self.context.attempts.push(input.clone());
+        let verdict = match precheck(&input) {
+            Some(verdict) => {
+                eprintln!("Pre-check rejected the answer, skipping the review call");
+                verdict
+            }
+            None => {
+                let reviewer = Arc::clone(&self.reviewer);
+                reviewer.review(&input, self).await?
+            }
+        };
+
+        let review = match &verdict {
+            Verdict::Pass => "YES".to_string(),
+            Verdict::Fail(reason) => format!("NO: {reason}"),
+        };
+        eprintln!("Review result: {review}");
+        self.context.review_result = Some(review);
+        self.context.review_feedback.push(match verdict {
+            Verdict::Pass => None,
+            Verdict::Fail(ref reason) => Some(reason.clone()),
+        });
+
+        Ok(verdict.passed())
+    }
+}
+
+#[async_trait]
+impl ReviewModel for Agent {
+    async fn complete(&mut self, messages: Vec<Message>) -> Result<String, AgentError> {
+        let response = self.chat(messages).await?;
+        response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content)
+            .ok_or(AgentError::ReviewFailed)
+    }
+}
+
+/// Builds the symbol index of `root`, reusing the one computed by `nishiogi warm` if the
+/// files have not changed since
+fn symbol_index(root: &Path) -> SymbolIndex {
+    #[cfg(feature = "sessions")]
+    if let Some(index) = crate::warm::cached_symbol_index(root) {
+        return index;
+    }
+    SymbolIndex::build(root)
+}
+
+/// Converts `duration` to whole milliseconds
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Removes the steps of `plan` whose tool is `denied`, along with the dependencies on them,
+/// returning the removed steps
+///
+/// # Arguments
+///
+/// * `plan` - The steps to filter
+/// * `denied` - Whether the policy denies a tool, by name
+fn drop_denied(plan: &mut Vec<PlanStep>, denied: impl Fn(&str) -> bool) -> Vec<PlanStep> {
+    let (dropped, kept): (Vec<PlanStep>, Vec<PlanStep>) = plan
+        .drain(..)
+        .partition(|step| denied(tool_name(&step.command)));
+    *plan = kept;
+    for step in plan.iter_mut() {
+        step.after
+            .retain(|id| !dropped.iter().any(|dropped| &dropped.id == id));
+    }
+    dropped
+}
+
+/// Turns the steps the planner asked for in follow-up round `round` into plan steps
+///
+/// Commands that were already planned are dropped, and the IDs of the rest are prefixed
+/// so they cannot collide with earlier steps. Dependencies are only kept on steps of the
+/// same round, since earlier steps already ran and dropped ones never will.
+///
+/// # Arguments
+///
+/// * `round` - The number of the follow-up round
+/// * `steps` - The steps parsed from the planner's reply
+/// * `planned` - The steps planned so far
+fn follow_up_steps(round: usize, steps: Vec<PlanStep>, planned: &[PlanStep]) -> Vec<PlanStep> {
+    let prefix = format!("follow-up {round}.");
+    let steps: Vec<PlanStep> = steps
+        .into_iter()
+        .filter(|step| {
+            !planned
+                .iter()
+                .any(|planned| planned.command == step.command)
+        })
+        .collect();
+    let ids: Vec<String> = steps.iter().map(|step| step.id.clone()).collect();
+    steps
+        .into_iter()
+        .map(|step| PlanStep {
+            id: format!("{prefix}{}", step.id),
+            command: step.command,
+            after: step
+                .after
+                .into_iter()
+                .filter(|id| ids.contains(id))
+                .map(|id| format!("{prefix}{id}"))
+                .collect(),
+        })
+        .collect()
+}
+
+/// Ask the user a yes/no question on the terminal, defaulting to no
+fn confirm(prompt: &str) -> Result<bool, AgentError> {
+    eprint!("{prompt} [y/N] ");
+    io::stderr().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    let answer = input.trim().to_lowercase();
+
+    Ok(answer == "y" || answer == "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Mutex};
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        github_copilot_client::{ChatChoice, Model},
+        org_policy::{OrgPolicy, OrgPolicyConfig},
+        policy::PolicyConfig,
+    };
+
+    /// Replies to every prompt with the next of its scripted replies.
+    struct ScriptedProvider {
+        models: Vec<Model>,
+        replies: Mutex<Vec<String>>,
+    }
+
+    impl ScriptedProvider {
+        fn new(replies: &[&str]) -> Box<Self> {
+            Box::new(Self {
+                models: vec![Model {
+                    id: "scripted".to_string(),
+                    name: "Scripted".to_string(),
+                    version: None,
+                    tokenizer: None,
+                    max_input_tokens: None,
+                    max_output_tokens: None,
+                }],
+                replies: Mutex::new(replies.iter().map(|reply| reply.to_string()).collect()),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl LlmProvider for ScriptedProvider {
+        fn name(&self) -> &'static str {
+            "scripted"
+        }
+
+        fn models(&self) -> &[Model] {
+            &self.models
+        }
+
+        async fn chat(
+            &self,
+            _messages: Vec<Message>,
+            _model_id: &str,
+            _stable_prefix: usize,
+            _max_tokens: Option<u32>,
+        ) -> Result<ChatResponse, CopilotError> {
+            let content = self.replies.lock().expect("Replies lock").remove(0);
+            Ok(ChatResponse {
+                choices: vec![ChatChoice {
+                    message: Message {
+                        role: "assistant".to_string(),
+                        content,
+                    },
+                    finish_reason: None,
+                    usage: None,
+                }],
+                usage: None,
+            })
+        }
+    }
+
+    /// A policy denying the tools that run programs.
+    fn deny_exec() -> Policy {
+        Policy::new(
+            PolicyConfig {
+                exec: Some(Permission::Deny),
+                ..PolicyConfig::default()
             },
-            Message {
-                role: "user".to_string(),
-                content: format!(
-                    "Question: {}\n\nAnswer: {}\n\nDoes this answer adequately address the question? Only respond with 'YES' if the answer is adequate, or 'NO: <reason>' if not.",
-                    self.context.question,
-                    answer
-                ),
+            false,
+        )
+    }
+
+    #[test]
+    fn test_drop_denied() {
+        let mut plan = vec![
+            PlanStep::new("1", "run cargo test"),
+            PlanStep::new("2", "show_file src/lib.rs"),
+            PlanStep {
+                id: "3".to_string(),
+                command: "grep fn src".to_string(),
+                after: vec!["1".to_string(), "2".to_string()],
             },
         ];
+        let policy = deny_exec();
+        let dropped = drop_denied(&mut plan, |tool| {
+            policy.permission(tool) == Permission::Deny
+        });
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].command, "run cargo test");
+        assert_eq!(plan.len(), 2);
+        assert_eq!(plan[1].after, vec!["2".to_string()]);
+    }
 
-        let response = self
-            .client
-            .chat_completion(messages, self.model_id.clone())
-            .await?;
-        if let Some(choice) = response.choices.first() {
-            let review = choice.message.content.clone();
-            self.context.review_result = Some(review.clone());
-            println!("Review result: {review}");
+    #[test]
+    fn test_follow_up_steps() {
+        let planned = vec![PlanStep::new("1", "show_file src/main.rs")];
+        let steps = vec![
+            PlanStep::new("1", "show_file src/main.rs"),
+            PlanStep {
+                id: "2".to_string(),
+                command: "show_file src/lib.rs".to_string(),
+                after: vec!["1".to_string(), "missing".to_string()],
+            },
+            PlanStep {
+                id: "3".to_string(),
+                command: "grep fn src".to_string(),
+                after: vec!["2".to_string()],
+            },
+        ];
+        let steps = follow_up_steps(2, steps, &planned);
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].id, "follow-up 2.2");
+        assert!(steps[0].after.is_empty());
+        assert_eq!(steps[1].after, vec!["follow-up 2.2".to_string()]);
+        assert!(stages(&steps).is_ok());
+    }
 
-            // Simple check if the review is positive
-            let passed = review.to_uppercase().starts_with("YES");
+    #[tokio::test]
+    async fn test_follow_up_skips_denied_tools() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let main = dir.path().join("main.rs");
+        let lib = dir.path().join("lib.rs");
+        fs::write(&main, "mod lib;\nfn main() {}\n").expect("Failed to write file");
+        fs::write(&lib, "pub fn api() {}\n").expect("Failed to write file");
 
-            Ok(passed)
-        } else {
-            Err(AgentError::ReviewFailed)
+        let reply = serde_json::json!([
+            {"id": "a", "command": format!("show_file {}", lib.display()), "after": ["1"]},
+            {"id": "b", "command": "run cat lib.rs"},
+            {"id": "c", "command": format!("show_file {}", main.display())},
+            {"id": "d", "command": format!("tree {}", dir.path().display()), "after": ["b", "c"]},
+        ])
+        .to_string();
+        let mut agent = Agent::with_client(
+            ScriptedProvider::new(&[&reply, "[]"]),
+            "scripted".to_string(),
+        )
+        .expect("The model is offered")
+        .with_policy(deny_exec())
+        .with_planner(PlannerConfig {
+            follow_up_rounds: 2,
+            ..PlannerConfig::default()
+        })
+        .with_root(dir.path());
+        let initial = PlanStep::new("1", format!("show_file {}", main.display()));
+        agent
+            .run_steps(std::slice::from_ref(&initial))
+            .expect("The plan runs");
+        agent.context.plan.push(initial);
+
+        agent.follow_up().await.expect("Follow-ups run");
+        let commands: Vec<&str> = agent
+            .context
+            .command_results
+            .iter()
+            .map(|(command, _)| command.as_str())
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                format!("show_file {}", main.display()),
+                format!("show_file {}", lib.display()),
+                format!("tree {}", dir.path().display()),
+            ]
+        );
+        assert!(agent
+            .context
+            .plan
+            .iter()
+            .all(|step| !step.command.starts_with("run ")));
+    }
+
+    #[tokio::test]
+    async fn test_rank_reads_skips_hidden_files() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        for file in ["lib.rs", "secrets/keys.rs", "generated.rs"] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().expect("Path has a parent"))
+                .expect("Failed to create dir");
+            fs::write(&path, "pub fn api() {}\n").expect("Failed to write file");
         }
+        org_policy::set_test_policy(
+            OrgPolicy::new(OrgPolicyConfig {
+                forbidden_paths: vec!["secrets".to_string()],
+                ..OrgPolicyConfig::default()
+            })
+            .expect("Failed to compile policy"),
+        );
+
+        // Ranking would ask the model, which has no reply scripted, about the symbols
+        let mut agent = Agent::with_client(ScriptedProvider::new(&[]), "scripted".to_string())
+            .expect("The model is offered")
+            .with_planner(PlannerConfig {
+                max_files: 1,
+                ..PlannerConfig::default()
+            })
+            .with_exclusions(Exclusions::new(vec!["generated.rs".to_string()]))
+            .with_root(dir.path());
+        agent.context.plan = ["lib.rs", "secrets/keys.rs", "generated.rs"]
+            .iter()
+            .enumerate()
+            .map(|(index, file)| {
+                PlanStep::new(
+                    (index + 1).to_string(),
+                    format!("show_file {}", dir.path().join(file).display()),
+                )
+            })
+            .collect();
+        agent.rank_reads().await.expect("Ranking runs");
+        assert_eq!(agent.context.plan.len(), 3);
     }
 }
@@ -0,0 +1,228 @@
+//! # Path Anonymization
+//!
+//! This module hides a project's structure in sessions shared outside the machine, enabled
+//! with `hash_paths = true` in the `[privacy]` section of the configuration. Every file
+//! path in an exported session is replaced with a salted hash that keeps only the file
+//! extension, such as `p-3f9a1c0e2b7d.rs`: the consulted files, the arguments of recorded
+//! tool calls, the working directory, and mentions of those paths in questions and answers.
+//!
+//! The salt is generated once and kept in `~/.nishiogi/path-salt`, so the same path hashes
+//! the same way across exports, and maintainers reading a shared session can correlate
+//! files without being able to recover their names. The owner can map a hash back by
+//! hashing their own paths.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::data_dir,
+    policy::tool_name,
+    session::{SessionEntry, SessionRecord},
+};
+
+/// Name of the file holding the salt, inside the data directory.
+const SALT_FILE: &str = "path-salt";
+
+/// Hex digits of the hash kept in an anonymized path.
+const HASH_LEN: usize = 12;
+
+/// Replaces file paths with salted hashes.
+#[derive(Debug, Clone)]
+pub struct PathHasher {
+    salt: String,
+}
+
+impl PathHasher {
+    /// Creates a hasher using `salt`.
+    pub fn new(salt: impl Into<String>) -> Self {
+        Self { salt: salt.into() }
+    }
+
+    /// Creates a hasher with the local salt, generating and saving one on first use.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data directory is unknown or the salt cannot be read or
+    /// written.
+    pub fn local() -> io::Result<Self> {
+        let dir = data_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+        let path = dir.join(SALT_FILE);
+        match fs::read_to_string(&path) {
+            Ok(salt) if !salt.trim().is_empty() => return Ok(Self::new(salt.trim())),
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos());
+        let seed = format!("{nanos}-{}-{}", std::process::id(), dir.display());
+        let salt = format!("{:x}", Sha256::digest(seed.as_bytes()));
+        fs::create_dir_all(&dir)?;
+        fs::write(&path, format!("{salt}\n"))?;
+        Ok(Self::new(salt))
+    }
+
+    /// Returns the anonymized form of `path`, keeping its extension.
+    pub fn hash_path(&self, path: &Path) -> String {
+        let normalized = path.to_string_lossy();
+        let normalized = normalized.trim_start_matches("./");
+        let digest = format!(
+            "{:x}",
+            Sha256::digest(format!("{}\0{normalized}", self.salt).as_bytes())
+        );
+        let hash = &digest[..HASH_LEN];
+        match path.extension() {
+            Some(extension) => format!("p-{hash}.{}", extension.to_string_lossy()),
+            None => format!("p-{hash}"),
+        }
+    }
+
+    /// Returns a copy of `record` with every file path anonymized.
+    pub fn anonymize(&self, record: &SessionRecord) -> SessionRecord {
+        let mut record = record.clone();
+        record.working_dir = record
+            .working_dir
+            .as_ref()
+            .map(|dir| PathBuf::from(self.hash_path(dir)));
+        for entry in &mut record.entries {
+            self.anonymize_entry(entry);
+        }
+        record
+    }
+
+    fn anonymize_entry(&self, entry: &mut SessionEntry) {
+        // Known paths are also replaced where the question or answer mentions them, longest
+        // first so a path is not replaced inside a longer one
+        let mut paths: Vec<String> = Vec::new();
+        for file in &mut entry.provenance.files {
+            paths.push(file.path.to_string_lossy().into_owned());
+            file.path = PathBuf::from(self.hash_path(&file.path));
+        }
+        for call in &mut entry.provenance.tool_calls {
+            let (command, arguments) = self.anonymize_command(&call.command);
+            paths.extend(arguments);
+            call.command = command;
+        }
+        paths.sort_by_key(|path| std::cmp::Reverse(path.len()));
+        paths.dedup();
+        // Bare names such as `src` could match ordinary words
+        for path in paths
+            .iter()
+            .filter(|path| path.len() > 1 && path.contains(['/', '.']))
+        {
+            let hashed = self.hash_path(Path::new(path));
+            entry.question = entry.question.replace(path.as_str(), &hashed);
+            entry.answer = entry.answer.replace(path.as_str(), &hashed);
+        }
+    }
+
+    /// Anonymizes the path arguments of a tool command, returning the command and the
+    /// paths it named.
+    ///
+    /// The pattern of `search` and `grep` and the line range of `blame` are kept. Commands of `run`,
+    /// `run_rust`, and unknown tools are replaced entirely, since their arguments cannot be
+    /// told apart.
+    fn anonymize_command(&self, command: &str) -> (String, Vec<String>) {
+        let tool = tool_name(command);
+        let arguments: Vec<&str> = command.split_whitespace().skip(1).collect();
+        let kept = match tool {
+            "search" | "grep" => 1,
+            "tree" | "show_file" | "show_signatures" | "outline" | "coverage" | "blame"
+            | "routes" | "usage" | "refresh" | "owners" | "licenses" | "workflows"
+            | "containers" | "makefiles" => 0,
+            _ => return (format!("{tool} [redacted]"), Vec::new()),
+        };
+        let mut paths = Vec::new();
+        let mut words = vec![tool.to_string()];
+        for (index, argument) in arguments.iter().enumerate() {
+            let is_range = tool == "blame" && index > 0;
+            if index < kept || is_range {
+                words.push((*argument).to_string());
+            } else {
+                paths.push((*argument).to_string());
+                words.push(self.hash_path(Path::new(argument)));
+            }
+        }
+        (words.join(" "), paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{
+        provenance::FileProvenance,
+        session::{Provenance, ToolCall},
+    };
+
+    #[test]
+    fn test_anonymize() {
+        let hasher = PathHasher::new("salt");
+        let hashed = hasher.hash_path(Path::new("src/agent.rs"));
+        assert!(hashed.starts_with("p-") && hashed.ends_with(".rs"));
+        assert_eq!(hasher.hash_path(Path::new("./src/agent.rs")), hashed);
+        assert_ne!(
+            PathHasher::new("other").hash_path(Path::new("src/agent.rs")),
+            hashed
+        );
+
+        let call = |command: &str| ToolCall {
+            command: command.to_string(),
+            duration_ms: 1,
+            bytes: 10,
+            truncated: false,
+        };
+        let mut record = SessionRecord::new(Some(PathBuf::from("/home/me/secret-project")));
+        record.entries.push(SessionEntry {
+            question: "What does src/agent.rs do?".to_string(),
+            answer: "See src/agent.rs:10 and src/plan.rs.".to_string(),
+            answered_at: Utc::now(),
+            provenance: Provenance {
+                files: vec![FileProvenance {
+                    path: PathBuf::from("src/agent.rs"),
+                    range: None,
+                    sha256: String::new(),
+                }],
+                tool_calls: vec![
+                    call("search fn\\s+run src"),
+                    call("blame src/plan.rs 3-9"),
+                    call("run cargo test -p secret"),
+                ],
+                ..Provenance::default()
+            },
+            question_embedding: None,
+            truncated: false,
+        });
+
+        let anonymized = hasher.anonymize(&record);
+        let entry = &anonymized.entries[0];
+        let plan = hasher.hash_path(Path::new("src/plan.rs"));
+        assert_eq!(entry.question, format!("What does {hashed} do?"));
+        assert_eq!(entry.answer, format!("See {hashed}:10 and {plan}."));
+        assert_eq!(entry.provenance.files[0].path, PathBuf::from(&hashed));
+        let commands: Vec<&str> = entry
+            .provenance
+            .tool_calls
+            .iter()
+            .map(|call| call.command.as_str())
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                format!("search fn\\s+run {}", hasher.hash_path(Path::new("src"))),
+                format!("blame {plan} 3-9"),
+                "run [redacted]".to_string(),
+            ]
+        );
+        assert!(!format!("{:?}", anonymized.working_dir).contains("secret"));
+    }
+}
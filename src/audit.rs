@@ -0,0 +1,346 @@
+//! # Security Audit
+//!
+//! This module implements `nishiogi audit`. The search subsystem looks for patterns that
+//! often indicate security problems (unsafe blocks, process execution, SQL built by string
+//! concatenation, and hard-coded secrets). Each finding is then sent to the model together
+//! with its surrounding lines to be triaged, and the result is a report ranked by severity.
+//!
+//! Pattern matches are only candidates: the model decides whether a match is a real
+//! problem, and findings it considers harmless are listed last as informational.
+
+use std::{fmt, fmt::Write, path::Path};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    agent::AgentError,
+    github_copilot_client::Message,
+    review::ReviewModel,
+    search::{render_snippets, search, SearchOptions, Snippet},
+};
+
+/// Lines of context shown around each match.
+const CONTEXT_LINES: usize = 3;
+
+/// Token budget of the matches collected for a single rule.
+const RULE_TOKEN_BUDGET: usize = 4000;
+
+/// Upper bound on the findings sent to the model, to keep the audit affordable.
+pub const MAX_FINDINGS: usize = 50;
+
+/// A pattern that often indicates a security problem.
+#[derive(Debug)]
+pub struct AuditRule {
+    /// Short name of the problem.
+    pub name: &'static str,
+    /// Regular expression matching candidate lines.
+    pub pattern: &'static str,
+    /// What the model should look for when triaging a match.
+    pub concern: &'static str,
+}
+
+/// The patterns searched for.
+pub const RULES: &[AuditRule] = &[
+    AuditRule {
+        name: "hard-coded secret",
+        pattern: r#"(?i)(api[_-]?key|secret|passw(or)?d|access[_-]?token)\s*[:=]\s*["'][^"'\s]{8,}["']|AKIA[0-9A-Z]{16}|-----BEGIN [A-Z ]*PRIVATE KEY-----"#,
+        concern: "a credential or key committed to the repository",
+    },
+    AuditRule {
+        name: "SQL string concatenation",
+        pattern: r#"(?i)(format!\s*\(\s*"|["'])\s*(select|insert|update|delete)\b[^"'\n]*["']\s*(\+|%|\.\s*format|,\s*\w)"#,
+        concern: "SQL built from strings that may contain untrusted input (SQL injection)",
+    },
+    AuditRule {
+        name: "process execution",
+        pattern: r"Command::new|\bos\.system\s*\(|\bsubprocess\.|child_process|\bexec(Sync|File)?\s*\(|\beval\s*\(",
+        concern:
+            "a command or code evaluation that may include untrusted input (command injection)",
+    },
+    AuditRule {
+        name: "unsafe block",
+        pattern: r"\bunsafe\s*(\{|fn\b|impl\b)",
+        concern: "unsafe code whose invariants may not be upheld (memory safety)",
+    },
+];
+
+/// How serious a finding is, most serious first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Exploitable now with serious impact.
+    Critical,
+    /// Likely exploitable.
+    High,
+    /// Exploitable under some conditions.
+    Medium,
+    /// Unlikely to be exploitable, but worth tidying up.
+    Low,
+    /// Not a security problem.
+    Info,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Severity::Critical => "CRITICAL",
+            Severity::High => "HIGH",
+            Severity::Medium => "MEDIUM",
+            Severity::Low => "LOW",
+            Severity::Info => "INFO",
+        };
+        write!(f, "{label}")
+    }
+}
+
+/// A match of an audit rule.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    /// The rule that matched.
+    pub rule: &'static AuditRule,
+    /// The matching lines with their context.
+    pub snippet: Snippet,
+}
+
+/// A finding with the model's assessment.
+#[derive(Debug, Clone)]
+pub struct TriagedFinding {
+    /// The finding that was assessed.
+    pub finding: Finding,
+    /// How serious the model considers it.
+    pub severity: Severity,
+    /// Why, and what to do about it.
+    pub explanation: String,
+}
+
+/// The model's reply to a triage request.
+#[derive(Deserialize)]
+struct Assessment {
+    severity: Severity,
+    explanation: String,
+}
+
+/// Searches the files under `root` for every audit rule.
+///
+/// At most [`MAX_FINDINGS`] findings are returned, taking them from each rule in turn.
+pub fn find_candidates(root: &Path) -> Vec<Finding> {
+    let options = SearchOptions {
+        context_lines: CONTEXT_LINES,
+        max_tokens: RULE_TOKEN_BUDGET,
+    };
+    let per_rule: Vec<Vec<Finding>> = RULES
+        .iter()
+        .map(|rule| {
+            let Ok(pattern) = Regex::new(rule.pattern) else {
+                return Vec::new();
+            };
+            search(root, &pattern, options)
+                .into_iter()
+                .map(|snippet| Finding { rule, snippet })
+                .collect()
+        })
+        .collect();
+
+    // Interleave the rules so one noisy pattern cannot crowd out the others
+    let mut findings = Vec::new();
+    let longest = per_rule.iter().map(Vec::len).max().unwrap_or(0);
+    for index in 0..longest {
+        for rule_findings in &per_rule {
+            if let Some(finding) = rule_findings.get(index)
+                && findings.len() < MAX_FINDINGS
+            {
+                findings.push(finding.clone());
+            }
+        }
+    }
+    findings
+}
+
+/// Asks the model to assess each finding and returns them ranked by severity.
+///
+/// Findings the model could not assess are kept with `Severity::Medium` so they are not
+/// silently dropped.
+///
+/// # Errors
+///
+/// Returns an `AgentError` if a request to the model fails.
+pub async fn triage<M: ReviewModel>(
+    model: &mut M,
+    findings: Vec<Finding>,
+) -> Result<Vec<TriagedFinding>, AgentError> {
+    let total = findings.len();
+    let mut triaged = Vec::with_capacity(total);
+    for (index, finding) in findings.into_iter().enumerate() {
+        eprintln!(
+            "Triaging finding {}/{total}: {} in {}:{}",
+            index + 1,
+            finding.rule.name,
+            finding.snippet.path.display(),
+            finding.snippet.start
+        );
+        let reply = model.complete(triage_messages(&finding)).await?;
+        let (severity, explanation) = match parse_assessment(&reply) {
+            Some(assessment) => (assessment.severity, assessment.explanation),
+            None => (
+                Severity::Medium,
+                format!(
+                    "The model's assessment could not be parsed: {}",
+                    reply.trim()
+                ),
+            ),
+        };
+        triaged.push(TriagedFinding {
+            finding,
+            severity,
+            explanation,
+        });
+    }
+    // Stable sort keeps search ranking within a severity
+    triaged.sort_by_key(|finding| finding.severity);
+    Ok(triaged)
+}
+
+/// Renders the triaged findings as a plain-text report.
+pub fn render_report(findings: &[TriagedFinding]) -> String {
+    let mut report = format!("Security audit: {} finding(s)\n", findings.len());
+    for triaged in findings {
+        let snippet = &triaged.finding.snippet;
+        let _ = write!(
+            report,
+            "\n[{}] {} in {}:{}-{}\n{}\n\n",
+            triaged.severity,
+            triaged.finding.rule.name,
+            snippet.path.display(),
+            snippet.start,
+            snippet.end,
+            triaged.explanation.trim()
+        );
+        for (offset, line) in snippet.excerpt.lines().enumerate() {
+            let _ = writeln!(report, "{:>5} | {line}", snippet.start + offset);
+        }
+    }
+    report
+}
+
+/// Builds the request asking the model to assess one finding.
+fn triage_messages(finding: &Finding) -> Vec<Message> {
+    vec![
+        Message {
+            role: "system".to_string(),
+            content: "You are a security reviewer triaging the results of a pattern-based scan. Matches are often false positives; judge each one from the code shown.".to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!(
+                "The scan flagged this code as a possible {} ({}):\n\n{}\nAssess whether it is a real problem. Respond with JSON only, in this format:\n\n{{\"severity\": \"critical|high|medium|low|info\", \"explanation\": \"why, and how to fix it\"}}\n\nUse \"info\" if it is not a security problem.",
+                finding.rule.name,
+                finding.rule.concern,
+                render_snippets(std::slice::from_ref(&finding.snippet))
+            ),
+        },
+    ]
+}
+
+/// Extracts the assessment from the model's reply, tolerating text around the JSON.
+fn parse_assessment(reply: &str) -> Option<Assessment> {
+    let start = reply.find('{')?;
+    let end = reply.rfind('}')?;
+    serde_json::from_str(reply.get(start..=end)?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use async_trait::async_trait;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::org_policy::{self, OrgPolicy, OrgPolicyConfig};
+
+    /// Replies to triage requests according to the rule that matched.
+    struct ScriptedModel;
+
+    #[async_trait]
+    impl ReviewModel for ScriptedModel {
+        async fn complete(&mut self, messages: Vec<Message>) -> Result<String, AgentError> {
+            let request = &messages[1].content;
+            Ok(if request.contains("hard-coded secret") {
+                r#"Sure: {"severity": "critical", "explanation": "Rotate the key."}"#.to_string()
+            } else if request.contains("unsafe block") {
+                r#"{"severity": "info", "explanation": "Sound."}"#.to_string()
+            } else {
+                "not sure".to_string()
+            })
+        }
+    }
+
+    #[test]
+    fn test_rules_compile() {
+        for rule in RULES {
+            assert!(Regex::new(rule.pattern).is_ok(), "{} is invalid", rule.name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_findings_are_triaged_and_ranked() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        fs::write(
+            root.join("ffi.rs"),
+            "fn f(p: *const u8) -> u8 {\n    unsafe { *p }\n}\n",
+        )
+        .expect("Failed to write file");
+        fs::write(
+            root.join("run.rs"),
+            "fn run(arg: &str) {\n    std::process::Command::new(\"sh\").arg(arg);\n}\n",
+        )
+        .expect("Failed to write file");
+        fs::write(
+            root.join("keys.py"),
+            "API_KEY = \"sk_live_0123456789abcdef\"\n",
+        )
+        .expect("Failed to write file");
+
+        let findings = find_candidates(root);
+        assert_eq!(findings.len(), 3);
+
+        // A file the organization forbids is never searched, so never sent to the model
+        fs::create_dir(root.join("secrets")).expect("Failed to create directory");
+        fs::write(
+            root.join("secrets/keys.py"),
+            "SECRET = \"sk_live_fedcba9876543210\"\n",
+        )
+        .expect("Failed to write file");
+        org_policy::set_test_policy(
+            OrgPolicy::new(OrgPolicyConfig {
+                forbidden_paths: vec!["secrets".to_string()],
+                ..OrgPolicyConfig::default()
+            })
+            .expect("Failed to compile policy"),
+        );
+        assert_eq!(find_candidates(root).len(), 3);
+
+        let triaged = triage(&mut ScriptedModel, findings)
+            .await
+            .expect("Failed to triage");
+        let ranked: Vec<(Severity, PathBuf)> = triaged
+            .iter()
+            .map(|t| (t.severity, t.finding.snippet.path.clone()))
+            .collect();
+        assert_eq!(
+            ranked,
+            vec![
+                (Severity::Critical, root.join("keys.py")),
+                (Severity::Medium, root.join("run.rs")),
+                (Severity::Info, root.join("ffi.rs")),
+            ]
+        );
+
+        let report = render_report(&triaged);
+        assert!(report.starts_with("Security audit: 3 finding(s)\n"));
+        assert!(report.contains("[CRITICAL] hard-coded secret in"));
+        assert!(report.contains("Rotate the key."));
+    }
+}
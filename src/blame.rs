@@ -0,0 +1,336 @@
+//! # Blame Context
+//!
+//! This module implements the `blame` tool, which explains where code came from so answers
+//! to "why does this code exist" questions can cite the historical rationale instead of
+//! only the current behavior:
+//!
+//! - `blame <path> <start>[-<end>]` runs `git blame` on the lines and includes the full
+//!   messages of the commits that last changed them
+//! - `blame <path>` lists the latest commits that changed the file
+//!
+//! Pull request and issue references (`#123`, `.../pull/123`) found in the commit messages
+//! are listed with each commit. Questions that name a file location such as
+//! `src/agent.rs:120` or `src/agent.rs line 120` get a `blame` step added to their plan
+//! automatically (see [`find_line_references`]).
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use chrono::DateTime;
+use regex::Regex;
+
+use crate::git::{git, GitError};
+
+/// Maximum commits whose messages are included.
+const MAX_COMMITS: usize = 10;
+
+/// Characters of a commit message beyond which it is cut.
+const MAX_MESSAGE_CHARS: usize = 1500;
+
+/// A file location such as `src/main.rs:10` or `src/main.rs:10-20`.
+static COLON_REFERENCE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"([\w./-]+\.\w+):(\d+)(?:-(\d+))?").expect("Invalid location pattern")
+});
+
+/// A file location such as `src/main.rs line 10` or `src/main.rs lines 10 to 20`.
+static WORD_REFERENCE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)([\w./-]+\.\w+),?\s+(?:at\s+|on\s+)?(?:lines?|L)\s*(\d+)(?:\s*(?:-|to)\s*(\d+))?",
+    )
+    .expect("Invalid location pattern")
+});
+
+/// The header of a line group in `git blame --porcelain` output.
+static PORCELAIN_HEADER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^([0-9a-f]{40}) \d+ (\d+)").expect("Invalid porcelain header pattern")
+});
+
+/// A pull request or issue reference in a commit message.
+static PR_REFERENCE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:/pull/|/issues/|#)(\d+)\b").expect("Invalid reference pattern")
+});
+
+/// A range of lines in a file mentioned in a question.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineReference {
+    /// The file as written in the question.
+    pub path: PathBuf,
+    /// First line (1-based).
+    pub start: usize,
+    /// Last line (1-based, inclusive).
+    pub end: usize,
+}
+
+impl LineReference {
+    /// Returns the `blame` command for the range.
+    pub fn command(&self) -> String {
+        format!("blame {} {}-{}", self.path.display(), self.start, self.end)
+    }
+}
+
+/// The author and summary of a commit, from blame output.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct BlameCommit {
+    author: String,
+    date: String,
+    summary: String,
+}
+
+/// The lines of a file and the commits that last changed them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Blame {
+    /// Commit hash of each line, with its line number, in file order.
+    lines: Vec<(usize, String)>,
+    /// The commits, by hash.
+    commits: HashMap<String, BlameCommit>,
+}
+
+/// Finds file locations mentioned in `question`.
+pub fn find_line_references(question: &str) -> Vec<LineReference> {
+    let mut references: Vec<LineReference> = Vec::new();
+    for pattern in [&*COLON_REFERENCE, &*WORD_REFERENCE] {
+        for captures in pattern.captures_iter(question) {
+            let Ok(start) = captures[2].parse::<usize>() else {
+                continue;
+            };
+            let end = captures
+                .get(3)
+                .and_then(|end| end.as_str().parse().ok())
+                .unwrap_or(start);
+            let reference = LineReference {
+                path: PathBuf::from(&captures[1]),
+                start: start.max(1),
+                end: end.max(start),
+            };
+            if !references.contains(&reference) {
+                references.push(reference);
+            }
+        }
+    }
+    references
+}
+
+/// Runs the `blame` tool on `path`, for the lines in `range` or, without a range, for the
+/// whole file's history.
+///
+/// # Errors
+///
+/// Returns a `GitError` if git fails, for example because the file is not tracked.
+pub fn run_blame(path: &Path, range: Option<(usize, usize)>) -> Result<String, GitError> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let name = path.file_name().map_or_else(
+        || path.to_string_lossy().into_owned(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+
+    let Some((start, end)) = range else {
+        let log = git(
+            dir,
+            &[
+                "log",
+                "--follow",
+                "-n",
+                &MAX_COMMITS.to_string(),
+                "--date=short",
+                "--format=%h %ad %an: %s",
+                "--",
+                &name,
+            ],
+        )?;
+        return Ok(format!(
+            "Latest commits changing {}:\n{log}",
+            path.display()
+        ));
+    };
+
+    let porcelain = git(
+        dir,
+        &[
+            "blame",
+            "--porcelain",
+            "-L",
+            &format!("{start},{end}"),
+            "--",
+            &name,
+        ],
+    )?;
+    let blame = parse_porcelain(&porcelain);
+
+    let mut messages = Vec::new();
+    for hash in commit_order(&blame).into_iter().take(MAX_COMMITS) {
+        if is_uncommitted(hash) {
+            continue;
+        }
+        let mut message = git(dir, &["show", "-s", "--format=%B", hash])?
+            .trim()
+            .to_string();
+        if message.len() > MAX_MESSAGE_CHARS {
+            message.truncate(message.floor_char_boundary(MAX_MESSAGE_CHARS));
+            message.push_str(" [...]");
+        }
+        messages.push((hash, message));
+    }
+    Ok(render_blame(path, &blame, &messages))
+}
+
+/// Parses the output of `git blame --porcelain`.
+fn parse_porcelain(output: &str) -> Blame {
+    let mut blame = Blame::default();
+    let mut current: Option<(String, usize)> = None;
+    for line in output.lines() {
+        if let Some(captures) = PORCELAIN_HEADER.captures(line) {
+            let hash = captures[1].to_string();
+            let number = captures[2].parse().unwrap_or(0);
+            blame.commits.entry(hash.clone()).or_default();
+            current = Some((hash, number));
+        } else if line.starts_with('\t') {
+            if let Some((hash, number)) = current.take() {
+                blame.lines.push((number, hash));
+            }
+        } else if let Some((hash, _)) = &current {
+            let commit = blame.commits.entry(hash.clone()).or_default();
+            if let Some(author) = line.strip_prefix("author ") {
+                commit.author = author.to_string();
+            } else if let Some(time) = line.strip_prefix("author-time ") {
+                commit.date = time
+                    .parse()
+                    .ok()
+                    .and_then(|secs| DateTime::from_timestamp(secs, 0))
+                    .map(|date| date.format("%Y-%m-%d").to_string())
+                    .unwrap_or_default();
+            } else if let Some(summary) = line.strip_prefix("summary ") {
+                commit.summary = summary.to_string();
+            }
+        }
+    }
+    blame
+}
+
+/// Returns the commits of `blame` in order of first appearance.
+fn commit_order(blame: &Blame) -> Vec<&str> {
+    let mut order: Vec<&str> = Vec::new();
+    for (_, hash) in &blame.lines {
+        if !order.contains(&hash.as_str()) {
+            order.push(hash);
+        }
+    }
+    order
+}
+
+/// Returns whether `hash` stands for changes that are not committed yet.
+fn is_uncommitted(hash: &str) -> bool {
+    hash.bytes().all(|byte| byte == b'0')
+}
+
+/// Renders the blamed ranges followed by the commit messages and their references.
+fn render_blame(path: &Path, blame: &Blame, messages: &[(&str, String)]) -> String {
+    let mut output = format!("Blame for {}:\n", path.display());
+    let mut index = 0;
+    while index < blame.lines.len() {
+        let (start, hash) = &blame.lines[index];
+        let mut end = *start;
+        while index + 1 < blame.lines.len() && blame.lines[index + 1].1 == *hash {
+            index += 1;
+            end = blame.lines[index].0;
+        }
+        index += 1;
+
+        let range = if end == *start {
+            start.to_string()
+        } else {
+            format!("{start}-{end}")
+        };
+        if is_uncommitted(hash) {
+            let _ = writeln!(output, "  {range:>9}  (not committed yet)");
+            continue;
+        }
+        let commit = &blame.commits[hash];
+        let _ = writeln!(
+            output,
+            "  {range:>9}  {}  {}  {}: {}",
+            &hash[..7],
+            commit.date,
+            commit.author,
+            commit.summary
+        );
+    }
+
+    if !messages.is_empty() {
+        output.push_str("\nCommit messages:\n");
+    }
+    for (hash, message) in messages {
+        let _ = writeln!(output, "\ncommit {}", &hash[..7]);
+        let mut references: Vec<&str> = Vec::new();
+        for captures in PR_REFERENCE.captures_iter(message) {
+            let number = captures.get(1).map_or("", |number| number.as_str());
+            if !references.contains(&number) {
+                references.push(number);
+            }
+        }
+        if !references.is_empty() {
+            let references: Vec<String> = references.iter().map(|n| format!("#{n}")).collect();
+            let _ = writeln!(output, "References: {}", references.join(", "));
+        }
+        for line in message.lines() {
+            let _ = writeln!(output, "    {line}");
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_line_references() {
+        assert_eq!(
+            find_line_references(
+                "Why does src/agent.rs:120-130 retry, and what about db.rs line 7?"
+            ),
+            vec![
+                LineReference {
+                    path: PathBuf::from("src/agent.rs"),
+                    start: 120,
+                    end: 130,
+                },
+                LineReference {
+                    path: PathBuf::from("db.rs"),
+                    start: 7,
+                    end: 7,
+                },
+            ]
+        );
+        assert!(find_line_references("How does the agent work?").is_empty());
+    }
+
+    #[test]
+    fn test_render_porcelain_blame() {
+        let first = "a".repeat(40);
+        let second = "b".repeat(40);
+        let porcelain = format!(
+            "{first} 1 10 2\nauthor Alice\nauthor-time 1700000000\nsummary Retry on timeout (#42)\nfilename x.rs\n\tretry();\n{first} 2 11\n\tretry();\n{second} 5 12 1\nauthor Bob\nauthor-time 1710000000\nsummary Log retries\nfilename x.rs\n\tlog();\n"
+        );
+        let blame = parse_porcelain(&porcelain);
+        assert_eq!(commit_order(&blame), [first.as_str(), second.as_str()]);
+
+        let messages = [(
+            first.as_str(),
+            "Retry on timeout (#42)\n\nThe upstream API drops connections under load.\nSee https://github.com/o/r/pull/40".to_string(),
+        )];
+        let output = render_blame(Path::new("x.rs"), &blame, &messages);
+        assert!(
+            output.contains("      10-11  aaaaaaa  2023-11-14  Alice: Retry on timeout (#42)\n")
+        );
+        assert!(output.contains("         12  bbbbbbb  2024-03-09  Bob: Log retries\n"));
+        assert!(output.contains("References: #42, #40\n"));
+        assert!(output.contains("    The upstream API drops connections under load.\n"));
+    }
+}
@@ -0,0 +1,492 @@
+//! # Build and CI Configuration
+//!
+//! This module implements the tools that describe how a project is built and deployed from
+//! the parsed structure of its configuration, so the model reads a short outline rather
+//! than raw YAML:
+//!
+//! - `workflows [dir]`: the GitHub Actions workflows under `.github/workflows`, with their
+//!   triggers, jobs, job dependencies, matrices, and steps
+//! - `makefiles [dir]`: the targets of every Makefile, with their prerequisites, the first
+//!   lines of their recipes, and their descriptions
+//!
+//! Long values are cut, since the outline is meant to show structure; `show_file` still
+//! gives the full text. Dockerfiles and compose files are covered by [`crate::containers`].
+
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use regex::Regex;
+use serde_yaml::Value;
+
+use crate::{org_policy, search::collect_files, tree::find_gitignore_patterns};
+
+/// Maximum characters of a step or recipe line.
+const MAX_LINE_CHARS: usize = 100;
+
+/// Maximum steps listed per job.
+const MAX_STEPS: usize = 20;
+
+/// Maximum recipe lines listed per target.
+const MAX_RECIPE_LINES: usize = 3;
+
+/// A Makefile rule line: its targets and prerequisites.
+static MAKE_RULE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^([^\s:=#][^:=#]*?)\s*::?\s*([^=].*)?$").expect("Invalid make rule pattern")
+});
+
+/// A GitHub Actions workflow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workflow {
+    /// The workflow file, relative to the scanned directory.
+    pub path: PathBuf,
+    /// The workflow's display name.
+    pub name: Option<String>,
+    /// The events that trigger the workflow.
+    pub triggers: Vec<String>,
+    /// The jobs, in file order.
+    pub jobs: Vec<Job>,
+}
+
+/// A job of a workflow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    /// The job's ID.
+    pub id: String,
+    /// The job's display name.
+    pub name: Option<String>,
+    /// The runner the job runs on.
+    pub runs_on: Option<String>,
+    /// The jobs that must finish first.
+    pub needs: Vec<String>,
+    /// The keys of the job's matrix.
+    pub matrix: Vec<String>,
+    /// The reusable workflow the job calls, if it calls one instead of running steps.
+    pub uses: Option<String>,
+    /// The steps, each as its name, the action it uses, or its command.
+    pub steps: Vec<String>,
+}
+
+/// A target of a Makefile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MakeTarget {
+    /// The target's name.
+    pub name: String,
+    /// Line of the rule (1-based).
+    pub line: usize,
+    /// The prerequisites.
+    pub prerequisites: Vec<String>,
+    /// The first lines of the recipe.
+    pub recipe: Vec<String>,
+    /// The description from a `## ...` comment on or above the rule.
+    pub description: Option<String>,
+    /// Whether the target is declared `.PHONY`.
+    pub phony: bool,
+}
+
+/// Parses a workflow file.
+///
+/// # Errors
+///
+/// Returns the YAML error if the file is not valid YAML.
+pub fn parse_workflow(path: PathBuf, content: &str) -> Result<Workflow, serde_yaml::Error> {
+    let document: Value = serde_yaml::from_str(content)?;
+    let triggers = match &document["on"] {
+        Value::Mapping(events) => events.keys().filter_map(scalar).collect(),
+        events => strings(events),
+    };
+    let jobs = document["jobs"]
+        .as_mapping()
+        .map(|jobs| {
+            jobs.iter()
+                .map(|(id, job)| Job {
+                    id: scalar(id).unwrap_or_default(),
+                    name: scalar(&job["name"]),
+                    runs_on: match &job["runs-on"] {
+                        Value::Sequence(labels) => Some(
+                            labels
+                                .iter()
+                                .filter_map(scalar)
+                                .collect::<Vec<_>>()
+                                .join(", "),
+                        ),
+                        labels => scalar(labels),
+                    },
+                    needs: strings(&job["needs"]),
+                    matrix: job["strategy"]["matrix"]
+                        .as_mapping()
+                        .map(|matrix| matrix.keys().filter_map(scalar).collect())
+                        .unwrap_or_default(),
+                    uses: scalar(&job["uses"]),
+                    steps: job["steps"]
+                        .as_sequence()
+                        .map(|steps| steps.iter().map(describe_step).collect())
+                        .unwrap_or_default(),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(Workflow {
+        path,
+        name: scalar(&document["name"]),
+        triggers,
+        jobs,
+    })
+}
+
+/// Describes a step by its name and the action or command it runs.
+fn describe_step(step: &Value) -> String {
+    let action = scalar(&step["uses"]).or_else(|| {
+        scalar(&step["run"]).map(|run| {
+            let mut lines = run.lines().filter(|line| !line.trim().is_empty());
+            let first = lines.next().unwrap_or_default().trim().to_string();
+            if lines.next().is_some() {
+                format!("{first} ...")
+            } else {
+                first
+            }
+        })
+    });
+    let description = match (scalar(&step["name"]), action) {
+        (Some(name), Some(action)) => format!("{name}: {action}"),
+        (Some(text), None) | (None, Some(text)) => text,
+        (None, None) => "(empty step)".to_string(),
+    };
+    truncate(&description)
+}
+
+/// Returns a scalar as a string.
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+}
+
+/// Returns a scalar or a sequence of scalars as strings.
+fn strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::Sequence(values) => values.iter().filter_map(scalar).collect(),
+        value => scalar(value).into_iter().collect(),
+    }
+}
+
+/// Parses the targets of a Makefile, skipping pattern rules and special targets other than
+/// `.PHONY`.
+pub fn parse_makefile(content: &str) -> Vec<MakeTarget> {
+    let mut targets: Vec<MakeTarget> = Vec::new();
+    let mut phony: Vec<String> = Vec::new();
+    let mut comment: Option<String> = None;
+    let mut in_rule = false;
+    for (line, text) in logical_lines(content) {
+        if text.starts_with('\t') {
+            if in_rule
+                && let Some(target) = targets.last_mut()
+                && target.recipe.len() < MAX_RECIPE_LINES
+            {
+                target.recipe.push(truncate(text.trim()));
+            }
+            continue;
+        }
+        let trimmed = text.trim();
+        if let Some(description) = trimmed.strip_prefix("##") {
+            comment = Some(description.trim().to_string());
+            continue;
+        }
+        let Some(captures) = MAKE_RULE.captures(trimmed) else {
+            in_rule = false;
+            comment = None;
+            continue;
+        };
+        let (rule, description) = match captures.get(2).map(|m| m.as_str()) {
+            Some(rest) => match rest.split_once("##") {
+                Some((rest, description)) => (rest, Some(description.trim().to_string())),
+                None => (rest, None),
+            },
+            None => ("", None),
+        };
+        let prerequisites: Vec<String> = rule
+            .split('#')
+            .next()
+            .unwrap_or_default()
+            .split(['|', ';'])
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        let names: Vec<&str> = captures[1].split_whitespace().collect();
+        if names == [".PHONY"] {
+            phony.extend(prerequisites);
+            in_rule = false;
+            comment = None;
+            continue;
+        }
+        let description = description.or_else(|| comment.take());
+        in_rule = false;
+        for name in names {
+            if name.starts_with('.') || name.contains('%') || name.contains('$') {
+                continue;
+            }
+            targets.push(MakeTarget {
+                name: name.to_string(),
+                line,
+                prerequisites: prerequisites.clone(),
+                recipe: Vec::new(),
+                description: description.clone(),
+                phony: false,
+            });
+            in_rule = true;
+        }
+        comment = None;
+    }
+    for target in &mut targets {
+        target.phony = phony.contains(&target.name);
+    }
+    targets
+}
+
+/// Joins lines continued with a backslash, skipping blank lines and `#` comments that
+/// are not `##` descriptions, and returns each logical line with its first line number.
+pub(crate) fn logical_lines(content: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    for (index, line) in content.lines().enumerate() {
+        let is_comment = line.trim_start().starts_with('#') && !line.trim_start().starts_with("##");
+        if is_comment || (current.is_none() && line.trim().is_empty()) {
+            continue;
+        }
+        let (start, mut text) = current.take().unwrap_or((index + 1, String::new()));
+        match line.trim_end().strip_suffix('\\') {
+            Some(part) => {
+                text.push_str(part.trim_end());
+                text.push(' ');
+                current = Some((start, text));
+            }
+            None => {
+                if text.is_empty() {
+                    text.push_str(line);
+                } else {
+                    text.push_str(line.trim_start());
+                }
+                lines.push((start, text));
+            }
+        }
+    }
+    lines.extend(current);
+    lines
+}
+
+/// Cuts `text` to [`MAX_LINE_CHARS`] characters.
+pub(crate) fn truncate(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.len() <= MAX_LINE_CHARS {
+        return text;
+    }
+    format!("{} ...", &text[..text.floor_char_boundary(MAX_LINE_CHARS)])
+}
+
+/// Runs the `workflows` tool on `dir`, skipping the workflows `hidden` accepts.
+pub fn run_workflows(dir: &Path, hidden: &dyn Fn(&Path) -> bool) -> String {
+    let workflows_dir = dir.join(".github").join("workflows");
+    let Ok(entries) = fs::read_dir(&workflows_dir) else {
+        return format!("No GitHub Actions workflows in {}", dir.display());
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "yml" || extension == "yaml")
+                && !org_policy::global().is_forbidden(path)
+                && !hidden(path)
+        })
+        .collect();
+    paths.sort();
+
+    let mut output = String::new();
+    for path in paths {
+        let relative = path.strip_prefix(dir).unwrap_or(&path).to_path_buf();
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        match parse_workflow(relative.clone(), &content) {
+            Ok(workflow) => render_workflow(&mut output, &workflow),
+            Err(err) => {
+                let _ = writeln!(output, "{}: invalid YAML: {err}\n", relative.display());
+            }
+        }
+    }
+    if output.is_empty() {
+        return format!("No GitHub Actions workflows in {}", dir.display());
+    }
+    output
+}
+
+fn render_workflow(output: &mut String, workflow: &Workflow) {
+    let name = workflow
+        .name
+        .as_ref()
+        .map(|name| format!(" ({name})"))
+        .unwrap_or_default();
+    let _ = writeln!(output, "{}{name}", workflow.path.display());
+    let _ = writeln!(output, "  on: {}", workflow.triggers.join(", "));
+    for job in &workflow.jobs {
+        let mut details = Vec::new();
+        if let Some(name) = &job.name {
+            details.push(format!("\"{name}\""));
+        }
+        if let Some(runs_on) = &job.runs_on {
+            details.push(format!("runs on {runs_on}"));
+        }
+        if !job.needs.is_empty() {
+            details.push(format!("needs {}", job.needs.join(", ")));
+        }
+        if !job.matrix.is_empty() {
+            details.push(format!("matrix over {}", job.matrix.join(", ")));
+        }
+        if let Some(uses) = &job.uses {
+            details.push(format!("calls {uses}"));
+        }
+        let _ = writeln!(output, "  job {}: {}", job.id, details.join("; "));
+        for step in job.steps.iter().take(MAX_STEPS) {
+            let _ = writeln!(output, "    - {step}");
+        }
+        if job.steps.len() > MAX_STEPS {
+            let _ = writeln!(output, "    ... and {} more", job.steps.len() - MAX_STEPS);
+        }
+    }
+    output.push('\n');
+}
+
+/// Runs the `makefiles` tool on `dir`, skipping the Makefiles `hidden` accepts.
+pub fn run_makefiles(dir: &Path, hidden: &dyn Fn(&Path) -> bool) -> String {
+    let mut output = String::new();
+    for path in find_files(dir, is_makefile, hidden) {
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let _ = writeln!(
+            output,
+            "{}",
+            path.strip_prefix(dir).unwrap_or(&path).display()
+        );
+        for target in parse_makefile(&content) {
+            let phony = if target.phony { " (phony)" } else { "" };
+            let prerequisites = if target.prerequisites.is_empty() {
+                String::new()
+            } else {
+                format!(": {}", target.prerequisites.join(" "))
+            };
+            let description = target
+                .description
+                .map(|description| format!(" -- {description}"))
+                .unwrap_or_default();
+            let _ = writeln!(
+                output,
+                "  {}{phony}{prerequisites} (line {}){description}",
+                target.name, target.line
+            );
+            for line in target.recipe {
+                let _ = writeln!(output, "    $ {line}");
+            }
+        }
+        output.push('\n');
+    }
+    if output.is_empty() {
+        return format!("No Makefiles in {}", dir.display());
+    }
+    output
+}
+
+/// Returns the files under `dir` whose names satisfy `matches`, skipping ignored and
+/// forbidden files and those `hidden` accepts.
+pub(crate) fn find_files(
+    dir: &Path,
+    matches: fn(&str) -> bool,
+    hidden: &dyn Fn(&Path) -> bool,
+) -> Vec<PathBuf> {
+    let ignore = find_gitignore_patterns(dir).unwrap_or_default();
+    let mut files = Vec::new();
+    collect_files(dir, dir, &ignore, &mut files);
+    files.retain(|path| {
+        path.file_name()
+            .is_some_and(|name| matches(&name.to_string_lossy()))
+            && !hidden(path)
+    });
+    files
+}
+
+fn is_makefile(name: &str) -> bool {
+    matches!(name, "Makefile" | "makefile" | "GNUmakefile") || name.ends_with(".mk")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_workflow() {
+        let workflow = parse_workflow(
+            PathBuf::from(".github/workflows/ci.yml"),
+            "name: CI\non:\n  push:\n    branches: [main]\n  pull_request:\njobs:\n  build:\n    runs-on: ubuntu-latest\n    strategy:\n      matrix:\n        rust: [stable, nightly]\n    steps:\n      - uses: actions/checkout@v4\n      - name: Test\n        run: |\n          cargo build\n          cargo test\n  deploy:\n    needs: build\n    uses: ./.github/workflows/deploy.yml\n",
+        )
+        .expect("Failed to parse workflow");
+        assert_eq!(workflow.name.as_deref(), Some("CI"));
+        assert_eq!(workflow.triggers, ["push", "pull_request"]);
+        assert_eq!(
+            workflow.jobs[0],
+            Job {
+                id: "build".to_string(),
+                name: None,
+                runs_on: Some("ubuntu-latest".to_string()),
+                needs: Vec::new(),
+                matrix: vec!["rust".to_string()],
+                uses: None,
+                steps: vec![
+                    "actions/checkout@v4".to_string(),
+                    "Test: cargo build ...".to_string()
+                ],
+            }
+        );
+        assert_eq!(workflow.jobs[1].needs, ["build"]);
+        assert_eq!(
+            workflow.jobs[1].uses.as_deref(),
+            Some("./.github/workflows/deploy.yml")
+        );
+    }
+
+    #[test]
+    fn test_parse_makefile() {
+        let targets = parse_makefile(
+            "CARGO ?= cargo\nVERSION := 1.0\n.PHONY: build test\n\n## Build the release binary\nbuild: fmt\n\t$(CARGO) build --release\n\ntest: build ## Run the tests\n\t$(CARGO) test\n\t$(CARGO) clippy\n\n%.o: %.c\n\tcc -c $<\n\ndist/app.tar.gz: build | dist\n\ttar czf $@ target/release/app\n",
+        );
+        let summary: Vec<(&str, bool, Option<&str>)> = targets
+            .iter()
+            .map(|target| {
+                (
+                    target.name.as_str(),
+                    target.phony,
+                    target.description.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("build", true, Some("Build the release binary")),
+                ("test", true, Some("Run the tests")),
+                ("dist/app.tar.gz", false, None),
+            ]
+        );
+        assert_eq!(targets[0].prerequisites, ["fmt"]);
+        assert_eq!(targets[1].recipe, ["$(CARGO) test", "$(CARGO) clippy"]);
+        assert_eq!(targets[2].prerequisites, ["build"]);
+        assert_eq!(targets[2].line, 16);
+    }
+}
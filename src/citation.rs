@@ -0,0 +1,288 @@
+//! # Citation Anchors
+//!
+//! This module gives every excerpt of code shown to the model a stable ID, so answers cite
+//! exactly what they are based on instead of naming files in free text. An ID names the
+//! file, the lines, and a hash of their content:
+//!
+//! ```text
+//! src/agent.rs#L61-L120@3f9a1c0e
+//! ```
+//!
+//! Files are cut into chunks of [`CHUNK_LINES`] lines and search results into one chunk per
+//! snippet. The same content always gets the same ID, so IDs stay valid across iterations
+//! and sessions until the code changes. Once the answer is final, [`resolve`] turns the
+//! cited IDs into Markdown links to the lines.
+
+use std::{collections::HashMap, sync::LazyLock};
+
+use regex::Regex;
+use sha2::{Digest, Sha256};
+
+/// Lines per chunk of a file.
+pub const CHUNK_LINES: usize = 60;
+
+/// Hex digits of the content hash kept in an ID.
+const HASH_LEN: usize = 8;
+
+/// A chunk ID, optionally in square brackets.
+static CHUNK_ID: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\[?([^\s\[\]()#]+#L\d+-L\d+@[0-9a-f]{8})\]?").expect("Invalid chunk ID pattern")
+});
+
+/// An elision marker inserted when a file was shortened.
+static ELISION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\[\.\.\. lines \d+-(\d+) of \d+ elided \.\.\.\]$")
+        .expect("Invalid elision marker pattern")
+});
+
+/// The header of a search snippet.
+static SNIPPET_HEADER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(\S+):(\d+)-(\d+) \(score").expect("Invalid snippet header pattern")
+});
+
+/// A numbered line of a search snippet or a file excerpt.
+static NUMBERED_LINE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(\d+) \| (.*)$").expect("Invalid numbered line pattern"));
+
+/// A range of lines of a file shown to the model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// Path of the file as the plan named it.
+    pub path: String,
+    /// First line of the chunk (1-based).
+    pub start: usize,
+    /// Last line of the chunk.
+    pub end: usize,
+    /// Leading hex digits of the SHA-256 of the chunk's lines.
+    pub hash: String,
+}
+
+impl Chunk {
+    /// Creates the chunk of `lines` of the file at `path`, starting at line `start`.
+    pub fn new(path: &str, start: usize, lines: &[&str]) -> Self {
+        let digest = format!("{:x}", Sha256::digest(lines.join("\n").as_bytes()));
+        Self {
+            path: path.to_string(),
+            start,
+            end: start + lines.len().saturating_sub(1),
+            hash: digest[..HASH_LEN].to_string(),
+        }
+    }
+
+    /// Returns the chunk's ID, such as `src/main.rs#L1-L60@0123abcd`.
+    pub fn id(&self) -> String {
+        format!("{}#L{}-L{}@{}", self.path, self.start, self.end, self.hash)
+    }
+
+    /// Returns a Markdown link to the chunk's lines.
+    pub fn link(&self) -> String {
+        format!(
+            "[{}:{}-{}]({}#L{}-L{})",
+            self.path, self.start, self.end, self.path, self.start, self.end
+        )
+    }
+}
+
+/// Instructions for the answer prompt on citing chunks.
+pub const CITATION_PROMPT: &str = "Excerpts of files are preceded by lines such as [chunk src/main.rs#L1-L60@0123abcd] naming their ID. Support each claim about the code by citing the IDs of the chunks it is based on in square brackets, like [src/main.rs#L1-L60@0123abcd]. Only cite IDs that appear in the command results.";
+
+/// Anchors the contents of the file at `path`, as shown to the model, in chunks.
+///
+/// Returns the text with a `[chunk <id>]` line before each chunk, and the chunks. Lines
+/// elided from the middle of the file are skipped, keeping the line numbers of the rest.
+pub fn anchor_file(path: &str, text: &str) -> (String, Vec<Chunk>) {
+    let mut output = String::new();
+    let mut chunks = Vec::new();
+    let mut run: Vec<&str> = Vec::new();
+    let mut run_start = 1;
+    let mut flush = |run: &mut Vec<&str>, start: usize, output: &mut String| {
+        for (index, lines) in run.chunks(CHUNK_LINES).enumerate() {
+            let chunk = Chunk::new(path, start + index * CHUNK_LINES, lines);
+            output.push_str(&format!("[chunk {}]\n", chunk.id()));
+            for line in lines {
+                output.push_str(line);
+                output.push('\n');
+            }
+            chunks.push(chunk);
+        }
+        run.clear();
+    };
+
+    for line in text.lines() {
+        if let Some(captures) = ELISION.captures(line) {
+            flush(&mut run, run_start, &mut output);
+            output.push_str(line);
+            output.push('\n');
+            run_start = captures[1].parse::<usize>().unwrap_or(run_start) + 1;
+        } else if line == "[output truncated]" {
+            break;
+        } else {
+            run.push(line);
+        }
+    }
+    flush(&mut run, run_start, &mut output);
+    if text.ends_with("[output truncated]") {
+        output.push_str("[output truncated]\n");
+    }
+    (output, chunks)
+}
+
+/// Anchors the numbered lines of an excerpt of the file at `path`, as shown by
+/// `show_file <path>:<start>-<end>`, in chunks.
+///
+/// Returns the text with a `[chunk <id>]` line before each chunk, and the chunks.
+pub fn anchor_excerpt(path: &str, text: &str) -> (String, Vec<Chunk>) {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut output = String::new();
+    let mut chunks = Vec::new();
+    let mut index = 0;
+    while index < lines.len() {
+        let numbered: Vec<(usize, &str)> = lines[index..]
+            .iter()
+            .take(CHUNK_LINES)
+            .map_while(|line| {
+                let captures = NUMBERED_LINE.captures(line)?;
+                let excerpt = captures.get(2).map_or("", |excerpt| excerpt.as_str());
+                Some((captures[1].parse().ok()?, excerpt))
+            })
+            .collect();
+        let Some(&(start, _)) = numbered.first() else {
+            output.push_str(lines[index]);
+            output.push('\n');
+            index += 1;
+            continue;
+        };
+        let excerpt: Vec<&str> = numbered.iter().map(|&(_, line)| line).collect();
+        let chunk = Chunk::new(path, start, &excerpt);
+        output.push_str(&format!("[chunk {}]\n", chunk.id()));
+        for line in &lines[index..index + numbered.len()] {
+            output.push_str(line);
+            output.push('\n');
+        }
+        index += numbered.len();
+        chunks.push(chunk);
+    }
+    (output, chunks)
+}
+
+/// Anchors each snippet of rendered search results.
+///
+/// Returns the text with a `[chunk <id>]` line after each snippet header, and the chunks.
+pub fn anchor_snippets(text: &str) -> (String, Vec<Chunk>) {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut output = String::new();
+    let mut chunks = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        output.push_str(line);
+        output.push('\n');
+        let Some(captures) = SNIPPET_HEADER.captures(line) else {
+            continue;
+        };
+        let excerpt: Vec<&str> = lines[index + 1..]
+            .iter()
+            .map_while(|line| NUMBERED_LINE.captures(line))
+            .map(|captures| captures.get(2).map_or("", |excerpt| excerpt.as_str()))
+            .collect();
+        let Ok(start) = captures[2].parse() else {
+            continue;
+        };
+        if excerpt.is_empty() {
+            continue;
+        }
+        let chunk = Chunk::new(&captures[1], start, &excerpt);
+        output.push_str(&format!("[chunk {}]\n", chunk.id()));
+        chunks.push(chunk);
+    }
+    (output, chunks)
+}
+
+/// Replaces the chunk IDs cited in `answer` with links to their lines.
+///
+/// Returns the resolved answer and the IDs that name no chunk in `chunks`, which are left
+/// as written.
+pub fn resolve(answer: &str, chunks: &HashMap<String, Chunk>) -> (String, Vec<String>) {
+    let mut unknown = Vec::new();
+    let resolved = CHUNK_ID.replace_all(answer, |captures: &regex::Captures| {
+        match chunks.get(&captures[1]) {
+            Some(chunk) => chunk.link(),
+            None => {
+                unknown.push(captures[1].to_string());
+                captures[0].to_string()
+            }
+        }
+    });
+    (resolved.into_owned(), unknown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anchor_file() {
+        let text: String = (1..=130).map(|n| format!("line {n}\n")).collect();
+        let (anchored, chunks) = anchor_file("src/lib.rs", &text);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!((chunks[1].start, chunks[1].end), (61, 120));
+        assert_eq!((chunks[2].start, chunks[2].end), (121, 130));
+        assert!(anchored.starts_with(&format!("[chunk {}]\nline 1\n", chunks[0].id())));
+
+        // The same lines get the same ID, other lines another one
+        let (_, again) = anchor_file("src/lib.rs", &text);
+        assert_eq!(again[0].id(), chunks[0].id());
+        assert_ne!(chunks[0].hash, chunks[1].hash);
+
+        let elided = "use std::fs;\n[... lines 2-99 of 120 elided ...]\nfn main() {}\n}\n";
+        let (anchored, chunks) = anchor_file("src/main.rs", elided);
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|chunk| (chunk.start, chunk.end))
+                .collect::<Vec<_>>(),
+            [(1, 1), (100, 101)]
+        );
+        assert!(anchored.contains("elided ...]\n[chunk src/main.rs#L100-L101@"));
+
+        // Excerpts are anchored at their line numbers, with the same IDs as whole files
+        let excerpt: String = (61..=70).map(|n| format!("{n:>5} | line {n}\n")).collect();
+        let (anchored, excerpt_chunks) = anchor_excerpt(
+            "src/lib.rs",
+            &format!("Lines 61-70 of src/lib.rs (130 lines):\n{excerpt}"),
+        );
+        assert_eq!(excerpt_chunks.len(), 1);
+        assert_eq!((excerpt_chunks[0].start, excerpt_chunks[0].end), (61, 70));
+        assert!(anchored.starts_with(&format!(
+            "Lines 61-70 of src/lib.rs (130 lines):\n[chunk {}]\n   61 | line 61\n",
+            excerpt_chunks[0].id()
+        )));
+        let (_, whole) = anchor_file(
+            "src/lib.rs",
+            &text[..text.find("line 71").expect("Missing line 71")],
+        );
+        assert_eq!(whole[1].hash, excerpt_chunks[0].hash);
+    }
+
+    #[test]
+    fn test_anchor_snippets_and_resolve() {
+        let results = "src/agent.rs:10-12 (score 3.0)\n   10 | fn run() {\n   11 |     plan();\n   12 | }\n\n";
+        let (anchored, chunks) = anchor_snippets(results);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!((chunks[0].start, chunks[0].end), (10, 12));
+        assert!(anchored.contains(&format!("(score 3.0)\n[chunk {}]\n", chunks[0].id())));
+
+        let known: HashMap<String, Chunk> = chunks
+            .into_iter()
+            .map(|chunk| (chunk.id(), chunk))
+            .collect();
+        let id = known.keys().next().expect("Missing chunk").clone();
+        let (resolved, unknown) = resolve(
+            &format!("run plans first [{id}], see also src/x.rs#L1-L2@deadbeef."),
+            &known,
+        );
+        assert_eq!(
+            resolved,
+            "run plans first [src/agent.rs:10-12](src/agent.rs#L10-L12), see also src/x.rs#L1-L2@deadbeef."
+        );
+        assert_eq!(unknown, ["src/x.rs#L1-L2@deadbeef"]);
+    }
+}
@@ -0,0 +1,629 @@
+//! # Claude Client
+//!
+//! This module talks to the [Anthropic API](https://docs.anthropic.com/en/api) directly, for
+//! users with an Anthropic API key instead of a Copilot subscription. It is selected with
+//! `--provider claude` and reads the key from `ANTHROPIC_API_KEY`.
+//!
+//! Requests follow the `/v1/messages` API: chat messages are turned into typed [`Message`]s
+//! of [`ContentBlock`]s, system messages become the request's system prompt, and the stable
+//! prefix of a request is marked with `cache_control`, so repeated prompts are served from
+//! Anthropic's prompt cache. Streamed replies are read from the API's own event format.
+//! Anthropic does not compute embeddings, so `index --semantic` needs another provider.
+
+#[cfg(feature = "native")]
+use std::env;
+
+use async_trait::async_trait;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, CONTENT_TYPE},
+    Client as HttpClient,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    github_copilot_client::{
+        ChatChoice, ChatResponse, CopilotError, Message as ChatMessage, Model, PromptTokensDetails,
+        TokenUsage,
+    },
+    org_policy,
+    provider::{
+        api_key_error, check_model, read_events, reported_context, Capabilities, LlmProvider,
+        TextSink,
+    },
+    scheduler,
+};
+
+/// Name of this provider in `--provider` and the `[limits.providers]` section.
+pub const PROVIDER: &str = "claude";
+
+/// The model used unless another one is configured.
+pub const DEFAULT_MODEL: &str = "claude-sonnet-4-5";
+
+/// Environment variable holding the API key.
+pub const API_KEY_VAR: &str = "ANTHROPIC_API_KEY";
+
+/// Base URL of the Anthropic API.
+const API_URL: &str = "https://api.anthropic.com/v1";
+
+/// Version of the Anthropic API the requests follow.
+const API_VERSION: &str = "2023-06-01";
+
+/// Most tokens of a reply when the request does not limit them; the API requires a limit.
+const DEFAULT_MAX_TOKENS: u32 = 8192;
+
+/// Context window of the Claude models, which the models list does not report.
+const CONTEXT_WINDOW: u32 = 200_000;
+
+/// A model, as listed by the Anthropic API.
+#[derive(Debug, Deserialize)]
+struct ClaudeModel {
+    id: String,
+    #[serde(default)]
+    display_name: Option<String>,
+}
+
+/// Response payload for listing models.
+#[derive(Debug, Deserialize)]
+struct ClaudeModelsResponse {
+    data: Vec<ClaudeModel>,
+}
+
+/// The author of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// The user.
+    User,
+    /// The model.
+    Assistant,
+}
+
+/// Marks the end of a prompt prefix to cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CacheControl {
+    /// Cached for a few minutes, refreshed on every use.
+    Ephemeral,
+}
+
+/// A block of message content.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    /// Text.
+    Text {
+        /// The text.
+        text: String,
+        /// Whether the prompt up to and including this block is cached.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
+    /// A block of another kind, such as a tool use, which is ignored.
+    #[serde(other)]
+    Other,
+}
+
+impl ContentBlock {
+    /// Creates a text block.
+    pub fn text(text: String) -> Self {
+        ContentBlock::Text {
+            text,
+            cache_control: None,
+        }
+    }
+}
+
+/// A message of a conversation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    /// The author of the message.
+    pub role: Role,
+    /// The content of the message.
+    pub content: Vec<ContentBlock>,
+}
+
+/// Request payload for the messages endpoint.
+#[derive(Debug, Serialize)]
+pub struct MessagesRequest {
+    /// The model identifier to use.
+    pub model: String,
+    /// The system prompt.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub system: Vec<ContentBlock>,
+    /// The conversation, starting with a user message.
+    pub messages: Vec<Message>,
+    /// Maximum number of tokens to generate.
+    pub max_tokens: u32,
+    /// Sampling temperature.
+    pub temperature: f64,
+    /// Whether to stream the response.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
+}
+
+impl MessagesRequest {
+    /// Builds a request to `model` for chat `messages`, marking the end of the first
+    /// `stable_prefix` messages as cacheable.
+    ///
+    /// System messages become blocks of the system prompt; the other messages are sent in
+    /// order.
+    pub fn new(
+        model: &str,
+        messages: Vec<ChatMessage>,
+        stable_prefix: usize,
+        max_tokens: Option<u32>,
+    ) -> Self {
+        let mut system = Vec::new();
+        let mut turns = Vec::new();
+        for (index, message) in messages.into_iter().enumerate() {
+            let block = ContentBlock::Text {
+                text: message.content,
+                cache_control: (index + 1 == stable_prefix).then_some(CacheControl::Ephemeral),
+            };
+            match message.role.as_str() {
+                "system" => system.push(block),
+                "assistant" => turns.push(Message {
+                    role: Role::Assistant,
+                    content: vec![block],
+                }),
+                _ => turns.push(Message {
+                    role: Role::User,
+                    content: vec![block],
+                }),
+            }
+        }
+        Self {
+            model: model.to_string(),
+            system,
+            messages: turns,
+            max_tokens: max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            temperature: 0.5,
+            stream: false,
+        }
+    }
+}
+
+/// Token usage of a request.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    /// Prompt tokens neither read from nor written to the cache.
+    pub input_tokens: u32,
+    /// Generated tokens.
+    pub output_tokens: u32,
+    /// Prompt tokens written to the cache.
+    pub cache_creation_input_tokens: u32,
+    /// Prompt tokens read from the cache.
+    pub cache_read_input_tokens: u32,
+}
+
+impl Usage {
+    /// Adds the counts `other` reports; streams report them across several events.
+    fn merge(&mut self, other: Usage) {
+        self.input_tokens = self.input_tokens.max(other.input_tokens);
+        self.output_tokens = self.output_tokens.max(other.output_tokens);
+        self.cache_creation_input_tokens = self
+            .cache_creation_input_tokens
+            .max(other.cache_creation_input_tokens);
+        self.cache_read_input_tokens = self
+            .cache_read_input_tokens
+            .max(other.cache_read_input_tokens);
+    }
+}
+
+impl From<Usage> for TokenUsage {
+    fn from(usage: Usage) -> Self {
+        TokenUsage {
+            total_tokens: usage.input_tokens
+                + usage.cache_creation_input_tokens
+                + usage.cache_read_input_tokens
+                + usage.output_tokens,
+            prompt_tokens_details: Some(PromptTokensDetails {
+                cached_tokens: usage.cache_read_input_tokens,
+            }),
+        }
+    }
+}
+
+/// Response payload for the messages endpoint.
+#[derive(Debug, Deserialize)]
+pub struct MessagesResponse {
+    /// The generated content.
+    pub content: Vec<ContentBlock>,
+    /// Why the generation stopped, such as `end_turn` or `max_tokens`.
+    #[serde(default)]
+    pub stop_reason: Option<String>,
+    /// Token usage of the request.
+    #[serde(default)]
+    pub usage: Usage,
+}
+
+impl From<MessagesResponse> for ChatResponse {
+    fn from(response: MessagesResponse) -> Self {
+        let content = response
+            .content
+            .into_iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text, .. } => Some(text),
+                ContentBlock::Other => None,
+            })
+            .collect::<Vec<_>>()
+            .join("");
+        reply(content, response.stop_reason, response.usage)
+    }
+}
+
+/// Returns a chat completion with the generated `content`.
+fn reply(content: String, stop_reason: Option<String>, usage: Usage) -> ChatResponse {
+    ChatResponse {
+        choices: vec![ChatChoice {
+            message: ChatMessage {
+                role: "assistant".to_string(),
+                content,
+            },
+            finish_reason: stop_reason,
+            usage: None,
+        }],
+        usage: Some(usage.into()),
+    }
+}
+
+/// An event of a streamed response.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    /// The response starts; reports the prompt's usage.
+    MessageStart { message: StreamStart },
+    /// A content block grows.
+    ContentBlockDelta { delta: Delta },
+    /// The response ends; reports why and the generated tokens.
+    MessageDelta {
+        delta: StopDelta,
+        #[serde(default)]
+        usage: Usage,
+    },
+    /// The response is complete.
+    MessageStop,
+    /// The request failed while streaming.
+    Error { error: StreamError },
+    /// Other events, such as pings and block boundaries.
+    #[serde(other)]
+    Other,
+}
+
+/// The message a stream starts.
+#[derive(Debug, Deserialize)]
+struct StreamStart {
+    #[serde(default)]
+    usage: Usage,
+}
+
+/// Content added to a block.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Delta {
+    /// Text added to a text block.
+    TextDelta { text: String },
+    /// Content of another kind.
+    #[serde(other)]
+    Other,
+}
+
+/// The end of a streamed response.
+#[derive(Debug, Deserialize)]
+struct StopDelta {
+    #[serde(default)]
+    stop_reason: Option<String>,
+}
+
+/// An error reported while streaming.
+#[derive(Debug, Deserialize)]
+struct StreamError {
+    #[serde(default)]
+    message: String,
+}
+
+/// A response assembled from the events of a stream.
+#[derive(Debug, Default)]
+struct StreamedMessage {
+    content: String,
+    stop_reason: Option<String>,
+    usage: Usage,
+    error: Option<String>,
+}
+
+impl StreamedMessage {
+    /// Reads the data of an event, passing the text it adds to `on_text`.
+    ///
+    /// Returns `false` once the stream is done.
+    fn feed(&mut self, data: &str, on_text: &mut TextSink<'_>) -> bool {
+        let Ok(event) = serde_json::from_str::<StreamEvent>(data) else {
+            return true;
+        };
+        match event {
+            StreamEvent::MessageStart { message } => self.usage.merge(message.usage),
+            StreamEvent::ContentBlockDelta {
+                delta: Delta::TextDelta { text },
+            } => {
+                on_text(&text);
+                self.content.push_str(&text);
+            }
+            StreamEvent::MessageDelta { delta, usage } => {
+                self.stop_reason = delta.stop_reason;
+                self.usage.merge(usage);
+            }
+            StreamEvent::MessageStop => return false,
+            StreamEvent::Error { error } => {
+                self.error = Some(error.message);
+                return false;
+            }
+            StreamEvent::ContentBlockDelta { .. } | StreamEvent::Other => {}
+        }
+        true
+    }
+
+    /// Returns the assembled response, or the error the stream ended with.
+    fn finish(self) -> Result<ChatResponse, CopilotError> {
+        match self.error {
+            Some(error) => Err(CopilotError::HttpError(error)),
+            None => Ok(reply(self.content, self.stop_reason, self.usage)),
+        }
+    }
+}
+
+/// Client for the Anthropic API.
+pub struct ClaudeClient {
+    http_client: HttpClient,
+    api_key: String,
+    /// List of available models.
+    models: Vec<Model>,
+}
+
+impl ClaudeClient {
+    /// Creates a client with the API key in `ANTHROPIC_API_KEY`, fetching the available
+    /// models.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CopilotError::ApiKey` if the variable is not set or the key is rejected, or
+    /// another `CopilotError` if the models cannot be fetched.
+    #[cfg(feature = "native")]
+    pub async fn from_env() -> Result<Self, CopilotError> {
+        let api_key = env::var(API_KEY_VAR)
+            .ok()
+            .filter(|key| !key.is_empty())
+            .ok_or_else(|| CopilotError::ApiKey(format!("{API_KEY_VAR} is not set")))?;
+        Self::new_with_models(api_key).await
+    }
+
+    /// Creates a client with the given API key, fetching the available models.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CopilotError::ApiKey` if the key is rejected, or another `CopilotError` if
+    /// the models cannot be fetched.
+    pub async fn new_with_models(api_key: String) -> Result<Self, CopilotError> {
+        let mut client = ClaudeClient {
+            http_client: HttpClient::new(),
+            api_key,
+            models: Vec::new(),
+        };
+        client.models = client.get_models().await?;
+        Ok(client)
+    }
+
+    /// Constructs the headers of Anthropic API requests.
+    fn get_headers(&self) -> Result<HeaderMap, CopilotError> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-api-key",
+            HeaderValue::from_str(&self.api_key)
+                .map_err(|e| CopilotError::ApiKey(e.to_string()))?,
+        );
+        headers.insert("anthropic-version", HeaderValue::from_static(API_VERSION));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        Ok(headers)
+    }
+
+    /// Fetches the models available to the API key.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `CopilotError` if the HTTP request fails or the response cannot be parsed.
+    pub async fn get_models(&self) -> Result<Vec<Model>, CopilotError> {
+        let res = self
+            .http_client
+            .get(format!("{API_URL}/models?limit=1000"))
+            .headers(self.get_headers()?)
+            .send()
+            .await
+            .map_err(api_key_error)?
+            .error_for_status()
+            .map_err(api_key_error)?;
+        let models: ClaudeModelsResponse = res
+            .json()
+            .await
+            .map_err(|e| CopilotError::Other(e.to_string()))?;
+        Ok(models
+            .data
+            .into_iter()
+            .map(|model| Model {
+                name: model.display_name.unwrap_or_else(|| model.id.clone()),
+                id: model.id,
+                version: None,
+                tokenizer: None,
+                max_input_tokens: Some(CONTEXT_WINDOW),
+                max_output_tokens: None,
+            })
+            .collect())
+    }
+
+    /// Sends a messages request after applying the organization policy, returning the
+    /// response before its body is read.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CopilotError::InvalidModel` if the model is not available,
+    /// `CopilotError::Forbidden` if the policy does not allow it, or another `CopilotError`
+    /// if the request fails.
+    pub async fn send_messages(
+        &self,
+        mut request: MessagesRequest,
+    ) -> Result<reqwest::Response, CopilotError> {
+        if !self.has_model(&request.model) {
+            return Err(CopilotError::InvalidModel(request.model));
+        }
+        check_model(PROVIDER, &request.model)?;
+        let policy = org_policy::global();
+        for block in request.system.iter_mut().chain(
+            request
+                .messages
+                .iter_mut()
+                .flat_map(|message| &mut message.content),
+        ) {
+            if let ContentBlock::Text { text, .. } = block {
+                *text = policy.redact(text).into_owned();
+            }
+        }
+        let _permit = scheduler::global().llm_call(PROVIDER).await;
+        self.http_client
+            .post(format!("{API_URL}/messages"))
+            .headers(self.get_headers()?)
+            .json(&request)
+            .send()
+            .await
+            .map_err(api_key_error)?
+            .error_for_status()
+            .map_err(api_key_error)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl LlmProvider for ClaudeClient {
+    fn name(&self) -> &'static str {
+        PROVIDER
+    }
+
+    fn models(&self) -> &[Model] {
+        &self.models
+    }
+
+    fn capabilities(&self, model_id: &str) -> Capabilities {
+        // The API has no JSON mode; replies are constrained by the prompt alone
+        Capabilities {
+            tool_calling: true,
+            streaming: true,
+            vision: true,
+            ..Capabilities::basic(reported_context(&self.models, model_id))
+        }
+    }
+
+    async fn chat(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_id: &str,
+        stable_prefix: usize,
+        max_tokens: Option<u32>,
+    ) -> Result<ChatResponse, CopilotError> {
+        let request = MessagesRequest::new(model_id, messages, stable_prefix, max_tokens);
+        let res = self.send_messages(request).await?;
+        let response: MessagesResponse = res
+            .json()
+            .await
+            .map_err(|e| CopilotError::Other(e.to_string()))?;
+        Ok(response.into())
+    }
+
+    async fn stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_id: &str,
+        max_tokens: Option<u32>,
+        on_text: &mut TextSink<'_>,
+    ) -> Result<ChatResponse, CopilotError> {
+        let mut request = MessagesRequest::new(model_id, messages, 0, max_tokens);
+        request.stream = true;
+        let res = self.send_messages(request).await?;
+        let mut message = StreamedMessage::default();
+        read_events(res, |data| message.feed(data, on_text)).await?;
+        message.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_request() {
+        let message = |role: &str| ChatMessage {
+            role: role.to_string(),
+            content: format!("{role} prompt"),
+        };
+        let request = MessagesRequest::new(
+            DEFAULT_MODEL,
+            vec![message("system"), message("user"), message("assistant")],
+            1,
+            None,
+        );
+        let body = serde_json::to_value(&request).expect("Failed to serialize request");
+        assert_eq!(body["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert_eq!(body["system"][0]["cache_control"]["type"], "ephemeral");
+        assert_eq!(body["messages"][0]["role"], "user");
+        assert_eq!(body["messages"][0]["content"][0]["type"], "text");
+        assert_eq!(body["messages"][1]["role"], "assistant");
+        assert!(!body["messages"].to_string().contains("cache_control"));
+        assert!(body.get("stream").is_none());
+
+        let response: MessagesResponse = serde_json::from_str(
+            r#"{
+                "content": [
+                    {"type": "text", "text": "It is in "},
+                    {"type": "tool_use", "id": "t", "name": "n", "input": {}},
+                    {"type": "text", "text": "main.rs."}
+                ],
+                "stop_reason": "end_turn",
+                "usage": {"input_tokens": 100, "output_tokens": 20, "cache_read_input_tokens": 1000}
+            }"#,
+        )
+        .expect("Failed to parse response");
+        let response = ChatResponse::from(response);
+        assert_eq!(response.choices[0].message.content, "It is in main.rs.");
+        assert_eq!(response.tokens_used(), 1120);
+        assert_eq!(response.cached_tokens(), 1000);
+    }
+
+    #[test]
+    fn test_streamed_message() {
+        let mut pieces = Vec::new();
+        let mut on_text = |text: &str| pieces.push(text.to_string());
+        let mut message = StreamedMessage::default();
+        for data in [
+            r#"{"type": "message_start", "message": {"usage": {"input_tokens": 10, "cache_read_input_tokens": 90}}}"#,
+            r#"{"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}"#,
+            r#"{"type": "ping"}"#,
+            r#"{"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "See "}}"#,
+            r#"{"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "main.rs"}}"#,
+            r#"{"type": "message_delta", "delta": {"stop_reason": "end_turn"}, "usage": {"output_tokens": 5}}"#,
+        ] {
+            assert!(message.feed(data, &mut on_text));
+        }
+        assert!(!message.feed(r#"{"type": "message_stop"}"#, &mut on_text));
+        let response = message.finish().expect("Stream failed");
+        assert_eq!(pieces, ["See ", "main.rs"]);
+        assert_eq!(response.choices[0].message.content, "See main.rs");
+        assert_eq!(
+            response.choices[0].finish_reason.as_deref(),
+            Some("end_turn")
+        );
+        assert_eq!(response.tokens_used(), 105);
+
+        let mut failed = StreamedMessage::default();
+        assert!(!failed.feed(
+            r#"{"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}}"#,
+            &mut |_| {}
+        ));
+        assert!(failed.finish().is_err());
+    }
+}
@@ -0,0 +1,372 @@
+//! # Commit and Branch Suggestions
+//!
+//! This module suggests commit messages and branch names for the staged changes, following
+//! the conventions the repository already uses. Conventions are detected from history:
+//!
+//! - whether commit subjects follow [Conventional Commits](https://www.conventionalcommits.org)
+//!   (`type(scope): description`), and which types and scopes are common
+//! - which prefixes branch names use (such as `feature/` or `fix/`) and whether words are
+//!   separated by `-` or `_`
+//!
+//! Scopes are suggested from the paths of the staged files, preferring scopes that already
+//! appear in history. The model writes the description; formatting is done here so the
+//! result always matches the detected conventions.
+
+use std::{collections::HashMap, sync::LazyLock};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{error::AgentError, github_copilot_client::Message, review::ReviewModel};
+
+/// Number of commits inspected to detect conventions.
+pub const HISTORY_DEPTH: usize = 200;
+
+/// Maximum characters of the diff sent to the model.
+const MAX_DIFF_CHARS: usize = 24_000;
+
+/// Maximum length of the descriptive part of a branch name.
+const MAX_SLUG_LENGTH: usize = 40;
+
+/// Directories that group code rather than name a part of it.
+const CONTAINER_DIRS: &[&str] = &[
+    "src", "lib", "crates", "packages", "apps", "internal", "pkg",
+];
+
+/// A Conventional Commits subject.
+static CONVENTIONAL_SUBJECT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<type>[a-z]+)(?:\((?P<scope>[^)]+)\))?!?: \S")
+        .expect("Invalid conventional subject pattern")
+});
+
+/// Commit and branch naming conventions of a repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conventions {
+    /// Whether most commit subjects follow Conventional Commits.
+    pub conventional_commits: bool,
+    /// Commit types used in history, most common first.
+    pub types: Vec<String>,
+    /// Commit scopes used in history, most common first.
+    pub scopes: Vec<String>,
+    /// Branch name prefixes used in history (without the `/`), most common first.
+    pub branch_prefixes: Vec<String>,
+    /// The character separating words in branch names.
+    pub separator: char,
+}
+
+impl Conventions {
+    /// Detects conventions from commit subjects and branch names.
+    pub fn detect(subjects: &[String], branches: &[String]) -> Self {
+        let mut types = HashMap::new();
+        let mut scopes = HashMap::new();
+        let mut conventional = 0;
+        for subject in subjects {
+            if let Some(captures) = CONVENTIONAL_SUBJECT.captures(subject) {
+                conventional += 1;
+                *types.entry(captures["type"].to_string()).or_insert(0) += 1;
+                if let Some(scope) = captures.name("scope") {
+                    *scopes.entry(scope.as_str().to_string()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let mut prefixes = HashMap::new();
+        let (mut dashes, mut underscores) = (0, 0);
+        for branch in branches {
+            if let Some((prefix, _)) = branch.split_once('/') {
+                *prefixes.entry(prefix.to_string()).or_insert(0) += 1;
+            }
+            dashes += branch.matches('-').count();
+            underscores += branch.matches('_').count();
+        }
+
+        Self {
+            conventional_commits: !subjects.is_empty() && conventional * 2 >= subjects.len(),
+            types: by_frequency(types),
+            scopes: by_frequency(scopes),
+            branch_prefixes: by_frequency(prefixes),
+            separator: if underscores > dashes { '_' } else { '-' },
+        }
+    }
+
+    /// Describes the conventions for a prompt.
+    fn describe(&self) -> String {
+        let mut description = if self.conventional_commits {
+            format!(
+                "Commit subjects follow Conventional Commits (`type(scope): description`). Types in use: {}. Scopes in use: {}.",
+                list_or_none(&self.types),
+                list_or_none(&self.scopes)
+            )
+        } else {
+            "Commit subjects do not follow Conventional Commits; use a short imperative sentence."
+                .to_string()
+        };
+        if !self.branch_prefixes.is_empty() {
+            description.push_str(&format!(
+                " Branch prefixes in use: {}.",
+                self.branch_prefixes.join(", ")
+            ));
+        }
+        description
+    }
+}
+
+/// A branch name as proposed by the model.
+#[derive(Debug, Deserialize)]
+struct ProposedBranch {
+    #[serde(rename = "type")]
+    kind: String,
+    summary: String,
+}
+
+/// Suggests commit scopes for the staged `files`, best first.
+///
+/// A scope is taken from each path: the first directory that names a part of the code, or
+/// the file name for files directly in a source directory. Scopes already used in history
+/// come first.
+pub fn scope_candidates(files: &[String], conventions: &Conventions) -> Vec<String> {
+    let mut counts = HashMap::new();
+    for file in files {
+        let mut parts: Vec<&str> = file.split('/').collect();
+        let Some(name) = parts.pop() else {
+            continue;
+        };
+        let scope = match parts.iter().find(|part| !CONTAINER_DIRS.contains(part)) {
+            Some(dir) => dir.to_string(),
+            None if !parts.is_empty() => {
+                let stem = name.split('.').next().unwrap_or(name);
+                if matches!(stem, "lib" | "main" | "mod" | "index" | "__init__") {
+                    continue;
+                }
+                stem.to_string()
+            }
+            None => continue,
+        };
+        *counts.entry(scope).or_insert(0) += 1;
+    }
+
+    let mut candidates = by_frequency(counts);
+    // Stable sort keeps frequency order within each group
+    candidates.sort_by_key(|scope| !conventions.scopes.contains(scope));
+    candidates
+}
+
+/// Asks the model for a commit message for `diff` that follows `conventions`.
+///
+/// # Errors
+///
+/// Returns an `AgentError` if the request fails.
+pub async fn suggest_commit_message<M: ReviewModel>(
+    model: &mut M,
+    diff: &str,
+    conventions: &Conventions,
+    scopes: &[String],
+) -> Result<String, AgentError> {
+    let scope_hint = match scopes {
+        [] => "No scope is suggested by the changed paths; omit the scope unless one is obvious."
+            .to_string(),
+        _ => format!("Suggested scopes, best first: {}.", scopes.join(", ")),
+    };
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: "You write commit messages: a subject of at most 72 characters in the imperative mood, then, if the change needs it, a blank line and a short body explaining why.".to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!(
+                "{} {scope_hint}\n\nWrite the commit message for this staged diff. Respond with the message only.\n\n```diff\n{}\n```",
+                conventions.describe(),
+                truncate(diff)
+            ),
+        },
+    ];
+    let reply = model.complete(messages).await?;
+    Ok(strip_fences(&reply))
+}
+
+/// Asks the model for a branch name for the staged `diff` or `description`, formatted with
+/// the repository's prefixes and separator.
+///
+/// # Errors
+///
+/// Returns an `AgentError` if the request fails, or `AgentError::Other` if the reply is
+/// not understood.
+pub async fn suggest_branch_name<M: ReviewModel>(
+    model: &mut M,
+    diff: &str,
+    description: Option<&str>,
+    conventions: &Conventions,
+) -> Result<String, AgentError> {
+    let mut work = String::new();
+    if let Some(description) = description {
+        work.push_str(&format!("Description of the work: {description}\n\n"));
+    }
+    if !diff.is_empty() {
+        work.push_str(&format!(
+            "Staged diff:\n\n```diff\n{}\n```\n\n",
+            truncate(diff)
+        ));
+    }
+    let types = match conventions.branch_prefixes.as_slice() {
+        [] => "feat, fix, docs, refactor, test, chore".to_string(),
+        prefixes => prefixes.join(", "),
+    };
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: "You name git branches after the work they contain.".to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!(
+                "{work}Name a branch for this work. Respond with JSON only, in this format:\n\n{{\"type\": \"one of: {types}\", \"summary\": \"two to five words\"}}"
+            ),
+        },
+    ];
+    let reply = model.complete(messages).await?;
+    let proposed: ProposedBranch = reply
+        .find('{')
+        .zip(reply.rfind('}'))
+        .and_then(|(start, end)| reply.get(start..=end))
+        .and_then(|json| serde_json::from_str(json).ok())
+        .ok_or_else(|| AgentError::Other(format!("Unexpected branch name: {}", reply.trim())))?;
+    Ok(format_branch(
+        &proposed.kind,
+        &proposed.summary,
+        conventions,
+    ))
+}
+
+/// Formats a branch name from a type and a summary.
+fn format_branch(kind: &str, summary: &str, conventions: &Conventions) -> String {
+    let kind = slugify(kind, conventions.separator);
+    let prefix = conventions
+        .branch_prefixes
+        .iter()
+        .find(|prefix| {
+            **prefix == kind
+                || matches!(
+                    (prefix.as_str(), kind.as_str()),
+                    ("feature", "feat")
+                        | ("feat", "feature")
+                        | ("bugfix", "fix")
+                        | ("fix", "bugfix")
+                )
+        })
+        .cloned()
+        .unwrap_or(kind);
+    let mut slug = slugify(summary, conventions.separator);
+    if slug.len() > MAX_SLUG_LENGTH {
+        // Cut at a word boundary
+        let cut = slug[..MAX_SLUG_LENGTH]
+            .rfind(conventions.separator)
+            .unwrap_or(MAX_SLUG_LENGTH);
+        slug.truncate(cut);
+    }
+    if prefix.is_empty() {
+        slug
+    } else {
+        format!("{prefix}/{slug}")
+    }
+}
+
+/// Lowercases `text` and joins its words with `separator`.
+fn slugify(text: &str, separator: char) -> String {
+    text.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join(&separator.to_string())
+}
+
+/// Returns the keys of `counts`, most frequent first and alphabetically among equals.
+fn by_frequency(counts: HashMap<String, usize>) -> Vec<String> {
+    let mut entries: Vec<(String, usize)> = counts.into_iter().collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    entries.into_iter().map(|(key, _)| key).collect()
+}
+
+/// Joins `items` for a prompt, or says there are none.
+fn list_or_none(items: &[String]) -> String {
+    if items.is_empty() {
+        "none".to_string()
+    } else {
+        items.join(", ")
+    }
+}
+
+/// Shortens `diff` to what is sent to the model.
+fn truncate(diff: &str) -> &str {
+    if diff.len() <= MAX_DIFF_CHARS {
+        diff
+    } else {
+        &diff[..diff.floor_char_boundary(MAX_DIFF_CHARS)]
+    }
+}
+
+/// Removes a Markdown code fence around the model's reply.
+fn strip_fences(reply: &str) -> String {
+    let reply = reply.trim();
+    let Some(inner) = reply.strip_prefix("```") else {
+        return reply.to_string();
+    };
+    let inner = inner.split_once('\n').map_or("", |(_, rest)| rest);
+    inner.trim_end_matches("```").trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn test_detect_conventions() {
+        let subjects = strings(&[
+            "feat(agent): add retries",
+            "fix(db): close connections",
+            "fix: typo",
+            "Merge pull request #3",
+        ]);
+        let branches = strings(&["main", "feature/retry_logic", "fix/db_pool", "fix/typo"]);
+        let conventions = Conventions::detect(&subjects, &branches);
+        assert_eq!(
+            conventions,
+            Conventions {
+                conventional_commits: true,
+                types: strings(&["fix", "feat"]),
+                scopes: strings(&["agent", "db"]),
+                branch_prefixes: strings(&["fix", "feature"]),
+                separator: '_',
+            }
+        );
+
+        let files = strings(&[
+            "src/db.rs",
+            "src/session.rs",
+            "src/main.rs",
+            "docs/db.md",
+            "Cargo.toml",
+        ]);
+        assert_eq!(
+            scope_candidates(&files, &conventions),
+            ["db", "docs", "session"]
+        );
+
+        assert_eq!(
+            format_branch("feat", "Retry failed requests!", &conventions),
+            "feature/retry_failed_requests"
+        );
+        assert_eq!(
+            format_branch(
+                "docs",
+                "a very long summary that keeps going and going",
+                &Conventions::detect(&[], &[])
+            ),
+            "docs/a-very-long-summary-that-keeps-going"
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! # Comparisons
+//!
+//! This module implements `nishiogi compare`, which contrasts how two or more parts of a
+//! codebase approach the same problem, e.g. `"error handling in the server"` and `"error
+//! handling in the CLI"`. Each target gets its own planned retrieval, condensed into notes
+//! so the final prompt stays small however much was read, and the model then writes one
+//! Markdown table with a column per target followed by a short recommendation.
+
+use std::path::Path;
+
+use crate::{
+    agent::AgentError,
+    github_copilot_client::Message,
+    pipeline::{Pipeline, Retrieve, Summarize},
+    review::ReviewModel,
+};
+
+/// Aspects every comparison covers, as rows of the table.
+pub const ASPECTS: [&str; 6] = [
+    "Responsibility",
+    "Key types and functions",
+    "Data flow",
+    "Error handling",
+    "Extensibility",
+    "Testing",
+];
+
+/// Notes gathered about one comparison target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetNotes {
+    /// The target as the user described it.
+    pub target: String,
+    /// Notes condensed from the commands run for the target.
+    pub notes: String,
+    /// The commands that were run.
+    pub commands: Vec<String>,
+}
+
+/// Retrieves and condenses what the code says about each of `targets`, in order, reading
+/// files below `root` only.
+///
+/// # Errors
+///
+/// Returns the `AgentError` of the first retrieval that fails.
+pub async fn gather(
+    model: &mut dyn ReviewModel,
+    targets: &[String],
+    root: &Path,
+) -> Result<Vec<TargetNotes>, AgentError> {
+    let pipeline = Pipeline::new("compare")
+        .step(Retrieve::planned().within(root))
+        .step(Summarize);
+    let mut gathered = Vec::new();
+    for target in targets {
+        let context = pipeline
+            .run(&format!("How does the code implement {target}?"), model)
+            .await?;
+        gathered.push(TargetNotes {
+            target: target.clone(),
+            notes: context.summary.unwrap_or_default(),
+            commands: context
+                .retrieved
+                .into_iter()
+                .map(|(command, _)| command)
+                .collect(),
+        });
+    }
+    Ok(gathered)
+}
+
+/// Asks the model for the comparison table of the gathered targets.
+///
+/// # Errors
+///
+/// Returns the model's `AgentError`, or `AgentError::Other` if the reply contains no
+/// table.
+pub async fn compare(
+    model: &mut dyn ReviewModel,
+    gathered: &[TargetNotes],
+) -> Result<String, AgentError> {
+    let mut material = String::new();
+    for (index, target) in gathered.iter().enumerate() {
+        material.push_str(&format!(
+            "## Target {}: {}\n\n{}\n\n",
+            index + 1,
+            target.target,
+            target.notes.trim()
+        ));
+    }
+    let columns: Vec<&str> = gathered
+        .iter()
+        .map(|target| target.target.as_str())
+        .collect();
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: "You compare how parts of a code repository approach a problem, for engineers deciding on a refactoring. Only state what the notes support and name files and functions.".to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!(
+                "{material}Write a Markdown table comparing the targets, with the columns | Aspect | {} | and one row for each of these aspects: {}. Keep cells short. After the table, write a paragraph on the main trade-offs and which approach to prefer for new code.",
+                columns.join(" | "),
+                ASPECTS.join(", ")
+            ),
+        },
+    ];
+    let reply = model.complete(messages).await?;
+    if !reply.lines().any(|line| line.trim_start().starts_with('|')) {
+        return Err(AgentError::Other(
+            "The comparison contains no table".to_string(),
+        ));
+    }
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use async_trait::async_trait;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Plans to read the file named in the question and compares whatever it is given.
+    struct ScriptedModel {
+        files: Vec<String>,
+        comparison: String,
+    }
+
+    #[async_trait]
+    impl ReviewModel for ScriptedModel {
+        async fn complete(&mut self, messages: Vec<Message>) -> Result<String, AgentError> {
+            let system = &messages[0].content;
+            let user = &messages[1].content;
+            Ok(if system.contains("plans how to answer") {
+                let file = self
+                    .files
+                    .iter()
+                    .find(|file| user.contains(file.as_str()))
+                    .expect("Question names a file");
+                format!("[\"show_file {file}\"]")
+            } else if system.contains("condense") {
+                if user.contains("retry") {
+                    "retries three times".to_string()
+                } else {
+                    "fails fast".to_string()
+                }
+            } else {
+                self.comparison = user.clone();
+                "| Aspect | a | b |\n|---|---|---|\n| Error handling | retries | fails fast |\n\nPrefer a.".to_string()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compare() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let a = dir.path().join("a.rs");
+        let b = dir.path().join("b.rs");
+        fs::write(&a, "fn fetch() { retry(3); }\n").expect("Failed to write a");
+        fs::write(&b, "fn fetch() { get()?; }\n").expect("Failed to write b");
+        let files: Vec<String> = [&a, &b]
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        let mut model = ScriptedModel {
+            files: files.clone(),
+            comparison: String::new(),
+        };
+
+        let gathered = gather(&mut model, &files, dir.path())
+            .await
+            .expect("Failed to gather");
+        assert_eq!(gathered[0].notes, "retries three times");
+        assert_eq!(gathered[1].notes, "fails fast");
+        assert_eq!(gathered[1].commands, [format!("show_file {}", files[1])]);
+
+        let table = compare(&mut model, &gathered)
+            .await
+            .expect("Failed to compare");
+        assert!(table.starts_with("| Aspect |"));
+        assert!(model
+            .comparison
+            .contains(&format!("## Target 2: {}\n\nfails fast", files[1])));
+        assert!(model.comparison.contains("Error handling, Extensibility"));
+    }
+}
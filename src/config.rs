@@ -0,0 +1,638 @@
+//! # Configuration Module
+//!
+//! This module loads nishiogi settings from TOML files. Two locations are consulted:
+//!
+//! 1. The user configuration at `<config dir>/nishiogi/config.toml`
+//! 2. The project configuration at `<repo root>/.nishiogi/config.toml`
+//!
+//! Project settings are merged on top of user settings table by table, so a project only
+//! needs to specify the keys it wants to change. Missing files are not an error; every field
+//! has a sensible default.
+//!
+//! On top of both, an administrator can provision an organization policy at
+//! [`ORG_POLICY_PATH`] (see [`crate::org_policy`]). It is applied after the merge, so
+//! neither file can select a forbidden model or re-enable a disabled tool.
+//!
+//! Projects can also describe their conventions in prose, in `CONVENTIONS.md` at the
+//! repository root or in `.nishiogi/instructions.md`. The contents of these files are
+//! appended to the system prompt of every step.
+//!
+//! Configuration files carry a `version` key (files without one are version 1). When the
+//! format changes, files written for an older version are upgraded in place on load; the
+//! original is kept next to it as `config.toml.v<N>.bak`. Files written by a newer version
+//! of nishiogi are rejected with an explicit error instead of a schema mismatch.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use toml::{Table, Value};
+
+#[cfg(feature = "server")]
+use crate::tenant::ServerConfig;
+use crate::{
+    github_copilot_client::get_config_path,
+    glossary::GlossaryTerm,
+    org_policy::{OrgPolicy, OrgPolicyConfig, ORG_POLICY_PATH},
+    planner::PlannerConfig,
+    policy::{Permission, PolicyConfig},
+    provider::ProviderKind,
+    review::ReviewConfig,
+    scheduler::LimitsConfig,
+    template::QuestionTemplate,
+    tree::find_repo_root,
+};
+
+/// Name of the per-project directory holding configuration and local state.
+pub const PROJECT_DIR: &str = ".nishiogi";
+
+/// Name of the configuration file inside both configuration directories.
+const CONFIG_FILE: &str = "config.toml";
+
+/// A step upgrading a configuration table by one version.
+type ConfigMigration = fn(&mut Table);
+
+/// Upgrades of the configuration format; entry `i` turns version `i + 1` into `i + 2`.
+const CONFIG_MIGRATIONS: &[ConfigMigration] = &[];
+
+/// Current version of the configuration file format.
+pub const CONFIG_VERSION: usize = CONFIG_MIGRATIONS.len() + 1;
+
+/// Project convention files, relative to the repository root, in the order they are included.
+const INSTRUCTION_FILES: [&str; 2] = ["CONVENTIONS.md", ".nishiogi/instructions.md"];
+
+/// Errors that can occur while loading configuration files.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// A configuration file exists but could not be read.
+    Io(PathBuf, std::io::Error),
+    /// A configuration file is not valid TOML or does not match the expected schema.
+    Parse(PathBuf, String),
+    /// A configuration file was written for a newer version of nishiogi.
+    UnsupportedVersion(PathBuf, i64),
+    /// An outdated configuration file could not be upgraded.
+    Migration(PathBuf, std::io::Error),
+    /// The configuration selects something the organization policy forbids.
+    Forbidden(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => {
+                write!(f, "Failed to read {}: {err}", path.display())
+            }
+            ConfigError::Parse(path, msg) => {
+                write!(f, "Invalid configuration in {}: {msg}", path.display())
+            }
+            ConfigError::UnsupportedVersion(path, version) => write!(
+                f,
+                "{} uses configuration version {version}, but this nishiogi supports up to {CONFIG_VERSION}; upgrade nishiogi",
+                path.display()
+            ),
+            ConfigError::Migration(path, err) => {
+                write!(f, "Failed to upgrade {}: {err}", path.display())
+            }
+            ConfigError::Forbidden(msg) => {
+                write!(f, "{msg}, by the organization policy in {ORG_POLICY_PATH}")
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(_, err) | ConfigError::Migration(_, err) => Some(err),
+            ConfigError::Parse(..)
+            | ConfigError::UnsupportedVersion(..)
+            | ConfigError::Forbidden(_) => None,
+        }
+    }
+}
+
+/// Which provider and model answer questions.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ProviderConfig {
+    /// The provider: `copilot`, `claude`, or `openai`.
+    pub name: ProviderKind,
+    /// The model to use instead of the provider's default one.
+    pub model: Option<String>,
+    /// A model with a larger context window, answering the questions whose context does not
+    /// fit the model's window instead of shortening it.
+    pub large_context_model: Option<String>,
+    /// Prompt price of the large-context model in dollars per million tokens, used to
+    /// estimate the cost of the questions it answers.
+    pub large_context_price: Option<f64>,
+}
+
+/// What nishiogi keeps on disk.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PrivacyConfig {
+    /// Whether questions and answers are saved as sessions.
+    pub save_sessions: bool,
+    /// Whether cached answers to similar questions are offered.
+    pub reuse_answers: bool,
+    /// Whether file paths in exported sessions are replaced with salted hashes.
+    pub hash_paths: bool,
+    /// Whether preferences learned from chats are remembered and applied.
+    pub remember_preferences: bool,
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        Self {
+            save_sessions: true,
+            reuse_answers: true,
+            hash_paths: false,
+            remember_preferences: true,
+        }
+    }
+}
+
+/// Settings for nishiogi, merged from the user and project configuration files.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Provider and model selection.
+    pub provider: ProviderConfig,
+    /// What is kept on disk.
+    pub privacy: PrivacyConfig,
+    /// Tool permission rules.
+    pub policy: PolicyConfig,
+    /// How generated answers are reviewed.
+    pub review: ReviewConfig,
+    /// Settings shaping the planning prompt.
+    pub planner: PlannerConfig,
+    /// How much work may run at once.
+    pub limits: LimitsConfig,
+    /// Repositories and client API keys of `nishiogi serve`.
+    #[cfg(feature = "server")]
+    pub server: ServerConfig,
+    /// Canned questions, by name.
+    pub templates: BTreeMap<String, QuestionTemplate>,
+    /// Project-specific terms, by term.
+    pub glossary: BTreeMap<String, GlossaryTerm>,
+    /// The organization policy the configuration was checked against; never read from the
+    /// configuration files.
+    #[serde(skip)]
+    pub org_policy: OrgPolicy,
+}
+
+impl Config {
+    /// Loads the configuration for the repository containing the current directory.
+    ///
+    /// The organization policy at [`ORG_POLICY_PATH`] is enforced on the result.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError` if a configuration file exists but cannot be read or parsed,
+    /// or if the configuration selects something the organization policy forbids.
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::load_with(None)
+    }
+
+    /// Loads the configuration like [`Config::load`], answering with `provider` instead of
+    /// the configured provider if given.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError` if a configuration file exists but cannot be read or parsed,
+    /// or if the configuration selects something the organization policy forbids.
+    pub fn load_with(provider: Option<ProviderKind>) -> Result<Self, ConfigError> {
+        let cwd = std::env::current_dir().map_err(|e| ConfigError::Io(PathBuf::from("."), e))?;
+        let mut config = Self::load_from(&config_paths(&cwd))?;
+        if let Some(provider) = provider {
+            config.provider.name = provider;
+        }
+        config.enforce(load_org_policy(Path::new(ORG_POLICY_PATH))?)?;
+        Ok(config)
+    }
+
+    /// Applies `policy` on top of the configuration: disabled tools are denied, and a
+    /// forbidden default model is replaced by the first allowed one.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::Forbidden` if the policy does not allow the provider or the
+    /// configured model.
+    pub fn enforce(&mut self, policy: OrgPolicy) -> Result<(), ConfigError> {
+        let provider = self.provider.name;
+        if !policy.allows_provider(provider.name()) {
+            return Err(ConfigError::Forbidden(format!(
+                "Provider {provider} is not allowed"
+            )));
+        }
+        match &self.provider.model {
+            Some(model) if !policy.allows_model(model) => {
+                return Err(ConfigError::Forbidden(format!(
+                    "Model {model} is not allowed (allowed: {})",
+                    policy.config().allowed_models.join(", ")
+                )));
+            }
+            None if !policy.allows_model(provider.default_model()) => {
+                self.provider.model = policy.config().allowed_models.first().cloned();
+            }
+            _ => {}
+        }
+        if let Some(model) = &self.provider.large_context_model
+            && !policy.allows_model(model)
+        {
+            return Err(ConfigError::Forbidden(format!(
+                "Large-context model {model} is not allowed (allowed: {})",
+                policy.config().allowed_models.join(", ")
+            )));
+        }
+        for tool in &policy.config().disabled_tools {
+            self.policy.tools.insert(tool.clone(), Permission::Deny);
+        }
+        self.org_policy = policy;
+        Ok(())
+    }
+
+    /// Sets the permission of `tool` for this run, as the `--tool` flag does.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::Forbidden` if `permission` would let a tool run that the
+    /// organization policy disables.
+    pub fn override_tool(&mut self, tool: &str, permission: Permission) -> Result<(), ConfigError> {
+        if permission != Permission::Deny && self.org_policy.disables_tool(tool) {
+            return Err(ConfigError::Forbidden(format!("Tool {tool} is disabled")));
+        }
+        self.policy.tools.insert(tool.to_string(), permission);
+        Ok(())
+    }
+
+    /// Loads and merges the given configuration files in order, later files taking precedence.
+    ///
+    /// Paths that do not exist are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError` if a file cannot be read, is not valid TOML, or the merged
+    /// result does not match the configuration schema.
+    pub fn load_from(paths: &[PathBuf]) -> Result<Self, ConfigError> {
+        let mut merged = Table::new();
+        let mut last_path = PathBuf::new();
+        for path in paths {
+            if !path.exists() {
+                continue;
+            }
+            let content = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+            let mut table: Table = content
+                .parse()
+                .map_err(|e: toml::de::Error| ConfigError::Parse(path.clone(), e.to_string()))?;
+            upgrade_config(path, &mut table, CONFIG_MIGRATIONS)?;
+            merge_tables(&mut merged, table);
+            last_path.clone_from(path);
+        }
+        Value::Table(merged)
+            .try_into()
+            .map_err(|e: toml::de::Error| ConfigError::Parse(last_path, e.to_string()))
+    }
+}
+
+/// Upgrades `table`, read from `path`, to the latest of `migrations`.
+///
+/// When any migration applies, the original file is backed up and replaced by the upgraded
+/// version. Returns whether the file was upgraded.
+fn upgrade_config(
+    path: &Path,
+    table: &mut Table,
+    migrations: &[ConfigMigration],
+) -> Result<bool, ConfigError> {
+    let latest = migrations.len() + 1;
+    let version = table
+        .get("version")
+        .and_then(Value::as_integer)
+        .unwrap_or(1);
+    let Ok(current) = usize::try_from(version) else {
+        return Err(ConfigError::UnsupportedVersion(path.to_path_buf(), version));
+    };
+    if current > latest {
+        return Err(ConfigError::UnsupportedVersion(path.to_path_buf(), version));
+    }
+    if current == latest {
+        return Ok(false);
+    }
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{current}.bak"));
+    let backup = PathBuf::from(backup);
+    fs::copy(path, &backup).map_err(|e| ConfigError::Migration(path.to_path_buf(), e))?;
+
+    for migration in &migrations[current - 1..] {
+        migration(table);
+    }
+    table.insert(
+        "version".to_string(),
+        Value::Integer(i64::try_from(latest).unwrap_or(i64::MAX)),
+    );
+    let content = toml::to_string(table)
+        .map_err(|e| ConfigError::Parse(path.to_path_buf(), e.to_string()))?;
+    fs::write(path, content).map_err(|e| ConfigError::Migration(path.to_path_buf(), e))?;
+
+    eprintln!(
+        "Upgraded {} from configuration version {current} to {latest} (backup: {})",
+        path.display(),
+        backup.display()
+    );
+    Ok(true)
+}
+
+/// Reads the organization policy at `path`; a missing file allows everything.
+///
+/// # Errors
+///
+/// Returns a `ConfigError` if the file exists but cannot be read, is not valid TOML, or
+/// has an invalid redaction pattern.
+pub fn load_org_policy(path: &Path) -> Result<OrgPolicy, ConfigError> {
+    if !path.exists() {
+        return Ok(OrgPolicy::default());
+    }
+    let content = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+    let config: OrgPolicyConfig = toml::from_str(&content)
+        .map_err(|e| ConfigError::Parse(path.to_path_buf(), e.to_string()))?;
+    OrgPolicy::new(config).map_err(|e| ConfigError::Parse(path.to_path_buf(), e.to_string()))
+}
+
+/// Reads the project convention files under `root`, joined in order.
+///
+/// Returns `None` when no convention file exists or all of them are empty.
+///
+/// # Errors
+///
+/// Returns `ConfigError::Io` if a convention file exists but cannot be read.
+pub fn load_instructions(root: &Path) -> Result<Option<String>, ConfigError> {
+    let mut sections = Vec::new();
+    for file in INSTRUCTION_FILES {
+        let path = root.join(file);
+        if !path.is_file() {
+            continue;
+        }
+        let content = fs::read_to_string(&path).map_err(|e| ConfigError::Io(path, e))?;
+        let content = content.trim();
+        if !content.is_empty() {
+            sections.push(content.to_string());
+        }
+    }
+    Ok((!sections.is_empty()).then(|| sections.join("\n\n")))
+}
+
+/// Returns the root of the repository containing the current directory.
+///
+/// Falls back to the current directory when no repository root is found.
+pub fn repo_root() -> PathBuf {
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    find_repo_root(&cwd).unwrap_or(cwd)
+}
+
+/// Returns the location of the user configuration file.
+///
+/// Returns `None` when the configuration directory cannot be determined.
+pub fn user_config_path() -> Option<PathBuf> {
+    get_config_path()
+        .ok()
+        .map(|config_dir| Path::new(&config_dir).join("nishiogi").join(CONFIG_FILE))
+}
+
+/// Returns whether no configuration file exists for the current directory yet.
+pub fn is_first_run() -> bool {
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    config_paths(&cwd).iter().all(|path| !path.exists())
+}
+
+/// Returns the configuration file locations for `start`, lowest precedence first.
+fn config_paths(start: &Path) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(path) = user_config_path() {
+        paths.push(path);
+    }
+    let root = find_repo_root(start).unwrap_or_else(|| start.to_path_buf());
+    paths.push(root.join(PROJECT_DIR).join(CONFIG_FILE));
+    paths
+}
+
+/// Returns the directory holding nishiogi's user-level state (`~/.nishiogi`).
+///
+/// Returns `None` when the home directory cannot be determined.
+pub fn data_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .filter(|home| !home.is_empty())
+        .map(|home| PathBuf::from(home).join(PROJECT_DIR))
+}
+
+/// Recursively merges `overlay` into `base`; nested tables are merged, other values replaced.
+fn merge_tables(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(incoming)) => {
+                merge_tables(existing, incoming);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_missing_files_yield_default() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let config = Config::load_from(&[temp_dir.path().join("missing.toml")])
+            .expect("Missing files should be skipped");
+        assert!(config.policy.tools.is_empty());
+    }
+
+    #[test]
+    fn test_project_overrides_user() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let user = temp_dir.path().join("user.toml");
+        let project = temp_dir.path().join("project.toml");
+        fs::write(
+            &user,
+            "[policy]\nexec = \"deny\"\n[policy.tools]\nshow_file = \"ask\"\n",
+        )
+        .expect("Failed to write user config");
+        fs::write(&project, "[policy.tools]\nrun = \"allow\"\n")
+            .expect("Failed to write project config");
+
+        let config = Config::load_from(&[user, project]).expect("Failed to load config");
+        assert_eq!(config.policy.exec, Some(Permission::Deny));
+        assert_eq!(config.policy.tools.get("show_file"), Some(&Permission::Ask));
+        assert_eq!(config.policy.tools.get("run"), Some(&Permission::Allow));
+    }
+
+    #[test]
+    fn test_load_instructions() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        assert!(load_instructions(root)
+            .expect("Failed to load instructions")
+            .is_none());
+
+        fs::write(
+            root.join("CONVENTIONS.md"),
+            "Handlers live under routes/.\n",
+        )
+        .expect("Failed to write conventions");
+        fs::create_dir(root.join(PROJECT_DIR)).expect("Failed to create project directory");
+        fs::write(
+            root.join(PROJECT_DIR).join("instructions.md"),
+            "Answer tersely.",
+        )
+        .expect("Failed to write instructions");
+
+        let instructions = load_instructions(root).expect("Failed to load instructions");
+        assert_eq!(
+            instructions.as_deref(),
+            Some("Handlers live under routes/.\n\nAnswer tersely.")
+        );
+    }
+
+    #[test]
+    fn test_outdated_config_is_upgraded_with_backup() {
+        fn rename_exec(table: &mut Table) {
+            if let Some(Value::Table(policy)) = table.get_mut("policy")
+                && let Some(value) = policy.remove("run")
+            {
+                policy.insert("exec".to_string(), value);
+            }
+        }
+
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("config.toml");
+        let original = "[policy]\nrun = \"deny\"\n";
+        fs::write(&path, original).expect("Failed to write config");
+
+        let mut table: Table = original.parse().expect("Invalid TOML");
+        let upgraded =
+            upgrade_config(&path, &mut table, &[rename_exec]).expect("Failed to upgrade");
+        assert!(upgraded);
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("config.toml.v1.bak")).expect("Missing backup"),
+            original
+        );
+
+        let rewritten: Table = fs::read_to_string(&path)
+            .expect("Failed to read config")
+            .parse()
+            .expect("Invalid TOML");
+        assert_eq!(
+            rewritten.get("version").and_then(Value::as_integer),
+            Some(2)
+        );
+        assert!(rewritten["policy"].get("exec").is_some());
+
+        let mut again = rewritten.clone();
+        assert!(!upgrade_config(&path, &mut again, &[rename_exec]).expect("Failed to upgrade"));
+    }
+
+    #[test]
+    fn test_newer_config_is_rejected() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("config.toml");
+        fs::write(&path, "version = 99\n").expect("Failed to write config");
+        let result = Config::load_from(&[path]);
+        assert!(matches!(
+            result,
+            Err(ConfigError::UnsupportedVersion(_, 99))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_toml_is_reported() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("config.toml");
+        fs::write(&path, "[policy\n").expect("Failed to write config");
+        let result = Config::load_from(&[path]);
+        assert!(matches!(result, Err(ConfigError::Parse(..))));
+    }
+
+    #[test]
+    fn test_org_policy_overrides_config() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(&config_path, "[policy.tools]\nrun = \"allow\"\n")
+            .expect("Failed to write config");
+        let policy_path = temp_dir.path().join("policy.toml");
+        fs::write(
+            &policy_path,
+            "allowed_models = [\"gpt-4o\"]\ndisabled_tools = [\"run\"]\n",
+        )
+        .expect("Failed to write policy");
+
+        let mut config =
+            Config::load_from(std::slice::from_ref(&config_path)).expect("Failed to load config");
+        let policy = load_org_policy(&policy_path).expect("Failed to load policy");
+        config
+            .enforce(policy.clone())
+            .expect("Policy rejected the config");
+        assert_eq!(config.policy.tools.get("run"), Some(&Permission::Deny));
+        assert_eq!(config.provider.model.as_deref(), Some("gpt-4o"));
+        // `--tool run=on` cannot enable what the organization disabled
+        assert!(matches!(
+            config.override_tool("run", Permission::Allow),
+            Err(ConfigError::Forbidden(_))
+        ));
+        assert_eq!(config.policy.tools.get("run"), Some(&Permission::Deny));
+        config
+            .override_tool("run", Permission::Deny)
+            .expect("Denying a disabled tool is allowed");
+        config
+            .override_tool("tree", Permission::Deny)
+            .expect("Failed to override tool");
+        assert_eq!(config.policy.tools.get("tree"), Some(&Permission::Deny));
+
+        fs::write(&config_path, "[provider]\nmodel = \"gpt-4\"\n").expect("Failed to write config");
+        let mut config =
+            Config::load_from(std::slice::from_ref(&config_path)).expect("Failed to load config");
+        assert!(matches!(
+            config.enforce(policy.clone()),
+            Err(ConfigError::Forbidden(_))
+        ));
+
+        fs::write(
+            &config_path,
+            "[provider]\nlarge_context_model = \"gpt-4.1\"\nlarge_context_price = 2.0\n",
+        )
+        .expect("Failed to write config");
+        let mut config = Config::load_from(&[config_path]).expect("Failed to load config");
+        assert_eq!(config.provider.large_context_price, Some(2.0));
+        assert!(matches!(
+            config.enforce(policy),
+            Err(ConfigError::Forbidden(_))
+        ));
+
+        let mut config = Config::default();
+        config.provider.name = ProviderKind::Claude;
+        let policy: OrgPolicy = load_org_policy(&policy_path).expect("Failed to load policy");
+        assert!(config.enforce(policy).is_ok());
+        fs::write(&policy_path, "allowed_providers = [\"copilot\"]\n")
+            .expect("Failed to write policy");
+        let policy = load_org_policy(&policy_path).expect("Failed to load policy");
+        assert!(matches!(
+            config.enforce(policy),
+            Err(ConfigError::Forbidden(_))
+        ));
+
+        let missing = load_org_policy(&temp_dir.path().join("missing.toml"));
+        assert!(missing.is_ok_and(|policy| policy.allows_model("gpt-4")));
+    }
+}
@@ -0,0 +1,642 @@
+//! # Container Configuration
+//!
+//! This module implements the `containers` tool, which answers deployment questions from
+//! the parsed Dockerfiles and compose files of a repository:
+//!
+//! - **Dockerfiles**: the build stages with their base images, and for each stage the
+//!   exposed ports, environment variables, copied paths, user, and command. The number of
+//!   layer-creating instructions (`RUN`, `COPY`, `ADD`) is shown per stage.
+//! - **Compose files**: the services with their image or build context, ports,
+//!   environment variables, volumes, and dependencies
+//!
+//! Obvious issues are flagged with their line, most importantly secrets baked into image
+//! layers: secret-looking `ENV` values and build arguments, copied key and `.env` files, and
+//! contexts copied whole without a `.dockerignore`. Unpinned base images, remote `ADD`s,
+//! final stages running as root, and privileged or secret-carrying compose services are
+//! flagged too. The checks are heuristics meant to prompt a closer look, not an audit.
+
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use regex::Regex;
+use serde_yaml::Value;
+
+use crate::build_config::{find_files, logical_lines, truncate};
+
+/// Names of variables likely to hold secrets.
+static SECRET_NAME: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)secret|passw(?:or)?d|token|api_?key|private_?key|credential|access_?key")
+        .expect("Invalid secret name pattern")
+});
+
+/// Names of files likely to hold secrets.
+static SECRET_FILE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(?:^|/)(?:\.env(?:\.[\w.-]+)?|id_rsa|id_ed25519|id_ecdsa|[\w.-]+\.pem|[\w.-]+\.key|\.npmrc|\.pypirc|\.netrc|credentials[\w.-]*|\.aws|\.ssh|\.docker/config\.json)$")
+        .expect("Invalid secret file pattern")
+});
+
+/// An instruction of a Dockerfile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    /// The instruction keyword, in upper case.
+    pub keyword: String,
+    /// The arguments as written, continuation lines joined.
+    pub arguments: String,
+    /// Line of the instruction (1-based).
+    pub line: usize,
+}
+
+/// A build stage of a Dockerfile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DockerStage {
+    /// The base image or earlier stage.
+    pub base: String,
+    /// The stage's name, given with `AS`.
+    pub name: Option<String>,
+    /// Line of the `FROM` instruction (1-based).
+    pub line: usize,
+    /// The instructions after `FROM`.
+    pub instructions: Vec<Instruction>,
+}
+
+impl DockerStage {
+    /// Returns the arguments of the instructions with one of `keywords`.
+    fn arguments<'a>(&'a self, keywords: &'a [&str]) -> impl Iterator<Item = &'a str> {
+        self.instructions
+            .iter()
+            .filter(|instruction| keywords.contains(&instruction.keyword.as_str()))
+            .map(|instruction| instruction.arguments.as_str())
+    }
+}
+
+/// A service of a compose file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ComposeService {
+    /// The service's name.
+    pub name: String,
+    /// The image the service runs.
+    pub image: Option<String>,
+    /// The build context, if the image is built.
+    pub build: Option<String>,
+    /// The published ports.
+    pub ports: Vec<String>,
+    /// The environment variables, with their value if set literally.
+    pub environment: Vec<(String, Option<String>)>,
+    /// The mounted volumes.
+    pub volumes: Vec<String>,
+    /// The services started first.
+    pub depends_on: Vec<String>,
+    /// Whether the service runs privileged.
+    pub privileged: bool,
+}
+
+/// A likely problem in a container configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// Line of the problem (1-based), if known.
+    pub line: Option<usize>,
+    /// What is wrong.
+    pub message: String,
+}
+
+/// Parses the stages of a Dockerfile.
+pub fn parse_dockerfile(content: &str) -> Vec<DockerStage> {
+    let mut stages: Vec<DockerStage> = Vec::new();
+    for (line, text) in logical_lines(content) {
+        let text = text.trim();
+        let (keyword, arguments) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let keyword = keyword.to_ascii_uppercase();
+        let arguments = arguments.trim().to_string();
+        if keyword == "FROM" {
+            let words: Vec<&str> = arguments
+                .split_whitespace()
+                .filter(|word| !word.starts_with("--"))
+                .collect();
+            let name = match words.as_slice() {
+                [_, alias, name, ..] if alias.eq_ignore_ascii_case("as") => {
+                    Some((*name).to_string())
+                }
+                _ => None,
+            };
+            stages.push(DockerStage {
+                base: words.first().copied().unwrap_or_default().to_string(),
+                name,
+                line,
+                instructions: Vec::new(),
+            });
+        } else if let Some(stage) = stages.last_mut() {
+            stage.instructions.push(Instruction {
+                keyword,
+                arguments,
+                line,
+            });
+        }
+    }
+    stages
+}
+
+/// Returns the variable names set by an `ENV` or `ARG` instruction, with their values.
+///
+/// Both `ENV KEY=value OTHER=value` and the legacy `ENV KEY value` are understood.
+fn variables(arguments: &str) -> Vec<(String, Option<String>)> {
+    if !arguments.contains('=') {
+        let mut words = arguments.splitn(2, char::is_whitespace);
+        let name = words.next().unwrap_or_default().to_string();
+        let value = words.next().map(|value| value.trim().to_string());
+        return vec![(name, value.filter(|value| !value.is_empty()))];
+    }
+    arguments
+        .split_whitespace()
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) => (
+                name.to_string(),
+                Some(value.trim_matches('"').to_string()).filter(|value| !value.is_empty()),
+            ),
+            None => (pair.to_string(), None),
+        })
+        .collect()
+}
+
+/// Splits the arguments of a `COPY` or `ADD` into its `--from` stage, sources, and
+/// destination.
+fn copy_parts(arguments: &str) -> (Option<&str>, Vec<&str>, &str) {
+    let mut from = None;
+    let mut paths = Vec::new();
+    if arguments.trim_start().starts_with('[') {
+        // The JSON form, `COPY ["src", "dest"]`
+        paths.extend(
+            arguments
+                .trim_matches(|c| c == '[' || c == ']' || char::is_whitespace(c))
+                .split(',')
+                .map(|path| path.trim().trim_matches('"')),
+        );
+    } else {
+        for word in arguments.split_whitespace() {
+            if let Some(stage) = word.strip_prefix("--from=") {
+                from = Some(stage);
+            } else if !word.starts_with("--") {
+                paths.push(word);
+            }
+        }
+    }
+    let destination = paths.pop().unwrap_or_default();
+    (from, paths, destination)
+}
+
+/// Returns whether a variable value is a literal rather than a reference to another
+/// variable.
+fn is_literal(value: &str) -> bool {
+    !value.is_empty() && !value.starts_with('$')
+}
+
+/// Checks the stages of a Dockerfile for likely problems.
+///
+/// `has_dockerignore` tells whether the build context has a `.dockerignore`.
+pub fn dockerfile_issues(stages: &[DockerStage], has_dockerignore: bool) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut issue = |line: usize, message: String| {
+        issues.push(Issue {
+            line: Some(line),
+            message,
+        });
+    };
+    let stage_names: Vec<&str> = stages
+        .iter()
+        .filter_map(|stage| stage.name.as_deref())
+        .collect();
+    for stage in stages {
+        let image = stage.base.rsplit('/').next().unwrap_or_default();
+        if stage.base != "scratch"
+            && !stage_names.contains(&stage.base.as_str())
+            && !stage.base.contains('@')
+            && !stage.base.starts_with('$')
+            && image.split_once(':').is_none_or(|(_, tag)| tag == "latest")
+        {
+            issue(
+                stage.line,
+                format!("Base image {} is not pinned to a version", stage.base),
+            );
+        }
+        for instruction in &stage.instructions {
+            let line = instruction.line;
+            match instruction.keyword.as_str() {
+                "ENV" => {
+                    for (name, value) in variables(&instruction.arguments) {
+                        if SECRET_NAME.is_match(&name) && value.as_deref().is_some_and(is_literal) {
+                            issue(line, format!("ENV {name} bakes a secret-looking value into the image; pass it at run time instead"));
+                        }
+                    }
+                }
+                "ARG" => {
+                    for (name, _) in variables(&instruction.arguments) {
+                        if SECRET_NAME.is_match(&name) {
+                            issue(line, format!("Build argument {name} looks like a secret; build arguments are kept in the image history, use a secret mount (RUN --mount=type=secret) instead"));
+                        }
+                    }
+                }
+                "COPY" | "ADD" => {
+                    let (from, sources, _) = copy_parts(&instruction.arguments);
+                    if from.is_some() {
+                        continue;
+                    }
+                    for source in sources {
+                        if SECRET_FILE.is_match(source.trim_end_matches('/')) {
+                            issue(
+                                line,
+                                format!(
+                                    "{} copies {source}, which looks like a secret, into a layer",
+                                    instruction.keyword
+                                ),
+                            );
+                        } else if (source == "." || source == "./") && !has_dockerignore {
+                            issue(line, format!("{} copies the whole build context without a .dockerignore, so local secrets and .git end up in a layer", instruction.keyword));
+                        } else if instruction.keyword == "ADD" && source.contains("://") {
+                            issue(line, format!("ADD downloads {source} without verifying it; use a pinned checksum (ADD --checksum) or curl with verification"));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if let Some(last) = stages.last() {
+        let user = last.arguments(&["USER"]).last();
+        if user.is_none_or(|user| user == "root" || user == "0" || user.starts_with("0:")) {
+            let line = last
+                .instructions
+                .iter()
+                .rfind(|instruction| instruction.keyword == "USER")
+                .map_or(last.line, |instruction| instruction.line);
+            issue(
+                line,
+                "The final stage runs as root; add a USER with an unprivileged account".to_string(),
+            );
+        }
+    }
+    issues
+}
+
+/// Parses the services of a compose file.
+///
+/// # Errors
+///
+/// Returns the YAML error if the file is not valid YAML.
+pub fn parse_compose(content: &str) -> Result<Vec<ComposeService>, serde_yaml::Error> {
+    let document: Value = serde_yaml::from_str(content)?;
+    let Some(services) = document["services"].as_mapping() else {
+        return Ok(Vec::new());
+    };
+    Ok(services
+        .iter()
+        .map(|(name, service)| ComposeService {
+            name: scalar(name).unwrap_or_default(),
+            image: scalar(&service["image"]),
+            build: scalar(&service["build"]).or_else(|| scalar(&service["build"]["context"])),
+            ports: strings(&service["ports"]),
+            environment: match &service["environment"] {
+                Value::Mapping(variables) => variables
+                    .iter()
+                    .filter_map(|(name, value)| Some((scalar(name)?, scalar(value))))
+                    .collect(),
+                variables => strings(variables)
+                    .into_iter()
+                    .map(|variable| match variable.split_once('=') {
+                        Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                        None => (variable, None),
+                    })
+                    .collect(),
+            },
+            volumes: strings(&service["volumes"]),
+            depends_on: match &service["depends_on"] {
+                Value::Mapping(services) => services.keys().filter_map(scalar).collect(),
+                services => strings(services),
+            },
+            privileged: service["privileged"].as_bool().unwrap_or(false),
+        })
+        .collect())
+}
+
+/// Checks the services of a compose file for likely problems.
+pub fn compose_issues(services: &[ComposeService]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for service in services {
+        for (name, value) in &service.environment {
+            if SECRET_NAME.is_match(name) && value.as_deref().is_some_and(is_literal) {
+                issues.push(Issue {
+                    line: None,
+                    message: format!("Service {} sets {name} literally in the compose file; use an env_file or secrets instead", service.name),
+                });
+            }
+        }
+        if service.privileged {
+            issues.push(Issue {
+                line: None,
+                message: format!("Service {} runs privileged", service.name),
+            });
+        }
+        if service
+            .volumes
+            .iter()
+            .any(|volume| volume.starts_with("/var/run/docker.sock"))
+        {
+            issues.push(Issue {
+                line: None,
+                message: format!(
+                    "Service {} mounts the Docker socket, which gives it root on the host",
+                    service.name
+                ),
+            });
+        }
+    }
+    issues
+}
+
+/// Returns a scalar as a string.
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+}
+
+/// Returns a scalar or a sequence of scalars as strings; long-form entries such as
+/// `{target: 80, published: 8080}` are written as `key=value` pairs.
+fn strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::Sequence(values) => values
+            .iter()
+            .filter_map(|value| match value {
+                Value::Mapping(fields) => Some(
+                    fields
+                        .iter()
+                        .filter_map(|(key, value)| {
+                            Some(format!("{}={}", scalar(key)?, scalar(value)?))
+                        })
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ),
+                value => scalar(value),
+            })
+            .collect(),
+        value => scalar(value).into_iter().collect(),
+    }
+}
+
+/// Runs the `containers` tool on `dir`, skipping the files `hidden` accepts.
+pub fn run_containers(dir: &Path, hidden: &dyn Fn(&Path) -> bool) -> String {
+    let mut output = String::new();
+    for path in find_files(dir, is_dockerfile, hidden) {
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let stages = parse_dockerfile(&content);
+        let context = path.parent().unwrap_or(dir);
+        let has_dockerignore = context.join(".dockerignore").is_file()
+            || path
+                .with_file_name(format!("{}.dockerignore", file_name(&path)))
+                .is_file();
+        let _ = writeln!(output, "{}", relative(dir, &path).display());
+        render_stages(&mut output, &stages);
+        render_issues(&mut output, &dockerfile_issues(&stages, has_dockerignore));
+        output.push('\n');
+    }
+    for path in find_files(dir, is_compose_file, hidden) {
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let _ = writeln!(output, "{}", relative(dir, &path).display());
+        match parse_compose(&content) {
+            Ok(services) => {
+                render_services(&mut output, &services);
+                render_issues(&mut output, &compose_issues(&services));
+            }
+            Err(err) => {
+                let _ = writeln!(output, "  invalid YAML: {err}");
+            }
+        }
+        output.push('\n');
+    }
+    if output.is_empty() {
+        return format!("No Dockerfiles or compose files in {}", dir.display());
+    }
+    output
+}
+
+fn render_stages(output: &mut String, stages: &[DockerStage]) {
+    for (index, stage) in stages.iter().enumerate() {
+        let role = if index + 1 == stages.len() {
+            "final stage"
+        } else {
+            "stage"
+        };
+        let name = stage
+            .name
+            .as_ref()
+            .map(|name| format!(" {name}"))
+            .unwrap_or_default();
+        let layers = stage
+            .instructions
+            .iter()
+            .filter(|instruction| matches!(instruction.keyword.as_str(), "RUN" | "COPY" | "ADD"))
+            .count();
+        let _ = writeln!(
+            output,
+            "  {role}{name} from {} (line {}), {layers} layer(s)",
+            stage.base, stage.line
+        );
+        let ports: Vec<&str> = stage
+            .arguments(&["EXPOSE"])
+            .flat_map(str::split_whitespace)
+            .collect();
+        if !ports.is_empty() {
+            let _ = writeln!(output, "    exposes: {}", ports.join(", "));
+        }
+        for (label, keyword) in [("env", "ENV"), ("build args", "ARG")] {
+            let names: Vec<String> = stage
+                .arguments(&[keyword])
+                .flat_map(variables)
+                .map(|(name, _)| name)
+                .collect();
+            if !names.is_empty() {
+                let _ = writeln!(output, "    {label}: {}", names.join(", "));
+            }
+        }
+        for instruction in &stage.instructions {
+            if instruction.keyword == "COPY" || instruction.keyword == "ADD" {
+                let (from, sources, destination) = copy_parts(&instruction.arguments);
+                let from = from
+                    .map(|from| format!(" (from {from})"))
+                    .unwrap_or_default();
+                let _ = writeln!(
+                    output,
+                    "    copies: {}{from} -> {destination}",
+                    sources.join(" ")
+                );
+            }
+        }
+        if let Some(user) = stage.arguments(&["USER"]).last() {
+            let _ = writeln!(output, "    user: {user}");
+        }
+        for keyword in ["ENTRYPOINT", "CMD"] {
+            if let Some(command) = stage.arguments(&[keyword]).last() {
+                let _ = writeln!(
+                    output,
+                    "    {}: {}",
+                    keyword.to_lowercase(),
+                    truncate(command)
+                );
+            }
+        }
+    }
+}
+
+fn render_services(output: &mut String, services: &[ComposeService]) {
+    for service in services {
+        let mut details = Vec::new();
+        if let Some(image) = &service.image {
+            details.push(format!("image {image}"));
+        }
+        if let Some(build) = &service.build {
+            details.push(format!("built from {build}"));
+        }
+        if !service.ports.is_empty() {
+            details.push(format!("ports {}", service.ports.join(", ")));
+        }
+        if !service.environment.is_empty() {
+            let names: Vec<&str> = service
+                .environment
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect();
+            details.push(format!("env {}", names.join(", ")));
+        }
+        if !service.volumes.is_empty() {
+            details.push(format!("volumes {}", service.volumes.join(", ")));
+        }
+        if !service.depends_on.is_empty() {
+            details.push(format!("depends on {}", service.depends_on.join(", ")));
+        }
+        let _ = writeln!(output, "  service {}: {}", service.name, details.join("; "));
+    }
+}
+
+fn render_issues(output: &mut String, issues: &[Issue]) {
+    if issues.is_empty() {
+        return;
+    }
+    output.push_str("  issues:\n");
+    for issue in issues {
+        match issue.line {
+            Some(line) => {
+                let _ = writeln!(output, "    line {line}: {}", issue.message);
+            }
+            None => {
+                let _ = writeln!(output, "    {}", issue.message);
+            }
+        }
+    }
+}
+
+fn relative(dir: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(dir).unwrap_or(path).to_path_buf()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn is_dockerfile(name: &str) -> bool {
+    name == "Dockerfile"
+        || name == "Containerfile"
+        || name.starts_with("Dockerfile.")
+        || name.ends_with(".Dockerfile")
+        || name.ends_with(".dockerfile")
+}
+
+fn is_compose_file(name: &str) -> bool {
+    let Some(stem) = name
+        .strip_suffix(".yml")
+        .or_else(|| name.strip_suffix(".yaml"))
+    else {
+        return false;
+    };
+    ["compose", "docker-compose"]
+        .iter()
+        .any(|prefix| stem == *prefix || stem.starts_with(&format!("{prefix}.")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dockerfile_issues() {
+        let stages = parse_dockerfile(
+            "# syntax=docker/dockerfile:1\nFROM --platform=$BUILDPLATFORM rust:1.80 AS build\nARG NPM_TOKEN\nWORKDIR /app\nCOPY . .\nRUN cargo build --release && \\\n    strip target/release/server\n\nFROM debian\nENV RUST_LOG=info API_KEY=\"abc123\" DB_PASSWORD=$DB_PASSWORD\nCOPY --from=build /app/target/release/server /usr/local/bin/\nCOPY config/.env /etc/app/\nEXPOSE 8080 9090\nCMD [\"server\"]\n",
+        );
+        assert_eq!(stages.len(), 2);
+        assert_eq!(stages[0].name.as_deref(), Some("build"));
+        assert_eq!(stages[0].instructions[3].line, 6);
+        assert_eq!(
+            stages[0].instructions[3].arguments,
+            "cargo build --release && strip target/release/server"
+        );
+
+        let found = dockerfile_issues(&stages, false);
+        let summary: Vec<(Option<usize>, &str)> = found
+            .iter()
+            .map(|issue| {
+                (
+                    issue.line,
+                    issue.message.split_whitespace().next().unwrap_or_default(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Some(3), "Build"),
+                (Some(5), "COPY"),
+                (Some(9), "Base"),
+                (Some(10), "ENV"),
+                (Some(12), "COPY"),
+                (Some(9), "The"),
+            ]
+        );
+        assert!(found[3].message.contains("API_KEY"));
+        // A .dockerignore keeps the context copy from being flagged
+        assert_eq!(dockerfile_issues(&stages, true).len(), 5);
+
+        let mut output = String::new();
+        render_stages(&mut output, &stages);
+        assert!(output.contains("  final stage from debian (line 9), 2 layer(s)\n    exposes: 8080, 9090\n    env: RUST_LOG, API_KEY, DB_PASSWORD\n    copies: /app/target/release/server (from build) -> /usr/local/bin/\n"));
+    }
+
+    #[test]
+    fn test_compose() {
+        let services = parse_compose(
+            "services:\n  web:\n    build: .\n    ports:\n      - \"8080:8080\"\n    environment:\n      DATABASE_URL: postgres://db/app\n      SECRET_KEY: hunter2\n      API_TOKEN: ${API_TOKEN}\n    depends_on:\n      db:\n        condition: service_healthy\n  db:\n    image: postgres:16\n    environment:\n      - POSTGRES_PASSWORD=postgres\n    volumes:\n      - /var/run/docker.sock:/var/run/docker.sock\n",
+        )
+        .expect("Failed to parse compose file");
+        assert_eq!(services[0].build.as_deref(), Some("."));
+        assert_eq!(services[0].depends_on, ["db"]);
+        assert_eq!(services[1].image.as_deref(), Some("postgres:16"));
+        let issues: Vec<String> = compose_issues(&services)
+            .into_iter()
+            .map(|issue| issue.message)
+            .collect();
+        assert_eq!(issues.len(), 3);
+        assert!(issues[0].contains("web sets SECRET_KEY"));
+        assert!(issues[1].contains("db sets POSTGRES_PASSWORD"));
+        assert!(issues[2].contains("Docker socket"));
+    }
+}
@@ -0,0 +1,301 @@
+//! # Coverage Reports
+//!
+//! This module reads test coverage reports so answers about whether code is tested can cite
+//! measured line coverage instead of guessing from test file names. Two formats are
+//! understood:
+//!
+//! - **lcov** (`lcov.info`), produced by `cargo llvm-cov`, `grcov`, Jest, c8, and others
+//! - **Cobertura** XML (`coverage.xml`), produced by `coverage.py`, `cargo tarpaulin`, and
+//!   others
+//!
+//! The first report found in a few conventional locations is used. Reports are not
+//! regenerated, so the coverage reflects the test run that wrote the report; its
+//! modification time is shown to make stale reports easy to spot.
+
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use chrono::{DateTime, Local};
+use regex::Regex;
+
+/// Conventional report locations relative to the repository root, in lookup order.
+const REPORT_PATHS: [&str; 8] = [
+    "lcov.info",
+    "coverage/lcov.info",
+    "target/coverage/lcov.info",
+    "target/llvm-cov/lcov.info",
+    "coverage.xml",
+    "cobertura.xml",
+    "coverage/cobertura-coverage.xml",
+    "target/tarpaulin/cobertura.xml",
+];
+
+/// An XML tag relevant to Cobertura reports.
+static COBERTURA_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<(class|line)\s[^>]*>").expect("Invalid Cobertura tag pattern"));
+
+/// An attribute of an XML tag.
+static XML_ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"([\w-]+)="([^"]*)""#).expect("Invalid XML attribute pattern"));
+
+/// Line coverage of one source file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileCoverage {
+    /// The source file as named in the report.
+    pub path: PathBuf,
+    /// Number of instrumented lines.
+    pub lines_found: usize,
+    /// Number of instrumented lines executed at least once.
+    pub lines_hit: usize,
+}
+
+impl FileCoverage {
+    /// Returns the percentage of instrumented lines that were executed.
+    pub fn percent(&self) -> f64 {
+        percent(self.lines_hit, self.lines_found)
+    }
+}
+
+/// A parsed coverage report.
+#[derive(Debug, Clone)]
+pub struct CoverageReport {
+    /// Where the report was read from.
+    pub source: PathBuf,
+    /// Coverage per file, in report order.
+    pub files: Vec<FileCoverage>,
+}
+
+/// Finds and parses the coverage report of the repository at `root`.
+///
+/// Returns `None` if no report exists in a conventional location.
+pub fn find_report(root: &Path) -> Option<CoverageReport> {
+    REPORT_PATHS.iter().find_map(|relative| {
+        let path = root.join(relative);
+        let content = fs::read_to_string(&path).ok()?;
+        let files = if relative.ends_with(".xml") {
+            parse_cobertura(&content)
+        } else {
+            parse_lcov(&content)
+        };
+        Some(CoverageReport {
+            source: path,
+            files,
+        })
+    })
+}
+
+/// Parses an lcov tracefile.
+pub fn parse_lcov(content: &str) -> Vec<FileCoverage> {
+    let mut files = Vec::new();
+    let mut current: Option<FileCoverage> = None;
+    // Line counts from DA records, used when LF/LH summaries are missing
+    let (mut found, mut hit) = (0, 0);
+    for line in content.lines() {
+        let line = line.trim();
+        if let Some(path) = line.strip_prefix("SF:") {
+            current = Some(FileCoverage {
+                path: PathBuf::from(path),
+                lines_found: 0,
+                lines_hit: 0,
+            });
+            (found, hit) = (0, 0);
+        } else if let Some(record) = line.strip_prefix("DA:") {
+            found += 1;
+            let hits = record.split(',').nth(1).unwrap_or("0");
+            if hits.parse::<u64>().unwrap_or(0) > 0 {
+                hit += 1;
+            }
+        } else if let (Some(value), Some(file)) = (line.strip_prefix("LF:"), current.as_mut()) {
+            file.lines_found = value.parse().unwrap_or(0);
+        } else if let (Some(value), Some(file)) = (line.strip_prefix("LH:"), current.as_mut()) {
+            file.lines_hit = value.parse().unwrap_or(0);
+        } else if line == "end_of_record"
+            && let Some(mut file) = current.take()
+        {
+            if file.lines_found == 0 {
+                (file.lines_found, file.lines_hit) = (found, hit);
+            }
+            files.push(file);
+        }
+    }
+    files
+}
+
+/// Parses a Cobertura XML report.
+pub fn parse_cobertura(content: &str) -> Vec<FileCoverage> {
+    let mut files: Vec<FileCoverage> = Vec::new();
+    let mut current: Option<usize> = None;
+    for tag in COBERTURA_TAG.captures_iter(content) {
+        let attribute = |name: &str| {
+            XML_ATTRIBUTE
+                .captures_iter(&tag[0])
+                .find(|attribute| &attribute[1] == name)
+                .map(|attribute| attribute[2].to_string())
+        };
+        if &tag[1] == "class" {
+            let Some(filename) = attribute("filename") else {
+                current = None;
+                continue;
+            };
+            // Classes of the same file are reported separately
+            let path = PathBuf::from(filename);
+            current = Some(match files.iter().position(|file| file.path == path) {
+                Some(index) => index,
+                None => {
+                    files.push(FileCoverage {
+                        path,
+                        lines_found: 0,
+                        lines_hit: 0,
+                    });
+                    files.len() - 1
+                }
+            });
+        } else if let Some(index) = current {
+            let hits: u64 = attribute("hits")
+                .and_then(|hits| hits.parse().ok())
+                .unwrap_or(0);
+            files[index].lines_found += 1;
+            if hits > 0 {
+                files[index].lines_hit += 1;
+            }
+        }
+    }
+    files
+}
+
+/// Renders the coverage of the files under `filter` (a file or directory), or of every
+/// file if `filter` is empty.
+///
+/// Paths in the report are shown relative to `root` where possible.
+pub fn render_coverage(report: &CoverageReport, root: &Path, filter: &str) -> String {
+    let filter = filter.trim().trim_start_matches("./").trim_end_matches('/');
+    let mut output = format!("Coverage from {}", report.source.display());
+    if let Ok(modified) = fs::metadata(&report.source).and_then(|meta| meta.modified()) {
+        let modified: DateTime<Local> = modified.into();
+        let _ = write!(output, " (written {})", modified.format("%Y-%m-%d %H:%M"));
+    }
+    output.push_str(":\n");
+
+    let (mut found, mut hit) = (0, 0);
+    let mut matched = 0;
+    for file in &report.files {
+        let path = file.path.strip_prefix(root).unwrap_or(&file.path);
+        if !filter.is_empty() && filter != "." && !path.starts_with(filter) {
+            continue;
+        }
+        matched += 1;
+        found += file.lines_found;
+        hit += file.lines_hit;
+        let _ = writeln!(
+            output,
+            "  {}  {}/{} lines ({:.1}%)",
+            path.display(),
+            file.lines_hit,
+            file.lines_found,
+            file.percent()
+        );
+    }
+
+    if matched == 0 {
+        let _ = writeln!(
+            output,
+            "  No coverage data for {filter}: it was not run by the tests that produced the report, or was excluded from it."
+        );
+    } else {
+        let _ = writeln!(
+            output,
+            "Total: {hit}/{found} lines ({:.1}%) in {matched} file(s)",
+            percent(hit, found)
+        );
+    }
+    output
+}
+
+/// Explains that no report exists and how to produce one.
+pub fn missing_report_message() -> String {
+    format!(
+        "No coverage report found (looked for {}). Coverage is unknown; generate a report with e.g. `cargo llvm-cov --lcov --output-path lcov.info` or `coverage xml`.\n",
+        REPORT_PATHS.join(", ")
+    )
+}
+
+/// Returns `hit` as a percentage of `found`; files without instrumented lines count as 0%.
+fn percent(hit: usize, found: usize) -> f64 {
+    if found == 0 {
+        0.0
+    } else {
+        hit as f64 * 100.0 / found as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_lcov() {
+        let content = "TN:\nSF:/repo/src/a.rs\nDA:1,3\nDA:2,0\nLF:2\nLH:1\nend_of_record\nSF:/repo/src/b.rs\nDA:1,1\nDA:2,1\nDA:3,0\nend_of_record\n";
+        assert_eq!(
+            parse_lcov(content),
+            vec![
+                FileCoverage {
+                    path: PathBuf::from("/repo/src/a.rs"),
+                    lines_found: 2,
+                    lines_hit: 1,
+                },
+                FileCoverage {
+                    path: PathBuf::from("/repo/src/b.rs"),
+                    lines_found: 3,
+                    lines_hit: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_cobertura() {
+        let content = r#"<?xml version="1.0"?>
+<coverage line-rate="0.5">
+  <packages><package name="app"><classes>
+    <class name="A" filename="app/a.py" line-rate="0.5">
+      <lines><line number="1" hits="2"/><line number="2" hits="0"/></lines>
+    </class>
+    <class name="B" filename="app/a.py" line-rate="1">
+      <lines><line number="9" hits="1"/></lines>
+    </class>
+  </classes></package></packages>
+</coverage>"#;
+        assert_eq!(
+            parse_cobertura(content),
+            vec![FileCoverage {
+                path: PathBuf::from("app/a.py"),
+                lines_found: 3,
+                lines_hit: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_render_filters_by_path() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        let lcov = format!(
+            "SF:{}\nLF:4\nLH:3\nend_of_record\nSF:tests/it.rs\nLF:2\nLH:2\nend_of_record\n",
+            root.join("src/lib.rs").display()
+        );
+        fs::write(root.join("lcov.info"), lcov).expect("Failed to write report");
+
+        let report = find_report(root).expect("Expected a report");
+        let output = render_coverage(&report, root, "src");
+        assert!(output.contains("  src/lib.rs  3/4 lines (75.0%)\n"));
+        assert!(output.contains("Total: 3/4 lines (75.0%) in 1 file(s)\n"));
+        assert!(!output.contains("tests/it.rs"));
+        assert!(render_coverage(&report, root, "src/main.rs").contains("No coverage data"));
+    }
+}
@@ -0,0 +1,472 @@
+//! # Local Database
+//!
+//! This module keeps all of nishiogi's per-repository state in one SQLite database at
+//! `<repo root>/.nishiogi/nishiogi.db`: saved sessions, the response cache, the command
+//! cache, and index metadata. Keeping everything in one file makes cache management and
+//! cleanup atomic. The database is listed in `.nishiogi/.gitignore` when it is created so
+//! that it is not committed alongside the project configuration.
+//!
+//! The schema is versioned with SQLite's `user_version` pragma. Opening a database applies
+//! every migration newer than its version inside a transaction.
+//!
+//! [`Database::clean`] removes cached data, index metadata, or sessions in a single
+//! transaction and compacts the file afterwards.
+
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::config::PROJECT_DIR;
+
+/// Name of the database file inside the project directory.
+pub const DB_FILE: &str = "nishiogi.db";
+
+/// Prefix of the metadata keys describing indexes.
+pub const INDEX_META_PREFIX: &str = "index.";
+
+/// Schema migrations; the database version is the number of migrations applied.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE sessions (
+        id TEXT PRIMARY KEY,
+        created_at TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE response_cache (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE TABLE command_cache (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE TABLE meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );",
+    "CREATE TABLE session_backups (
+        id TEXT NOT NULL,
+        version INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        backed_up_at TEXT NOT NULL
+    );",
+];
+
+/// Errors that can occur while accessing the database.
+#[derive(Debug)]
+pub enum DbError {
+    /// The database directory could not be prepared.
+    Io(PathBuf, io::Error),
+    /// SQLite reported an error.
+    Sqlite(rusqlite::Error),
+    /// The database was written by a newer version of nishiogi.
+    UnsupportedVersion(usize),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Io(path, err) => write!(f, "Failed to access {}: {err}", path.display()),
+            DbError::Sqlite(err) => write!(f, "Database error: {err}"),
+            DbError::UnsupportedVersion(version) => write!(
+                f,
+                "Database schema version {version} is newer than supported ({}); upgrade nishiogi",
+                MIGRATIONS.len()
+            ),
+        }
+    }
+}
+
+impl Error for DbError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DbError::Io(_, err) => Some(err),
+            DbError::Sqlite(err) => Some(err),
+            DbError::UnsupportedVersion(_) => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(error: rusqlite::Error) -> Self {
+        DbError::Sqlite(error)
+    }
+}
+
+/// The kinds of cached values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    /// Model responses, keyed by a hash of the prompt.
+    Response,
+    /// Tool outputs, keyed by the command and the state of its inputs.
+    Command,
+}
+
+impl CacheKind {
+    fn table(self) -> &'static str {
+        match self {
+            CacheKind::Response => "response_cache",
+            CacheKind::Command => "command_cache",
+        }
+    }
+}
+
+/// What [`Database::clean`] should remove.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CleanTargets {
+    /// Cached model responses and command outputs.
+    pub cache: bool,
+    /// Index metadata.
+    pub index: bool,
+    /// Saved sessions.
+    pub sessions: bool,
+}
+
+/// What [`Database::clean`] removed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CleanReport {
+    /// Number of removed cache entries.
+    pub cache_entries: usize,
+    /// Number of removed index metadata entries.
+    pub index_entries: usize,
+    /// Number of removed sessions.
+    pub sessions: usize,
+    /// Bytes by which the database files shrank.
+    pub reclaimed_bytes: u64,
+}
+
+impl fmt::Display for CleanReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Removed {} cache entries", self.cache_entries)?;
+        writeln!(f, "Removed {} index entries", self.index_entries)?;
+        writeln!(f, "Removed {} sessions", self.sessions)?;
+        writeln!(f, "Reclaimed {}", format_bytes(self.reclaimed_bytes))
+    }
+}
+
+/// Formats a byte count with a binary unit, e.g. `1.5 MiB`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// A connection to a nishiogi database.
+pub struct Database {
+    conn: Connection,
+    path: Option<PathBuf>,
+}
+
+impl Database {
+    /// Opens the database of the repository rooted at `root`, creating it if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DbError` if the database cannot be created, opened, or migrated.
+    pub fn open_for_repo(root: &Path) -> Result<Self, DbError> {
+        let dir = root.join(PROJECT_DIR);
+        fs::create_dir_all(&dir).map_err(|e| DbError::Io(dir.clone(), e))?;
+        let gitignore = dir.join(".gitignore");
+        if !gitignore.exists() {
+            fs::write(&gitignore, format!("{DB_FILE}\n{DB_FILE}-*\n"))
+                .map_err(|e| DbError::Io(gitignore, e))?;
+        }
+        Self::open(&dir.join(DB_FILE))
+    }
+
+    /// Opens the database at `path`, creating it if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DbError` if the database cannot be opened or migrated.
+    pub fn open(path: &Path) -> Result<Self, DbError> {
+        let mut db = Self {
+            conn: Connection::open(path)?,
+            path: Some(path.to_path_buf()),
+        };
+        db.migrate()?;
+        Ok(db)
+    }
+
+    /// Opens a fresh database that only lives in memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DbError` if the database cannot be created.
+    pub fn open_in_memory() -> Result<Self, DbError> {
+        let mut db = Self {
+            conn: Connection::open_in_memory()?,
+            path: None,
+        };
+        db.migrate()?;
+        Ok(db)
+    }
+
+    /// Returns the path of the database file, or `None` for in-memory databases.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the schema version of the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DbError` if the version cannot be read.
+    pub fn schema_version(&self) -> Result<usize, DbError> {
+        let version: i64 = self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        Ok(usize::try_from(version).unwrap_or(0))
+    }
+
+    /// Looks up a cached value.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DbError` if the cache cannot be read.
+    pub fn cache_get(&self, kind: CacheKind, key: &str) -> Result<Option<String>, DbError> {
+        let sql = format!("SELECT value FROM {} WHERE key = ?1", kind.table());
+        Ok(self
+            .conn
+            .query_row(&sql, params![key], |row| row.get(0))
+            .optional()?)
+    }
+
+    /// Stores a value in the cache, replacing any previous value for `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DbError` if the cache cannot be written.
+    pub fn cache_put(&self, kind: CacheKind, key: &str, value: &str) -> Result<(), DbError> {
+        let sql = format!(
+            "INSERT OR REPLACE INTO {} (key, value, created_at) VALUES (?1, ?2, ?3)",
+            kind.table()
+        );
+        self.conn
+            .execute(&sql, params![key, value, Utc::now().to_rfc3339()])?;
+        Ok(())
+    }
+
+    /// Looks up a metadata value, such as the state of an index.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DbError` if the metadata cannot be read.
+    pub fn meta(&self, key: &str) -> Result<Option<String>, DbError> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Stores a metadata value, replacing any previous value for `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DbError` if the metadata cannot be written.
+    pub fn set_meta(&self, key: &str, value: &str) -> Result<(), DbError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO meta (key, value, updated_at) VALUES (?1, ?2, ?3)",
+            params![key, value, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// Removes the selected data in one transaction and compacts the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DbError` if the data cannot be removed or the database compacted.
+    pub fn clean(&mut self, targets: CleanTargets) -> Result<CleanReport, DbError> {
+        let size_before = self.size_on_disk();
+        let mut report = CleanReport::default();
+
+        let tx = self.conn.transaction()?;
+        if targets.cache {
+            for kind in [CacheKind::Response, CacheKind::Command] {
+                report.cache_entries += tx.execute(&format!("DELETE FROM {}", kind.table()), [])?;
+            }
+        }
+        if targets.index {
+            report.index_entries = tx.execute(
+                "DELETE FROM meta WHERE key LIKE ?1 || '%'",
+                params![INDEX_META_PREFIX],
+            )?;
+        }
+        if targets.sessions {
+            report.sessions = tx.execute("DELETE FROM sessions", [])?;
+            tx.execute("DELETE FROM session_backups", [])?;
+        }
+        tx.commit()?;
+
+        self.conn.execute_batch("VACUUM")?;
+        report.reclaimed_bytes = size_before.saturating_sub(self.size_on_disk());
+        Ok(report)
+    }
+
+    /// Returns the size of the database files in bytes, or 0 for in-memory databases.
+    pub fn size_on_disk(&self) -> u64 {
+        let Some(path) = &self.path else {
+            return 0;
+        };
+        ["", "-wal", "-shm", "-journal"]
+            .iter()
+            .filter_map(|suffix| {
+                let mut file = path.clone().into_os_string();
+                file.push(suffix);
+                fs::metadata(file).ok()
+            })
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+
+    /// Returns the underlying connection for modules that own a table.
+    pub(crate) fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Applies the migrations the database has not seen yet.
+    fn migrate(&mut self) -> Result<(), DbError> {
+        let version = self.schema_version()?;
+        if version > MIGRATIONS.len() {
+            return Err(DbError::UnsupportedVersion(version));
+        }
+
+        let tx = self.conn.transaction()?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", index + 1)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_applied_once() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let db = Database::open_for_repo(temp_dir.path()).expect("Failed to open database");
+        assert_eq!(
+            db.schema_version().expect("Failed to read version"),
+            MIGRATIONS.len()
+        );
+        db.set_meta("index", "fresh").expect("Failed to write meta");
+        drop(db);
+
+        let db = Database::open_for_repo(temp_dir.path()).expect("Failed to reopen database");
+        assert_eq!(
+            db.meta("index").expect("Failed to read meta").as_deref(),
+            Some("fresh")
+        );
+        let gitignore = fs::read_to_string(temp_dir.path().join(PROJECT_DIR).join(".gitignore"))
+            .expect("Failed to read .gitignore");
+        assert!(gitignore.contains(DB_FILE));
+    }
+
+    #[test]
+    fn test_newer_schema_is_rejected() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let path = temp_dir.path().join(DB_FILE);
+        let conn = Connection::open(&path).expect("Failed to create database");
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .expect("Failed to set version");
+        drop(conn);
+
+        assert!(matches!(
+            Database::open(&path),
+            Err(DbError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn test_cache_kinds_are_separate() {
+        let db = Database::open_in_memory().expect("Failed to open database");
+        db.cache_put(CacheKind::Response, "k", "response")
+            .expect("Failed to write cache");
+        db.cache_put(CacheKind::Command, "k", "command")
+            .expect("Failed to write cache");
+        db.cache_put(CacheKind::Command, "k", "updated")
+            .expect("Failed to write cache");
+
+        assert_eq!(
+            db.cache_get(CacheKind::Response, "k")
+                .expect("Failed to read cache")
+                .as_deref(),
+            Some("response")
+        );
+        assert_eq!(
+            db.cache_get(CacheKind::Command, "k")
+                .expect("Failed to read cache")
+                .as_deref(),
+            Some("updated")
+        );
+        assert!(db
+            .cache_get(CacheKind::Command, "missing")
+            .expect("Failed to read cache")
+            .is_none());
+    }
+
+    #[test]
+    fn test_clean_selected_targets() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let mut db =
+            Database::open(&temp_dir.path().join(DB_FILE)).expect("Failed to open database");
+        let large = "x".repeat(64 * 1024);
+        for key in ["a", "b"] {
+            db.cache_put(CacheKind::Response, key, &large)
+                .expect("Failed to write cache");
+        }
+        db.set_meta("index.files", "42")
+            .expect("Failed to write meta");
+        db.set_meta("other", "kept").expect("Failed to write meta");
+
+        let report = db
+            .clean(CleanTargets {
+                cache: true,
+                index: true,
+                sessions: false,
+            })
+            .expect("Failed to clean database");
+        assert_eq!(report.cache_entries, 2);
+        assert_eq!(report.index_entries, 1);
+        assert_eq!(report.sessions, 0);
+        assert!(report.reclaimed_bytes > 64 * 1024);
+        assert!(db
+            .meta("index.files")
+            .expect("Failed to read meta")
+            .is_none());
+        assert!(db.meta("other").expect("Failed to read meta").is_some());
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+    }
+}
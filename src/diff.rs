@@ -0,0 +1,224 @@
+//! # Line Diff
+//!
+//! This module computes a line-based diff between two texts using the longest common
+//! subsequence of their lines, and a word-level similarity score built on the same
+//! algorithm. It is intended for small inputs such as generated answers, where clarity of
+//! output matters more than speed.
+//!
+//! Diffs can also be rendered in the unified format understood by `git apply` and `patch`,
+//! which is how proposed file changes are shown for review.
+
+/// A single line in a diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine<'a> {
+    /// The line is present in both texts.
+    Same(&'a str),
+    /// The line only exists in the old text.
+    Removed(&'a str),
+    /// The line only exists in the new text.
+    Added(&'a str),
+}
+
+/// Builds the table where `lcs[i][j]` holds the LCS length of `old[i..]` and `new[j..]`.
+fn lcs_table<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Vec<usize>> {
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    lcs
+}
+
+/// Returns how similar two texts are, from 0.0 (nothing shared) to 1.0 (identical words).
+///
+/// The score is the number of words in the longest common subsequence, relative to the
+/// average word count of both texts.
+pub fn similarity(old: &str, new: &str) -> f64 {
+    let old: Vec<&str> = old.split_whitespace().collect();
+    let new: Vec<&str> = new.split_whitespace().collect();
+    if old.is_empty() && new.is_empty() {
+        return 1.0;
+    }
+
+    let common = lcs_table(&old, &new)[0][0];
+    (2 * common) as f64 / (old.len() + new.len()) as f64
+}
+
+/// Computes the line diff turning `old` into `new`.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let lcs = lcs_table(&old, &new);
+
+    let mut result = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            result.push(DiffLine::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            result.push(DiffLine::Removed(old[i]));
+            i += 1;
+        } else {
+            result.push(DiffLine::Added(new[j]));
+            j += 1;
+        }
+    }
+    result.extend(old[i..].iter().map(|line| DiffLine::Removed(line)));
+    result.extend(new[j..].iter().map(|line| DiffLine::Added(line)));
+    result
+}
+
+/// Renders the diff between `old` and `new` with `-`, `+`, and space prefixes.
+pub fn render_diff(old: &str, new: &str) -> String {
+    let mut output = String::new();
+    for line in diff_lines(old, new) {
+        let (marker, text) = match line {
+            DiffLine::Same(text) => (' ', text),
+            DiffLine::Removed(text) => ('-', text),
+            DiffLine::Added(text) => ('+', text),
+        };
+        output.push(marker);
+        output.push(' ');
+        output.push_str(text);
+        output.push('\n');
+    }
+    output
+}
+
+/// Renders the diff turning `old` into `new` in unified format, with `context` lines
+/// around each change.
+///
+/// `path` is used for both file headers, prefixed with `a/` and `b/` as git does. Returns an
+/// empty string if the texts have the same lines.
+pub fn unified_diff(path: &str, old: &str, new: &str, context: usize) -> String {
+    let lines = diff_lines(old, new);
+    // Indices of changed lines, grouped into hunks whose context overlaps
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        if matches!(line, DiffLine::Same(_)) {
+            continue;
+        }
+        match hunks.last_mut() {
+            Some((_, end)) if index <= *end + 2 * context + 1 => *end = index,
+            _ => hunks.push((index, index)),
+        }
+    }
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    // Line numbers (1-based) in both texts at the start of each diff line
+    let mut positions = Vec::with_capacity(lines.len() + 1);
+    let (mut old_line, mut new_line) = (1, 1);
+    for line in &lines {
+        positions.push((old_line, new_line));
+        match line {
+            DiffLine::Same(_) => {
+                old_line += 1;
+                new_line += 1;
+            }
+            DiffLine::Removed(_) => old_line += 1,
+            DiffLine::Added(_) => new_line += 1,
+        }
+    }
+    positions.push((old_line, new_line));
+
+    let mut output = format!("--- a/{path}\n+++ b/{path}\n");
+    for (first, last) in hunks {
+        let start = first.saturating_sub(context);
+        let end = (last + context + 1).min(lines.len());
+        let hunk = &lines[start..end];
+        let old_count = hunk
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Added(_)))
+            .count();
+        let new_count = hunk
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Removed(_)))
+            .count();
+        // Empty ranges are numbered by the line before them
+        let (old_start, new_start) = positions[start];
+        let old_start = if old_count == 0 {
+            old_start - 1
+        } else {
+            old_start
+        };
+        let new_start = if new_count == 0 {
+            new_start - 1
+        } else {
+            new_start
+        };
+        output.push_str(&format!(
+            "@@ -{old_start},{old_count} +{new_start},{new_count} @@\n"
+        ));
+        for line in hunk {
+            let (marker, text) = match line {
+                DiffLine::Same(text) => (' ', text),
+                DiffLine::Removed(text) => ('-', text),
+                DiffLine::Added(text) => ('+', text),
+            };
+            output.push(marker);
+            output.push_str(text);
+            output.push('\n');
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_texts() {
+        let diff = diff_lines("a\nb", "a\nb");
+        assert_eq!(diff, vec![DiffLine::Same("a"), DiffLine::Same("b")]);
+    }
+
+    #[test]
+    fn test_changed_line() {
+        let expected = "  The entry point is\n- src/lib.rs\n+ src/main.rs\n  Done.\n";
+        let result = render_diff(
+            "The entry point is\nsrc/lib.rs\nDone.",
+            "The entry point is\nsrc/main.rs\nDone.",
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_similarity() {
+        assert!((similarity("a b c", "a b c") - 1.0).abs() < f64::EPSILON);
+        assert!(similarity("a b c", "x y z").abs() < f64::EPSILON);
+        let score = similarity("the loop lives in agent", "the loop lives in agent.rs");
+        assert!(score > 0.7 && score < 1.0);
+        assert!((similarity("", "") - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\n";
+        assert_eq!(
+            unified_diff("src/x.rs", old, new, 1),
+            "--- a/src/x.rs\n+++ b/src/x.rs\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n@@ -8,1 +8,2 @@\n h\n+i\n"
+        );
+        assert_eq!(
+            unified_diff("new.rs", "", "x\n", 3),
+            "--- a/new.rs\n+++ b/new.rs\n@@ -0,0 +1,1 @@\n+x\n"
+        );
+        assert_eq!(unified_diff("same.rs", old, old, 3), "");
+    }
+
+    #[test]
+    fn test_added_and_removed_tails() {
+        assert_eq!(diff_lines("", "new"), vec![DiffLine::Added("new")]);
+        assert_eq!(diff_lines("old", ""), vec![DiffLine::Removed("old")]);
+    }
+}
@@ -0,0 +1,379 @@
+//! # Documentation Generation
+//!
+//! This module implements `nishiogi doc`. The symbol index finds the public items of a file
+//! or module that have no doc comment, the model writes documentation for them, and the
+//! comments are inserted in the language's own style:
+//!
+//! - Rust: `///` comments above the item and its attributes
+//! - Python: a docstring as the first statement of the body
+//! - JavaScript and TypeScript: a `/** ... */` block
+//! - Go: `//` comments above the declaration
+//!
+//! The result is a [`Patch`] so the changes can be reviewed before they are written.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    agent::AgentError,
+    github_copilot_client::Message,
+    patch::{FileEdit, Patch},
+    review::ReviewModel,
+    search::collect_files,
+    symbols::{Language, Symbol, SymbolIndex, SymbolKind},
+    tree::find_gitignore_patterns,
+};
+
+/// A doc comment proposed by the model.
+#[derive(Debug, Deserialize)]
+struct ProposedDoc {
+    /// Line of the documented item (1-based).
+    line: usize,
+    /// The documentation text, without comment markers.
+    doc: String,
+}
+
+/// Resolves the argument of `nishiogi doc` to source files under `root`.
+///
+/// `target` is a file, a directory, or a module path such as `agent` or `db::schema`, which
+/// is looked up as `src/<path>.rs`, `src/<path>/mod.rs`, `<path>.py`, or
+/// `<path>/__init__.py`. Returns `None` if nothing matches.
+pub fn resolve_target(root: &Path, target: &str) -> Option<Vec<PathBuf>> {
+    let path = root.join(target);
+    if path.is_file() {
+        return Some(vec![path]);
+    }
+    if path.is_dir() {
+        let ignore = find_gitignore_patterns(root).unwrap_or_default();
+        let mut files = Vec::new();
+        collect_files(root, &path, &ignore, &mut files);
+        files.retain(|file| Language::of(file).is_some());
+        files.sort();
+        return Some(files);
+    }
+
+    let module = target.replace("::", "/").replace('.', "/");
+    [
+        format!("src/{module}.rs"),
+        format!("src/{module}/mod.rs"),
+        format!("{module}.py"),
+        format!("{module}/__init__.py"),
+    ]
+    .into_iter()
+    .map(|candidate| root.join(candidate))
+    .find(|candidate| candidate.is_file())
+    .map(|file| vec![file])
+}
+
+/// Returns the public items in `index` that have no documentation.
+///
+/// Rust modules are skipped, since their documentation usually lives in the module's own
+/// file as `//!` comments.
+pub fn undocumented<'a>(index: &'a SymbolIndex, root: &Path) -> Vec<&'a Symbol> {
+    let mut undocumented = Vec::new();
+    let mut current: Option<(&Path, Vec<String>)> = None;
+    for symbol in index.symbols().iter().filter(|symbol| symbol.public) {
+        let Some(language) = Language::of(&symbol.path) else {
+            continue;
+        };
+        if language == Language::Rust && symbol.kind == SymbolKind::Module {
+            continue;
+        }
+        if current
+            .as_ref()
+            .is_none_or(|(path, _)| *path != symbol.path)
+        {
+            let content = fs::read_to_string(root.join(&symbol.path)).unwrap_or_default();
+            let lines = content.lines().map(str::to_string).collect();
+            current = Some((&symbol.path, lines));
+        }
+        let Some((_, lines)) = &current else {
+            continue;
+        };
+        if !is_documented(lines, symbol.line - 1, language) {
+            undocumented.push(symbol);
+        }
+    }
+    undocumented
+}
+
+/// Asks the model to document `items` and returns the changes as a patch.
+///
+/// Items of the same file are documented in one request. Documentation the model did not
+/// provide, or provided for a line that is not an item, is left out.
+///
+/// # Errors
+///
+/// Returns an `AgentError` if a request to the model fails.
+pub async fn document<M: ReviewModel>(
+    model: &mut M,
+    root: &Path,
+    items: &[&Symbol],
+) -> Result<Patch, AgentError> {
+    let mut patch = Patch::default();
+    let mut files: Vec<&Path> = items.iter().map(|item| item.path.as_path()).collect();
+    files.dedup();
+    for path in files {
+        let Some(language) = Language::of(path) else {
+            continue;
+        };
+        let file_items: Vec<&Symbol> = items
+            .iter()
+            .copied()
+            .filter(|item| item.path == path)
+            .collect();
+        let original = fs::read_to_string(root.join(path))?;
+        eprintln!(
+            "Documenting {} item(s) in {}",
+            file_items.len(),
+            path.display()
+        );
+        let reply = model
+            .complete(doc_messages(path, language, &original, &file_items))
+            .await?;
+        let docs: Vec<(usize, String)> = parse_docs(&reply)
+            .into_iter()
+            .filter(|doc| file_items.iter().any(|item| item.line == doc.line))
+            .filter(|doc| !doc.doc.trim().is_empty())
+            .map(|doc| (doc.line, doc.doc))
+            .collect();
+        patch.push(FileEdit {
+            path: path.to_path_buf(),
+            updated: insert_docs(&original, language, &docs),
+            original,
+        });
+    }
+    Ok(patch)
+}
+
+/// Returns whether the item on line `index` (0-based) of `lines` has documentation.
+fn is_documented(lines: &[String], index: usize, language: Language) -> bool {
+    if language == Language::Python {
+        let body = lines
+            .iter()
+            .skip(header_end(lines, index) + 1)
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty());
+        return body.is_some_and(|line| {
+            let line = line.trim_start_matches(['r', 'R', 'u', 'U']);
+            line.starts_with("\"\"\"") || line.starts_with("'''")
+        });
+    }
+
+    let mut above = lines[..index].iter().rev().map(|line| line.trim());
+    if language == Language::Rust {
+        // Doc comments may come before or after the attributes
+        let mut above =
+            above.skip_while(|line| line.starts_with("#[") && !line.starts_with("#[doc"));
+        return above.next().is_some_and(|line| {
+            line.starts_with("///") || line.starts_with("#[doc") || line.ends_with("*/")
+        });
+    }
+    above.next().is_some_and(|line| match language {
+        Language::Go => line.starts_with("//"),
+        _ => line.ends_with("*/") || line.starts_with("//"),
+    })
+}
+
+/// Returns the index of the line ending the Python definition header starting at `index`.
+fn header_end(lines: &[String], index: usize) -> usize {
+    (index..lines.len())
+        .find(|&i| {
+            let code = lines[i].split('#').next().unwrap_or_default();
+            code.trim_end().ends_with(':')
+        })
+        .unwrap_or(index)
+}
+
+/// Inserts each `(line, doc)` pair as a comment for the item on that line (1-based).
+fn insert_docs(content: &str, language: Language, docs: &[(usize, String)]) -> String {
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let mut docs: Vec<&(usize, String)> = docs.iter().collect();
+    // Insert from the bottom so earlier line numbers stay valid
+    docs.sort_by_key(|(line, _)| std::cmp::Reverse(*line));
+    docs.dedup_by_key(|(line, _)| *line);
+    for (line, doc) in docs {
+        let index = line - 1;
+        if index >= lines.len() {
+            continue;
+        }
+        let indent: String = lines[index]
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .collect();
+        let doc_lines: Vec<&str> = doc.trim().lines().map(str::trim_end).collect();
+        let (at, comment) = match language {
+            Language::Rust => {
+                let mut at = index;
+                while at > 0 && lines[at - 1].trim().starts_with("#[") {
+                    at -= 1;
+                }
+                (at, line_comment(&indent, "///", &doc_lines))
+            }
+            Language::Go => (index, line_comment(&indent, "//", &doc_lines)),
+            Language::JavaScript => {
+                let mut comment = vec![format!("{indent}/**")];
+                comment.extend(
+                    doc_lines
+                        .iter()
+                        .map(|line| format!("{indent} * {line}").trim_end().to_string()),
+                );
+                comment.push(format!("{indent} */"));
+                (index, comment)
+            }
+            Language::Python => {
+                let at = header_end(&lines, index) + 1;
+                (at, docstring(&format!("{indent}    "), &doc_lines))
+            }
+        };
+        lines.splice(at..at, comment);
+    }
+
+    let mut updated = lines.join("\n");
+    if content.ends_with('\n') {
+        updated.push('\n');
+    }
+    updated
+}
+
+/// Formats `doc_lines` as line comments starting with `marker`.
+fn line_comment(indent: &str, marker: &str, doc_lines: &[&str]) -> Vec<String> {
+    doc_lines
+        .iter()
+        .map(|line| format!("{indent}{marker} {line}").trim_end().to_string())
+        .collect()
+}
+
+/// Formats `doc_lines` as a Python docstring.
+fn docstring(indent: &str, doc_lines: &[&str]) -> Vec<String> {
+    match doc_lines {
+        [line] => vec![format!("{indent}\"\"\"{line}\"\"\"")],
+        [first, rest @ ..] => {
+            let mut docstring = vec![format!("{indent}\"\"\"{first}")];
+            docstring.extend(
+                rest.iter()
+                    .map(|line| format!("{indent}{line}").trim_end().to_string()),
+            );
+            docstring.push(format!("{indent}\"\"\""));
+            docstring
+        }
+        [] => Vec::new(),
+    }
+}
+
+/// Builds the request asking the model to document the items of one file.
+fn doc_messages(path: &Path, language: Language, content: &str, items: &[&Symbol]) -> Vec<Message> {
+    let style = match language {
+        Language::Rust => "rustdoc: a one-line summary, then details and `# Errors`/`# Panics` sections where relevant, using Markdown",
+        Language::Python => "a docstring: a one-line summary, then Args/Returns/Raises sections where relevant",
+        Language::JavaScript => "JSDoc: a one-line summary, then @param and @returns tags where relevant",
+        Language::Go => "Go doc comments: complete sentences starting with the item's name",
+    };
+    let numbered: String = content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| format!("{:>5} | {line}\n", index + 1))
+        .collect();
+    let listed: String = items
+        .iter()
+        .map(|item| format!("- line {}: {} {}\n", item.line, item.kind, item.name))
+        .collect();
+    vec![
+        Message {
+            role: "system".to_string(),
+            content: "You write concise, accurate API documentation. Describe what an item does and how to use it, based only on the code shown; do not restate the signature.".to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!(
+                "File {}:\n\n{numbered}\nWrite documentation in the style of {style} for these undocumented items:\n\n{listed}\nRespond with JSON only, in this format, giving the documentation text without comment markers or indentation:\n\n[{{\"line\": 12, \"doc\": \"Summary.\\n\\nDetails.\"}}]",
+                path.display()
+            ),
+        },
+    ]
+}
+
+/// Extracts the proposed docs from the model's reply, tolerating text around the JSON.
+fn parse_docs(reply: &str) -> Vec<ProposedDoc> {
+    let (Some(start), Some(end)) = (reply.find('['), reply.rfind(']')) else {
+        return Vec::new();
+    };
+    reply
+        .get(start..=end)
+        .and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Documents every item on lines 4 and 9, whatever the file.
+    struct ScriptedModel;
+
+    #[async_trait]
+    impl ReviewModel for ScriptedModel {
+        async fn complete(&mut self, _messages: Vec<Message>) -> Result<String, AgentError> {
+            Ok(r#"Here you go: [{"line": 4, "doc": "Adds one.\n\nSaturates at the maximum."}, {"line": 9, "doc": "Greets."}]"#.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_documents_undocumented_public_items() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        fs::create_dir(root.join("src")).expect("Failed to create directory");
+        let rust = "/// Already documented.\npub fn documented() {}\n\n#[inline]\npub fn add_one(x: u8) -> u8 {\n    x.saturating_add(1)\n}\nfn private() {}\n";
+        fs::write(root.join("src/math.rs"), rust).expect("Failed to write file");
+        let python = "def greet_all(names):\n    \"\"\"Greets everyone.\"\"\"\n    pass\n\n\nclass Greeter:\n    pass\n\n    def greet(self, name):\n        print(name)\n";
+        fs::write(root.join("greet.py"), python).expect("Failed to write file");
+
+        let files = resolve_target(root, "math").expect("Expected the module to resolve");
+        let index = SymbolIndex::from_files(root, &files);
+        let items = undocumented(&index, root);
+        let names: Vec<&str> = items.iter().map(|item| item.name.as_str()).collect();
+        assert_eq!(names, ["add_one"]);
+
+        let files = resolve_target(root, "greet.py").expect("Expected the file to resolve");
+        let index = SymbolIndex::from_files(root, &files);
+        let items = undocumented(&index, root);
+        let names: Vec<&str> = items.iter().map(|item| item.name.as_str()).collect();
+        assert_eq!(names, ["Greeter", "greet"]);
+
+        let patch = document(&mut ScriptedModel, root, &items)
+            .await
+            .expect("Failed to document");
+        assert_eq!(
+            patch.edits[0].updated,
+            "def greet_all(names):\n    \"\"\"Greets everyone.\"\"\"\n    pass\n\n\nclass Greeter:\n    pass\n\n    def greet(self, name):\n        \"\"\"Greets.\"\"\"\n        print(name)\n"
+        );
+    }
+
+    #[test]
+    fn test_insert_docs_above_attributes() {
+        let content = "#[derive(Debug)]\npub struct Point;\n\n    pub fn origin() {}\n";
+        let docs = [
+            (2, "A point.\n\nIn two dimensions.".to_string()),
+            (4, "Returns the origin.".to_string()),
+        ];
+        assert_eq!(
+            insert_docs(content, Language::Rust, &docs),
+            "/// A point.\n///\n/// In two dimensions.\n#[derive(Debug)]\npub struct Point;\n\n    /// Returns the origin.\n    pub fn origin() {}\n"
+        );
+        assert_eq!(
+            insert_docs(
+                "export function f() {}",
+                Language::JavaScript,
+                &[(1, "Does f.".to_string())]
+            ),
+            "/**\n * Does f.\n */\nexport function f() {}"
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! # Environment Doctor
+//!
+//! This module diagnoses the environment nishiogi runs in. Each check reports whether a
+//! prerequisite is met and, when it is not, what the user can do about it. The checks
+//! cover the configuration files, git, the GitHub credentials, reachability of the Copilot
+//! API, availability of the default model, and the local database and its indexes.
+
+use std::{fmt, process::Command, time::Duration};
+
+use reqwest::Client as HttpClient;
+
+use crate::{
+    agent::DEFAULT_MODEL,
+    config::{repo_root, Config},
+    db::{Database, INDEX_META_PREFIX},
+    github_copilot_client::{get_github_token, CopilotClient},
+    tree::find_repo_root,
+};
+
+/// How long the reachability check waits for the Copilot API.
+const REACHABILITY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The outcome of a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The prerequisite is met.
+    Ok,
+    /// nishiogi works, but not as well as it could.
+    Warn,
+    /// nishiogi cannot work until this is fixed.
+    Fail,
+}
+
+/// The result of a single check.
+#[derive(Debug, Clone)]
+pub struct Check {
+    /// What was checked.
+    pub name: &'static str,
+    /// The outcome.
+    pub status: Status,
+    /// What was found.
+    pub detail: String,
+    /// How to fix a failed or degraded check.
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Warn,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        writeln!(f, "[{label:>4}] {}: {}", self.name, self.detail)?;
+        if let Some(fix) = &self.fix {
+            writeln!(f, "       fix: {fix}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs every check in order.
+pub async fn run_checks() -> Vec<Check> {
+    let mut checks = vec![check_config(), check_git(), check_database()];
+    checks.push(check_reachability().await);
+    checks.extend(check_credentials_and_model().await);
+    checks
+}
+
+fn check_config() -> Check {
+    const NAME: &str = "configuration";
+    match Config::load() {
+        Ok(_) => Check::ok(NAME, "configuration files are valid"),
+        Err(err) => Check::fail(
+            NAME,
+            err.to_string(),
+            "Correct the reported file; every setting is optional, so removing a broken key restores its default",
+        ),
+    }
+}
+
+fn check_git() -> Check {
+    const NAME: &str = "git";
+    let version = Command::new("git")
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    let Some(version) = version else {
+        return Check::warn(
+            NAME,
+            "git is not installed or not on PATH",
+            "Install git to enable repository-aware features",
+        );
+    };
+
+    let cwd = std::env::current_dir().unwrap_or_default();
+    match find_repo_root(&cwd) {
+        Some(root) => Check::ok(NAME, format!("{version}, repository at {}", root.display())),
+        None => Check::warn(
+            NAME,
+            format!("{version}, but the current directory is not inside a repository"),
+            "Run nishiogi from inside the repository you want to ask about",
+        ),
+    }
+}
+
+fn check_database() -> Check {
+    const NAME: &str = "local database";
+    let db = match Database::open_for_repo(&repo_root()) {
+        Ok(db) => db,
+        Err(err) => {
+            return Check::fail(
+                NAME,
+                err.to_string(),
+                "Run `nishiogi clean --all` or delete .nishiogi/nishiogi.db to start over",
+            );
+        }
+    };
+
+    let version = db.schema_version().unwrap_or_default();
+    match db.meta(&format!("{INDEX_META_PREFIX}updated_at")) {
+        Ok(Some(updated)) => Check::ok(
+            NAME,
+            format!("schema version {version}, indexes last updated {updated}"),
+        ),
+        Ok(None) => Check::ok(
+            NAME,
+            format!("schema version {version}, no indexes built yet"),
+        ),
+        Err(err) => Check::fail(
+            NAME,
+            err.to_string(),
+            "Run `nishiogi clean --index` to reset the index metadata",
+        ),
+    }
+}
+
+async fn check_reachability() -> Check {
+    const NAME: &str = "provider";
+    let client = match HttpClient::builder().timeout(REACHABILITY_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(err) => return Check::fail(NAME, err.to_string(), "Check your TLS setup"),
+    };
+    // Any HTTP response, even an authentication error, proves the API is reachable
+    match client
+        .get("https://api.githubcopilot.com/models")
+        .send()
+        .await
+    {
+        Ok(_) => Check::ok(NAME, "api.githubcopilot.com is reachable"),
+        Err(err) => Check::fail(
+            NAME,
+            format!("api.githubcopilot.com is unreachable: {err}"),
+            "Check your network connection and the HTTPS_PROXY setting",
+        ),
+    }
+}
+
+async fn check_credentials_and_model() -> Vec<Check> {
+    const CREDENTIALS: &str = "credentials";
+    const MODEL: &str = "model";
+    let token = match get_github_token() {
+        Ok(token) => token,
+        Err(err) => {
+            return vec![Check::fail(
+                CREDENTIALS,
+                err.to_string(),
+                "Run `nishiogi auth login`, sign in to GitHub Copilot in your editor, or set GITHUB_TOKEN in a Codespace",
+            )];
+        }
+    };
+
+    let client = match CopilotClient::new_with_models(token, "1.0.0".to_string()).await {
+        Ok(client) => client,
+        Err(err) => {
+            return vec![Check::fail(
+                CREDENTIALS,
+                format!("the GitHub token was rejected or models could not be listed: {err}"),
+                "Run `nishiogi auth login` to sign in again and make sure your account has an active Copilot subscription",
+            )];
+        }
+    };
+
+    let mut checks = vec![Check::ok(CREDENTIALS, "GitHub token is valid")];
+    if client.has_model(DEFAULT_MODEL) {
+        checks.push(Check::ok(MODEL, format!("{DEFAULT_MODEL} is available")));
+    } else {
+        checks.push(Check::fail(
+            MODEL,
+            format!("{DEFAULT_MODEL} is not available to this account"),
+            "Enable the model in your Copilot settings, or run `nishiogi models` and pick another one",
+        ));
+    }
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_includes_fix() {
+        let check = Check::fail("git", "missing", "install it");
+        assert_eq!(
+            check.to_string(),
+            "[FAIL] git: missing\n       fix: install it\n"
+        );
+        let check = Check::ok("model", "gpt-4 is available");
+        assert_eq!(check.to_string(), "[  ok] model: gpt-4 is available\n");
+    }
+}
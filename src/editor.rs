@@ -0,0 +1,157 @@
+//! # Opening Cited Files
+//!
+//! This module implements `ask --open`, which takes the reader from an answer to the code
+//! it cites. The locations come from the `path:line` references and chunk links of the
+//! answer, and from consulted files it mentions by name. They are opened in `$VISUAL` or
+//! `$EDITOR` with the editor's syntax for jumping to a line, or printed as
+//! `code -g path:line` commands when no editor is configured.
+
+use std::path::{Path, PathBuf};
+
+use crate::{blame::find_line_references, provenance::FileProvenance};
+
+/// A place in a file cited by an answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// The file, as it can be opened from the current directory.
+    pub path: PathBuf,
+    /// The first cited line (1-based).
+    pub line: usize,
+}
+
+impl Location {
+    /// Returns the `code -g` command opening the location in Visual Studio Code.
+    pub fn vscode_command(&self) -> String {
+        format!("code -g {}:{}", self.path.display(), self.line)
+    }
+}
+
+/// Returns the existing files cited by `answer`, each once at its first cited line.
+///
+/// References are matched against the `consulted` files first, since those carry the path
+/// the file was actually read from, e.g. inside a workspace member.
+pub fn cited_locations(answer: &str, consulted: &[FileProvenance]) -> Vec<Location> {
+    let find_consulted = |reference: &Path| {
+        consulted
+            .iter()
+            .find(|file| file.path == reference || file.path.ends_with(reference))
+            .map(|file| file.path.clone())
+    };
+
+    let mut locations: Vec<Location> = Vec::new();
+    let mut add = |path: PathBuf, line: usize| {
+        if path.is_file() && !locations.iter().any(|location| location.path == path) {
+            locations.push(Location { path, line });
+        }
+    };
+    for reference in find_line_references(answer) {
+        let path = find_consulted(&reference.path).unwrap_or(reference.path);
+        add(path, reference.start);
+    }
+    // Files named without a line are opened where they were read from
+    for file in consulted {
+        let path = file.path.to_string_lossy();
+        let path = path.trim_start_matches("./");
+        if answer.contains(path) {
+            add(file.path.clone(), file.range.map_or(1, |range| range.start));
+        }
+    }
+    locations
+}
+
+/// Returns the program and arguments opening `location` with the editor command `editor`,
+/// such as `nvim` or `code --wait`.
+pub fn open_command(editor: &str, location: &Location) -> Vec<String> {
+    let mut command: Vec<String> = editor.split_whitespace().map(str::to_string).collect();
+    let program = command
+        .first()
+        .and_then(|program| Path::new(program).file_stem())
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let path = location.path.display().to_string();
+    match program.as_str() {
+        "code" | "code-insiders" | "codium" | "cursor" => {
+            command.extend(["-g".to_string(), format!("{path}:{}", location.line)]);
+        }
+        "subl" | "zed" | "hx" | "helix" | "micro" => {
+            command.push(format!("{path}:{}", location.line));
+        }
+        "vi" | "vim" | "nvim" | "gvim" | "emacs" | "emacsclient" | "nano" | "kak" | "joe" => {
+            command.extend([format!("+{}", location.line), path]);
+        }
+        // Editors with unknown syntax at least open the file
+        _ => command.push(path),
+    }
+    command
+}
+
+/// Returns the editor configured in `$VISUAL` or `$EDITOR`, if any.
+pub fn configured_editor() -> Option<String> {
+    ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|editor| !editor.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_cited_locations() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let agent = dir.path().join("src/agent.rs");
+        let main = dir.path().join("src/main.rs");
+        fs::create_dir_all(dir.path().join("src")).expect("Failed to create src");
+        fs::write(&agent, "").expect("Failed to write agent");
+        fs::write(&main, "").expect("Failed to write main");
+        let consulted: Vec<FileProvenance> = [&agent, &main]
+            .iter()
+            .map(|path| FileProvenance {
+                path: path.to_path_buf(),
+                range: None,
+                sha256: String::new(),
+            })
+            .collect();
+
+        let answer = format!(
+            "The loop is in [src/agent.rs:40-52](src/agent.rs#L40-L52), see also src/agent.rs:90, {} and missing/file.rs:3.",
+            main.display()
+        );
+        assert_eq!(
+            cited_locations(&answer, &consulted),
+            [
+                Location {
+                    path: agent,
+                    line: 40
+                },
+                Location {
+                    path: main,
+                    line: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_open_command() {
+        let location = Location {
+            path: PathBuf::from("src/main.rs"),
+            line: 12,
+        };
+        assert_eq!(
+            open_command("/usr/bin/nvim", &location),
+            ["/usr/bin/nvim", "+12", "src/main.rs"]
+        );
+        assert_eq!(
+            open_command("code --wait", &location),
+            ["code", "--wait", "-g", "src/main.rs:12"]
+        );
+        assert_eq!(open_command("ed", &location), ["ed", "src/main.rs"]);
+        assert_eq!(location.vscode_command(), "code -g src/main.rs:12");
+    }
+}
@@ -0,0 +1,262 @@
+//! # Middle Elision
+//!
+//! This module shortens files that exceed their token budget before they are added to a
+//! prompt. Rather than cutting the file at the budget, it keeps the parts most likely to
+//! matter:
+//!
+//! - the head, where imports, module docs and the main types usually are
+//! - the tail, where entry points and tests usually are
+//! - the regions around lines mentioning keywords of the question
+//!
+//! Everything else is replaced by markers naming the elided lines, so the model knows what
+//! it has not seen and can ask for it.
+//!
+//! When all the command results of a question still do not fit in the model's context
+//! window, [`fit_results`] shrinks the oldest ones first, so the files the planner asked for
+//! last, usually after seeing what earlier commands found, are the last to go.
+
+use crate::{
+    show_file::reads_range,
+    tokens::{count_tokens, estimate_tokens},
+};
+
+/// Share of the budget spent on the head of the file.
+const HEAD_SHARE: f64 = 0.25;
+
+/// Share of the budget spent on the tail of the file.
+const TAIL_SHARE: f64 = 0.15;
+
+/// Lines kept before and after each line matching a keyword.
+const CONTEXT_LINES: usize = 3;
+
+/// Tokens of the heading and fences around each command result in the answer prompt.
+const SECTION_TOKENS: usize = 12;
+
+/// Fewest tokens worth shortening a result to; below, it is replaced by a summary line.
+const MIN_RESULT_TOKENS: usize = 200;
+
+/// Shortens `content` to about `budget` tokens, keeping its head, its tail and the regions
+/// matching `keywords` (lowercase).
+///
+/// Returns `None` when `content` already fits.
+pub fn elide(content: &str, budget: usize, keywords: &[String]) -> Option<String> {
+    if estimate_tokens(content) <= budget {
+        return None;
+    }
+    let lines: Vec<&str> = content.lines().collect();
+    let costs: Vec<usize> = lines.iter().map(|line| estimate_tokens(line) + 1).collect();
+    let mut kept = vec![false; lines.len()];
+    let mut remaining = budget;
+    let keep = |index: usize, kept: &mut [bool], remaining: &mut usize| {
+        if kept[index] {
+            return true;
+        }
+        if costs[index] > *remaining {
+            return false;
+        }
+        *remaining -= costs[index];
+        kept[index] = true;
+        true
+    };
+
+    let head_budget = (budget as f64 * HEAD_SHARE) as usize;
+    let mut head = 0;
+    for (index, cost) in costs.iter().enumerate() {
+        head += cost;
+        if head > head_budget || !keep(index, &mut kept, &mut remaining) {
+            break;
+        }
+    }
+    let tail_budget = (budget as f64 * TAIL_SHARE) as usize;
+    let mut tail = 0;
+    for index in (0..lines.len()).rev() {
+        tail += costs[index];
+        if tail > tail_budget || !keep(index, &mut kept, &mut remaining) {
+            break;
+        }
+    }
+
+    // Regions around the lines mentioning the most keywords come first
+    let mut matches: Vec<(usize, usize)> = lines
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !kept[*index])
+        .map(|(index, line)| {
+            let line = line.to_lowercase();
+            let hits = keywords
+                .iter()
+                .filter(|word| line.contains(word.as_str()))
+                .count();
+            (index, hits)
+        })
+        .filter(|(_, hits)| *hits > 0)
+        .collect();
+    matches.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    for (index, _) in matches {
+        let start = index.saturating_sub(CONTEXT_LINES);
+        let end = (index + CONTEXT_LINES).min(lines.len() - 1);
+        let cost: usize = (start..=end)
+            .filter(|&line| !kept[line])
+            .map(|line| costs[line])
+            .sum();
+        if cost <= remaining {
+            for line in start..=end {
+                keep(line, &mut kept, &mut remaining);
+            }
+        }
+    }
+
+    let mut output = String::new();
+    let mut index = 0;
+    while index < lines.len() {
+        if kept[index] {
+            output.push_str(lines[index]);
+            output.push('\n');
+            index += 1;
+            continue;
+        }
+        let start = index;
+        while index < lines.len() && !kept[index] {
+            index += 1;
+        }
+        output.push_str(&format!(
+            "[... lines {}-{} of {} elided ...]\n",
+            start + 1,
+            index,
+            lines.len()
+        ));
+    }
+    Some(output)
+}
+
+/// Shrinks command `results` to about `budget` tokens in total, visiting them from the
+/// most recent.
+///
+/// Results are kept whole while they fit. Past that, a file is elided to the budget left,
+/// another result is cut after its first lines, and a result with less than
+/// `MIN_RESULT_TOKENS` left is replaced by a line saying how much was omitted.
+///
+/// Returns the commands whose results were shortened, from the most recent.
+pub(crate) fn fit_results(
+    results: &mut [(String, String)],
+    budget: usize,
+    keywords: &[String],
+) -> Vec<String> {
+    let mut remaining = budget;
+    let mut shortened = Vec::new();
+    for (command, result) in results.iter_mut().rev() {
+        let available = remaining.saturating_sub(count_tokens(command) + SECTION_TOKENS);
+        let tokens = count_tokens(result);
+        if tokens <= available {
+            remaining = available - tokens;
+            continue;
+        }
+        let fitted = if available < MIN_RESULT_TOKENS {
+            format!(
+                "[{} lines (about {tokens} tokens) omitted to fit the model's context window]",
+                result.lines().count()
+            )
+        } else if command.starts_with("show_file ") && !reads_range(command) {
+            // Elision budgets with the rougher estimate, so leave it some slack
+            let estimated = available * estimate_tokens(result) / tokens.max(1);
+            elide(result, estimated * 9 / 10, keywords).unwrap_or_else(|| head(result, available))
+        } else {
+            head(result, available)
+        };
+        remaining = available.saturating_sub(count_tokens(&fitted));
+        *result = fitted;
+        shortened.push(command.clone());
+    }
+    shortened
+}
+
+/// Returns the first lines of `text` fitting in `budget` tokens, followed by a marker
+/// naming the omitted lines.
+fn head(text: &str, budget: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut output = String::new();
+    let mut used = 0;
+    let mut kept = 0;
+    for line in &lines {
+        used += count_tokens(line) + 1;
+        if used > budget.saturating_sub(SECTION_TOKENS) {
+            break;
+        }
+        output.push_str(line);
+        output.push('\n');
+        kept += 1;
+    }
+    output.push_str(&format!(
+        "[... lines {}-{} of {} omitted to fit the model's context window ...]\n",
+        kept + 1,
+        lines.len(),
+        lines.len()
+    ));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps_head_tail_and_matches() {
+        let content: String = (1..=400)
+            .map(|n| {
+                if n == 200 {
+                    "fn save_session() {}\n".to_string()
+                } else {
+                    format!("let filler_{n:03} = compute_something_long({n});\n")
+                }
+            })
+            .collect();
+        assert!(elide(&content, 100_000, &[]).is_none());
+
+        let elided =
+            elide(&content, 1000, &["session".to_string()]).expect("Content was not elided");
+        assert!(estimate_tokens(&elided) <= 1100);
+        assert!(elided.starts_with("let filler_001"));
+        assert!(elided.contains("let filler_400"));
+        assert!(elided.contains("let filler_197"));
+        assert!(elided.contains("fn save_session() {}\n"));
+        assert!(elided.contains("let filler_203"));
+        assert!(!elided.contains("let filler_204"));
+        assert!(elided.contains("elided ...]\nlet filler_197"));
+        assert!(elided.contains(" of 400 elided ...]"));
+    }
+
+    #[test]
+    fn test_fit_results_keeps_recent_results() {
+        let file: String = (1..=400)
+            .map(|n| format!("let filler_{n:03} = compute_something_long({n});\n"))
+            .collect();
+        let listing: String = (1..=400)
+            .map(|n| format!("src/module_{n:03}.rs\n"))
+            .collect();
+        let mut results = vec![
+            ("tree .".to_string(), listing.clone()),
+            ("show_file src/old.rs".to_string(), file.clone()),
+            ("show_file src/new.rs".to_string(), file.clone()),
+        ];
+        let budget = count_tokens(&file) + 1500;
+        let shortened = fit_results(&mut results, budget, &[]);
+
+        assert_eq!(shortened, ["show_file src/old.rs", "tree ."]);
+        assert_eq!(results[2].1, file);
+        assert!(results[1].1.contains(" of 400 elided ...]"));
+        assert!(results[0].1.starts_with("src/module_001.rs\n"));
+        assert!(results[0]
+            .1
+            .ends_with(" of 400 omitted to fit the model's context window ...]\n"));
+        let total: usize = results
+            .iter()
+            .map(|(command, result)| count_tokens(command) + SECTION_TOKENS + count_tokens(result))
+            .sum();
+        assert!(total <= budget + 100);
+
+        let mut listed = vec![("tree .".to_string(), listing)];
+        assert!(fit_results(&mut listed, 100_000, &[]).is_empty());
+        assert_eq!(fit_results(&mut listed, 100, &[]), ["tree ."]);
+        assert!(listed[0].1.starts_with("[400 lines (about "));
+    }
+}
@@ -0,0 +1,174 @@
+//! # Errors
+//!
+//! This module defines [`AgentError`], the error type shared by the agent and every step
+//! that talks to the model. It lives outside the agent so the filesystem-free core (plans,
+//! reviewers and the Copilot client) can use it without the native tools.
+
+use std::{
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{github_copilot_client::CopilotError, plan::PlanError};
+
+/// Errors that can occur during agent operations
+#[derive(Debug)]
+pub enum AgentError {
+    // Intent errors
+    IntentExtractionFailed,
+    MalformedIntentResponse,
+    EmptyIntentResponse,
+
+    // Planning errors
+    PlanningFailed,
+    EmptyPlan,
+    InvalidPlanFormat {
+        /// Why the plan could not be read.
+        reason: PlanError,
+        /// The model's reply.
+        text: String,
+    },
+    InvalidPlan(PlanError),
+
+    // Command errors
+    UnknownCommand(String), // Keep string for command name
+    InvalidArguments {
+        /// The planned command.
+        command: String,
+        /// How the tool is invoked.
+        usage: String,
+    },
+    PathNotFound(PathBuf), // Use PathBuf instead of String
+    PathIsDirectory(PathBuf),
+    PathForbidden(PathBuf),
+    PathOutsideSandbox(PathBuf),
+    CommandExecutionFailed,
+    ToolDenied(String),
+    SandboxUnavailable,
+
+    // Answer errors
+    AnswerGenerationFailed,
+    EmptyAnswerResponse,
+
+    // Review errors
+    ReviewFailed,
+    NoAnswerToReview,
+    MaxIterationsReached,
+
+    // External errors
+    CopilotError(CopilotError),
+    IoError(std::io::Error),
+
+    // Fallback for truly custom errors
+    Other(String),
+}
+
+impl fmt::Display for AgentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // Intent errors
+            AgentError::IntentExtractionFailed => {
+                write!(f, "Failed to extract intent from question")
+            }
+            AgentError::MalformedIntentResponse => {
+                write!(f, "Intent extraction produced malformed response")
+            }
+            AgentError::EmptyIntentResponse => {
+                write!(f, "Intent extraction produced empty response")
+            }
+
+            // Planning errors
+            AgentError::PlanningFailed => write!(f, "Failed to create execution plan"),
+            AgentError::EmptyPlan => write!(f, "Generated plan contains no commands"),
+            AgentError::InvalidPlanFormat { reason, text } => {
+                write!(f, "Generated plan has invalid format ({reason}): {text}")
+            }
+            AgentError::InvalidPlan(err) => write!(f, "Generated plan is invalid: {err}"),
+
+            // Command errors
+            AgentError::UnknownCommand(cmd) => write!(f, "Unknown command: {cmd}"),
+            AgentError::InvalidArguments { command, usage } => {
+                write!(f, "Invalid arguments in {command}; expected {usage}")
+            }
+            AgentError::PathNotFound(path) => write!(f, "Path does not exist: {}", path.display()),
+            AgentError::PathIsDirectory(path) => {
+                write!(f, "Path is a directory: {}", path.display())
+            }
+            AgentError::PathForbidden(path) => write!(
+                f,
+                "Path is forbidden by the organization policy: {}",
+                path.display()
+            ),
+            AgentError::PathOutsideSandbox(path) => {
+                write!(f, "Path is outside the repository root: {}", path.display())
+            }
+            AgentError::CommandExecutionFailed => write!(f, "Command execution failed"),
+            AgentError::ToolDenied(cmd) => write!(f, "Command denied by tool policy: {cmd}"),
+            AgentError::SandboxUnavailable => {
+                write!(f, "No sandbox is available to run shell commands in")
+            }
+
+            // Answer errors
+            AgentError::AnswerGenerationFailed => write!(f, "Failed to generate answer"),
+            AgentError::EmptyAnswerResponse => write!(f, "Generated answer is empty"),
+
+            // Review errors
+            AgentError::ReviewFailed => write!(f, "Failed to review answer"),
+            AgentError::NoAnswerToReview => write!(f, "No answer available to review"),
+            AgentError::MaxIterationsReached => {
+                write!(f, "Maximum iterations reached without satisfactory answer")
+            }
+
+            // External errors
+            AgentError::CopilotError(err) => write!(f, "Copilot error: {err}"),
+            AgentError::IoError(err) => write!(f, "I/O error: {err}"),
+
+            // Fallback
+            AgentError::Other(msg) => write!(f, "Other error: {msg}"),
+        }
+    }
+}
+
+impl Error for AgentError {}
+
+impl AgentError {
+    /// Returns the path a command could not read, for errors about a single path
+    pub fn unreadable_path(&self) -> Option<&Path> {
+        match self {
+            AgentError::PathNotFound(path)
+            | AgentError::PathIsDirectory(path)
+            | AgentError::PathForbidden(path)
+            | AgentError::PathOutsideSandbox(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Suggests what the user can do about the error, if anything
+    pub fn remedy(&self) -> Option<&'static str> {
+        match self {
+            AgentError::CopilotError(err) => err.remedy(),
+            AgentError::SandboxUnavailable => {
+                Some(
+                    "Install bubblewrap (`bwrap`) on Linux; macOS ships with sandbox-exec. Windows is not supported",
+                )
+            }
+            AgentError::PathOutsideSandbox(_) => {
+                Some("Pass --root to let commands read another directory tree")
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<CopilotError> for AgentError {
+    fn from(error: CopilotError) -> Self {
+        AgentError::CopilotError(error)
+    }
+}
+
+impl From<std::io::Error> for AgentError {
+    fn from(error: std::io::Error) -> Self {
+        AgentError::IoError(error)
+    }
+}
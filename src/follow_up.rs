@@ -0,0 +1,126 @@
+//! # Follow-up Suggestions
+//!
+//! This module suggests questions to ask after an answer, so exploring a codebase in
+//! `nishiogi chat` can go on by picking a number instead of thinking up the next question.
+//! The suggestions are grounded in the files just read: the model is given their paths and
+//! the answer, and asked for questions naming concrete types, functions, or files from
+//! them, such as "How is `AgentContext` reset between queries?".
+
+use std::path::PathBuf;
+
+use crate::{error::AgentError, github_copilot_client::Message, review::ReviewModel};
+
+/// Maximum number of suggestions kept.
+pub const MAX_SUGGESTIONS: usize = 3;
+
+/// Characters of the answer included in the prompt.
+const MAX_ANSWER_CHARS: usize = 4000;
+
+/// Asks the model for up to [`MAX_SUGGESTIONS`] follow-up questions to `question`.
+///
+/// # Errors
+///
+/// Returns the model's `AgentError`.
+pub async fn suggest(
+    model: &mut dyn ReviewModel,
+    question: &str,
+    answer: &str,
+    files: &[PathBuf],
+) -> Result<Vec<String>, AgentError> {
+    let mut answer = answer.trim().to_string();
+    if answer.len() > MAX_ANSWER_CHARS {
+        answer.truncate(answer.floor_char_boundary(MAX_ANSWER_CHARS));
+        answer.push_str(" [...]");
+    }
+    let files: Vec<String> = files
+        .iter()
+        .map(|file| format!("- {}", file.display()))
+        .collect();
+    let files = if files.is_empty() {
+        "(none)".to_string()
+    } else {
+        files.join("\n")
+    };
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: "You suggest what a developer exploring a code repository could ask next. Each suggestion is one short question about something concrete in the files that were read: a type, a function, a file, or how they interact. Do not suggest questions the answer already answers.".to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!(
+                "Question: {question}\n\nFiles read:\n{files}\n\nAnswer:\n{answer}\n\nSuggest 2 or 3 follow-up questions. Respond with a JSON array of strings only."
+            ),
+        },
+    ];
+    let reply = model.complete(messages).await?;
+    Ok(parse_suggestions(&reply))
+}
+
+/// Extracts the suggestions from the model's reply, a JSON array or else a numbered or
+/// bulleted list.
+fn parse_suggestions(reply: &str) -> Vec<String> {
+    let parsed = match (reply.find('['), reply.rfind(']')) {
+        (Some(start), Some(end)) if start < end => {
+            serde_json::from_str::<Vec<String>>(&reply[start..=end]).ok()
+        }
+        _ => None,
+    };
+    let suggestions = parsed.unwrap_or_else(|| {
+        reply
+            .lines()
+            .map(|line| {
+                line.trim()
+                    .trim_start_matches(|c: char| {
+                        c.is_ascii_digit() || matches!(c, '.' | ')' | '-' | '*')
+                    })
+                    .trim()
+                    .to_string()
+            })
+            .filter(|line| line.ends_with('?'))
+            .collect()
+    });
+    suggestions
+        .into_iter()
+        .map(|suggestion| suggestion.trim().to_string())
+        .filter(|suggestion| !suggestion.is_empty())
+        .take(MAX_SUGGESTIONS)
+        .collect()
+}
+
+/// Returns the suggestion `input` picks by its number, if it is one.
+pub fn pick<'a>(input: &str, suggestions: &'a [String]) -> Option<&'a str> {
+    let number: usize = input.trim().parse().ok()?;
+    suggestions.get(number.checked_sub(1)?).map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_suggestions() {
+        assert_eq!(
+            parse_suggestions(
+                "Here you go:\n[\"How is `AgentContext` reset?\", \"Where is the plan parsed?\"]"
+            ),
+            ["How is `AgentContext` reset?", "Where is the plan parsed?"]
+        );
+        assert_eq!(
+            parse_suggestions(
+                "1. What calls `run_tool`?\n2) Why is the cache keyed by root?\nThat's all."
+            ),
+            ["What calls `run_tool`?", "Why is the cache keyed by root?"]
+        );
+        assert_eq!(
+            parse_suggestions("[\"a?\", \"b?\", \"c?\", \"d?\"]").len(),
+            MAX_SUGGESTIONS
+        );
+
+        let suggestions = vec!["a?".to_string(), "b?".to_string()];
+        assert_eq!(pick(" 2\n", &suggestions), Some("b?"));
+        assert_eq!(pick("0", &suggestions), None);
+        assert_eq!(pick("3", &suggestions), None);
+        assert_eq!(pick("why?", &suggestions), None);
+    }
+}
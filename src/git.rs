@@ -0,0 +1,129 @@
+//! # Git
+//!
+//! This module runs the `git` command line for the repository-aware features. Only
+//! read-only commands are used; nishiogi never changes the repository's history or index.
+
+use std::{error::Error, fmt, io, path::Path, process::Command};
+
+use crate::{
+    org_policy,
+    scheduler::{self, Resource},
+};
+
+/// Errors that can occur while running git.
+#[derive(Debug)]
+pub enum GitError {
+    /// git could not be started.
+    Unavailable(io::Error),
+    /// git exited with an error, with its message.
+    Failed(String),
+}
+
+impl fmt::Display for GitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitError::Unavailable(err) => write!(f, "Failed to run git: {err}"),
+            GitError::Failed(message) => write!(f, "git failed: {message}"),
+        }
+    }
+}
+
+impl Error for GitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GitError::Unavailable(err) => Some(err),
+            GitError::Failed(_) => None,
+        }
+    }
+}
+
+/// Runs git with `args` in `dir` and returns its standard output.
+///
+/// Waits for a subprocess slot of the [`scheduler`] first.
+///
+/// # Errors
+///
+/// Returns `GitError::Unavailable` if git cannot be started and `GitError::Failed` if it
+/// exits unsuccessfully.
+pub fn git(dir: &Path, args: &[&str]) -> Result<String, GitError> {
+    let _permit = scheduler::global().acquire(Resource::Subprocess);
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(GitError::Unavailable)?;
+    if !output.status.success() {
+        return Err(GitError::Failed(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Returns the staged changes as a unified diff, leaving out the files the organization
+/// policy forbids.
+///
+/// # Errors
+///
+/// Returns a `GitError` if git fails, for example outside a repository.
+pub fn staged_diff(dir: &Path) -> Result<String, GitError> {
+    let policy = org_policy::global();
+    if !policy.forbids_paths() {
+        return git(dir, &["diff", "--cached", "--no-color"]);
+    }
+    let pathspecs: Vec<String> = staged_files(dir)?
+        .into_iter()
+        .filter(|file| !policy.is_forbidden(Path::new(file)))
+        // The names are relative to the repository root, which `dir` may be below
+        .map(|file| format!(":(top,literal){file}"))
+        .collect();
+    if pathspecs.is_empty() {
+        return Ok(String::new());
+    }
+    let mut args = vec!["diff", "--cached", "--no-color", "--"];
+    args.extend(pathspecs.iter().map(String::as_str));
+    git(dir, &args)
+}
+
+/// Returns the paths of the staged files, relative to the repository root.
+///
+/// # Errors
+///
+/// Returns a `GitError` if git fails.
+pub fn staged_files(dir: &Path) -> Result<Vec<String>, GitError> {
+    Ok(git(dir, &["diff", "--cached", "--name-only"])?
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
+
+/// Returns the subjects of the latest `count` commits, newest first.
+///
+/// A repository without commits has no subjects.
+///
+/// # Errors
+///
+/// Returns a `GitError` if git cannot be started.
+pub fn recent_subjects(dir: &Path, count: usize) -> Result<Vec<String>, GitError> {
+    match git(dir, &["log", "-n", &count.to_string(), "--format=%s"]) {
+        Ok(log) => Ok(log.lines().map(str::to_string).collect()),
+        Err(GitError::Failed(_)) => Ok(Vec::new()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns the names of the local and remote-tracking branches.
+///
+/// # Errors
+///
+/// Returns a `GitError` if git fails.
+pub fn branch_names(dir: &Path) -> Result<Vec<String>, GitError> {
+    Ok(git(dir, &["branch", "--all", "--format=%(refname:short)"])?
+        .lines()
+        .map(|name| {
+            // Remote-tracking branches are named like "origin/feature/x"
+            name.strip_prefix("origin/").unwrap_or(name).to_string()
+        })
+        .filter(|name| name != "HEAD" && name != "origin")
+        .collect())
+}
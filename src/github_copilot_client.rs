@@ -7,20 +7,56 @@
 //!
 //! ## Features
 //!
-//! - Retrieve a GitHub token from the environment or configuration files.
+//! - Retrieve a GitHub token from the environment or configuration files (`native` feature
+//!   only; embedders without a filesystem pass the token to [`CopilotClient::new_with_models`]).
 //! - Fetch available Copilot models and agents.
 //! - Send chat completion requests and receive responses.
 //! - Request embeddings for provided input strings.
 
-use std::{env, error::Error, fmt, fs, path::Path};
+#[cfg(feature = "native")]
+use std::{env, fs, path::Path};
+use std::{error::Error, fmt};
 
+use async_trait::async_trait;
 use reqwest::{
     header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT},
-    Client as HttpClient,
+    Client as HttpClient, StatusCode,
 };
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "native")]
 use serde_json::Value;
 
+use crate::{
+    org_policy,
+    provider::{
+        apply_policy, check_provider, read_stream, reported_context, Capabilities, LlmProvider,
+        TextSink,
+    },
+    scheduler,
+};
+
+/// Name of this provider in the `[limits.providers]` section of the configuration file.
+pub const PROVIDER: &str = "copilot";
+
+/// The model used unless another one is configured.
+pub const DEFAULT_MODEL: &str = "gpt-4";
+
+/// Serializes `request`, marking the last of its first `stable_prefix` messages as the end
+/// of the cacheable prompt prefix.
+fn mark_cache_breakpoint(
+    request: &ChatRequest,
+    stable_prefix: usize,
+) -> Result<serde_json::Value, CopilotError> {
+    let mut body = serde_json::to_value(request).map_err(|e| CopilotError::Other(e.to_string()))?;
+    if let Some(message) = stable_prefix
+        .checked_sub(1)
+        .and_then(|index| body["messages"].get_mut(index))
+    {
+        message["copilot_cache_control"] = serde_json::json!({ "type": "ephemeral" });
+    }
+    Ok(body)
+}
+
 /// Represents errors that can occur when interacting with the GitHub Copilot API.
 #[derive(Debug)]
 pub enum CopilotError {
@@ -28,21 +64,84 @@ pub enum CopilotError {
     InvalidModel(String),
     /// An error occurred while retrieving or parsing the GitHub token.
     TokenError(String),
+    /// The GitHub token was rejected, typically because it expired or was revoked.
+    Unauthorized(String),
+    /// The API could not be reached.
+    Network(String),
     /// An HTTP error occurred during the API call.
     HttpError(String),
+    /// The organization policy forbids the request.
+    Forbidden(String),
+    /// The API key of a provider is missing or was rejected.
+    ApiKey(String),
+    /// The provider does not support the request.
+    Unsupported(String),
     /// Other errors.
     Other(String),
 }
 
+impl CopilotError {
+    /// Classifies a failed HTTP request.
+    pub(crate) fn from_http(err: reqwest::Error) -> Self {
+        if err.is_connect() || err.is_timeout() {
+            return CopilotError::Network(err.to_string());
+        }
+        match err.status() {
+            Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                CopilotError::Unauthorized(err.to_string())
+            }
+            _ => CopilotError::HttpError(err.to_string()),
+        }
+    }
+
+    /// Suggests what the user can do about the error, if anything.
+    pub fn remedy(&self) -> Option<&'static str> {
+        match self {
+            CopilotError::InvalidModel(_) => Some(
+                "Run `nishiogi models` to list the models available to your account, then set `model` in the [provider] section of your configuration",
+            ),
+            CopilotError::TokenError(_) => {
+                Some("Run `nishiogi auth login` to sign in to GitHub Copilot")
+            }
+            CopilotError::Unauthorized(_) => Some(
+                "Run `nishiogi auth login` to sign in again, and check that your account has an active Copilot subscription",
+            ),
+            CopilotError::Network(_) => Some(
+                "Check your network connection and the HTTPS_PROXY setting, then run `nishiogi doctor`",
+            ),
+            CopilotError::Forbidden(_) => Some(
+                "The organization policy is provisioned by your administrator and cannot be overridden; ask them which providers and models it allows",
+            ),
+            CopilotError::ApiKey(_) => Some(
+                "Set ANTHROPIC_API_KEY to use --provider claude, or OPENAI_API_KEY to use --provider openai",
+            ),
+            CopilotError::HttpError(_) | CopilotError::Unsupported(_) | CopilotError::Other(_) => {
+                None
+            }
+        }
+    }
+}
+
 impl fmt::Display for CopilotError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             CopilotError::InvalidModel(model) => {
-                write!(f, "Invalid model specified: {model}")
+                write!(f, "Model {model} is not available to this account")
             }
-            CopilotError::TokenError(msg) => write!(f, "Token error: {msg}"),
+            CopilotError::TokenError(msg) => {
+                write!(f, "No usable GitHub Copilot credentials: {msg}")
+            }
+            CopilotError::Unauthorized(msg) => write!(
+                f,
+                "The GitHub token was rejected; it may have expired or been revoked ({msg})"
+            ),
+            CopilotError::Network(msg) => write!(f, "Could not reach the model provider: {msg}"),
             CopilotError::HttpError(msg) => write!(f, "HTTP error: {msg}"),
-            CopilotError::Other(msg) => write!(f, "{msg}"),
+            CopilotError::Forbidden(msg) => {
+                write!(f, "Forbidden by the organization policy: {msg}")
+            }
+            CopilotError::ApiKey(msg) => write!(f, "No usable API key: {msg}"),
+            CopilotError::Unsupported(msg) | CopilotError::Other(msg) => write!(f, "{msg}"),
         }
     }
 }
@@ -105,7 +204,7 @@ pub struct ModelsResponse {
 /// Represents a chat message.
 ///
 /// The `role` field typically contains values such as `"system"`, `"user"`, or `"assistant"`.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     /// The role of the message sender.
     pub role: String,
@@ -131,6 +230,26 @@ pub struct ChatRequest {
     /// Optional maximum number of tokens to generate.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
+    /// The format the reply must follow, if constrained.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+}
+
+/// The format of a chat completion, for providers with a JSON mode.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResponseFormat {
+    /// The kind of format, such as `json_object`.
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+impl ResponseFormat {
+    /// Constrains the reply to a single JSON object.
+    pub fn json_object() -> Self {
+        Self {
+            kind: "json_object".to_string(),
+        }
+    }
 }
 
 /// Represents a single choice in a chat completion response.
@@ -149,6 +268,17 @@ pub struct ChatChoice {
 pub struct TokenUsage {
     /// Total tokens used.
     pub total_tokens: u32,
+    /// Details about the prompt tokens, if reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+}
+
+/// Breakdown of the prompt tokens of a request.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PromptTokensDetails {
+    /// Prompt tokens served from the provider's prompt cache.
+    #[serde(default)]
+    pub cached_tokens: u32,
 }
 
 /// Response payload for a chat completion request.
@@ -156,6 +286,41 @@ pub struct TokenUsage {
 pub struct ChatResponse {
     /// List of generated chat choices.
     pub choices: Vec<ChatChoice>,
+    /// Optional token usage information for the whole request.
+    #[serde(default)]
+    pub usage: Option<TokenUsage>,
+}
+
+impl ChatResponse {
+    /// Returns the tokens the request used, as reported by the API, or zero if it did not
+    /// report them.
+    pub fn tokens_used(&self) -> u64 {
+        match &self.usage {
+            Some(usage) => u64::from(usage.total_tokens),
+            None => self
+                .choices
+                .iter()
+                .filter_map(|choice| choice.usage.as_ref())
+                .map(|usage| u64::from(usage.total_tokens))
+                .sum(),
+        }
+    }
+
+    /// Returns the prompt tokens the provider served from its prompt cache, or zero if it
+    /// did not report them.
+    pub fn cached_tokens(&self) -> u64 {
+        self.usage
+            .iter()
+            .chain(
+                self.choices
+                    .iter()
+                    .filter_map(|choice| choice.usage.as_ref()),
+            )
+            .filter_map(|usage| usage.prompt_tokens_details.as_ref())
+            .map(|details| u64::from(details.cached_tokens))
+            .max()
+            .unwrap_or(0)
+    }
 }
 
 /// Request payload for an embeddings request.
@@ -208,6 +373,7 @@ impl CopilotClient {
     /// # Errors
     ///
     /// Returns a `CopilotError` if the token retrieval or model fetching fails.
+    #[cfg(feature = "native")]
     pub async fn from_env_with_models(editor_version: String) -> Result<Self, CopilotError> {
         let github_token =
             get_github_token().map_err(|e| CopilotError::TokenError(e.to_string()))?;
@@ -293,9 +459,9 @@ impl CopilotClient {
             .headers(headers)
             .send()
             .await
-            .map_err(|e| CopilotError::HttpError(e.to_string()))?
+            .map_err(CopilotError::from_http)?
             .error_for_status()
-            .map_err(|e| CopilotError::HttpError(e.to_string()))?;
+            .map_err(CopilotError::from_http)?;
         let token_response: CopilotTokenResponse = res
             .json()
             .await
@@ -318,9 +484,9 @@ impl CopilotClient {
             .headers(headers)
             .send()
             .await
-            .map_err(|e| CopilotError::HttpError(e.to_string()))?
+            .map_err(CopilotError::from_http)?
             .error_for_status()
-            .map_err(|e| CopilotError::HttpError(e.to_string()))?;
+            .map_err(CopilotError::from_http)?;
         let agents_response: AgentsResponse = res
             .json()
             .await
@@ -342,9 +508,9 @@ impl CopilotClient {
             .headers(headers)
             .send()
             .await
-            .map_err(|e| CopilotError::HttpError(e.to_string()))?
+            .map_err(CopilotError::from_http)?
             .error_for_status()
-            .map_err(|e| CopilotError::HttpError(e.to_string()))?;
+            .map_err(CopilotError::from_http)?;
         let models_response: ModelsResponse = res
             .json()
             .await
@@ -352,8 +518,21 @@ impl CopilotClient {
         Ok(models_response.data)
     }
 
+    /// Returns the models available to this account.
+    pub fn models(&self) -> &[Model] {
+        &self.models
+    }
+
+    /// Returns whether the model with the given ID is available to this account.
+    pub fn has_model(&self, model_id: &str) -> bool {
+        self.models.iter().any(|m| m.id == model_id)
+    }
+
     /// Sends a chat completion request to the GitHub Copilot API.
     ///
+    /// Waits for a model request slot of the [`scheduler`] first. The message contents are
+    /// redacted according to the [`org_policy`].
+    ///
     /// # Arguments
     ///
     /// * `messages` - A vector of chat messages to send.
@@ -362,16 +541,64 @@ impl CopilotClient {
     /// # Errors
     ///
     /// Returns a `CopilotError::InvalidModel` error if the specified model is not available,
-    /// or another `CopilotError` if the HTTP request or response parsing fails.
+    /// `CopilotError::Forbidden` if the organization policy does not allow it, or another `CopilotError` if the HTTP request or response parsing fails.
     pub async fn chat_completion(
         &self,
         messages: Vec<Message>,
         model_id: String,
     ) -> Result<ChatResponse, CopilotError> {
+        let system = messages
+            .iter()
+            .take_while(|message| message.role == "system")
+            .count();
+        self.chat_completion_cached(messages, model_id, system, None)
+            .await
+    }
+
+    /// Sends a chat completion request whose first `stable_prefix` messages are identical
+    /// across requests, marking them for the provider's prompt cache.
+    ///
+    /// OpenAI models cache identical prompt prefixes by themselves; Anthropic models only
+    /// cache up to the message carrying `copilot_cache_control`, so the last stable message
+    /// is marked. Cached prompt tokens are reported by [`ChatResponse::cached_tokens`].
+    ///
+    /// The completion is limited to `max_tokens` tokens if given.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`CopilotClient::chat_completion`].
+    pub async fn chat_completion_cached(
+        &self,
+        messages: Vec<Message>,
+        model_id: String,
+        stable_prefix: usize,
+        max_tokens: Option<u32>,
+    ) -> Result<ChatResponse, CopilotError> {
+        let res = self
+            .send_chat(messages, model_id, stable_prefix, max_tokens, false)
+            .await?;
+        let chat_response: ChatResponse = res
+            .json()
+            .await
+            .map_err(|e| CopilotError::Other(e.to_string()))?;
+        Ok(chat_response)
+    }
+
+    /// Sends a chat completion request, returning the response before its body is read.
+    async fn send_chat(
+        &self,
+        messages: Vec<Message>,
+        model_id: String,
+        stable_prefix: usize,
+        max_tokens: Option<u32>,
+        stream: bool,
+    ) -> Result<reqwest::Response, CopilotError> {
         // Check if the specified model is available.
-        if !self.models.iter().any(|m| m.id == model_id) {
+        if !self.has_model(&model_id) {
             return Err(CopilotError::InvalidModel(model_id));
         }
+        let messages = apply_policy(PROVIDER, &model_id, messages)?;
+        let _permit = scheduler::global().llm_call(PROVIDER).await;
         let url = "https://api.githubcopilot.com/chat/completions";
         let headers = self.get_headers().await?;
         let request_body = ChatRequest {
@@ -379,41 +606,48 @@ impl CopilotClient {
             messages,
             n: 1,
             top_p: 1.0,
-            stream: false,
+            stream,
             temperature: 0.5,
-            max_tokens: None,
+            max_tokens,
+            response_format: None,
         };
-        let res = self
-            .http_client
+        let request_body = mark_cache_breakpoint(&request_body, stable_prefix)?;
+        self.http_client
             .post(url)
             .headers(headers)
             .json(&request_body)
             .send()
             .await
-            .map_err(|e| CopilotError::HttpError(e.to_string()))?
+            .map_err(CopilotError::from_http)?
             .error_for_status()
-            .map_err(|e| CopilotError::HttpError(e.to_string()))?;
-        let chat_response: ChatResponse = res
-            .json()
-            .await
-            .map_err(|e| CopilotError::Other(e.to_string()))?;
-        Ok(chat_response)
+            .map_err(CopilotError::from_http)
     }
 
     /// Sends an embeddings request to the GitHub Copilot API.
     ///
+    /// Waits for a model request slot of the [`scheduler`] first. The inputs are redacted
+    /// according to the [`org_policy`].
+    ///
     /// # Arguments
     ///
     /// * `inputs` - A vector of input strings to generate embeddings for.
     ///
     /// # Errors
     ///
-    /// Returns a `CopilotError` if the HTTP request fails or the response cannot be parsed.
-    #[allow(dead_code)]
+    /// Returns `CopilotError::Forbidden` if the organization policy does not allow this
+    /// provider, or another `CopilotError` if the HTTP request fails or the response cannot
+    /// be parsed.
     pub async fn get_embeddings(
         &self,
         inputs: Vec<String>,
     ) -> Result<Vec<Embedding>, CopilotError> {
+        check_provider(PROVIDER)?;
+        let policy = org_policy::global();
+        let inputs = inputs
+            .into_iter()
+            .map(|input| policy.redact(&input).into_owned())
+            .collect();
+        let _permit = scheduler::global().llm_call(PROVIDER).await;
         let url = "https://api.githubcopilot.com/embeddings";
         let headers = self.get_headers().await?;
         let request_body = EmbeddingRequest {
@@ -428,9 +662,9 @@ impl CopilotClient {
             .json(&request_body)
             .send()
             .await
-            .map_err(|e| CopilotError::HttpError(e.to_string()))?
+            .map_err(CopilotError::from_http)?
             .error_for_status()
-            .map_err(|e| CopilotError::HttpError(e.to_string()))?;
+            .map_err(CopilotError::from_http)?;
         let embedding_response: EmbeddingResponse = res
             .json()
             .await
@@ -439,16 +673,65 @@ impl CopilotClient {
     }
 }
 
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl LlmProvider for CopilotClient {
+    fn name(&self) -> &'static str {
+        PROVIDER
+    }
+
+    fn models(&self) -> &[Model] {
+        &self.models
+    }
+
+    fn capabilities(&self, model_id: &str) -> Capabilities {
+        Capabilities {
+            tool_calling: true,
+            streaming: true,
+            ..Capabilities::basic(reported_context(&self.models, model_id))
+        }
+    }
+
+    async fn chat(
+        &self,
+        messages: Vec<Message>,
+        model_id: &str,
+        stable_prefix: usize,
+        max_tokens: Option<u32>,
+    ) -> Result<ChatResponse, CopilotError> {
+        self.chat_completion_cached(messages, model_id.to_string(), stable_prefix, max_tokens)
+            .await
+    }
+
+    async fn stream(
+        &self,
+        messages: Vec<Message>,
+        model_id: &str,
+        max_tokens: Option<u32>,
+        on_text: &mut TextSink<'_>,
+    ) -> Result<ChatResponse, CopilotError> {
+        let res = self
+            .send_chat(messages, model_id.to_string(), 0, max_tokens, true)
+            .await?;
+        read_stream(res, on_text).await
+    }
+
+    async fn embeddings(&self, inputs: Vec<String>) -> Result<Vec<Embedding>, CopilotError> {
+        self.get_embeddings(inputs).await
+    }
+}
+
 /// Retrieves the GitHub token from the `GITHUB_TOKEN` environment variable or from a configuration file.
 ///
 /// # Errors
 ///
 /// Returns an error if the token is not found in the environment or configuration files.
+#[cfg(feature = "native")]
 pub fn get_github_token() -> Result<String, Box<dyn Error>> {
-    if let Ok(token) = env::var("GITHUB_TOKEN") {
-        if env::var("CODESPACES").is_ok() {
-            return Ok(token);
-        }
+    if let Ok(token) = env::var("GITHUB_TOKEN")
+        && env::var("CODESPACES").is_ok()
+    {
+        return Ok(token);
     }
     let config_dir = get_config_path()?;
     let file_paths = vec![
@@ -461,12 +744,11 @@ pub fn get_github_token() -> Result<String, Box<dyn Error>> {
             let json_value: Value = serde_json::from_str(&content)?;
             if let Some(obj) = json_value.as_object() {
                 for (key, value) in obj {
-                    if key.contains("github.com") {
-                        if let Some(oauth_token) = value.get("oauth_token") {
-                            if let Some(token_str) = oauth_token.as_str() {
-                                return Ok(token_str.to_string());
-                            }
-                        }
+                    if key.contains("github.com")
+                        && let Some(oauth_token) = value.get("oauth_token")
+                        && let Some(token_str) = oauth_token.as_str()
+                    {
+                        return Ok(token_str.to_string());
                     }
                 }
             }
@@ -483,20 +765,70 @@ pub fn get_github_token() -> Result<String, Box<dyn Error>> {
 /// # Errors
 ///
 /// Returns an error if the configuration directory cannot be determined.
+#[cfg(feature = "native")]
 pub fn get_config_path() -> Result<String, Box<dyn Error>> {
-    if let Ok(xdg) = env::var("XDG_CONFIG_HOME") {
-        if !xdg.is_empty() {
-            return Ok(xdg);
-        }
+    if let Ok(xdg) = env::var("XDG_CONFIG_HOME")
+        && !xdg.is_empty()
+    {
+        return Ok(xdg);
     }
     if cfg!(target_os = "windows") {
-        if let Ok(local) = env::var("LOCALAPPDATA") {
-            if !local.is_empty() {
-                return Ok(local);
-            }
+        if let Ok(local) = env::var("LOCALAPPDATA")
+            && !local.is_empty()
+        {
+            return Ok(local);
         }
     } else if let Ok(home) = env::var("HOME") {
         return Ok(format!("{home}/.config"));
     }
     Err("Failed to find config directory".into())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_breakpoint() {
+        let message = |role: &str| Message {
+            role: role.to_string(),
+            content: format!("{role} prompt"),
+        };
+        let request = ChatRequest {
+            model: "claude-sonnet-4".to_string(),
+            messages: vec![message("system"), message("user"), message("user")],
+            n: 1,
+            top_p: 1.0,
+            stream: false,
+            temperature: 0.5,
+            max_tokens: None,
+            response_format: None,
+        };
+        let body = mark_cache_breakpoint(&request, 2).expect("Failed to serialize request");
+        let marked: Vec<bool> = body["messages"]
+            .as_array()
+            .expect("Messages are not an array")
+            .iter()
+            .map(|message| message.get("copilot_cache_control").is_some())
+            .collect();
+        assert_eq!(marked, [false, true, false]);
+
+        let body = mark_cache_breakpoint(&request, 0).expect("Failed to serialize request");
+        assert!(!body.to_string().contains("copilot_cache_control"));
+    }
+
+    #[test]
+    fn test_cached_tokens() {
+        let response: ChatResponse = serde_json::from_str(
+            r#"{"choices": [], "usage": {"total_tokens": 1500, "prompt_tokens_details": {"cached_tokens": 1024}}}"#,
+        )
+        .expect("Failed to parse response");
+        assert_eq!(response.tokens_used(), 1500);
+        assert_eq!(response.cached_tokens(), 1024);
+
+        let response: ChatResponse =
+            serde_json::from_str(r#"{"choices": [], "usage": {"total_tokens": 10}}"#)
+                .expect("Failed to parse response");
+        assert_eq!(response.cached_tokens(), 0);
+    }
+}
@@ -0,0 +1,289 @@
+//! # Project Glossary
+//!
+//! This module keeps the jargon of a repository, so a question such as "how does the warm
+//! cache get invalidated?" is resolved to the module implementing it rather than searched
+//! for by guesswork. Terms come from two sources:
+//!
+//! - **Configuration**: the `[glossary]` section maps a term to its definition, its
+//!   locations, or both:
+//!
+//!   ```toml
+//!   [glossary]
+//!   "warm cache" = "Symbol index and trees precomputed by `nishiogi warm`, in src/warm.rs"
+//!   Pin = { definition = "An answer marked as still valid", locations = ["src/session.rs"] }
+//!   ```
+//!
+//! - **Earlier sessions**: identifiers a past question asked about (backticked words,
+//!   `CamelCase` or `snake_case` names) that its answer explained, with the files the
+//!   answer cited. Configured terms take precedence over learned ones.
+//!
+//! The terms a question uses are described to the planning step (see [`Glossary::prompt`]).
+
+use std::{collections::BTreeMap, path::Path, sync::LazyLock};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::session::SessionRecord;
+
+/// Most locations kept per term.
+const MAX_LOCATIONS: usize = 3;
+
+/// Most terms described for one question.
+const MAX_TERMS: usize = 10;
+
+/// Identifier-like words of a question: backticked code, `CamelCase`, and `snake_case`.
+static IDENTIFIER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"`([^`\s]+)`|\b([A-Z][a-z0-9]+[A-Z][A-Za-z0-9]*|[a-z][a-z0-9]*(?:_[a-z0-9]+)+)\b")
+        .expect("valid regex")
+});
+
+/// A term of the `[glossary]` section.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum GlossaryTerm {
+    /// Only a definition.
+    Definition(String),
+    /// A definition and where the term is implemented.
+    Detailed {
+        /// What the term means.
+        #[serde(default)]
+        definition: Option<String>,
+        /// Files or directories implementing it.
+        #[serde(default)]
+        locations: Vec<String>,
+    },
+}
+
+/// What a term means and where it lives.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GlossaryEntry {
+    /// What the term means, if known.
+    pub definition: Option<String>,
+    /// Files or directories implementing it.
+    pub locations: Vec<String>,
+}
+
+/// The terms of a repository, by term.
+#[derive(Debug, Clone, Default)]
+pub struct Glossary {
+    entries: BTreeMap<String, GlossaryEntry>,
+}
+
+impl Glossary {
+    /// Creates a glossary of the configured `terms`.
+    pub fn new(terms: &BTreeMap<String, GlossaryTerm>) -> Self {
+        let entries = terms
+            .iter()
+            .map(|(term, configured)| {
+                let entry = match configured {
+                    GlossaryTerm::Definition(definition) => GlossaryEntry {
+                        definition: Some(definition.clone()),
+                        locations: Vec::new(),
+                    },
+                    GlossaryTerm::Detailed {
+                        definition,
+                        locations,
+                    } => GlossaryEntry {
+                        definition: definition.clone(),
+                        locations: locations.clone(),
+                    },
+                };
+                (term.clone(), entry)
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// Learns the identifiers explained by the answers of `records` asked from `dir`, with
+    /// the files each answer cited.
+    ///
+    /// Terms already in the glossary are left as they are.
+    pub fn learn(&mut self, records: &[SessionRecord], dir: &Path) {
+        let mut learned: BTreeMap<String, GlossaryEntry> = BTreeMap::new();
+        let records = records.iter().filter(|record| {
+            record
+                .working_dir
+                .as_deref()
+                .is_none_or(|working| working == dir)
+        });
+        for entry in records.flat_map(|record| &record.entries) {
+            if entry.truncated {
+                continue;
+            }
+            let cited: Vec<String> = entry
+                .provenance
+                .files
+                .iter()
+                .map(|file| display_path(&file.path))
+                .filter(|path| is_cited(&entry.answer, path))
+                .collect();
+            if cited.is_empty() {
+                continue;
+            }
+            for term in identifiers(&entry.question) {
+                if self.contains(&term) || !entry.answer.contains(&term) {
+                    continue;
+                }
+                let locations = &mut learned.entry(term).or_default().locations;
+                for path in &cited {
+                    if locations.len() < MAX_LOCATIONS && !locations.contains(path) {
+                        locations.push(path.clone());
+                    }
+                }
+            }
+        }
+        self.entries.extend(learned);
+    }
+
+    /// Returns whether `term` is in the glossary, ignoring case.
+    fn contains(&self, term: &str) -> bool {
+        self.entries
+            .keys()
+            .any(|known| known.eq_ignore_ascii_case(term))
+    }
+
+    /// Returns the number of terms.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the glossary has no terms.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the terms `question` uses, as whole words ignoring case.
+    pub fn terms_in(&self, question: &str) -> Vec<(&str, &GlossaryEntry)> {
+        self.entries
+            .iter()
+            .filter(|(term, _)| {
+                Regex::new(&format!(r"(?i)(?:^|\W){}(?:$|\W)", regex::escape(term)))
+                    .is_ok_and(|regex| regex.is_match(question))
+            })
+            .take(MAX_TERMS)
+            .map(|(term, entry)| (term.as_str(), entry))
+            .collect()
+    }
+
+    /// Describes the terms `question` uses for a prompt, if it uses any.
+    pub fn prompt(&self, question: &str) -> Option<String> {
+        let terms = self.terms_in(question);
+        if terms.is_empty() {
+            return None;
+        }
+        let mut text = String::from("Project terms used in the question:\n");
+        for (term, entry) in terms {
+            text.push_str(&format!("- {term}"));
+            if let Some(definition) = &entry.definition {
+                text.push_str(&format!(": {definition}"));
+            }
+            if !entry.locations.is_empty() {
+                text.push_str(&format!(" (see {})", entry.locations.join(", ")));
+            }
+            text.push('\n');
+        }
+        Some(text)
+    }
+}
+
+/// Returns the identifier-like words of `text`, in order and without duplicates.
+fn identifiers(text: &str) -> Vec<String> {
+    let mut found: Vec<String> = Vec::new();
+    for captures in IDENTIFIER.captures_iter(text) {
+        let Some(word) = captures.get(1).or_else(|| captures.get(2)) else {
+            continue;
+        };
+        let word = word.as_str().trim_end_matches("()");
+        if !word.is_empty() && !found.iter().any(|known| known == word) {
+            found.push(word.to_string());
+        }
+    }
+    found
+}
+
+/// Returns `path` with `/` separators and without a leading `./`.
+fn display_path(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    path.trim_start_matches("./").to_string()
+}
+
+/// Returns whether `answer` mentions `path` or its file name.
+fn is_cited(answer: &str, path: &str) -> bool {
+    answer.contains(path)
+        || path
+            .rsplit('/')
+            .next()
+            .is_some_and(|name| !name.is_empty() && answer.contains(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::session::{FileProvenance, Provenance, SessionEntry};
+
+    fn entry(question: &str, answer: &str, files: &[&str]) -> SessionEntry {
+        SessionEntry {
+            question: question.to_string(),
+            answer: answer.to_string(),
+            answered_at: Utc::now(),
+            provenance: Provenance {
+                model: "gpt-4o".to_string(),
+                files: files
+                    .iter()
+                    .map(|path| FileProvenance {
+                        path: PathBuf::from(path),
+                        range: None,
+                        sha256: String::new(),
+                    })
+                    .collect(),
+                prompt_hashes: Vec::new(),
+                tool_calls: Vec::new(),
+                pin: None,
+            },
+            question_embedding: None,
+            truncated: false,
+        }
+    }
+
+    #[test]
+    fn test_glossary() {
+        let terms = toml::from_str::<BTreeMap<String, GlossaryTerm>>(
+            "\"warm cache\" = \"Precomputed symbol index\"\nPin = { locations = [\"src/session.rs\"] }\n",
+        )
+        .expect("Valid glossary");
+        let mut glossary = Glossary::new(&terms);
+
+        let dir = PathBuf::from("/repo");
+        let mut record = SessionRecord::new(Some(dir.clone()));
+        record.entries = vec![
+            entry(
+                "How does `SymbolIndex` get built, and what is a Pin?",
+                "SymbolIndex is built in ./src/symbols.rs by walking the tree. A Pin marks answers.",
+                &["./src/symbols.rs", "src/tree.rs"],
+            ),
+            // Nothing cited, so nothing is learned
+            entry("What does run_tool do?", "run_tool runs tools.", &["src/agent.rs"]),
+        ];
+        let mut elsewhere = SessionRecord::new(Some(PathBuf::from("/other")));
+        elsewhere.entries = vec![entry(
+            "What is plan_execution?",
+            "plan_execution is in src/agent.rs",
+            &["src/agent.rs"],
+        )];
+        glossary.learn(&[record, elsewhere], &dir);
+        assert_eq!(glossary.len(), 3);
+
+        assert_eq!(
+            glossary
+                .prompt("Why is the Warm Cache stale after a pin? Where is SymbolIndex used?")
+                .as_deref(),
+            Some("Project terms used in the question:\n- Pin (see src/session.rs)\n- SymbolIndex (see src/symbols.rs)\n- warm cache: Precomputed symbol index\n")
+        );
+        assert_eq!(glossary.prompt("What is a pinch of salt?"), None);
+    }
+}
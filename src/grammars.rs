@@ -0,0 +1,579 @@
+//! # Installed Grammars
+//!
+//! This module installs [tree-sitter] grammars for languages the [`outline`](crate::outline)
+//! module has no grammar compiled in for, and loads them at run time, so the binary does not
+//! grow with every supported language.
+//!
+//! `nishiogi grammars install <language>` clones the grammar's repository, by default
+//! `https://github.com/tree-sitter/tree-sitter-<language>`, or takes a local checkout with
+//! `--from`. It compiles the generated parser (`src/parser.c` and the optional external
+//! scanner) with the C compiler (`$CC`, or `cc`) into a shared library, checks that the
+//! linked tree-sitter can use it, and saves it under `~/.nishiogi/grammars`:
+//!
+//! - `<language>.so` (`.dylib` on macOS): the parser, exporting `tree_sitter_<language>`
+//! - `<language>.json`: the extensions of the files in the language and where the grammar
+//!   came from
+//!
+//! The extensions are given with `--extension`, or else read from the `file-types` of the
+//! grammar's `tree-sitter.json` or `package.json`. Grammars compiled in take precedence over
+//! installed ones for the same extension.
+//!
+//! The library runs in the process, so remote grammars are only cloned over https, and git
+//! is not allowed to follow redirects to other protocols.
+//!
+//! [tree-sitter]: https://tree-sitter.github.io
+
+use std::{
+    env,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::LazyLock,
+};
+
+use libloading::{Library, Symbol};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tree_sitter::{Language, Parser};
+use tree_sitter_language::LanguageFn;
+
+use crate::{
+    config::data_dir,
+    git,
+    scheduler::{self, Resource},
+};
+
+/// Directory under the data directory holding the installed grammars.
+const GRAMMARS_DIR: &str = "grammars";
+
+/// The installed grammars, loaded on first use.
+static INSTALLED: LazyLock<Vec<InstalledGrammar>> =
+    LazyLock::new(|| grammars_dir().map(|dir| load_all(&dir)).unwrap_or_default());
+
+/// Errors installing or loading a grammar.
+#[derive(Debug)]
+pub enum GrammarError {
+    /// The language name cannot be used as a file or symbol name.
+    InvalidName(String),
+    /// The source is a URL that is not https.
+    InsecureSource(String),
+    /// Cloning the grammar's repository failed.
+    Clone(git::GitError),
+    /// The source has no generated parser (`src/parser.c`).
+    NoParser(PathBuf),
+    /// The C compiler could not be run or failed, with its message.
+    Compile(String),
+    /// The library could not be loaded, lacks the language function, or was generated for
+    /// an ABI version the linked tree-sitter does not support.
+    Load(String),
+    /// The data directory cannot be determined.
+    NoDataDir,
+    /// Reading or writing a grammar failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrammarError::InvalidName(name) => write!(
+                f,
+                "Invalid language name {name}; use lowercase letters, digits, - and _"
+            ),
+            GrammarError::InsecureSource(source) => write!(
+                f,
+                "Refusing to download {source}; grammars are only downloaded over https"
+            ),
+            GrammarError::Clone(err) => write!(f, "Failed to clone the grammar: {err}"),
+            GrammarError::NoParser(root) => write!(
+                f,
+                "No generated parser (src/parser.c) in {}",
+                root.display()
+            ),
+            GrammarError::Compile(message) => {
+                write!(f, "Failed to compile the grammar: {message}")
+            }
+            GrammarError::Load(message) => write!(f, "Failed to load the grammar: {message}"),
+            GrammarError::NoDataDir => write!(f, "Cannot determine the home directory"),
+            GrammarError::Io(err) => write!(f, "Failed to access grammar: {err}"),
+        }
+    }
+}
+
+impl Error for GrammarError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GrammarError::Clone(err) => Some(err),
+            GrammarError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for GrammarError {
+    fn from(err: io::Error) -> Self {
+        GrammarError::Io(err)
+    }
+}
+
+/// The manifest saved next to an installed grammar's library.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Manifest {
+    /// Extensions of the files in the language, without the dot.
+    extensions: Vec<String>,
+    /// The URL or directory the grammar was built from.
+    source: String,
+}
+
+/// Where a grammar is built from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Source {
+    /// A git repository cloned over https.
+    Remote(String),
+    /// A local checkout.
+    Local(PathBuf),
+}
+
+/// An installed grammar, loaded and ready to parse.
+#[derive(Debug)]
+pub struct InstalledGrammar {
+    name: String,
+    extensions: Vec<String>,
+    source: String,
+    language: Language,
+    // Declared last so it is dropped last: the language points into the library
+    _library: Library,
+}
+
+impl InstalledGrammar {
+    /// Returns the language the grammar is installed for.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the extensions of the files in the language.
+    pub fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    /// Returns the URL or directory the grammar was built from.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the tree-sitter language of the grammar.
+    pub fn language(&self) -> &Language {
+        &self.language
+    }
+}
+
+/// Returns the directory holding the installed grammars (`~/.nishiogi/grammars`).
+pub fn grammars_dir() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join(GRAMMARS_DIR))
+}
+
+/// Checks that `name` can name a grammar's files and its language function.
+fn validate_name(name: &str) -> Result<(), GrammarError> {
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(GrammarError::InvalidName(name.to_string()))
+    }
+}
+
+/// Resolves where to build the grammar for `name` from: `from` if given, or else the
+/// tree-sitter organization's repository for the language.
+fn resolve_source(name: &str, from: Option<&str>) -> Result<Source, GrammarError> {
+    let Some(from) = from else {
+        return Ok(Source::Remote(format!(
+            "https://github.com/tree-sitter/tree-sitter-{name}"
+        )));
+    };
+    if from.starts_with("https://") {
+        Ok(Source::Remote(from.to_string()))
+    } else if from.contains("://") || from.starts_with("git@") {
+        Err(GrammarError::InsecureSource(from.to_string()))
+    } else {
+        Ok(Source::Local(PathBuf::from(from)))
+    }
+}
+
+/// Returns the file name of the library of the grammar for `name`.
+fn library_file(name: &str) -> String {
+    format!("{name}.{}", env::consts::DLL_EXTENSION)
+}
+
+/// Returns the directory holding the generated parser of the grammar for `name` in the
+/// checkout `root`, which is `src` or, in repositories holding several grammars,
+/// `<name>/src`.
+fn parser_dir(root: &Path, name: &str) -> Option<PathBuf> {
+    [root.join("src"), root.join(name).join("src")]
+        .into_iter()
+        .find(|dir| dir.join("parser.c").is_file())
+}
+
+/// Reads the extensions of the files in the language `name` from the `file-types` of the
+/// grammar's `tree-sitter.json` or `package.json` in one of `dirs`.
+///
+/// Falls back to the language name, as for `.zig` files.
+fn detect_extensions(dirs: &[&Path], name: &str) -> Vec<String> {
+    let file_types = |dir: &Path, file: &str, list: &str, key: &str| {
+        let text = fs::read_to_string(dir.join(file)).ok()?;
+        let value: Value = serde_json::from_str(&text).ok()?;
+        let grammars = value.get(list)?.as_array()?;
+        // A repository may hold several grammars; prefer the one named after the language
+        let grammar = grammars
+            .iter()
+            .find(|grammar| {
+                grammar
+                    .get(key)
+                    .and_then(Value::as_str)
+                    .is_some_and(|value| value == name || value.rsplit('.').next() == Some(name))
+            })
+            .or_else(|| grammars.first())?;
+        let extensions: Vec<String> = grammar
+            .get("file-types")?
+            .as_array()?
+            .iter()
+            .filter_map(Value::as_str)
+            .map(|extension| extension.trim_start_matches('.').to_string())
+            .filter(|extension| !extension.is_empty())
+            .collect();
+        (!extensions.is_empty()).then_some(extensions)
+    };
+    dirs.iter()
+        .find_map(|dir| {
+            file_types(dir, "tree-sitter.json", "grammars", "name")
+                .or_else(|| file_types(dir, "package.json", "tree-sitter", "scope"))
+        })
+        .unwrap_or_else(|| vec![name.to_string()])
+}
+
+/// Compiles the parser in `src` into the shared library `output`.
+fn compile(src: &Path, output: &Path) -> Result<(), GrammarError> {
+    // Older grammars have their external scanner in C++
+    let cpp_scanner = src.join("scanner.cc");
+    let cpp = cpp_scanner.is_file();
+    let (variable, default) = if cpp { ("CXX", "c++") } else { ("CC", "cc") };
+    let compiler = env::var_os(variable).unwrap_or_else(|| default.into());
+    let mut command = Command::new(&compiler);
+    command.args(["-shared", "-fPIC", "-O2", "-I"]).arg(src);
+    if cpp {
+        command
+            .args(["-x", "c"])
+            .arg(src.join("parser.c"))
+            .args(["-x", "c++"])
+            .arg(cpp_scanner);
+    } else {
+        command.arg(src.join("parser.c"));
+        let scanner = src.join("scanner.c");
+        if scanner.is_file() {
+            command.arg(scanner);
+        }
+    }
+    command.arg("-o").arg(output);
+
+    let _permit = scheduler::global().acquire(Resource::Subprocess);
+    let result = command.output().map_err(|err| {
+        GrammarError::Compile(format!("cannot run {}: {err}", compiler.to_string_lossy()))
+    })?;
+    if !result.status.success() {
+        return Err(GrammarError::Compile(
+            String::from_utf8_lossy(&result.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Loads the grammar for `name` from the library at `path`, checking that the linked
+/// tree-sitter supports its ABI version.
+fn load(path: &Path, name: &str) -> Result<(Language, Library), GrammarError> {
+    let symbol = format!("tree_sitter_{}", name.replace('-', "_"));
+    // SAFETY: the library is a grammar compiled by `install` from a local checkout or a
+    // repository cloned over https; loading it runs its initializers, as linking would
+    let library =
+        unsafe { Library::new(path) }.map_err(|err| GrammarError::Load(err.to_string()))?;
+    let language = {
+        // SAFETY: tree-sitter generates the language function with exactly this signature
+        let function: Symbol<'_, unsafe extern "C" fn() -> *const ()> =
+            unsafe { library.get(symbol.as_bytes()) }
+                .map_err(|err| GrammarError::Load(err.to_string()))?;
+        // SAFETY: as above; the returned language is kept together with the library
+        Language::new(unsafe { LanguageFn::from_raw(*function) })
+    };
+    Parser::new()
+        .set_language(&language)
+        .map_err(|err| GrammarError::Load(err.to_string()))?;
+    Ok((language, library))
+}
+
+/// Builds the grammar for `name` from `from` (an https URL or a local checkout; the
+/// tree-sitter organization's repository by default) and installs it in `dir` for the
+/// files with `extensions`, replacing any grammar installed for the language before.
+///
+/// Without `extensions`, they are read from the grammar's metadata.
+///
+/// # Errors
+///
+/// Returns a `GrammarError` if the name or source is invalid, or the grammar cannot be
+/// cloned, compiled, loaded or saved.
+pub fn install(
+    dir: &Path,
+    name: &str,
+    from: Option<&str>,
+    extensions: &[String],
+) -> Result<InstalledGrammar, GrammarError> {
+    validate_name(name)?;
+    let source = resolve_source(name, from)?;
+    let build = dir.join(format!(".build-{name}"));
+    if build.exists() {
+        fs::remove_dir_all(&build)?;
+    }
+    fs::create_dir_all(&build)?;
+    let installed = build_and_install(dir, &build, name, &source, extensions);
+    // The checkout is not needed once the library is in place
+    let _ = fs::remove_dir_all(&build);
+    installed
+}
+
+/// Builds the grammar for `name` from `source` in the scratch directory `build` and moves it
+/// into `dir`.
+fn build_and_install(
+    dir: &Path,
+    build: &Path,
+    name: &str,
+    source: &Source,
+    extensions: &[String],
+) -> Result<InstalledGrammar, GrammarError> {
+    let (root, source) = match source {
+        Source::Remote(url) => {
+            git::git(
+                build,
+                &[
+                    "-c",
+                    "protocol.allow=never",
+                    "-c",
+                    "protocol.https.allow=always",
+                    "clone",
+                    "--depth",
+                    "1",
+                    "--quiet",
+                    "--",
+                    url,
+                    "checkout",
+                ],
+            )
+            .map_err(GrammarError::Clone)?;
+            (build.join("checkout"), url.clone())
+        }
+        Source::Local(path) => {
+            let path = path.canonicalize()?;
+            let source = path.display().to_string();
+            (path, source)
+        }
+    };
+    let src = parser_dir(&root, name).ok_or_else(|| GrammarError::NoParser(root.clone()))?;
+    let built = build.join(library_file(name));
+    compile(&src, &built)?;
+    let (language, library) = load(&built, name)?;
+
+    let extensions = if extensions.is_empty() {
+        let grammar_dir = src.parent().unwrap_or(&root);
+        detect_extensions(&[grammar_dir, &root], name)
+    } else {
+        extensions
+            .iter()
+            .map(|extension| extension.trim_start_matches('.').to_string())
+            .collect()
+    };
+    let manifest = Manifest {
+        extensions: extensions.clone(),
+        source: source.clone(),
+    };
+    // Renamed rather than copied: other processes may have the old library mapped
+    fs::rename(&built, dir.join(library_file(name)))?;
+    fs::write(
+        dir.join(format!("{name}.json")),
+        serde_json::to_string_pretty(&manifest).map_err(io::Error::from)?,
+    )?;
+    Ok(InstalledGrammar {
+        name: name.to_string(),
+        extensions,
+        source,
+        language,
+        _library: library,
+    })
+}
+
+/// Removes the grammar installed for `name` in `dir`, returning whether there was one.
+///
+/// # Errors
+///
+/// Returns a `GrammarError` if the name is invalid or the grammar cannot be removed.
+pub fn remove(dir: &Path, name: &str) -> Result<bool, GrammarError> {
+    validate_name(name)?;
+    let mut removed = false;
+    for file in [format!("{name}.json"), library_file(name)] {
+        match fs::remove_file(dir.join(file)) {
+            Ok(()) => removed = true,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(removed)
+}
+
+/// Loads the grammars installed in `dir`, sorted by language.
+///
+/// Grammars that cannot be loaded are reported and skipped so one broken grammar does not
+/// disable the others.
+pub fn load_all(dir: &Path) -> Vec<InstalledGrammar> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut grammars: Vec<InstalledGrammar> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_string_lossy().into_owned();
+            let loaded = validate_name(&name)
+                .and_then(|()| {
+                    let text = fs::read_to_string(&path)?;
+                    serde_json::from_str::<Manifest>(&text)
+                        .map_err(|err| GrammarError::Io(err.into()))
+                })
+                .and_then(|manifest| {
+                    let (language, library) = load(&dir.join(library_file(&name)), &name)?;
+                    Ok(InstalledGrammar {
+                        name,
+                        extensions: manifest.extensions,
+                        source: manifest.source,
+                        language,
+                        _library: library,
+                    })
+                });
+            match loaded {
+                Ok(grammar) => Some(grammar),
+                Err(err) => {
+                    eprintln!("Skipping grammar {}: {err}", path.display());
+                    None
+                }
+            }
+        })
+        .collect();
+    grammars.sort_by(|a, b| a.name.cmp(&b.name));
+    grammars
+}
+
+/// Returns the installed grammars, loaded on first use.
+pub fn installed() -> &'static [InstalledGrammar] {
+    &INSTALLED
+}
+
+/// Returns the installed grammar for the language of the file at `path`, if any.
+pub fn for_path(path: &Path) -> Option<&'static InstalledGrammar> {
+    let extension = path.extension()?.to_str()?;
+    installed()
+        .iter()
+        .find(|grammar| grammar.extensions.iter().any(|known| known == extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve_source() {
+        assert!(validate_name("c-sharp").is_ok());
+        for name in ["", "../zig", "Zig", "zig.so", "-zig"] {
+            assert!(matches!(
+                validate_name(name),
+                Err(GrammarError::InvalidName(_))
+            ));
+        }
+
+        assert_eq!(
+            resolve_source("zig", None).expect("Failed to resolve source"),
+            Source::Remote("https://github.com/tree-sitter/tree-sitter-zig".to_string())
+        );
+        assert_eq!(
+            resolve_source("zig", Some("https://example.com/tree-sitter-zig.git"))
+                .expect("Failed to resolve source"),
+            Source::Remote("https://example.com/tree-sitter-zig.git".to_string())
+        );
+        assert_eq!(
+            resolve_source("zig", Some("../tree-sitter-zig")).expect("Failed to resolve source"),
+            Source::Local(PathBuf::from("../tree-sitter-zig"))
+        );
+        for insecure in [
+            "http://example.com/tree-sitter-zig.git",
+            "git://example.com/tree-sitter-zig.git",
+            "git@example.com:tree-sitter-zig.git",
+        ] {
+            assert!(matches!(
+                resolve_source("zig", Some(insecure)),
+                Err(GrammarError::InsecureSource(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_detect_extensions() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let root = dir.path();
+        assert_eq!(detect_extensions(&[root], "zig"), ["zig"]);
+
+        fs::write(
+            root.join("package.json"),
+            r#"{"tree-sitter": [{"scope": "source.elixir", "file-types": ["ex", "exs"]}]}"#,
+        )
+        .expect("Failed to write package.json");
+        assert_eq!(detect_extensions(&[root], "elixir"), ["ex", "exs"]);
+
+        // tree-sitter.json is preferred, and the grammar named after the language is chosen
+        fs::write(
+            root.join("tree-sitter.json"),
+            r#"{"grammars": [
+                {"name": "markdown", "file-types": ["md"]},
+                {"name": "markdown_inline", "file-types": []}
+            ]}"#,
+        )
+        .expect("Failed to write tree-sitter.json");
+        assert_eq!(detect_extensions(&[root], "markdown"), ["md"]);
+        assert_eq!(detect_extensions(&[root], "elixir"), ["md"]);
+    }
+
+    #[test]
+    fn test_install_and_remove() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let grammars = dir.path().join(GRAMMARS_DIR);
+        let checkout = dir.path().join("tree-sitter-zig");
+        fs::create_dir_all(&checkout).expect("Failed to create checkout");
+        assert!(matches!(
+            install(&grammars, "zig", checkout.to_str(), &[]),
+            Err(GrammarError::NoParser(_))
+        ));
+        assert!(!grammars.join(".build-zig").exists());
+
+        // A manifest whose library is missing is skipped, but can still be removed
+        fs::write(
+            grammars.join("zig.json"),
+            r#"{"extensions": ["zig"], "source": "https://github.com/tree-sitter/tree-sitter-zig"}"#,
+        )
+        .expect("Failed to write manifest");
+        assert!(load_all(&grammars).is_empty());
+        assert!(remove(&grammars, "zig").expect("Failed to remove grammar"));
+        assert!(!remove(&grammars, "zig").expect("Failed to remove grammar"));
+        assert!(remove(&grammars, "../zig").is_err());
+    }
+}
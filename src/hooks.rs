@@ -0,0 +1,266 @@
+//! # Git Hooks
+//!
+//! This module installs the git hooks that keep nishiogi's caches fresh without a
+//! watcher: after a branch switch or a merge, `nishiogi warm --background` recomputes the
+//! tree and symbol index (see [`crate::warm`]).
+//!
+//! Hooks the repository already has are kept. nishiogi's lines are appended between two
+//! marker comments, so uninstalling removes exactly them and deletes the hook file only if
+//! nothing else is left in it.
+
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::git::{git, GitError};
+
+/// First line of the block nishiogi adds to a hook.
+const BEGIN_MARKER: &str = "# >>> nishiogi: refresh caches >>>";
+
+/// Last line of the block nishiogi adds to a hook.
+const END_MARKER: &str = "# <<< nishiogi <<<";
+
+/// Errors that can occur while installing or removing hooks.
+#[derive(Debug)]
+pub enum HookError {
+    /// The hooks directory could not be determined.
+    Git(GitError),
+    /// A hook file could not be read or written.
+    Io(PathBuf, io::Error),
+    /// An existing hook is not a shell script, so nishiogi's lines cannot be added to it.
+    NotShellScript(PathBuf),
+}
+
+impl fmt::Display for HookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookError::Git(err) => write!(f, "Failed to find the hooks directory: {err}"),
+            HookError::Io(path, err) => write!(f, "Failed to access {}: {err}", path.display()),
+            HookError::NotShellScript(path) => write!(
+                f,
+                "{} is not a shell script; add `nishiogi warm --background` to it by hand",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for HookError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HookError::Git(err) => Some(err),
+            HookError::Io(_, err) => Some(err),
+            HookError::NotShellScript(_) => None,
+        }
+    }
+}
+
+/// The hooks nishiogi can install.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    /// Runs after `git checkout` and `git switch`.
+    PostCheckout,
+    /// Runs after `git merge` and `git pull`.
+    PostMerge,
+}
+
+impl Hook {
+    /// Returns the hook's file name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::PostCheckout => "post-checkout",
+            Hook::PostMerge => "post-merge",
+        }
+    }
+
+    /// Returns the lines nishiogi adds to the hook, markers included.
+    fn block(self) -> String {
+        let warm =
+            "command -v nishiogi >/dev/null 2>&1 && nishiogi warm --background >/dev/null 2>&1";
+        let body = match self {
+            // The third argument is 0 when only files were checked out
+            Hook::PostCheckout => format!("if [ \"$3\" = 1 ]; then\n    {warm}\nfi"),
+            Hook::PostMerge => warm.to_string(),
+        };
+        format!("{BEGIN_MARKER}\n{body}\n{END_MARKER}\n")
+    }
+}
+
+/// What [`install`] and [`uninstall`] did to a hook file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The hook file was created.
+    Created,
+    /// nishiogi's lines were added to or removed from an existing hook.
+    Updated,
+    /// The hook file was deleted, since only nishiogi's lines were in it.
+    Removed,
+    /// Nothing needed to change.
+    Unchanged,
+}
+
+/// Returns the hooks directory of the repository containing `dir`, honoring
+/// `core.hooksPath`.
+///
+/// # Errors
+///
+/// Returns `HookError::Git` if git fails, for example outside a repository.
+pub fn hooks_dir(dir: &Path) -> Result<PathBuf, HookError> {
+    let path = git(dir, &["rev-parse", "--git-path", "hooks"]).map_err(HookError::Git)?;
+    Ok(dir.join(path.trim()))
+}
+
+/// Adds nishiogi's lines to `hook` in `hooks_dir`, creating the hook if needed.
+///
+/// # Errors
+///
+/// Returns `HookError::NotShellScript` if the existing hook uses another interpreter, or
+/// `HookError::Io` if it cannot be written.
+pub fn install(hooks_dir: &Path, hook: Hook) -> Result<Outcome, HookError> {
+    let path = hooks_dir.join(hook.name());
+    let existing = read_hook(&path)?;
+    let (text, outcome) = match &existing {
+        Some(text) if text.contains(BEGIN_MARKER) => return Ok(Outcome::Unchanged),
+        Some(text) if !is_shell_script(text) => return Err(HookError::NotShellScript(path)),
+        Some(text) => {
+            let separator = if text.ends_with('\n') { "\n" } else { "\n\n" };
+            (
+                format!("{text}{separator}{}", hook.block()),
+                Outcome::Updated,
+            )
+        }
+        None => (format!("#!/bin/sh\n{}", hook.block()), Outcome::Created),
+    };
+    fs::create_dir_all(hooks_dir).map_err(|err| HookError::Io(hooks_dir.to_path_buf(), err))?;
+    fs::write(&path, text).map_err(|err| HookError::Io(path.clone(), err))?;
+    make_executable(&path)?;
+    Ok(outcome)
+}
+
+/// Removes nishiogi's lines from `hook` in `hooks_dir`, deleting the hook if nothing else
+/// is left in it.
+///
+/// # Errors
+///
+/// Returns `HookError::Io` if the hook cannot be read, written, or deleted.
+pub fn uninstall(hooks_dir: &Path, hook: Hook) -> Result<Outcome, HookError> {
+    let path = hooks_dir.join(hook.name());
+    let Some(text) = read_hook(&path)? else {
+        return Ok(Outcome::Unchanged);
+    };
+    let Some(remaining) = remove_block(&text) else {
+        return Ok(Outcome::Unchanged);
+    };
+    let is_empty = remaining
+        .lines()
+        .all(|line| line.trim().is_empty() || line.starts_with("#!"));
+    if is_empty {
+        fs::remove_file(&path).map_err(|err| HookError::Io(path, err))?;
+        Ok(Outcome::Removed)
+    } else {
+        fs::write(&path, remaining).map_err(|err| HookError::Io(path, err))?;
+        Ok(Outcome::Updated)
+    }
+}
+
+/// Returns `text` without nishiogi's block, or `None` if it has none.
+fn remove_block(text: &str) -> Option<String> {
+    let start = text.find(BEGIN_MARKER)?;
+    let end = text[start..].find(END_MARKER)? + start + END_MARKER.len();
+    let before = text[..start].trim_end_matches('\n');
+    let after = text[end..].trim_start_matches('\n');
+    Some(match (before.is_empty(), after.is_empty()) {
+        (true, _) => after.to_string(),
+        (false, true) => format!("{before}\n"),
+        (false, false) => format!("{before}\n\n{after}"),
+    })
+}
+
+fn read_hook(path: &Path) -> Result<Option<String>, HookError> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(HookError::Io(path.to_path_buf(), err)),
+    }
+}
+
+/// Returns whether a hook runs with a POSIX shell, so shell lines can be appended.
+fn is_shell_script(text: &str) -> bool {
+    let Some(shebang) = text.lines().next().and_then(|line| line.strip_prefix("#!")) else {
+        // git runs hooks without a shebang with sh
+        return true;
+    };
+    let interpreter = shebang
+        .split_whitespace()
+        .find(|word| *word != "/usr/bin/env" && !word.starts_with('-'))
+        .and_then(|word| word.rsplit('/').next())
+        .unwrap_or_default();
+    matches!(interpreter, "sh" | "bash" | "dash" | "zsh" | "ksh")
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<(), HookError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+        .map_err(|err| HookError::Io(path.to_path_buf(), err))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<(), HookError> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_install_and_uninstall() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let hooks = dir.path().join("hooks");
+
+        assert_eq!(
+            install(&hooks, Hook::PostMerge).expect("Failed to install"),
+            Outcome::Created
+        );
+        assert_eq!(
+            install(&hooks, Hook::PostMerge).expect("Failed to reinstall"),
+            Outcome::Unchanged
+        );
+        assert_eq!(
+            uninstall(&hooks, Hook::PostMerge).expect("Failed to uninstall"),
+            Outcome::Removed
+        );
+        assert!(!hooks.join("post-merge").exists());
+
+        // An existing hook keeps its own lines
+        let path = hooks.join("post-checkout");
+        fs::write(&path, "#!/usr/bin/env bash\nnpm install\n").expect("Failed to write hook");
+        assert_eq!(
+            install(&hooks, Hook::PostCheckout).expect("Failed to install"),
+            Outcome::Updated
+        );
+        let text = fs::read_to_string(&path).expect("Failed to read hook");
+        assert!(text.starts_with("#!/usr/bin/env bash\nnpm install\n\n# >>> nishiogi"));
+        assert!(text.contains("\"$3\" = 1"));
+        assert_eq!(
+            uninstall(&hooks, Hook::PostCheckout).expect("Failed to uninstall"),
+            Outcome::Updated
+        );
+        assert_eq!(
+            fs::read_to_string(&path).expect("Failed to read hook"),
+            "#!/usr/bin/env bash\nnpm install\n"
+        );
+
+        fs::write(&path, "#!/usr/bin/env python3\nprint()\n").expect("Failed to write hook");
+        assert!(matches!(
+            install(&hooks, Hook::PostCheckout),
+            Err(HookError::NotShellScript(_))
+        ));
+    }
+}
@@ -0,0 +1,326 @@
+//! # Impact Analysis
+//!
+//! This module implements `nishiogi impact`, which answers "what would break if I changed
+//! this?" for a file or a symbol before the change is made. From the symbol index it lists:
+//!
+//! - **Dependents**: the places outside tests that mention each affected symbol
+//! - **Affected tests**: mentions in test files (see [`crate::usage::is_example_path`]) and
+//!   in Rust `#[cfg(test)]` modules, i.e. the tests worth running after the change
+//! - **Public API exposure**: which of the symbols are visible outside their module
+//!
+//! The model then writes a short risk summary from that listing. The index matches names
+//! only, so a common name such as `new` collects the mentions of every `new`; the summary
+//! prompt says so, and the listing is meant to be skimmed rather than trusted blindly.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    agent::AgentError,
+    github_copilot_client::Message,
+    review::ReviewModel,
+    symbols::{Reference, Symbol, SymbolIndex},
+    usage::is_example_path,
+};
+
+/// Upper bound on the dependents and tests listed per symbol.
+const MAX_MENTIONS: usize = 50;
+
+/// A symbol the change touches and where it is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolImpact {
+    /// The symbol's definition.
+    pub symbol: Symbol,
+    /// Mentions outside tests and outside the definition.
+    pub dependents: Vec<Reference>,
+    /// Mentions in tests.
+    pub tests: Vec<Reference>,
+}
+
+/// What a change to a file or symbol would affect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImpactReport {
+    /// The file or symbol as given.
+    pub target: String,
+    /// The symbols defined in the file, or the definitions of the symbol.
+    pub symbols: Vec<SymbolImpact>,
+}
+
+impl ImpactReport {
+    /// Returns the files with a dependent, excluding the definitions' own files.
+    pub fn dependent_files(&self) -> Vec<&Path> {
+        let mut files: Vec<&Path> = self
+            .symbols
+            .iter()
+            .flat_map(|affected| {
+                affected
+                    .dependents
+                    .iter()
+                    .filter(|site| site.path != affected.symbol.path)
+                    .map(|site| site.path.as_path())
+            })
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// Returns the files containing affected tests.
+    pub fn test_files(&self) -> Vec<&Path> {
+        let mut files: Vec<&Path> = self
+            .symbols
+            .iter()
+            .flat_map(|affected| affected.tests.iter().map(|site| site.path.as_path()))
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+}
+
+/// Lists what a change to `target` would affect, or returns `None` if it names neither a
+/// file under `root` nor an indexed symbol.
+///
+/// A file target covers every symbol defined in it outside its unit tests.
+pub fn analyze(root: &Path, index: &SymbolIndex, target: &str) -> Option<ImpactReport> {
+    let file = Path::new(target);
+    let file = file.strip_prefix(root).unwrap_or(file);
+    let definitions: Vec<&Symbol> = if root.join(file).is_file() {
+        // The file's own tests are not part of what the change affects
+        let tests_start = inline_tests_start(&root.join(file));
+        index
+            .symbols()
+            .iter()
+            .filter(|symbol| {
+                symbol.path == file && tests_start.is_none_or(|start| symbol.line < start)
+            })
+            .collect()
+    } else {
+        index.definitions(target)
+    };
+    if definitions.is_empty() {
+        return None;
+    }
+
+    let mut test_starts: HashMap<PathBuf, Option<usize>> = HashMap::new();
+    let symbols = definitions
+        .into_iter()
+        .map(|symbol| {
+            let (mut tests, mut dependents): (Vec<Reference>, Vec<Reference>) = index
+                .references(&symbol.name)
+                .into_iter()
+                .partition(|site| {
+                    let start = test_starts
+                        .entry(site.path.clone())
+                        .or_insert_with(|| inline_tests_start(&root.join(&site.path)));
+                    is_example_path(&site.path) || start.is_some_and(|start| site.line >= start)
+                });
+            dependents.truncate(MAX_MENTIONS);
+            tests.truncate(MAX_MENTIONS);
+            SymbolImpact {
+                symbol: symbol.clone(),
+                dependents,
+                tests,
+            }
+        })
+        .collect();
+    Some(ImpactReport {
+        target: target.to_string(),
+        symbols,
+    })
+}
+
+/// Returns the line of the first `#[cfg(test)]` attribute of a Rust file, after which
+/// its unit tests live.
+fn inline_tests_start(path: &Path) -> Option<usize> {
+    if path.extension().is_none_or(|extension| extension != "rs") {
+        return None;
+    }
+    let content = fs::read_to_string(path).ok()?;
+    content
+        .lines()
+        .position(|line| line.trim() == "#[cfg(test)]")
+        .map(|index| index + 1)
+}
+
+/// Renders the report as plain text.
+pub fn render_report(report: &ImpactReport) -> String {
+    let mut output = format!("Impact of changing {}\n", report.target);
+    for affected in &report.symbols {
+        let symbol = &affected.symbol;
+        let exposure = if symbol.public { "public" } else { "private" };
+        let _ = writeln!(
+            output,
+            "\n{} {} ({}:{}), {exposure}",
+            symbol.kind,
+            symbol.name,
+            symbol.path.display(),
+            symbol.line
+        );
+        render_mentions(&mut output, "Dependents", &affected.dependents);
+        render_mentions(&mut output, "Tests", &affected.tests);
+    }
+
+    let public: Vec<&str> = report
+        .symbols
+        .iter()
+        .filter(|affected| affected.symbol.public)
+        .map(|affected| affected.symbol.name.as_str())
+        .collect();
+    let _ = writeln!(
+        output,
+        "\nSummary: {} symbol(s), {} public; {} dependent file(s); {} test file(s)",
+        report.symbols.len(),
+        public.len(),
+        report.dependent_files().len(),
+        report.test_files().len()
+    );
+    if !public.is_empty() {
+        let _ = writeln!(output, "Public API: {}", public.join(", "));
+    }
+    output
+}
+
+/// Renders mentions grouped by file, with their line numbers.
+fn render_mentions(output: &mut String, heading: &str, mentions: &[Reference]) {
+    if mentions.is_empty() {
+        let _ = writeln!(output, "  {heading}: none");
+        return;
+    }
+    let mut by_file: BTreeMap<&Path, Vec<usize>> = BTreeMap::new();
+    for mention in mentions {
+        by_file.entry(&mention.path).or_default().push(mention.line);
+    }
+    let _ = writeln!(output, "  {heading}:");
+    for (path, lines) in by_file {
+        let lines: Vec<String> = lines.iter().map(ToString::to_string).collect();
+        let _ = writeln!(output, "    {}:{}", path.display(), lines.join(","));
+    }
+}
+
+/// Asks the model for a risk summary of the change described by `report`.
+///
+/// The source lines of the dependents are included so the model can tell real uses from
+/// name clashes.
+///
+/// # Errors
+///
+/// Returns the model's `AgentError`.
+pub async fn assess_risk(
+    model: &mut dyn ReviewModel,
+    root: &Path,
+    report: &ImpactReport,
+) -> Result<String, AgentError> {
+    let mut sources: HashMap<&Path, Option<String>> = HashMap::new();
+    let mut call_sites = String::new();
+    for affected in &report.symbols {
+        for site in &affected.dependents {
+            let content = sources
+                .entry(&site.path)
+                .or_insert_with(|| fs::read_to_string(root.join(&site.path)).ok());
+            if let Some(line) = content
+                .as_deref()
+                .and_then(|content| content.lines().nth(site.line - 1))
+            {
+                let _ = writeln!(
+                    call_sites,
+                    "{}:{}: {}",
+                    site.path.display(),
+                    site.line,
+                    line.trim()
+                );
+            }
+        }
+    }
+
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: "You assess the risk of changing code, for an engineer about to make the change. The listing comes from an index that matches names only, so some mentions may belong to other items with the same name.".to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!(
+                "{}\nSource lines of the dependents:\n\n{call_sites}\nWrite a short risk summary: start with \"Risk: low\", \"Risk: medium\", or \"Risk: high\", then say what would most likely break, whether callers outside the repository could be affected through the public API, and which tests to run.",
+                render_report(report)
+            ),
+        },
+    ];
+    model.complete(messages).await
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    struct ScriptedModel {
+        prompt: String,
+    }
+
+    #[async_trait]
+    impl ReviewModel for ScriptedModel {
+        async fn complete(&mut self, messages: Vec<Message>) -> Result<String, AgentError> {
+            self.prompt = messages[1].content.clone();
+            Ok("Risk: medium".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_impact() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let root = dir.path();
+        fs::create_dir_all(root.join("src")).expect("Failed to create src");
+        fs::create_dir_all(root.join("tests")).expect("Failed to create tests");
+        fs::write(
+            root.join("src/net.rs"),
+            "pub fn fetch() {}\nfn helper() {}\n\n#[cfg(test)]\nmod tests {\n    fn test_fetch() { super::fetch(); }\n}\n",
+        )
+        .expect("Failed to write net");
+        fs::write(
+            root.join("src/main.rs"),
+            "fn main() {\n    net::fetch();\n}\n",
+        )
+        .expect("Failed to write main");
+        fs::write(root.join("tests/net.rs"), "fn it() { fetch(); }\n")
+            .expect("Failed to write test");
+        let index = SymbolIndex::build(root);
+
+        assert!(analyze(root, &index, "missing").is_none());
+        let report = analyze(root, &index, "fetch").expect("Symbol not found");
+        assert_eq!(report.symbols.len(), 1);
+        let affected = &report.symbols[0];
+        assert_eq!(
+            affected.dependents,
+            [Reference {
+                path: PathBuf::from("src/main.rs"),
+                line: 2
+            }]
+        );
+        assert_eq!(
+            report.test_files(),
+            [Path::new("src/net.rs"), Path::new("tests/net.rs")]
+        );
+
+        let by_file = analyze(root, &index, "src/net.rs").expect("File not found");
+        assert_eq!(by_file.symbols.len(), 2);
+        let rendered = render_report(&by_file);
+        assert!(rendered.contains("function helper (src/net.rs:2), private"));
+        assert!(rendered.contains("Public API: fetch"));
+
+        let mut model = ScriptedModel {
+            prompt: String::new(),
+        };
+        let summary = assess_risk(&mut model, root, &report)
+            .await
+            .expect("Failed to assess");
+        assert_eq!(summary, "Risk: medium");
+        assert!(model.prompt.contains("src/main.rs:2: net::fetch();"));
+    }
+}
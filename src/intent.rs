@@ -0,0 +1,352 @@
+//! # Question Types
+//!
+//! This module classifies questions so each kind is answered with a preset suited to it,
+//! instead of one generic flow for all of them:
+//!
+//! | Type         | Example                               | Preferred commands              |
+//! |--------------|---------------------------------------|---------------------------------|
+//! | `locate`     | "Where is the login handler?"         | `grep`, `search`, `routes`, ... |
+//! | `explain`    | "How does the cache invalidate?"      | `show_file`, `search`           |
+//! | `compare`    | "How do `Foo` and `Bar` differ?"      | `show_file`, `show_signatures`  |
+//! | `history`    | "Why was retrying added?"             | `blame`, `owners`               |
+//! | `how_to_run` | "How do I run the integration tests?" | `makefiles`, `workflows`, ...   |
+//! | `debug`      | "Why does startup panic with X?"      | `search`, `show_file`, `run`    |
+//!
+//! The planning step asks the model for the type and the paths of interest in the same
+//! reply as the plan ([`INTENT_PROMPT`]), so classifying costs no extra call. Its prompt
+//! tells how to plan for each type ([`QuestionType::planner_guidance`]), which keeps cheap
+//! questions such as `locate` from reading whole files. The reply is read into a
+//! [`PlanReply`], whose [`Intent`] is logged and kept with the query. When the reply names
+//! no type, [`QuestionType::guess`] classifies the question by its wording. The type then
+//! adds [`QuestionType::answer_guidance`] to the answer prompt.
+
+use std::fmt;
+
+use serde::Deserialize;
+
+/// Asks the planner for the question type and the paths the question concerns, along with
+/// the plan.
+pub const INTENT_PROMPT: &str = "Classify the question as one of the types above and identify what directories and files it concerns, then create a plan of what commands to run. Respond with a single JSON object in this format:\n\n{\"intent\": {\"type\": \"explain\", \"tree\": [\"src\"], \"show_file\": [\"src/main.rs\"]}, \"plan\": [\"tree src\", \"show_file src/main.rs\"]}\n\nThe plan is a JSON array of commands. Independent commands run concurrently; if a command must wait for others, write it as an object naming them, like {\"id\": \"main\", \"command\": \"show_file src/main.rs\", \"after\": [\"1\"]}. Commands without an id are identified by their 1-based position.";
+
+/// The kind of question being asked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuestionType {
+    /// Where something is defined, declared, or handled.
+    Locate,
+    /// How or why code works.
+    #[default]
+    Explain,
+    /// How two or more things differ.
+    Compare,
+    /// Why or when code changed, and who changed it.
+    History,
+    /// How to build, test, run, or deploy the project.
+    HowToRun,
+    /// What causes an error or unexpected behavior.
+    Debug,
+}
+
+impl fmt::Display for QuestionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            QuestionType::Locate => "locate",
+            QuestionType::Explain => "explain",
+            QuestionType::Compare => "compare",
+            QuestionType::History => "history",
+            QuestionType::HowToRun => "how_to_run",
+            QuestionType::Debug => "debug",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// What a question is after, as interpreted by the planner in reply to [`INTENT_PROMPT`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Intent {
+    /// The kind of question, if the reply named a known one.
+    #[serde(rename = "type", default, deserialize_with = "known_type")]
+    pub question_type: Option<QuestionType>,
+    /// Directories the question concerns.
+    #[serde(default)]
+    pub tree: Vec<String>,
+    /// Files the question concerns.
+    #[serde(default)]
+    pub show_file: Vec<String>,
+}
+
+impl fmt::Display for Intent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.question_type {
+            Some(kind) => write!(f, "type {kind}")?,
+            None => write!(f, "no type")?,
+        }
+        if !self.tree.is_empty() {
+            write!(f, ", directories {}", self.tree.join(", "))?;
+        }
+        if !self.show_file.is_empty() {
+            write!(f, ", files {}", self.show_file.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The planner's reply to [`INTENT_PROMPT`]: the intent it read in the question and its plan.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlanReply {
+    /// The intent, if the reply held a readable one.
+    pub intent: Option<Intent>,
+    /// The JSON text of the plan, for [`parse_plan`](crate::plan::parse_plan).
+    pub plan: String,
+}
+
+impl PlanReply {
+    /// Reads the reply, which may surround the JSON object with prose or a code fence.
+    ///
+    /// A reply that is not such an object, such as a bare array of commands, is taken as
+    /// the plan alone.
+    pub fn parse(reply: &str) -> Self {
+        /// The object requested by [`INTENT_PROMPT`].
+        #[derive(Deserialize)]
+        struct Raw {
+            intent: Option<serde_json::Value>,
+            plan: serde_json::Value,
+        }
+
+        let raw = match (reply.find('{'), reply.rfind('}')) {
+            (Some(start), Some(end)) if start < end => {
+                serde_json::from_str::<Raw>(&reply[start..=end]).ok()
+            }
+            _ => None,
+        };
+        match raw {
+            Some(raw) => PlanReply {
+                // An unreadable intent does not discard the plan
+                intent: raw
+                    .intent
+                    .and_then(|intent| Intent::deserialize(intent).ok()),
+                plan: raw.plan.to_string(),
+            },
+            None => PlanReply {
+                intent: None,
+                plan: reply.to_string(),
+            },
+        }
+    }
+}
+
+/// Reads a question type, treating unknown types as missing.
+fn known_type<'de, D>(deserializer: D) -> Result<Option<QuestionType>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    Ok(QuestionType::deserialize(value).ok())
+}
+
+impl QuestionType {
+    /// Every type, in the order the planner is told about them.
+    pub const ALL: [QuestionType; 6] = [
+        QuestionType::Locate,
+        QuestionType::Explain,
+        QuestionType::Compare,
+        QuestionType::History,
+        QuestionType::HowToRun,
+        QuestionType::Debug,
+    ];
+
+    /// Returns the type named by `intent`, or else guesses it from `question`.
+    pub fn classify(intent: Option<&Intent>, question: &str) -> Self {
+        intent
+            .and_then(|intent| intent.question_type)
+            .unwrap_or_else(|| Self::guess(question))
+    }
+
+    /// Guesses the type of `question` from its wording.
+    pub fn guess(question: &str) -> Self {
+        let question = question.to_lowercase();
+        let mentions = |words: &[&str]| words.iter().any(|word| question.contains(word));
+        if mentions(&[
+            "error",
+            "panic",
+            "crash",
+            "fails",
+            "failing",
+            "bug",
+            "broken",
+            "doesn't work",
+            "does not work",
+            "exception",
+        ]) {
+            QuestionType::Debug
+        } else if mentions(&["differ", "difference", " vs ", "versus", "compare"]) {
+            QuestionType::Compare
+        } else if mentions(&[
+            "who ",
+            "when was",
+            "why was",
+            "history",
+            "changed",
+            "introduced",
+        ]) {
+            QuestionType::History
+        } else if mentions(&[
+            "how do i run",
+            "how to run",
+            "how do i build",
+            "how to build",
+            "how do i test",
+            "run the tests",
+            "deploy",
+            "install",
+        ]) {
+            QuestionType::HowToRun
+        } else if question.starts_with("where") || mentions(&["which file", "defined", "located"]) {
+            QuestionType::Locate
+        } else {
+            QuestionType::Explain
+        }
+    }
+
+    /// Returns the commands best suited to the type, best first.
+    pub fn preferred_tools(self) -> &'static [&'static str] {
+        match self {
+            QuestionType::Locate => &["grep", "search", "routes", "tree"],
+            QuestionType::Explain => &["show_file", "search"],
+            QuestionType::Compare => &["show_file", "show_signatures"],
+            QuestionType::History => &["blame", "owners"],
+            QuestionType::HowToRun => &["makefiles", "workflows", "containers", "show_file"],
+            QuestionType::Debug => &["search", "show_file", "run"],
+        }
+    }
+
+    /// Returns the planning instructions for every type, recommending only the commands
+    /// `allowed` accepts.
+    ///
+    /// The planner classifies the question in the same reply as its plan, so it is told how
+    /// to plan for each type rather than for one.
+    pub fn planner_guidance(allowed: impl Fn(&str) -> bool) -> String {
+        let mut guidance = "Plan according to the type of the question:".to_string();
+        for kind in Self::ALL {
+            let approach = match kind {
+                QuestionType::Locate => {
+                    "where something is defined or handled. Plan few commands and avoid reading whole files; the answer only needs paths and line numbers."
+                }
+                QuestionType::Explain => {
+                    "how or why code works. Read the code implementing the subject and the code it calls."
+                }
+                QuestionType::Compare => {
+                    "differences between two or more things. Read each of the compared items, equally thoroughly."
+                }
+                QuestionType::History => {
+                    "why or when code changed, who wrote it. Blame the relevant files to find the commits and pull requests behind the code."
+                }
+                QuestionType::HowToRun => {
+                    "building, testing, running, or deploying the project. Read the build and CI configuration and the README or contributing guide rather than source code."
+                }
+                QuestionType::Debug => {
+                    "the cause of an error, crash, or unexpected behavior. Search for the error message or the failing behavior, then read the code producing it."
+                }
+            };
+            let tools: Vec<&str> = kind
+                .preferred_tools()
+                .iter()
+                .copied()
+                .filter(|tool| allowed(tool))
+                .collect();
+            guidance.push_str(&format!(
+                "\n- \"{kind}\": {approach} Prefer these commands: {}.",
+                tools.join(", ")
+            ));
+        }
+        guidance
+    }
+
+    /// Returns the answering instructions for the type.
+    pub fn answer_guidance(self) -> &'static str {
+        match self {
+            QuestionType::Locate => {
+                "Lead with the locations (file and line) and keep the explanation to a sentence or two."
+            }
+            QuestionType::Explain => {
+                "Explain how the code works step by step, following the flow of control."
+            }
+            QuestionType::Compare => {
+                "Compare the items side by side, for example in a table, covering both similarities and differences."
+            }
+            QuestionType::History => {
+                "Explain how and why the code came to be, citing the commits and pull requests."
+            }
+            QuestionType::HowToRun => {
+                "Give the exact commands to run, in order, with any prerequisites first."
+            }
+            QuestionType::Debug => {
+                "State the most likely cause first with the evidence for it, then how to fix it."
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let reply = PlanReply::parse(
+            "Sure: {\"intent\": {\"type\": \"how_to_run\", \"tree\": [\".\"], \"show_file\": []}, \"plan\": [\"makefiles\"]}",
+        );
+        assert_eq!(
+            QuestionType::classify(reply.intent.as_ref(), "What do I need?"),
+            QuestionType::HowToRun
+        );
+        assert_eq!(reply.plan, "[\"makefiles\"]");
+        // Replies without a type fall back to the question's wording
+        let reply = PlanReply::parse("{\"intent\": {\"tree\": [\"src\"]}, \"plan\": []}");
+        assert_eq!(
+            QuestionType::classify(reply.intent.as_ref(), "Where is the login handler?"),
+            QuestionType::Locate
+        );
+        assert_eq!(
+            QuestionType::classify(None, "Why does startup panic?"),
+            QuestionType::Debug
+        );
+        assert_eq!(
+            QuestionType::guess("How does Foo differ from Bar?"),
+            QuestionType::Compare
+        );
+        assert_eq!(
+            QuestionType::guess("Why was retrying introduced?"),
+            QuestionType::History
+        );
+        assert_eq!(
+            QuestionType::guess("How do I run the integration tests?"),
+            QuestionType::HowToRun
+        );
+        assert_eq!(
+            QuestionType::guess("How does the cache work?"),
+            QuestionType::Explain
+        );
+        // An unknown type does not discard the paths
+        let reply = PlanReply::parse(
+            "```json\n{\"intent\": {\"type\": \"refactor\", \"tree\": [\"src\"], \"show_file\": [\"src/agent.rs\"]}, \"plan\": [\"tree src\"]}\n```",
+        );
+        let intent = reply.intent.expect("Reply holds an intent");
+        assert_eq!(intent.question_type, None);
+        assert_eq!(
+            intent.to_string(),
+            "no type, directories src, files src/agent.rs"
+        );
+        // A bare plan is read as the plan alone
+        let bare = "[{\"id\": \"a\", \"command\": \"tree src\"}]";
+        assert_eq!(
+            PlanReply::parse(bare),
+            PlanReply {
+                intent: None,
+                plan: bare.to_string()
+            }
+        );
+        assert!(QuestionType::planner_guidance(|tool| tool != "run")
+            .ends_with("\n- \"debug\": the cause of an error, crash, or unexpected behavior. Search for the error message or the failing behavior, then read the code producing it. Prefer these commands: search, show_file."));
+    }
+}
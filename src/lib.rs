@@ -1,4 +1,145 @@
+//! nishiogi answers questions about code repositories with GitHub Copilot, Claude, or OpenAI
+//! models.
+//!
+//! The crate is split by cargo features so embedders only build what they use:
+//!
+//! - The core, always available, holds everything that needs neither a filesystem nor
+//!   child processes: plan parsing, planner and policy configuration, prompts, review
+//!   strategies, token estimation and the provider clients, which only make HTTP requests.
+//!   It compiles to `wasm32-unknown-unknown`, where reqwest sends requests through `fetch`,
+//!   so a browser extension can drive the same engine over files it fetched itself.
+//! - `native` adds the modules that read the working tree and run git and the other tools:
+//!   the agent loop, the pipeline and the code-changing commands.
+//! - `sessions` adds the SQLite database storing sessions and the index, and `doctor`,
+//!   which inspects it. SQLite is compiled from source, so this is the heaviest feature.
+//! - `server` adds the async runtime and the HTTP server of `nishiogi serve`.
+//! - `outline` adds the tree-sitter parsers behind the `outline` command, whose grammars
+//!   are compiled from source, and loads the grammars installed with `nishiogi grammars`.
+//! - `cli` adds the command line interface and builds the `nishiogi` binary. It enables all
+//!   the others.
+//!
+//! No feature is enabled by default. Library consumers embedding the agent loop depend on
+//! the crate with `features = ["native"]`, and the binary is installed with
+//! `cargo install nishiogi --features cli`.
+
+#[cfg(feature = "native")]
+pub mod ab;
+#[cfg(feature = "native")]
 pub mod agent;
-mod github_copilot_client;
+#[cfg(feature = "native")]
+pub mod anonymize;
+#[cfg(feature = "native")]
+pub mod audit;
+#[cfg(feature = "native")]
+mod blame;
+#[cfg(feature = "native")]
+mod build_config;
+pub mod citation;
+pub mod claude_client;
+pub mod commit;
+#[cfg(feature = "native")]
+pub mod compare;
+#[cfg(feature = "native")]
+pub mod config;
+#[cfg(feature = "native")]
+mod containers;
+#[cfg(feature = "native")]
+mod coverage;
+#[cfg(feature = "sessions")]
+pub mod db;
+#[cfg(feature = "native")]
+mod diff;
+#[cfg(feature = "native")]
+pub mod docgen;
+#[cfg(feature = "sessions")]
+pub mod doctor;
+#[cfg(feature = "native")]
+pub mod editor;
+#[cfg(feature = "native")]
+mod elide;
+pub mod error;
+pub mod follow_up;
+#[cfg(feature = "native")]
+pub mod git;
+pub mod github_copilot_client;
+#[cfg(feature = "native")]
+pub mod glossary;
+#[cfg(feature = "outline")]
+pub mod grammars;
+#[cfg(feature = "native")]
+pub mod hooks;
+#[cfg(feature = "native")]
+pub mod impact;
+pub mod intent;
+#[cfg(feature = "native")]
+mod licenses;
+#[cfg(feature = "native")]
+pub mod memory;
+#[cfg(feature = "native")]
+pub mod migrate;
+pub mod openai_client;
+pub mod org_policy;
+#[cfg(feature = "outline")]
+pub mod outline;
+pub mod overrides;
+#[cfg(feature = "native")]
+mod owners;
+#[cfg(feature = "native")]
+pub mod patch;
+#[cfg(feature = "native")]
+mod pattern_check;
+#[cfg(feature = "native")]
+pub mod pipeline;
+pub mod plan;
+pub mod planner;
+pub mod policy;
+pub mod provenance;
+pub mod provider;
+#[cfg(feature = "native")]
+pub mod refactor;
+#[cfg(feature = "native")]
+mod relevance;
+#[cfg(feature = "native")]
+pub mod report;
+pub mod review;
+#[cfg(feature = "native")]
+pub mod root_sandbox;
+#[cfg(feature = "native")]
+mod routes;
+#[cfg(feature = "native")]
+mod sandbox;
+pub mod scheduler;
+#[cfg(feature = "native")]
+mod search;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "native")]
+pub mod session;
+#[cfg(feature = "native")]
+pub mod setup;
+#[cfg(feature = "native")]
 mod show_file;
+#[cfg(feature = "native")]
+mod snippet;
+pub mod style;
+#[cfg(feature = "native")]
+pub mod symbols;
+pub mod template;
+#[cfg(feature = "server")]
+pub mod tenant;
+pub mod tokens;
+#[cfg(feature = "native")]
+pub mod tools;
+#[cfg(feature = "native")]
+pub mod transcript;
+#[cfg(feature = "native")]
 mod tree;
+pub mod unknown;
+#[cfg(feature = "native")]
+pub mod unused;
+#[cfg(feature = "native")]
+mod usage;
+#[cfg(feature = "sessions")]
+pub mod warm;
+#[cfg(feature = "native")]
+pub mod workspace;
@@ -0,0 +1,328 @@
+//! # License Inventory
+//!
+//! This module implements the `licenses` tool, which lists the licenses declared across a
+//! repository so questions such as "are there any GPL dependencies?" can be answered from
+//! one compact listing instead of reading every manifest. Two kinds of sources are read:
+//!
+//! - **Manifests**: the `license` field of `Cargo.toml`, `package.json`, and
+//!   `pyproject.toml`, an SPDX expression such as `MIT OR Apache-2.0`
+//! - **License files**: `LICENSE`, `COPYING`, and `NOTICE` files (with any extension),
+//!   identified from their text by the phrases of common licenses
+//!
+//! Sources under vendored directories (`vendor`, `third_party`, ...) are marked as such.
+//! Ignored files are skipped, so dependencies installed by a package manager (for example
+//! `node_modules`) are only listed when they are committed.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{search::collect_files, tree::find_gitignore_patterns};
+
+/// Directories holding vendored third-party code.
+const VENDOR_DIRS: [&str; 6] = [
+    "vendor",
+    "third_party",
+    "third-party",
+    "node_modules",
+    "external",
+    "deps",
+];
+
+/// License families whose terms require derived work to use the same license.
+const COPYLEFT: [&str; 8] = ["GPL", "LGPL", "AGPL", "MPL", "EPL", "EUPL", "CDDL", "OSL"];
+
+/// Phrases identifying license texts, with their SPDX identifier; earlier entries win.
+const LICENSE_PHRASES: [(&str, &str); 12] = [
+    ("GNU AFFERO GENERAL PUBLIC LICENSE", "AGPL-3.0"),
+    ("GNU LESSER GENERAL PUBLIC LICENSE", "LGPL"),
+    ("GNU LIBRARY GENERAL PUBLIC LICENSE", "LGPL-2.0"),
+    ("GNU GENERAL PUBLIC LICENSE", "GPL"),
+    ("Mozilla Public License Version 2.0", "MPL-2.0"),
+    ("Eclipse Public License", "EPL"),
+    ("Apache License", "Apache-2.0"),
+    ("Permission is hereby granted, free of charge", "MIT"),
+    ("Permission to use, copy, modify, and/or distribute", "ISC"),
+    ("Redistribution and use in source and binary forms", "BSD"),
+    ("released into the public domain", "Unlicense"),
+    ("Boost Software License", "BSL-1.0"),
+];
+
+/// Maximum sources listed per license.
+const MAX_SOURCES: usize = 20;
+
+/// Where a license was declared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LicenseSource {
+    /// The manifest or license file, relative to the scanned directory.
+    pub path: PathBuf,
+    /// The license as an SPDX expression, or `"unknown"` for unrecognized license files.
+    pub license: String,
+    /// Whether the source is under a vendored directory.
+    pub vendored: bool,
+}
+
+/// How strongly a license expression requires sharing derived work under its terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Copyleft {
+    /// No copyleft terms.
+    None,
+    /// Copyleft is one of the alternatives, e.g. `MIT OR GPL-2.0`.
+    Optional,
+    /// Every alternative is copyleft.
+    Required,
+}
+
+/// Classifies an SPDX expression by its copyleft terms.
+pub fn copyleft(expression: &str) -> Copyleft {
+    let alternatives: Vec<bool> = expression
+        .split(" OR ")
+        .map(|alternative| {
+            alternative
+                .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                .any(|term| {
+                    let family = term.split(['-', '+']).next().unwrap_or_default();
+                    COPYLEFT.contains(&family)
+                })
+        })
+        .collect();
+    if alternatives.iter().all(|&copyleft| copyleft) {
+        Copyleft::Required
+    } else if alternatives.contains(&true) {
+        Copyleft::Optional
+    } else {
+        Copyleft::None
+    }
+}
+
+/// Finds the licenses declared under `root`, in path order.
+pub fn find_licenses(root: &Path) -> Vec<LicenseSource> {
+    let ignore = find_gitignore_patterns(root).unwrap_or_default();
+    let mut files = Vec::new();
+    collect_files(root, root, &ignore, &mut files);
+
+    let mut sources = Vec::new();
+    for path in files {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let license = match name.as_str() {
+            "Cargo.toml" | "pyproject.toml" | "package.json" => fs::read_to_string(&path)
+                .ok()
+                .and_then(|content| manifest_license(&name, &content)),
+            _ if is_license_file(&name) => fs::read_to_string(&path)
+                .ok()
+                .map(|content| identify_license(&content)),
+            _ => None,
+        };
+        let Some(license) = license else {
+            continue;
+        };
+        let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        let vendored = relative.parent().is_some_and(|dir| {
+            dir.components().any(|component| {
+                VENDOR_DIRS.contains(&component.as_os_str().to_string_lossy().as_ref())
+            })
+        });
+        sources.push(LicenseSource {
+            path: relative,
+            license,
+            vendored,
+        });
+    }
+    sources
+}
+
+/// Returns whether `name` is a license or notice file, such as `LICENSE-MIT` or
+/// `COPYING.txt`, as opposed to source code such as `license.rs`.
+fn is_license_file(name: &str) -> bool {
+    let (stem, extension) = name.split_once('.').unwrap_or((name, ""));
+    let stem = stem.to_ascii_uppercase();
+    ["", "txt", "md", "rst"].contains(&extension.to_ascii_lowercase().as_str())
+        && ["LICENSE", "LICENCE", "COPYING", "NOTICE"]
+            .iter()
+            .any(|prefix| {
+                stem.strip_prefix(prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(['-', '_']))
+            })
+}
+
+/// Returns the license declared in a manifest, if any.
+fn manifest_license(name: &str, content: &str) -> Option<String> {
+    let license = if name == "package.json" {
+        let manifest: serde_json::Value = serde_json::from_str(content).ok()?;
+        match &manifest["license"] {
+            serde_json::Value::String(license) => license.clone(),
+            // The deprecated form, `{"type": "MIT", "url": "..."}`
+            license => license["type"].as_str()?.to_string(),
+        }
+    } else {
+        let manifest: toml::Table = toml::from_str(content).ok()?;
+        let section = if name == "Cargo.toml" {
+            "package"
+        } else {
+            "project"
+        };
+        match manifest.get(section)?.get("license")? {
+            toml::Value::String(license) => license.clone(),
+            // pyproject.toml also allows `license = {text = "MIT"}`
+            license => license.get("text")?.as_str()?.to_string(),
+        }
+    };
+    let license = license.trim();
+    (!license.is_empty()).then(|| license.to_string())
+}
+
+/// Identifies the license of a license file from its text, as an SPDX identifier.
+fn identify_license(content: &str) -> String {
+    let text = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    let lowercase = text.to_lowercase();
+    let Some((_, family)) = LICENSE_PHRASES
+        .iter()
+        .find(|(phrase, _)| lowercase.contains(&phrase.to_lowercase()))
+    else {
+        return "unknown".to_string();
+    };
+    match *family {
+        "GPL" | "LGPL" => {
+            let version = ["3", "2.1", "2"]
+                .iter()
+                .find(|version| text.contains(&format!("Version {version},")));
+            version.map_or_else(
+                || (*family).to_string(),
+                |version| format!("{family}-{}", version_id(version)),
+            )
+        }
+        "BSD" if text.contains("Neither the name") => "BSD-3-Clause".to_string(),
+        "BSD" => "BSD-2-Clause".to_string(),
+        family => family.to_string(),
+    }
+}
+
+/// Returns the SPDX version of a GNU license version, e.g. `2.0` for `2`.
+fn version_id(version: &str) -> String {
+    if version.contains('.') {
+        version.to_string()
+    } else {
+        format!("{version}.0")
+    }
+}
+
+/// Renders the inventory grouped by license, copyleft licenses first.
+pub fn render_licenses(sources: &[LicenseSource]) -> String {
+    if sources.is_empty() {
+        return "No licenses declared in manifests or license files".to_string();
+    }
+    let mut by_license: BTreeMap<(Copyleft, &str), Vec<&LicenseSource>> = BTreeMap::new();
+    for source in sources {
+        by_license
+            .entry((copyleft(&source.license), &source.license))
+            .or_default()
+            .push(source);
+    }
+
+    let mut output = format!(
+        "License inventory ({} source(s), {} vendored):\n",
+        sources.len(),
+        sources.iter().filter(|source| source.vendored).count()
+    );
+    for ((copyleft, license), sources) in by_license.iter().rev() {
+        let note = match copyleft {
+            Copyleft::Required => " [copyleft]",
+            Copyleft::Optional => " [copyleft option]",
+            Copyleft::None => "",
+        };
+        let _ = writeln!(output, "\n{license}{note}:");
+        for source in sources.iter().take(MAX_SOURCES) {
+            let vendored = if source.vendored { " (vendored)" } else { "" };
+            let _ = writeln!(output, "  {}{vendored}", source.path.display());
+        }
+        if sources.len() > MAX_SOURCES {
+            let _ = writeln!(output, "  ... and {} more", sources.len() - MAX_SOURCES);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().expect("Path has a parent"))
+            .expect("Failed to create directory");
+        fs::write(path, content).expect("Failed to write file");
+    }
+
+    #[test]
+    fn test_copyleft() {
+        assert_eq!(copyleft("MIT OR Apache-2.0"), Copyleft::None);
+        assert_eq!(copyleft("MIT OR GPL-2.0-or-later"), Copyleft::Optional);
+        assert_eq!(copyleft("(LGPL-2.1 AND MIT)"), Copyleft::Required);
+        assert_eq!(copyleft("GPL-3.0+"), Copyleft::Required);
+    }
+
+    #[test]
+    fn test_find_licenses() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let root = dir.path();
+        write(
+            root,
+            "Cargo.toml",
+            "[package]\nname = \"app\"\nlicense = \"MIT OR Apache-2.0\"\n",
+        );
+        write(
+            root,
+            "LICENSE-MIT",
+            "MIT License\n\nPermission is hereby granted, free\nof charge, to any person",
+        );
+        write(
+            root,
+            "vendor/readline/COPYING",
+            "GNU GENERAL PUBLIC LICENSE\n   Version 3, 29 June 2007\n",
+        );
+        write(
+            root,
+            "web/package.json",
+            "{\"name\": \"web\", \"license\": {\"type\": \"ISC\"}}",
+        );
+        write(
+            root,
+            "tools/pyproject.toml",
+            "[project]\nlicense = {text = \"BSD-3-Clause\"}\n",
+        );
+        write(root, "src/license.rs", "fn check() {}\n");
+
+        let sources = find_licenses(root);
+        let found: Vec<(&str, &str, bool)> = sources
+            .iter()
+            .map(|source| {
+                (
+                    source.path.to_str().unwrap_or_default(),
+                    source.license.as_str(),
+                    source.vendored,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("Cargo.toml", "MIT OR Apache-2.0", false),
+                ("LICENSE-MIT", "MIT", false),
+                ("tools/pyproject.toml", "BSD-3-Clause", false),
+                ("vendor/readline/COPYING", "GPL-3.0", true),
+                ("web/package.json", "ISC", false),
+            ]
+        );
+
+        let rendered = render_licenses(&sources);
+        assert!(rendered.starts_with("License inventory (5 source(s), 1 vendored):\n\nGPL-3.0 [copyleft]:\n  vendor/readline/COPYING (vendored)\n"));
+    }
+}
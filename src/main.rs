@@ -1,56 +1,2450 @@
-use std::process;
+use std::{
+    collections::BTreeMap,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
-use clap::{Parser, Subcommand};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
 
-use nishiogi::agent::Agent;
+use nishiogi::{
+    ab,
+    agent::Agent,
+    anonymize::PathHasher,
+    audit::{find_candidates, render_report, triage, MAX_FINDINGS},
+    commit::{self, Conventions},
+    compare,
+    config::{is_first_run, load_instructions, repo_root, Config},
+    db::{CleanTargets, Database},
+    docgen,
+    doctor::{run_checks, Status},
+    editor::{cited_locations, configured_editor, open_command, Location},
+    follow_up, git,
+    glossary::{Glossary, GlossaryTerm},
+    grammars,
+    hooks::{self, Hook},
+    impact,
+    memory::{self, Preferences},
+    migrate, org_policy,
+    overrides::{ContextOverride, Exclusions, ToolOverride},
+    patch::Patch,
+    planner::ContextMode,
+    policy::{Permission, Policy},
+    provider::ProviderKind,
+    refactor,
+    report::{ContextReport, FailureReport, HtmlReport, LatencyReport},
+    scheduler,
+    server::Server,
+    session::{
+        find_reusable_answer, FileProvenance, Pin, ReusableAnswer, SessionRecord, SessionStore,
+    },
+    setup::{login, run_setup, Prompter},
+    style::AnswerStyle,
+    symbols::SymbolIndex,
+    template::QuestionTemplate,
+    tools::CommandCache,
+    transcript,
+    unknown::{self, AnswerStatus, Unknown},
+    unused, warm,
+    workspace::{detect_packages, find_package},
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// Print diagnostic output, such as answer diffs between review iterations
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Deny every tool that runs programs or modifies files and refuse to apply patches,
+    /// whatever the plan or configuration says; on by default, `--read-only=false` lifts it
+    #[arg(
+        long,
+        global = true,
+        default_value_t = true,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        action = ArgAction::Set
+    )]
+    read_only: bool,
+
+    /// Answer with this provider instead of the configured one
+    #[arg(long, global = true, value_enum)]
+    provider: Option<ProviderArg>,
+
+    /// Let planned commands read files below this directory instead of the repository root
+    #[arg(long, global = true, value_name = "DIR")]
+    root: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+/// The provider given with `--provider`, overriding the configured one
+static PROVIDER: OnceLock<ProviderKind> = OnceLock::new();
+
+/// The directory given with `--root`, confining planned commands instead of the repository
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
 #[derive(Subcommand)]
 enum Commands {
     /// Ask a question about the codebase
-    Ask {
-        /// The question you want to ask
-        #[arg(required = true)]
-        question: String,
-    },
+    Ask(AskArgs),
+    /// Ask follow-up questions in a conversation, one per line (`/history` lists earlier
+    /// turns, `/reset` forgets them, `/exit` quits)
+    Chat(ChatArgs),
+    /// Remove cached data, indexes, or sessions of the current repository
+    Clean(CleanArgs),
+    /// Check credentials, connectivity, and configuration, suggesting fixes for problems
+    Doctor,
+    /// Interactively choose a provider, model, and privacy options and write the configuration
+    Setup,
+    /// Manage GitHub Copilot credentials
+    #[command(subcommand)]
+    Auth(AuthCommand),
+    /// List the models available to your account
+    Models,
+    /// Scan for dangerous patterns and have the model triage each finding by severity
+    Audit(AuditArgs),
+    /// List dead code and unused dependency candidates, checked by the model
+    Unused(UnusedArgs),
+    /// Answer a question with several models and show the answers side by side with their costs
+    Ab(AbArgs),
+    /// Compare how parts of the codebase approach something, as a table
+    Compare(CompareArgs),
+    /// List what depends on a file or symbol and summarize the risk of changing it
+    Impact(ImpactArgs),
+    /// Propose doc comments for undocumented public items of a file or module, as a patch
+    Doc(DocArgs),
+    /// Analyze the impact of a refactoring and propose it as a reviewed patch series
+    Refactor(RefactorArgs),
+    /// Find uses of APIs that change between two versions of a dependency and migrate them
+    Migrate(MigrateArgs),
+    /// Suggest a branch name for the staged changes, following the repository's conventions
+    BranchName(BranchNameArgs),
+    /// Suggest a commit message and scope for the staged changes, following the repository's conventions
+    CommitMessage,
+    /// Answer questions over HTTP, with health, readiness, and metrics endpoints
+    Serve(ServeArgs),
+    /// Re-check whether the answers of a saved session still match the current code
+    Verify(VerifyArgs),
+    /// List saved sessions, export them to share them, or import shared ones
+    #[command(subcommand, alias = "sessions")]
+    History(HistoryCommand),
+    /// Install tree-sitter grammars to outline more languages
+    #[command(subcommand)]
+    Grammars(GrammarsCommand),
+    /// Install git hooks that refresh the caches after checkouts and merges
+    #[command(subcommand)]
+    Hook(HookCommand),
+    /// Show or forget the preferences learned from chats (style, language, favorite files)
+    #[command(subcommand)]
+    Memory(MemoryCommand),
+    /// Precompute the directory tree, symbol index, and question embeddings so the next
+    /// question starts fast, e.g. from a post-checkout hook
+    Warm(WarmArgs),
+}
+
+#[derive(Subcommand)]
+enum HistoryCommand {
+    /// List the saved sessions, oldest first, to find one to resume with `ask --resume`
+    List,
+    /// Print a saved session in a shareable format
+    Export(HistoryExportArgs),
+    /// Save the sessions of an exported file in this repository
+    Import(HistoryImportArgs),
+    /// Copy a saved session to a new one, to continue it with `ask --resume` along another
+    /// line of questioning
+    Fork(HistoryForkArgs),
+    /// Print the answers of two sessions side by side, e.g. a session and its fork asked
+    /// with another model
+    Compare(HistoryCompareArgs),
+}
+
+#[derive(Args)]
+struct HistoryExportArgs {
+    /// The session to export
+    id: String,
+
+    /// The format to export in
+    #[arg(long, value_enum, default_value_t = HistoryFormat::Markdown)]
+    format: HistoryFormat,
+
+    /// Replace file paths with salted hashes, as `hash_paths` in `[privacy]` does
+    #[arg(long)]
+    hash_paths: bool,
+}
+
+#[derive(Args)]
+struct HistoryImportArgs {
+    /// The exported file, or `-` to read standard input
+    file: PathBuf,
+
+    /// The format the file is in
+    #[arg(long, value_enum, default_value_t = HistoryFormat::Markdown)]
+    format: HistoryFormat,
+}
+
+#[derive(Args)]
+struct HistoryForkArgs {
+    /// The session to fork
+    id: String,
+
+    /// Keep only the first N answers, to branch off earlier in the session
+    #[arg(long, value_name = "N")]
+    at: Option<usize>,
+}
+
+#[derive(Args)]
+struct HistoryCompareArgs {
+    /// The session shown on the left
+    left: String,
+
+    /// The session shown on the right
+    right: String,
+
+    /// Width of the output in characters
+    #[arg(long, default_value_t = 120)]
+    width: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum HistoryFormat {
+    /// Readable Markdown that keeps the provenance, for pull requests and issues
+    Markdown,
+    /// The session record as stored
+    Json,
+    /// ShareGPT conversations, for fine-tuning and evaluation data
+    Sharegpt,
+}
+
+impl From<HistoryFormat> for transcript::Format {
+    fn from(format: HistoryFormat) -> Self {
+        match format {
+            HistoryFormat::Markdown => transcript::Format::Markdown,
+            HistoryFormat::Json => transcript::Format::Json,
+            HistoryFormat::Sharegpt => transcript::Format::ShareGpt,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum GrammarsCommand {
+    /// Download and compile the tree-sitter grammar of a language
+    Install(GrammarInstallArgs),
+    /// List the installed grammars
+    List,
+    /// Remove the grammar of a language
+    Remove(GrammarRemoveArgs),
+}
+
+#[derive(Args)]
+struct GrammarInstallArgs {
+    /// The language, as in the name of its grammar's repository (`tree-sitter-<language>`)
+    language: String,
+
+    /// Build the grammar from this https git URL or local checkout instead of the
+    /// tree-sitter organization's repository
+    #[arg(long)]
+    from: Option<String>,
+
+    /// An extension of the files in the language; repeatable [default: read from the
+    /// grammar]
+    #[arg(long = "extension", value_name = "EXTENSION")]
+    extensions: Vec<String>,
+}
+
+#[derive(Args)]
+struct GrammarRemoveArgs {
+    /// The language whose grammar to remove
+    language: String,
+}
+
+#[derive(Subcommand)]
+enum MemoryCommand {
+    /// Print the remembered preferences
+    Show,
+    /// Forget the remembered preferences
+    Clear,
+}
+
+#[derive(Subcommand)]
+enum HookCommand {
+    /// Install hooks running `nishiogi warm --background`, keeping existing hooks
+    Install(HookArgs),
+    /// Remove nishiogi's lines from the hooks
+    Uninstall(HookArgs),
+}
+
+#[derive(Args)]
+struct HookArgs {
+    /// The post-checkout hook, run after switching branches (both hooks if neither is given)
+    #[arg(long)]
+    post_checkout: bool,
+
+    /// The post-merge hook, run after merges and pulls
+    #[arg(long)]
+    post_merge: bool,
+}
+
+impl HookArgs {
+    /// Returns the selected hooks, all of them if none was selected
+    fn hooks(&self) -> Vec<Hook> {
+        let selected: Vec<Hook> = [
+            (self.post_checkout, Hook::PostCheckout),
+            (self.post_merge, Hook::PostMerge),
+        ]
+        .into_iter()
+        .filter_map(|(selected, hook)| selected.then_some(hook))
+        .collect();
+        if selected.is_empty() {
+            vec![Hook::PostCheckout, Hook::PostMerge]
+        } else {
+            selected
+        }
+    }
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    /// The session to verify
+    id: String,
+
+    /// Verify only this answer of the session (1 for the first)
+    #[arg(long, value_name = "N")]
+    entry: Option<usize>,
+}
+
+#[derive(Args)]
+struct WarmArgs {
+    /// Return immediately and warm the caches in a detached process
+    #[arg(long)]
+    background: bool,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    addr: String,
+}
+
+#[derive(Args)]
+struct BranchNameArgs {
+    /// What the branch is for; used alone when nothing is staged
+    description: Option<String>,
+}
+
+#[derive(Args)]
+struct MigrateArgs {
+    /// The version migrated from, e.g. "tokio 0.2"
+    #[arg(long)]
+    from: String,
+
+    /// The version migrated to, e.g. "tokio 1"
+    #[arg(long)]
+    to: String,
+
+    /// Also propose a patch making the changes
+    #[arg(long)]
+    patch: bool,
+
+    /// Write the proposed patch after confirmation (implies `--patch`)
+    #[arg(long)]
+    apply: bool,
+
+    /// Directory to migrate
+    #[arg(default_value = ".")]
+    dir: PathBuf,
+}
+
+#[derive(Args)]
+struct RefactorArgs {
+    /// The refactoring to make, e.g. "extract the retry logic into a helper"
+    request: String,
+
+    /// Write the changes after confirmation instead of only printing the patch series
+    #[arg(long)]
+    apply: bool,
+}
+
+#[derive(Args)]
+struct DocArgs {
+    /// File, directory, or module path (e.g. `agent` or `db::schema`) to document
+    target: String,
+
+    /// Write the changes after confirmation instead of only printing the patch
+    #[arg(long)]
+    apply: bool,
+}
+
+#[derive(Args)]
+struct UnusedArgs {
+    /// Directory to analyze
+    #[arg(default_value = ".")]
+    dir: PathBuf,
+}
+
+#[derive(Args)]
+struct AbArgs {
+    /// Your question about the codebase
+    question: String,
+
+    /// The models to compare, separated by commas, e.g. gpt-4,claude-3
+    #[arg(long, value_delimiter = ',', num_args = 1.., required = true)]
+    models: Vec<String>,
+
+    /// Width of the output in characters
+    #[arg(long, default_value_t = 120)]
+    width: usize,
+}
+
+#[derive(Args)]
+struct CompareArgs {
+    /// What to compare, e.g. "error handling in the server" "error handling in the CLI"
+    #[arg(num_args = 2.., required = true)]
+    targets: Vec<String>,
+}
+
+#[derive(Args)]
+struct ImpactArgs {
+    /// File path or symbol name
+    target: String,
+}
+
+#[derive(Args)]
+struct AuditArgs {
+    /// Directory to scan
+    #[arg(default_value = ".")]
+    dir: PathBuf,
+}
+
+#[derive(Subcommand)]
+enum AuthCommand {
+    /// Sign in with a GitHub OAuth token that has Copilot access
+    Login,
+}
+
+#[derive(Args)]
+struct CleanArgs {
+    /// Remove cached model responses and command outputs (the default)
+    #[arg(long)]
+    cache: bool,
+
+    /// Remove index data
+    #[arg(long)]
+    index: bool,
+
+    /// Remove saved sessions
+    #[arg(long)]
+    sessions: bool,
+
+    /// Remove everything
+    #[arg(long)]
+    all: bool,
+}
+
+#[derive(Args)]
+struct AskArgs {
+    /// The question you want to ask; with `--template`, it fills in the template
+    #[arg(required_unless_present = "template")]
+    question: Option<String>,
+
+    /// Expand a question template defined in the configuration
+    #[arg(long, value_name = "NAME")]
+    template: Option<String>,
+
+    /// Permit tools that modify files (still subject to the configured policy)
+    #[arg(long)]
+    allow_write: bool,
+
+    /// Continue a previously saved session
+    #[arg(long, value_name = "ID")]
+    resume: Option<String>,
+
+    /// Print the result as JSON instead of human-readable text (same as `--format json`)
+    #[arg(long, conflicts_with = "format")]
+    json: bool,
+
+    /// Output format of the result
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Always run the full agent loop, even if a similar question was answered before
+    #[arg(long)]
+    no_reuse: bool,
+
+    /// Scope the question to one member of a monorepo workspace (name or directory)
+    #[arg(long, value_name = "NAME")]
+    package: Option<String>,
+
+    /// Record the checked-out commit and a snapshot of the consulted files with the answer,
+    /// so `nishiogi verify` can re-check it later
+    #[arg(long)]
+    pin: bool,
+
+    /// Show only the signatures and doc comments of files at first, reading whole files
+    /// once a review asks for more detail
+    #[arg(long)]
+    signatures: bool,
+
+    /// After answering, offer to open the cited files at the cited lines in $VISUAL or
+    /// $EDITOR, or print `code -g` commands to open them
+    #[arg(long)]
+    open: bool,
+
+    /// Stop after this long (such as `20s`, `2m`, or `1m30s`, at most `24h`) and print the
+    /// best partial answer, marked as incomplete
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    deadline: Option<Duration>,
+
+    /// Write every rendered prompt and raw model response to numbered files in this
+    /// directory, to debug plans and answers or attach to bug reports
+    #[arg(long, value_name = "DIR")]
+    dump_prompts: Option<PathBuf>,
+
+    /// How long and detailed the answer is [default: the style learned from chats, or
+    /// normal]
+    #[arg(long, value_enum)]
+    style: Option<StyleArg>,
+
+    /// Allow or deny a tool for this question whatever the configuration, e.g. `tree=off`
+    /// or `run=on`, except that tools the organization policy disables stay denied;
+    /// repeatable
+    #[arg(long = "tool", value_name = "NAME=on|off")]
+    tools: Vec<ToolOverride>,
+
+    /// Include a file or a range of its lines in the context whatever the plan, e.g.
+    /// `file=src/agent.rs:1-200`; repeatable
+    #[arg(long = "context", value_name = "file=PATH[:START-END]")]
+    context: Vec<ContextOverride>,
+
+    /// Always read this file, or a range of its lines, and include it in the context,
+    /// e.g. `src/agent.rs:1-200`; repeatable (a shorthand for `--context file=...`)
+    #[arg(long, value_name = "PATH[:START-END]", value_parser = ContextOverride::file)]
+    with_file: Vec<ContextOverride>,
+
+    /// Hide paths matching a glob from every tool for this question, e.g. `tests/` when
+    /// asking about production behavior; repeatable
+    #[arg(long, value_name = "GLOB")]
+    without: Vec<String>,
+}
+
+#[derive(Args)]
+struct ChatArgs {
+    /// Permit tools that modify files (still subject to the configured policy)
+    #[arg(long)]
+    allow_write: bool,
+
+    /// Scope the conversation to one member of a monorepo workspace (name or directory)
+    #[arg(long, value_name = "NAME")]
+    package: Option<String>,
+
+    /// Tell the model how the files it read changed between questions, as a diff
+    #[arg(long)]
+    watch: bool,
+
+    /// Append each question and answer to a Markdown transcript as you go
+    #[arg(long, value_name = "FILE")]
+    transcript: Option<PathBuf>,
+
+    /// Do not suggest follow-up questions after each answer
+    #[arg(long)]
+    no_suggestions: bool,
+
+    /// How long and detailed answers are [default: the style learned from chats, or normal]
+    #[arg(long, value_enum)]
+    style: Option<StyleArg>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum StyleArg {
+    /// One short paragraph naming the key location
+    Brief,
+    /// The default answer
+    Normal,
+    /// A detailed walkthrough quoting code excerpts
+    Deep,
+}
+
+impl From<StyleArg> for AnswerStyle {
+    fn from(style: StyleArg) -> Self {
+        match style {
+            StyleArg::Brief => AnswerStyle::Brief,
+            StyleArg::Normal => AnswerStyle::Normal,
+            StyleArg::Deep => AnswerStyle::Deep,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ProviderArg {
+    /// GitHub Copilot, signed in with `nishiogi auth login`
+    Copilot,
+    /// The Anthropic API, with the key in ANTHROPIC_API_KEY
+    Claude,
+    /// The OpenAI API, with the key in OPENAI_API_KEY
+    Openai,
+}
+
+impl From<ProviderArg> for ProviderKind {
+    fn from(provider: ProviderArg) -> Self {
+        match provider {
+            ProviderArg::Copilot => ProviderKind::Copilot,
+            ProviderArg::Claude => ProviderKind::Claude,
+            ProviderArg::Openai => ProviderKind::Openai,
+        }
+    }
+}
+
+/// How the result of the `ask` command is printed
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable text
+    Text,
+    /// Machine-readable JSON
+    Json,
+    /// A self-contained HTML report for sharing
+    Html,
+}
+
+impl AskArgs {
+    /// Returns the requested output format, honoring the `--json` shorthand
+    fn output_format(&self) -> OutputFormat {
+        if self.json {
+            OutputFormat::Json
+        } else {
+            self.format
+        }
+    }
+}
+
+/// Longest duration [`parse_duration`] accepts, so that adding it to an instant cannot
+/// overflow
+const MAX_DURATION: Duration = Duration::from_secs(24 * 3600);
+
+/// Parses a duration such as `90`, `20s`, `2m`, or `1m30s`; bare numbers are seconds
+///
+/// Durations longer than [`MAX_DURATION`] are rejected.
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration `{text}`, expected e.g. `20s`, `2m`, or `1m30s`");
+    let mut seconds: u64 = 0;
+    let mut number = String::new();
+    for c in text.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        let part = number.parse::<u64>().map_err(|_| invalid())?;
+        seconds = part
+            .checked_mul(unit)
+            .and_then(|part| seconds.checked_add(part))
+            .ok_or_else(invalid)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        let part = number.parse::<u64>().map_err(|_| invalid())?;
+        seconds = seconds.checked_add(part).ok_or_else(invalid)?;
+    }
+    if seconds == 0 {
+        return Err(invalid());
+    }
+    if seconds > MAX_DURATION.as_secs() {
+        return Err(format!(
+            "duration `{text}` is longer than the maximum of {}h",
+            MAX_DURATION.as_secs() / 3600
+        ));
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Questions at least this similar to a stored one are offered its cached answer
+const REUSE_SIMILARITY: f64 = 0.92;
+
+/// Characters of each answer shown by `/history` in chat
+const HISTORY_PREVIEW_CHARS: usize = 100;
+
+/// Exit code when the user interrupts a question, as for a process killed by SIGINT
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Appended to an answer the user interrupted before it was final
+const TRUNCATED_MARKER: &str = "(answer truncated by user)";
+
+/// Appended to an answer cut short by `--deadline`
+const INCOMPLETE_MARKER: &str = "(answer incomplete: the deadline was reached)";
+
+/// Machine-readable result of the `ask` command
+#[derive(Serialize)]
+struct AskOutput<'a> {
+    #[serde(skip)]
+    question: &'a str,
+    answer: &'a str,
+    session_id: &'a str,
+    reused: bool,
+    truncated: bool,
+    status: AnswerStatus,
+    unknown: Option<Unknown>,
+    context: Option<&'a ContextReport>,
+    failure: Option<&'a FailureReport>,
+    latency: Option<LatencyReport>,
+    #[serde(skip)]
+    files: &'a [FileProvenance],
 }
 
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
+    if let Some(provider) = cli.provider {
+        PROVIDER.get_or_init(|| provider.into());
+    }
+    if let Some(root) = &cli.root {
+        if !root.is_dir() {
+            eprintln!("Not a directory: {}", root.display());
+            process::exit(1);
+        }
+        ROOT.get_or_init(|| root.clone());
+    }
 
     match &cli.command {
-        Commands::Ask { question } => {
-            println!("Processing question: {question}");
+        Commands::Ask(args) => ask(args, cli.verbose, cli.read_only).await,
+        Commands::Chat(args) => chat(args, cli.verbose, cli.read_only).await,
+        Commands::Clean(args) => clean(args),
+        Commands::Doctor => doctor().await,
+        Commands::Setup => {
+            if !setup().await {
+                process::exit(1);
+            }
+        }
+        Commands::Auth(AuthCommand::Login) => auth_login().await,
+        Commands::Models => models(&config_or_exit()).await,
+        Commands::Audit(args) => audit(args).await,
+        Commands::Unused(args) => unused(args).await,
+        Commands::Ab(args) => ab(args, cli.verbose, cli.read_only).await,
+        Commands::Compare(args) => compare(args).await,
+        Commands::Impact(args) => impact(args).await,
+        Commands::Doc(args) => doc(args, cli.read_only).await,
+        Commands::Refactor(args) => refactor(args, cli.read_only).await,
+        Commands::Migrate(args) => migrate(args, cli.read_only).await,
+        Commands::BranchName(args) => branch_name(args).await,
+        Commands::CommitMessage => commit_message().await,
+        Commands::Serve(args) => serve(args, cli.read_only).await,
+        Commands::Verify(args) => verify(args),
+        Commands::Hook(HookCommand::Install(args)) => hook_install(args),
+        Commands::Hook(HookCommand::Uninstall(args)) => hook_uninstall(args),
+        Commands::Memory(MemoryCommand::Show) => memory_show(),
+        Commands::Memory(MemoryCommand::Clear) => memory_clear(),
+        Commands::Warm(args) => warm(args).await,
+        Commands::History(HistoryCommand::List) => history_list(),
+        Commands::History(HistoryCommand::Export(args)) => history_export(args),
+        Commands::History(HistoryCommand::Import(args)) => history_import(args),
+        Commands::History(HistoryCommand::Fork(args)) => history_fork(args),
+        Commands::History(HistoryCommand::Compare(args)) => history_compare(args),
+        Commands::Grammars(GrammarsCommand::Install(args)) => grammars_install(args),
+        Commands::Grammars(GrammarsCommand::List) => grammars_list(),
+        Commands::Grammars(GrammarsCommand::Remove(args)) => grammars_remove(args),
+    }
+}
 
-            // Initialize the agent
-            let mut agent = match Agent::new().await {
-                Ok(agent) => agent,
-                Err(err) => {
-                    eprintln!("Failed to initialize agent: {err}");
-                    process::exit(1);
-                }
-            };
+/// Runs the `ask` command
+async fn ask(args: &AskArgs, verbose: bool, read_only: bool) {
+    if read_only && args.allow_write {
+        eprintln!("--allow-write has no effect in read-only mode; pass --read-only=false as well");
+    }
+    if is_first_run() && io::stdin().is_terminal() && offer_setup() && !setup().await {
+        process::exit(1);
+    }
+
+    let started = tokio::time::Instant::now();
+    let mut config = config_or_exit();
+    let mut instructions = match load_instructions(&repo_root()) {
+        Ok(instructions) => instructions,
+        Err(err) => {
+            eprintln!("Failed to load project conventions: {err}");
+            process::exit(1);
+        }
+    };
+
+    let question = match &args.template {
+        Some(name) => {
+            let template = find_template(&config, name);
+            if let Some(prompt) = &template.prompt {
+                instructions = Some(match instructions {
+                    Some(conventions) => format!("{conventions}\n\n{prompt}"),
+                    None => prompt.clone(),
+                });
+            }
+            config
+                .planner
+                .preferred_tools
+                .extend(template.tools.iter().cloned());
+            template.expand(args.question.as_deref())
+        }
+        None => args.question.clone().unwrap_or_default(),
+    };
+    eprintln!("Processing question: {question}");
+    if args.signatures {
+        config.planner.context = ContextMode::Signatures;
+    }
+
+    if let Some(dir) = &args.dump_prompts
+        && let Err(err) = std::fs::create_dir_all(dir)
+    {
+        eprintln!("Failed to create {}: {err}", dir.display());
+        process::exit(1);
+    }
+
+    let context: Vec<ContextOverride> = args
+        .context
+        .iter()
+        .chain(&args.with_file)
+        .cloned()
+        .collect();
+    // Paths are relative to the package with --package, which is resolved later
+    if args.package.is_none()
+        && let Some(missing) = context.iter().find(|context| !context.path.is_file())
+    {
+        eprintln!("Cannot include {}: not a file", missing.path.display());
+        process::exit(1);
+    }
+    for tool in &args.tools {
+        if let Err(err) = config.override_tool(&tool.tool, tool.permission()) {
+            eprintln!("Cannot apply --tool: {err}");
+            process::exit(1);
+        }
+    }
+
+    // Initialize the agent
+    let preferences = load_preferences(&config).map(|(_, preferences)| preferences);
+    let mut agent = init_agent(&config)
+        .await
+        .with_reviewer(config.review.build())
+        .with_planner(config.planner)
+        .with_policy(Policy::new(config.policy, args.allow_write).read_only(read_only))
+        .with_instructions(instructions)
+        .with_verbose(verbose)
+        .with_prompt_dump(args.dump_prompts.clone())
+        .with_style(answer_style(args.style, preferences.as_ref()))
+        .with_context(context)
+        .with_exclusions(Exclusions::new(args.without.clone()));
+    if let Some(preferences) = &preferences {
+        agent = agent.with_preferences(preferences, &repo_root());
+    }
+
+    if let Some(name) = &args.package {
+        agent = scope_to_package(agent, name);
+    }
+
+    let store = match SessionStore::open_default() {
+        Ok(store) => store,
+        Err(err) => {
+            eprintln!("Failed to open session store: {err}");
+            process::exit(1);
+        }
+    };
+    agent = agent.with_glossary(load_glossary(&config.glossary, Some(&store)));
+
+    let mut record = match &args.resume {
+        Some(id) => match store.load(id) {
+            Ok(record) => {
+                warn_stale(&record);
+                agent.resume(&record);
+                record
+            }
+            Err(err) => {
+                eprintln!("Failed to resume session: {err}");
+                process::exit(1);
+            }
+        },
+        None => SessionRecord::new(std::env::current_dir().ok()),
+    };
+
+    // Offer a stored answer to a highly similar question before running the full loop
+    let working_dir = std::env::current_dir().ok();
+    let embedding = if args.no_reuse || args.resume.is_some() || !config.privacy.reuse_answers {
+        None
+    } else {
+        match agent.embed(&question).await {
+            Ok(embedding) => Some(embedding),
+            Err(err) => {
+                eprintln!("Skipping answer reuse: {err}");
+                None
+            }
+        }
+    };
+    if let (Some(embedding), Some(dir)) = (&embedding, &working_dir)
+        && let Some(reusable) = find_reusable(&store, dir, embedding)
+        && offer_reuse(&reusable)
+    {
+        print_answer(
+            &AskOutput {
+                question: &question,
+                answer: &reusable.entry.answer,
+                session_id: &reusable.session_id,
+                reused: true,
+                truncated: false,
+                status: AnswerStatus::of(&reusable.entry.answer),
+                unknown: unknown::parse(&reusable.entry.answer),
+                context: None,
+                failure: None,
+                latency: None,
+                files: &reusable.entry.provenance.files,
+            },
+            args.output_format(),
+        );
+        if args.open {
+            open_cited(&reusable.entry.answer, &reusable.entry.provenance.files);
+        }
+        return;
+    }
+
+    // Process the question, keeping the latest draft if the user presses Ctrl-C or the
+    // deadline passes
+    let deadline = async {
+        match args.deadline {
+            Some(deadline) => tokio::time::sleep_until(started + deadline).await,
+            None => std::future::pending().await,
+        }
+    };
+    let mut interrupted = false;
+    let result = tokio::select! {
+        result = agent.process_query(&question) => Some(result),
+        _ = tokio::signal::ctrl_c() => {
+            interrupted = true;
+            None
+        }
+        () = deadline => None,
+    };
+    let (answer, truncated) = match result {
+        Some(Ok(answer)) => (answer, false),
+        Some(Err(err)) => {
+            eprintln!("Error processing query: {err}");
+            process::exit(1);
+        }
+        None if interrupted => match agent.partial_answer() {
+            Some(draft) => (format!("{draft}\n\n{TRUNCATED_MARKER}"), true),
+            None => {
+                eprintln!("\nInterrupted before an answer was drafted");
+                process::exit(EXIT_INTERRUPTED);
+            }
+        },
+        None => match agent.partial_results() {
+            Some(partial) => {
+                eprintln!("Deadline reached; printing the partial answer");
+                (format!("{partial}\n\n{INCOMPLETE_MARKER}"), true)
+            }
+            None => {
+                eprintln!("Deadline reached before any results were gathered");
+                process::exit(1);
+            }
+        },
+    };
+
+    if verbose {
+        eprintln!(
+            "Used {} tokens; {} prompt tokens were served from the provider's cache",
+            agent.tokens_used(),
+            agent.cached_tokens()
+        );
+    }
+
+    let mut entry = agent.session_entry(&answer);
+    entry.truncated = truncated;
+    if args.pin {
+        let pin = Pin::capture(Path::new("."), &entry.provenance.files);
+        match &pin.commit {
+            Some(commit) if pin.dirty => eprintln!(
+                "Pinned to commit {} with uncommitted changes (snapshot {})",
+                short_hash(commit),
+                short_hash(&pin.snapshot)
+            ),
+            Some(commit) => eprintln!(
+                "Pinned to commit {} (snapshot {})",
+                short_hash(commit),
+                short_hash(&pin.snapshot)
+            ),
+            None => eprintln!("Pinned to snapshot {}", short_hash(&pin.snapshot)),
+        }
+        entry.provenance.pin = Some(pin);
+    }
+    if !truncated {
+        entry.question_embedding = embedding;
+    }
+    let files = entry.provenance.files.clone();
+    record.entries.push(entry);
+    if config.privacy.save_sessions {
+        match store.save(&record) {
+            Ok(()) => eprintln!("Session {} saved", record.id),
+            Err(err) => eprintln!("Failed to save session: {err}"),
+        }
+    }
+
+    print_answer(
+        &AskOutput {
+            question: &question,
+            answer: &answer,
+            session_id: &record.id,
+            reused: false,
+            truncated,
+            status: AnswerStatus::of(&answer),
+            unknown: unknown::parse(&answer),
+            context: agent.context_report(),
+            failure: agent.failure_report(),
+            latency: agent.latency_report(),
+            files: &files,
+        },
+        args.output_format(),
+    );
+    if args.open {
+        open_cited(&answer, &files);
+    }
+    if interrupted {
+        process::exit(EXIT_INTERRUPTED);
+    }
+}
+
+/// Answers questions read from stdin one per line, keeping earlier turns as context, until
+/// an empty line, `exit` or `/exit`, or the end of input
+///
+/// `/history` lists the earlier turns and `/reset` forgets them.
+async fn chat(args: &ChatArgs, verbose: bool, read_only: bool) {
+    if read_only && args.allow_write {
+        eprintln!("--allow-write has no effect in read-only mode; pass --read-only=false as well");
+    }
+    let config = config_or_exit();
+    let instructions = match load_instructions(&repo_root()) {
+        Ok(instructions) => instructions,
+        Err(err) => {
+            eprintln!("Failed to load project conventions: {err}");
+            process::exit(1);
+        }
+    };
+    let repo = repo_root();
+    let mut memory = load_preferences(&config);
+    let mut agent = init_agent(&config)
+        .await
+        .with_reviewer(config.review.build())
+        .with_planner(config.planner)
+        .with_policy(Policy::new(config.policy, args.allow_write).read_only(read_only))
+        .with_instructions(instructions)
+        .with_verbose(verbose)
+        .with_watch(args.watch)
+        .with_style(answer_style(
+            args.style,
+            memory.as_ref().map(|(_, preferences)| preferences),
+        ))
+        .with_glossary(load_glossary(
+            &config.glossary,
+            SessionStore::open_default().ok().as_ref(),
+        ));
+    if let Some((_, preferences)) = &memory {
+        agent = agent.with_preferences(preferences, &repo);
+    }
+    if let Some(name) = &args.package {
+        agent = scope_to_package(agent, name);
+    }
+    let mut transcript_file = args.transcript.as_deref().map(open_transcript);
+    let mut suggestions: Vec<String> = Vec::new();
 
-            // Process the question
-            match agent.process_query(question).await {
-                Ok(answer) => {
-                    println!();
-                    println!("=== Answer ===");
-                    println!();
-                    println!("{answer}");
+    loop {
+        eprint!("> ");
+        let mut input = String::new();
+        if io::stderr().flush().is_err() || io::stdin().read_line(&mut input).unwrap_or(0) == 0 {
+            break;
+        }
+        let question = match follow_up::pick(&input, &suggestions) {
+            Some(suggestion) => {
+                eprintln!("{suggestion}");
+                suggestion.to_string()
+            }
+            None => input.trim().to_string(),
+        };
+        suggestions.clear();
+        match question.as_str() {
+            "" | "exit" | "/exit" => break,
+            "/reset" => {
+                agent.clear_history();
+                eprintln!("Conversation reset; earlier questions are forgotten");
+                continue;
+            }
+            "/history" => {
+                print_history(&agent);
+                continue;
+            }
+            command if command.starts_with('/') => {
+                eprintln!("Unknown command {command}; use /history, /reset, or /exit");
+                continue;
+            }
+            _ => {}
+        }
+        if args.watch {
+            let changed = agent.refresh_changes();
+            if !changed.is_empty() {
+                let names: Vec<String> = changed
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                eprintln!("Including changes to {}", names.join(", "));
+            }
+        }
+        match agent.process_query(&question).await {
+            Ok(answer) => {
+                println!("{answer}\n");
+                if let Some((path, preferences)) = &mut memory {
+                    preferences.learn(&question, &repo, &agent.consulted_paths());
+                    if let Err(err) = preferences.save(path) {
+                        eprintln!("Failed to remember preferences: {err}");
+                    }
                 }
-                Err(err) => {
-                    eprintln!("Error processing query: {err}");
-                    process::exit(1);
+                if let Some((file, entries)) = &mut transcript_file {
+                    *entries += 1;
+                    let entry = transcript::markdown_entry(*entries, &agent.session_entry(&answer));
+                    if let Err(err) = file.write_all(entry.as_bytes()) {
+                        eprintln!("Failed to append to the transcript: {err}");
+                    }
+                }
+                if !args.no_suggestions {
+                    // Suggestions are a convenience, so failing to get them is only logged
+                    match agent.suggest_follow_ups(&answer).await {
+                        Ok(suggested) => suggestions = suggested,
+                        Err(err) if verbose => eprintln!("No follow-up suggestions: {err}"),
+                        Err(_) => {}
+                    }
+                    if !suggestions.is_empty() {
+                        eprintln!("Follow-up questions (enter a number to ask one):");
+                        for (index, suggestion) in suggestions.iter().enumerate() {
+                            eprintln!("  {}. {suggestion}", index + 1);
+                        }
+                        eprintln!();
+                    }
                 }
             }
+            Err(err) => eprintln!("Error processing query: {err}"),
+        }
+    }
+}
+
+/// Builds the glossary of the configured terms and those learned from the sessions in
+/// `store`, if any
+fn load_glossary(terms: &BTreeMap<String, GlossaryTerm>, store: Option<&SessionStore>) -> Glossary {
+    let mut glossary = Glossary::new(terms);
+    if let (Some(store), Ok(dir)) = (store, std::env::current_dir()) {
+        match store.list() {
+            Ok(records) => glossary.learn(&records, &dir),
+            Err(err) => eprintln!("Skipping glossary terms learned from sessions: {err}"),
+        }
+    }
+    glossary
+}
+
+/// Loads the preferences learned from earlier chats with their location, unless
+/// remembering them is disabled
+fn load_preferences(config: &Config) -> Option<(PathBuf, Preferences)> {
+    if !config.privacy.remember_preferences {
+        return None;
+    }
+    let path = memory::memory_path()?;
+    match Preferences::load(&path) {
+        Ok(preferences) => Some((path, preferences)),
+        Err(err) => {
+            // Leave the file alone so it is not overwritten with fresh preferences
+            eprintln!(
+                "Ignoring remembered preferences in {}: {err}",
+                path.display()
+            );
+            None
+        }
+    }
+}
+
+/// Returns the answer style: the one chosen on the command line, else the remembered one
+fn answer_style(chosen: Option<StyleArg>, preferences: Option<&Preferences>) -> AnswerStyle {
+    match chosen {
+        Some(style) => style.into(),
+        None => preferences
+            .and_then(|preferences| preferences.style)
+            .unwrap_or_default(),
+    }
+}
+
+/// Lists the earlier questions of a chat with the start of their answers
+fn print_history(agent: &Agent) {
+    let mut empty = true;
+    for (number, (question, answer)) in agent.history().enumerate() {
+        empty = false;
+        let mut preview = answer.lines().next().unwrap_or_default().to_string();
+        if preview.len() > HISTORY_PREVIEW_CHARS {
+            preview.truncate(preview.floor_char_boundary(HISTORY_PREVIEW_CHARS));
+            preview.push_str("...");
+        }
+        eprintln!("{}. {question}\n   {preview}", number + 1);
+    }
+    if empty {
+        eprintln!("No questions asked yet");
+    }
+}
+
+/// Opens the Markdown transcript of `chat --transcript` for appending, starting it if the
+/// file is new, and returns it with the number of entries it already has
+fn open_transcript(path: &Path) -> (std::fs::File, usize) {
+    let existing = std::fs::read_to_string(path).unwrap_or_default();
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path);
+    let mut file = match file {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Failed to open {}: {err}", path.display());
+            process::exit(1);
+        }
+    };
+    if existing.trim().is_empty() {
+        let header = transcript::markdown_header(&SessionRecord::new(std::env::current_dir().ok()));
+        if let Err(err) = file.write_all(header.as_bytes()) {
+            eprintln!("Failed to write {}: {err}", path.display());
+            process::exit(1);
+        }
+    }
+    (file, transcript::markdown_entry_count(&existing))
+}
+
+/// Offers to open the files cited by `answer` in the configured editor, or prints commands
+/// opening them when there is no editor or terminal to ask on
+fn open_cited(answer: &str, files: &[FileProvenance]) {
+    let locations = cited_locations(answer, files);
+    if locations.is_empty() {
+        eprintln!("The answer cites no files to open");
+        return;
+    }
+    let editor = configured_editor().filter(|_| io::stdin().is_terminal());
+    let Some(editor) = editor else {
+        eprintln!("Open the cited code with:");
+        for location in &locations {
+            eprintln!("  {}", location.vscode_command());
         }
+        return;
+    };
+
+    eprintln!("Cited files:");
+    for (index, location) in locations.iter().enumerate() {
+        eprintln!(
+            "  {}. {}:{}",
+            index + 1,
+            location.path.display(),
+            location.line
+        );
+    }
+    eprint!("Open which in {editor}? (numbers, `a` for all, Enter for none) ");
+    let mut input = String::new();
+    if io::stderr().flush().is_err() || io::stdin().read_line(&mut input).is_err() {
+        return;
+    }
+    let selected: Vec<&Location> = if input.trim().eq_ignore_ascii_case("a") {
+        locations.iter().collect()
+    } else {
+        input
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter_map(|choice| choice.parse::<usize>().ok())
+            .filter_map(|choice| locations.get(choice.checked_sub(1)?))
+            .collect()
+    };
+    for location in selected {
+        let command = open_command(&editor, location);
+        let status = process::Command::new(&command[0])
+            .args(&command[1..])
+            .status();
+        if let Err(err) = status {
+            eprintln!("Failed to run {editor}: {err}");
+            return;
+        }
+    }
+}
+
+/// Returns the named question template, exiting with the available names if it is missing
+fn find_template(config: &Config, name: &str) -> QuestionTemplate {
+    if let Some(template) = config.templates.get(name) {
+        return template.clone();
+    }
+
+    eprintln!("Template not found: {name}");
+    if config.templates.is_empty() {
+        eprintln!(
+            "No templates are defined; add a [templates.<name>] section to your configuration"
+        );
+    } else {
+        eprintln!("Available templates:");
+        for (name, template) in &config.templates {
+            eprintln!("  {name}  {}", template.description);
+        }
+    }
+    process::exit(1);
+}
+
+/// Loads the configuration, exiting if it is invalid
+fn config_or_exit() -> Config {
+    match Config::load_with(PROVIDER.get().copied()) {
+        Ok(config) => {
+            scheduler::configure(&config.limits);
+            org_policy::configure(config.org_policy.clone());
+            config
+        }
+        Err(err) => {
+            eprintln!("Failed to load configuration: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Creates the agent for the configured provider and model, exiting with a remedy if that fails
+async fn init_agent(config: &Config) -> Agent {
+    let agent = Agent::for_provider(config.provider.name, config.provider.model.clone()).await;
+    match agent {
+        Ok(agent) => {
+            let agent = match &config.provider.large_context_model {
+                Some(model) => agent
+                    .with_large_context_model(model.clone(), config.provider.large_context_price),
+                None => agent,
+            };
+            confine(agent)
+        }
+        Err(err) => {
+            eprintln!("Failed to initialize agent: {err}");
+            eprintln!(
+                "hint: {}",
+                err.remedy()
+                    .unwrap_or("Run `nishiogi doctor` to diagnose the problem")
+            );
+            process::exit(1);
+        }
+    }
+}
+
+/// Confines the planned commands of `agent` to the directory given with `--root`, if any
+fn confine(agent: Agent) -> Agent {
+    match ROOT.get() {
+        Some(root) => agent.with_root(root),
+        None => agent,
+    }
+}
+
+/// Runs the `auth login` command
+async fn auth_login() {
+    let stdin = io::stdin();
+    let mut prompter = Prompter::new(stdin.lock(), io::stderr());
+    match login(&mut prompter, false).await {
+        Ok(_) => eprintln!("Signed in to GitHub Copilot"),
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `models` command, marking the configured model
+async fn models(config: &Config) {
+    let agent = match Agent::for_provider(config.provider.name, None).await {
+        Ok(agent) => agent,
+        Err(err) => {
+            eprintln!("Failed to list models: {err}");
+            if let Some(remedy) = err.remedy() {
+                eprintln!("hint: {remedy}");
+            }
+            process::exit(1);
+        }
+    };
+    let selected = config
+        .provider
+        .model
+        .as_deref()
+        .unwrap_or(config.provider.name.default_model());
+    let models = agent.available_models();
+    let width = models.iter().map(|id| id.len()).max().unwrap_or(0);
+    for id in models {
+        let marker = if id == selected { "*" } else { " " };
+        println!("{marker} {id:<width$}  {}", agent.capabilities(id));
+    }
+}
+
+/// Runs the `audit` command
+async fn audit(args: &AuditArgs) {
+    // Loading the configuration installs the organization policy the scan must follow
+    let config = config_or_exit();
+    if !args.dir.is_dir() {
+        eprintln!("Not a directory: {}", args.dir.display());
+        process::exit(1);
+    }
+
+    eprintln!("Scanning {} for dangerous patterns", args.dir.display());
+    let findings = find_candidates(&args.dir);
+    if findings.len() == MAX_FINDINGS {
+        eprintln!("Only the first {MAX_FINDINGS} findings are triaged");
+    }
+    if findings.is_empty() {
+        println!("Security audit: no dangerous patterns found");
+        return;
+    }
+
+    let mut agent = init_agent(&config).await;
+    match triage(&mut agent, findings).await {
+        Ok(triaged) => print!("{}", render_report(&triaged)),
+        Err(err) => {
+            eprintln!("Failed to triage findings: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `ab` command, answering the question with each model in turn
+async fn ab(args: &AbArgs, verbose: bool, read_only: bool) {
+    let config = config_or_exit();
+    let instructions = match load_instructions(&repo_root()) {
+        Ok(instructions) => instructions,
+        Err(err) => {
+            eprintln!("Failed to load project conventions: {err}");
+            process::exit(1);
+        }
+    };
+
+    // Models run one after the other, so later ones find the files earlier ones read
+    let cache = CommandCache::new();
+    let mut runs = Vec::new();
+    for model in &args.models {
+        eprintln!("Asking {model}");
+        let started = tokio::time::Instant::now();
+        let agent = Agent::for_provider(config.provider.name, Some(model.clone())).await;
+        let run = match agent {
+            Ok(agent) => {
+                let mut agent = confine(agent)
+                    .with_reviewer(config.review.build())
+                    .with_planner(config.planner.clone())
+                    .with_policy(Policy::new(config.policy.clone(), false).read_only(read_only))
+                    .with_instructions(instructions.clone())
+                    .with_verbose(verbose)
+                    .with_command_cache(cache.clone());
+                let answer = agent.process_query(&args.question).await;
+                ab::ModelRun {
+                    model: model.clone(),
+                    answer: answer.map_err(|err| err.to_string()),
+                    latency: started.elapsed(),
+                    tokens_used: agent.tokens_used(),
+                    cached_tokens: agent.cached_tokens(),
+                }
+            }
+            Err(err) => ab::ModelRun {
+                model: model.clone(),
+                answer: Err(err.to_string()),
+                latency: started.elapsed(),
+                tokens_used: 0,
+                cached_tokens: 0,
+            },
+        };
+        runs.push(run);
+    }
+    print!("{}", ab::render(&runs, args.width));
+    if verbose {
+        eprintln!(
+            "{} commands were served from the shared cache",
+            cache.hits()
+        );
+    }
+}
+
+/// Runs the `compare` command, printing a table that contrasts the targets
+async fn compare(args: &CompareArgs) {
+    let config = config_or_exit();
+    let mut agent = init_agent(&config).await;
+    let root = ROOT.get().cloned().unwrap_or_else(repo_root);
+    let gathered = match compare::gather(&mut agent, &args.targets, &root).await {
+        Ok(gathered) => gathered,
+        Err(err) => {
+            eprintln!("Failed to retrieve the targets: {err}");
+            process::exit(1);
+        }
+    };
+    match compare::compare(&mut agent, &gathered).await {
+        Ok(table) => println!("{}", table.trim_end()),
+        Err(err) => {
+            eprintln!("Failed to compare the targets: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `impact` command
+async fn impact(args: &ImpactArgs) {
+    let config = config_or_exit();
+    let root = repo_root();
+    let index = warm::cached_symbol_index(&root).unwrap_or_else(|| {
+        eprintln!("Indexing symbols in {}", root.display());
+        SymbolIndex::build(&root)
+    });
+    let Some(report) = impact::analyze(&root, &index, &args.target) else {
+        eprintln!("No file or symbol named {} found", args.target);
+        process::exit(1);
+    };
+    print!("{}", impact::render_report(&report));
+
+    let mut agent = init_agent(&config).await;
+    match impact::assess_risk(&mut agent, &root, &report).await {
+        Ok(summary) => println!("\n{}", summary.trim()),
+        Err(err) => {
+            eprintln!("Failed to summarize the risk: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `unused` command
+async fn unused(args: &UnusedArgs) {
+    let config = config_or_exit();
+    if !args.dir.is_dir() {
+        eprintln!("Not a directory: {}", args.dir.display());
+        process::exit(1);
+    }
+
+    eprintln!("Indexing symbols and manifests in {}", args.dir.display());
+    let candidates = unused::find_candidates(&args.dir);
+    if candidates.len() == unused::MAX_CANDIDATES {
+        eprintln!(
+            "Only the first {} candidates are checked",
+            unused::MAX_CANDIDATES
+        );
+    }
+    if candidates.is_empty() {
+        println!("No unused code or dependencies found");
+        return;
+    }
+
+    let mut agent = init_agent(&config).await;
+    eprintln!("Checking {} candidate(s)", candidates.len());
+    match unused::sanity_check(&mut agent, &args.dir, candidates).await {
+        Ok(assessments) => print!("{}", unused::render_report(&assessments)),
+        Err(err) => {
+            eprintln!("Failed to check candidates: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `doc` command
+async fn doc(args: &DocArgs, read_only: bool) {
+    let config = config_or_exit();
+    let root = repo_root();
+    let Some(files) = docgen::resolve_target(&root, &args.target) else {
+        eprintln!("No file or module named {}", args.target);
+        process::exit(1);
+    };
+
+    let index = SymbolIndex::from_files(&root, &files);
+    let items = docgen::undocumented(&index, &root);
+    if items.is_empty() {
+        eprintln!("Every public item in {} is documented", args.target);
+        return;
+    }
+
+    let mut agent = init_agent(&config).await;
+    let patch = match docgen::document(&mut agent, &root, &items).await {
+        Ok(patch) => patch,
+        Err(err) => {
+            eprintln!("Failed to generate documentation: {err}");
+            process::exit(1);
+        }
+    };
+    review_patch(&patch, &root, args.apply, config, read_only);
+}
+
+/// Prints `patch` and, if `apply` is set, writes it once the write policy allows it
+fn review_patch(patch: &Patch, root: &Path, apply: bool, config: Config, read_only: bool) {
+    if patch.is_empty() {
+        eprintln!("No changes proposed");
+        return;
+    }
+    print!("{}", patch.render());
+    if apply {
+        apply_patch(patch, root, config, read_only);
+    } else {
+        eprintln!("Review the patch above; pass --apply to write it, or pipe it to `git apply`");
+    }
+}
+
+/// Writes `patch` under `root` if the write policy and read-only mode allow it, asking first
+/// if so configured
+fn apply_patch(patch: &Patch, root: &Path, config: Config, read_only: bool) {
+    let policy = Policy::new(config.policy, true).read_only(read_only);
+    let approved = match policy.permission("write_file") {
+        Permission::Allow => true,
+        Permission::Deny if policy.is_read_only() => {
+            eprintln!("Writing files is disabled in read-only mode; pass --read-only=false to apply the patch");
+            process::exit(1);
+        }
+        Permission::Deny => {
+            eprintln!("Writing files is denied by the configured policy");
+            process::exit(1);
+        }
+        Permission::Ask => confirm(&format!(
+            "Apply the patch to {} file(s)?",
+            patch.edits.len()
+        )),
+    };
+    if !approved {
+        eprintln!("Patch not applied");
+        return;
+    }
+    match patch.apply(root) {
+        Ok(()) => eprintln!("Applied the patch to {} file(s)", patch.edits.len()),
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `refactor` command
+async fn refactor(args: &RefactorArgs, read_only: bool) {
+    let config = config_or_exit();
+    let root = repo_root();
+    eprintln!("Locating code and call sites affected by the refactoring");
+    let impact = refactor::analyze(&root, &args.request);
+    print!("{}", refactor::render_impact(&impact, &root));
+    if impact.snippets.is_empty() {
+        eprintln!(
+            "No code related to the request was found; name the items to change in backticks"
+        );
+        process::exit(1);
+    }
+
+    let mut agent = init_agent(&config).await;
+    let steps = match refactor::propose(&mut agent, &root, &args.request, &impact).await {
+        Ok(steps) => steps,
+        Err(err) => {
+            eprintln!("Failed to propose the refactoring: {err}");
+            process::exit(1);
+        }
+    };
+    print!("{}", refactor::render_series(&steps));
+    if !args.apply {
+        eprintln!("Review the patch series above; pass --apply to write it");
+        return;
+    }
+
+    let mut patch = Patch::default();
+    for step in steps {
+        patch.then(step.patch);
+    }
+    apply_patch(&patch, &root, config, read_only);
+}
+
+/// Runs the `migrate` command
+async fn migrate(args: &MigrateArgs, read_only: bool) {
+    if !args.dir.is_dir() {
+        eprintln!("Not a directory: {}", args.dir.display());
+        process::exit(1);
+    }
+
+    let config = config_or_exit();
+    let mut agent = init_agent(&config).await;
+    eprintln!("Listing API changes from {} to {}", args.from, args.to);
+    let changes = match migrate::identify_changes(&mut agent, &args.from, &args.to).await {
+        Ok(changes) => changes,
+        Err(err) => {
+            eprintln!("Failed to list API changes: {err}");
+            process::exit(1);
+        }
+    };
+
+    eprintln!(
+        "Searching {} for {} changed API(s)",
+        args.dir.display(),
+        changes.len()
+    );
+    let usages = migrate::find_usages(&args.dir, &changes);
+    if usages.len() == migrate::MAX_USAGES {
+        eprintln!("Only the first {} uses are listed", migrate::MAX_USAGES);
+    }
+    print!("{}", migrate::render_summary(&changes, &usages));
+    if usages.is_empty() || !(args.patch || args.apply) {
+        return;
+    }
+
+    match migrate::propose_patch(&mut agent, &args.dir, &changes, &usages).await {
+        Ok(patch) => {
+            println!();
+            review_patch(&patch, &args.dir, args.apply, config, read_only);
+        }
+        Err(err) => {
+            eprintln!("Failed to propose a patch: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `branch-name` command
+async fn branch_name(args: &BranchNameArgs) {
+    let config = config_or_exit();
+    let root = repo_root();
+    let (diff, conventions) = staged_changes(&root);
+    if diff.trim().is_empty() && args.description.is_none() {
+        eprintln!("Nothing is staged; stage changes or describe the work, e.g. `nishiogi branch-name \"retry failed requests\"`");
+        process::exit(1);
+    }
+
+    let mut agent = init_agent(&config).await;
+    match commit::suggest_branch_name(&mut agent, &diff, args.description.as_deref(), &conventions)
+        .await
+    {
+        Ok(name) => println!("{name}"),
+        Err(err) => {
+            eprintln!("Failed to suggest a branch name: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `commit-message` command
+async fn commit_message() {
+    let config = config_or_exit();
+    let root = repo_root();
+    let (diff, conventions) = staged_changes(&root);
+    if diff.trim().is_empty() {
+        eprintln!("Nothing is staged; stage the changes to describe with `git add`");
+        process::exit(1);
+    }
+
+    let files = git::staged_files(&root).unwrap_or_default();
+    let scopes = commit::scope_candidates(&files, &conventions);
+    if conventions.conventional_commits && !scopes.is_empty() {
+        eprintln!("Suggested scopes: {}", scopes.join(", "));
+    }
+
+    let mut agent = init_agent(&config).await;
+    match commit::suggest_commit_message(&mut agent, &diff, &conventions, &scopes).await {
+        Ok(message) => println!("{message}"),
+        Err(err) => {
+            eprintln!("Failed to suggest a commit message: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `serve` command
+async fn serve(args: &ServeArgs, read_only: bool) {
+    let config = config_or_exit();
+    let root = repo_root();
+    let instructions = match load_instructions(&root) {
+        Ok(instructions) => instructions,
+        Err(err) => {
+            eprintln!("Failed to load project conventions: {err}");
+            process::exit(1);
+        }
+    };
+
+    let server = Arc::new(Server::new(root, config, instructions).with_read_only(read_only));
+    if let Err(err) = server.run(&args.addr).await {
+        eprintln!("Server stopped: {err}");
+        process::exit(1);
+    }
+}
+
+/// Returns the hooks directory of the current repository, exiting if there is none
+fn hooks_dir_or_exit() -> PathBuf {
+    match hooks::hooks_dir(&repo_root()) {
+        Ok(dir) => dir,
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `hook install` command
+fn hook_install(args: &HookArgs) {
+    let dir = hooks_dir_or_exit();
+    let mut failed = false;
+    for hook in args.hooks() {
+        match hooks::install(&dir, hook) {
+            Ok(hooks::Outcome::Unchanged) => eprintln!("{} hook already installed", hook.name()),
+            Ok(hooks::Outcome::Updated) => eprintln!("Added to the existing {} hook", hook.name()),
+            Ok(_) => eprintln!("Installed the {} hook", hook.name()),
+            Err(err) => {
+                eprintln!("{err}");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// Runs the `hook uninstall` command
+fn hook_uninstall(args: &HookArgs) {
+    let dir = hooks_dir_or_exit();
+    let mut failed = false;
+    for hook in args.hooks() {
+        match hooks::uninstall(&dir, hook) {
+            Ok(hooks::Outcome::Unchanged) => eprintln!("{} hook was not installed", hook.name()),
+            Ok(hooks::Outcome::Removed) => eprintln!("Removed the {} hook", hook.name()),
+            Ok(_) => eprintln!("Removed nishiogi from the {} hook", hook.name()),
+            Err(err) => {
+                eprintln!("{err}");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// Runs the `warm` command, precomputing the caches of the current repository
+async fn warm(args: &WarmArgs) {
+    if args.background {
+        let spawned = std::env::current_exe().and_then(|exe| {
+            process::Command::new(exe)
+                .arg("warm")
+                .stdin(process::Stdio::null())
+                .stdout(process::Stdio::null())
+                .stderr(process::Stdio::null())
+                .spawn()
+        });
+        match spawned {
+            Ok(child) => eprintln!("Warming caches in the background (pid {})", child.id()),
+            Err(err) => {
+                eprintln!("Failed to start warming in the background: {err}");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // The index must leave out the paths the organization policy forbids
+    let config = config_or_exit();
+    let root = repo_root();
+    let store = match SessionStore::open_default() {
+        Ok(store) => store,
+        Err(err) => {
+            eprintln!("Failed to open the database: {err}");
+            process::exit(1);
+        }
+    };
+    match warm::warm(&root, store.database()) {
+        Ok(report) if report.up_to_date => eprintln!(
+            "Tree and symbol index are up to date ({} files, {} definitions)",
+            report.files, report.symbols
+        ),
+        Ok(report) => eprintln!(
+            "Cached the tree and symbol index ({} files, {} definitions)",
+            report.files, report.symbols
+        ),
+        Err(err) => {
+            eprintln!("Failed to warm the caches: {err}");
+            process::exit(1);
+        }
+    }
+
+    // Embed saved questions so similar ones can be answered from the history right away
+    if !config.privacy.reuse_answers {
+        return;
+    }
+    let records = match store.list() {
+        Ok(records) => records,
+        Err(err) => {
+            eprintln!("Skipping question embeddings: {err}");
+            return;
+        }
+    };
+    let missing = records
+        .iter()
+        .flat_map(|record| &record.entries)
+        .filter(|entry| !entry.truncated && entry.question_embedding.is_none())
+        .count();
+    if missing == 0 {
+        return;
+    }
+    let agent = Agent::for_provider(config.provider.name, config.provider.model.clone()).await;
+    let agent = match agent {
+        Ok(agent) => agent,
+        Err(err) => {
+            eprintln!("Skipping question embeddings: {err}");
+            return;
+        }
+    };
+    let mut embedded = 0;
+    for mut record in records {
+        let mut changed = false;
+        for entry in &mut record.entries {
+            if entry.truncated || entry.question_embedding.is_some() {
+                continue;
+            }
+            match agent.embed(&entry.question).await {
+                Ok(embedding) => {
+                    entry.question_embedding = Some(embedding);
+                    changed = true;
+                    embedded += 1;
+                }
+                Err(err) => {
+                    eprintln!("Skipping question embeddings: {err}");
+                    return;
+                }
+            }
+        }
+        if changed && let Err(err) = store.save(&record) {
+            eprintln!("Failed to save session {}: {err}", record.id);
+        }
+    }
+    eprintln!("Embedded {embedded} saved questions");
+}
+
+/// Runs the `verify` command, exiting with status 1 if an answer may be outdated
+fn verify(args: &VerifyArgs) {
+    let record = match store_or_exit().load(&args.id) {
+        Ok(record) => record,
+        Err(err) => {
+            eprintln!("Failed to load session: {err}");
+            process::exit(1);
+        }
+    };
+    if let Some(number) = args.entry
+        && !(1..=record.entries.len()).contains(&number)
+    {
+        eprintln!(
+            "Session {} has {} answer(s); there is no answer {number}",
+            record.id,
+            record.entries.len()
+        );
+        process::exit(1);
+    }
+
+    let mut consistent = true;
+    for (index, entry) in record.entries.iter().enumerate() {
+        if args.entry.is_some_and(|number| number != index + 1) {
+            continue;
+        }
+        let verification = entry.verify(record.working_dir.as_deref());
+        println!("Answer {}: {}", index + 1, entry.question);
+        match &entry.provenance.pin {
+            Some(pin) => {
+                let commit = pin.commit.as_deref().map_or("no commit", short_hash);
+                let dirty = if pin.dirty {
+                    ", with uncommitted changes"
+                } else {
+                    ""
+                };
+                println!(
+                    "  Pinned to {commit}{dirty} (snapshot {})",
+                    short_hash(&pin.snapshot)
+                );
+            }
+            None => println!("  Not pinned; checking the recorded file hashes"),
+        }
+        for commit in &verification.commits {
+            println!("  Changed since by {commit}");
+        }
+        if verification.is_consistent() {
+            println!(
+                "  Consistent: {} consulted file(s) unchanged",
+                entry.provenance.files.len()
+            );
+        } else {
+            consistent = false;
+            println!("  May be outdated; these consulted files changed:");
+            for file in &verification.stale {
+                println!("    {} ({})", file.path.display(), file.status);
+            }
+        }
+    }
+    if !consistent {
+        process::exit(1);
+    }
+}
+
+/// Opens the session store of the current repository, exiting if it cannot be opened
+fn store_or_exit() -> SessionStore {
+    SessionStore::open_default().unwrap_or_else(|err| {
+        eprintln!("Failed to open the session store: {err}");
+        process::exit(1);
+    })
+}
+
+/// Runs the `history list` command
+fn history_list() {
+    let store = store_or_exit();
+    match store.list() {
+        Ok(records) if records.is_empty() => eprintln!("No saved sessions"),
+        Ok(records) => {
+            for record in records {
+                println!("{}", record.summary());
+            }
+        }
+        Err(err) => {
+            eprintln!("Failed to list sessions: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `history export` command
+fn history_export(args: &HistoryExportArgs) {
+    let hash_paths = args.hash_paths || config_or_exit().privacy.hash_paths;
+    match store_or_exit().load(&args.id) {
+        Ok(record) if hash_paths => match PathHasher::local() {
+            Ok(hasher) => {
+                let record = hasher.anonymize(&record);
+                println!("{}", transcript::export(&record, args.format.into()));
+            }
+            Err(err) => {
+                eprintln!("Failed to load the path salt: {err}");
+                process::exit(1);
+            }
+        },
+        Ok(record) => println!("{}", transcript::export(&record, args.format.into())),
+        Err(err) => {
+            eprintln!("Failed to load session: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `history import` command
+fn history_import(args: &HistoryImportArgs) {
+    let data = if args.file.as_os_str() == "-" {
+        io::read_to_string(io::stdin())
+    } else {
+        std::fs::read_to_string(&args.file)
+    };
+    let data = data.unwrap_or_else(|err| {
+        eprintln!("Failed to read {}: {err}", args.file.display());
+        process::exit(1);
+    });
+    let records = transcript::import(&data, args.format.into()).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+
+    let store = store_or_exit();
+    for mut record in records {
+        // Recorded paths are relative to the directory asked from, so check them here
+        record.working_dir = std::env::current_dir().ok();
+        record.id = unused_id(&store, record.id);
+        match store.save(&record) {
+            Ok(()) => eprintln!(
+                "Imported session {} with {} answer(s)",
+                record.id,
+                record.entries.len()
+            ),
+            Err(err) => {
+                eprintln!("Failed to save session {}: {err}", record.id);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+/// Returns `id`, or `id` with the first numeric suffix no stored session uses if it is
+/// taken
+fn unused_id(store: &SessionStore, id: String) -> String {
+    if store.load(&id).is_err() {
+        return id;
+    }
+    (2..)
+        .map(|n| format!("{id}-{n}"))
+        .find(|candidate| store.load(candidate).is_err())
+        .unwrap_or(id)
+}
+
+/// Loads the session `id`, exiting if it cannot be loaded
+fn session_or_exit(store: &SessionStore, id: &str) -> SessionRecord {
+    store.load(id).unwrap_or_else(|err| {
+        eprintln!("Failed to load session: {err}");
+        process::exit(1);
+    })
+}
+
+/// Runs the `history fork` command, printing the ID of the new session
+fn history_fork(args: &HistoryForkArgs) {
+    let store = store_or_exit();
+    let record = session_or_exit(&store, &args.id);
+    if let Some(at) = args.at
+        && at > record.entries.len()
+    {
+        eprintln!(
+            "Session {} has only {} answer(s)",
+            record.id,
+            record.entries.len()
+        );
+        process::exit(1);
+    }
+    let mut fork = record.fork(args.at);
+    fork.id = unused_id(&store, fork.id);
+    if let Err(err) = store.save(&fork) {
+        eprintln!("Failed to save session {}: {err}", fork.id);
+        process::exit(1);
+    }
+    eprintln!(
+        "Forked session {} with {} answer(s); continue it with `nishiogi ask --resume {}`",
+        record.id,
+        fork.entries.len(),
+        fork.id
+    );
+    println!("{}", fork.id);
+}
+
+/// Runs the `history compare` command
+fn history_compare(args: &HistoryCompareArgs) {
+    let store = store_or_exit();
+    let left = session_or_exit(&store, &args.left);
+    let right = session_or_exit(&store, &args.right);
+    print!("{}", transcript::compare(&left, &right, args.width));
+}
+
+/// Returns the grammar directory, exiting if the home directory is unknown
+fn grammars_dir_or_exit() -> PathBuf {
+    grammars::grammars_dir().unwrap_or_else(|| {
+        eprintln!("{}", grammars::GrammarError::NoDataDir);
+        process::exit(1);
+    })
+}
+
+/// Returns the location of the preferences file, or exits if it cannot be determined
+fn memory_path_or_exit() -> PathBuf {
+    memory::memory_path().unwrap_or_else(|| {
+        eprintln!("Cannot determine the home directory");
+        process::exit(1);
+    })
+}
+
+/// Runs the `memory show` command
+fn memory_show() {
+    let path = memory_path_or_exit();
+    let preferences = Preferences::load(&path).unwrap_or_else(|err| {
+        eprintln!("Failed to read {}: {err}", path.display());
+        process::exit(1);
+    });
+    if preferences.is_empty() {
+        println!("No preferences remembered");
+        return;
+    }
+    if let Some(style) = preferences.style {
+        println!("Style:    {style}");
+    }
+    if let Some(language) = &preferences.language {
+        println!("Language: {language}");
+    }
+    for (repo, counts) in &preferences.modules {
+        println!("Favorite files in {repo}:");
+        for module in preferences.favorite_modules(Path::new(repo)) {
+            println!("  {module} (consulted {} time(s))", counts[&module]);
+        }
+    }
+    if !config_or_exit().privacy.remember_preferences {
+        println!("(Not applied: remember_preferences is disabled in [privacy])");
+    }
+}
+
+/// Runs the `memory clear` command
+fn memory_clear() {
+    match memory::clear(&memory_path_or_exit()) {
+        Ok(true) => println!("Forgot the remembered preferences"),
+        Ok(false) => println!("No preferences remembered"),
+        Err(err) => {
+            eprintln!("Failed to forget the preferences: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `grammars install` command
+fn grammars_install(args: &GrammarInstallArgs) {
+    let source = args.from.as_deref().unwrap_or("the tree-sitter repository");
+    eprintln!("Building the {} grammar from {source}", args.language);
+    let installed = grammars::install(
+        &grammars_dir_or_exit(),
+        &args.language,
+        args.from.as_deref(),
+        &args.extensions,
+    );
+    match installed {
+        Ok(grammar) => println!(
+            "Installed the {} grammar for .{} files",
+            grammar.name(),
+            grammar.extensions().join(", .")
+        ),
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `grammars list` command
+fn grammars_list() {
+    let installed = grammars::load_all(&grammars_dir_or_exit());
+    if installed.is_empty() {
+        println!("No grammars installed");
+    }
+    for grammar in installed {
+        println!(
+            "{:<16} {:<24} {}",
+            grammar.name(),
+            format!(".{}", grammar.extensions().join(", .")),
+            grammar.source()
+        );
+    }
+}
+
+/// Runs the `grammars remove` command
+fn grammars_remove(args: &GrammarRemoveArgs) {
+    match grammars::remove(&grammars_dir_or_exit(), &args.language) {
+        Ok(true) => println!("Removed the {} grammar", args.language),
+        Ok(false) => {
+            eprintln!("No grammar is installed for {}", args.language);
+            process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Shortens a commit or snapshot hash for display
+fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(12)]
+}
+
+/// Returns the staged diff and the conventions detected from history, exiting if git fails
+fn staged_changes(root: &Path) -> (String, Conventions) {
+    let result = git::staged_diff(root).and_then(|diff| {
+        let subjects = git::recent_subjects(root, commit::HISTORY_DEPTH)?;
+        let branches = git::branch_names(root)?;
+        Ok((diff, Conventions::detect(&subjects, &branches)))
+    });
+    match result {
+        Ok(changes) => changes,
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Asks a yes/no question on the terminal, defaulting to no
+fn confirm(prompt: &str) -> bool {
+    eprint!("{prompt} [y/N] ");
+    if io::stderr().flush().is_err() {
+        return false;
+    }
+
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input).is_err() {
+        return false;
+    }
+    matches!(input.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Runs the `clean` command
+fn clean(args: &CleanArgs) {
+    let nothing_selected = !(args.cache || args.index || args.sessions);
+    let targets = CleanTargets {
+        cache: args.all || args.cache || nothing_selected,
+        index: args.all || args.index,
+        sessions: args.all || args.sessions,
+    };
+
+    let mut db = match Database::open_for_repo(&repo_root()) {
+        Ok(db) => db,
+        Err(err) => {
+            eprintln!("Failed to open database: {err}");
+            process::exit(1);
+        }
+    };
+    match db.clean(targets) {
+        Ok(report) => print!("{report}"),
+        Err(err) => {
+            eprintln!("Failed to clean database: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the `doctor` command, exiting with an error if any check failed
+async fn doctor() {
+    let checks = run_checks().await;
+    for check in &checks {
+        print!("{check}");
+    }
+    if checks.iter().any(|check| check.status == Status::Fail) {
+        process::exit(1);
+    }
+}
+
+/// Runs the interactive setup, returning whether a configuration file was written
+async fn setup() -> bool {
+    let stdin = io::stdin();
+    let mut prompter = Prompter::new(stdin.lock(), io::stderr());
+    match run_setup(&mut prompter).await {
+        Ok(path) => {
+            eprintln!("Configuration written to {}", path.display());
+            true
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            false
+        }
+    }
+}
+
+/// Asks whether to run the setup because no configuration file exists yet
+fn offer_setup() -> bool {
+    eprint!("No nishiogi configuration was found. Run the interactive setup now? [Y/n] ");
+    if io::stderr().flush().is_err() {
+        return false;
+    }
+
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input).is_err() {
+        return false;
+    }
+    matches!(input.trim().to_lowercase().as_str(), "" | "y" | "yes")
+}
+
+/// Prints the result of the `ask` command in the requested format
+fn print_answer(output: &AskOutput, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            println!();
+            println!("=== Answer ===");
+            println!();
+            println!("{}", output.answer);
+            if let Some(report) = output.context {
+                println!();
+                print!("{report}");
+            }
+            if let Some(report) = &output.latency {
+                println!();
+                print!("{report}");
+            }
+        }
+        OutputFormat::Json => match serde_json::to_string_pretty(output) {
+            Ok(json) => println!("{json}"),
+            Err(err) => {
+                eprintln!("Failed to serialize output: {err}");
+                process::exit(1);
+            }
+        },
+        OutputFormat::Html => {
+            let report = HtmlReport {
+                question: output.question,
+                answer: output.answer,
+                session_id: output.session_id,
+                files: output.files,
+                context: output.context,
+            };
+            print!("{}", report.render());
+        }
+    }
+}
+
+/// Looks up a stored answer to a question similar to the one being asked
+fn find_reusable(store: &SessionStore, dir: &Path, embedding: &[f64]) -> Option<ReusableAnswer> {
+    match store.list() {
+        Ok(records) => find_reusable_answer(&records, dir, embedding, REUSE_SIMILARITY),
+        Err(err) => {
+            eprintln!("Skipping answer reuse: {err}");
+            None
+        }
+    }
+}
+
+/// Shows a cached answer's question and asks whether to use it instead of a fresh run
+fn offer_reuse(reusable: &ReusableAnswer) -> bool {
+    eprintln!(
+        "A similar question was already answered in session {} ({:.0}% similar):",
+        reusable.session_id,
+        reusable.similarity * 100.0
+    );
+    eprintln!("  {}", reusable.entry.question);
+    eprint!("Use the cached answer? (pass --no-reuse to skip this check) [y/N] ");
+    if io::stderr().flush().is_err() {
+        return false;
+    }
+
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input).is_err() {
+        return false;
+    }
+    matches!(input.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Confines the agent to the named workspace member, exiting if it does not exist
+fn scope_to_package(agent: Agent, name: &str) -> Agent {
+    let root = repo_root();
+    let packages = detect_packages(&root);
+    if let Some(package) = find_package(&packages, name) {
+        eprintln!(
+            "Scoping question to package {} ({})",
+            package.name,
+            package.path.display()
+        );
+        return agent.with_package_scope(package.name.clone(), root.join(&package.path));
+    }
+
+    eprintln!("Package not found: {name}");
+    if packages.is_empty() {
+        eprintln!("No workspace members were detected in {}", root.display());
+    } else {
+        eprintln!("Available packages:");
+        for package in &packages {
+            eprintln!("  {} ({})", package.name, package.path.display());
+        }
+    }
+    process::exit(1);
+}
+
+/// Warns about files cited by a resumed session that changed since they were read
+fn warn_stale(record: &SessionRecord) {
+    let stale = record.stale_files();
+    if stale.is_empty() {
+        return;
+    }
+
+    eprintln!(
+        "Warning: {} file(s) cited in session {} changed since they were read:",
+        stale.len(),
+        record.id
+    );
+    for file in &stale {
+        eprintln!("  {} ({})", file.path.display(), file.status);
+    }
+    eprintln!("Earlier answers may be outdated; changed files will be re-read.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1m30s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("5x").is_err());
+        // Overflowing and overlong durations are errors, not panics
+        assert!(parse_duration("99999999999999999h").is_err());
+        assert!(parse_duration("18446744073709551615s1s").is_err());
+        assert!(parse_duration("99999999999999999999").is_err());
+        assert!(parse_duration("25h").is_err());
+        assert_eq!(parse_duration("24h"), Ok(MAX_DURATION));
     }
 }
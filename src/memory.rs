@@ -0,0 +1,296 @@
+//! # Preference Memory
+//!
+//! This module remembers how a user likes to be answered across sessions, so preferences
+//! stated once in a chat do not have to be repeated in every later one. The preferences are
+//! learned from chat questions and stored in `~/.nishiogi/preferences.json`:
+//!
+//! - **Verbosity**: asking for a short answer ("briefly", "tl;dr") or a detailed one ("in
+//!   detail", "step by step") sets the default `--style`.
+//! - **Language**: asking for a language ("answer in Japanese") or writing questions in a
+//!   non-Latin script sets the language answers are written in.
+//! - **Favorite modules**: the files consulted for answers, counted per repository. The
+//!   most consulted ones are mentioned to the planner.
+//!
+//! `nishiogi memory show` prints what is remembered and `nishiogi memory clear` forgets it.
+//! Setting `remember_preferences = false` in `[privacy]` stops both learning and applying.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::data_dir, style::AnswerStyle};
+
+/// Name of the file holding the preferences, inside the data directory.
+const MEMORY_FILE: &str = "preferences.json";
+
+/// Most favorite modules mentioned to the planner.
+const MAX_FAVORITES: usize = 5;
+
+/// Most modules counted per repository; the least consulted ones are forgotten first.
+const MAX_MODULES: usize = 50;
+
+/// Languages an answer can be requested in, in lowercase.
+const LANGUAGES: &[&str] = &[
+    "english",
+    "japanese",
+    "chinese",
+    "korean",
+    "french",
+    "german",
+    "spanish",
+    "portuguese",
+    "italian",
+    "russian",
+];
+
+/// A request for answers in a language, such as "answer in Japanese".
+static LANGUAGE_REQUEST: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        r"(?i)\b(?:answer|reply|respond|write|explain)\b[^.?!]*?\bin ({})\b",
+        LANGUAGES.join("|")
+    ))
+    .expect("valid regex")
+});
+
+/// What is remembered about the user.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    /// The preferred answer style, from the latest request for shorter or longer answers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<AnswerStyle>,
+    /// The language answers are written in, such as `Japanese`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// How often each file was consulted, by repository.
+    pub modules: BTreeMap<String, BTreeMap<String, u32>>,
+}
+
+impl Preferences {
+    /// Loads the preferences at `path`, or empty preferences if there are none yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not valid.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Writes the preferences to `path`, creating its directory if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, text)
+    }
+
+    /// Returns whether nothing is remembered.
+    pub fn is_empty(&self) -> bool {
+        self.style.is_none() && self.language.is_none() && self.modules.is_empty()
+    }
+
+    /// Learns from a chat `question` asked in `repo` whose answer consulted `files`.
+    pub fn learn(&mut self, question: &str, repo: &Path, files: &[PathBuf]) {
+        if let Some(style) = requested_style(question) {
+            self.style = Some(style);
+        }
+        if let Some(language) = requested_language(question).or_else(|| script_language(question)) {
+            self.language = Some(language.to_string());
+        }
+
+        let counts = self.modules.entry(repo_key(repo)).or_default();
+        for file in files {
+            let path = file.to_string_lossy().replace('\\', "/");
+            *counts
+                .entry(path.trim_start_matches("./").to_string())
+                .or_default() += 1;
+        }
+        while counts.len() > MAX_MODULES {
+            let Some(least) = ranked(counts).last().map(|(path, _)| path.to_string()) else {
+                break;
+            };
+            counts.remove(&least);
+        }
+        if counts.is_empty() {
+            self.modules.remove(&repo_key(repo));
+        }
+    }
+
+    /// Returns the files most often consulted in `repo`, most consulted first.
+    pub fn favorite_modules(&self, repo: &Path) -> Vec<String> {
+        self.modules
+            .get(&repo_key(repo))
+            .map(|counts| {
+                ranked(counts)
+                    .into_iter()
+                    .take(MAX_FAVORITES)
+                    .map(|(path, _)| path.to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Returns the location of the preferences file.
+///
+/// Returns `None` when the home directory cannot be determined.
+pub fn memory_path() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join(MEMORY_FILE))
+}
+
+/// Forgets the preferences at `path`, returning whether there were any.
+///
+/// # Errors
+///
+/// Returns an error if the file exists but cannot be removed.
+pub fn clear(path: &Path) -> io::Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns the key of `repo` in [`Preferences::modules`].
+fn repo_key(repo: &Path) -> String {
+    repo.canonicalize()
+        .unwrap_or_else(|_| repo.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Returns `counts` from the most to the least consulted, by path among equals.
+fn ranked(counts: &BTreeMap<String, u32>) -> Vec<(&str, u32)> {
+    let mut ranked: Vec<(&str, u32)> = counts
+        .iter()
+        .map(|(path, count)| (path.as_str(), *count))
+        .collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    ranked
+}
+
+/// Returns the style `question` asks for, if it asks for one.
+fn requested_style(question: &str) -> Option<AnswerStyle> {
+    let question = question.to_lowercase();
+    let mentions = |phrases: &[&str]| phrases.iter().any(|phrase| question.contains(phrase));
+    if mentions(&[
+        "briefly",
+        "be brief",
+        "keep it short",
+        "shorter",
+        "in short",
+        "concise",
+        "tl;dr",
+    ]) {
+        Some(AnswerStyle::Brief)
+    } else if mentions(&[
+        "in detail",
+        "more detail",
+        "detailed",
+        "elaborate",
+        "deep dive",
+        "step by step",
+    ]) {
+        Some(AnswerStyle::Deep)
+    } else {
+        None
+    }
+}
+
+/// Returns the language `question` asks answers in, capitalized, if it asks for one.
+fn requested_language(question: &str) -> Option<String> {
+    let name = LANGUAGE_REQUEST.captures(question)?.get(1)?.as_str();
+    let mut chars = name.chars();
+    let first = chars.next()?.to_ascii_uppercase();
+    Some(format!("{first}{}", chars.as_str().to_lowercase()))
+}
+
+/// Returns the language of the non-Latin script `question` is written in, if any.
+fn script_language(question: &str) -> Option<String> {
+    let count = |range: &[(char, char)]| {
+        question
+            .chars()
+            .filter(|c| range.iter().any(|&(start, end)| (start..=end).contains(c)))
+            .count()
+    };
+    let language = if count(&[('\u{3040}', '\u{30ff}')]) > 0 {
+        "Japanese"
+    } else if count(&[('\u{ac00}', '\u{d7af}'), ('\u{1100}', '\u{11ff}')]) > 0 {
+        "Korean"
+    } else if count(&[('\u{4e00}', '\u{9fff}')]) > 0 {
+        "Chinese"
+    } else if count(&[('\u{0400}', '\u{04ff}')]) > 0 {
+        "Russian"
+    } else {
+        return None;
+    };
+    Some(language.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_preferences() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let repo = dir.path();
+        let mut preferences = Preferences::default();
+        preferences.learn(
+            "Briefly, where is the config loaded? Answer in japanese please.",
+            repo,
+            &[
+                PathBuf::from("./src/config.rs"),
+                PathBuf::from("src/main.rs"),
+            ],
+        );
+        preferences.learn(
+            "設定ファイルはどこで読み込まれますか?",
+            repo,
+            &[PathBuf::from("src/config.rs")],
+        );
+        assert_eq!(preferences.style, Some(AnswerStyle::Brief));
+        assert_eq!(preferences.language.as_deref(), Some("Japanese"));
+        assert_eq!(
+            preferences.favorite_modules(repo),
+            vec!["src/config.rs".to_string(), "src/main.rs".to_string()]
+        );
+
+        // Later requests replace earlier ones; other questions change nothing
+        preferences.learn("Explain the planner step by step", repo, &[]);
+        preferences.learn("Where are sessions saved?", repo, &[]);
+        assert_eq!(preferences.style, Some(AnswerStyle::Deep));
+        assert_eq!(preferences.language.as_deref(), Some("Japanese"));
+
+        let path = dir.path().join("memory").join(MEMORY_FILE);
+        assert_eq!(
+            Preferences::load(&path).expect("Failed to load"),
+            Preferences::default()
+        );
+        preferences.save(&path).expect("Failed to save");
+        assert_eq!(
+            Preferences::load(&path).expect("Failed to load"),
+            preferences
+        );
+        assert!(clear(&path).expect("Failed to clear"));
+        assert!(!clear(&path).expect("Failed to clear"));
+    }
+}
@@ -0,0 +1,398 @@
+//! # Migration Assistant
+//!
+//! This module implements `nishiogi migrate`, which helps move a codebase from one version
+//! of a library or framework to another (for example `--from "tokio 0.2" --to "tokio 1"`):
+//!
+//! 1. The model lists the APIs that change between the versions, each with a pattern that
+//!    matches its uses.
+//! 2. Every file is searched for the patterns, and each use is attributed to the item it
+//!    appears in with the symbol index.
+//! 3. The required changes are summarized per file.
+//! 4. Optionally, the model rewrites each affected file as search/replace edits, which are
+//!    collected into one patch for review.
+//!
+//! The list of changes comes from the model's knowledge of the library, so uses of APIs it
+//! does not mention are not found; the summary is a starting point, not a guarantee.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    agent::AgentError,
+    github_copilot_client::Message,
+    patch::{Draft, Patch, Replacement},
+    review::ReviewModel,
+    search::collect_files,
+    symbols::SymbolIndex,
+    tree::find_gitignore_patterns,
+};
+
+/// Upper bound on the uses collected, to keep prompts and output manageable.
+pub const MAX_USAGES: usize = 500;
+
+/// Number of attempts at rewriting one file before it is skipped.
+const MAX_ATTEMPTS: usize = 2;
+
+/// An API that changes between the two versions.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ApiChange {
+    /// The API, e.g. `tokio::time::delay_for`.
+    pub api: String,
+    /// Regular expression matching uses of the API in source code.
+    pub pattern: String,
+    /// What has to change, e.g. "renamed to `tokio::time::sleep`".
+    pub change: String,
+}
+
+/// A use of a changed API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    /// Index of the change in the list of changes.
+    pub change: usize,
+    /// The file, relative to the searched root.
+    pub path: PathBuf,
+    /// Line of the use (1-based).
+    pub line: usize,
+    /// The line's text, trimmed.
+    pub text: String,
+    /// The item the use appears in, if the symbol index knows it.
+    pub enclosing: Option<String>,
+}
+
+/// Asks the model which APIs change when migrating from `from` to `to`.
+///
+/// Changes whose pattern is not a valid regular expression are dropped with a warning.
+///
+/// # Errors
+///
+/// Returns an `AgentError` if the request fails, or `AgentError::Other` if the reply is
+/// not a list of changes.
+pub async fn identify_changes<M: ReviewModel>(
+    model: &mut M,
+    from: &str,
+    to: &str,
+) -> Result<Vec<ApiChange>, AgentError> {
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: "You are an expert in library upgrades. List breaking and deprecated API changes precisely; do not invent changes you are unsure of.".to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!(
+                "List the API changes a codebase needs when migrating from {from} to {to}. Respond with JSON only, in this format:\n\n[{{\"api\": \"old API\", \"pattern\": \"regular expression matching uses of it\", \"change\": \"what to change it to\"}}]\n\nPatterns use Rust regex syntax and are matched against single lines; include the dependency declaration in manifests if its version must change."
+            ),
+        },
+    ];
+    let reply = model.complete(messages).await?;
+    let changes: Vec<ApiChange> = reply
+        .find('[')
+        .zip(reply.rfind(']'))
+        .and_then(|(start, end)| reply.get(start..=end))
+        .and_then(|json| serde_json::from_str(json).ok())
+        .ok_or_else(|| {
+            AgentError::Other(format!("Unexpected list of changes: {}", reply.trim()))
+        })?;
+
+    Ok(changes
+        .into_iter()
+        .filter(|change| {
+            let valid = Regex::new(&change.pattern).is_ok();
+            if !valid {
+                eprintln!(
+                    "warning: skipping {}: invalid pattern {}",
+                    change.api, change.pattern
+                );
+            }
+            valid
+        })
+        .collect())
+}
+
+/// Finds the uses of `changes` in the files under `root`.
+///
+/// At most [`MAX_USAGES`] uses are returned, in file and line order.
+pub fn find_usages(root: &Path, changes: &[ApiChange]) -> Vec<Usage> {
+    let patterns: Vec<Option<Regex>> = changes
+        .iter()
+        .map(|change| Regex::new(&change.pattern).ok())
+        .collect();
+    let ignore = find_gitignore_patterns(root).unwrap_or_default();
+    let mut files = Vec::new();
+    collect_files(root, root, &ignore, &mut files);
+
+    let mut usages = Vec::new();
+    let mut affected_files = Vec::new();
+    'files: for path in files {
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let rel_path = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        for (index, line) in content.lines().enumerate() {
+            let Some(change) = patterns
+                .iter()
+                .position(|pattern| pattern.as_ref().is_some_and(|p| p.is_match(line)))
+            else {
+                continue;
+            };
+            if usages.len() == MAX_USAGES {
+                break 'files;
+            }
+            if affected_files.last() != Some(&path) {
+                affected_files.push(path.clone());
+            }
+            usages.push(Usage {
+                change,
+                path: rel_path.clone(),
+                line: index + 1,
+                text: line.trim().to_string(),
+                enclosing: None,
+            });
+        }
+    }
+
+    let index = SymbolIndex::from_files(root, &affected_files);
+    for usage in &mut usages {
+        usage.enclosing = index
+            .symbols()
+            .iter()
+            .rfind(|symbol| symbol.path == usage.path && symbol.line <= usage.line)
+            .map(|symbol| format!("{} {}", symbol.kind, symbol.name));
+    }
+    usages
+}
+
+/// Renders the required changes grouped by file.
+pub fn render_summary(changes: &[ApiChange], usages: &[Usage]) -> String {
+    let by_file = group_by_file(usages);
+    let mut output = format!(
+        "Migration summary: {} use(s) of {} changed API(s) in {} file(s)\n",
+        usages.len(),
+        changes.len(),
+        by_file.len()
+    );
+    for (path, file_usages) in &by_file {
+        let _ = writeln!(output, "\n{}:", path.display());
+        for usage in file_usages {
+            let change = &changes[usage.change];
+            let location = match &usage.enclosing {
+                Some(enclosing) => format!("line {} (in {enclosing})", usage.line),
+                None => format!("line {}", usage.line),
+            };
+            let _ = writeln!(
+                output,
+                "  {location}: {} -> {}\n    {}",
+                change.api, change.change, usage.text
+            );
+        }
+    }
+
+    let unused: Vec<&str> = changes
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !usages.iter().any(|usage| usage.change == *index))
+        .map(|(_, change)| change.api.as_str())
+        .collect();
+    if !unused.is_empty() {
+        let _ = writeln!(output, "\nNot used: {}", unused.join(", "));
+    }
+    output
+}
+
+/// Asks the model to rewrite the uses in each affected file and returns the result as one
+/// patch.
+///
+/// Files whose edits still do not apply after a retry are skipped with a warning.
+///
+/// # Errors
+///
+/// Returns an `AgentError` if a request to the model fails.
+pub async fn propose_patch<M: ReviewModel>(
+    model: &mut M,
+    root: &Path,
+    changes: &[ApiChange],
+    usages: &[Usage],
+) -> Result<Patch, AgentError> {
+    let mut draft = Draft::new(root);
+    let mut patch = Patch::default();
+    for (path, file_usages) in group_by_file(usages) {
+        eprintln!("Migrating {}", path.display());
+        let Ok(content) = draft.content(path).map(str::to_string) else {
+            continue;
+        };
+        let mut messages = rewrite_messages(path, &content, changes, &file_usages);
+        for attempt in 1..=MAX_ATTEMPTS {
+            let reply = model.complete(messages.clone()).await?;
+            let result = parse_edits(&reply)
+                .ok_or_else(|| "The reply could not be parsed as the requested JSON.".to_string())
+                .and_then(|mut edits| {
+                    // The edits may only touch the file being migrated
+                    edits
+                        .iter_mut()
+                        .for_each(|edit| edit.path = path.to_path_buf());
+                    draft.edit(&edits).map_err(|err| err.to_string())
+                });
+            match result {
+                Ok(file_patch) => {
+                    patch.then(file_patch);
+                    break;
+                }
+                Err(reason) if attempt < MAX_ATTEMPTS => {
+                    messages.push(Message {
+                        role: "assistant".to_string(),
+                        content: reply,
+                    });
+                    messages.push(Message {
+                        role: "user".to_string(),
+                        content: format!(
+                            "{reason}\n\nRespond with corrected edits, in the same JSON format."
+                        ),
+                    });
+                }
+                Err(reason) => {
+                    eprintln!("warning: skipping {}: {reason}", path.display());
+                }
+            }
+        }
+    }
+    Ok(patch)
+}
+
+/// Groups uses by file, keeping file and line order.
+fn group_by_file(usages: &[Usage]) -> BTreeMap<&Path, Vec<&Usage>> {
+    let mut by_file: BTreeMap<&Path, Vec<&Usage>> = BTreeMap::new();
+    for usage in usages {
+        by_file.entry(&usage.path).or_default().push(usage);
+    }
+    by_file
+}
+
+/// Builds the request asking the model to rewrite the uses in one file.
+fn rewrite_messages(
+    path: &Path,
+    content: &str,
+    changes: &[ApiChange],
+    usages: &[&Usage],
+) -> Vec<Message> {
+    let listed: String = usages
+        .iter()
+        .map(|usage| {
+            let change = &changes[usage.change];
+            format!(
+                "- line {}: {} -> {}\n",
+                usage.line, change.api, change.change
+            )
+        })
+        .collect();
+    vec![
+        Message {
+            role: "system".to_string(),
+            content: "You are migrating code to a new library version. Make only the changes the migration requires and keep the code's behavior.".to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!(
+                "File {}:\n\n```\n{content}```\n\nRequired changes:\n\n{listed}\nRespond with JSON only, in this format:\n\n[{{\"search\": \"exact existing text\", \"replace\": \"new text\"}}]\n\n`search` must be copied exactly from the file and occur exactly once in it.",
+                path.display()
+            ),
+        },
+    ]
+}
+
+/// Extracts the edits from the model's reply, tolerating text around the JSON.
+fn parse_edits(reply: &str) -> Option<Vec<Replacement>> {
+    /// An edit as proposed for a single file, without a path.
+    #[derive(Deserialize)]
+    struct Edit {
+        search: String,
+        replace: String,
+    }
+
+    let edits: Vec<Edit> = reply
+        .find('[')
+        .zip(reply.rfind(']'))
+        .and_then(|(start, end)| reply.get(start..=end))
+        .and_then(|json| serde_json::from_str(json).ok())?;
+    Some(
+        edits
+            .into_iter()
+            .map(|edit| Replacement {
+                path: PathBuf::new(),
+                search: edit.search,
+                replace: edit.replace,
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Lists one change, then rewrites the file, getting the edit wrong the first time.
+    struct ScriptedModel {
+        rewrites: usize,
+    }
+
+    #[async_trait]
+    impl ReviewModel for ScriptedModel {
+        async fn complete(&mut self, messages: Vec<Message>) -> Result<String, AgentError> {
+            if messages[1].content.starts_with("List the API changes") {
+                return Ok(r#"[{"api": "tokio::time::delay_for", "pattern": "delay_for\\(", "change": "renamed to tokio::time::sleep"},
+                    {"api": "broken", "pattern": "(", "change": "none"},
+                    {"api": "tokio::net::TcpListener::incoming", "pattern": "\\.incoming\\(", "change": "use accept in a loop"}]"#.to_string());
+            }
+            self.rewrites += 1;
+            Ok(if self.rewrites == 1 {
+                r#"[{"search": "delay_for(d)", "replace": "sleep(d)"}]"#.to_string()
+            } else {
+                r#"[{"search": "time::delay_for(d)", "replace": "time::sleep(d)"}]"#.to_string()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migration_finds_and_rewrites_usages() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        let content = "async fn pause(d: Duration) {\n    // delay_for(d) is gone in tokio 1\n    tokio::time::delay_for(d).await;\n}\n";
+        fs::write(root.join("lib.rs"), content).expect("Failed to write file");
+
+        let mut model = ScriptedModel { rewrites: 0 };
+        let changes = identify_changes(&mut model, "tokio 0.2", "tokio 1")
+            .await
+            .expect("Failed to identify changes");
+        assert_eq!(changes.len(), 2);
+
+        let usages = find_usages(root, &changes);
+        assert_eq!(usages.len(), 2);
+        assert_eq!(usages[1].line, 3);
+        assert_eq!(usages[1].enclosing.as_deref(), Some("function pause"));
+
+        let summary = render_summary(&changes, &usages);
+        assert!(
+            summary.starts_with("Migration summary: 2 use(s) of 2 changed API(s) in 1 file(s)\n")
+        );
+        assert!(summary.contains("  line 3 (in function pause): tokio::time::delay_for -> renamed to tokio::time::sleep\n"));
+        assert!(summary.contains("Not used: tokio::net::TcpListener::incoming\n"));
+
+        let patch = propose_patch(&mut model, root, &changes, &usages)
+            .await
+            .expect("Failed to propose patch");
+        assert_eq!(model.rewrites, 2);
+        assert_eq!(
+            patch.edits[0].updated,
+            content.replace("time::delay_for(d)", "time::sleep(d)")
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! # OpenAI Client
+//!
+//! This module talks to the [OpenAI API](https://platform.openai.com/docs/api-reference)
+//! directly, for users with an OpenAI API key instead of a Copilot subscription. It is
+//! selected with `--provider openai` and reads the key from `OPENAI_API_KEY`.
+//!
+//! Chat completions use the same request format as Copilot, and replies can be streamed or
+//! constrained to JSON.
+//! Embeddings are computed with `text-embedding-3-small`.
+
+#[cfg(feature = "native")]
+use std::env;
+
+use async_trait::async_trait;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    Client as HttpClient,
+};
+use serde::Deserialize;
+
+use crate::{
+    github_copilot_client::{
+        ChatRequest, ChatResponse, CopilotError, Embedding, EmbeddingRequest, EmbeddingResponse,
+        Message, Model, ResponseFormat,
+    },
+    org_policy,
+    provider::{
+        api_key_error, apply_policy, check_provider, read_stream, Capabilities, LlmProvider,
+        TextSink,
+    },
+    scheduler,
+};
+
+/// Name of this provider in `--provider` and the `[limits.providers]` section.
+pub const PROVIDER: &str = "openai";
+
+/// The model used unless another one is configured.
+pub const DEFAULT_MODEL: &str = "gpt-4o";
+
+/// Environment variable holding the API key.
+pub const API_KEY_VAR: &str = "OPENAI_API_KEY";
+
+/// Base URL of the OpenAI API.
+const API_URL: &str = "https://api.openai.com/v1";
+
+/// Model computing embeddings.
+const EMBEDDING_MODEL: &str = "text-embedding-3-small";
+
+/// Context window of the GPT-4o models, which the models API does not report.
+const CONTEXT_WINDOW: usize = 128_000;
+
+/// Prefixes of the IDs of models accepting images.
+const VISION_MODELS: &[&str] = &["gpt-4o", "gpt-4.1", "gpt-5", "o3", "o4"];
+
+/// Dimensions of the embeddings, matching the Copilot ones.
+const EMBEDDING_DIMENSIONS: u32 = 512;
+
+/// A model, as listed by the OpenAI API.
+#[derive(Debug, Deserialize)]
+struct OpenAiModel {
+    id: String,
+}
+
+/// Response payload for listing models.
+#[derive(Debug, Deserialize)]
+struct OpenAiModelsResponse {
+    data: Vec<OpenAiModel>,
+}
+
+/// Client for the OpenAI API.
+pub struct OpenAiClient {
+    http_client: HttpClient,
+    api_key: String,
+    /// List of available models.
+    models: Vec<Model>,
+}
+
+impl OpenAiClient {
+    /// Creates a client with the API key in `OPENAI_API_KEY`, fetching the available
+    /// models.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CopilotError::ApiKey` if the variable is not set or the key is rejected, or
+    /// another `CopilotError` if the models cannot be fetched.
+    #[cfg(feature = "native")]
+    pub async fn from_env() -> Result<Self, CopilotError> {
+        let api_key = env::var(API_KEY_VAR)
+            .ok()
+            .filter(|key| !key.is_empty())
+            .ok_or_else(|| CopilotError::ApiKey(format!("{API_KEY_VAR} is not set")))?;
+        Self::new_with_models(api_key).await
+    }
+
+    /// Creates a client with the given API key, fetching the available models.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CopilotError::ApiKey` if the key is rejected, or another `CopilotError` if
+    /// the models cannot be fetched.
+    pub async fn new_with_models(api_key: String) -> Result<Self, CopilotError> {
+        let mut client = OpenAiClient {
+            http_client: HttpClient::new(),
+            api_key,
+            models: Vec::new(),
+        };
+        client.models = client.get_models().await?;
+        Ok(client)
+    }
+
+    /// Constructs the headers of OpenAI API requests.
+    fn get_headers(&self) -> Result<HeaderMap, CopilotError> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", self.api_key))
+                .map_err(|e| CopilotError::ApiKey(e.to_string()))?,
+        );
+        Ok(headers)
+    }
+
+    /// Fetches the models available to the API key.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `CopilotError` if the HTTP request fails or the response cannot be parsed.
+    pub async fn get_models(&self) -> Result<Vec<Model>, CopilotError> {
+        let res = self
+            .http_client
+            .get(format!("{API_URL}/models"))
+            .headers(self.get_headers()?)
+            .send()
+            .await
+            .map_err(api_key_error)?
+            .error_for_status()
+            .map_err(api_key_error)?;
+        let models: OpenAiModelsResponse = res
+            .json()
+            .await
+            .map_err(|e| CopilotError::Other(e.to_string()))?;
+        Ok(models
+            .data
+            .into_iter()
+            .map(|model| Model {
+                name: model.id.clone(),
+                id: model.id,
+                version: None,
+                tokenizer: None,
+                max_input_tokens: None,
+                max_output_tokens: None,
+            })
+            .collect())
+    }
+
+    /// Sends a chat completion request, returning the response before its body is read.
+    async fn send_chat(
+        &self,
+        messages: Vec<Message>,
+        model_id: &str,
+        max_tokens: Option<u32>,
+        stream: bool,
+        response_format: Option<ResponseFormat>,
+    ) -> Result<reqwest::Response, CopilotError> {
+        if !self.has_model(model_id) {
+            return Err(CopilotError::InvalidModel(model_id.to_string()));
+        }
+        let messages = apply_policy(PROVIDER, model_id, messages)?;
+        let _permit = scheduler::global().llm_call(PROVIDER).await;
+        let request_body = ChatRequest {
+            model: model_id.to_string(),
+            messages,
+            n: 1,
+            top_p: 1.0,
+            stream,
+            temperature: 0.5,
+            max_tokens,
+            response_format,
+        };
+        self.http_client
+            .post(format!("{API_URL}/chat/completions"))
+            .headers(self.get_headers()?)
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(api_key_error)?
+            .error_for_status()
+            .map_err(api_key_error)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl LlmProvider for OpenAiClient {
+    fn name(&self) -> &'static str {
+        PROVIDER
+    }
+
+    fn models(&self) -> &[Model] {
+        &self.models
+    }
+
+    fn capabilities(&self, model_id: &str) -> Capabilities {
+        Capabilities {
+            tool_calling: true,
+            json_mode: true,
+            streaming: true,
+            vision: VISION_MODELS
+                .iter()
+                .any(|prefix| model_id.starts_with(prefix)),
+            max_context: CONTEXT_WINDOW,
+        }
+    }
+
+    async fn chat(
+        &self,
+        messages: Vec<Message>,
+        model_id: &str,
+        _stable_prefix: usize,
+        max_tokens: Option<u32>,
+    ) -> Result<ChatResponse, CopilotError> {
+        // OpenAI caches long prompt prefixes automatically, without markers
+        let res = self
+            .send_chat(messages, model_id, max_tokens, false, None)
+            .await?;
+        res.json()
+            .await
+            .map_err(|e| CopilotError::Other(e.to_string()))
+    }
+
+    async fn chat_json(
+        &self,
+        messages: Vec<Message>,
+        model_id: &str,
+        _stable_prefix: usize,
+        max_tokens: Option<u32>,
+    ) -> Result<ChatResponse, CopilotError> {
+        let format = ResponseFormat::json_object();
+        let res = self
+            .send_chat(messages, model_id, max_tokens, false, Some(format))
+            .await?;
+        res.json()
+            .await
+            .map_err(|e| CopilotError::Other(e.to_string()))
+    }
+
+    async fn stream(
+        &self,
+        messages: Vec<Message>,
+        model_id: &str,
+        max_tokens: Option<u32>,
+        on_text: &mut TextSink<'_>,
+    ) -> Result<ChatResponse, CopilotError> {
+        let res = self
+            .send_chat(messages, model_id, max_tokens, true, None)
+            .await?;
+        read_stream(res, on_text).await
+    }
+
+    async fn embeddings(&self, inputs: Vec<String>) -> Result<Vec<Embedding>, CopilotError> {
+        check_provider(PROVIDER)?;
+        let policy = org_policy::global();
+        let input = inputs
+            .iter()
+            .map(|input| policy.redact(input).into_owned())
+            .collect();
+        let _permit = scheduler::global().llm_call(PROVIDER).await;
+        let request_body = EmbeddingRequest {
+            dimensions: EMBEDDING_DIMENSIONS,
+            input,
+            model: EMBEDDING_MODEL.to_string(),
+        };
+        let res = self
+            .http_client
+            .post(format!("{API_URL}/embeddings"))
+            .headers(self.get_headers()?)
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(api_key_error)?
+            .error_for_status()
+            .map_err(api_key_error)?;
+        let embeddings: EmbeddingResponse = res
+            .json()
+            .await
+            .map_err(|e| CopilotError::Other(e.to_string()))?;
+        Ok(embeddings.data)
+    }
+}
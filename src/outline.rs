@@ -0,0 +1,362 @@
+//! # Symbol Outlines
+//!
+//! This module outlines a source file from its syntax tree: the signatures of its
+//! functions, types, traits, classes, and impl blocks, nested as in the file, with their
+//! line numbers and without their bodies. It backs the `outline <file>` command, which lets
+//! the model learn the shape of a 3,000-line file for a few hundred tokens and then read
+//! only the lines it needs with `show_file <file>:<start>-<end>`.
+//!
+//! Files are parsed with [tree-sitter], whose grammars are compiled in with the `outline`
+//! feature:
+//!
+//! - Rust (`.rs`): functions, structs, enums, unions, traits, impl blocks, modules, type
+//!   aliases, constants, statics, and macros
+//! - Python (`.py`): functions and classes, with their methods
+//! - TypeScript and JavaScript (`.ts`, `.tsx`, `.js`, `.jsx`, `.mjs`, `.cjs`): functions,
+//!   classes with their methods, interfaces, type aliases, enums, namespaces, and functions
+//!   bound to variables
+//!
+//! Grammars for other languages can be installed with `nishiogi grammars install <language>`
+//! and are loaded at run time (see [`grammars`](crate::grammars)). Their outline is guessed
+//! from the names of the syntax nodes: declarations and definitions are shown, and those
+//! of classes, interfaces, modules, and the like are shown with their members.
+//!
+//! Unlike the line patterns of [`symbols`](crate::symbols), the parser sees signatures
+//! spanning several lines and the methods of impl blocks and classes. Files in languages
+//! without a grammar are outlined by those patterns instead.
+//!
+//! [tree-sitter]: https://tree-sitter.github.io
+
+use std::path::Path;
+
+use tree_sitter::{Language, Node, Parser};
+
+use crate::grammars;
+
+/// Longest signature shown, in characters; longer ones are cut.
+const MAX_SIGNATURE_CHARS: usize = 200;
+
+/// A language whose grammar is compiled in or installed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grammar {
+    /// Rust.
+    Rust,
+    /// Python.
+    Python,
+    /// TypeScript.
+    TypeScript,
+    /// TypeScript with JSX, which also parses JavaScript.
+    Tsx,
+    /// A grammar installed with `nishiogi grammars install`.
+    Installed(&'static Language),
+}
+
+/// How a syntax node appears in the outline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    /// Shown without its body.
+    Item,
+    /// Shown with the items of its body nested under it.
+    Container,
+    /// Not shown, but its children may be items, as for `export` statements.
+    Wrapper,
+    /// Neither shown nor searched.
+    Other,
+}
+
+impl Grammar {
+    /// Detects the grammar of a file from its extension.
+    pub fn of(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "rs" => Some(Grammar::Rust),
+            "py" => Some(Grammar::Python),
+            "ts" | "mts" | "cts" => Some(Grammar::TypeScript),
+            // JavaScript is mostly TypeScript without types, and JSX needs the TSX grammar
+            "tsx" | "js" | "jsx" | "mjs" | "cjs" => Some(Grammar::Tsx),
+            _ => grammars::for_path(path).map(|grammar| Grammar::Installed(grammar.language())),
+        }
+    }
+
+    /// Returns the grammar's language.
+    fn language(self) -> Language {
+        match self {
+            Grammar::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Grammar::Python => tree_sitter_python::LANGUAGE.into(),
+            Grammar::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Grammar::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+            Grammar::Installed(language) => language.clone(),
+        }
+    }
+
+    /// Returns how nodes of `kind` appear in the outline.
+    fn role(self, kind: &str) -> Role {
+        match self {
+            Grammar::Rust => match kind {
+                "function_item"
+                | "function_signature_item"
+                | "struct_item"
+                | "enum_item"
+                | "union_item"
+                | "type_item"
+                | "const_item"
+                | "static_item"
+                | "macro_definition"
+                | "associated_type" => Role::Item,
+                "impl_item" | "trait_item" | "mod_item" => Role::Container,
+                _ => Role::Other,
+            },
+            Grammar::Python => match kind {
+                "function_definition" => Role::Item,
+                "class_definition" => Role::Container,
+                "decorated_definition" => Role::Wrapper,
+                _ => Role::Other,
+            },
+            Grammar::TypeScript | Grammar::Tsx => match kind {
+                "function_declaration"
+                | "generator_function_declaration"
+                | "function_signature"
+                | "method_definition"
+                | "method_signature"
+                | "abstract_method_signature"
+                | "interface_declaration"
+                | "type_alias_declaration"
+                | "enum_declaration" => Role::Item,
+                "class_declaration" | "abstract_class_declaration" | "internal_module" => {
+                    Role::Container
+                }
+                "export_statement" | "ambient_declaration" | "expression_statement" => {
+                    Role::Wrapper
+                }
+                // Only shown when they bind a function, see `function_binding`
+                "lexical_declaration" | "variable_declaration" => Role::Item,
+                _ => Role::Other,
+            },
+            Grammar::Installed(_) => installed_role(kind),
+        }
+    }
+}
+
+/// Guesses how nodes of `kind` appear in the outline for an installed grammar, whose node
+/// kinds are only known by their names, such as `method_declaration` or `class`.
+fn installed_role(kind: &str) -> Role {
+    let words: Vec<&str> = kind.split('_').collect();
+    if matches!(words[0], "export" | "decorated" | "template") {
+        return Role::Wrapper;
+    }
+    // Imports, and the declarations inside signatures and bodies
+    if words.iter().any(|word| {
+        matches!(
+            *word,
+            "import"
+                | "package"
+                | "use"
+                | "using"
+                | "include"
+                | "parameter"
+                | "variable"
+                | "field"
+                | "local"
+        )
+    }) {
+        return Role::Other;
+    }
+    let declares = matches!(
+        words.as_slice(),
+        [_, .., "declaration" | "definition" | "item"]
+    ) || matches!(
+        kind,
+        "class" | "module" | "function" | "method" | "singleton_method"
+    );
+    if !declares {
+        Role::Other
+    } else if words.iter().any(|word| {
+        matches!(
+            *word,
+            "class"
+                | "impl"
+                | "trait"
+                | "interface"
+                | "module"
+                | "namespace"
+                | "object"
+                | "protocol"
+        )
+    }) {
+        Role::Container
+    } else {
+        Role::Item
+    }
+}
+
+/// Outlines `content`, the source of the file at `path`.
+///
+/// Returns `None` if there is no grammar for the file's language, or it defines nothing.
+pub fn outline(path: &Path, content: &str) -> Option<String> {
+    let grammar = Grammar::of(path)?;
+    let mut parser = Parser::new();
+    parser.set_language(&grammar.language()).ok()?;
+    let tree = parser.parse(content, None)?;
+    let mut output = String::new();
+    outline_children(grammar, tree.root_node(), content, 0, &mut output);
+    (!output.is_empty()).then_some(output)
+}
+
+/// Appends the outline of the items among the children of `node`, indented by `depth`.
+fn outline_children(
+    grammar: Grammar,
+    node: Node<'_>,
+    source: &str,
+    depth: usize,
+    out: &mut String,
+) {
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        match grammar.role(child.kind()) {
+            Role::Item => {
+                let end = match child.kind() {
+                    "lexical_declaration" | "variable_declaration" => {
+                        let Some(body) = function_binding(child) else {
+                            continue;
+                        };
+                        body.start_byte()
+                    }
+                    "const_item" | "static_item" => child
+                        .child_by_field_name("value")
+                        .map_or(child.end_byte(), |value| value.start_byte()),
+                    _ => child
+                        .child_by_field_name("body")
+                        .map_or(child.end_byte(), |body| body.start_byte()),
+                };
+                push_signature(child, &source[child.start_byte()..end], depth, out);
+            }
+            Role::Container => {
+                let body = child.child_by_field_name("body");
+                let end = body.map_or(child.end_byte(), |body| body.start_byte());
+                push_signature(child, &source[child.start_byte()..end], depth, out);
+                if let Some(body) = body {
+                    outline_children(grammar, body, source, depth + 1, out);
+                }
+            }
+            Role::Wrapper => outline_children(grammar, child, source, depth, out),
+            Role::Other => {}
+        }
+    }
+}
+
+/// Returns the body of the function a variable declaration binds, as in
+/// `const handler = async (req) => { ... }`, or `None` if it binds no function.
+fn function_binding(declaration: Node<'_>) -> Option<Node<'_>> {
+    let mut cursor = declaration.walk();
+    let declarators: Vec<Node<'_>> = declaration.named_children(&mut cursor).collect();
+    declarators.into_iter().find_map(|declarator| {
+        let value = declarator.child_by_field_name("value")?;
+        match value.kind() {
+            "arrow_function" | "function_expression" | "function" => {
+                value.child_by_field_name("body")
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Appends the line of the item `node`, whose signature is `text`, indented by `depth`.
+fn push_signature(node: Node<'_>, text: &str, depth: usize, out: &mut String) {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    // What introduces the body or the value is not part of the signature
+    let text = text.strip_suffix("=>").unwrap_or(&text).trim_end();
+    let mut signature = text
+        .trim_end_matches(['=', ':', ';'])
+        .trim_end()
+        .to_string();
+    if signature.chars().count() > MAX_SIGNATURE_CHARS {
+        signature = signature.chars().take(MAX_SIGNATURE_CHARS).collect();
+        signature.push_str(" ...");
+    }
+    out.push_str(&format!(
+        "{:>5}  {}{signature}\n",
+        node.start_position().row + 1,
+        "  ".repeat(depth)
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outline() {
+        let rust = r#"
+/// Docs are left out.
+pub struct Agent {
+    model: String,
+}
+
+impl Agent {
+    pub async fn process_query(
+        &mut self,
+        query: &str,
+    ) -> Result<String, AgentError> {
+        Ok(query.to_string())
+    }
+}
+
+pub const LIMIT: usize = 3;
+"#;
+        assert_eq!(
+            outline(Path::new("agent.rs"), rust).as_deref(),
+            Some(
+                "    3  pub struct Agent\n\
+                 \x20   7  impl Agent\n\
+                 \x20   8    pub async fn process_query( &mut self, query: &str, ) -> Result<String, AgentError>\n\
+                 \x20  16  pub const LIMIT: usize\n"
+            )
+        );
+
+        let python = "import os\n\nclass Cache:\n    @staticmethod\n    def get(key: str) -> str:\n        return key\n\ndef main():\n    pass\n";
+        assert_eq!(
+            outline(Path::new("cache.py"), python).as_deref(),
+            Some(
+                "    3  class Cache\n\
+                 \x20   5    def get(key: str) -> str\n\
+                 \x20   8  def main()\n"
+            )
+        );
+
+        let typescript = "export interface Options { verbose: boolean }\nexport class Server {\n  listen(port: number): void {}\n}\nexport const handler = async (req: Request) => {\n  return req;\n};\nconst limit = 3;\n";
+        assert_eq!(
+            outline(Path::new("server.ts"), typescript).as_deref(),
+            Some(
+                "    1  interface Options\n\
+                 \x20   2  class Server\n\
+                 \x20   3    listen(port: number): void\n\
+                 \x20   5  const handler = async (req: Request)\n"
+            )
+        );
+
+        assert_eq!(outline(Path::new("notes.txt"), "func main() {}\n"), None);
+        assert_eq!(outline(Path::new("empty.rs"), "// Nothing here\n"), None);
+    }
+
+    #[test]
+    fn test_installed_role() {
+        let roles = [
+            ("function_declaration", Role::Item),
+            ("method", Role::Item),
+            ("singleton_method", Role::Item),
+            ("type_declaration", Role::Item),
+            ("class_declaration", Role::Container),
+            ("class", Role::Container),
+            ("namespace_definition", Role::Container),
+            ("export_statement", Role::Wrapper),
+            ("import_declaration", Role::Other),
+            ("field_declaration", Role::Other),
+            ("local_variable_declaration", Role::Other),
+            ("class_body", Role::Other),
+            ("identifier", Role::Other),
+            ("declaration", Role::Other),
+        ];
+        for (kind, role) in roles {
+            assert_eq!(installed_role(kind), role, "{kind}");
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! # Query Overrides
+//!
+//! This module parses the overrides of a single `ask`, for users who already know what is
+//! relevant and want to bypass the planner's decisions:
+//!
+//! - `--tool <name>=on|off` allows or denies one of the plan [`COMMANDS`] for the query,
+//!   taking precedence over the configured policy (but not over `--read-only`). Planned
+//!   commands using a denied tool are skipped.
+//! - `--context file=<path>[:<start>-<end>]` adds a file, or an inclusive range of its
+//!   lines, to the command results before the plan runs, whatever the planner decides.
+//!   `--with-file <path>[:<start>-<end>]` is a shorthand for it.
+//! - `--without <glob>` hides matching paths from the tools for the query, such as `tests/`
+//!   when asking about production behavior. Globs follow the syntax of the organization
+//!   policy's `forbidden_paths`. Shell commands run by `run` are not affected.
+//!
+//! All flags can be given several times.
+
+use std::{
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use regex::Regex;
+
+use crate::{
+    org_policy::{glob_to_regex, matches_path},
+    plan::COMMANDS,
+    policy::Permission,
+    provenance::LineRange,
+};
+
+/// Errors in override flags.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverrideError {
+    /// The override is not of the form `<key>=<value>`.
+    MissingValue(String),
+    /// The tool is not one of the plan commands.
+    UnknownTool(String),
+    /// The tool setting is neither `on` nor `off`.
+    InvalidSetting(String),
+    /// The context kind is not `file`.
+    UnknownContext(String),
+    /// The line range is not `<start>-<end>` with `1 <= start <= end`.
+    InvalidRange(String),
+}
+
+impl fmt::Display for OverrideError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverrideError::MissingValue(text) => write!(f, "Expected <name>=<value>, got {text}"),
+            OverrideError::UnknownTool(tool) => write!(
+                f,
+                "Unknown tool {tool}; expected one of {}",
+                COMMANDS.join(", ")
+            ),
+            OverrideError::InvalidSetting(setting) => {
+                write!(f, "Invalid tool setting {setting}; expected on or off")
+            }
+            OverrideError::UnknownContext(kind) => {
+                write!(f, "Unknown context kind {kind}; expected file")
+            }
+            OverrideError::InvalidRange(range) => write!(
+                f,
+                "Invalid line range {range}; expected <start>-<end> starting at line 1"
+            ),
+        }
+    }
+}
+
+impl Error for OverrideError {}
+
+/// A tool allowed or denied for one query, parsed from `<name>=on|off`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolOverride {
+    /// The tool, one of the plan commands.
+    pub tool: String,
+    /// Whether the tool may run.
+    pub enabled: bool,
+}
+
+impl ToolOverride {
+    /// Returns the permission the override grants.
+    pub fn permission(&self) -> Permission {
+        if self.enabled {
+            Permission::Allow
+        } else {
+            Permission::Deny
+        }
+    }
+}
+
+impl FromStr for ToolOverride {
+    type Err = OverrideError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (tool, setting) = text
+            .split_once('=')
+            .ok_or_else(|| OverrideError::MissingValue(text.to_string()))?;
+        if !COMMANDS.contains(&tool) {
+            return Err(OverrideError::UnknownTool(tool.to_string()));
+        }
+        let enabled = match setting {
+            "on" => true,
+            "off" => false,
+            _ => return Err(OverrideError::InvalidSetting(setting.to_string())),
+        };
+        Ok(Self {
+            tool: tool.to_string(),
+            enabled,
+        })
+    }
+}
+
+/// A file, or part of one, included in a query's context, parsed from
+/// `file=<path>[:<start>-<end>]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextOverride {
+    /// The file to include.
+    pub path: PathBuf,
+    /// The lines to include, or `None` for the whole file.
+    pub range: Option<LineRange>,
+}
+
+impl ContextOverride {
+    /// Returns the part of the file's `content` to include.
+    ///
+    /// Lines outside the range are replaced by elision markers, so excerpts are cited with
+    /// the line numbers of the file.
+    pub fn excerpt(&self, content: &str) -> String {
+        let Some(range) = self.range else {
+            return content.to_string();
+        };
+        let lines: Vec<&str> = content.lines().collect();
+        let total = lines.len();
+        let end = range.end.min(total);
+        let mut text = String::new();
+        if range.start > 1 {
+            text.push_str(&format!(
+                "[... lines 1-{} of {total} elided ...]\n",
+                (range.start - 1).min(total)
+            ));
+        }
+        for line in lines.iter().take(end).skip(range.start - 1) {
+            text.push_str(line);
+            text.push('\n');
+        }
+        if end < total && range.start <= total {
+            text.push_str(&format!(
+                "[... lines {}-{total} of {total} elided ...]\n",
+                end + 1
+            ));
+        }
+        text
+    }
+}
+
+impl fmt::Display for ContextOverride {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.range {
+            Some(range) => write!(
+                f,
+                "{} lines {}-{}",
+                self.path.display(),
+                range.start,
+                range.end
+            ),
+            None => write!(f, "{}", self.path.display()),
+        }
+    }
+}
+
+impl FromStr for ContextOverride {
+    type Err = OverrideError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = text
+            .split_once('=')
+            .ok_or_else(|| OverrideError::MissingValue(text.to_string()))?;
+        if kind != "file" {
+            return Err(OverrideError::UnknownContext(kind.to_string()));
+        }
+        Self::file(value)
+    }
+}
+
+impl ContextOverride {
+    /// Parses a `<path>[:<start>-<end>]` file to include, as given to `--with-file`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OverrideError::InvalidRange` if the line range is not valid.
+    pub fn file(value: &str) -> Result<Self, OverrideError> {
+        // Only a trailing `:<digits>-<digits>` is a range; other colons belong to the path
+        let (path, range) = match value.rsplit_once(':') {
+            Some((path, range)) if range.contains('-') && !path.is_empty() => {
+                (path, Some(parse_range(range)?))
+            }
+            _ => (value, None),
+        };
+        Ok(Self {
+            path: PathBuf::from(path),
+            range,
+        })
+    }
+}
+
+/// Paths hidden from the tools for one query, from `--without` globs.
+#[derive(Debug, Clone, Default)]
+pub struct Exclusions {
+    globs: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl Exclusions {
+    /// Compiles the `globs` to hide.
+    ///
+    /// Patterns without a `/` match names at any depth, and a directory hides everything
+    /// below it.
+    pub fn new(globs: Vec<String>) -> Self {
+        let patterns = globs
+            .iter()
+            .filter_map(|glob| Regex::new(&glob_to_regex(glob)).ok())
+            .collect();
+        Self { globs, patterns }
+    }
+
+    /// Returns the globs, as given.
+    pub fn globs(&self) -> &[String] {
+        &self.globs
+    }
+
+    /// Returns whether nothing is hidden.
+    pub fn is_empty(&self) -> bool {
+        self.globs.is_empty()
+    }
+
+    /// Returns whether `path` is hidden.
+    pub fn excludes(&self, path: &Path) -> bool {
+        matches_path(&self.patterns, path)
+    }
+}
+
+/// Parses an inclusive, 1-based `<start>-<end>` line range.
+fn parse_range(text: &str) -> Result<LineRange, OverrideError> {
+    let invalid = || OverrideError::InvalidRange(text.to_string());
+    let (start, end) = text.split_once('-').ok_or_else(invalid)?;
+    let start: usize = start.parse().map_err(|_| invalid())?;
+    let end: usize = end.parse().map_err(|_| invalid())?;
+    if start == 0 || start > end {
+        return Err(invalid());
+    }
+    Ok(LineRange { start, end })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_overrides() {
+        assert_eq!(
+            "tree=off".parse::<ToolOverride>(),
+            Ok(ToolOverride {
+                tool: "tree".to_string(),
+                enabled: false,
+            })
+        );
+        assert!(matches!(
+            "cat=on".parse::<ToolOverride>(),
+            Err(OverrideError::UnknownTool(_))
+        ));
+        assert!(matches!(
+            "search=yes".parse::<ToolOverride>(),
+            Err(OverrideError::InvalidSetting(_))
+        ));
+
+        let context: ContextOverride = "file=src/agent.rs:2-3".parse().expect("Valid context");
+        assert_eq!(context.path, PathBuf::from("src/agent.rs"));
+        assert_eq!(context.range, Some(LineRange { start: 2, end: 3 }));
+        assert_eq!(context.to_string(), "src/agent.rs lines 2-3");
+        assert_eq!(
+            context.excerpt("a\nb\nc\nd\n"),
+            "[... lines 1-1 of 4 elided ...]\nb\nc\n[... lines 4-4 of 4 elided ...]\n"
+        );
+        let whole: ContextOverride = "file=C:/src/main.rs".parse().expect("Valid context");
+        assert_eq!(whole.range, None);
+        assert_eq!(ContextOverride::file("src/agent.rs:2-3"), Ok(context));
+        assert!(matches!(
+            "file=src/main.rs:5-2".parse::<ContextOverride>(),
+            Err(OverrideError::InvalidRange(_))
+        ));
+        assert!(matches!(
+            "dir=src".parse::<ContextOverride>(),
+            Err(OverrideError::UnknownContext(_))
+        ));
+
+        let excluded = Exclusions::new(vec!["tests/".to_string(), "*.snap".to_string()]);
+        assert!(excluded.excludes(Path::new("tests/agent.rs")));
+        assert!(excluded.excludes(Path::new("/repo/crates/core/tests")));
+        assert!(excluded.excludes(Path::new("src/snapshots/answer.snap")));
+        assert!(!excluded.excludes(Path::new("src/tests.rs")));
+        assert!(Exclusions::default().is_empty());
+    }
+}
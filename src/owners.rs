@@ -0,0 +1,297 @@
+//! # Code Ownership
+//!
+//! This module implements the `owners` tool, which answers "who should I ask about this
+//! code?" with the people actually responsible for it rather than a guess:
+//!
+//! - the owners declared in the repository's `CODEOWNERS` file, looked up in the locations
+//!   GitHub and GitLab use, with the rule that assigned them
+//! - the top committers to the path, from `git shortlog`
+//!
+//! `CODEOWNERS` patterns follow the gitignore syntax used by GitHub: a pattern without an
+//! inner slash matches at any depth, a directory matches everything below it, and the last
+//! matching rule wins. GitLab's `[Section]` headers are skipped, so sections are treated
+//! as one list.
+
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+
+use crate::git::{git, GitError};
+
+/// Locations of the `CODEOWNERS` file relative to the repository root, in lookup order.
+const CODEOWNERS_PATHS: [&str; 4] = [
+    ".github/CODEOWNERS",
+    "CODEOWNERS",
+    "docs/CODEOWNERS",
+    ".gitlab/CODEOWNERS",
+];
+
+/// Maximum committers listed for a path.
+const MAX_COMMITTERS: usize = 5;
+
+/// A rule of a `CODEOWNERS` file.
+#[derive(Debug, Clone)]
+pub struct OwnerRule {
+    /// The pattern as written.
+    pub pattern: String,
+    /// The owners assigned by the rule; empty if the rule removes ownership.
+    pub owners: Vec<String>,
+    /// Line of the rule (1-based).
+    pub line: usize,
+    regex: Regex,
+}
+
+/// The parsed `CODEOWNERS` file of a repository.
+#[derive(Debug, Clone)]
+pub struct CodeOwners {
+    /// Where the file was read from.
+    pub source: PathBuf,
+    /// The rules, in file order.
+    pub rules: Vec<OwnerRule>,
+}
+
+impl CodeOwners {
+    /// Reads the `CODEOWNERS` file of the repository at `root`.
+    ///
+    /// Returns `None` if none exists in a conventional location.
+    pub fn find(root: &Path) -> Option<Self> {
+        CODEOWNERS_PATHS.iter().find_map(|relative| {
+            let content = fs::read_to_string(root.join(relative)).ok()?;
+            Some(Self::parse(PathBuf::from(relative), &content))
+        })
+    }
+
+    /// Parses the content of a `CODEOWNERS` file read from `source`.
+    ///
+    /// Lines whose pattern cannot be translated are skipped.
+    pub fn parse(source: PathBuf, content: &str) -> Self {
+        let mut rules = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            let line_text = line.split(" #").next().unwrap_or_default().trim();
+            if line_text.is_empty() || line_text.starts_with('#') || line_text.starts_with('[') {
+                continue;
+            }
+            let mut words = line_text.split_whitespace();
+            let Some(pattern) = words.next() else {
+                continue;
+            };
+            let Ok(regex) = Regex::new(&pattern_to_regex(pattern)) else {
+                continue;
+            };
+            rules.push(OwnerRule {
+                pattern: pattern.to_string(),
+                owners: words.map(str::to_string).collect(),
+                line: index + 1,
+                regex,
+            });
+        }
+        Self { source, rules }
+    }
+
+    /// Returns the rule deciding the owners of `path`, relative to the repository root.
+    pub fn rule_for(&self, path: &Path) -> Option<&OwnerRule> {
+        let path = path.to_string_lossy().replace('\\', "/");
+        let path = path.trim_start_matches("./").trim_end_matches('/');
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.regex.is_match(path))
+    }
+}
+
+/// Translates a `CODEOWNERS` pattern into an anchored regular expression.
+fn pattern_to_regex(pattern: &str) -> String {
+    let anchored = pattern.starts_with('/') || pattern.trim_end_matches('/').contains('/');
+    let glob = pattern.trim_start_matches('/').trim_end_matches('/');
+    let mut regex = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    // A directory owns everything below it
+    regex.push_str("(?:/.*)?$");
+    regex
+}
+
+/// Someone who committed to a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Committer {
+    /// The author name.
+    pub name: String,
+    /// The author email, if recorded.
+    pub email: Option<String>,
+    /// Number of commits touching the path.
+    pub commits: usize,
+}
+
+/// Returns the people with the most commits touching `path`, most first.
+///
+/// # Errors
+///
+/// Returns a `GitError` if git fails, for example outside a repository.
+pub fn top_committers(root: &Path, path: &Path) -> Result<Vec<Committer>, GitError> {
+    let path = path.to_string_lossy();
+    let path = if path.is_empty() { "." } else { &path };
+    // An explicit revision keeps shortlog from reading a log from standard input
+    let output = git(
+        root,
+        &["shortlog", "-sne", "--no-merges", "HEAD", "--", path],
+    )?;
+    let mut committers = parse_shortlog(&output);
+    committers.truncate(MAX_COMMITTERS);
+    Ok(committers)
+}
+
+/// Parses the output of `git shortlog -sne`.
+fn parse_shortlog(output: &str) -> Vec<Committer> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (count, author) = line.trim().split_once('\t')?;
+            let (name, email) = match author.rsplit_once(" <") {
+                Some((name, email)) => (name, Some(email.trim_end_matches('>').to_string())),
+                None => (author, None),
+            };
+            Some(Committer {
+                name: name.trim().to_string(),
+                email,
+                commits: count.trim().parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Runs the `owners` tool on `path` in the repository at `root`.
+pub fn run_owners(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let mut output = format!("Ownership of {}:\n\n", relative.display());
+
+    match CodeOwners::find(root) {
+        Some(codeowners) => match codeowners.rule_for(relative) {
+            Some(rule) if rule.owners.is_empty() => {
+                let _ = writeln!(
+                    output,
+                    "No owners: {} line {} (`{}`) leaves the path unowned",
+                    codeowners.source.display(),
+                    rule.line,
+                    rule.pattern
+                );
+            }
+            Some(rule) => {
+                let _ = writeln!(
+                    output,
+                    "Code owners: {} ({} line {}: `{}`)",
+                    rule.owners.join(" "),
+                    codeowners.source.display(),
+                    rule.line,
+                    rule.pattern
+                );
+            }
+            None => {
+                let _ = writeln!(
+                    output,
+                    "No code owners: no rule of {} matches",
+                    codeowners.source.display()
+                );
+            }
+        },
+        None => output.push_str("No CODEOWNERS file found\n"),
+    }
+
+    // A missing history still leaves the declared owners worth reporting
+    match top_committers(root, relative) {
+        Ok(committers) if committers.is_empty() => output.push_str("\nNo commits found\n"),
+        Ok(committers) => {
+            output.push_str("\nTop committers:\n");
+            for committer in committers {
+                let email = committer
+                    .email
+                    .map(|email| format!(" <{email}>"))
+                    .unwrap_or_default();
+                let _ = writeln!(
+                    output,
+                    "  {}{email}: {} commit(s)",
+                    committer.name, committer.commits
+                );
+            }
+        }
+        Err(err) => {
+            let _ = writeln!(output, "\nNo history available: {err}");
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rule_for() {
+        let codeowners = CodeOwners::parse(
+            PathBuf::from("CODEOWNERS"),
+            "# Default owners\n* @org/core\n\n[Docs]\n*.md @writer # prose\n/src/server/ @alice bob@example.com\ndocs/**/api @api-team\nsrc/generated/\n",
+        );
+        let owners = |path: &str| {
+            codeowners
+                .rule_for(Path::new(path))
+                .map(|rule| (rule.owners.join(" "), rule.line))
+        };
+        assert_eq!(owners("Cargo.toml"), Some(("@org/core".to_string(), 2)));
+        assert_eq!(
+            owners("src/lib/README.md"),
+            Some(("@writer".to_string(), 5))
+        );
+        assert_eq!(
+            owners("src/server/routes.rs"),
+            Some(("@alice bob@example.com".to_string(), 6))
+        );
+        // Patterns with an inner slash are anchored at the root
+        assert_eq!(
+            owners("lib/src/server/a.rs"),
+            Some(("@org/core".to_string(), 2))
+        );
+        assert_eq!(
+            owners("docs/v1/http/api"),
+            Some(("@api-team".to_string(), 7))
+        );
+        assert_eq!(owners("./src/generated/types.rs"), Some((String::new(), 8)));
+    }
+
+    #[test]
+    fn test_parse_shortlog() {
+        let committers = parse_shortlog("    12\tAlice Smith <alice@example.com>\n     3\tbob\n");
+        assert_eq!(
+            committers,
+            [
+                Committer {
+                    name: "Alice Smith".to_string(),
+                    email: Some("alice@example.com".to_string()),
+                    commits: 12,
+                },
+                Committer {
+                    name: "bob".to_string(),
+                    email: None,
+                    commits: 3,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! # Patches
+//!
+//! This module holds file changes proposed by nishiogi. Changes are shown as a unified
+//! diff for review and are only written when the user approves them. Each change
+//! remembers the content it was computed from, and applying it fails if the file has been
+//! modified since, so a reviewed patch never overwrites newer work.
+//!
+//! Patches proposed by the model are written as search/replace edits ([`Replacement`]),
+//! which are turned into patches with a [`Draft`]. A draft keeps the edited contents, so a
+//! series of patches can be built where each one applies on top of the previous.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::diff::unified_diff;
+
+/// Lines of context shown around each change.
+const DIFF_CONTEXT: usize = 3;
+
+/// Errors that can occur while applying a patch.
+#[derive(Debug)]
+pub enum PatchError {
+    /// A file changed after the patch was computed.
+    Conflict(PathBuf),
+    /// The text an edit replaces does not occur in the file.
+    NotFound(PathBuf, String),
+    /// The text an edit replaces occurs more than once in the file.
+    Ambiguous(PathBuf, String),
+    /// An edit creates a file that already exists.
+    Exists(PathBuf),
+    /// A file could not be read or written.
+    Io(PathBuf, io::Error),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::Conflict(path) => write!(
+                f,
+                "{} changed after the patch was created; run the command again",
+                path.display()
+            ),
+            PatchError::NotFound(path, search) => {
+                write!(f, "{} does not contain the text to replace:\n{search}", path.display())
+            }
+            PatchError::Ambiguous(path, search) => write!(
+                f,
+                "{} contains the text to replace more than once; include more lines to make it unique:\n{search}",
+                path.display()
+            ),
+            PatchError::Exists(path) => write!(
+                f,
+                "{} already exists; replace part of it instead of creating it",
+                path.display()
+            ),
+            PatchError::Io(path, err) => write!(f, "Failed to update {}: {err}", path.display()),
+        }
+    }
+}
+
+impl Error for PatchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PatchError::Io(_, err) => Some(err),
+            PatchError::Conflict(_)
+            | PatchError::NotFound(..)
+            | PatchError::Ambiguous(..)
+            | PatchError::Exists(_) => None,
+        }
+    }
+}
+
+/// A change to one file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEdit {
+    /// The file, relative to the root the patch is applied in.
+    pub path: PathBuf,
+    /// The content the change was computed from.
+    pub original: String,
+    /// The content after the change.
+    pub updated: String,
+}
+
+/// A set of file changes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Patch {
+    /// The changed files; files without changes are left out.
+    pub edits: Vec<FileEdit>,
+}
+
+impl Patch {
+    /// Adds the change of one file, unless it leaves the file as it was.
+    pub fn push(&mut self, edit: FileEdit) {
+        if edit.original != edit.updated {
+            self.edits.push(edit);
+        }
+    }
+
+    /// Returns whether the patch changes nothing.
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Adds the changes of `later`, a patch computed on top of this one.
+    pub fn then(&mut self, later: Patch) {
+        for edit in later.edits {
+            match self
+                .edits
+                .iter()
+                .position(|earlier| earlier.path == edit.path)
+            {
+                Some(index) => {
+                    self.edits[index].updated = edit.updated;
+                    if self.edits[index].original == self.edits[index].updated {
+                        self.edits.remove(index);
+                    }
+                }
+                None => self.push(edit),
+            }
+        }
+    }
+
+    /// Renders the patch as a unified diff that `git apply` accepts.
+    pub fn render(&self) -> String {
+        self.edits
+            .iter()
+            .map(|edit| {
+                unified_diff(
+                    &edit.path.to_string_lossy(),
+                    &edit.original,
+                    &edit.updated,
+                    DIFF_CONTEXT,
+                )
+            })
+            .collect()
+    }
+
+    /// Writes the changes to the files under `root`.
+    ///
+    /// All files are checked before any is written, so a conflict leaves every file as it
+    /// was.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PatchError::Conflict` if a file no longer has the content the patch was
+    /// computed from, and `PatchError::Io` if a file cannot be read or written.
+    pub fn apply(&self, root: &Path) -> Result<(), PatchError> {
+        for edit in &self.edits {
+            let path = root.join(&edit.path);
+            let current = match fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+                Err(err) => return Err(PatchError::Io(path, err)),
+            };
+            if current != edit.original {
+                return Err(PatchError::Conflict(edit.path.clone()));
+            }
+        }
+        for edit in &self.edits {
+            let path = root.join(&edit.path);
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|e| PatchError::Io(dir.to_path_buf(), e))?;
+            }
+            fs::write(&path, &edit.updated).map_err(|e| PatchError::Io(path, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// A search/replace edit of one file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Replacement {
+    /// The file, relative to the root of the draft.
+    pub path: PathBuf,
+    /// The exact text to replace; empty to create the file.
+    #[serde(default)]
+    pub search: String,
+    /// The text to put in its place.
+    pub replace: String,
+}
+
+/// File contents with the edits made so far, read lazily from the files under a root.
+#[derive(Debug)]
+pub struct Draft {
+    root: PathBuf,
+    files: BTreeMap<PathBuf, String>,
+}
+
+impl Draft {
+    /// Creates a draft of the files under `root`, with no edits yet.
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the drafted content of `path`; a file that does not exist is empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PatchError::Io` if the file exists but cannot be read.
+    pub fn content(&mut self, path: &Path) -> Result<&str, PatchError> {
+        if !self.files.contains_key(path) {
+            let content = match fs::read_to_string(self.root.join(path)) {
+                Ok(content) => content,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+                Err(err) => return Err(PatchError::Io(path.to_path_buf(), err)),
+            };
+            self.files.insert(path.to_path_buf(), content);
+        }
+        Ok(&self.files[path])
+    }
+
+    /// Makes `replacements` in order and returns them as a patch on top of the earlier
+    /// edits.
+    ///
+    /// Nothing is changed if any replacement fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PatchError::NotFound` or `PatchError::Ambiguous` if the text to replace does
+    /// not occur exactly once, and `PatchError::Io` if a file cannot be read.
+    pub fn edit(&mut self, replacements: &[Replacement]) -> Result<Patch, PatchError> {
+        let mut edited: BTreeMap<PathBuf, (String, String)> = BTreeMap::new();
+        for replacement in replacements {
+            let path = &replacement.path;
+            if !edited.contains_key(path) {
+                let content = self.content(path)?.to_string();
+                edited.insert(path.clone(), (content.clone(), content));
+            }
+            let (_, updated) = edited.get_mut(path).expect("File was just drafted");
+            if replacement.search.is_empty() {
+                if !updated.is_empty() {
+                    return Err(PatchError::Exists(path.clone()));
+                }
+                *updated = replacement.replace.clone();
+                continue;
+            }
+            match updated.matches(&replacement.search).count() {
+                0 => {
+                    return Err(PatchError::NotFound(
+                        path.clone(),
+                        replacement.search.clone(),
+                    ))
+                }
+                1 => *updated = updated.replacen(&replacement.search, &replacement.replace, 1),
+                _ => {
+                    return Err(PatchError::Ambiguous(
+                        path.clone(),
+                        replacement.search.clone(),
+                    ));
+                }
+            }
+        }
+
+        let mut patch = Patch::default();
+        for (path, (original, updated)) in edited {
+            self.files.insert(path.clone(), updated.clone());
+            patch.push(FileEdit {
+                path,
+                original,
+                updated,
+            });
+        }
+        Ok(patch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_apply_checks_for_conflicts() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        fs::write(root.join("a.rs"), "fn a() {}\n").expect("Failed to write file");
+
+        let mut patch = Patch::default();
+        patch.push(FileEdit {
+            path: PathBuf::from("a.rs"),
+            original: "fn a() {}\n".to_string(),
+            updated: "/// Does a.\nfn a() {}\n".to_string(),
+        });
+        patch.push(FileEdit {
+            path: PathBuf::from("b.rs"),
+            original: "same\n".to_string(),
+            updated: "same\n".to_string(),
+        });
+        assert_eq!(patch.edits.len(), 1);
+        assert!(patch.render().contains("+/// Does a.\n"));
+
+        fs::write(root.join("a.rs"), "fn a() { changed }\n").expect("Failed to write file");
+        assert!(matches!(
+            patch.apply(root),
+            Err(PatchError::Conflict(path)) if path == Path::new("a.rs")
+        ));
+
+        fs::write(root.join("a.rs"), "fn a() {}\n").expect("Failed to write file");
+        patch.apply(root).expect("Failed to apply patch");
+        assert_eq!(
+            fs::read_to_string(root.join("a.rs")).expect("Failed to read file"),
+            "/// Does a.\nfn a() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_draft_builds_patch_series() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        fs::write(root.join("a.rs"), "fn a() {\n    b();\n    b();\n}\n")
+            .expect("Failed to write file");
+
+        let replacement = |search: &str, replace: &str| Replacement {
+            path: PathBuf::from("a.rs"),
+            search: search.to_string(),
+            replace: replace.to_string(),
+        };
+        let mut draft = Draft::new(root);
+        assert!(matches!(
+            draft.edit(&[replacement("b();", "c();")]),
+            Err(PatchError::Ambiguous(..))
+        ));
+        let first = draft
+            .edit(&[replacement("fn a() {\n    b();", "fn a() {\n    c();")])
+            .expect("Failed to edit");
+        let second = draft
+            .edit(&[replacement("    b();\n}", "    c();\n}")])
+            .expect("Failed to edit");
+        assert!(matches!(
+            draft.edit(&[replacement("b();", "d();")]),
+            Err(PatchError::NotFound(..))
+        ));
+
+        first.apply(root).expect("Failed to apply first patch");
+        second.apply(root).expect("Failed to apply second patch");
+        assert_eq!(
+            fs::read_to_string(root.join("a.rs")).expect("Failed to read file"),
+            "fn a() {\n    c();\n    c();\n}\n"
+        );
+    }
+}
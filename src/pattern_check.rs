@@ -0,0 +1,222 @@
+//! # Pattern Checks
+//!
+//! This module implements two small deterministic tools the planner can use to check its
+//! own assumptions instead of guessing what a pattern matches:
+//!
+//! - `regex_test <pattern> <sample>`: whether a regular expression (Rust `regex` syntax)
+//!   matches a sample, with every match and capture group. The pattern must not contain
+//!   spaces; the sample is everything after the space following it.
+//! - `glob_expand <pattern>`: the repository files a glob matches. `*` and `?` do not cross
+//!   `/`, `**` matches any number of directories, and `[abc]` and `{a,b}` are supported.
+//!   Ignored and forbidden files are left out.
+
+use std::{fmt::Write, path::Path};
+
+use regex::Regex;
+
+use crate::{search::collect_files, tree::find_gitignore_patterns};
+
+/// Maximum matches listed by `regex_test`.
+const MAX_MATCHES: usize = 20;
+
+/// Maximum paths listed by `glob_expand`.
+const MAX_PATHS: usize = 100;
+
+/// Runs the `regex_test` tool, describing how `pattern` matches `sample`.
+pub fn regex_test(pattern: &str, sample: &str) -> String {
+    let regex = match Regex::new(pattern) {
+        Ok(regex) => regex,
+        Err(err) => return format!("Invalid regex `{pattern}`: {err}"),
+    };
+    let matches: Vec<_> = regex.captures_iter(sample).collect();
+    if matches.is_empty() {
+        return format!("`{pattern}` does not match {sample:?}");
+    }
+
+    let names: Vec<Option<&str>> = regex.capture_names().collect();
+    let mut output = format!(
+        "`{pattern}` matches {sample:?} {} time(s):\n",
+        matches.len()
+    );
+    for (index, captures) in matches.iter().take(MAX_MATCHES).enumerate() {
+        let whole = captures.get(0).expect("Capture 0 is the whole match");
+        let _ = writeln!(
+            output,
+            "{}. bytes {}..{}: {:?}",
+            index + 1,
+            whole.start(),
+            whole.end(),
+            whole.as_str()
+        );
+        for (group, name) in names.iter().enumerate().skip(1) {
+            let label = match name {
+                Some(name) => format!("group {group} ({name})"),
+                None => format!("group {group}"),
+            };
+            match captures.get(group) {
+                Some(capture) => {
+                    let _ = writeln!(output, "   {label}: {:?}", capture.as_str());
+                }
+                None => {
+                    let _ = writeln!(output, "   {label}: did not participate");
+                }
+            }
+        }
+    }
+    if matches.len() > MAX_MATCHES {
+        let _ = writeln!(output, "... and {} more", matches.len() - MAX_MATCHES);
+    }
+    output
+}
+
+/// Runs the `glob_expand` tool, listing the files under `root` that `pattern` matches,
+/// except those `hidden` accepts.
+pub fn glob_expand(root: &Path, pattern: &str, hidden: &dyn Fn(&Path) -> bool) -> String {
+    let regex = match glob_to_regex(pattern).map(|regex| Regex::new(&regex)) {
+        Some(Ok(regex)) => regex,
+        _ => return format!("Invalid glob `{pattern}`"),
+    };
+    let ignore = find_gitignore_patterns(root).unwrap_or_default();
+    let mut files = Vec::new();
+    collect_files(root, root, &ignore, &mut files);
+    let matched: Vec<String> = files
+        .iter()
+        .filter(|path| !hidden(path))
+        .filter_map(|path| {
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            regex.is_match(&relative).then_some(relative)
+        })
+        .collect();
+    if matched.is_empty() {
+        return format!("`{pattern}` matches no files");
+    }
+
+    let mut output = format!("`{pattern}` matches {} file(s):\n", matched.len());
+    for path in matched.iter().take(MAX_PATHS) {
+        let _ = writeln!(output, "{path}");
+    }
+    if matched.len() > MAX_PATHS {
+        let _ = writeln!(output, "... and {} more", matched.len() - MAX_PATHS);
+    }
+    output
+}
+
+/// Translates a glob into an anchored regular expression, or `None` if a bracket or brace
+/// is not closed.
+fn glob_to_regex(glob: &str) -> Option<String> {
+    let mut regex = String::from("^");
+    let mut in_braces = false;
+    let mut chars = glob.trim_start_matches("./").chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                regex.push('[');
+                if chars.next_if(|&c| c == '!' || c == '^').is_some() {
+                    regex.push('^');
+                }
+                loop {
+                    match chars.next()? {
+                        ']' => break,
+                        c @ ('\\' | '[' | '&' | '~') => {
+                            regex.push('\\');
+                            regex.push(c);
+                        }
+                        c => regex.push(c),
+                    }
+                }
+                regex.push(']');
+            }
+            '{' if !in_braces => {
+                in_braces = true;
+                regex.push_str("(?:");
+            }
+            ',' if in_braces => regex.push('|'),
+            '}' if in_braces => {
+                in_braces = false;
+                regex.push(')');
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    if in_braces {
+        return None;
+    }
+    regex.push('$');
+    Some(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_regex_test() {
+        assert_eq!(
+            regex_test(r"(?P<key>\w+)=(\d+)?", "a=1 b="),
+            "`(?P<key>\\w+)=(\\d+)?` matches \"a=1 b=\" 2 time(s):\n1. bytes 0..3: \"a=1\"\n   group 1 (key): \"a\"\n   group 2: \"1\"\n2. bytes 4..6: \"b=\"\n   group 1 (key): \"b\"\n   group 2: did not participate\n"
+        );
+        assert_eq!(
+            regex_test(r"^\d+$", "12a"),
+            "`^\\d+$` does not match \"12a\""
+        );
+        assert!(regex_test("(", "x").starts_with("Invalid regex `(`"));
+    }
+
+    #[test]
+    fn test_glob_expand() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let root = dir.path();
+        for path in [
+            "src/main.rs",
+            "src/net/http.rs",
+            "src/net/http.toml",
+            "build.rs",
+        ] {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().expect("Path has a parent"))
+                .expect("Failed to create directory");
+            fs::write(path, "").expect("Failed to write file");
+        }
+
+        assert_eq!(
+            glob_expand(root, "**/*.rs", &|_| false),
+            "`**/*.rs` matches 3 file(s):\nbuild.rs\nsrc/main.rs\nsrc/net/http.rs\n"
+        );
+        assert_eq!(
+            glob_expand(root, "src/*.rs", &|_| false),
+            "`src/*.rs` matches 1 file(s):\nsrc/main.rs\n"
+        );
+        assert_eq!(
+            glob_expand(root, "src/net/http.{rs,toml}", &|_| false),
+            "`src/net/http.{rs,toml}` matches 2 file(s):\nsrc/net/http.rs\nsrc/net/http.toml\n"
+        );
+        assert_eq!(
+            glob_expand(root, "[!s]*.rs", &|_| false),
+            "`[!s]*.rs` matches 1 file(s):\nbuild.rs\n"
+        );
+        assert_eq!(
+            glob_expand(root, "*.md", &|_| false),
+            "`*.md` matches no files"
+        );
+        assert_eq!(
+            glob_expand(root, "src/[ab", &|_| false),
+            "Invalid glob `src/[ab`"
+        );
+    }
+}
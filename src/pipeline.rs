@@ -0,0 +1,486 @@
+//! # Pipelines
+//!
+//! This module exposes the stages of answering a question as composable steps, so other
+//! crates can build their own flows from nishiogi's components instead of running the full
+//! [`Agent`](crate::agent::Agent) loop. A [`Pipeline`] is a named list of [`Step`]s that
+//! run in order over a shared [`PipelineContext`]. The built-in steps are:
+//!
+//! - [`Retrieve`]: runs read-only tools (`tree`, `show_file`, `search`, ...), either a fixed
+//!   list of commands or a plan requested from the model
+//! - [`Summarize`]: condenses the retrieved output into notes relevant to the question
+//! - [`Answer`]: answers the question from the notes, or from the retrieved output
+//! - [`Review`]: judges the answer with any [`Reviewer`]
+//!
+//! For example, a retrieval-only flow that makes no model calls:
+//!
+//! ```no_run
+//! # async fn example(model: &mut dyn nishiogi::review::ReviewModel) -> Result<(), nishiogi::agent::AgentError> {
+//! use nishiogi::pipeline::{Pipeline, Retrieve};
+//!
+//! let context = Pipeline::new("context")
+//!     .step(Retrieve::commands(["tree src", "search fn\\smain"]))
+//!     .run("Where does the program start?", model)
+//!     .await?;
+//! for (command, output) in &context.retrieved {
+//!     println!("{command}:\n{output}");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Custom steps implement [`Step`] and can be mixed freely with the built-in ones.
+
+use std::{error::Error, fmt, path::Path, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::{
+    agent::AgentError,
+    config::repo_root,
+    github_copilot_client::Message,
+    overrides::Exclusions,
+    plan::{parse_plan, stages},
+    policy::{Policy, ToolClass},
+    review::{LlmReviewer, ReviewInput, ReviewModel, Reviewer, Verdict},
+    root_sandbox::RootSandbox,
+    session::FileProvenance,
+    tools::{ToolContext, ToolRegistry},
+};
+
+/// Errors that can occur while assembling a pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipelineError {
+    /// A step name is not one of the built-in steps.
+    UnknownStep(String),
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::UnknownStep(name) => write!(
+                f,
+                "Unknown pipeline step {name}; expected retrieve, summarize, answer, or review"
+            ),
+        }
+    }
+}
+
+impl Error for PipelineError {}
+
+/// The state a pipeline's steps read and extend.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineContext {
+    /// The question being answered.
+    pub question: String,
+    /// Commands run by retrieval steps, with their output.
+    pub retrieved: Vec<(String, String)>,
+    /// Files read by retrieval steps.
+    pub consulted_files: Vec<FileProvenance>,
+    /// Notes condensed from the retrieved output, if a summarize step ran.
+    pub summary: Option<String>,
+    /// The answer, if an answer step ran.
+    pub answer: Option<String>,
+    /// The review verdict, if a review step ran.
+    pub verdict: Option<Verdict>,
+}
+
+/// A stage of a pipeline.
+#[async_trait]
+pub trait Step: Send + Sync {
+    /// Returns the step's name, shown in progress output.
+    fn name(&self) -> &str;
+
+    /// Runs the step, reading and extending `context`.
+    async fn run(
+        &self,
+        context: &mut PipelineContext,
+        model: &mut dyn ReviewModel,
+    ) -> Result<(), AgentError>;
+}
+
+/// A named sequence of steps.
+pub struct Pipeline {
+    name: String,
+    steps: Vec<Box<dyn Step>>,
+}
+
+impl Pipeline {
+    /// Creates an empty pipeline.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Creates the standard flow: planned retrieval, an answer, and a model review.
+    pub fn standard() -> Self {
+        Self::new("standard")
+            .step(Retrieve::planned())
+            .step(Answer)
+            .step(Review::new(Arc::new(LlmReviewer)))
+    }
+
+    /// Creates a pipeline from built-in step names (`retrieve`, `summarize`, `answer`, and
+    /// `review`), e.g. from configuration.
+    ///
+    /// `retrieve` plans its commands with the model and `review` uses the model as
+    /// reviewer.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PipelineError::UnknownStep` for a name that is not a built-in step.
+    pub fn from_step_names(
+        name: impl Into<String>,
+        steps: &[impl AsRef<str>],
+    ) -> Result<Self, PipelineError> {
+        let mut pipeline = Self::new(name);
+        for step in steps {
+            pipeline = match step.as_ref() {
+                "retrieve" => pipeline.step(Retrieve::planned()),
+                "summarize" => pipeline.step(Summarize),
+                "answer" => pipeline.step(Answer),
+                "review" => pipeline.step(Review::new(Arc::new(LlmReviewer))),
+                other => return Err(PipelineError::UnknownStep(other.to_string())),
+            };
+        }
+        Ok(pipeline)
+    }
+
+    /// Appends a step.
+    #[must_use]
+    pub fn step(mut self, step: impl Step + 'static) -> Self {
+        self.steps.push(Box::new(step));
+        self
+    }
+
+    /// Returns the pipeline's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Runs every step in order for `question` and returns the resulting context.
+    ///
+    /// # Errors
+    ///
+    /// Returns the `AgentError` of the first step that fails.
+    pub async fn run(
+        &self,
+        question: &str,
+        model: &mut dyn ReviewModel,
+    ) -> Result<PipelineContext, AgentError> {
+        let mut context = PipelineContext {
+            question: question.to_string(),
+            ..PipelineContext::default()
+        };
+        for step in &self.steps {
+            eprintln!("Pipeline {}: {}", self.name, step.name());
+            step.run(&mut context, model).await?;
+        }
+        Ok(context)
+    }
+}
+
+/// Runs read-only tools and records their output.
+pub struct Retrieve {
+    /// Commands to run, or `None` to ask the model for a plan.
+    commands: Option<Vec<String>>,
+    /// The tools running the commands.
+    tools: ToolRegistry,
+    /// The directory tree the commands may read, or `None` for any path.
+    sandbox: Option<RootSandbox>,
+}
+
+impl Retrieve {
+    /// Runs the given commands, e.g. `show_file src/main.rs`.
+    ///
+    /// The commands are trusted, so their paths are not confined to the repository.
+    pub fn commands<I, S>(commands: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            commands: Some(commands.into_iter().map(Into::into).collect()),
+            tools: ToolRegistry::builtin(),
+            sandbox: None,
+        }
+    }
+
+    /// Asks the model which commands to run for the question, confined to the repository.
+    pub fn planned() -> Self {
+        Self {
+            commands: None,
+            tools: ToolRegistry::builtin(),
+            sandbox: Some(RootSandbox::new(&repo_root())),
+        }
+    }
+
+    /// Confines the commands to `root` instead.
+    #[must_use]
+    pub fn within(mut self, root: &Path) -> Self {
+        self.sandbox = Some(RootSandbox::new(root));
+        self
+    }
+
+    /// Asks the model for the commands to run, in dependency order.
+    async fn plan(
+        &self,
+        question: &str,
+        model: &mut dyn ReviewModel,
+    ) -> Result<Vec<String>, AgentError> {
+        let messages = vec![
+            Message {
+                role: "system".to_string(),
+                content: self
+                    .tools
+                    .planner_prompt(|tool| Policy::default().classify(tool) == ToolClass::ReadOnly),
+            },
+            Message {
+                role: "user".to_string(),
+                content: format!(
+                    "Based on this question: '{question}', create a plan of what commands to run. Return a JSON array of commands like [\"tree src\", \"show_file src/main.rs\"]."
+                ),
+            },
+        ];
+        let reply = model.complete(messages).await?;
+        let steps = parse_plan(&reply, &self.tools.names()).map_err(|reason| {
+            AgentError::InvalidPlanFormat {
+                reason,
+                text: reply,
+            }
+        })?;
+        let order = stages(&steps).map_err(AgentError::InvalidPlan)?;
+        Ok(order
+            .into_iter()
+            .flatten()
+            .map(|index| steps[index].command.clone())
+            .collect())
+    }
+}
+
+#[async_trait]
+impl Step for Retrieve {
+    fn name(&self) -> &str {
+        "retrieve"
+    }
+
+    async fn run(
+        &self,
+        context: &mut PipelineContext,
+        model: &mut dyn ReviewModel,
+    ) -> Result<(), AgentError> {
+        let commands = match &self.commands {
+            Some(commands) => commands.clone(),
+            None => self.plan(&context.question, model).await?,
+        };
+        let excluded = Exclusions::default();
+        let tools = ToolContext {
+            base: None,
+            excluded: &excluded,
+            sandbox: self.sandbox.as_ref(),
+        };
+        for command in commands {
+            let output = self.tools.run(&command, &tools)?;
+            if let Some(file) = output.file {
+                context.consulted_files.push(file);
+            }
+            context.retrieved.push((command, output.text));
+        }
+        Ok(())
+    }
+}
+
+/// Condenses the retrieved output into notes relevant to the question.
+pub struct Summarize;
+
+#[async_trait]
+impl Step for Summarize {
+    fn name(&self) -> &str {
+        "summarize"
+    }
+
+    async fn run(
+        &self,
+        context: &mut PipelineContext,
+        model: &mut dyn ReviewModel,
+    ) -> Result<(), AgentError> {
+        let messages = vec![
+            Message {
+                role: "system".to_string(),
+                content: "You condense command output from a code repository into notes. Keep file paths, names, and facts relevant to the question; drop everything else.".to_string(),
+            },
+            Message {
+                role: "user".to_string(),
+                content: format!(
+                    "Question: {}\n\nCommand results:\n\n{}Write the notes needed to answer the question.",
+                    context.question,
+                    render_retrieved(&context.retrieved)
+                ),
+            },
+        ];
+        context.summary = Some(model.complete(messages).await?);
+        Ok(())
+    }
+}
+
+/// Answers the question from the summary if there is one, or from the retrieved output.
+pub struct Answer;
+
+#[async_trait]
+impl Step for Answer {
+    fn name(&self) -> &str {
+        "answer"
+    }
+
+    async fn run(
+        &self,
+        context: &mut PipelineContext,
+        model: &mut dyn ReviewModel,
+    ) -> Result<(), AgentError> {
+        let material = match &context.summary {
+            Some(summary) => format!("Notes:\n\n{summary}\n\n"),
+            None => format!(
+                "Command results:\n\n{}",
+                render_retrieved(&context.retrieved)
+            ),
+        };
+        let messages = vec![
+            Message {
+                role: "system".to_string(),
+                content: "You are an assistant that analyzes code repositories. Create a helpful response based on executed commands.".to_string(),
+            },
+            Message {
+                role: "user".to_string(),
+                content: format!(
+                    "Question: {}\n\n{material}Based on the above information, please provide a comprehensive answer to the question.",
+                    context.question
+                ),
+            },
+        ];
+        let answer = model.complete(messages).await?;
+        if answer.trim().is_empty() {
+            return Err(AgentError::EmptyAnswerResponse);
+        }
+        context.answer = Some(answer);
+        Ok(())
+    }
+}
+
+/// Judges the answer with a reviewer and records the verdict.
+pub struct Review {
+    reviewer: Arc<dyn Reviewer>,
+}
+
+impl Review {
+    /// Reviews with `reviewer`.
+    pub fn new(reviewer: Arc<dyn Reviewer>) -> Self {
+        Self { reviewer }
+    }
+}
+
+#[async_trait]
+impl Step for Review {
+    fn name(&self) -> &str {
+        "review"
+    }
+
+    async fn run(
+        &self,
+        context: &mut PipelineContext,
+        model: &mut dyn ReviewModel,
+    ) -> Result<(), AgentError> {
+        let Some(answer) = &context.answer else {
+            return Err(AgentError::NoAnswerToReview);
+        };
+        let input = ReviewInput {
+            question: context.question.clone(),
+            answer: answer.clone(),
+            consulted_files: context.consulted_files.clone(),
+        };
+        context.verdict = Some(self.reviewer.review(&input, model).await?);
+        Ok(())
+    }
+}
+
+/// Renders retrieved command output for a prompt.
+fn render_retrieved(retrieved: &[(String, String)]) -> String {
+    retrieved
+        .iter()
+        .map(|(command, output)| format!("## Command: {command}\n\n```\n{output}\n```\n\n"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Answers with the notes it was given and approves every answer.
+    struct ScriptedModel {
+        calls: usize,
+    }
+
+    #[async_trait]
+    impl ReviewModel for ScriptedModel {
+        async fn complete(&mut self, messages: Vec<Message>) -> Result<String, AgentError> {
+            self.calls += 1;
+            let system = &messages[0].content;
+            Ok(if system.contains("condense") {
+                "main prints a greeting".to_string()
+            } else if system.contains("critical reviewer") {
+                "YES".to_string()
+            } else if messages[1].content.contains("main prints a greeting") {
+                "It prints a greeting.".to_string()
+            } else {
+                "I do not know.".to_string()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_pipeline() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("main.rs");
+        fs::write(&path, "fn main() { println!(\"hi\"); }\n").expect("Failed to write file");
+        let retrieve = || Retrieve::commands([format!("show_file {}", path.display())]);
+
+        let mut model = ScriptedModel { calls: 0 };
+        let context = Pipeline::new("retrieval-only")
+            .step(retrieve())
+            .run("What does main do?", &mut model)
+            .await
+            .expect("Failed to run pipeline");
+        assert_eq!(model.calls, 0);
+        assert!(context.retrieved[0].1.contains("println!"));
+        assert_eq!(context.consulted_files.len(), 1);
+        assert!(context.answer.is_none());
+
+        let context = Pipeline::new("summarized")
+            .step(retrieve())
+            .step(Summarize)
+            .step(Answer)
+            .step(Review::new(Arc::new(LlmReviewer)))
+            .run("What does main do?", &mut model)
+            .await
+            .expect("Failed to run pipeline");
+        assert_eq!(model.calls, 3);
+        assert_eq!(context.answer.as_deref(), Some("It prints a greeting."));
+        assert_eq!(context.verdict, Some(Verdict::Pass));
+    }
+
+    #[test]
+    fn test_from_step_names() {
+        let pipeline = Pipeline::from_step_names("quick", &["retrieve", "answer"])
+            .expect("Failed to build pipeline");
+        assert_eq!(pipeline.name(), "quick");
+        assert_eq!(pipeline.steps.len(), 2);
+        assert!(matches!(
+            Pipeline::from_step_names("bad", &["retrieve", "guess"]),
+            Err(PipelineError::UnknownStep(name)) if name == "guess"
+        ));
+    }
+}
@@ -0,0 +1,336 @@
+//! # Execution Plans
+//!
+//! This module defines the plan format produced by the planning step. A plan is a JSON
+//! array whose items are either plain command strings or step objects that name the steps
+//! they depend on:
+//!
+//! ```json
+//! [
+//!     {"id": "layout", "command": "tree src"},
+//!     {"id": "entry", "command": "show_file src/main.rs", "after": ["layout"]},
+//!     "show_file Cargo.toml"
+//! ]
+//! ```
+//!
+//! Steps without dependencies are independent of each other. [`stages`] groups the steps so
+//! that every stage only depends on earlier stages and the steps within a stage can run
+//! concurrently.
+//!
+//! Models often wrap the array in a Markdown code fence or explain it in a sentence;
+//! [`parse_plan`] reads the first fenced block, if any, and the array within it. Every
+//! command must name one of the commands the agent can run, such as the built-in
+//! [`COMMANDS`].
+
+use std::{collections::HashMap, error::Error, fmt};
+
+use serde::Deserialize;
+
+use crate::policy::tool_name;
+
+/// Opening of the planning prompt, followed by the descriptions of the available commands
+/// (see [`ToolRegistry::planner_prompt`](crate::tools::ToolRegistry::planner_prompt)).
+pub const PLANNER_PROMPT: &str = "You are an assistant that plans how to answer questions about code repositories. You can use these commands:";
+
+/// The commands of the built-in tools.
+pub const COMMANDS: &[&str] = &[
+    "tree",
+    "show_file",
+    "show_signatures",
+    "outline",
+    "search",
+    "grep",
+    "coverage",
+    "blame",
+    "routes",
+    "owners",
+    "licenses",
+    "workflows",
+    "containers",
+    "makefiles",
+    "regex_test",
+    "glob_expand",
+    "run",
+    "run_rust",
+];
+
+/// Errors that make a plan unusable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlanError {
+    /// The plan is not a JSON array of commands or steps.
+    Syntax(String),
+    /// Two steps share the same ID.
+    DuplicateId(String),
+    /// A step runs a command the agent does not know.
+    UnknownCommand(String),
+    /// A step depends on an ID that no step has.
+    UnknownDependency {
+        /// The step declaring the dependency.
+        step: String,
+        /// The missing ID.
+        dependency: String,
+    },
+    /// The dependencies form a cycle through the listed steps.
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::Syntax(msg) => write!(f, "Plan is not a JSON array of steps: {msg}"),
+            PlanError::DuplicateId(id) => write!(f, "Plan step ID is used twice: {id}"),
+            PlanError::UnknownCommand(command) => {
+                write!(f, "Plan uses an unknown command: {command}")
+            }
+            PlanError::UnknownDependency { step, dependency } => {
+                write!(f, "Plan step {step} depends on unknown step {dependency}")
+            }
+            PlanError::Cycle(ids) => {
+                write!(f, "Plan steps depend on each other: {}", ids.join(", "))
+            }
+        }
+    }
+}
+
+impl Error for PlanError {}
+
+/// A single command in a plan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanStep {
+    /// Identifier other steps use to depend on this one.
+    pub id: String,
+    /// The command to run, e.g. `show_file src/main.rs`.
+    pub command: String,
+    /// IDs of the steps that must finish before this one starts.
+    pub after: Vec<String>,
+}
+
+impl PlanStep {
+    /// Creates a step without dependencies.
+    pub fn new(id: impl Into<String>, command: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            command: command.into(),
+            after: Vec::new(),
+        }
+    }
+}
+
+/// A plan item as written by the model.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawStep {
+    Command(String),
+    Step {
+        id: Option<String>,
+        command: String,
+        #[serde(default)]
+        after: Vec<String>,
+    },
+}
+
+/// Parses a plan from its JSON representation, as written by the model.
+///
+/// The array may be inside a Markdown code fence or surrounded by prose. Steps without an
+/// explicit ID are numbered by their position, starting at 1.
+///
+/// # Errors
+///
+/// Returns `PlanError::Syntax` if `text` holds no JSON array of commands or steps,
+/// `PlanError::DuplicateId` if two steps share an ID, and `PlanError::UnknownCommand` if a
+/// step's command is not one of `commands`.
+pub fn parse_plan(text: &str, commands: &[&str]) -> Result<Vec<PlanStep>, PlanError> {
+    let raw: Vec<RawStep> =
+        serde_json::from_str(extract_array(text)).map_err(|e| PlanError::Syntax(e.to_string()))?;
+
+    let mut steps: Vec<PlanStep> = Vec::with_capacity(raw.len());
+    for (index, item) in raw.into_iter().enumerate() {
+        let position = (index + 1).to_string();
+        let step = match item {
+            RawStep::Command(command) => PlanStep::new(position, command),
+            RawStep::Step { id, command, after } => PlanStep {
+                id: id.unwrap_or(position),
+                command,
+                after,
+            },
+        };
+        if steps.iter().any(|existing| existing.id == step.id) {
+            return Err(PlanError::DuplicateId(step.id));
+        }
+        steps.push(step);
+    }
+    if let Some(step) = steps
+        .iter()
+        .find(|step| !commands.contains(&tool_name(&step.command)))
+    {
+        return Err(PlanError::UnknownCommand(step.command.clone()));
+    }
+    Ok(steps)
+}
+
+/// Returns the JSON array in `text`: the contents of the first Markdown code fence if there
+/// is one, from its first `[` to its last `]`.
+fn extract_array(text: &str) -> &str {
+    let mut text = text.trim();
+    if let Some((_, fenced)) = text.split_once("```") {
+        // Skip the language tag, e.g. ```json
+        let fenced = fenced.split_once('\n').map_or(fenced, |(_, body)| body);
+        text = fenced.split_once("```").map_or(fenced, |(body, _)| body);
+    }
+    match (text.find('['), text.rfind(']')) {
+        (Some(start), Some(end)) if start < end => &text[start..=end],
+        _ => text.trim(),
+    }
+}
+
+/// Groups steps into stages that run in order; steps within a stage are independent.
+///
+/// Each stage lists indices into `steps`, in plan order.
+///
+/// # Errors
+///
+/// Returns `PlanError::UnknownDependency` if a step depends on a missing ID, and
+/// `PlanError::Cycle` if the dependencies cannot be ordered.
+pub fn stages(steps: &[PlanStep]) -> Result<Vec<Vec<usize>>, PlanError> {
+    let index_of: HashMap<&str, usize> = steps
+        .iter()
+        .enumerate()
+        .map(|(index, step)| (step.id.as_str(), index))
+        .collect();
+
+    let mut dependencies = Vec::with_capacity(steps.len());
+    for step in steps {
+        let indices = step
+            .after
+            .iter()
+            .map(|dependency| {
+                index_of.get(dependency.as_str()).copied().ok_or_else(|| {
+                    PlanError::UnknownDependency {
+                        step: step.id.clone(),
+                        dependency: dependency.clone(),
+                    }
+                })
+            })
+            .collect::<Result<Vec<usize>, PlanError>>()?;
+        dependencies.push(indices);
+    }
+
+    let mut stage_of: Vec<Option<usize>> = vec![None; steps.len()];
+    let mut stages: Vec<Vec<usize>> = Vec::new();
+    while stage_of.iter().any(Option::is_none) {
+        let ready: Vec<usize> = (0..steps.len())
+            .filter(|&index| stage_of[index].is_none())
+            .filter(|&index| {
+                dependencies[index]
+                    .iter()
+                    .all(|&dependency| stage_of[dependency].is_some())
+            })
+            .collect();
+        if ready.is_empty() {
+            let blocked = (0..steps.len())
+                .filter(|&index| stage_of[index].is_none())
+                .map(|index| steps[index].id.clone())
+                .collect();
+            return Err(PlanError::Cycle(blocked));
+        }
+        for &index in &ready {
+            stage_of[index] = Some(stages.len());
+        }
+        stages.push(ready);
+    }
+    Ok(stages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_plain_commands() {
+        let steps = parse_plan(r#"["tree src", "show_file src/main.rs"]"#, COMMANDS)
+            .expect("Failed to parse plan");
+        assert_eq!(steps[0], PlanStep::new("1", "tree src"));
+        assert_eq!(steps[1], PlanStep::new("2", "show_file src/main.rs"));
+        assert_eq!(
+            stages(&steps).expect("Failed to order plan"),
+            vec![vec![0, 1]]
+        );
+    }
+
+    #[test]
+    fn test_dependencies_form_stages() {
+        let steps = parse_plan(
+            r#"[
+                {"id": "layout", "command": "tree src"},
+                {"id": "entry", "command": "show_file src/main.rs", "after": ["layout"]},
+                "show_file Cargo.toml",
+                {"command": "show_file src/lib.rs", "after": ["entry", "3"]}
+            ]"#,
+            COMMANDS,
+        )
+        .expect("Failed to parse plan");
+        assert_eq!(steps[3].id, "4");
+        assert_eq!(
+            stages(&steps).expect("Failed to order plan"),
+            vec![vec![0, 2], vec![1], vec![3]]
+        );
+    }
+
+    #[test]
+    fn test_parse_wrapped_plan() {
+        let reply = "I'll start with the layout:\n\n```json\n[\"tree src\", \"search main src\"]\n```\n\nThen read [the entry point].";
+        assert_eq!(
+            parse_plan(reply, COMMANDS),
+            Ok(vec![
+                PlanStep::new("1", "tree src"),
+                PlanStep::new("2", "search main src")
+            ])
+        );
+        assert_eq!(
+            parse_plan("Plan: [\"show_file src/lib.rs\"] should do.", COMMANDS),
+            Ok(vec![PlanStep::new("1", "show_file src/lib.rs")])
+        );
+    }
+
+    #[test]
+    fn test_invalid_plans() {
+        assert!(matches!(
+            parse_plan("tree src", COMMANDS),
+            Err(PlanError::Syntax(_))
+        ));
+        assert_eq!(
+            parse_plan(
+                r#"[{"id": "a", "command": "x"}, {"id": "a", "command": "y"}]"#,
+                COMMANDS
+            ),
+            Err(PlanError::DuplicateId("a".to_string()))
+        );
+        assert_eq!(
+            parse_plan(r#"["tree src", "cat src/main.rs"]"#, COMMANDS),
+            Err(PlanError::UnknownCommand("cat src/main.rs".to_string()))
+        );
+
+        let missing = vec![PlanStep {
+            after: vec!["nope".to_string()],
+            ..PlanStep::new("a", "tree .")
+        }];
+        assert!(matches!(
+            stages(&missing),
+            Err(PlanError::UnknownDependency { .. })
+        ));
+
+        let cycle = vec![
+            PlanStep {
+                after: vec!["b".to_string()],
+                ..PlanStep::new("a", "tree .")
+            },
+            PlanStep {
+                after: vec!["a".to_string()],
+                ..PlanStep::new("b", "tree .")
+            },
+        ];
+        assert_eq!(
+            stages(&cycle),
+            Err(PlanError::Cycle(vec!["a".to_string(), "b".to_string()]))
+        );
+    }
+}
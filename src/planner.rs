@@ -0,0 +1,208 @@
+//! # Planner Settings
+//!
+//! This module holds the settings that shape the planning prompt. Directories can be
+//! marked as low priority (for example `vendor/` or `generated/`): they still appear in
+//! `tree` output, but the planner is told to avoid reading files inside them unless the
+//! question explicitly asks about them.
+//!
+//! Projects can also provide few-shot examples (a question, a good plan, and a good answer)
+//! that are shown to the planner to teach it the project's conventions, e.g. that API
+//! handlers live under `routes/` rather than `controllers/`.
+//!
+//! Commands can be marked as preferred, e.g. by a question template that relies on
+//! `search`, so the planner reaches for them first.
+//!
+//! After the plan has run, the planner may request follow-up commands based on what the
+//! plan found (for example opening a file a `tree` listing revealed) without waiting for a
+//! full answer and review cycle. The number of such rounds per iteration is limited.
+//!
+//! When a plan reads more files than `max_files`, or more tokens than `read_budget`, the
+//! files are ranked by relevance to the question and only the best ones are read. A file
+//! longer than `file_budget` tokens is shortened to its head, its tail and the regions
+//! mentioning words of the question.
+//!
+//! With `context = "signatures"`, planned file reads first show only the signatures and doc
+//! comments of each file. The full files are read once a review finds the answer lacking
+//! detail.
+
+use serde::Deserialize;
+
+/// The `[planner]` section of the configuration file.
+///
+/// ```toml
+/// [planner]
+/// low_priority = ["vendor/", "generated/"]
+/// preferred_tools = ["search"]
+/// follow_up_rounds = 2
+/// max_files = 8
+/// read_budget = 24000
+/// file_budget = 8000
+/// context = "signatures"
+///
+/// [[planner.examples]]
+/// question = "Where is the login endpoint handled?"
+/// plan = ["tree routes", "show_file routes/auth.rs"]
+/// answer = "API handlers live under routes/; login is handled in routes/auth.rs."
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PlannerConfig {
+    /// Directories the planner should avoid reading from.
+    pub low_priority: Vec<String>,
+    /// Commands the planner should use first when they can help.
+    pub preferred_tools: Vec<String>,
+    /// Few-shot examples included in the planning prompt.
+    pub examples: Vec<PlannerExample>,
+    /// How many times per iteration the planner may request follow-up commands.
+    pub follow_up_rounds: usize,
+    /// Most files a plan may read before the files are ranked by relevance.
+    pub max_files: usize,
+    /// Most estimated tokens a plan may read before the files are ranked by relevance.
+    pub read_budget: usize,
+    /// Most estimated tokens of a single file shown to the model before its middle is
+    /// elided.
+    pub file_budget: usize,
+    /// How much of each planned file is shown to the model.
+    pub context: ContextMode,
+}
+
+/// How much of a file a planned read shows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextMode {
+    /// The whole file.
+    #[default]
+    Full,
+    /// The signatures and doc comments of the file's public definitions, until a review
+    /// asks for more detail.
+    Signatures,
+}
+
+impl Default for PlannerConfig {
+    fn default() -> Self {
+        Self {
+            low_priority: Vec::new(),
+            preferred_tools: Vec::new(),
+            examples: Vec::new(),
+            follow_up_rounds: 2,
+            max_files: 8,
+            read_budget: 24_000,
+            file_budget: 8_000,
+            context: ContextMode::Full,
+        }
+    }
+}
+
+/// A worked example of answering a question about the project.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PlannerExample {
+    /// An example question.
+    pub question: String,
+    /// The commands a good plan runs for the question.
+    pub plan: Vec<String>,
+    /// A good answer to the question.
+    pub answer: String,
+}
+
+impl PlannerConfig {
+    /// Returns the instructions to append to the planning prompt, if any.
+    pub fn guidance(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if !self.low_priority.is_empty() {
+            let dirs: Vec<String> = self
+                .low_priority
+                .iter()
+                .map(|dir| format!("{}/", dir.trim_end_matches('/')))
+                .collect();
+            parts.push(format!(
+                "The following directories are low priority: {}. They may appear in tree output, but do not plan to read files inside them unless the question explicitly requires it.",
+                dirs.join(", ")
+            ));
+        }
+        if !self.preferred_tools.is_empty() {
+            parts.push(format!(
+                "Prefer these commands whenever they can help: {}.",
+                self.preferred_tools.join(", ")
+            ));
+        }
+        (!parts.is_empty()).then(|| parts.join(" "))
+    }
+
+    /// Renders the few-shot examples for the planning prompt, if any.
+    pub fn examples_prompt(&self) -> Option<String> {
+        if self.examples.is_empty() {
+            return None;
+        }
+
+        let mut prompt =
+            "Here are examples of good plans for questions about this project:".to_string();
+        for example in &self.examples {
+            let plan = serde_json::to_string(&example.plan).unwrap_or_default();
+            prompt.push_str(&format!(
+                "\n\nQuestion: {}\nPlan: {plan}\nAnswer: {}",
+                example.question, example.answer
+            ));
+        }
+        Some(prompt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_guidance_by_default() {
+        assert!(PlannerConfig::default().guidance().is_none());
+    }
+
+    #[test]
+    fn test_follow_up_rounds() {
+        let config: PlannerConfig = toml::from_str("").expect("Failed to parse planner config");
+        assert_eq!(config.follow_up_rounds, 2);
+        let config: PlannerConfig =
+            toml::from_str("follow_up_rounds = 0").expect("Failed to parse planner config");
+        assert_eq!(config.follow_up_rounds, 0);
+    }
+
+    #[test]
+    fn test_guidance_lists_directories() {
+        let config = PlannerConfig {
+            low_priority: vec!["vendor".to_string(), "generated/".to_string()],
+            ..PlannerConfig::default()
+        };
+        let guidance = config.guidance().expect("Expected guidance");
+        assert!(guidance.contains("vendor/, generated/"));
+    }
+
+    #[test]
+    fn test_guidance_lists_preferred_tools() {
+        let config = PlannerConfig {
+            preferred_tools: vec!["search".to_string(), "show_file".to_string()],
+            ..PlannerConfig::default()
+        };
+        assert_eq!(
+            config.guidance().as_deref(),
+            Some("Prefer these commands whenever they can help: search, show_file.")
+        );
+    }
+
+    #[test]
+    fn test_examples_prompt() {
+        assert!(PlannerConfig::default().examples_prompt().is_none());
+
+        let config: PlannerConfig = toml::from_str(
+            r#"
+            [[examples]]
+            question = "Where is login handled?"
+            plan = ["tree routes", "show_file routes/auth.rs"]
+            answer = "In routes/auth.rs."
+            "#,
+        )
+        .expect("Failed to parse planner config");
+        let prompt = config.examples_prompt().expect("Expected examples");
+        assert!(prompt.contains("Question: Where is login handled?"));
+        assert!(prompt.contains(r#"Plan: ["tree routes","show_file routes/auth.rs"]"#));
+        assert!(prompt.contains("Answer: In routes/auth.rs."));
+    }
+}
@@ -0,0 +1,259 @@
+//! # Tool Permission Policy
+//!
+//! This module decides whether the agent may run a planned command. Every tool belongs to a
+//! class describing what it can do to the machine:
+//!
+//! - `read_only`: only inspects the repository (`tree`, `show_file`, `show_signatures`,
+//!   `outline`, `search`, `grep`, `coverage`, `blame`, `routes`, `owners`, `licenses`,
+//!   `workflows`, `containers`, `makefiles`, `regex_test`, `glob_expand`)
+//! - `exec`: runs external programs (`run`, `run_rust`)
+//! - `write`: modifies files (`write_file`)
+//!
+//! Each class has a default permission (allow, deny, or ask for confirmation) which can be
+//! overridden per class or per tool in the `[policy]` section of the configuration. Write
+//! tools are always denied unless the user passes `--allow-write`, and opt-in tools such as
+//! `run_rust` are denied unless configured in `[policy.tools]`.
+//!
+//! In read-only mode (see [`Policy::read_only`]), every tool that is not `read_only` is
+//! denied whatever the configuration says.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+/// Tools denied unless the configuration sets their permission.
+const OPT_IN_TOOLS: [&str; 1] = ["run_rust"];
+
+/// What a tool is capable of doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolClass {
+    /// The tool only reads from the repository.
+    ReadOnly,
+    /// The tool executes external programs.
+    Exec,
+    /// The tool modifies files.
+    Write,
+}
+
+/// The rule applied to a tool invocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// Run the tool without asking.
+    Allow,
+    /// Refuse to run the tool.
+    Deny,
+    /// Ask the user before running the tool.
+    Ask,
+}
+
+/// The `[policy]` section of the configuration file.
+///
+/// ```toml
+/// [policy]
+/// exec = "deny"
+///
+/// [policy.tools]
+/// show_file = "ask"
+///
+/// [policy.classes]
+/// my_tool = "exec"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PolicyConfig {
+    /// Permission for read-only tools (default: allow).
+    pub read_only: Option<Permission>,
+    /// Permission for tools that execute programs (default: ask).
+    pub exec: Option<Permission>,
+    /// Permission for tools that write files (default: ask, once `--allow-write` is given).
+    pub write: Option<Permission>,
+    /// Per-tool permissions, taking precedence over the class permissions.
+    pub tools: HashMap<String, Permission>,
+    /// Class assignments for tools not known to nishiogi.
+    pub classes: HashMap<String, ToolClass>,
+}
+
+/// Evaluates tool invocations against the configured rules.
+#[derive(Debug, Default)]
+pub struct Policy {
+    config: PolicyConfig,
+    allow_write: bool,
+    unattended: bool,
+    read_only: bool,
+}
+
+impl Policy {
+    /// Creates a policy from configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The `[policy]` section of the configuration.
+    /// * `allow_write` - Whether write tools may run at all (the `--allow-write` flag).
+    pub fn new(config: PolicyConfig, allow_write: bool) -> Self {
+        Self {
+            config,
+            allow_write,
+            unattended: false,
+            read_only: false,
+        }
+    }
+
+    /// Denies every tool that runs programs or modifies files, regardless of the
+    /// configuration, as a safety switch for demos and CI (the `--read-only` flag).
+    #[must_use]
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Returns whether only read-only tools may run.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Denies every tool that would otherwise ask for confirmation, for use where nobody
+    /// can answer, such as server mode.
+    #[must_use]
+    pub fn unattended(mut self) -> Self {
+        self.unattended = true;
+        self
+    }
+
+    /// Returns the class of the given tool.
+    ///
+    /// Built-in tools have fixed classes. Other tools use the configured class and are
+    /// treated as `exec` when unconfigured, since nothing is known about what they do.
+    pub fn classify(&self, tool: &str) -> ToolClass {
+        match tool {
+            "tree" | "show_file" | "show_signatures" | "outline" | "search" | "grep"
+            | "coverage" | "blame" | "routes" | "owners" | "licenses" | "workflows"
+            | "containers" | "makefiles" | "regex_test" | "glob_expand" => ToolClass::ReadOnly,
+            "run" | "run_rust" => ToolClass::Exec,
+            "write_file" => ToolClass::Write,
+            _ => self
+                .config
+                .classes
+                .get(tool)
+                .copied()
+                .unwrap_or(ToolClass::Exec),
+        }
+    }
+
+    /// Returns the permission for running `tool`.
+    pub fn permission(&self, tool: &str) -> Permission {
+        match self.configured_permission(tool) {
+            Permission::Ask if self.unattended => Permission::Deny,
+            permission => permission,
+        }
+    }
+
+    /// Returns the permission for running `tool` according to the configuration alone.
+    fn configured_permission(&self, tool: &str) -> Permission {
+        let class = self.classify(tool);
+        if self.read_only && class != ToolClass::ReadOnly {
+            return Permission::Deny;
+        }
+        if class == ToolClass::Write && !self.allow_write {
+            return Permission::Deny;
+        }
+        if let Some(permission) = self.config.tools.get(tool) {
+            return *permission;
+        }
+        if OPT_IN_TOOLS.contains(&tool) {
+            return Permission::Deny;
+        }
+        match class {
+            ToolClass::ReadOnly => self.config.read_only.unwrap_or(Permission::Allow),
+            ToolClass::Exec => self.config.exec.unwrap_or(Permission::Ask),
+            ToolClass::Write => self.config.write.unwrap_or(Permission::Ask),
+        }
+    }
+}
+
+/// Extracts the tool name (the first word) from a command string.
+pub fn tool_name(command: &str) -> &str {
+    command.split_whitespace().next().unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_permissions() {
+        let policy = Policy::default();
+        assert_eq!(policy.permission("show_file"), Permission::Allow);
+        assert_eq!(policy.permission("tree"), Permission::Allow);
+        assert_eq!(policy.permission("run"), Permission::Ask);
+        assert_eq!(policy.permission("run_rust"), Permission::Deny);
+        assert_eq!(policy.permission("write_file"), Permission::Deny);
+        assert_eq!(policy.permission("mystery"), Permission::Ask);
+    }
+
+    #[test]
+    fn test_write_requires_flag() {
+        let mut config = PolicyConfig::default();
+        config
+            .tools
+            .insert("write_file".to_string(), Permission::Allow);
+        assert_eq!(
+            Policy::new(config, false).permission("write_file"),
+            Permission::Deny
+        );
+
+        let policy = Policy::new(PolicyConfig::default(), true);
+        assert_eq!(policy.permission("write_file"), Permission::Ask);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut config = PolicyConfig {
+            read_only: Some(Permission::Ask),
+            ..PolicyConfig::default()
+        };
+        config.tools.insert("tree".to_string(), Permission::Allow);
+        config.tools.insert("run_rust".to_string(), Permission::Ask);
+        config
+            .classes
+            .insert("lint".to_string(), ToolClass::ReadOnly);
+        let policy = Policy::new(config, false);
+        assert_eq!(policy.permission("tree"), Permission::Allow);
+        assert_eq!(policy.permission("show_file"), Permission::Ask);
+        assert_eq!(policy.classify("lint"), ToolClass::ReadOnly);
+        assert_eq!(policy.permission("lint"), Permission::Ask);
+        assert_eq!(policy.permission("run_rust"), Permission::Ask);
+
+        let policy = policy.unattended();
+        assert_eq!(policy.permission("tree"), Permission::Allow);
+        assert_eq!(policy.permission("lint"), Permission::Deny);
+    }
+
+    #[test]
+    fn test_read_only() {
+        let mut config = PolicyConfig {
+            exec: Some(Permission::Allow),
+            ..PolicyConfig::default()
+        };
+        config
+            .tools
+            .insert("write_file".to_string(), Permission::Allow);
+        config
+            .classes
+            .insert("lint".to_string(), ToolClass::ReadOnly);
+        let policy = Policy::new(config, true).read_only(true);
+        assert_eq!(policy.permission("run"), Permission::Deny);
+        assert_eq!(policy.permission("write_file"), Permission::Deny);
+        assert_eq!(policy.permission("mystery"), Permission::Deny);
+        assert_eq!(policy.permission("lint"), Permission::Allow);
+        assert_eq!(policy.permission("show_file"), Permission::Allow);
+    }
+
+    #[test]
+    fn test_tool_name() {
+        assert_eq!(tool_name("show_file src/main.rs"), "show_file");
+        assert_eq!(tool_name("  tree"), "tree");
+        assert_eq!(tool_name(""), "");
+    }
+}
@@ -0,0 +1,29 @@
+//! # File Provenance
+//!
+//! This module records which files, and which lines of them, an answer was produced from.
+//! The records are plain data so reviewers can check citations without touching the
+//! filesystem; [`crate::session`] stores them and checks them against the working tree.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// An inclusive, 1-based range of lines within a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineRange {
+    /// First line of the range.
+    pub start: usize,
+    /// Last line of the range.
+    pub end: usize,
+}
+
+/// A file that was read while producing an answer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileProvenance {
+    /// Path of the file as requested by the plan.
+    pub path: PathBuf,
+    /// The lines that were read, or `None` when the whole file was read.
+    pub range: Option<LineRange>,
+    /// SHA-256 of the file content at the time it was read.
+    pub sha256: String,
+}
@@ -0,0 +1,478 @@
+//! # Model Providers
+//!
+//! This module abstracts the service that answers prompts, so the agent is not tied to
+//! GitHub Copilot. Every backend implements [`LlmProvider`]:
+//!
+//! - `copilot`: GitHub Copilot ([`CopilotClient`](github_copilot_client::CopilotClient)), signed in with `nishiogi auth login`
+//! - `claude`: the Anthropic API ([`ClaudeClient`](claude_client::ClaudeClient)), with the key in `ANTHROPIC_API_KEY`
+//! - `openai`: the OpenAI API ([`OpenAiClient`](openai_client::OpenAiClient)), with the key in `OPENAI_API_KEY`
+//!
+//! The provider is chosen with `--provider` or `name` in the `[provider]` section of the
+//! configuration. Whatever the provider, the organization policy applies: the provider and
+//! the model must be allowed and message contents are redacted. Requests wait for a slot of
+//! the [scheduler](crate::scheduler) under the provider's name, so `[limits.providers]`
+//! quotas apply per provider.
+//!
+//! Providers and models differ in what they support, so each provider describes its
+//! models' [`Capabilities`]. The agent records them when it starts and picks its strategies
+//! accordingly: plans are requested in JSON mode where the provider has one and as JSON
+//! written in prose otherwise, and prompts are fitted to the model's context window.
+//! Backends that do not describe their models get conservative defaults.
+
+use std::{fmt, str::FromStr};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::{
+    claude_client,
+    github_copilot_client::{
+        self, ChatChoice, ChatResponse, CopilotError, Embedding, Message, Model, TokenUsage,
+    },
+    openai_client, org_policy,
+    tokens::DEFAULT_CONTEXT_WINDOW,
+};
+
+/// Receives the pieces of a streamed reply as they arrive.
+pub type TextSink<'a> = dyn FnMut(&str) + Send + 'a;
+
+/// What a model of a provider supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Whether the model can call functions declared in the request.
+    pub tool_calling: bool,
+    /// Whether replies can be constrained to a JSON object, with [`LlmProvider::chat_json`].
+    pub json_mode: bool,
+    /// Whether replies are streamed as they are generated by [`LlmProvider::stream`].
+    pub streaming: bool,
+    /// Whether prompts may include images.
+    pub vision: bool,
+    /// The most tokens a prompt may hold.
+    pub max_context: usize,
+}
+
+impl Capabilities {
+    /// Returns the capabilities assumed for a model nothing is known about: plain text
+    /// replies and the given context window.
+    pub fn basic(max_context: usize) -> Self {
+        Self {
+            tool_calling: false,
+            json_mode: false,
+            streaming: false,
+            vision: false,
+            max_context,
+        }
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let features: Vec<&str> = [
+            (self.tool_calling, "tools"),
+            (self.json_mode, "json"),
+            (self.streaming, "streaming"),
+            (self.vision, "vision"),
+        ]
+        .into_iter()
+        .filter_map(|(supported, name)| supported.then_some(name))
+        .collect();
+        if !features.is_empty() {
+            write!(f, "{}; ", features.join(", "))?;
+        }
+        write!(f, "{}k context", self.max_context / 1000)
+    }
+}
+
+/// A service that answers prompts.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait LlmProvider: Send + Sync {
+    /// The provider's name, as in `--provider` and `[limits.providers]`.
+    fn name(&self) -> &'static str;
+
+    /// Returns the models available to the account.
+    fn models(&self) -> &[Model];
+
+    /// Returns whether the model with the given ID is available to the account.
+    fn has_model(&self, model_id: &str) -> bool {
+        self.models().iter().any(|model| model.id == model_id)
+    }
+
+    /// Returns what the model `model_id` supports.
+    ///
+    /// By default, nothing beyond plain text replies, within the context window the models
+    /// list reports or else [`DEFAULT_CONTEXT_WINDOW`].
+    fn capabilities(&self, model_id: &str) -> Capabilities {
+        Capabilities::basic(reported_context(self.models(), model_id))
+    }
+
+    /// Sends `messages` to the model `model_id` and returns its reply.
+    ///
+    /// The first `stable_prefix` messages are identical across requests, so the provider may
+    /// serve them from its prompt cache. The reply is limited to `max_tokens` tokens if
+    /// given.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CopilotError::InvalidModel` if the model is not available,
+    /// `CopilotError::Forbidden` if the organization policy does not allow the provider or
+    /// the model, or another `CopilotError` if the request fails.
+    async fn chat(
+        &self,
+        messages: Vec<Message>,
+        model_id: &str,
+        stable_prefix: usize,
+        max_tokens: Option<u32>,
+    ) -> Result<ChatResponse, CopilotError>;
+
+    /// Sends `messages` like [`LlmProvider::chat`], constraining the reply to a JSON object
+    /// if the model has a JSON mode.
+    ///
+    /// By default, the reply is not constrained, so it must be read leniently either way.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`LlmProvider::chat`].
+    async fn chat_json(
+        &self,
+        messages: Vec<Message>,
+        model_id: &str,
+        stable_prefix: usize,
+        max_tokens: Option<u32>,
+    ) -> Result<ChatResponse, CopilotError> {
+        self.chat(messages, model_id, stable_prefix, max_tokens)
+            .await
+    }
+
+    /// Sends `messages` like [`LlmProvider::chat`], passing the reply to `on_text` piece by
+    /// piece as it is generated.
+    ///
+    /// By default, the whole reply is passed at once when it is complete.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`LlmProvider::chat`].
+    async fn stream(
+        &self,
+        messages: Vec<Message>,
+        model_id: &str,
+        max_tokens: Option<u32>,
+        on_text: &mut TextSink<'_>,
+    ) -> Result<ChatResponse, CopilotError> {
+        let response = self.chat(messages, model_id, 0, max_tokens).await?;
+        if let Some(choice) = response.choices.first() {
+            on_text(&choice.message.content);
+        }
+        Ok(response)
+    }
+
+    /// Computes embeddings of `inputs`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CopilotError::Unsupported` by default, for providers without embeddings.
+    async fn embeddings(&self, inputs: Vec<String>) -> Result<Vec<Embedding>, CopilotError> {
+        let _ = inputs;
+        Err(CopilotError::Unsupported(format!(
+            "The {} provider does not compute embeddings",
+            self.name()
+        )))
+    }
+}
+
+/// The built-in providers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    /// GitHub Copilot.
+    #[default]
+    Copilot,
+    /// The Anthropic API.
+    Claude,
+    /// The OpenAI API.
+    Openai,
+}
+
+impl ProviderKind {
+    /// Returns the provider's name, as in `--provider`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ProviderKind::Copilot => github_copilot_client::PROVIDER,
+            ProviderKind::Claude => claude_client::PROVIDER,
+            ProviderKind::Openai => openai_client::PROVIDER,
+        }
+    }
+
+    /// Returns the model used unless another one is configured.
+    pub fn default_model(self) -> &'static str {
+        match self {
+            ProviderKind::Copilot => github_copilot_client::DEFAULT_MODEL,
+            ProviderKind::Claude => claude_client::DEFAULT_MODEL,
+            ProviderKind::Openai => openai_client::DEFAULT_MODEL,
+        }
+    }
+
+    /// Connects to the provider with the credentials of the environment, fetching the
+    /// available models.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `CopilotError` if there are no usable credentials or the models cannot be
+    /// fetched.
+    #[cfg(feature = "native")]
+    pub async fn connect(self) -> Result<Box<dyn LlmProvider>, CopilotError> {
+        Ok(match self {
+            ProviderKind::Copilot => Box::new(
+                github_copilot_client::CopilotClient::from_env_with_models("1.0.0".to_string())
+                    .await?,
+            ),
+            ProviderKind::Claude => Box::new(claude_client::ClaudeClient::from_env().await?),
+            ProviderKind::Openai => Box::new(openai_client::OpenAiClient::from_env().await?),
+        })
+    }
+}
+
+impl fmt::Display for ProviderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ProviderKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        [
+            ProviderKind::Copilot,
+            ProviderKind::Claude,
+            ProviderKind::Openai,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == name)
+        .ok_or_else(|| format!("Unknown provider {name}; expected copilot, claude, or openai"))
+    }
+}
+
+/// Returns the context window of `model_id` as listed in `models`, or else
+/// [`DEFAULT_CONTEXT_WINDOW`].
+pub(crate) fn reported_context(models: &[Model], model_id: &str) -> usize {
+    models
+        .iter()
+        .find(|model| model.id == model_id)
+        .and_then(|model| model.max_input_tokens)
+        .map_or(DEFAULT_CONTEXT_WINDOW, |tokens| tokens as usize)
+}
+
+/// Fails unless the organization policy allows `provider`.
+pub(crate) fn check_provider(provider: &str) -> Result<(), CopilotError> {
+    if org_policy::global().allows_provider(provider) {
+        Ok(())
+    } else {
+        Err(CopilotError::Forbidden(format!(
+            "provider {provider} is not allowed"
+        )))
+    }
+}
+
+/// Fails unless the organization policy allows `provider` and its model `model_id`.
+pub(crate) fn check_model(provider: &str, model_id: &str) -> Result<(), CopilotError> {
+    check_provider(provider)?;
+    if org_policy::global().allows_model(model_id) {
+        Ok(())
+    } else {
+        Err(CopilotError::Forbidden(format!(
+            "model {model_id} is not allowed"
+        )))
+    }
+}
+
+/// Applies the organization policy to a request of `provider` to `model_id`, returning the
+/// redacted `messages`.
+pub(crate) fn apply_policy(
+    provider: &str,
+    model_id: &str,
+    messages: Vec<Message>,
+) -> Result<Vec<Message>, CopilotError> {
+    check_model(provider, model_id)?;
+    let policy = org_policy::global();
+    Ok(messages
+        .into_iter()
+        .map(|message| Message {
+            content: policy.redact(&message.content).into_owned(),
+            ..message
+        })
+        .collect())
+}
+
+/// Classifies a failed HTTP request to a provider authenticated with an API key.
+pub(crate) fn api_key_error(err: reqwest::Error) -> CopilotError {
+    match CopilotError::from_http(err) {
+        CopilotError::Unauthorized(msg) => CopilotError::ApiKey(msg),
+        err => err,
+    }
+}
+
+/// One event of a streamed chat completion.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct StreamEvent {
+    choices: Vec<StreamChoice>,
+    usage: Option<TokenUsage>,
+}
+
+/// A choice of a streamed chat completion event.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct StreamChoice {
+    delta: StreamDelta,
+    finish_reason: Option<String>,
+}
+
+/// The text a streamed chat completion event adds.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct StreamDelta {
+    content: Option<String>,
+}
+
+/// A chat completion assembled from the events of a stream.
+#[derive(Debug, Default)]
+struct StreamedReply {
+    content: String,
+    finish_reason: Option<String>,
+    usage: Option<TokenUsage>,
+}
+
+impl StreamedReply {
+    /// Reads the data of a server-sent event, passing the text it adds to `on_text`.
+    ///
+    /// Returns `false` once the stream is done.
+    fn feed(&mut self, data: &str, on_text: &mut TextSink<'_>) -> bool {
+        if data == "[DONE]" {
+            return false;
+        }
+        let Ok(event) = serde_json::from_str::<StreamEvent>(data) else {
+            return true;
+        };
+        for choice in event.choices {
+            if let Some(text) = choice.delta.content.filter(|text| !text.is_empty()) {
+                on_text(&text);
+                self.content.push_str(&text);
+            }
+            if choice.finish_reason.is_some() {
+                self.finish_reason = choice.finish_reason;
+            }
+        }
+        if event.usage.is_some() {
+            self.usage = event.usage;
+        }
+        true
+    }
+
+    /// Returns the assembled reply.
+    fn finish(self) -> ChatResponse {
+        ChatResponse {
+            choices: vec![ChatChoice {
+                message: Message {
+                    role: "assistant".to_string(),
+                    content: self.content,
+                },
+                finish_reason: self.finish_reason,
+                usage: None,
+            }],
+            usage: self.usage,
+        }
+    }
+}
+
+/// Reads the data of each server-sent event of `response`, passing it to `on_data` until it
+/// returns `false` or the stream ends.
+pub(crate) async fn read_events(
+    mut response: reqwest::Response,
+    mut on_data: impl FnMut(&str) -> bool + Send,
+) -> Result<(), CopilotError> {
+    let mut line_data =
+        |line: &[u8]| match String::from_utf8_lossy(line).trim().strip_prefix("data:") {
+            Some(data) => on_data(data.trim()),
+            None => true,
+        };
+    let mut pending: Vec<u8> = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(CopilotError::from_http)? {
+        pending.extend_from_slice(&chunk);
+        // Lines are only decoded once complete, so characters split across chunks survive
+        while let Some(end) = pending.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            if !line_data(&line) {
+                return Ok(());
+            }
+        }
+    }
+    line_data(&pending);
+    Ok(())
+}
+
+/// Reads a chat completion streamed in the OpenAI format, passing its text to `on_text` as
+/// it arrives.
+pub(crate) async fn read_stream(
+    response: reqwest::Response,
+    on_text: &mut TextSink<'_>,
+) -> Result<ChatResponse, CopilotError> {
+    let mut reply = StreamedReply::default();
+    read_events(response, |data| reply.feed(data, on_text)).await?;
+    Ok(reply.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_streamed_reply() {
+        let mut pieces = Vec::new();
+        let mut on_text = |text: &str| pieces.push(text.to_string());
+        let mut reply = StreamedReply::default();
+        for data in [
+            r#"{"choices": [{"delta": {"role": "assistant", "content": ""}}]}"#,
+            r#"{"choices": [{"delta": {"content": "The entry point"}}]}"#,
+            r#"{"choices": [{"delta": {"content": " is main."}, "finish_reason": "stop"}]}"#,
+            r#"{"choices": [], "usage": {"total_tokens": 42}}"#,
+        ] {
+            assert!(reply.feed(data, &mut on_text));
+        }
+        assert!(!reply.feed("[DONE]", &mut on_text));
+        let response = reply.finish();
+        assert_eq!(pieces, ["The entry point", " is main."]);
+        assert_eq!(
+            response.choices[0].message.content,
+            "The entry point is main."
+        );
+        assert_eq!(response.choices[0].finish_reason.as_deref(), Some("stop"));
+        assert_eq!(response.tokens_used(), 42);
+
+        assert_eq!("claude".parse(), Ok(ProviderKind::Claude));
+        assert!("gemini".parse::<ProviderKind>().is_err());
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let models = [Model {
+            id: "local".to_string(),
+            name: "local".to_string(),
+            version: None,
+            tokenizer: None,
+            max_input_tokens: Some(8192),
+            max_output_tokens: None,
+        }];
+        assert_eq!(reported_context(&models, "local"), 8192);
+        assert_eq!(reported_context(&models, "other"), DEFAULT_CONTEXT_WINDOW);
+        assert_eq!(Capabilities::basic(8192).to_string(), "8k context");
+        let full = Capabilities {
+            tool_calling: true,
+            json_mode: true,
+            streaming: true,
+            vision: false,
+            max_context: 128_000,
+        };
+        assert_eq!(full.to_string(), "tools, json, streaming; 128k context");
+    }
+}
@@ -0,0 +1,503 @@
+//! # Refactoring Suggestions
+//!
+//! This module implements `nishiogi refactor`. A refactoring is proposed in three stages:
+//!
+//! 1. **Impact analysis**: the code relevant to the request is located with the search
+//!    subsystem, and every call site of the symbols defined there is listed from the symbol
+//!    index.
+//! 2. **Proposal**: the model receives the relevant code and the call sites and proposes a
+//!    series of steps, each a set of search/replace edits.
+//! 3. **Review**: the series is checked by the model against the request and the call sites.
+//!    Rejected series, and edits that do not apply, are sent back with the reason, as in
+//!    the answer review loop of `ask`.
+//!
+//! The result is a patch per step, so each step can be reviewed (and committed) on its own.
+
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    agent::AgentError,
+    github_copilot_client::Message,
+    patch::{Draft, Patch, Replacement},
+    review::{LlmReviewer, ReviewInput, ReviewModel, Reviewer, Verdict},
+    search::{render_snippets, search, SearchOptions, Snippet},
+    symbols::{Reference, Symbol, SymbolIndex},
+    tokens::estimate_tokens,
+};
+
+/// Maximum number of proposals sent to review before giving up.
+const MAX_ATTEMPTS: usize = 3;
+
+/// Token budget of the relevant code located by search.
+const SEARCH_TOKEN_BUDGET: usize = 3000;
+
+/// Token budget of the full file contents included with the proposal request.
+const FILE_TOKEN_BUDGET: usize = 12000;
+
+/// Upper bound on the call sites listed per symbol.
+const MAX_CALL_SITES: usize = 50;
+
+/// Words of a refactoring request that describe the change rather than the code.
+const STOP_WORDS: &[&str] = &[
+    "about",
+    "across",
+    "code",
+    "each",
+    "every",
+    "extract",
+    "file",
+    "files",
+    "from",
+    "function",
+    "functions",
+    "helper",
+    "inline",
+    "into",
+    "logic",
+    "make",
+    "method",
+    "methods",
+    "module",
+    "move",
+    "rename",
+    "replace",
+    "shared",
+    "should",
+    "split",
+    "that",
+    "their",
+    "them",
+    "then",
+    "there",
+    "this",
+    "use",
+    "used",
+    "uses",
+    "with",
+];
+
+/// A symbol affected by a refactoring and the places it is used.
+#[derive(Debug, Clone)]
+pub struct AffectedSymbol {
+    /// The symbol's definition.
+    pub symbol: Symbol,
+    /// Mentions of the symbol outside its definition.
+    pub call_sites: Vec<Reference>,
+}
+
+/// The code a refactoring request concerns.
+#[derive(Debug, Clone, Default)]
+pub struct Impact {
+    /// Code relevant to the request, best match first.
+    pub snippets: Vec<Snippet>,
+    /// Symbols defined in the relevant code or named in the request.
+    pub symbols: Vec<AffectedSymbol>,
+}
+
+impl Impact {
+    /// Returns the files that are relevant or contain a call site, relative to the root.
+    fn files(&self, root: &Path) -> Vec<PathBuf> {
+        let mut files = BTreeSet::new();
+        for snippet in &self.snippets {
+            files.insert(
+                snippet
+                    .path
+                    .strip_prefix(root)
+                    .unwrap_or(&snippet.path)
+                    .to_path_buf(),
+            );
+        }
+        for affected in &self.symbols {
+            files.insert(affected.symbol.path.clone());
+            files.extend(affected.call_sites.iter().map(|site| site.path.clone()));
+        }
+        files.into_iter().collect()
+    }
+}
+
+/// A step of a proposed refactoring.
+#[derive(Debug, Clone)]
+pub struct RefactorStep {
+    /// What the step does.
+    pub title: String,
+    /// The changes of the step, on top of the previous steps.
+    pub patch: Patch,
+}
+
+/// A step as proposed by the model.
+#[derive(Debug, Deserialize)]
+struct ProposedStep {
+    title: String,
+    edits: Vec<Replacement>,
+}
+
+/// Locates the code relevant to `request` and lists the call sites it affects.
+pub fn analyze(root: &Path, request: &str) -> Impact {
+    let keywords = keywords(request);
+    if keywords.is_empty() {
+        return Impact::default();
+    }
+    let alternatives: Vec<String> = keywords.iter().map(|word| regex::escape(word)).collect();
+    let Ok(pattern) = Regex::new(&format!("(?i){}", alternatives.join("|"))) else {
+        return Impact::default();
+    };
+    let options = SearchOptions {
+        context_lines: 3,
+        max_tokens: SEARCH_TOKEN_BUDGET,
+    };
+    let snippets = search(root, &pattern, options);
+
+    let index = SymbolIndex::build(root);
+    let symbols = index
+        .symbols()
+        .iter()
+        .filter(|symbol| {
+            let named = keywords.iter().any(|word| word == &symbol.name);
+            let located = snippets.iter().any(|snippet| {
+                snippet.path.strip_prefix(root).unwrap_or(&snippet.path) == symbol.path
+                    && (snippet.start..=snippet.end).contains(&symbol.line)
+            });
+            named || located
+        })
+        .map(|symbol| {
+            let mut call_sites = index.references(&symbol.name);
+            call_sites.truncate(MAX_CALL_SITES);
+            AffectedSymbol {
+                symbol: symbol.clone(),
+                call_sites,
+            }
+        })
+        .collect();
+    Impact { snippets, symbols }
+}
+
+/// Renders the impact analysis as plain text.
+pub fn render_impact(impact: &Impact, root: &Path) -> String {
+    let mut output = String::from("Impact analysis\n\nRelevant code:\n");
+    if impact.snippets.is_empty() {
+        output.push_str("  (no matches)\n");
+    }
+    for snippet in &impact.snippets {
+        let path = snippet.path.strip_prefix(root).unwrap_or(&snippet.path);
+        let _ = writeln!(
+            output,
+            "  {}:{}-{}",
+            path.display(),
+            snippet.start,
+            snippet.end
+        );
+    }
+
+    output.push_str("\nAffected symbols:\n");
+    if impact.symbols.is_empty() {
+        output.push_str("  (none)\n");
+    }
+    for affected in &impact.symbols {
+        let symbol = &affected.symbol;
+        let _ = writeln!(
+            output,
+            "  {} {} ({}:{}): {} call site(s)",
+            symbol.kind,
+            symbol.name,
+            symbol.path.display(),
+            symbol.line,
+            affected.call_sites.len()
+        );
+        for site in &affected.call_sites {
+            let _ = writeln!(output, "    {}:{}", site.path.display(), site.line);
+        }
+    }
+    output
+}
+
+/// Asks the model for a series of steps implementing `request` and reviews it.
+///
+/// A series whose edits do not apply, or that the review rejects, is sent back with the
+/// reason. If no series passes review within the attempt limit, the last one that applies
+/// is returned with a warning.
+///
+/// # Errors
+///
+/// Returns an `AgentError` if a request to the model fails, and
+/// `AgentError::MaxIterationsReached` if no proposed series could be applied.
+pub async fn propose<M: ReviewModel>(
+    model: &mut M,
+    root: &Path,
+    request: &str,
+    impact: &Impact,
+) -> Result<Vec<RefactorStep>, AgentError> {
+    let mut messages = proposal_messages(root, request, impact);
+    let mut fallback = None;
+    for attempt in 1..=MAX_ATTEMPTS {
+        eprintln!("Proposing refactoring (attempt {attempt}/{MAX_ATTEMPTS})");
+        let reply = model.complete(messages.clone()).await?;
+        let feedback = match build_series(root, &reply) {
+            Ok(steps) => {
+                let input = ReviewInput {
+                    question: review_question(request, impact),
+                    answer: render_series(&steps),
+                    consulted_files: Vec::new(),
+                };
+                match LlmReviewer.review(&input, model).await? {
+                    Verdict::Pass => return Ok(steps),
+                    Verdict::Fail(reason) => {
+                        fallback = Some(steps);
+                        format!("The review rejected this series: {reason}")
+                    }
+                }
+            }
+            Err(reason) => reason,
+        };
+        eprintln!("{feedback}");
+        messages.push(Message {
+            role: "assistant".to_string(),
+            content: reply,
+        });
+        messages.push(Message {
+            role: "user".to_string(),
+            content: format!("{feedback}\n\nRespond with the complete corrected series, in the same JSON format."),
+        });
+    }
+
+    match fallback {
+        Some(steps) => {
+            eprintln!("warning: the proposed series did not pass review; check it carefully");
+            Ok(steps)
+        }
+        None => Err(AgentError::MaxIterationsReached),
+    }
+}
+
+/// Renders the steps as a patch series with a heading per step.
+pub fn render_series(steps: &[RefactorStep]) -> String {
+    let mut output = String::new();
+    for (index, step) in steps.iter().enumerate() {
+        let _ = write!(
+            output,
+            "\n# Step {}/{}: {}\n\n{}",
+            index + 1,
+            steps.len(),
+            step.title,
+            step.patch.render()
+        );
+    }
+    output
+}
+
+/// Extracts the words of `request` likely to name code.
+///
+/// Words in backticks are kept as written; other words are kept if they are not common
+/// refactoring vocabulary.
+fn keywords(request: &str) -> Vec<String> {
+    let mut keywords = Vec::new();
+    for (index, part) in request.split('`').enumerate() {
+        if index % 2 == 1 {
+            keywords.push(part.trim().to_string());
+            continue;
+        }
+        for word in part.split(|c: char| !(c.is_alphanumeric() || c == '_')) {
+            let lower = word.to_lowercase();
+            if word.len() >= 4 && !STOP_WORDS.contains(&lower.as_str()) {
+                keywords.push(word.to_string());
+            }
+        }
+    }
+    keywords.retain(|word| !word.is_empty());
+    keywords.dedup();
+    keywords
+}
+
+/// Builds the request asking the model to propose the refactoring.
+fn proposal_messages(root: &Path, request: &str, impact: &Impact) -> Vec<Message> {
+    let mut files = String::new();
+    let mut budget = FILE_TOKEN_BUDGET;
+    for path in impact.files(root) {
+        let Ok(content) = fs::read_to_string(root.join(&path)) else {
+            continue;
+        };
+        let tokens = estimate_tokens(&content);
+        if tokens > budget {
+            continue;
+        }
+        budget -= tokens;
+        let _ = write!(files, "### {}\n\n```\n{content}```\n\n", path.display());
+    }
+
+    vec![
+        Message {
+            role: "system".to_string(),
+            content: "You are a careful software engineer proposing a refactoring as a series of small, reviewable steps. Preserve behavior, and update every call site the change affects.".to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!(
+                "Refactoring request: {request}\n\n{}\nRelevant code:\n\n{}\n{files}Propose the refactoring as a series of steps that each leave the code working. Respond with JSON only, in this format:\n\n[{{\"title\": \"what the step does\", \"edits\": [{{\"path\": \"src/file.rs\", \"search\": \"exact existing text\", \"replace\": \"new text\"}}]}}]\n\nPaths are relative to the repository root. `search` must be copied exactly from the current file (after earlier steps) and occur exactly once; use an empty `search` to create a new file.",
+                render_impact(impact, root),
+                render_snippets(&impact.snippets)
+            ),
+        },
+    ]
+}
+
+/// Builds the question the review checks the proposed series against.
+fn review_question(request: &str, impact: &Impact) -> String {
+    let mut question = format!("Refactor the code: {request}");
+    let sites: Vec<String> = impact
+        .symbols
+        .iter()
+        .flat_map(|affected| {
+            affected.call_sites.iter().map(|site| {
+                format!(
+                    "{} at {}:{}",
+                    affected.symbol.name,
+                    site.path.display(),
+                    site.line
+                )
+            })
+        })
+        .collect();
+    if !sites.is_empty() {
+        let _ = write!(
+            question,
+            "\n\nThe answer is a patch series. It must preserve behavior and update every affected call site among: {}",
+            sites.join(", ")
+        );
+    }
+    question
+}
+
+/// Parses the model's reply and applies its steps to a draft of the files under `root`.
+///
+/// Returns a description of the problem, for the model, if the reply cannot be used.
+fn build_series(root: &Path, reply: &str) -> Result<Vec<RefactorStep>, String> {
+    let proposed: Vec<ProposedStep> = reply
+        .find('[')
+        .zip(reply.rfind(']'))
+        .and_then(|(start, end)| reply.get(start..=end))
+        .and_then(|json| serde_json::from_str(json).ok())
+        .ok_or_else(|| "The reply could not be parsed as the requested JSON.".to_string())?;
+
+    let mut draft = Draft::new(root);
+    let mut steps = Vec::new();
+    for (index, step) in proposed.into_iter().enumerate() {
+        if step.edits.iter().any(|edit| !is_relative(&edit.path)) {
+            return Err(format!(
+                "Step {} edits a path outside the repository.",
+                index + 1
+            ));
+        }
+        let patch = draft
+            .edit(&step.edits)
+            .map_err(|err| format!("Step {} does not apply: {err}", index + 1))?;
+        if !patch.is_empty() {
+            steps.push(RefactorStep {
+                title: step.title,
+                patch,
+            });
+        }
+    }
+    if steps.is_empty() {
+        return Err("The series does not change anything.".to_string());
+    }
+    Ok(steps)
+}
+
+/// Returns whether `path` stays inside the directory it is relative to.
+fn is_relative(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, std::path::Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Replies with the scripted proposals in order, and rejects the first series it
+    /// reviews.
+    struct ScriptedModel {
+        proposals: Vec<&'static str>,
+        reviews: usize,
+    }
+
+    #[async_trait]
+    impl ReviewModel for ScriptedModel {
+        async fn complete(&mut self, messages: Vec<Message>) -> Result<String, AgentError> {
+            if messages[0].content.contains("critical reviewer") {
+                self.reviews += 1;
+                return Ok(if self.reviews == 1 {
+                    "NO: fetch_all still retries inline".to_string()
+                } else {
+                    "YES".to_string()
+                });
+            }
+            Ok(self.proposals.remove(0).to_string())
+        }
+    }
+
+    #[test]
+    fn test_keywords() {
+        assert_eq!(
+            keywords("extract the retry logic into a helper used by `Client::get`"),
+            ["retry", "Client::get"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proposal_is_reviewed_and_corrected() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        fs::write(
+            root.join("net.rs"),
+            "pub fn fetch(url: &str) {\n    for _ in 0..3 { get(url); }\n}\n",
+        )
+        .expect("Failed to write file");
+        fs::write(root.join("main.rs"), "fn main() {\n    fetch(\"a\");\n}\n")
+            .expect("Failed to write file");
+
+        let impact = analyze(root, "extract the retry loop of `fetch` into a helper");
+        let fetch = impact
+            .symbols
+            .iter()
+            .find(|affected| affected.symbol.name == "fetch")
+            .expect("Expected fetch to be affected");
+        assert_eq!(fetch.call_sites.len(), 1);
+        assert_eq!(fetch.call_sites[0].path, Path::new("main.rs"));
+        assert!(render_impact(&impact, root).contains("    main.rs:2\n"));
+
+        let mut model = ScriptedModel {
+            proposals: vec![
+                r#"[{"title": "Add helper", "edits": [{"path": "net.rs", "search": "missing", "replace": "x"}]}]"#,
+                r#"[{"title": "Add helper", "edits": [{"path": "net.rs", "search": "pub fn fetch", "replace": "fn retry(f: impl Fn()) {\n    for _ in 0..3 { f(); }\n}\n\npub fn fetch"}]}]"#,
+                r#"[{"title": "Add helper", "edits": [{"path": "net.rs", "search": "pub fn fetch", "replace": "fn retry(f: impl Fn()) {\n    for _ in 0..3 { f(); }\n}\n\npub fn fetch"}]},
+                   {"title": "Use helper", "edits": [{"path": "net.rs", "search": "    for _ in 0..3 { get(url); }", "replace": "    retry(|| get(url));"}]}]"#,
+            ],
+            reviews: 0,
+        };
+        let steps = propose(&mut model, root, "extract the retry loop", &impact)
+            .await
+            .expect("Failed to propose");
+        assert_eq!(model.reviews, 2);
+        assert!(model.proposals.is_empty());
+        assert_eq!(steps.len(), 2);
+
+        for step in &steps {
+            step.patch.apply(root).expect("Failed to apply step");
+        }
+        assert_eq!(
+            fs::read_to_string(root.join("net.rs")).expect("Failed to read file"),
+            "fn retry(f: impl Fn()) {\n    for _ in 0..3 { f(); }\n}\n\npub fn fetch(url: &str) {\n    retry(|| get(url));\n}\n"
+        );
+        assert!(render_series(&steps).contains("# Step 2/2: Use helper"));
+    }
+}
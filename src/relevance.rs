@@ -0,0 +1,228 @@
+//! # File Relevance Ranking
+//!
+//! This module decides which files to read when a plan asks for more than the configured
+//! limits allow. Each candidate is scored from three signals:
+//!
+//! - its path, which often names the feature it implements
+//! - the symbols it defines, from the symbol index
+//! - a single cheap model call that rates every candidate at once from its path and symbols
+//!
+//! The best files are then read until the file limit or the token budget is reached, which
+//! keeps both the cost and the focus of the answer under control.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    agent::AgentError, github_copilot_client::Message, review::ReviewModel, symbols::SymbolIndex,
+};
+
+/// Weight of the model's rating relative to the path and symbol matches.
+const MODEL_WEIGHT: f64 = 4.0;
+
+/// Rating assumed for files the model did not rate, on its 0-10 scale.
+const DEFAULT_RATING: f64 = 5.0;
+
+/// Symbols listed per file in the rating request.
+const MAX_LISTED_SYMBOLS: usize = 15;
+
+/// Words too common in questions to say anything about a file.
+const STOP_WORDS: &[&str] = &[
+    "and", "are", "can", "does", "for", "from", "how", "the", "this", "what", "when", "where",
+    "which", "who", "why", "with",
+];
+
+/// A file a plan wants to read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// The file, as the plan names it.
+    pub path: PathBuf,
+    /// Estimated tokens of the file's content.
+    pub tokens: usize,
+    /// Names of the symbols the file defines.
+    pub symbols: Vec<String>,
+}
+
+impl Candidate {
+    /// Describes the file at `path` for ranking; unreadable files have no symbols.
+    pub fn from_path(path: &Path) -> Self {
+        let tokens = fs::metadata(path).map_or(0, |meta| meta.len() as usize / 4);
+        let index = SymbolIndex::from_files(Path::new(""), &[path.to_path_buf()]);
+        let mut symbols: Vec<String> = Vec::new();
+        for symbol in index.symbols() {
+            if !symbols.contains(&symbol.name) {
+                symbols.push(symbol.name.clone());
+            }
+        }
+        Self {
+            path: path.to_path_buf(),
+            tokens,
+            symbols,
+        }
+    }
+}
+
+/// Scores `candidates` for `question`, asking the model to rate them in one request.
+///
+/// Returns one score per candidate, in the same order; higher is more relevant. If the
+/// model's reply cannot be understood, the path and symbol matches decide alone.
+///
+/// # Errors
+///
+/// Returns an `AgentError` if the request to the model fails.
+pub async fn score<M: ReviewModel>(
+    model: &mut M,
+    question: &str,
+    candidates: &[Candidate],
+) -> Result<Vec<f64>, AgentError> {
+    let listing: String = candidates
+        .iter()
+        .map(|candidate| {
+            let symbols = candidate
+                .symbols
+                .iter()
+                .take(MAX_LISTED_SYMBOLS)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("- {} (defines: {symbols})\n", candidate.path.display())
+        })
+        .collect();
+    let messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: "You decide which files are worth reading to answer a question about a code repository.".to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!(
+                "Question: {question}\n\nCandidate files:\n\n{listing}\nRate how likely each file is needed to answer the question, from 0 (irrelevant) to 10 (essential). Respond with JSON only, mapping each path to its rating, like {{\"src/main.rs\": 7}}."
+            ),
+        },
+    ];
+    let reply = model.complete(messages).await?;
+    let ratings: HashMap<String, f64> = reply
+        .find('{')
+        .zip(reply.rfind('}'))
+        .and_then(|(start, end)| reply.get(start..=end))
+        .and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default();
+
+    let keywords = keywords(question);
+    Ok(candidates
+        .iter()
+        .map(|candidate| {
+            let rating = ratings
+                .get(candidate.path.to_string_lossy().as_ref())
+                .copied()
+                .unwrap_or(DEFAULT_RATING)
+                .clamp(0.0, 10.0);
+            rating / 10.0 * MODEL_WEIGHT + match_score(candidate, &keywords)
+        })
+        .collect())
+}
+
+/// Picks the candidates to read: the best scored first, up to `max_files` files and
+/// `budget` estimated tokens.
+///
+/// The best candidate is always picked, even if it alone exceeds the budget. Returns
+/// indices into `candidates`, best first.
+pub fn select(
+    candidates: &[Candidate],
+    scores: &[f64],
+    max_files: usize,
+    budget: usize,
+) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..candidates.len()).collect();
+    // Stable sort keeps plan order among equal scores
+    order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+
+    let mut selected = Vec::new();
+    let mut remaining = budget;
+    for index in order {
+        if selected.len() == max_files {
+            break;
+        }
+        let tokens = candidates[index].tokens;
+        if tokens > remaining && !selected.is_empty() {
+            continue;
+        }
+        remaining = remaining.saturating_sub(tokens);
+        selected.push(index);
+    }
+    selected
+}
+
+/// Scores how well the path and symbols of `candidate` match `keywords`.
+fn match_score(candidate: &Candidate, keywords: &[String]) -> f64 {
+    let path = candidate.path.to_string_lossy().to_lowercase();
+    let path_hits = keywords.iter().filter(|word| path.contains(*word)).count();
+    let symbol_hits = keywords
+        .iter()
+        .filter(|word| {
+            candidate
+                .symbols
+                .iter()
+                .any(|symbol| symbol.to_lowercase().contains(*word))
+        })
+        .count();
+    path_hits as f64 + 0.5 * symbol_hits.min(3) as f64
+}
+
+/// Extracts the lowercase words of `question` that may name code.
+pub(crate) fn keywords(question: &str) -> Vec<String> {
+    let mut keywords: Vec<String> = Vec::new();
+    for word in question.split(|c: char| !c.is_alphanumeric() && c != '_') {
+        let word = word.to_lowercase();
+        if word.len() >= 3 && !STOP_WORDS.contains(&word.as_str()) && !keywords.contains(&word) {
+            keywords.push(word);
+        }
+    }
+    keywords
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+
+    /// Rates the session store as essential and leaves the cache unrated.
+    struct ScriptedModel;
+
+    #[async_trait]
+    impl ReviewModel for ScriptedModel {
+        async fn complete(&mut self, _messages: Vec<Message>) -> Result<String, AgentError> {
+            Ok(r#"Ratings: {"src/session.rs": 9, "src/db.rs": 2, "README.md": 0}"#.to_string())
+        }
+    }
+
+    fn candidate(path: &str, tokens: usize, symbols: &[&str]) -> Candidate {
+        Candidate {
+            path: PathBuf::from(path),
+            tokens,
+            symbols: symbols.iter().map(|symbol| symbol.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reads_best_files_within_budget() {
+        let candidates = [
+            candidate("README.md", 500, &[]),
+            candidate("src/db.rs", 3000, &["Database", "clean"]),
+            candidate("src/session.rs", 4000, &["SessionStore", "save_session"]),
+            candidate("src/cache.rs", 100, &["Cache"]),
+        ];
+        let scores = score(&mut ScriptedModel, "How are sessions saved?", &candidates)
+            .await
+            .expect("Failed to score");
+        assert!(scores[2] > scores[3] && scores[3] > scores[1] && scores[1] > scores[0]);
+
+        assert_eq!(select(&candidates, &scores, 2, 4500), [2, 3]);
+        assert_eq!(select(&candidates, &scores, 4, 5000), [2, 3, 0]);
+        assert_eq!(select(&candidates, &scores, 4, 10), [2]);
+    }
+}
@@ -0,0 +1,605 @@
+//! # Query Reports
+//!
+//! This module describes what went into answering a query, for display after the answer
+//! and for inclusion in JSON output. The context report breaks the answer prompt down into
+//! its parts (templates, history, each command result) so users can see why a query cost
+//! what it did and what to exclude next time.
+//!
+//! When the review loop gives up, the failure report lists what the reviewer kept flagging,
+//! the commands that were run, and the files that could not be read, so users can rephrase
+//! the question around the gaps instead of retrying it as is.
+//!
+//! The latency report splits the wall-clock time of the query by step of the agent loop
+//! (intent, plan, commands, answer, review) and lists how long each command took. Every
+//! step also records how much of its time was spent waiting on the provider, so slowness
+//! can be told apart from local traversal.
+//!
+//! Answers can also be exported as a self-contained HTML page for sharing with people who
+//! will not read terminal output: the answer, a table of the consulted files linking to
+//! excerpts of their code, and the context breakdown.
+
+use std::{
+    fmt::{self, Write},
+    fs,
+    path::PathBuf,
+};
+
+use serde::Serialize;
+
+use crate::{
+    session::{FileProvenance, ToolCall},
+    tokens::estimate_tokens,
+};
+
+/// Lines of each consulted file included in HTML reports when no range was read.
+const HTML_EXCERPT_LINES: usize = 200;
+
+/// One part of a prompt and its estimated size.
+#[derive(Debug, Clone, Serialize)]
+pub struct ContextSection {
+    /// What this part of the prompt is, e.g. `template` or `show_file src/main.rs`.
+    pub label: String,
+    /// Estimated number of tokens.
+    pub tokens: usize,
+}
+
+/// Breakdown of the prompt used to generate the answer.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContextReport {
+    /// The parts of the prompt, in prompt order.
+    pub sections: Vec<ContextSection>,
+    /// Estimated size of the whole prompt.
+    pub total_tokens: usize,
+}
+
+impl ContextReport {
+    /// Builds a report for a prompt whose full text is `prompt`.
+    ///
+    /// `parts` lists the variable parts of the prompt; whatever remains of the total is
+    /// attributed to the fixed `template` text.
+    pub fn new(prompt: &str, parts: Vec<(String, &str)>) -> Self {
+        let total_tokens = estimate_tokens(prompt);
+        let mut sections: Vec<ContextSection> = parts
+            .into_iter()
+            .map(|(label, text)| ContextSection {
+                label,
+                tokens: estimate_tokens(text),
+            })
+            .collect();
+        let variable: usize = sections.iter().map(|section| section.tokens).sum();
+        sections.insert(
+            0,
+            ContextSection {
+                label: "template".to_string(),
+                tokens: total_tokens.saturating_sub(variable),
+            },
+        );
+        Self {
+            sections,
+            total_tokens,
+        }
+    }
+}
+
+impl fmt::Display for ContextReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Context size of the answer prompt: ~{} tokens",
+            self.total_tokens
+        )?;
+        let width = self
+            .sections
+            .iter()
+            .map(|section| section.label.chars().count())
+            .max()
+            .unwrap_or(0);
+        for section in &self.sections {
+            writeln!(f, "  {:<width$}  {:>7}", section.label, section.tokens)?;
+        }
+        Ok(())
+    }
+}
+
+/// Feedback the reviewer gave, and the iterations it gave it in.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReviewFlag {
+    /// Why the answer was rejected.
+    pub reason: String,
+    /// The iterations rejected for this reason, starting at 1.
+    pub iterations: Vec<usize>,
+}
+
+/// A file a command could not read.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnreadableFile {
+    /// The path as resolved by the command.
+    pub path: PathBuf,
+    /// Why it could not be read.
+    pub error: String,
+}
+
+/// Why no answer passed the review, appended to the best answer when the loop gives up.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FailureReport {
+    /// Why the loop stopped, completing "This answer was provided after ...".
+    pub reason: String,
+    /// The reviewer's feedback, most frequent first.
+    pub review_flags: Vec<ReviewFlag>,
+    /// The commands that were run, in the order they first ran.
+    pub commands: Vec<String>,
+    /// The files that could not be read.
+    pub unreadable_files: Vec<UnreadableFile>,
+}
+
+impl FailureReport {
+    /// Builds a report from the review feedback of each iteration, `None` for iterations
+    /// that passed or were not reviewed.
+    pub fn new(
+        reason: String,
+        feedback: &[Option<String>],
+        commands: Vec<String>,
+        unreadable_files: Vec<UnreadableFile>,
+    ) -> Self {
+        let mut review_flags: Vec<ReviewFlag> = Vec::new();
+        for (index, reason) in feedback.iter().enumerate() {
+            let Some(reason) = reason.as_deref().map(str::trim) else {
+                continue;
+            };
+            match review_flags.iter_mut().find(|flag| flag.reason == reason) {
+                Some(flag) => flag.iterations.push(index + 1),
+                None => review_flags.push(ReviewFlag {
+                    reason: reason.to_string(),
+                    iterations: vec![index + 1],
+                }),
+            }
+        }
+        // Stable, so equally frequent feedback stays in the order it was given
+        review_flags.sort_by_key(|flag| std::cmp::Reverse(flag.iterations.len()));
+
+        let mut unique: Vec<String> = Vec::new();
+        for command in commands {
+            if !unique.contains(&command) {
+                unique.push(command);
+            }
+        }
+        Self {
+            reason,
+            review_flags,
+            commands: unique,
+            unreadable_files,
+        }
+    }
+}
+
+impl fmt::Display for FailureReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(Note: This answer was provided after {}.)", self.reason)?;
+        if !self.review_flags.is_empty() {
+            write!(f, "\n\nThe reviewer kept flagging:")?;
+            for flag in &self.review_flags {
+                let iterations: Vec<String> =
+                    flag.iterations.iter().map(ToString::to_string).collect();
+                let label = if flag.iterations.len() == 1 {
+                    "iteration"
+                } else {
+                    "iterations"
+                };
+                write!(f, "\n- {} ({label} {})", flag.reason, iterations.join(", "))?;
+            }
+        }
+        if !self.commands.is_empty() {
+            write!(f, "\n\nCommands run:")?;
+            for command in &self.commands {
+                write!(f, "\n- `{command}`")?;
+            }
+        }
+        if !self.unreadable_files.is_empty() {
+            write!(f, "\n\nFiles that could not be read:")?;
+            for file in &self.unreadable_files {
+                write!(f, "\n- {}: {}", file.path.display(), file.error)?;
+            }
+        }
+        if !self.review_flags.is_empty() || !self.unreadable_files.is_empty() {
+            write!(
+                f,
+                "\n\nRephrasing the question to address these points, or naming the relevant files with `--with-file`, may help."
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A step of the agent loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// Classifying the question and planning the commands.
+    Plan,
+    /// Ranking the planned file reads by relevance.
+    Rank,
+    /// Running the planned commands.
+    Commands,
+    /// Running the commands the planner asked for after seeing the results.
+    FollowUp,
+    /// Generating the answer.
+    Answer,
+    /// Reviewing the answer.
+    Review,
+    /// Choosing the best answer after the review loop gave up.
+    Select,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Step::Plan => "plan",
+            Step::Rank => "rank",
+            Step::Commands => "commands",
+            Step::FollowUp => "follow-up",
+            Step::Answer => "answer",
+            Step::Review => "review",
+            Step::Select => "select",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Wall-clock time of one step.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StepTiming {
+    /// The step.
+    pub step: Step,
+    /// The iteration of the review loop the step belongs to, starting at 1.
+    pub iteration: usize,
+    /// Time the step took, in milliseconds.
+    pub duration_ms: u64,
+    /// Part of that time spent waiting on the provider, in milliseconds.
+    pub provider_ms: u64,
+}
+
+/// Breakdown of the wall-clock time of a query.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LatencyReport {
+    /// The steps, in the order they ran.
+    pub steps: Vec<StepTiming>,
+    /// The commands, in the order they ran. Commands of a stage run in parallel, so their
+    /// durations may add up to more than their step's.
+    pub commands: Vec<ToolCall>,
+    /// Time of all steps, in milliseconds.
+    pub total_ms: u64,
+    /// Time spent waiting on the provider, in milliseconds.
+    pub provider_ms: u64,
+    /// Time spent locally, reading files and running commands, in milliseconds.
+    pub local_ms: u64,
+}
+
+impl LatencyReport {
+    /// Builds a report from the timings of the steps and the commands that ran.
+    pub fn new(steps: Vec<StepTiming>, commands: Vec<ToolCall>) -> Self {
+        let total_ms = steps.iter().map(|step| step.duration_ms).sum();
+        let provider_ms = steps.iter().map(|step| step.provider_ms).sum();
+        Self {
+            steps,
+            commands,
+            total_ms,
+            provider_ms,
+            local_ms: total_ms.saturating_sub(provider_ms),
+        }
+    }
+}
+
+/// Formats `ms` milliseconds as seconds.
+fn seconds(ms: u64) -> String {
+    format!("{:.1} s", ms as f64 / 1000.0)
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Latency: {} ({} waiting on the provider, {} local)",
+            seconds(self.total_ms),
+            seconds(self.provider_ms),
+            seconds(self.local_ms)
+        )?;
+        for step in &self.steps {
+            let label = format!("{}. {}", step.iteration, step.step);
+            write!(f, "  {label:<14}  {:>8}", seconds(step.duration_ms))?;
+            if step.provider_ms > 0 {
+                write!(f, "  (provider {})", seconds(step.provider_ms))?;
+            }
+            writeln!(f)?;
+        }
+        let width = self
+            .commands
+            .iter()
+            .map(|call| call.command.chars().count())
+            .max()
+            .unwrap_or(0);
+        for call in &self.commands {
+            writeln!(
+                f,
+                "    {:<width$}  {:>8}",
+                call.command,
+                seconds(call.duration_ms)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Content of an HTML report.
+pub struct HtmlReport<'a> {
+    /// The question that was asked.
+    pub question: &'a str,
+    /// The final answer.
+    pub answer: &'a str,
+    /// The session the answer belongs to.
+    pub session_id: &'a str,
+    /// Files consulted for the answer.
+    pub files: &'a [FileProvenance],
+    /// Size breakdown of the answer prompt, if known.
+    pub context: Option<&'a ContextReport>,
+}
+
+impl HtmlReport<'_> {
+    /// Renders the report as a standalone HTML document with inline styles.
+    ///
+    /// File excerpts are read from disk when rendering; files that can no longer be read
+    /// are listed without an excerpt.
+    pub fn render(&self) -> String {
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{HTML_STYLE}</style>\n</head>\n<body>\n",
+            escape_html(self.question)
+        );
+        let _ = writeln!(html, "<h1>{}</h1>", escape_html(self.question));
+        let _ = writeln!(
+            html,
+            "<p class=\"meta\">Session {}</p>",
+            escape_html(self.session_id)
+        );
+        let _ = writeln!(
+            html,
+            "<h2>Answer</h2>\n<pre class=\"answer\">{}</pre>",
+            escape_html(self.answer)
+        );
+
+        if !self.files.is_empty() {
+            html.push_str("<h2>Consulted files</h2>\n<table>\n<tr><th>File</th><th>Lines</th><th>SHA-256</th></tr>\n");
+            for (index, file) in self.files.iter().enumerate() {
+                let lines = file
+                    .range
+                    .map_or_else(|| "all".to_string(), |r| format!("{}-{}", r.start, r.end));
+                let _ = writeln!(
+                    html,
+                    "<tr><td><a href=\"#file-{index}\">{}</a></td><td>{lines}</td><td><code>{}</code></td></tr>",
+                    escape_html(&file.path.display().to_string()),
+                    &file.sha256[..file.sha256.len().min(12)]
+                );
+            }
+            html.push_str("</table>\n");
+
+            for (index, file) in self.files.iter().enumerate() {
+                let _ = writeln!(
+                    html,
+                    "<h3 id=\"file-{index}\">{}</h3>",
+                    escape_html(&file.path.display().to_string())
+                );
+                match excerpt(file) {
+                    Some((start, text)) => {
+                        html.push_str("<pre class=\"code\">");
+                        for (offset, line) in text.lines().enumerate() {
+                            let _ = writeln!(
+                                html,
+                                "<span class=\"ln\">{:>5}</span> {}",
+                                start + offset,
+                                escape_html(line)
+                            );
+                        }
+                        html.push_str("</pre>\n");
+                    }
+                    None => html.push_str("<p class=\"meta\">File is no longer readable.</p>\n"),
+                }
+            }
+        }
+
+        if let Some(context) = self.context {
+            let _ = writeln!(
+                html,
+                "<h2>Context</h2>\n<p class=\"meta\">Answer prompt: ~{} tokens</p>\n<table>\n<tr><th>Section</th><th>Tokens</th></tr>",
+                context.total_tokens
+            );
+            for section in &context.sections {
+                let _ = writeln!(
+                    html,
+                    "<tr><td>{}</td><td>{}</td></tr>",
+                    escape_html(&section.label),
+                    section.tokens
+                );
+            }
+            html.push_str("</table>\n");
+        }
+
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+/// Inline stylesheet of HTML reports.
+const HTML_STYLE: &str = "body{font-family:sans-serif;max-width:960px;margin:2em auto;padding:0 1em;color:#222}pre{background:#f6f8fa;padding:1em;overflow-x:auto}pre.answer{white-space:pre-wrap}table{border-collapse:collapse}td,th{border:1px solid #ddd;padding:.3em .6em;text-align:left}.meta{color:#666}.ln{color:#999}";
+
+/// Reads the lines of `file` to show in a report, returning the first line number and text.
+fn excerpt(file: &FileProvenance) -> Option<(usize, String)> {
+    let content = fs::read_to_string(&file.path).ok()?;
+    let (start, end) = match file.range {
+        Some(range) => (range.start, range.end),
+        None => (1, HTML_EXCERPT_LINES),
+    };
+    let text = content
+        .lines()
+        .skip(start.saturating_sub(1))
+        .take(end.saturating_sub(start) + 1)
+        .collect::<Vec<_>>()
+        .join("\n");
+    Some((start, text))
+}
+
+/// Escapes the characters with special meaning in HTML.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::session::{sha256_hex, LineRange};
+
+    #[test]
+    fn test_template_gets_remainder() {
+        let file = "x".repeat(400);
+        let prompt = format!("{}{file}", "t".repeat(40));
+        let report = ContextReport::new(&prompt, vec![("show_file a.rs".to_string(), &file)]);
+        assert_eq!(report.total_tokens, 110);
+        assert_eq!(report.sections[0].label, "template");
+        assert_eq!(report.sections[0].tokens, 10);
+        assert_eq!(report.sections[1].tokens, 100);
+    }
+
+    #[test]
+    fn test_display_lists_sections() {
+        let report = ContextReport::new("question", vec![("question".to_string(), "question")]);
+        let rendered = report.to_string();
+        assert!(rendered.starts_with("Context size of the answer prompt: ~2 tokens"));
+        assert!(rendered.contains("question"));
+    }
+
+    #[test]
+    fn test_failure_report() {
+        let report = FailureReport::new(
+            "reaching the maximum number of iteration attempts".to_string(),
+            &[
+                Some("The answer does not name the caller".to_string()),
+                Some("No file is cited".to_string()),
+                Some("No file is cited".to_string()),
+            ],
+            vec![
+                "tree src".to_string(),
+                "show_file src/a.rs".to_string(),
+                "tree src".to_string(),
+            ],
+            vec![UnreadableFile {
+                path: PathBuf::from("src/b.rs"),
+                error: "Path does not exist: src/b.rs".to_string(),
+            }],
+        );
+        assert_eq!(report.review_flags[0].iterations, [2, 3]);
+        assert_eq!(report.commands, ["tree src", "show_file src/a.rs"]);
+        let rendered = report.to_string();
+        assert!(rendered.starts_with(
+            "(Note: This answer was provided after reaching the maximum number of iteration attempts.)"
+        ));
+        assert!(rendered.contains("- No file is cited (iterations 2, 3)\n- The answer does not name the caller (iteration 1)"));
+        assert!(rendered.contains("- `show_file src/a.rs`"));
+        assert!(rendered.contains("- src/b.rs: Path does not exist"));
+
+        let quiet = FailureReport::new("stalling".to_string(), &[None], Vec::new(), Vec::new());
+        assert_eq!(
+            quiet.to_string(),
+            "(Note: This answer was provided after stalling.)"
+        );
+    }
+
+    #[test]
+    fn test_latency_report() {
+        let step = |step, iteration, duration_ms, provider_ms| StepTiming {
+            step,
+            iteration,
+            duration_ms,
+            provider_ms,
+        };
+        let report = LatencyReport::new(
+            vec![
+                step(Step::Plan, 1, 800, 790),
+                step(Step::Commands, 1, 1200, 0),
+                step(Step::Answer, 1, 5000, 4900),
+            ],
+            vec![ToolCall {
+                command: "show_file src/main.rs".to_string(),
+                duration_ms: 1100,
+                bytes: 2048,
+                truncated: false,
+            }],
+        );
+        assert_eq!(report.total_ms, 7000);
+        assert_eq!(report.provider_ms, 5690);
+        assert_eq!(report.local_ms, 1310);
+        assert_eq!(
+            report.to_string(),
+            "Latency: 7.0 s (5.7 s waiting on the provider, 1.3 s local)\n\
+             \x20 1. plan            0.8 s  (provider 0.8 s)\n\
+             \x20 1. commands        1.2 s\n\
+             \x20 1. answer          5.0 s  (provider 4.9 s)\n\
+             \x20   show_file src/main.rs     1.1 s\n"
+        );
+    }
+
+    #[test]
+    fn test_html_report() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("lib.rs");
+        fs::write(&path, "mod a;\nmod b;\nfn f() -> Vec<u8> {}\n").expect("Failed to write file");
+        let files = vec![FileProvenance {
+            path: path.clone(),
+            range: Some(LineRange { start: 2, end: 3 }),
+            sha256: sha256_hex(b"content"),
+        }];
+        let context = ContextReport::new("question", vec![]);
+
+        let html = HtmlReport {
+            question: "What is <f>?",
+            answer: "A & B",
+            session_id: "20240101-1",
+            files: &files,
+            context: Some(&context),
+        }
+        .render();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<h1>What is &lt;f&gt;?</h1>"));
+        assert!(html.contains("A &amp; B"));
+        assert!(html.contains("href=\"#file-0\""));
+        assert!(html.contains("<span class=\"ln\">    3</span> fn f() -&gt; Vec&lt;u8&gt; {}"));
+        assert!(!html.contains("mod a;"));
+        assert!(html.contains("<td>template</td>"));
+
+        fs::remove_file(&path).expect("Failed to remove file");
+        let html = HtmlReport {
+            question: "q",
+            answer: "a",
+            session_id: "s",
+            files: &[FileProvenance {
+                path,
+                range: None,
+                sha256: String::new(),
+            }],
+            context: None,
+        }
+        .render();
+        assert!(html.contains("File is no longer readable."));
+    }
+}
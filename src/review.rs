@@ -0,0 +1,519 @@
+//! # Review Strategies
+//!
+//! This module decides whether a generated answer is good enough to return. Reviewers
+//! implement the [`Reviewer`] trait; three strategies are provided:
+//!
+//! - [`LlmReviewer`]: asks the model to critique the answer (the default)
+//! - [`RuleReviewer`]: cheap local checks such as "cites at least one file"
+//! - [`CompositeReviewer`]: runs several reviewers in order, failing on the first rejection
+//!
+//! The strategy is selected with the `[review]` section of the configuration file.
+//!
+//! Before any strategy runs, [`precheck`] rejects answers that are obviously unusable (empty,
+//! a bare apology, or citing files that were never read) so no review call is spent on them.
+
+use std::sync::{Arc, LazyLock};
+
+use async_trait::async_trait;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    error::AgentError, github_copilot_client::Message, provenance::FileProvenance, unknown,
+};
+
+/// The answer under review together with the context it was produced from.
+#[derive(Debug, Clone)]
+pub struct ReviewInput {
+    /// The user's question.
+    pub question: String,
+    /// The generated answer.
+    pub answer: String,
+    /// Files that were read while producing the answer.
+    pub consulted_files: Vec<FileProvenance>,
+}
+
+/// The outcome of a review.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The answer adequately addresses the question.
+    Pass,
+    /// The answer is inadequate, for the given reason.
+    Fail(String),
+}
+
+impl Verdict {
+    /// Returns `true` if the answer passed the review.
+    pub fn passed(&self) -> bool {
+        matches!(self, Verdict::Pass)
+    }
+}
+
+/// Access to the language model for reviewers that need it.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait ReviewModel: Send {
+    /// Sends `messages` to the model and returns the content of its reply.
+    async fn complete(&mut self, messages: Vec<Message>) -> Result<String, AgentError>;
+}
+
+/// A strategy for judging generated answers.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait Reviewer: Send + Sync {
+    /// Reviews `input`, using `model` if the strategy needs the language model.
+    async fn review(
+        &self,
+        input: &ReviewInput,
+        model: &mut dyn ReviewModel,
+    ) -> Result<Verdict, AgentError>;
+
+    /// Picks the best of several answers to the same question, none of which passed review.
+    ///
+    /// Returns an index into `candidates`. The default implementation asks the model to
+    /// choose and falls back to the most recent answer if its reply cannot be understood.
+    async fn select_best(
+        &self,
+        candidates: &[ReviewInput],
+        model: &mut dyn ReviewModel,
+    ) -> Result<usize, AgentError> {
+        let Some(first) = candidates.first() else {
+            return Err(AgentError::NoAnswerToReview);
+        };
+        if candidates.len() == 1 {
+            return Ok(0);
+        }
+
+        let mut listing = String::new();
+        for (i, candidate) in candidates.iter().enumerate() {
+            listing.push_str(&format!("### Answer {}\n\n{}\n\n", i + 1, candidate.answer));
+        }
+        let messages = vec![
+            Message {
+                role: "system".to_string(),
+                content: "You are a critical reviewer. Choose the answer that best addresses the question.".to_string(),
+            },
+            Message {
+                role: "user".to_string(),
+                content: format!(
+                    "Question: {}\n\n{listing}Which answer addresses the question best? Respond with only its number.",
+                    first.question
+                ),
+            },
+        ];
+
+        let reply = model.complete(messages).await?;
+        Ok(parse_choice(&reply, candidates.len()).unwrap_or(candidates.len() - 1))
+    }
+}
+
+/// Asks the language model whether the answer adequately addresses the question.
+pub struct LlmReviewer;
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Reviewer for LlmReviewer {
+    async fn review(
+        &self,
+        input: &ReviewInput,
+        model: &mut dyn ReviewModel,
+    ) -> Result<Verdict, AgentError> {
+        let messages = vec![
+            Message {
+                role: "system".to_string(),
+                content: "You are a critical reviewer. Evaluate if the answer adequately addresses the question.".to_string(),
+            },
+            Message {
+                role: "user".to_string(),
+                content: format!(
+                    "Question: {}\n\nFiles read: {}\n\nAnswer: {}\n\nDoes this answer adequately address the question? An answer that guesses, or asserts details the files read could not have shown, is not adequate: it should instead open with 'Cannot determine from the repository.' and name what is missing and what to check. Such an answer is adequate when the files read cannot answer the question. Only respond with 'YES' if the answer is adequate, or 'NO: <reason>' if not.",
+                    input.question,
+                    files_read(&input.consulted_files),
+                    input.answer
+                ),
+            },
+        ];
+
+        let review = model.complete(messages).await?;
+        if review.to_uppercase().starts_with("YES") {
+            Ok(Verdict::Pass)
+        } else {
+            let reason = review
+                .trim_start_matches(|c: char| c.is_ascii_alphabetic() || c == ':')
+                .trim();
+            Ok(Verdict::Fail(reason.to_string()))
+        }
+    }
+}
+
+/// Settings for [`RuleReviewer`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RuleConfig {
+    /// Minimum number of consulted files the answer must mention.
+    pub min_cited_files: usize,
+    /// Phrases that make an answer fail, matched case-insensitively.
+    pub forbidden_phrases: Vec<String>,
+}
+
+impl Default for RuleConfig {
+    fn default() -> Self {
+        Self {
+            min_cited_files: 1,
+            forbidden_phrases: vec!["I don't have access".to_string()],
+        }
+    }
+}
+
+/// Judges answers with deterministic local rules, without calling the model.
+pub struct RuleReviewer {
+    config: RuleConfig,
+}
+
+impl RuleReviewer {
+    /// Creates a rule-based reviewer with the given settings.
+    pub fn new(config: RuleConfig) -> Self {
+        Self { config }
+    }
+
+    /// Applies the rules to `input`.
+    pub fn check(&self, input: &ReviewInput) -> Verdict {
+        let answer = input.answer.to_lowercase();
+        for phrase in &self.config.forbidden_phrases {
+            if answer.contains(&phrase.to_lowercase()) {
+                return Verdict::Fail(format!("answer contains forbidden phrase \"{phrase}\""));
+            }
+        }
+
+        // Stating that the files read do not answer the question needs no citation
+        let cited = input
+            .consulted_files
+            .iter()
+            .filter(|file| is_cited(&input.answer, &file.path.to_string_lossy()))
+            .count();
+        if cited < self.config.min_cited_files && unknown::parse(&input.answer).is_none() {
+            return Verdict::Fail(format!(
+                "answer cites {cited} of the consulted files, at least {} required",
+                self.config.min_cited_files
+            ));
+        }
+
+        Verdict::Pass
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Reviewer for RuleReviewer {
+    async fn review(
+        &self,
+        input: &ReviewInput,
+        _model: &mut dyn ReviewModel,
+    ) -> Result<Verdict, AgentError> {
+        Ok(self.check(input))
+    }
+
+    /// Prefers the answer citing the most consulted files, the latest one on ties.
+    async fn select_best(
+        &self,
+        candidates: &[ReviewInput],
+        _model: &mut dyn ReviewModel,
+    ) -> Result<usize, AgentError> {
+        candidates
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, candidate)| {
+                candidate
+                    .consulted_files
+                    .iter()
+                    .filter(|file| is_cited(&candidate.answer, &file.path.to_string_lossy()))
+                    .count()
+            })
+            .map(|(i, _)| i)
+            .ok_or(AgentError::NoAnswerToReview)
+    }
+}
+
+/// Runs several reviewers in order; the answer passes only if all of them pass.
+pub struct CompositeReviewer {
+    reviewers: Vec<Arc<dyn Reviewer>>,
+}
+
+impl CompositeReviewer {
+    /// Creates a composite of `reviewers`, consulted in the given order.
+    pub fn new(reviewers: Vec<Arc<dyn Reviewer>>) -> Self {
+        Self { reviewers }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Reviewer for CompositeReviewer {
+    async fn review(
+        &self,
+        input: &ReviewInput,
+        model: &mut dyn ReviewModel,
+    ) -> Result<Verdict, AgentError> {
+        for reviewer in &self.reviewers {
+            let verdict = reviewer.review(input, model).await?;
+            if !verdict.passed() {
+                return Ok(verdict);
+            }
+        }
+        Ok(Verdict::Pass)
+    }
+}
+
+/// Which review strategy to use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewStrategy {
+    /// Ask the language model to critique the answer.
+    #[default]
+    Llm,
+    /// Only apply local rules.
+    Rules,
+    /// Apply local rules first, then ask the language model.
+    Composite,
+}
+
+/// The `[review]` section of the configuration file.
+///
+/// ```toml
+/// [review]
+/// strategy = "composite"
+///
+/// [review.rules]
+/// min_cited_files = 1
+/// forbidden_phrases = ["I don't have access", "as an AI"]
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ReviewConfig {
+    /// The strategy to use.
+    pub strategy: ReviewStrategy,
+    /// Settings for the rule-based checks.
+    pub rules: RuleConfig,
+}
+
+impl ReviewConfig {
+    /// Builds the reviewer described by this configuration.
+    pub fn build(&self) -> Arc<dyn Reviewer> {
+        match self.strategy {
+            ReviewStrategy::Llm => Arc::new(LlmReviewer),
+            ReviewStrategy::Rules => Arc::new(RuleReviewer::new(self.rules.clone())),
+            ReviewStrategy::Composite => Arc::new(CompositeReviewer::new(vec![
+                Arc::new(RuleReviewer::new(self.rules.clone())),
+                Arc::new(LlmReviewer),
+            ])),
+        }
+    }
+}
+
+/// Matches path-like tokens with at least one directory and an extension, e.g. `src/main.rs`.
+static FILE_REFERENCE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:[A-Za-z0-9_.-]+/)+[A-Za-z0-9_-]+\.[A-Za-z0-9]+").expect("valid regex")
+});
+
+/// Openings that mark an answer as an apology rather than an explanation.
+const APOLOGY_OPENINGS: &[&str] = &[
+    "sorry",
+    "i'm sorry",
+    "i am sorry",
+    "i apologize",
+    "unfortunately",
+];
+
+/// Answers shorter than this that open with an apology are rejected by [`precheck`].
+const APOLOGY_MAX_LEN: usize = 300;
+
+/// Runs cheap local checks that reject obviously unusable answers.
+///
+/// Returns a failing verdict if the answer is empty, is only an apology, references files
+/// that were never read, or says the question cannot be answered without naming what is
+/// missing and what to check; returns `None` if the answer should go on to review.
+pub fn precheck(input: &ReviewInput) -> Option<Verdict> {
+    let answer = input.answer.trim();
+    if answer.is_empty() {
+        return Some(Verdict::Fail("answer is empty".to_string()));
+    }
+
+    // Files suggested for checking were not read, so the remaining checks do not apply
+    if let Some(unknown) = unknown::parse(answer) {
+        return (!unknown.is_complete()).then(|| {
+            Verdict::Fail(
+                "answer says the question cannot be determined without both a 'Missing:' and a 'Suggest checking:' line".to_string(),
+            )
+        });
+    }
+
+    let lowered = answer.to_lowercase();
+    if answer.len() < APOLOGY_MAX_LEN
+        && APOLOGY_OPENINGS
+            .iter()
+            .any(|opening| lowered.starts_with(opening))
+    {
+        return Some(Verdict::Fail(
+            "answer is an apology instead of an explanation; if the question cannot be answered, use the 'Cannot determine from the repository.' form".to_string(),
+        ));
+    }
+
+    let mut unread: Vec<&str> = FILE_REFERENCE
+        .find_iter(answer)
+        .map(|m| m.as_str())
+        .filter(|reference| !was_read(reference, &input.consulted_files))
+        .collect();
+    unread.dedup();
+    if !unread.is_empty() {
+        return Some(Verdict::Fail(format!(
+            "answer references files that were never read: {}",
+            unread.join(", ")
+        )));
+    }
+
+    None
+}
+
+/// Returns `true` if `reference` names one of the consulted files.
+fn was_read(reference: &str, consulted_files: &[FileProvenance]) -> bool {
+    let reference = reference.trim_start_matches("./");
+    consulted_files.iter().any(|file| {
+        let path = file.path.to_string_lossy();
+        let path = path.trim_start_matches("./");
+        path == reference
+            || path.ends_with(&format!("/{reference}"))
+            || reference.ends_with(&format!("/{path}"))
+    })
+}
+
+/// Extracts a 1-based choice from a model reply and converts it to an index below `count`.
+fn parse_choice(reply: &str, count: usize) -> Option<usize> {
+    let digits: String = reply
+        .chars()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(char::is_ascii_digit)
+        .collect();
+    let choice: usize = digits.parse().ok()?;
+    (1..=count).contains(&choice).then(|| choice - 1)
+}
+
+/// Lists the paths of `files` for a review prompt.
+fn files_read(files: &[FileProvenance]) -> String {
+    if files.is_empty() {
+        return "(none)".to_string();
+    }
+    let paths: Vec<String> = files
+        .iter()
+        .map(|file| file.path.display().to_string())
+        .collect();
+    paths.join(", ")
+}
+
+/// Returns `true` if `answer` mentions `path` or, failing that, its file name.
+fn is_cited(answer: &str, path: &str) -> bool {
+    let path = path.trim_start_matches("./");
+    if answer.contains(path) {
+        return true;
+    }
+    path.rsplit('/')
+        .next()
+        .is_some_and(|name| !name.is_empty() && answer.contains(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn input(answer: &str, files: &[&str]) -> ReviewInput {
+        ReviewInput {
+            question: "Where is the entry point?".to_string(),
+            answer: answer.to_string(),
+            consulted_files: files
+                .iter()
+                .map(|path| FileProvenance {
+                    path: PathBuf::from(path),
+                    range: None,
+                    sha256: String::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_rules_require_citation() {
+        let reviewer = RuleReviewer::new(RuleConfig::default());
+        assert_eq!(
+            reviewer.check(&input("It starts in `src/main.rs`.", &["src/main.rs"])),
+            Verdict::Pass
+        );
+        assert_eq!(
+            reviewer.check(&input("It starts in main.rs.", &["./src/main.rs"])),
+            Verdict::Pass
+        );
+        assert!(!reviewer
+            .check(&input("It starts somewhere.", &["src/main.rs"]))
+            .passed());
+        assert!(!reviewer.check(&input("It starts somewhere.", &[])).passed());
+    }
+
+    #[test]
+    fn test_rules_reject_forbidden_phrases() {
+        let reviewer = RuleReviewer::new(RuleConfig::default());
+        let verdict = reviewer.check(&input(
+            "Sorry, I DON'T HAVE ACCESS to src/main.rs.",
+            &["src/main.rs"],
+        ));
+        assert!(!verdict.passed());
+    }
+
+    #[test]
+    fn test_precheck_rejects_empty_and_apologies() {
+        assert!(precheck(&input("   ", &[])).is_some());
+        assert!(precheck(&input("Sorry, I cannot help with that.", &[])).is_some());
+        assert!(precheck(&input("The agent lives in the agent module.", &[])).is_none());
+    }
+
+    #[test]
+    fn test_precheck_rejects_unread_files() {
+        let files = ["./src/agent.rs"];
+        assert!(precheck(&input("See src/agent.rs for the loop.", &files)).is_none());
+        let verdict = precheck(&input("See src/agent.rs and src/server/routes.rs.", &files));
+        assert_eq!(
+            verdict,
+            Some(Verdict::Fail(
+                "answer references files that were never read: src/server/routes.rs".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_unknown_answers() {
+        let unknown = "Cannot determine from the repository.\nMissing: the production config\nSuggest checking: deploy/prod.toml in the infrastructure repository";
+        assert_eq!(precheck(&input(unknown, &[])), None);
+        let reviewer = RuleReviewer::new(RuleConfig::default());
+        assert!(reviewer.check(&input(unknown, &["src/main.rs"])).passed());
+
+        let incomplete = "Cannot determine from the repository.\nIt is probably in main.";
+        assert!(precheck(&input(incomplete, &[])).is_some());
+    }
+
+    #[test]
+    fn test_parse_choice() {
+        assert_eq!(parse_choice("2", 3), Some(1));
+        assert_eq!(parse_choice("Answer 3 is best.", 3), Some(2));
+        assert_eq!(parse_choice("4", 3), None);
+        assert_eq!(parse_choice("0", 3), None);
+        assert_eq!(parse_choice("none", 3), None);
+    }
+
+    #[test]
+    fn test_rules_can_be_relaxed() {
+        let reviewer = RuleReviewer::new(RuleConfig {
+            min_cited_files: 0,
+            forbidden_phrases: Vec::new(),
+        });
+        assert!(reviewer.check(&input("I don't have access.", &[])).passed());
+    }
+}
@@ -0,0 +1,122 @@
+//! # Repository Root Sandbox
+//!
+//! This module confines the paths of planned commands to the repository. Plans are
+//! written by the model, so nothing prevents one from asking for `show_file ~/.ssh/config`
+//! or `tree ../..`; every path a tool resolves is checked against a [`RootSandbox`] first
+//! and rejected with `AgentError::PathOutsideSandbox` if it leads outside the root.
+//!
+//! The root is the detected repository root, or the directory given with `--root`. Paths
+//! are compared after following symbolic links and `..`, so neither can be used to leave
+//! the root. Files the user names with `--with-file` are not checked, and neither are the
+//! shell commands of `run`, which are confined by an OS-level sandbox instead.
+
+use std::{
+    env,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::agent::AgentError;
+
+/// The directory tree planned commands may read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootSandbox {
+    root: PathBuf,
+}
+
+impl RootSandbox {
+    /// Confines paths to `root`.
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: normalize(root),
+        }
+    }
+
+    /// Returns the root, with symbolic links followed.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns whether `path`, relative to the current directory, lies under the root.
+    ///
+    /// Paths that do not exist are checked as far as they do, so a missing file under the
+    /// root is reported as missing by the tool rather than as outside the sandbox.
+    pub fn contains(&self, path: &Path) -> bool {
+        normalize(path).starts_with(&self.root)
+    }
+
+    /// Checks that `path` lies under the root.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AgentError::PathOutsideSandbox` if it does not.
+    pub fn check(&self, path: &Path) -> Result<(), AgentError> {
+        if self.contains(path) {
+            Ok(())
+        } else {
+            Err(AgentError::PathOutsideSandbox(path.to_path_buf()))
+        }
+    }
+}
+
+/// Makes `path` absolute, following the symbolic links of the parts that exist and
+/// resolving `.` and `..`.
+fn normalize(path: &Path) -> PathBuf {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir().unwrap_or_default().join(path)
+    };
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => {
+                normalized.push(component);
+                // A link is followed before the next `..`, like the filesystem does
+                if let Ok(canonical) = normalized.canonicalize() {
+                    normalized = canonical;
+                }
+            }
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_rejects_paths_outside_root() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let root = dir.path().join("repo");
+        fs::create_dir_all(root.join("src")).expect("Failed to create dir");
+        fs::write(dir.path().join("secret"), "key").expect("Failed to write file");
+        let sandbox = RootSandbox::new(&root);
+
+        assert!(sandbox.contains(&root.join("src")));
+        assert!(sandbox.contains(&root.join("src/missing.rs")));
+        assert!(sandbox.contains(&root.join("src/../Cargo.toml")));
+        assert!(!sandbox.contains(&root.join("../secret")));
+        assert!(!sandbox.contains(&root.join("src/../../missing")));
+        assert!(!sandbox.contains(Path::new("/etc/passwd")));
+        assert!(matches!(
+            sandbox.check(&root.join("../secret")),
+            Err(AgentError::PathOutsideSandbox(_))
+        ));
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path(), root.join("escape"))
+                .expect("Failed to create link");
+            assert!(!sandbox.contains(&root.join("escape/secret")));
+        }
+    }
+}
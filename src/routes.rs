@@ -0,0 +1,436 @@
+//! # Route Discovery
+//!
+//! This module implements the `routes` tool, which lists the HTTP endpoints a service
+//! exposes so questions about its API are answered from the code's structure rather than
+//! guessed from file names. Routes are recognized line by line in the common forms of:
+//!
+//! - **axum**: `.route("/path", get(handler).post(other))`
+//! - **actix-web**: `#[get("/path")]` attributes and `.route("/path", web::get().to(handler))`
+//! - **Express**: `app.get("/path", handler)` on an app or router
+//! - **FastAPI**: `@app.get("/path")` decorators on an app or `APIRouter`
+//!
+//! A file is only searched for the routes of a framework it imports, which keeps unrelated
+//! `.get(...)` calls out. Prefixes added by nesting routers (`.nest`, `app.use`, `include_router`)
+//! are not resolved, so paths are listed as written where they are declared. Handlers given
+//! by name are located with the [`SymbolIndex`].
+
+use std::{
+    fmt::{self, Write},
+    fs,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use regex::Regex;
+
+use crate::{
+    search::collect_files,
+    symbols::{SymbolIndex, SymbolKind},
+    tree::find_gitignore_patterns,
+};
+
+/// Lines searched for the rest of a route declaration split over several lines.
+const MAX_DECLARATION_LINES: usize = 8;
+
+/// HTTP methods as routing functions are named.
+const METHODS: &str = "get|post|put|delete|patch|head|options|trace|any|all";
+
+/// An axum or actix-web `.route("/path", ...)` call.
+static RUST_ROUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\.route\(\s*"([^"]*)"\s*,(.*)"#).expect("Invalid route pattern")
+});
+
+/// An axum method router such as `get(handler)`.
+static AXUM_METHOD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(r"\b({METHODS})\(\s*([\w:]+)")).expect("Invalid method pattern")
+});
+
+/// An actix-web route such as `web::get().to(handler)`.
+static ACTIX_METHOD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(r"web::({METHODS})\(\)\s*\.to\(\s*([\w:]+)"))
+        .expect("Invalid method pattern")
+});
+
+/// An actix-web route attribute such as `#[get("/path")]`.
+static ACTIX_ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        r#"^\s*#\[(?:actix_web::)?({METHODS})\(\s*"([^"]*)""#
+    ))
+    .expect("Invalid attribute pattern")
+});
+
+/// An Express route such as `router.get("/path", auth, handler)`.
+static EXPRESS_ROUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        r#"\b\w+\.({METHODS})\(\s*['"`]([^'"`]*)['"`]\s*,(.*)"#
+    ))
+    .expect("Invalid route pattern")
+});
+
+/// A FastAPI route decorator such as `@router.get("/path")`.
+static FASTAPI_DECORATOR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(r#"^\s*@\w+\.({METHODS})\(\s*['"]([^'"]*)['"]"#))
+        .expect("Invalid decorator pattern")
+});
+
+/// A Rust or Python function definition.
+static FUNCTION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*(?:pub(?:\([^)]*\))?\s+)?(?:async\s+)?(?:fn|def)\s+(\w+)")
+        .expect("Invalid function pattern")
+});
+
+/// The last identifier of an argument list, taken as the handler.
+static LAST_IDENTIFIER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"([\w.]+)\s*\)?\s*;?\s*$").expect("Invalid handler pattern"));
+
+/// Web frameworks whose routes are recognized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framework {
+    /// axum (Rust).
+    Axum,
+    /// actix-web (Rust).
+    Actix,
+    /// Express (JavaScript and TypeScript).
+    Express,
+    /// FastAPI (Python).
+    FastApi,
+}
+
+impl Framework {
+    /// Returns the frameworks imported by a file with the given extension and content.
+    fn detect(extension: &str, content: &str) -> Vec<Self> {
+        let markers: &[(Self, &str)] = match extension {
+            "rs" => &[(Framework::Axum, "axum"), (Framework::Actix, "actix_web")],
+            "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" => &[
+                (Framework::Express, "'express'"),
+                (Framework::Express, "\"express\""),
+            ],
+            "py" => &[(Framework::FastApi, "fastapi")],
+            _ => &[],
+        };
+        let mut frameworks: Vec<Self> = markers
+            .iter()
+            .filter(|(_, marker)| content.contains(marker))
+            .map(|(framework, _)| *framework)
+            .collect();
+        frameworks.dedup();
+        frameworks
+    }
+}
+
+impl fmt::Display for Framework {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Framework::Axum => "axum",
+            Framework::Actix => "actix-web",
+            Framework::Express => "Express",
+            Framework::FastApi => "FastAPI",
+        };
+        write!(f, "{label}")
+    }
+}
+
+/// Where a route's handler is defined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handler {
+    /// The handler's name as written in the route.
+    pub name: String,
+    /// The defining file and line, if found.
+    pub location: Option<(PathBuf, usize)>,
+}
+
+/// An HTTP endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    /// The HTTP method, uppercase; `ANY` for every method.
+    pub method: String,
+    /// The path as declared.
+    pub path: String,
+    /// The framework declaring the route.
+    pub framework: Framework,
+    /// The file declaring the route, relative to the searched root.
+    pub file: PathBuf,
+    /// Line of the declaration (1-based).
+    pub line: usize,
+    /// The handler, unless it is written inline.
+    pub handler: Option<Handler>,
+}
+
+/// Finds the routes declared in the source files under `root`, skipping ignored files.
+pub fn find_routes(root: &Path) -> Vec<Route> {
+    let ignore = find_gitignore_patterns(root).unwrap_or_default();
+    let mut files = Vec::new();
+    collect_files(root, root, &ignore, &mut files);
+
+    let mut routes = Vec::new();
+    for path in &files {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let Ok(content) = fs::read_to_string(path) else {
+            continue;
+        };
+        let rel_path = path.strip_prefix(root).unwrap_or(path);
+        for framework in Framework::detect(&extension, &content) {
+            routes.extend(parse_routes(framework, rel_path, &content));
+        }
+    }
+    if routes.is_empty() {
+        return routes;
+    }
+
+    let index = SymbolIndex::from_files(root, &files);
+    for route in &mut routes {
+        if let Some(handler) = &mut route.handler
+            && handler.location.is_none()
+        {
+            handler.location = locate(&index, &handler.name, &route.file);
+        }
+    }
+    routes.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+    routes
+}
+
+/// Parses the routes `framework` declares in the file at `path`.
+fn parse_routes(framework: Framework, path: &Path, content: &str) -> Vec<Route> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut routes = Vec::new();
+    let route = |method: &str, route_path: &str, line: usize, handler: Option<Handler>| Route {
+        method: method.to_uppercase(),
+        path: route_path.to_string(),
+        framework,
+        file: path.to_path_buf(),
+        line,
+        handler,
+    };
+    for (index, line) in lines.iter().enumerate() {
+        match framework {
+            Framework::Axum | Framework::Actix => {
+                if let Some(captures) = ACTIX_ATTRIBUTE.captures(line) {
+                    if framework == Framework::Actix {
+                        let handler =
+                            next_function(&lines[index + 1..]).map(|(offset, name)| Handler {
+                                name,
+                                location: Some((path.to_path_buf(), index + offset + 2)),
+                            });
+                        routes.push(route(&captures[1], &captures[2], index + 1, handler));
+                    }
+                    continue;
+                }
+                let Some(declaration) = starting_call(&RUST_ROUTE, &lines[index..]) else {
+                    continue;
+                };
+                let Some(captures) = RUST_ROUTE.captures(&declaration) else {
+                    continue;
+                };
+                let methods = if framework == Framework::Axum {
+                    &AXUM_METHOD
+                } else {
+                    &ACTIX_METHOD
+                };
+                for method in methods.captures_iter(&captures[2]) {
+                    let handler = Handler {
+                        name: method[2].to_string(),
+                        location: None,
+                    };
+                    routes.push(route(&method[1], &captures[1], index + 1, Some(handler)));
+                }
+            }
+            Framework::Express => {
+                let Some(declaration) = starting_call(&EXPRESS_ROUTE, &lines[index..]) else {
+                    continue;
+                };
+                let Some(captures) = EXPRESS_ROUTE.captures(&declaration) else {
+                    continue;
+                };
+                // The handler comes last, after any middleware
+                let arguments = captures[3].trim();
+                let handler = (!arguments.contains("=>") && !arguments.contains("function"))
+                    .then(|| LAST_IDENTIFIER.captures(arguments))
+                    .flatten()
+                    .map(|handler| Handler {
+                        name: handler[1].to_string(),
+                        location: None,
+                    });
+                routes.push(route(&captures[1], &captures[2], index + 1, handler));
+            }
+            Framework::FastApi => {
+                let Some(captures) = FASTAPI_DECORATOR.captures(line) else {
+                    continue;
+                };
+                let handler = next_function(&lines[index + 1..]).map(|(offset, name)| Handler {
+                    name,
+                    location: Some((path.to_path_buf(), index + offset + 2)),
+                });
+                routes.push(route(&captures[1], &captures[2], index + 1, handler));
+            }
+        }
+    }
+    routes
+}
+
+/// Returns the call matching `pattern` that starts on the first of `lines`, joined with the
+/// next lines it continues on.
+///
+/// Calls that merely continue on the first line are ignored, so each is only found once.
+fn starting_call(pattern: &Regex, lines: &[&str]) -> Option<String> {
+    let first = lines.first()?.trim().len();
+    let text = declaration(lines);
+    pattern
+        .find(&text)
+        .is_some_and(|found| found.start() < first)
+        .then_some(text)
+}
+
+/// Joins the lines of a call starting on the first of `lines` until its parentheses close.
+fn declaration(lines: &[&str]) -> String {
+    let mut text = String::new();
+    let mut depth = 0i32;
+    for line in lines.iter().take(MAX_DECLARATION_LINES) {
+        text.push_str(line.trim());
+        text.push(' ');
+        depth += line.matches('(').count() as i32 - line.matches(')').count() as i32;
+        if depth <= 0 {
+            break;
+        }
+    }
+    text
+}
+
+/// Returns the offset and name of the first function defined in `lines`, skipping the
+/// attributes and decorators before it.
+fn next_function(lines: &[&str]) -> Option<(usize, String)> {
+    lines
+        .iter()
+        .take(MAX_DECLARATION_LINES)
+        .enumerate()
+        .find_map(|(offset, line)| {
+            FUNCTION
+                .captures(line)
+                .map(|captures| (offset, captures[1].to_string()))
+        })
+}
+
+/// Locates the function `name`, preferring a definition in `file`.
+fn locate(index: &SymbolIndex, name: &str, file: &Path) -> Option<(PathBuf, usize)> {
+    // `handlers::list` and `users.list` are defined as `list`
+    let name = name.rsplit([':', '.']).next().unwrap_or(name);
+    let definitions: Vec<_> = index
+        .definitions(name)
+        .into_iter()
+        .filter(|symbol| symbol.kind == SymbolKind::Function)
+        .collect();
+    definitions
+        .iter()
+        .find(|symbol| symbol.path == file)
+        .or_else(|| definitions.first())
+        .map(|symbol| (symbol.path.clone(), symbol.line))
+}
+
+/// Renders routes as a table for the model.
+pub fn render_routes(routes: &[Route]) -> String {
+    if routes.is_empty() {
+        return "No axum, actix-web, Express or FastAPI routes found.".to_string();
+    }
+    let mut output = format!("{} routes:\n", routes.len());
+    for route in routes {
+        let handler = match &route.handler {
+            Some(Handler {
+                name,
+                location: Some((path, line)),
+            }) => format!("{name} at {}:{line}", path.display()),
+            Some(Handler {
+                name,
+                location: None,
+            }) => format!("{name} (definition not found)"),
+            None => "inline handler".to_string(),
+        };
+        let _ = writeln!(
+            output,
+            "{:<7} {}  -> {handler}  [{}, declared at {}:{}]",
+            route.method,
+            route.path,
+            route.framework,
+            route.file.display(),
+            route.line
+        );
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_find_routes() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let root = dir.path();
+        fs::create_dir_all(root.join("src")).expect("Failed to create src");
+        fs::write(
+            root.join("src/main.rs"),
+            "use axum::{routing::get, Router};\n\nfn app() -> Router {\n    Router::new()\n        .route(\"/health\", get(health))\n        .route(\n            \"/users/:id\",\n            get(handlers::show).delete(remove),\n        )\n}\n\nasync fn health() {}\nasync fn remove() {}\n",
+        )
+        .expect("Failed to write axum app");
+        fs::write(
+            root.join("src/handlers.rs"),
+            "pub async fn show() {}\n// map.get(\"/not-a-route\", x)\n",
+        )
+        .expect("Failed to write handlers");
+        fs::write(
+            root.join("api.py"),
+            "from fastapi import APIRouter\n\nrouter = APIRouter()\n\n@router.post(\"/items\")\nasync def create_item(item):\n    pass\n",
+        )
+        .expect("Failed to write FastAPI app");
+        fs::write(
+            root.join("server.js"),
+            "const express = require('express');\nconst app = express();\napp.get('/', (req, res) => res.send('hi'));\napp.put('/items/:id', auth, updateItem);\nfunction updateItem(req, res) {}\n",
+        )
+        .expect("Failed to write Express app");
+
+        let routes = find_routes(root);
+        let summary: Vec<String> = routes
+            .iter()
+            .map(|route| {
+                let location = match route.handler.as_ref().and_then(|h| h.location.as_ref()) {
+                    Some((path, line)) => format!("{}:{line}", path.display()),
+                    None => "-".to_string(),
+                };
+                format!("{} {} {location}", route.method, route.path)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                "POST /items api.py:6",
+                "GET / -",
+                "PUT /items/:id server.js:5",
+                "GET /health src/main.rs:12",
+                "GET /users/:id src/handlers.rs:1",
+                "DELETE /users/:id src/main.rs:13",
+            ]
+        );
+        assert_eq!(routes[0].framework, Framework::FastApi);
+        assert!(render_routes(&routes).contains("remove at src/main.rs:13"));
+    }
+
+    #[test]
+    fn test_actix_routes() {
+        let content = "use actix_web::{get, web, App};\n\n#[get(\"/users/{id}\")]\nasync fn user() {}\n\nfn config(cfg: &mut web::ServiceConfig) {\n    cfg.route(\"/items\", web::post().to(create));\n}\n";
+        let routes = parse_routes(Framework::Actix, Path::new("src/lib.rs"), content);
+        assert_eq!(routes.len(), 2);
+        assert_eq!((routes[0].method.as_str(), routes[0].line), ("GET", 3));
+        assert_eq!(
+            routes[0].handler.as_ref().and_then(|h| h.location.clone()),
+            Some(("src/lib.rs".into(), 4))
+        );
+        assert_eq!(routes[1].path, "/items");
+        assert_eq!(
+            routes[1].handler.as_ref().map(|h| h.name.as_str()),
+            Some("create")
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! # Sandboxed Commands
+//!
+//! This module runs the shell commands of the `run` tool inside an OS-level sandbox that
+//! confines them to the repository: they cannot reach the network, can only write inside
+//! the repository root (and a private `/tmp`), and can only read the repository, the
+//! system directories, and the toolchains. The home directory and every other repository
+//! stay out of reach, and so do the paths the organization policy forbids.
+//!
+//! The toolchains are the directories on `PATH` and the Rust toolchain homes (`CARGO_HOME`
+//! and `RUSTUP_HOME`, by default `~/.cargo` and `~/.rustup`), so compilers and test
+//! runners work. A toolchain that reads files from elsewhere in the home directory fails
+//! inside the sandbox.
+//!
+//! The sandbox is provided by an external program, detected on `PATH`:
+//!
+//! - Linux: [bubblewrap](https://github.com/containers/bubblewrap) (`bwrap`), which
+//!   builds the confinement from namespaces and read-only bind mounts of the readable
+//!   directories only
+//! - macOS: `sandbox-exec` with a generated Seatbelt profile, which denies reading the
+//!   home and user directories except the repository and the toolchains
+//!
+//! Windows has no sandbox: confining commands with job objects is not implemented, so
+//! [`Sandbox::detect`] returns `None` there as it does when no sandbox is installed, and
+//! the `run` tool refuses to run anything rather than running it unconfined.
+//!
+//! [`output_within`] runs a sandboxed command with a time limit and kills it once it runs
+//! over, so that a hanging test cannot hold up the query.
+
+use std::{
+    env, fmt,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    process::{self, Child, Command, ExitStatus, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// System directories readable inside the bubblewrap sandbox, where they exist.
+const SYSTEM_DIRS: [&str; 7] = ["/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/etc"];
+
+/// Counter distinguishing the output files of commands run by this process.
+static NEXT_OUTPUT: AtomicUsize = AtomicUsize::new(0);
+
+/// An available sandboxing mechanism.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sandbox {
+    /// bubblewrap, at the given path.
+    Bubblewrap(PathBuf),
+    /// macOS Seatbelt through `sandbox-exec`, at the given path.
+    SandboxExec(PathBuf),
+}
+
+impl fmt::Display for Sandbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sandbox::Bubblewrap(_) => write!(f, "bubblewrap"),
+            Sandbox::SandboxExec(_) => write!(f, "sandbox-exec"),
+        }
+    }
+}
+
+impl Sandbox {
+    /// Returns the sandbox available on this machine, if any.
+    pub fn detect() -> Option<Self> {
+        if cfg!(target_os = "linux") {
+            find_program("bwrap").map(Sandbox::Bubblewrap)
+        } else if cfg!(target_os = "macos") {
+            find_program("sandbox-exec").map(Sandbox::SandboxExec)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the command running `script` with `sh` in `root`, confined to it.
+    ///
+    /// `root` should be absolute, since the sandbox profile names it.
+    pub fn command(&self, root: &Path, script: &str) -> Command {
+        self.command_hiding(root, script, &[])
+    }
+
+    /// Returns the command running `script` like [`Sandbox::command`], with the `hidden`
+    /// paths inside `root` made unreadable.
+    ///
+    /// `root` and the `hidden` paths should be absolute, since the sandbox profile names
+    /// them.
+    pub fn command_hiding(&self, root: &Path, script: &str, hidden: &[PathBuf]) -> Command {
+        let root_arg = root.to_string_lossy();
+        let mut command = match self {
+            Sandbox::Bubblewrap(program) => {
+                let mut command = Command::new(program);
+                for dir in SYSTEM_DIRS
+                    .iter()
+                    .map(PathBuf::from)
+                    .chain(toolchain_dirs())
+                {
+                    command.arg("--ro-bind-try").arg(&dir).arg(&dir);
+                }
+                command
+                    .args(["--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp"])
+                    .args(["--bind", &root_arg, &root_arg]);
+                // Directories are replaced by empty ones and files by an empty file
+                for path in hidden {
+                    if path.is_dir() {
+                        command.arg("--tmpfs").arg(path);
+                    } else {
+                        command.args(["--ro-bind", "/dev/null"]).arg(path);
+                    }
+                }
+                command
+                    .args(["--unshare-net", "--unshare-pid", "--die-with-parent"])
+                    .args(["--chdir", &root_arg, "--", "sh", "-c", script]);
+                command
+            }
+            Sandbox::SandboxExec(program) => {
+                let mut command = Command::new(program);
+                command
+                    .args(["-p", &seatbelt_profile(root, &toolchain_dirs(), hidden)])
+                    .args(["sh", "-c", script]);
+                command
+            }
+        };
+        command.current_dir(root);
+        command
+    }
+}
+
+/// Returns the toolchain directories readable inside the sandbox: the directories on
+/// `PATH` and the Rust toolchain homes.
+///
+/// The filesystem root and the home directory are left out, since they would expose
+/// everything else.
+fn toolchain_dirs() -> Vec<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let in_home = |dir: &str| home.as_ref().map(|home| home.join(dir));
+    let mut dirs: Vec<PathBuf> = env::var_os("PATH")
+        .map(|path| env::split_paths(&path).collect())
+        .unwrap_or_default();
+    dirs.extend(
+        env::var_os("CARGO_HOME")
+            .map(PathBuf::from)
+            .or_else(|| in_home(".cargo")),
+    );
+    dirs.extend(
+        env::var_os("RUSTUP_HOME")
+            .map(PathBuf::from)
+            .or_else(|| in_home(".rustup")),
+    );
+    dirs.retain(|dir| dir.is_absolute() && dir.parent().is_some() && Some(dir) != home.as_ref());
+    dirs.dedup();
+    dirs
+}
+
+/// Returns the Seatbelt profile denying network access, writes outside `root`, and reads
+/// of the user directories other than `root` and the `toolchains`, and of the `hidden`
+/// paths.
+fn seatbelt_profile(root: &Path, toolchains: &[PathBuf], hidden: &[PathBuf]) -> String {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let user_dirs = subpaths(
+        [Path::new("/Users"), Path::new("/Volumes")]
+            .into_iter()
+            .chain(home.as_deref()),
+    );
+    let readable = subpaths(std::iter::once(root).chain(toolchains.iter().map(PathBuf::as_path)));
+    let mut profile = format!(
+        "(version 1)\n(allow default)\n(deny network*)\n(deny file-write*)\n(allow file-write*{} (subpath \"/private/tmp\") (subpath \"/private/var/folders\") (literal \"/dev/null\"))\n(deny file-read-data{user_dirs})\n(allow file-read-data{readable})\n",
+        subpaths([root])
+    );
+    if !hidden.is_empty() {
+        let hidden = subpaths(hidden.iter().map(PathBuf::as_path));
+        profile.push_str(&format!("(deny file-read* file-write*{hidden})\n"));
+    }
+    profile
+}
+
+/// Returns the Seatbelt filters matching `paths` and everything below them.
+fn subpaths<'a>(paths: impl IntoIterator<Item = &'a Path>) -> String {
+    paths
+        .into_iter()
+        .map(|path| {
+            let path = path
+                .to_string_lossy()
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"");
+            format!(" (subpath \"{path}\")")
+        })
+        .collect()
+}
+
+/// Waits for `child`, killing it if it runs longer than `timeout`.
+///
+/// Returns `None` if the child was killed.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the child cannot be waited for or killed.
+pub fn wait_within(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if started.elapsed() > timeout {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Runs `command` with its standard output and error captured together, killing it if it
+/// runs longer than `timeout`.
+///
+/// The output goes through a temporary file rather than pipes, so that processes the
+/// command leaves behind cannot keep this waiting once it is killed.
+///
+/// Returns the exit status, or `None` if the command was killed, and the output so far.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the output file cannot be created or read, or the command
+/// cannot be started.
+pub fn output_within(
+    mut command: Command,
+    timeout: Duration,
+) -> io::Result<(Option<ExitStatus>, Vec<u8>)> {
+    let path = env::temp_dir().join(format!(
+        "nishiogi-run-{}-{}.txt",
+        process::id(),
+        NEXT_OUTPUT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let file = File::create(&path)?;
+    let result = command
+        .stdin(Stdio::null())
+        .stdout(file.try_clone()?)
+        .stderr(file)
+        .spawn()
+        .and_then(|mut child| wait_within(&mut child, timeout));
+    let output = result.and_then(|status| Ok((status, fs::read(&path)?)));
+    let _ = fs::remove_file(&path);
+    output
+}
+
+/// Returns the path of `name` on `PATH`, if it is installed.
+fn find_program(name: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        let root = Path::new("/work/repo");
+        let hidden = [root.join("secrets"), root.join(".env")];
+        let command = Sandbox::Bubblewrap(PathBuf::from("/usr/bin/bwrap")).command_hiding(
+            root,
+            "cargo test",
+            &hidden,
+        );
+        let args: Vec<String> = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        assert!(!args.windows(3).any(|args| args == ["--ro-bind", "/", "/"]));
+        assert!(args
+            .windows(3)
+            .any(|args| args == ["--ro-bind-try", "/usr", "/usr"]));
+        assert!(args
+            .windows(3)
+            .any(|args| args == ["--bind", "/work/repo", "/work/repo"]));
+        assert!(args
+            .windows(3)
+            .any(|args| args == ["--ro-bind", "/dev/null", "/work/repo/.env"]));
+        assert!(args.contains(&"--unshare-net".to_string()));
+        assert!(args.ends_with(&["sh".to_string(), "-c".to_string(), "cargo test".to_string()]));
+
+        let profile = seatbelt_profile(
+            Path::new("/Users/me/repo \"x\""),
+            &[PathBuf::from("/Users/me/.cargo")],
+            &[PathBuf::from("/Users/me/repo \"x\"/.env")],
+        );
+        assert!(profile.contains("(deny network*)"));
+        assert!(profile.contains("(deny file-read-data (subpath \"/Users\")"));
+        assert!(profile.contains(
+            "(allow file-read-data (subpath \"/Users/me/repo \\\"x\\\"\") (subpath \"/Users/me/.cargo\"))"
+        ));
+        assert!(profile.ends_with(
+            "(deny file-read* file-write* (subpath \"/Users/me/repo \\\"x\\\"/.env\"))\n"
+        ));
+    }
+
+    #[test]
+    fn test_toolchain_dirs() {
+        let dirs = toolchain_dirs();
+        assert!(!dirs.contains(&PathBuf::from("/")));
+        assert!(dirs.iter().all(|dir| dir.is_absolute()));
+        if let Some(home) = env::var_os("HOME") {
+            assert!(!dirs.contains(&PathBuf::from(home)));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_output_within() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo out; echo err >&2; exit 3"]);
+        let (status, output) =
+            output_within(command, Duration::from_secs(10)).expect("Failed to run command");
+        assert_eq!(status.and_then(|status| status.code()), Some(3));
+        assert_eq!(String::from_utf8_lossy(&output), "out\nerr\n");
+
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo started; sleep 10"]);
+        let started = Instant::now();
+        let (status, _) =
+            output_within(command, Duration::from_millis(200)).expect("Failed to run command");
+        assert_eq!(status, None);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
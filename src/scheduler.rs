@@ -0,0 +1,302 @@
+//! # Scheduler
+//!
+//! This module limits how much work nishiogi does at once, so long-running and batch uses
+//! behave predictably under load. Three kinds of work are limited separately:
+//!
+//! - model requests, overall and per provider
+//! - file reads by the tools
+//! - subprocesses such as `git`
+//!
+//! The limits come from the `[limits]` section of the configuration file and apply to the
+//! whole process through [`global`]. Work waits for a [`Permit`] and gives it back when the
+//! permit is dropped. Model requests wait asynchronously; file reads and subprocesses run on
+//! tool threads and wait by blocking.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    future::poll_fn,
+    sync::{Condvar, Mutex, MutexGuard, OnceLock},
+    task::{Poll, Waker},
+};
+
+use serde::Deserialize;
+
+/// The scheduler shared by the whole process.
+static GLOBAL: OnceLock<Scheduler> = OnceLock::new();
+
+/// The `[limits]` section of the configuration file.
+///
+/// ```toml
+/// [limits]
+/// llm_calls = 4
+/// file_reads = 16
+/// subprocesses = 8
+///
+/// [limits.providers]
+/// copilot = 2
+/// ```
+///
+/// Every limit is at least one; zero is treated as one.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Most model requests in flight at once, over all providers.
+    pub llm_calls: usize,
+    /// Most files read at once.
+    pub file_reads: usize,
+    /// Most subprocesses running at once.
+    pub subprocesses: usize,
+    /// Most model requests in flight at once per provider, by provider name.
+    pub providers: BTreeMap<String, usize>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            llm_calls: 4,
+            file_reads: 16,
+            subprocesses: 8,
+            providers: BTreeMap::new(),
+        }
+    }
+}
+
+/// A kind of limited work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// A request to a model, whatever the provider.
+    LlmCall,
+    /// Reading a file.
+    FileRead,
+    /// Running a subprocess.
+    Subprocess,
+}
+
+/// A counting semaphore that can be waited on by blocking or asynchronously.
+#[derive(Debug)]
+struct Semaphore {
+    state: Mutex<SemaphoreState>,
+    released: Condvar,
+}
+
+#[derive(Debug)]
+struct SemaphoreState {
+    available: usize,
+    /// Tasks waiting asynchronously for a permit.
+    waiters: VecDeque<Waker>,
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Self {
+            state: Mutex::new(SemaphoreState {
+                available: permits.max(1),
+                waiters: VecDeque::new(),
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SemaphoreState> {
+        // The state stays consistent even if a holder panicked
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn acquire_blocking(&self) {
+        let mut state = self.lock();
+        while state.available == 0 {
+            state = self
+                .released
+                .wait(state)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+        state.available -= 1;
+    }
+
+    async fn acquire(&self) {
+        poll_fn(|cx| {
+            let mut state = self.lock();
+            if state.available > 0 {
+                state.available -= 1;
+                Poll::Ready(())
+            } else {
+                state.waiters.push_back(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await;
+    }
+
+    fn release(&self) {
+        let mut state = self.lock();
+        state.available += 1;
+        // Waiters that were dropped never take their turn, so everyone retries
+        for waker in state.waiters.drain(..) {
+            waker.wake();
+        }
+        drop(state);
+        self.released.notify_all();
+    }
+
+    fn available(&self) -> usize {
+        self.lock().available
+    }
+}
+
+/// Permission to do limited work, given back when dropped.
+#[must_use = "the work is only limited while the permit is held"]
+#[derive(Debug)]
+pub struct Permit<'a> {
+    semaphores: Vec<&'a Semaphore>,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        for semaphore in &self.semaphores {
+            semaphore.release();
+        }
+    }
+}
+
+/// Hands out permits for limited work.
+#[derive(Debug)]
+pub struct Scheduler {
+    llm_calls: Semaphore,
+    file_reads: Semaphore,
+    subprocesses: Semaphore,
+    providers: HashMap<String, Semaphore>,
+}
+
+impl Scheduler {
+    /// Creates a scheduler enforcing `config`.
+    pub fn new(config: &LimitsConfig) -> Self {
+        Self {
+            llm_calls: Semaphore::new(config.llm_calls),
+            file_reads: Semaphore::new(config.file_reads),
+            subprocesses: Semaphore::new(config.subprocesses),
+            providers: config
+                .providers
+                .iter()
+                .map(|(name, limit)| (name.clone(), Semaphore::new(*limit)))
+                .collect(),
+        }
+    }
+
+    fn semaphore(&self, resource: Resource) -> &Semaphore {
+        match resource {
+            Resource::LlmCall => &self.llm_calls,
+            Resource::FileRead => &self.file_reads,
+            Resource::Subprocess => &self.subprocesses,
+        }
+    }
+
+    /// Blocks until `resource` is available.
+    pub fn acquire(&self, resource: Resource) -> Permit<'_> {
+        let semaphore = self.semaphore(resource);
+        semaphore.acquire_blocking();
+        Permit {
+            semaphores: vec![semaphore],
+        }
+    }
+
+    /// Waits until a request to `provider` may be sent, within both the provider's quota
+    /// and the overall limit.
+    pub async fn llm_call(&self, provider: &str) -> Permit<'_> {
+        let mut semaphores = Vec::new();
+        // The provider quota is taken first so waiting on it does not hold an overall slot
+        if let Some(quota) = self.providers.get(provider) {
+            quota.acquire().await;
+            semaphores.push(quota);
+        }
+        self.llm_calls.acquire().await;
+        semaphores.push(&self.llm_calls);
+        Permit { semaphores }
+    }
+
+    /// Returns how many more permits for `resource` can be handed out right now.
+    pub fn available(&self, resource: Resource) -> usize {
+        self.semaphore(resource).available()
+    }
+}
+
+/// Sets the limits of the process-wide scheduler.
+///
+/// Returns `false`, leaving the limits unchanged, if the scheduler was already configured
+/// or used.
+pub fn configure(config: &LimitsConfig) -> bool {
+    GLOBAL.set(Scheduler::new(config)).is_ok()
+}
+
+/// Returns the process-wide scheduler, with the default limits unless [`configure`] was
+/// called first.
+pub fn global() -> &'static Scheduler {
+    GLOBAL.get_or_init(|| Scheduler::new(&LimitsConfig::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_blocking_limit() {
+        let config: LimitsConfig =
+            toml::from_str("subprocesses = 2").expect("Failed to parse limits");
+        let scheduler = Arc::new(Scheduler::new(&config));
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..6)
+            .map(|_| {
+                let (scheduler, running, peak) = (
+                    Arc::clone(&scheduler),
+                    Arc::clone(&running),
+                    Arc::clone(&peak),
+                );
+                thread::spawn(move || {
+                    let _permit = scheduler.acquire(Resource::Subprocess);
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("Worker panicked");
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(scheduler.available(Resource::Subprocess), 2);
+        assert_eq!(scheduler.available(Resource::FileRead), 16);
+    }
+
+    #[tokio::test]
+    async fn test_provider_quota() {
+        let config: LimitsConfig = toml::from_str("llm_calls = 3\n[providers]\ncopilot = 1")
+            .expect("Failed to parse limits");
+        let scheduler = Scheduler::new(&config);
+
+        let first = scheduler.llm_call("copilot").await;
+        let other = scheduler.llm_call("other").await;
+        assert_eq!(scheduler.available(Resource::LlmCall), 1);
+
+        // The second copilot request waits for the first one to finish
+        let second = scheduler.llm_call("copilot");
+        let waited = tokio::time::timeout(Duration::from_millis(20), second).await;
+        assert!(waited.is_err());
+
+        drop(first);
+        let second = scheduler.llm_call("copilot").await;
+        assert_eq!(scheduler.available(Resource::LlmCall), 1);
+        drop((second, other));
+        assert_eq!(scheduler.available(Resource::LlmCall), 3);
+    }
+}
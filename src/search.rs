@@ -0,0 +1,429 @@
+//! # Content Search
+//!
+//! This module searches file contents under a directory and returns ranked snippets rather
+//! than raw matching lines. Nearby matches in a file are merged into one snippet together
+//! with a few lines of surrounding context. Snippets are ranked by how many matches they
+//! contain, with a bonus when the file path itself matches, and the result is limited to a
+//! token budget so it can be placed directly into an answer prompt.
+//!
+//! For questions such as "where is X used?", where every occurrence matters more than the
+//! best ones, [`grep`] lists the matching lines themselves, in file and line order.
+//!
+//! Files matching the repository's `.gitignore` patterns, the `.git` directory, files that
+//! are not valid UTF-8, and paths the [organization policy](crate::org_policy) forbids are
+//! skipped. Every command walking the repository goes through [`collect_files`], so none
+//! of them can read a forbidden file.
+
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{org_policy, tokens::estimate_tokens, tree::find_gitignore_patterns};
+
+/// Options controlling snippet extraction.
+#[derive(Debug, Clone, Copy)]
+pub struct SearchOptions {
+    /// Lines of context included before and after each match.
+    pub context_lines: usize,
+    /// Maximum estimated tokens of all returned excerpts together.
+    pub max_tokens: usize,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            context_lines: 2,
+            max_tokens: 2000,
+        }
+    }
+}
+
+/// A ranked excerpt of a file around one or more matches.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Snippet {
+    /// Path of the file, including the searched directory.
+    pub path: PathBuf,
+    /// First line of the excerpt (1-based).
+    pub start: usize,
+    /// Last line of the excerpt (1-based, inclusive).
+    pub end: usize,
+    /// The excerpt text.
+    pub excerpt: String,
+    /// Relevance score; higher is better.
+    pub score: f64,
+}
+
+/// A line matching a `grep` pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineMatch {
+    /// Path of the file, including the searched directory.
+    pub path: PathBuf,
+    /// The matching line's number (1-based).
+    pub line: usize,
+    /// The matching line, without surrounding whitespace.
+    pub text: String,
+}
+
+/// Maximum matching lines listed by `grep`.
+pub const MAX_LINE_MATCHES: usize = 200;
+
+/// Maximum characters of a matching line listed by `grep`; longer lines are cut.
+const MAX_LINE_CHARS: usize = 200;
+
+/// Score added to snippets from files whose path (relative to the search root) matches.
+const PATH_MATCH_BONUS: f64 = 2.0;
+
+/// Searches the files under `root` for `pattern` and returns the best snippets.
+///
+/// Snippets are ordered by descending score. Lower ranked snippets are dropped once the
+/// token budget is used up, but the best snippet is always returned.
+pub fn search(root: &Path, pattern: &Regex, options: SearchOptions) -> Vec<Snippet> {
+    let ignore = find_gitignore_patterns(root).unwrap_or_default();
+    let mut files = Vec::new();
+    collect_files(root, root, &ignore, &mut files);
+
+    let mut snippets: Vec<Snippet> = files
+        .iter()
+        .filter_map(|path| {
+            let content = fs::read_to_string(path).ok()?;
+            Some(file_snippets(
+                root,
+                path,
+                &content,
+                pattern,
+                options.context_lines,
+            ))
+        })
+        .flatten()
+        .collect();
+    snippets.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.path.cmp(&b.path))
+            .then_with(|| a.start.cmp(&b.start))
+    });
+
+    let mut budget = options.max_tokens;
+    let mut ranked = Vec::new();
+    for snippet in snippets {
+        let tokens = estimate_tokens(&snippet.excerpt);
+        if tokens > budget && !ranked.is_empty() {
+            break;
+        }
+        budget = budget.saturating_sub(tokens);
+        ranked.push(snippet);
+    }
+    ranked
+}
+
+/// Lists the lines matching `pattern` in `path`, a file or a directory searched
+/// recursively, in path and line order.
+///
+/// Returns the first `limit` matches and the total number of matches.
+pub fn grep(path: &Path, pattern: &Regex, limit: usize) -> (Vec<LineMatch>, usize) {
+    let mut files = Vec::new();
+    if path.is_file() {
+        if !org_policy::global().is_forbidden(path) {
+            files.push(path.to_path_buf());
+        }
+    } else {
+        let ignore = find_gitignore_patterns(path).unwrap_or_default();
+        collect_files(path, path, &ignore, &mut files);
+    }
+
+    let mut matches = Vec::new();
+    let mut total = 0;
+    for file in files {
+        let Ok(content) = fs::read_to_string(&file) else {
+            continue;
+        };
+        for (index, line) in content.lines().enumerate() {
+            if !pattern.is_match(line) {
+                continue;
+            }
+            total += 1;
+            if matches.len() < limit {
+                let text = line.trim();
+                let text = match text.char_indices().nth(MAX_LINE_CHARS) {
+                    Some((end, _)) => format!("{}...", &text[..end]),
+                    None => text.to_string(),
+                };
+                matches.push(LineMatch {
+                    path: file.clone(),
+                    line: index + 1,
+                    text,
+                });
+            }
+        }
+    }
+    (matches, total)
+}
+
+/// Renders `grep` matches for a prompt as `path:line: text` lines, noting how many of
+/// `total` matches were left out.
+pub fn render_matches(matches: &[LineMatch], total: usize) -> String {
+    if matches.is_empty() {
+        return "No matches found.\n".to_string();
+    }
+
+    let mut output = String::new();
+    for found in matches {
+        let _ = writeln!(
+            output,
+            "{}:{}: {}",
+            found.path.display(),
+            found.line,
+            found.text
+        );
+    }
+    if total > matches.len() {
+        let _ = writeln!(
+            output,
+            "... and {} more matches; narrow the pattern or the path",
+            total - matches.len()
+        );
+    }
+    output
+}
+
+/// Renders snippets for a prompt, with line numbers prefixed to each excerpt line.
+pub fn render_snippets(snippets: &[Snippet]) -> String {
+    if snippets.is_empty() {
+        return "No matches found.\n".to_string();
+    }
+
+    let mut output = String::new();
+    for snippet in snippets {
+        let _ = writeln!(
+            output,
+            "{}:{}-{} (score {:.1})",
+            snippet.path.display(),
+            snippet.start,
+            snippet.end,
+            snippet.score
+        );
+        for (offset, line) in snippet.excerpt.lines().enumerate() {
+            let _ = writeln!(output, "{:>5} | {line}", snippet.start + offset);
+        }
+        output.push('\n');
+    }
+    output
+}
+
+/// Extracts the snippets for one file, merging matches whose context overlaps.
+fn file_snippets(
+    root: &Path,
+    path: &Path,
+    content: &str,
+    pattern: &Regex,
+    context: usize,
+) -> Vec<Snippet> {
+    let lines: Vec<&str> = content.lines().collect();
+    let rel_path = path.strip_prefix(root).unwrap_or(path);
+    let bonus = if pattern.is_match(&rel_path.to_string_lossy()) {
+        PATH_MATCH_BONUS
+    } else {
+        0.0
+    };
+
+    // Spans of 0-based line indices with their match counts
+    let mut spans: Vec<(usize, usize, usize)> = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        if !pattern.is_match(line) {
+            continue;
+        }
+        let start = index.saturating_sub(context);
+        let end = (index + context).min(lines.len() - 1);
+        match spans.last_mut() {
+            Some((_, last_end, hits)) if start <= *last_end + 1 => {
+                *last_end = end;
+                *hits += 1;
+            }
+            _ => spans.push((start, end, 1)),
+        }
+    }
+
+    spans
+        .into_iter()
+        .map(|(start, end, hits)| Snippet {
+            path: path.to_path_buf(),
+            start: start + 1,
+            end: end + 1,
+            excerpt: lines[start..=end].join("\n"),
+            score: hits as f64 + bonus,
+        })
+        .collect()
+}
+
+/// Recursively collects the files under `dir` that are neither ignored nor forbidden by the
+/// organization policy.
+pub(crate) fn collect_files(root: &Path, dir: &Path, ignore: &[Regex], files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries: Vec<_> = entries.filter_map(Result::ok).collect();
+    entries.sort_by_key(fs::DirEntry::file_name);
+
+    let policy = org_policy::global();
+    for entry in entries {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        let rel_path = relative.to_string_lossy();
+        if file_name == ".git"
+            || ignore
+                .iter()
+                .any(|r| r.is_match(&file_name) || r.is_match(&rel_path))
+            || policy.is_forbidden(relative)
+        {
+            continue;
+        }
+        if path.is_dir() {
+            collect_files(root, &path, ignore, files);
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::org_policy::{OrgPolicy, OrgPolicyConfig};
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().expect("Path has a parent"))
+            .expect("Failed to create directory");
+        fs::write(path, content).expect("Failed to write file");
+    }
+
+    #[test]
+    fn test_nearby_matches_are_merged_and_ranked() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        write(
+            root,
+            "a.rs",
+            "fn retry() {}\nlet x = 1;\nretry();\nlet y = 2;\nlet z = 3;\nlet w = 4;\nlet v = 5;\nlet u = 6;\nretry();\n",
+        );
+        write(root, "retry.rs", "// retry helpers\n");
+
+        let pattern = Regex::new("retry").expect("Invalid regex");
+        let snippets = search(
+            root,
+            &pattern,
+            SearchOptions {
+                context_lines: 1,
+                max_tokens: 1000,
+            },
+        );
+
+        assert_eq!(snippets.len(), 3);
+        // The path bonus ranks the single hit in retry.rs first
+        assert!(snippets[0].path.ends_with("retry.rs"));
+        assert!((snippets[0].score - 3.0).abs() < f64::EPSILON);
+        assert_eq!((snippets[1].start, snippets[1].end), (1, 4));
+        assert!((snippets[1].score - 2.0).abs() < f64::EPSILON);
+        assert_eq!((snippets[2].start, snippets[2].end), (8, 9));
+    }
+
+    #[test]
+    fn test_grep_lists_matching_lines() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        write(root, "b.rs", "use a::retry;\n\n    retry();\n");
+        write(root, "a.rs", "fn retry() {}\n");
+        write(root, "target/out.rs", "retry();\n");
+        write(root, ".gitignore", "target\n");
+
+        let pattern = Regex::new(r"retry\(").expect("Invalid regex");
+        let (matches, total) = grep(root, &pattern, 2);
+        assert_eq!(total, 2);
+        assert_eq!(
+            render_matches(&matches, total),
+            format!(
+                "{}:1: fn retry() {{}}\n{}:3: retry();\n",
+                root.join("a.rs").display(),
+                root.join("b.rs").display()
+            )
+        );
+
+        let (matches, total) = grep(
+            &root.join("b.rs"),
+            &Regex::new("retry").expect("Invalid regex"),
+            1,
+        );
+        assert_eq!((matches.len(), total), (1, 2));
+        assert!(render_matches(&matches, total)
+            .ends_with("... and 1 more matches; narrow the pattern or the path\n"));
+    }
+
+    #[test]
+    fn test_token_budget_limits_snippets() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        write(root, "a.txt", &format!("needle {}\n", "x".repeat(400)));
+        write(root, "b.txt", &format!("needle {}\n", "y".repeat(400)));
+
+        let pattern = Regex::new("needle").expect("Invalid regex");
+        let snippets = search(
+            root,
+            &pattern,
+            SearchOptions {
+                context_lines: 0,
+                max_tokens: 150,
+            },
+        );
+        assert_eq!(snippets.len(), 1);
+    }
+
+    #[test]
+    fn test_forbidden_files_are_skipped() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        write(root, "src/lib.rs", "let token = load();\n");
+        write(root, "secrets/prod.env", "token = hunter2\n");
+        write(root, "deploy/server.pem", "token\n");
+        org_policy::set_test_policy(
+            OrgPolicy::new(OrgPolicyConfig {
+                forbidden_paths: vec!["secrets/**".to_string(), "*.pem".to_string()],
+                ..OrgPolicyConfig::default()
+            })
+            .expect("Failed to compile policy"),
+        );
+
+        let pattern = Regex::new("token").expect("Invalid regex");
+        let snippets = search(root, &pattern, SearchOptions::default());
+        assert_eq!(
+            snippets.iter().map(|s| s.path.clone()).collect::<Vec<_>>(),
+            vec![root.join("src/lib.rs")]
+        );
+        let (matches, total) = grep(root, &pattern, MAX_LINE_MATCHES);
+        assert_eq!((matches.len(), total), (1, 1));
+        assert_eq!(grep(&root.join("secrets/prod.env"), &pattern, 10).1, 0);
+    }
+
+    #[test]
+    fn test_render_snippets() {
+        let snippets = vec![Snippet {
+            path: PathBuf::from("src/lib.rs"),
+            start: 7,
+            end: 8,
+            excerpt: "mod a;\nmod b;".to_string(),
+            score: 1.0,
+        }];
+        assert_eq!(
+            render_snippets(&snippets),
+            "src/lib.rs:7-8 (score 1.0)\n    7 | mod a;\n    8 | mod b;\n\n"
+        );
+        assert_eq!(render_snippets(&[]), "No matches found.\n");
+    }
+}
@@ -0,0 +1,685 @@
+//! # Server Mode
+//!
+//! This module runs nishiogi as an internal HTTP service (`nishiogi serve`), answering
+//! questions about the repository it was started in. Besides the question endpoint it
+//! exposes what is needed to operate it as a service:
+//!
+//! - `POST /ask` takes `{"question": "...", "repo": "..."}` and returns an object with the
+//!   `answer`, its `status` (`answered` or `unknown`), `unknown`, `tokens` and `latency`.
+//!   `repo` names one of the repositories configured in the `[server]` section and defaults
+//!   to the one the server was started in. A `status` of `unknown` means the repository did
+//!   not answer the question, and `unknown` then holds what is missing and what to check.
+//!   `latency` breaks the time of the answer down by step of the agent loop
+//! - `GET /healthz` reports that the process is up
+//! - `GET /readyz` reports whether questions can be answered: the local database and its
+//!   index metadata can be read, and the provider was reached to list the models
+//! - `GET /metrics` reports request counts, latencies and token usage in the Prometheus
+//!   text format
+//!
+//! `POST /ask` requires an API key once keys are configured; see [`crate::tenant`] for the
+//! per-key rate limits, token budgets and repository scopes. The operational endpoints are
+//! open, for probes and scrapers.
+//!
+//! Questions about each repository are answered one at a time by a single agent, which
+//! forgets earlier questions before each one. Tools that would ask for confirmation are
+//! denied, since nobody can answer the prompt. The HTTP handling is deliberately minimal:
+//! one request per connection, with a limited body size, at most [`MAX_CONNECTIONS`]
+//! connections at once, and [`READ_TIMEOUT`] to send the request before the connection is
+//! answered with `408`, so slow clients cannot hold the handlers.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{Mutex as AsyncMutex, Semaphore},
+};
+
+use crate::{
+    agent::{Agent, AgentError},
+    config::Config,
+    db::{Database, INDEX_META_PREFIX},
+    planner::PlannerConfig,
+    policy::{Policy, PolicyConfig},
+    provider::ProviderKind,
+    report::LatencyReport,
+    review::Reviewer,
+    tenant::{Tenants, DEFAULT_REPO},
+    unknown::{self, AnswerStatus, Unknown},
+};
+
+/// Largest request, headers and body together, that is accepted.
+const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
+/// Most connections handled at once; further clients wait to be accepted.
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// Time a client has to send its whole request.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Upper bounds of the request latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Paths with a handler; other paths are counted together as `other` in the metrics.
+const ENDPOINTS: &[&str] = &["/ask", "/healthz", "/readyz", "/metrics"];
+
+/// A parsed HTTP request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Request {
+    method: String,
+    /// The path, without the query string.
+    path: String,
+    /// Header names (lowercase) and values, in order.
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Request {
+    /// Returns the value of the header `name` (lowercase), if present.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// An HTTP response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn text(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into(),
+        }
+    }
+
+    fn json(status: u16, value: &impl Serialize) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_string(value).unwrap_or_default(),
+        }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Self::json(
+            status,
+            &ErrorBody {
+                error: message.into(),
+            },
+        )
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            reason(self.status),
+            self.content_type,
+            self.body.len(),
+            self.body
+        )
+        .into_bytes()
+    }
+}
+
+/// The body of `POST /ask`.
+#[derive(Debug, Deserialize)]
+struct AskBody {
+    question: String,
+    #[serde(default)]
+    repo: Option<String>,
+}
+
+/// The reply to `POST /ask`.
+#[derive(Debug, Serialize)]
+struct AnswerBody<'a> {
+    answer: &'a str,
+    status: AnswerStatus,
+    unknown: Option<Unknown>,
+    tokens: u64,
+    latency: Option<LatencyReport>,
+}
+
+/// The reply to a failed request.
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+/// Request latencies of one endpoint.
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Requests at most as slow as each bucket bound, in the order of `LATENCY_BUCKETS`.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Counters describing the requests served so far.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Requests by endpoint and status.
+    requests: BTreeMap<(String, u16), u64>,
+    /// Latencies by endpoint.
+    latencies: BTreeMap<String, Histogram>,
+    /// Tokens the provider reported using to answer questions, by API key name.
+    tokens: BTreeMap<String, u64>,
+    /// Of those, prompt tokens served from the provider's prompt cache, by API key name.
+    cached_tokens: BTreeMap<String, u64>,
+}
+
+impl Metrics {
+    /// Records a request to `path` that was answered with `status` after `elapsed`.
+    pub fn record_request(&mut self, path: &str, status: u16, elapsed: Duration) {
+        let endpoint = endpoint_label(path).to_string();
+        *self.requests.entry((endpoint.clone(), status)).or_insert(0) += 1;
+
+        let seconds = elapsed.as_secs_f64();
+        let histogram = self.latencies.entry(endpoint).or_default();
+        histogram.buckets.resize(LATENCY_BUCKETS.len(), 0);
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    /// Records `tokens` used to answer a question asked with the API key `key`, `cached` of
+    /// which were prompt tokens served from the provider's prompt cache.
+    pub fn record_tokens(&mut self, key: &str, tokens: u64, cached: u64) {
+        *self.tokens.entry(key.to_string()).or_insert(0) += tokens;
+        *self.cached_tokens.entry(key.to_string()).or_insert(0) += cached;
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut output = String::from(
+            "# HELP nishiogi_requests_total HTTP requests handled, by endpoint and status.\n# TYPE nishiogi_requests_total counter\n",
+        );
+        for ((endpoint, status), count) in &self.requests {
+            let _ = writeln!(
+                output,
+                "nishiogi_requests_total{{endpoint=\"{endpoint}\",status=\"{status}\"}} {count}"
+            );
+        }
+
+        output.push_str("# HELP nishiogi_request_duration_seconds Time taken to handle HTTP requests, by endpoint.\n# TYPE nishiogi_request_duration_seconds histogram\n");
+        for (endpoint, histogram) in &self.latencies {
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+                let _ = writeln!(
+                    output,
+                    "nishiogi_request_duration_seconds_bucket{{endpoint=\"{endpoint}\",le=\"{bound}\"}} {count}"
+                );
+            }
+            let _ = writeln!(
+                output,
+                "nishiogi_request_duration_seconds_bucket{{endpoint=\"{endpoint}\",le=\"+Inf\"}} {}\nnishiogi_request_duration_seconds_sum{{endpoint=\"{endpoint}\"}} {}\nnishiogi_request_duration_seconds_count{{endpoint=\"{endpoint}\"}} {}",
+                histogram.count, histogram.sum, histogram.count
+            );
+        }
+
+        output.push_str("# HELP nishiogi_tokens_total Tokens the provider reported using to answer questions, by API key.\n# TYPE nishiogi_tokens_total counter\n");
+        for (key, tokens) in &self.tokens {
+            let _ = writeln!(output, "nishiogi_tokens_total{{key=\"{key}\"}} {tokens}");
+        }
+
+        output.push_str("# HELP nishiogi_cached_tokens_total Prompt tokens served from the provider's prompt cache, by API key.\n# TYPE nishiogi_cached_tokens_total counter\n");
+        for (key, tokens) in &self.cached_tokens {
+            let _ = writeln!(
+                output,
+                "nishiogi_cached_tokens_total{{key=\"{key}\"}} {tokens}"
+            );
+        }
+        output
+    }
+}
+
+/// How the agent answering questions is set up.
+struct AgentSettings {
+    provider: ProviderKind,
+    model: Option<String>,
+    reviewer: Arc<dyn Reviewer>,
+    planner: PlannerConfig,
+    policy: PolicyConfig,
+    instructions: Option<String>,
+    read_only: bool,
+}
+
+/// A repository the server answers questions about.
+struct Repo {
+    /// The repository root.
+    root: PathBuf,
+    /// Whether paths are resolved against `root` rather than the working directory.
+    scoped: bool,
+    /// The agent, once the provider could be reached.
+    agent: AsyncMutex<Option<Agent>>,
+}
+
+/// Answers questions about repositories over HTTP.
+pub struct Server {
+    /// The repositories, by name.
+    repos: BTreeMap<String, Repo>,
+    settings: AgentSettings,
+    tenants: Tenants,
+    metrics: Mutex<Metrics>,
+}
+
+impl Server {
+    /// Creates a server for the repository at `root` and the repositories configured in
+    /// `config`, set up like `nishiogi ask`.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The root of the repository the server is started in
+    /// * `config` - The loaded configuration
+    /// * `instructions` - The project conventions, if any
+    pub fn new(root: PathBuf, config: Config, instructions: Option<String>) -> Self {
+        let repo = |root: PathBuf, scoped: bool| Repo {
+            root,
+            scoped,
+            agent: AsyncMutex::new(None),
+        };
+        let mut repos: BTreeMap<String, Repo> = config
+            .server
+            .repos
+            .into_iter()
+            .map(|(name, root)| (name, repo(root, true)))
+            .collect();
+        repos.insert(DEFAULT_REPO.to_string(), repo(root, false));
+        Self {
+            repos,
+            tenants: Tenants::new(config.server.keys),
+            settings: AgentSettings {
+                provider: config.provider.name,
+                model: config.provider.model,
+                reviewer: config.review.build(),
+                planner: config.planner,
+                policy: config.policy,
+                instructions,
+                read_only: false,
+            },
+            metrics: Mutex::new(Metrics::default()),
+        }
+    }
+
+    /// Lets the agents run only read-only tools if `read_only` is set, whatever the policy
+    /// configuration says.
+    #[must_use]
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.settings.read_only = read_only;
+        self
+    }
+
+    /// Serves requests on `addr` until accepting a connection fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if `addr` cannot be bound or a connection cannot be accepted.
+    pub async fn run(self: Arc<Self>, addr: &str) -> io::Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        eprintln!("Listening on http://{}", listener.local_addr()?);
+        let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+        loop {
+            // Waiting for a permit before accepting leaves further clients in the backlog
+            let permit = Arc::clone(&connections)
+                .acquire_owned()
+                .await
+                .map_err(io::Error::other)?;
+            let (stream, _) = listener.accept().await?;
+            let server = Arc::clone(&self);
+            tokio::spawn(async move {
+                server.handle_connection(stream).await;
+                drop(permit);
+            });
+        }
+    }
+
+    /// Reads one request from `stream`, answers it, and records it in the metrics.
+    async fn handle_connection(&self, mut stream: TcpStream) {
+        let started = Instant::now();
+        let (path, response) = match read_request_within(&mut stream, READ_TIMEOUT).await {
+            Ok(request) => (request.path.clone(), self.route(request).await),
+            Err(response) => (String::new(), response),
+        };
+        self.metrics()
+            .record_request(&path, response.status, started.elapsed());
+        // The client may already be gone; there is nobody to report that to
+        let _ = stream.write_all(&response.to_bytes()).await;
+        let _ = stream.shutdown().await;
+    }
+
+    async fn route(&self, request: Request) -> Response {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/healthz") => Response::text(200, "ok\n"),
+            ("GET", "/readyz") => self.readiness().await,
+            ("GET", "/metrics") => Response {
+                status: 200,
+                content_type: "text/plain; version=0.0.4",
+                body: self.metrics().render(),
+            },
+            ("POST", "/ask") => self.ask(&request).await,
+            (_, path) if ENDPOINTS.contains(&path) => {
+                Response::error(405, format!("Method {} not allowed", request.method))
+            }
+            (_, path) => Response::error(404, format!("Not found: {path}")),
+        }
+    }
+
+    /// Checks that the database of the default repository can be read and the provider
+    /// reached.
+    async fn readiness(&self) -> Response {
+        let Some(repo) = self.repos.get(DEFAULT_REPO) else {
+            return Response::text(503, "no repository\n");
+        };
+        let mut ready = true;
+        let mut report = String::new();
+        match Database::open_for_repo(&repo.root)
+            .and_then(|db| db.meta(&format!("{INDEX_META_PREFIX}updated_at")))
+        {
+            Ok(Some(updated)) => {
+                let _ = writeln!(report, "database: ok, indexes last updated {updated}");
+            }
+            Ok(None) => report.push_str("database: ok, no indexes built yet\n"),
+            Err(err) => {
+                ready = false;
+                let _ = writeln!(report, "database: {err}");
+            }
+        }
+
+        // A busy agent was created, so the provider was reachable
+        match repo.agent.try_lock() {
+            Ok(mut slot) => match self.ensure_agent(DEFAULT_REPO, repo, &mut slot).await {
+                Ok(_) => report.push_str("provider: ok\n"),
+                Err(err) => {
+                    ready = false;
+                    let _ = writeln!(report, "provider: {err}");
+                }
+            },
+            Err(_) => report.push_str("provider: ok, answering a question\n"),
+        }
+        Response::text(if ready { 200 } else { 503 }, report)
+    }
+
+    /// Answers the question in the body of `request`, if its API key allows it.
+    async fn ask(&self, request: &Request) -> Response {
+        let body = match serde_json::from_str::<AskBody>(&request.body) {
+            Ok(body) if !body.question.trim().is_empty() => body,
+            _ => {
+                return Response::error(400, "Expected a JSON body like {\"question\": \"...\"}");
+            }
+        };
+        let name = body.repo.as_deref().unwrap_or(DEFAULT_REPO);
+        let key = match self.tenants.admit(request.header("authorization"), name) {
+            Ok(key) => key,
+            Err(denial) => return Response::error(denial.status(), denial.to_string()),
+        };
+        let Some(repo) = self.repos.get(name) else {
+            return Response::error(404, format!("Unknown repository: {name}"));
+        };
+
+        let mut slot = repo.agent.lock().await;
+        let agent = match self.ensure_agent(name, repo, &mut slot).await {
+            Ok(agent) => agent,
+            Err(err) => return Response::error(503, err.to_string()),
+        };
+        agent.clear_history();
+        let result = agent.process_query(&body.question).await;
+        let tokens = agent.tokens_used();
+        self.tenants.record_tokens(&key, tokens);
+        self.metrics()
+            .record_tokens(&key, tokens, agent.cached_tokens());
+        match result {
+            Ok(answer) => Response::json(
+                200,
+                &AnswerBody {
+                    answer: &answer,
+                    status: AnswerStatus::of(&answer),
+                    unknown: unknown::parse(&answer),
+                    tokens,
+                    latency: agent.latency_report(),
+                },
+            ),
+            Err(err) => Response::error(500, err.to_string()),
+        }
+    }
+
+    /// Returns the agent of `repo` in `slot`, creating it first if the provider was not
+    /// reached yet.
+    async fn ensure_agent<'a>(
+        &self,
+        name: &str,
+        repo: &Repo,
+        slot: &'a mut Option<Agent>,
+    ) -> Result<&'a mut Agent, AgentError> {
+        if slot.is_none() {
+            let settings = &self.settings;
+            let mut agent = Agent::for_provider(settings.provider, settings.model.clone())
+                .await?
+                .with_reviewer(Arc::clone(&settings.reviewer))
+                .with_planner(settings.planner.clone())
+                .with_policy(
+                    Policy::new(settings.policy.clone(), false)
+                        .unattended()
+                        .read_only(settings.read_only),
+                )
+                .with_instructions(settings.instructions.clone())
+                .with_root(&repo.root);
+            if repo.scoped {
+                agent = agent.with_package_scope(name.to_string(), repo.root.clone());
+            }
+            *slot = Some(agent);
+        }
+        slot.as_mut()
+            .ok_or_else(|| AgentError::Other("Agent is not available".to_string()))
+    }
+
+    fn metrics(&self) -> std::sync::MutexGuard<'_, Metrics> {
+        // Counters stay usable even if a handler panicked while holding them
+        self.metrics.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Reads a request from `stream` like [`read_request`], answering `408` if the client takes
+/// longer than `timeout` to send it.
+async fn read_request_within(
+    stream: &mut TcpStream,
+    timeout: Duration,
+) -> Result<Request, Response> {
+    tokio::time::timeout(timeout, read_request(stream))
+        .await
+        .unwrap_or_else(|_| Err(Response::error(408, "Request timeout")))
+}
+
+/// Reads a request from `stream`, or returns the error response to send instead.
+async fn read_request(stream: &mut TcpStream) -> Result<Request, Response> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 8192];
+    let head_end = loop {
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end;
+        }
+        if buffer.len() > MAX_REQUEST_BYTES {
+            return Err(Response::error(413, "Request too large"));
+        }
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return Err(Response::error(400, "Incomplete request")),
+            Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..head_end]).into_owned();
+    let mut request = parse_head(&head).ok_or_else(|| Response::error(400, "Malformed request"))?;
+    let length: usize = request
+        .header("content-length")
+        .map_or(Ok(0), str::parse)
+        .map_err(|_| Response::error(400, "Malformed Content-Length"))?;
+    // The length comes from the client, so the sum must not overflow
+    if length > MAX_REQUEST_BYTES.saturating_sub(head_end + 4) {
+        return Err(Response::error(413, "Request too large"));
+    }
+
+    let mut body = buffer.split_off(head_end + 4);
+    while body.len() < length {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return Err(Response::error(400, "Incomplete request")),
+            Ok(read) => body.extend_from_slice(&chunk[..read]),
+        }
+    }
+    body.truncate(length);
+    request.body =
+        String::from_utf8(body).map_err(|_| Response::error(400, "Body is not UTF-8"))?;
+    Ok(request)
+}
+
+/// Parses the request line and headers; the body is left empty.
+fn parse_head(head: &str) -> Option<Request> {
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?;
+    if !request_line.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    let path = target.split('?').next().unwrap_or(target).to_string();
+
+    let mut headers = Vec::new();
+    for line in lines {
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    Some(Request {
+        method,
+        path,
+        headers,
+        body: String::new(),
+    })
+}
+
+/// Returns the metrics label for requests to `path`.
+fn endpoint_label(path: &str) -> &str {
+    ENDPOINTS
+        .iter()
+        .find(|endpoint| **endpoint == path)
+        .copied()
+        .unwrap_or("other")
+}
+
+/// Returns the reason phrase of an HTTP status code.
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_head() {
+        let request =
+            parse_head("POST /ask?verbose=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 17")
+                .expect("Failed to parse request");
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/ask");
+        assert_eq!(request.header("content-length"), Some("17"));
+        assert_eq!(request.header("host"), Some("localhost"));
+
+        assert!(parse_head("GET /").is_none());
+        assert!(parse_head("GET / HTTP/1.1\r\nbroken header").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind");
+        let addr = listener.local_addr().expect("Failed to get address");
+        let mut client = TcpStream::connect(addr).await.expect("Failed to connect");
+        let (mut stream, _) = listener.accept().await.expect("Failed to accept");
+
+        // A client sending only part of its request is cut off
+        client
+            .write_all(b"GET /healthz HTTP/1.1\r\n")
+            .await
+            .expect("Failed to write");
+        let response = read_request_within(&mut stream, Duration::from_millis(50))
+            .await
+            .expect_err("The request is incomplete");
+        assert_eq!(response.status, 408);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_content_length() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind");
+        let addr = listener.local_addr().expect("Failed to get address");
+        let mut client = TcpStream::connect(addr).await.expect("Failed to connect");
+        let (mut stream, _) = listener.accept().await.expect("Failed to accept");
+
+        for length in [usize::MAX.to_string(), (MAX_REQUEST_BYTES + 1).to_string()] {
+            client
+                .write_all(
+                    format!("POST /ask HTTP/1.1\r\nContent-Length: {length}\r\n\r\n{{}}")
+                        .as_bytes(),
+                )
+                .await
+                .expect("Failed to write");
+            let response = read_request_within(&mut stream, Duration::from_secs(5))
+                .await
+                .expect_err("The request is too large");
+            assert_eq!(response.status, 413);
+        }
+    }
+
+    #[test]
+    fn test_render_metrics() {
+        let mut metrics = Metrics::default();
+        metrics.record_request("/ask", 200, Duration::from_millis(700));
+        metrics.record_request("/ask", 500, Duration::from_secs(3));
+        metrics.record_request("/favicon.ico", 404, Duration::from_millis(1));
+        metrics.record_tokens("payments", 1200, 800);
+
+        let output = metrics.render();
+        assert!(output.contains("nishiogi_requests_total{endpoint=\"/ask\",status=\"200\"} 1\n"));
+        assert!(output.contains("nishiogi_requests_total{endpoint=\"other\",status=\"404\"} 1\n"));
+        assert!(output
+            .contains("nishiogi_request_duration_seconds_bucket{endpoint=\"/ask\",le=\"1\"} 1\n"));
+        assert!(output
+            .contains("nishiogi_request_duration_seconds_bucket{endpoint=\"/ask\",le=\"5\"} 2\n"));
+        assert!(output.contains("nishiogi_request_duration_seconds_count{endpoint=\"/ask\"} 2\n"));
+        assert!(output.contains("nishiogi_tokens_total{key=\"payments\"} 1200\n"));
+        assert!(output.contains("nishiogi_cached_tokens_total{key=\"payments\"} 800\n"));
+    }
+}
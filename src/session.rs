@@ -0,0 +1,959 @@
+//! # Session Store
+//!
+//! This module persists answered questions in the repository's local database (see
+//! [`crate::db`]), one JSON document per session. Each entry carries a provenance record describing
+//! exactly which context produced the answer: the files that were read (with content
+//! hashes), the model, and hashes of every prompt sent to it. This lets teams audit a
+//! recommendation long after it was made.
+//!
+//! When a session is resumed, the recorded hashes are compared against the working tree so
+//! that answers citing files which have since changed can be flagged as stale. The same
+//! check lets a new question be routed to a previously stored answer when a highly similar
+//! question was already answered against the current code.
+//!
+//! The record types are always available with the `native` feature; the database-backed
+//! [`SessionStore`] requires the `sessions` feature.
+//!
+//! Sessions saved by earlier versions as files under `~/.nishiogi/sessions/` are imported
+//! into the database the first time it is opened for their repository.
+//!
+//! Session records carry a format version. Records written by an older version are
+//! upgraded when they are loaded, after their original JSON has been copied to the
+//! `session_backups` table; records written by a newer version are reported as such.
+
+#[cfg(feature = "sessions")]
+use std::error::Error;
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+#[cfg(feature = "sessions")]
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::git::git;
+pub use crate::provenance::{FileProvenance, LineRange};
+#[cfg(feature = "sessions")]
+use crate::{
+    config::{data_dir, repo_root},
+    db::{Database, DbError},
+};
+
+/// A step upgrading a session record by one version.
+type SessionMigration = fn(&mut serde_json::Value);
+
+/// Upgrades of the session format; entry `i` turns version `i + 1` into `i + 2`.
+const SESSION_MIGRATIONS: &[SessionMigration] = &[];
+
+/// Longest first question shown in a session summary, in characters.
+const SUMMARY_QUESTION_CHARS: usize = 60;
+
+/// Current version of the session record format.
+pub const SESSION_VERSION: usize = SESSION_MIGRATIONS.len() + 1;
+
+#[cfg(feature = "sessions")]
+/// Metadata key recording that legacy session files were imported.
+const LEGACY_IMPORTED: &str = "legacy_sessions_imported";
+
+#[cfg(feature = "sessions")]
+/// Errors that can occur while reading or writing sessions.
+#[derive(Debug)]
+pub enum SessionError {
+    /// The session database could not be accessed.
+    Database(DbError),
+    /// No session with the given ID exists.
+    NotFound(String),
+    /// The session was written by a newer version of nishiogi.
+    UnsupportedVersion(String, u64),
+    /// An underlying I/O error occurred.
+    Io(std::io::Error),
+    /// A session file could not be serialized or deserialized.
+    Serde(serde_json::Error),
+}
+
+#[cfg(feature = "sessions")]
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Database(err) => write!(f, "{err}"),
+            SessionError::NotFound(id) => write!(f, "Session not found: {id}"),
+            SessionError::UnsupportedVersion(id, version) => write!(
+                f,
+                "Session {id} uses format version {version}, but this nishiogi supports up to {SESSION_VERSION}; upgrade nishiogi"
+            ),
+            SessionError::Io(err) => write!(f, "I/O error: {err}"),
+            SessionError::Serde(err) => write!(f, "Malformed session file: {err}"),
+        }
+    }
+}
+
+#[cfg(feature = "sessions")]
+impl Error for SessionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SessionError::Database(err) => Some(err),
+            SessionError::Io(err) => Some(err),
+            SessionError::Serde(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "sessions")]
+impl From<std::io::Error> for SessionError {
+    fn from(error: std::io::Error) -> Self {
+        SessionError::Io(error)
+    }
+}
+
+#[cfg(feature = "sessions")]
+impl From<DbError> for SessionError {
+    fn from(error: DbError) -> Self {
+        SessionError::Database(error)
+    }
+}
+
+#[cfg(feature = "sessions")]
+impl From<rusqlite::Error> for SessionError {
+    fn from(error: rusqlite::Error) -> Self {
+        SessionError::Database(DbError::Sqlite(error))
+    }
+}
+
+#[cfg(feature = "sessions")]
+impl From<serde_json::Error> for SessionError {
+    fn from(error: serde_json::Error) -> Self {
+        SessionError::Serde(error)
+    }
+}
+
+/// Describes the context that produced an answer.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Provenance {
+    /// The model that generated the answer.
+    pub model: String,
+    /// Files whose content was included in the answer prompt.
+    pub files: Vec<FileProvenance>,
+    /// SHA-256 of every prompt sent to the model, in order.
+    pub prompt_hashes: Vec<String>,
+    /// Every tool executed while answering, in order.
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCall>,
+    /// The state of the code the answer was produced from, if it was pinned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin: Option<Pin>,
+}
+
+/// The state of the code an answer was produced from, recorded with `ask --pin`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pin {
+    /// The commit checked out, if the question was asked in a git repository.
+    pub commit: Option<String>,
+    /// Whether consulted files had uncommitted changes, so the commit alone does not
+    /// reproduce them.
+    pub dirty: bool,
+    /// SHA-256 over the paths and content hashes of the consulted files.
+    pub snapshot: String,
+}
+
+impl Pin {
+    /// Records the commit checked out in `dir` and a snapshot hash of `files`.
+    pub fn capture(dir: &Path, files: &[FileProvenance]) -> Self {
+        let commit = git(dir, &["rev-parse", "HEAD"])
+            .ok()
+            .map(|commit| commit.trim().to_string());
+        let dirty = commit.is_some() && {
+            let mut args = vec!["status", "--porcelain", "--"];
+            let paths: Vec<String> = files
+                .iter()
+                .map(|file| file.path.to_string_lossy().into_owned())
+                .collect();
+            args.extend(paths.iter().map(String::as_str));
+            git(dir, &args).is_ok_and(|status| !status.trim().is_empty())
+        };
+        Self {
+            commit,
+            dirty,
+            snapshot: snapshot_hash(files),
+        }
+    }
+}
+
+/// Whether an answer still matches the code, as found by `nishiogi verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    /// The commit checked out now, if in a git repository.
+    pub head: Option<String>,
+    /// Commits since the pinned one that changed consulted files, as `<hash> <subject>`.
+    pub commits: Vec<String>,
+    /// Consulted files whose content changed since the answer was produced.
+    pub stale: Vec<StaleFile>,
+}
+
+impl Verification {
+    /// Returns whether every consulted file still has the content the answer was based on.
+    pub fn is_consistent(&self) -> bool {
+        self.stale.is_empty()
+    }
+}
+
+/// Timing and size of a single tool execution.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// The command as planned, e.g. `show_file src/main.rs`.
+    pub command: String,
+    /// Wall-clock time the tool took, in milliseconds.
+    pub duration_ms: u64,
+    /// Size of the tool output in bytes, before truncation.
+    pub bytes: usize,
+    /// Whether the output was cut short before being added to the prompt.
+    pub truncated: bool,
+}
+
+/// A single answered question within a session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionEntry {
+    /// The user's question.
+    pub question: String,
+    /// The final answer.
+    pub answer: String,
+    /// When the answer was produced.
+    pub answered_at: DateTime<Utc>,
+    /// What context produced the answer.
+    pub provenance: Provenance,
+    /// Embedding of the question, used to recognize similar questions later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub question_embedding: Option<Vec<f64>>,
+    /// Whether the user interrupted the agent before the answer was final.
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+/// A persisted session: one or more answered questions about a repository.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRecord {
+    /// Format version of the record.
+    #[serde(default = "legacy_version")]
+    pub version: usize,
+    /// Unique session identifier.
+    pub id: String,
+    /// When the session was created.
+    pub created_at: DateTime<Utc>,
+    /// Directory the questions were asked from; recorded file paths are relative to it.
+    pub working_dir: Option<PathBuf>,
+    /// Answered questions, oldest first.
+    pub entries: Vec<SessionEntry>,
+    /// The session this one was forked from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forked_from: Option<String>,
+}
+
+impl SessionRecord {
+    /// Creates an empty session with a fresh ID.
+    pub fn new(working_dir: Option<PathBuf>) -> Self {
+        let created_at = Utc::now();
+        Self {
+            version: SESSION_VERSION,
+            id: format!(
+                "{}-{}",
+                created_at.format("%Y%m%d%H%M%S"),
+                std::process::id()
+            ),
+            created_at,
+            working_dir,
+            entries: Vec::new(),
+            forked_from: None,
+        }
+    }
+
+    /// Starts a new session continuing from this one, to explore another line of
+    /// questioning without changing it.
+    ///
+    /// The fork has a fresh ID and keeps the first `entries` answers, or all of them if
+    /// `None`.
+    pub fn fork(&self, entries: Option<usize>) -> Self {
+        let mut fork = Self::new(self.working_dir.clone());
+        let kept = entries.unwrap_or(self.entries.len());
+        fork.entries = self.entries.iter().take(kept).cloned().collect();
+        fork.forked_from = Some(self.id.clone());
+        fork
+    }
+
+    /// Summarizes the session in one line for `history list`: its ID, when it was created,
+    /// how many answers it holds, and its first question.
+    pub fn summary(&self) -> String {
+        let question = self
+            .entries
+            .first()
+            .map(|entry| {
+                entry
+                    .question
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .unwrap_or_default();
+        let question = if question.chars().count() > SUMMARY_QUESTION_CHARS {
+            let cut: String = question.chars().take(SUMMARY_QUESTION_CHARS).collect();
+            format!("{cut}...")
+        } else {
+            question
+        };
+        format!(
+            "{}  {}  {:>3} answer(s)  {question}",
+            self.id,
+            self.created_at.format("%Y-%m-%d %H:%M"),
+            self.entries.len()
+        )
+    }
+}
+
+/// Version of records written before the format was versioned.
+fn legacy_version() -> usize {
+    1
+}
+
+impl FileProvenance {
+    /// Checks whether the file changed since it was read.
+    ///
+    /// Relative paths are resolved against `base`. Returns `None` when the file still has
+    /// the recorded content.
+    pub fn staleness(&self, base: Option<&Path>) -> Option<Staleness> {
+        let path = match base {
+            Some(base) => base.join(&self.path),
+            None => self.path.clone(),
+        };
+        if !path.is_file() {
+            return Some(Staleness::Deleted);
+        }
+        match hash_file(&path) {
+            Ok(hash) if hash == self.sha256 => None,
+            _ => Some(Staleness::Modified),
+        }
+    }
+}
+
+/// How a recorded file differs from the working tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Staleness {
+    /// The file content changed since it was read.
+    Modified,
+    /// The file no longer exists.
+    Deleted,
+}
+
+impl fmt::Display for Staleness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Staleness::Modified => write!(f, "modified"),
+            Staleness::Deleted => write!(f, "deleted"),
+        }
+    }
+}
+
+/// A file cited by a session that no longer matches the working tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleFile {
+    /// Path of the file as recorded in the session.
+    pub path: PathBuf,
+    /// How the file changed.
+    pub status: Staleness,
+}
+
+impl SessionEntry {
+    /// Re-checks the answer against the code in `base` (or the working directory).
+    ///
+    /// The content hashes of the consulted files decide whether the answer is still
+    /// consistent. For pinned answers, the commits that changed those files since the
+    /// pinned commit are listed too.
+    pub fn verify(&self, base: Option<&Path>) -> Verification {
+        let dir = base.unwrap_or(Path::new("."));
+        let head = git(dir, &["rev-parse", "HEAD"])
+            .ok()
+            .map(|head| head.trim().to_string());
+        let mut commits = Vec::new();
+        if let Some(Pin {
+            commit: Some(pinned),
+            ..
+        }) = &self.provenance.pin
+            && head.as_ref().is_some_and(|head| head != pinned)
+        {
+            let range = format!("{pinned}..HEAD");
+            let mut args = vec!["log", "--format=%h %s", range.as_str(), "--"];
+            let paths: Vec<String> = self
+                .provenance
+                .files
+                .iter()
+                .map(|file| file.path.to_string_lossy().into_owned())
+                .collect();
+            args.extend(paths.iter().map(String::as_str));
+            if let Ok(log) = git(dir, &args) {
+                commits = log.lines().map(str::to_string).collect();
+            }
+        }
+        Verification {
+            head,
+            commits,
+            stale: self.stale_files(base),
+        }
+    }
+
+    /// Returns the files cited by this answer that changed since it was produced.
+    pub fn stale_files(&self, base: Option<&Path>) -> Vec<StaleFile> {
+        let mut stale: Vec<StaleFile> = Vec::new();
+        for file in &self.provenance.files {
+            if stale.iter().any(|s| s.path == file.path) {
+                continue;
+            }
+            if let Some(status) = file.staleness(base) {
+                stale.push(StaleFile {
+                    path: file.path.clone(),
+                    status,
+                });
+            }
+        }
+        stale
+    }
+}
+
+impl SessionRecord {
+    /// Returns every file cited anywhere in the session that changed since it was read.
+    pub fn stale_files(&self) -> Vec<StaleFile> {
+        let mut stale: Vec<StaleFile> = Vec::new();
+        for entry in &self.entries {
+            for file in entry.stale_files(self.working_dir.as_deref()) {
+                if !stale.iter().any(|s| s.path == file.path) {
+                    stale.push(file);
+                }
+            }
+        }
+        stale
+    }
+}
+
+#[cfg(feature = "sessions")]
+/// Reads and writes sessions in a database.
+pub struct SessionStore {
+    db: Database,
+}
+
+#[cfg(feature = "sessions")]
+impl SessionStore {
+    /// Creates a store backed by `db`.
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Opens the store of the repository containing the current directory.
+    ///
+    /// Legacy session files for this repository are imported on first use.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SessionError` if the database cannot be opened or legacy sessions cannot
+    /// be imported.
+    pub fn open_default() -> Result<Self, SessionError> {
+        let root = repo_root();
+        let store = Self::new(Database::open_for_repo(&root)?);
+        if store.db.meta(LEGACY_IMPORTED)?.is_none() {
+            if let Some(dir) = data_dir() {
+                store.import_legacy(&dir.join("sessions"), &root)?;
+            }
+            store.db.set_meta(LEGACY_IMPORTED, "true")?;
+        }
+        Ok(store)
+    }
+
+    /// Returns the database backing the store.
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    /// Saves `record`, replacing any previous version.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SessionError` if the record cannot be serialized or written.
+    pub fn save(&self, record: &SessionRecord) -> Result<(), SessionError> {
+        self.db.connection().execute(
+            "INSERT OR REPLACE INTO sessions (id, created_at, data) VALUES (?1, ?2, ?3)",
+            params![
+                record.id,
+                record.created_at.to_rfc3339(),
+                serde_json::to_string(record)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Loads the session with the given ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SessionError::NotFound` if no such session exists, or another
+    /// `SessionError` if it cannot be read or parsed.
+    pub fn load(&self, id: &str) -> Result<SessionRecord, SessionError> {
+        let data: Option<String> = self
+            .db
+            .connection()
+            .query_row(
+                "SELECT data FROM sessions WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let data = data.ok_or_else(|| SessionError::NotFound(id.to_string()))?;
+        self.decode(&data, SESSION_MIGRATIONS)
+    }
+
+    /// Loads every session in the store, oldest first; unparsable sessions are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SessionError` if the sessions cannot be read.
+    pub fn list(&self) -> Result<Vec<SessionRecord>, SessionError> {
+        let conn = self.db.connection();
+        let mut statement = conn.prepare("SELECT data FROM sessions ORDER BY created_at")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+
+        let mut records = Vec::new();
+        for data in rows {
+            if let Ok(record) = self.decode(&data?, SESSION_MIGRATIONS) {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Parses a stored record, upgrading it with `migrations` if it is outdated.
+    ///
+    /// Upgraded records are saved after their original JSON has been backed up.
+    fn decode(
+        &self,
+        data: &str,
+        migrations: &[SessionMigration],
+    ) -> Result<SessionRecord, SessionError> {
+        let latest = migrations.len() + 1;
+        let mut value: serde_json::Value = serde_json::from_str(data)?;
+        let version = value
+            .get("version")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(1);
+        let current = usize::try_from(version).unwrap_or(usize::MAX);
+        if current > latest {
+            let id = value
+                .get("id")
+                .and_then(serde_json::Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            return Err(SessionError::UnsupportedVersion(id, version));
+        }
+        if current == latest {
+            return Ok(serde_json::from_value(value)?);
+        }
+
+        for migration in &migrations[current - 1..] {
+            migration(&mut value);
+        }
+        value["version"] = latest.into();
+        let record: SessionRecord = serde_json::from_value(value)?;
+        self.db.connection().execute(
+            "INSERT INTO session_backups (id, version, data, backed_up_at) VALUES (?1, ?2, ?3, ?4)",
+            params![record.id, version, data, Utc::now().to_rfc3339()],
+        )?;
+        self.save(&record)?;
+        Ok(record)
+    }
+
+    /// Imports the session files in `dir` that were recorded inside `root`.
+    ///
+    /// Returns the number of imported sessions; unreadable files are skipped.
+    fn import_legacy(&self, dir: &Path, root: &Path) -> Result<usize, SessionError> {
+        if !dir.is_dir() {
+            return Ok(0);
+        }
+
+        let mut imported = 0;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let Ok(record) = self.decode(&content, SESSION_MIGRATIONS) else {
+                continue;
+            };
+            if record
+                .working_dir
+                .as_deref()
+                .is_some_and(|dir| dir.starts_with(root))
+            {
+                self.save(&record)?;
+                imported += 1;
+            }
+        }
+        Ok(imported)
+    }
+}
+
+/// A stored answer to a question similar to the one being asked.
+#[derive(Debug, Clone)]
+pub struct ReusableAnswer {
+    /// The session the answer was found in.
+    pub session_id: String,
+    /// The stored question and answer.
+    pub entry: SessionEntry,
+    /// Cosine similarity between the stored and the new question.
+    pub similarity: f64,
+}
+
+/// Finds the stored answer whose question is most similar to `embedding`.
+///
+/// Only complete answers given in `working_dir` whose cited files are unchanged are
+/// considered, and only if their similarity is at least `threshold`.
+pub fn find_reusable_answer(
+    records: &[SessionRecord],
+    working_dir: &Path,
+    embedding: &[f64],
+    threshold: f64,
+) -> Option<ReusableAnswer> {
+    let mut best: Option<ReusableAnswer> = None;
+    for record in records {
+        if record.working_dir.as_deref() != Some(working_dir) {
+            continue;
+        }
+        for entry in record.entries.iter().filter(|entry| !entry.truncated) {
+            let Some(stored) = &entry.question_embedding else {
+                continue;
+            };
+            let similarity = cosine_similarity(stored, embedding);
+            if similarity < threshold || best.as_ref().is_some_and(|b| b.similarity >= similarity) {
+                continue;
+            }
+            if !entry.stale_files(Some(working_dir)).is_empty() {
+                continue;
+            }
+            best = Some(ReusableAnswer {
+                session_id: record.id.clone(),
+                entry: entry.clone(),
+                similarity,
+            });
+        }
+    }
+    best
+}
+
+/// Returns the cosine similarity of two vectors, or 0.0 if they are incomparable.
+pub fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+/// Returns a hash identifying the content of `files`, independent of their order.
+pub fn snapshot_hash(files: &[FileProvenance]) -> String {
+    let mut lines: Vec<String> = files
+        .iter()
+        .map(|file| format!("{}\0{}\n", file.path.display(), file.sha256))
+        .collect();
+    lines.sort();
+    lines.dedup();
+    sha256_hex(lines.concat().as_bytes())
+}
+
+/// Returns the hex-encoded SHA-256 digest of `data`.
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Returns the hex-encoded SHA-256 digest of the file at `path`.
+///
+/// # Errors
+///
+/// Returns an I/O error if the file cannot be read.
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    Ok(sha256_hex(&fs::read(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[cfg(feature = "sessions")]
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let store = SessionStore::new(Database::open_in_memory().expect("Failed to open database"));
+
+        let mut record = SessionRecord::new(Some(PathBuf::from("/repo")));
+        record.entries.push(SessionEntry {
+            question: "Where is main?".to_string(),
+            answer: "src/main.rs".to_string(),
+            answered_at: Utc::now(),
+            provenance: Provenance {
+                model: "gpt-4".to_string(),
+                files: vec![FileProvenance {
+                    path: PathBuf::from("src/main.rs"),
+                    range: None,
+                    sha256: sha256_hex(b"fn main() {}"),
+                }],
+                prompt_hashes: vec![sha256_hex(b"prompt")],
+                tool_calls: vec![ToolCall {
+                    command: "show_file src/main.rs".to_string(),
+                    duration_ms: 3,
+                    bytes: 12,
+                    truncated: false,
+                }],
+                pin: None,
+            },
+            question_embedding: None,
+            truncated: false,
+        });
+
+        store.save(&record).expect("Failed to save session");
+        let loaded = store.load(&record.id).expect("Failed to load session");
+        assert_eq!(loaded.id, record.id);
+        assert_eq!(loaded.entries.len(), 1);
+        assert_eq!(
+            loaded.entries[0].provenance.files,
+            record.entries[0].provenance.files
+        );
+        assert_eq!(
+            loaded.entries[0].provenance.tool_calls,
+            record.entries[0].provenance.tool_calls
+        );
+        let summary = loaded.summary();
+        assert!(summary.starts_with(&record.id));
+        assert!(summary.ends_with("  1 answer(s)  Where is main?"));
+    }
+
+    #[cfg(feature = "sessions")]
+    #[test]
+    fn test_load_missing_session() {
+        let store = SessionStore::new(Database::open_in_memory().expect("Failed to open database"));
+        assert!(matches!(
+            store.load("nope"),
+            Err(SessionError::NotFound(id)) if id == "nope"
+        ));
+    }
+
+    #[cfg(feature = "sessions")]
+    #[test]
+    fn test_import_legacy_sessions() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let legacy = temp_dir.path().join("sessions");
+        fs::create_dir(&legacy).expect("Failed to create directory");
+        let repo = temp_dir.path().join("repo");
+
+        let ours = SessionRecord::new(Some(repo.join("src")));
+        let mut theirs = SessionRecord::new(Some(temp_dir.path().join("other")));
+        theirs.id = format!("{}-other", theirs.id);
+        for record in [&ours, &theirs] {
+            fs::write(
+                legacy.join(format!("{}.json", record.id)),
+                serde_json::to_string(record).expect("Failed to serialize"),
+            )
+            .expect("Failed to write session");
+        }
+        fs::write(legacy.join("notes.txt"), "not a session").expect("Failed to write");
+
+        let store = SessionStore::new(Database::open_in_memory().expect("Failed to open database"));
+        let imported = store
+            .import_legacy(&legacy, &repo)
+            .expect("Failed to import sessions");
+        assert_eq!(imported, 1);
+        assert!(store.load(&ours.id).is_ok());
+        assert!(store.load(&theirs.id).is_err());
+    }
+
+    #[cfg(feature = "sessions")]
+    #[test]
+    fn test_outdated_session_is_upgraded_with_backup() {
+        fn rename_cwd(value: &mut serde_json::Value) {
+            if let Some(record) = value.as_object_mut()
+                && let Some(dir) = record.remove("cwd")
+            {
+                record.insert("working_dir".to_string(), dir);
+            }
+        }
+
+        let store = SessionStore::new(Database::open_in_memory().expect("Failed to open database"));
+        let old =
+            r#"{"id": "old", "created_at": "2024-01-01T00:00:00Z", "cwd": "/repo", "entries": []}"#;
+        let record = store
+            .decode(old, &[rename_cwd])
+            .expect("Failed to upgrade session");
+        assert_eq!(record.version, 2);
+        assert_eq!(record.working_dir, Some(PathBuf::from("/repo")));
+
+        let backup: String = store
+            .db
+            .connection()
+            .query_row(
+                "SELECT data FROM session_backups WHERE id = 'old'",
+                [],
+                |row| row.get(0),
+            )
+            .expect("Missing backup");
+        assert_eq!(backup, old);
+        let saved: String = store
+            .db
+            .connection()
+            .query_row("SELECT data FROM sessions WHERE id = 'old'", [], |row| {
+                row.get(0)
+            })
+            .expect("Upgraded session was not saved");
+        assert!(saved.contains("\"working_dir\":\"/repo\""));
+    }
+
+    #[cfg(feature = "sessions")]
+    #[test]
+    fn test_newer_session_is_rejected() {
+        let store = SessionStore::new(Database::open_in_memory().expect("Failed to open database"));
+        let future = r#"{"version": 99, "id": "new", "created_at": "2024-01-01T00:00:00Z", "working_dir": null, "entries": []}"#;
+        assert!(matches!(
+            store.decode(future, SESSION_MIGRATIONS),
+            Err(SessionError::UnsupportedVersion(id, 99)) if id == "new"
+        ));
+    }
+
+    #[test]
+    fn test_stale_files() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        fs::write(temp_dir.path().join("kept.rs"), "fn kept() {}").expect("Failed to write");
+        fs::write(temp_dir.path().join("edited.rs"), "fn old() {}").expect("Failed to write");
+
+        let provenance = |name: &str, content: &[u8]| FileProvenance {
+            path: PathBuf::from(name),
+            range: None,
+            sha256: sha256_hex(content),
+        };
+        let mut record = SessionRecord::new(Some(temp_dir.path().to_path_buf()));
+        record.entries.push(SessionEntry {
+            question: "q".to_string(),
+            answer: "a".to_string(),
+            answered_at: Utc::now(),
+            provenance: Provenance {
+                model: "gpt-4".to_string(),
+                files: vec![
+                    provenance("kept.rs", b"fn kept() {}"),
+                    provenance("edited.rs", b"fn old() {}"),
+                    provenance("gone.rs", b"fn gone() {}"),
+                ],
+                prompt_hashes: Vec::new(),
+                tool_calls: Vec::new(),
+                pin: None,
+            },
+            question_embedding: None,
+            truncated: false,
+        });
+        assert_eq!(record.stale_files().len(), 1);
+
+        fs::write(temp_dir.path().join("edited.rs"), "fn new() {}").expect("Failed to write");
+        let stale = record.stale_files();
+        assert_eq!(
+            stale,
+            vec![
+                StaleFile {
+                    path: PathBuf::from("edited.rs"),
+                    status: Staleness::Modified,
+                },
+                StaleFile {
+                    path: PathBuf::from("gone.rs"),
+                    status: Staleness::Deleted,
+                },
+            ]
+        );
+
+        let entry = &record.entries[0];
+        let pin = Pin::capture(temp_dir.path(), &entry.provenance.files);
+        assert_eq!(pin.commit, None);
+        let mut files = entry.provenance.files.clone();
+        files.reverse();
+        assert_eq!(snapshot_hash(&files), pin.snapshot);
+        let verification = entry.verify(Some(temp_dir.path()));
+        assert!(!verification.is_consistent());
+        assert_eq!(verification.stale, stale);
+        assert!(verification.commits.is_empty());
+    }
+
+    #[cfg(feature = "sessions")]
+    #[test]
+    fn test_find_reusable_answer() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let store = SessionStore::new(Database::open_in_memory().expect("Failed to open database"));
+        fs::write(temp_dir.path().join("main.rs"), "fn main() {}").expect("Failed to write");
+
+        let entry = |question: &str, embedding: Vec<f64>, hash: &[u8]| SessionEntry {
+            question: question.to_string(),
+            answer: format!("answer to {question}"),
+            answered_at: Utc::now(),
+            provenance: Provenance {
+                model: "gpt-4".to_string(),
+                files: vec![FileProvenance {
+                    path: PathBuf::from("main.rs"),
+                    range: None,
+                    sha256: sha256_hex(hash),
+                }],
+                prompt_hashes: Vec::new(),
+                tool_calls: Vec::new(),
+                pin: None,
+            },
+            question_embedding: Some(embedding),
+            truncated: false,
+        };
+        let mut record = SessionRecord::new(Some(temp_dir.path().to_path_buf()));
+        record
+            .entries
+            .push(entry("fresh", vec![1.0, 0.1], b"fn main() {}"));
+        record
+            .entries
+            .push(entry("stale", vec![1.0, 0.0], b"fn old() {}"));
+        record
+            .entries
+            .push(entry("unrelated", vec![0.0, 1.0], b"fn main() {}"));
+        record.entries.push(SessionEntry {
+            truncated: true,
+            ..entry("interrupted", vec![1.0, 0.0], b"fn main() {}")
+        });
+        store.save(&record).expect("Failed to save session");
+
+        let records = store.list().expect("Failed to list sessions");
+        let found = find_reusable_answer(&records, temp_dir.path(), &[1.0, 0.0], 0.9)
+            .expect("Expected a reusable answer");
+        assert_eq!(found.entry.question, "fresh");
+        assert_eq!(found.session_id, record.id);
+
+        assert!(
+            find_reusable_answer(&records, Path::new("/elsewhere"), &[1.0, 0.0], 0.9).is_none()
+        );
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert!((cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-9);
+        assert!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]).abs() < 1e-9);
+        assert!(cosine_similarity(&[1.0], &[1.0, 0.0]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,363 @@
+//! # First-Run Setup
+//!
+//! This module implements the interactive setup offered when nishiogi runs without any
+//! configuration file, and by `nishiogi setup`. It walks the user through choosing a
+//! provider, authenticating, picking a default model, and deciding what is kept on disk,
+//! then writes the user configuration file.
+//!
+//! GitHub Copilot is currently the only provider. When no Copilot credentials are found,
+//! the user can paste a GitHub OAuth token, which is stored where the Copilot editor
+//! plugins keep theirs (`github-copilot/hosts.json` in the configuration directory).
+//! [`login`] performs just this step and backs `nishiogi auth login`.
+
+use std::{
+    error::Error,
+    fmt, fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use serde_json::{json, Value};
+use toml::Table;
+
+use crate::{
+    agent::DEFAULT_MODEL,
+    config::{user_config_path, CONFIG_VERSION},
+    github_copilot_client::{get_config_path, get_github_token, CopilotClient},
+};
+
+/// Providers that can answer questions, as shown to the user.
+const PROVIDERS: [&str; 1] = ["GitHub Copilot"];
+
+/// Errors that end the setup before a configuration file is written.
+#[derive(Debug)]
+pub enum SetupError {
+    /// Reading an answer or writing a prompt failed.
+    Io(io::Error),
+    /// The user declined to continue.
+    Aborted,
+    /// The credentials were missing or rejected.
+    Auth(String),
+    /// The configuration directory could not be determined.
+    NoConfigDir,
+    /// A file could not be written.
+    Write(PathBuf, io::Error),
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetupError::Io(err) => write!(f, "Failed to read input: {err}"),
+            SetupError::Aborted => write!(f, "Setup aborted"),
+            SetupError::Auth(msg) => write!(f, "Authentication failed: {msg}"),
+            SetupError::NoConfigDir => {
+                write!(f, "Could not determine the configuration directory")
+            }
+            SetupError::Write(path, err) => {
+                write!(f, "Failed to write {}: {err}", path.display())
+            }
+        }
+    }
+}
+
+impl Error for SetupError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SetupError::Io(err) | SetupError::Write(_, err) => Some(err),
+            SetupError::Aborted | SetupError::Auth(_) | SetupError::NoConfigDir => None,
+        }
+    }
+}
+
+impl From<io::Error> for SetupError {
+    fn from(err: io::Error) -> Self {
+        SetupError::Io(err)
+    }
+}
+
+/// The settings chosen during setup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetupChoices {
+    /// The default model.
+    pub model: String,
+    /// Whether questions and answers are saved as sessions.
+    pub save_sessions: bool,
+    /// Whether cached answers to similar questions are offered.
+    pub reuse_answers: bool,
+}
+
+impl SetupChoices {
+    /// Renders the choices as a configuration file.
+    pub fn render(&self) -> String {
+        let mut provider = Table::new();
+        provider.insert("model".to_string(), self.model.clone().into());
+        let mut privacy = Table::new();
+        privacy.insert("save_sessions".to_string(), self.save_sessions.into());
+        privacy.insert("reuse_answers".to_string(), self.reuse_answers.into());
+
+        let mut table = Table::new();
+        table.insert(
+            "version".to_string(),
+            i64::try_from(CONFIG_VERSION).unwrap_or(i64::MAX).into(),
+        );
+        table.insert("provider".to_string(), provider.into());
+        table.insert("privacy".to_string(), privacy.into());
+        format!("# Written by `nishiogi setup`; run it again to change these settings.\n{table}")
+    }
+}
+
+/// Asks questions on `output` and reads the answers from `input`.
+pub struct Prompter<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Prompter<R, W> {
+    /// Creates a prompter reading from `input` and writing to `output`.
+    pub fn new(input: R, output: W) -> Self {
+        Self { input, output }
+    }
+
+    /// Writes a line of information.
+    pub fn say(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.output, "{text}")
+    }
+
+    /// Asks for free text; an empty answer returns `default`.
+    pub fn ask(&mut self, question: &str, default: &str) -> Result<String, SetupError> {
+        if default.is_empty() {
+            write!(self.output, "{question}: ")?;
+        } else {
+            write!(self.output, "{question} [{default}]: ")?;
+        }
+        self.output.flush()?;
+
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Err(SetupError::Aborted);
+        }
+        let answer = line.trim();
+        Ok(if answer.is_empty() {
+            default.to_string()
+        } else {
+            answer.to_string()
+        })
+    }
+
+    /// Asks a yes/no question, re-asking until the answer is understood.
+    pub fn confirm(&mut self, question: &str, default: bool) -> Result<bool, SetupError> {
+        let hint = if default { "Y/n" } else { "y/N" };
+        loop {
+            write!(self.output, "{question} [{hint}] ")?;
+            self.output.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Err(SetupError::Aborted);
+            }
+            match line.trim().to_lowercase().as_str() {
+                "" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => self.say("Please answer y or n.")?,
+            }
+        }
+    }
+
+    /// Lets the user pick one of `options` by number; returns its index.
+    pub fn choose(
+        &mut self,
+        question: &str,
+        options: &[&str],
+        default: usize,
+    ) -> Result<usize, SetupError> {
+        self.say(question)?;
+        for (index, option) in options.iter().enumerate() {
+            self.say(&format!("  {}) {option}", index + 1))?;
+        }
+        loop {
+            let answer = self.ask("Choice", &(default + 1).to_string())?;
+            match answer.parse::<usize>() {
+                Ok(choice) if (1..=options.len()).contains(&choice) => return Ok(choice - 1),
+                _ => self.say(&format!(
+                    "Please enter a number between 1 and {}.",
+                    options.len()
+                ))?,
+            }
+        }
+    }
+}
+
+/// Runs the interactive setup and writes the user configuration file.
+///
+/// Returns the path of the written file.
+///
+/// # Errors
+///
+/// Returns a `SetupError` if the user aborts, authentication fails, or the configuration
+/// file cannot be written.
+pub async fn run_setup<R: BufRead, W: Write>(
+    prompter: &mut Prompter<R, W>,
+) -> Result<PathBuf, SetupError> {
+    let path = user_config_path().ok_or(SetupError::NoConfigDir)?;
+    if path.exists()
+        && !prompter.confirm(
+            &format!("{} already exists. Overwrite it?", path.display()),
+            false,
+        )?
+    {
+        return Err(SetupError::Aborted);
+    }
+
+    prompter.choose("Which provider should answer questions?", &PROVIDERS, 0)?;
+
+    let client = login(prompter, true).await?;
+
+    let mut models: Vec<&str> = Vec::new();
+    for model in client.models() {
+        if !models.contains(&model.id.as_str()) {
+            models.push(&model.id);
+        }
+    }
+    let model = if models.is_empty() {
+        prompter.ask("Default model", DEFAULT_MODEL)?
+    } else {
+        let default = models
+            .iter()
+            .position(|&id| id == DEFAULT_MODEL)
+            .unwrap_or(0);
+        let index = prompter.choose("Which model should be used by default?", &models, default)?;
+        models[index].to_string()
+    };
+
+    let choices = SetupChoices {
+        model,
+        save_sessions: prompter.confirm(
+            "Save questions and answers in .nishiogi/ so sessions can be resumed?",
+            true,
+        )?,
+        reuse_answers: prompter.confirm("Offer saved answers to similar questions?", true)?,
+    };
+    write_config(&path, &choices.render())?;
+    Ok(path)
+}
+
+/// Authenticates with GitHub Copilot and returns a client for the account.
+///
+/// With `reuse_existing`, credentials already stored by an editor plugin are used when
+/// present; otherwise the user is asked for a token, which is stored once it is accepted.
+///
+/// # Errors
+///
+/// Returns `SetupError::Aborted` if no token is entered, and `SetupError::Auth` if the
+/// token is rejected.
+pub async fn login<R: BufRead, W: Write>(
+    prompter: &mut Prompter<R, W>,
+    reuse_existing: bool,
+) -> Result<CopilotClient, SetupError> {
+    let existing = get_github_token().ok();
+    let (token, entered) = match existing.filter(|_| reuse_existing) {
+        Some(token) => {
+            prompter.say("Found existing GitHub Copilot credentials.")?;
+            (token, false)
+        }
+        None => {
+            if reuse_existing {
+                prompter.say("No GitHub Copilot credentials were found.")?;
+            }
+            prompter.say(
+                "Sign in to Copilot in your editor, or paste a GitHub OAuth token with Copilot access.",
+            )?;
+            let token = prompter.ask("GitHub token (leave empty to abort)", "")?;
+            if token.is_empty() {
+                return Err(SetupError::Aborted);
+            }
+            (token, true)
+        }
+    };
+
+    prompter.say("Checking credentials...")?;
+    let client = CopilotClient::new_with_models(token.clone(), "1.0.0".to_string())
+        .await
+        .map_err(|e| SetupError::Auth(e.to_string()))?;
+    if entered {
+        let hosts = store_github_token(&token)?;
+        prompter.say(&format!("Saved the token to {}", hosts.display()))?;
+    }
+    Ok(client)
+}
+
+/// Writes `content` to `path`, creating its directory.
+fn write_config(path: &Path, content: &str) -> Result<(), SetupError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| SetupError::Write(dir.to_path_buf(), e))?;
+    }
+    fs::write(path, content).map_err(|e| SetupError::Write(path.to_path_buf(), e))
+}
+
+/// Stores `token` in the Copilot hosts file, keeping the other hosts it lists.
+fn store_github_token(token: &str) -> Result<PathBuf, SetupError> {
+    let config_dir = get_config_path().map_err(|_| SetupError::NoConfigDir)?;
+    let path = Path::new(&config_dir)
+        .join("github-copilot")
+        .join("hosts.json");
+    let mut hosts = fs::read_to_string(&path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<Value>(&content).ok())
+        .filter(Value::is_object)
+        .unwrap_or_else(|| json!({}));
+    hosts["github.com"] = json!({ "oauth_token": token });
+    write_config(&path, &format!("{hosts:#}\n"))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_rendered_config_loads() {
+        let choices = SetupChoices {
+            model: "gpt-4o".to_string(),
+            save_sessions: false,
+            reuse_answers: true,
+        };
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("config.toml");
+        fs::write(&path, choices.render()).expect("Failed to write config");
+
+        let config = Config::load_from(&[path]).expect("Failed to load config");
+        assert_eq!(config.provider.model.as_deref(), Some("gpt-4o"));
+        assert!(!config.privacy.save_sessions);
+        assert!(config.privacy.reuse_answers);
+    }
+
+    #[test]
+    fn test_prompts_use_defaults_and_retry() {
+        let input = Cursor::new("\nmaybe\nn\n7\n2\n");
+        let mut output = Vec::new();
+        let mut prompter = Prompter::new(input, &mut output);
+
+        assert!(prompter.confirm("Save?", true).expect("Failed to confirm"));
+        assert!(!prompter.confirm("Reuse?", true).expect("Failed to confirm"));
+        assert_eq!(
+            prompter
+                .choose("Model?", &["gpt-4", "gpt-4o"], 0)
+                .expect("Failed to choose"),
+            1
+        );
+        assert!(matches!(
+            prompter.ask("Token", ""),
+            Err(SetupError::Aborted)
+        ));
+
+        let output = String::from_utf8(output).expect("Output is UTF-8");
+        assert!(output.contains("Please answer y or n."));
+        assert!(output.contains("  2) gpt-4o"));
+        assert!(output.contains("Please enter a number between 1 and 2."));
+    }
+}
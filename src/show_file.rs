@@ -3,9 +3,15 @@
 //! This module provides functionality to read the contents of a file specified by its path,
 //! returning the content as a string. It uses a dedicated error enum, `FileReadError`,
 //! to clearly represent possible failure cases in a manner that facilitates pattern matching.
+//!
+//! The `show_file` command also accepts a line range, as in `show_file src/main.rs:100-180`,
+//! so the model can read part of a large file; [`split_range`] reads the argument like
+//! `--context file=<path>:<start>-<end>` and [`number_lines`] renders the slice.
 
 use std::{error::Error, fmt, fs, path::Path};
 
+use crate::{overrides::ContextOverride, provenance::LineRange};
+
 /// Represents errors that can occur while reading a file.
 ///
 /// This enum encapsulates various error conditions encountered when attempting
@@ -64,6 +70,43 @@ pub fn read_file_content(path: &Path) -> Result<String, FileReadError> {
     fs::read_to_string(path).map_err(FileReadError::Io)
 }
 
+/// Splits the argument of `show_file` into the path and the line range, if it ends with one.
+///
+/// # Errors
+///
+/// Returns a message naming the range if it is not of the form `<start>-<end>` with
+/// `1 <= start <= end`.
+pub fn split_range(arg: &str) -> Result<(&str, Option<LineRange>), String> {
+    let target = ContextOverride::file(arg).map_err(|err| err.to_string())?;
+    match target.range {
+        // The path is the argument up to its last colon
+        Some(range) => Ok((&arg[..arg.rfind(':').unwrap_or(arg.len())], Some(range))),
+        None => Ok((arg, None)),
+    }
+}
+
+/// Returns whether `command` is a `show_file` of a line range, whose output is already
+/// short and numbered.
+pub fn reads_range(command: &str) -> bool {
+    command
+        .strip_prefix("show_file ")
+        .is_some_and(|arg| matches!(split_range(arg), Ok((_, Some(_)))))
+}
+
+/// Returns the lines of `content` in `range`, each prefixed with its line number.
+///
+/// The range is clamped to the end of the file; a range starting past it gives an empty
+/// string.
+pub fn number_lines(content: &str, range: LineRange) -> String {
+    content
+        .lines()
+        .enumerate()
+        .skip(range.start - 1)
+        .take(range.end + 1 - range.start)
+        .map(|(index, line)| format!("{:>5} | {line}\n", index + 1))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use std::{fs::File, io::Write};
@@ -97,4 +140,35 @@ mod tests {
         let result = read_file_content(temp_dir.path());
         assert!(matches!(result, Err(FileReadError::IsDirectory)));
     }
+
+    #[test]
+    fn test_line_ranges() {
+        assert_eq!(
+            split_range("src/main.rs:100-180"),
+            Ok((
+                "src/main.rs",
+                Some(LineRange {
+                    start: 100,
+                    end: 180
+                })
+            ))
+        );
+        assert_eq!(split_range("src/main.rs"), Ok(("src/main.rs", None)));
+        assert_eq!(split_range("C:main.rs"), Ok(("C:main.rs", None)));
+        assert!(split_range("src/main.rs:9-2").is_err());
+        assert!(reads_range("show_file src/main.rs:1-20"));
+        assert!(!reads_range("show_file src/main.rs"));
+        assert!(!reads_range("grep main src/main.rs:1-20"));
+
+        let content = "one\ntwo\nthree\nfour\n";
+        assert_eq!(
+            number_lines(content, LineRange { start: 2, end: 3 }),
+            "    2 | two\n    3 | three\n"
+        );
+        assert_eq!(
+            number_lines(content, LineRange { start: 4, end: 10 }),
+            "    4 | four\n"
+        );
+        assert_eq!(number_lines(content, LineRange { start: 7, end: 8 }), "");
+    }
 }
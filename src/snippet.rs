@@ -0,0 +1,206 @@
+//! # Rust Snippets
+//!
+//! This module implements the `run_rust` tool, which compiles and runs a small
+//! self-contained Rust program so the agent can check a claim about behavior ("does
+//! `trim_matches` strip both ends?") before asserting it. The snippet is built with `rustc`
+//! in a fresh temporary directory and runs in the sandbox of the `run` tool, so it cannot
+//! reach the network and can only write inside that directory. Compiling and running are
+//! each time-limited, and the process is killed once it runs over.
+//!
+//! Only the standard library is available, since dependencies would have to be
+//! downloaded. A snippet without a `main` function is wrapped in one.
+//!
+//! The tool is opt-in: the policy denies it unless `[policy.tools]` configures `run_rust`.
+
+use std::{
+    env,
+    fmt::Write,
+    fs, io,
+    path::{Path, PathBuf},
+    process::{self, Command, ExitStatus, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::sandbox::{wait_within, Sandbox};
+
+/// Time allowed for compiling a snippet.
+const COMPILE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time allowed for running a compiled snippet.
+const RUN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum bytes kept of each output stream.
+const MAX_OUTPUT_BYTES: usize = 4000;
+
+/// Counter distinguishing the directories of snippets run by this process.
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// How running a snippet ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The snippet does not compile; holds the compiler's diagnostics.
+    CompileError(String),
+    /// Compiling or running took longer than the given limit.
+    TimedOut(Duration),
+    /// The program ran to completion.
+    Exited {
+        /// The exit code, or `None` if the program was terminated by a signal.
+        code: Option<i32>,
+        /// What the program printed to standard output.
+        stdout: String,
+        /// What the program printed to standard error.
+        stderr: String,
+    },
+}
+
+/// A temporary directory removed when dropped.
+struct SnippetDir(PathBuf);
+
+impl SnippetDir {
+    fn create() -> io::Result<Self> {
+        let path = env::temp_dir().join(format!(
+            "nishiogi-snippet-{}-{}",
+            process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path)?;
+        // The sandbox profile names the directory, so symlinks such as macOS's /var must
+        // be resolved
+        Ok(Self(path.canonicalize()?))
+    }
+}
+
+impl Drop for SnippetDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Returns the program to compile for `code`, wrapping it in `main` if it has none.
+fn program_source(code: &str) -> String {
+    if code.contains("fn main") {
+        format!("{code}\n")
+    } else {
+        format!("fn main() {{\n{code}\n}}\n")
+    }
+}
+
+/// Compiles and runs `code` confined by `sandbox`.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the temporary directory cannot be set up or the sandbox
+/// cannot be started.
+pub fn run_snippet(sandbox: &Sandbox, code: &str) -> io::Result<Outcome> {
+    let dir = SnippetDir::create()?;
+    fs::write(dir.0.join("main.rs"), program_source(code))?;
+
+    // `exec` makes the shell's process the compiler or program, so the timeout kills it
+    let compile = "exec rustc --edition 2021 -o snippet main.rs > build.txt 2>&1";
+    match run_limited(sandbox.command(&dir.0, compile), COMPILE_TIMEOUT)? {
+        None => return Ok(Outcome::TimedOut(COMPILE_TIMEOUT)),
+        Some(status) if !status.success() => {
+            return Ok(Outcome::CompileError(read_output(&dir.0.join("build.txt"))));
+        }
+        Some(_) => {}
+    }
+    let run = "exec ./snippet > stdout.txt 2> stderr.txt";
+    let outcome = match run_limited(sandbox.command(&dir.0, run), RUN_TIMEOUT)? {
+        None => Outcome::TimedOut(RUN_TIMEOUT),
+        Some(status) => Outcome::Exited {
+            code: status.code(),
+            stdout: read_output(&dir.0.join("stdout.txt")),
+            stderr: read_output(&dir.0.join("stderr.txt")),
+        },
+    };
+    Ok(outcome)
+}
+
+/// Runs `command`, killing it if it takes longer than `timeout`.
+///
+/// Returns `None` if the command was killed.
+fn run_limited(mut command: Command, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    wait_within(&mut child, timeout)
+}
+
+/// Reads an output file, truncated to [`MAX_OUTPUT_BYTES`].
+fn read_output(path: &Path) -> String {
+    let mut output = String::from_utf8_lossy(&fs::read(path).unwrap_or_default()).into_owned();
+    if output.len() > MAX_OUTPUT_BYTES {
+        output.truncate(output.floor_char_boundary(MAX_OUTPUT_BYTES));
+        output.push_str("\n[truncated]");
+    }
+    output
+}
+
+/// Renders the outcome of a snippet run by `sandbox` as the output of the `run_rust` tool.
+pub fn render_outcome(outcome: &Outcome, sandbox: &Sandbox) -> String {
+    match outcome {
+        Outcome::CompileError(diagnostics) => {
+            format!("The snippet does not compile:\n{diagnostics}")
+        }
+        Outcome::TimedOut(limit) => format!(
+            "The snippet was stopped after {} seconds (sandboxed with {sandbox})",
+            limit.as_secs()
+        ),
+        Outcome::Exited {
+            code,
+            stdout,
+            stderr,
+        } => {
+            let status = match code {
+                Some(code) => format!("exit status {code}"),
+                None => "terminated by a signal".to_string(),
+            };
+            let mut output = format!("{status} (sandboxed with {sandbox})\n");
+            for (name, text) in [("stdout", stdout), ("stderr", stderr)] {
+                if !text.is_empty() {
+                    let _ = write!(output, "{name}:\n{text}");
+                    if !text.ends_with('\n') {
+                        output.push('\n');
+                    }
+                }
+            }
+            output
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_program_source() {
+        assert_eq!(
+            program_source("println!(\"{}\", \"a,b\".split(',').count());"),
+            "fn main() {\nprintln!(\"{}\", \"a,b\".split(',').count());\n}\n"
+        );
+        let program = "use std::fmt;\n\nfn main() {}";
+        assert_eq!(program_source(program), format!("{program}\n"));
+    }
+
+    #[test]
+    fn test_render_outcome() {
+        let sandbox = Sandbox::Bubblewrap(PathBuf::from("/usr/bin/bwrap"));
+        let outcome = Outcome::Exited {
+            code: Some(0),
+            stdout: "2".to_string(),
+            stderr: String::new(),
+        };
+        assert_eq!(
+            render_outcome(&outcome, &sandbox),
+            "exit status 0 (sandboxed with bubblewrap)\nstdout:\n2\n"
+        );
+        assert_eq!(
+            render_outcome(&Outcome::TimedOut(RUN_TIMEOUT), &sandbox),
+            "The snippet was stopped after 10 seconds (sandboxed with bubblewrap)"
+        );
+    }
+}
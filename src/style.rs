@@ -0,0 +1,63 @@
+//! # Answer Styles
+//!
+//! This module defines how long and detailed answers are, chosen per question with
+//! `--style`:
+//!
+//! - `brief`: one short paragraph naming the key location, with a small token limit
+//! - `normal`: the default answer prompt, without a limit
+//! - `deep`: a detailed walkthrough quoting code excerpts, with a larger token limit
+//!
+//! The style adds [`AnswerStyle::answer_guidance`] to the answer prompt and limits the
+//! answer's completion to [`AnswerStyle::max_tokens`]. Planning and review are unaffected.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// How long and detailed answers are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnswerStyle {
+    /// One short paragraph.
+    Brief,
+    /// The default answer.
+    #[default]
+    Normal,
+    /// A detailed walkthrough with code excerpts.
+    Deep,
+}
+
+impl fmt::Display for AnswerStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AnswerStyle::Brief => "brief",
+            AnswerStyle::Normal => "normal",
+            AnswerStyle::Deep => "deep",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl AnswerStyle {
+    /// Returns the instructions added to the answer prompt, if the style has any.
+    pub fn answer_guidance(self) -> Option<&'static str> {
+        match self {
+            AnswerStyle::Brief => Some(
+                "Answer in one short paragraph of at most four sentences, without code excerpts, naming the most important location.",
+            ),
+            AnswerStyle::Normal => None,
+            AnswerStyle::Deep => Some(
+                "Give a detailed walkthrough: go through the relevant code step by step, quote short code excerpts with their locations, and explain how the pieces connect, including edge cases and error handling.",
+            ),
+        }
+    }
+
+    /// Returns the most tokens the answer may use, or `None` for the provider's default.
+    pub fn max_tokens(self) -> Option<u32> {
+        match self {
+            AnswerStyle::Brief => Some(400),
+            AnswerStyle::Normal => None,
+            AnswerStyle::Deep => Some(8000),
+        }
+    }
+}
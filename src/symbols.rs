@@ -0,0 +1,756 @@
+//! # Symbol Index
+//!
+//! This module builds an index of the symbols defined in a repository and of every place
+//! an identifier is mentioned. Definitions are recognized line by line with patterns for
+//! the common forms in the supported languages:
+//!
+//! - Rust (`.rs`): functions, types, traits, constants, statics, modules, and macros
+//! - Python (`.py`): functions and classes
+//! - JavaScript and TypeScript (`.js`, `.jsx`, `.mjs`, `.ts`, `.tsx`): functions, classes,
+//!   top-level bindings, interfaces, type aliases, and enums
+//! - Go (`.go`): functions, methods, and types
+//!
+//! The same patterns extract the signatures of a file with their doc comments, a compact
+//! stand-in for the whole file when only its interface matters.
+//!
+//! Source files in other common languages (Java, C, Ruby, Swift, and others; see
+//! [`is_fallback_source`]) are indexed with [universal-ctags] when it is installed, and
+//! otherwise with generic patterns for definition keywords (`class`, `def`, `func`, ...) and
+//! C-style functions. Their outline (see [`outline`]) always comes from the generic
+//! patterns, which know nothing about doc comments.
+//!
+//! [universal-ctags]: https://ctags.io
+//!
+//! References are found by identifier, so a mention in a comment or an unrelated item with
+//! the same name also counts. The index is therefore suited to finding candidates (for
+//! example symbols that are never mentioned again), not to proving facts about the code.
+
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::LazyLock,
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    org_policy,
+    scheduler::{self, Resource},
+    search::collect_files,
+    tree::find_gitignore_patterns,
+};
+
+/// Extensions of the source files indexed without language-specific patterns.
+const FALLBACK_EXTENSIONS: [&str; 27] = [
+    "java", "kt", "kts", "scala", "groovy", "swift", "rb", "php", "c", "h", "cc", "cpp", "cxx",
+    "hpp", "hh", "cs", "m", "mm", "lua", "ex", "exs", "erl", "hs", "ml", "dart", "zig", "jl",
+];
+
+/// Files passed to one ctags invocation.
+const CTAGS_BATCH: usize = 256;
+
+/// What a symbol is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SymbolKind {
+    /// A function or method.
+    Function,
+    /// A struct, enum, union, class, interface, or type alias.
+    Type,
+    /// A trait.
+    Trait,
+    /// A constant, static, or top-level binding.
+    Constant,
+    /// A module.
+    Module,
+    /// A macro.
+    Macro,
+}
+
+impl fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            SymbolKind::Function => "function",
+            SymbolKind::Type => "type",
+            SymbolKind::Trait => "trait",
+            SymbolKind::Constant => "constant",
+            SymbolKind::Module => "module",
+            SymbolKind::Macro => "macro",
+        };
+        write!(f, "{label}")
+    }
+}
+
+/// A symbol definition.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Symbol {
+    /// The symbol's name.
+    pub name: String,
+    /// What the symbol is.
+    pub kind: SymbolKind,
+    /// Path of the defining file, relative to the indexed root.
+    pub path: PathBuf,
+    /// Line of the definition (1-based).
+    pub line: usize,
+    /// Whether the symbol is visible outside its module or file.
+    pub public: bool,
+}
+
+/// A place an identifier is mentioned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reference {
+    /// Path of the file, relative to the indexed root.
+    pub path: PathBuf,
+    /// Line of the mention (1-based).
+    pub line: usize,
+}
+
+/// Source languages with definition patterns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    /// Rust.
+    Rust,
+    /// Python.
+    Python,
+    /// JavaScript and TypeScript.
+    JavaScript,
+    /// Go.
+    Go,
+}
+
+impl Language {
+    /// Detects the language of a file from its extension.
+    pub fn of(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "rs" => Some(Language::Rust),
+            "py" => Some(Language::Python),
+            "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" => Some(Language::JavaScript),
+            "go" => Some(Language::Go),
+            _ => None,
+        }
+    }
+}
+
+static RUST_ITEM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"^\s*(?P<vis>pub(\([^)]*\))?\s+)?(?:(?:async|const|unsafe|extern\s+"[^"]*")\s+)*(?P<kind>fn|struct|enum|union|type|trait|const|static|mod)\s+(?:mut\s+)?(?P<name>[A-Za-z_]\w*)"#,
+    )
+    .expect("Invalid Rust item pattern")
+});
+
+static RUST_MACRO: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*(?P<export>#\[macro_export\]\s*)?macro_rules!\s*(?P<name>[A-Za-z_]\w*)")
+        .expect("Invalid Rust macro pattern")
+});
+
+static PYTHON_ITEM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*(?:async\s+)?(?P<kind>def|class)\s+(?P<name>[A-Za-z_]\w*)")
+        .expect("Invalid Python item pattern")
+});
+
+static JS_ITEM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^\s*(?P<export>export\s+(?:default\s+)?)?(?:declare\s+)?(?:abstract\s+)?(?:async\s+)?(?P<kind>function\*?|class|const|let|var|interface|type|enum)\s+(?P<name>[A-Za-z_$][\w$]*)",
+    )
+    .expect("Invalid JavaScript item pattern")
+});
+
+static GO_ITEM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<kind>func|type)\s+(?:\([^)]*\)\s*)?(?P<name>[A-Za-z_]\w*)")
+        .expect("Invalid Go item pattern")
+});
+
+/// A definition introduced by a keyword, in most languages.
+static GENERIC_ITEM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^\s*(?P<modifiers>(?:(?:export|public|private|protected|internal|static|final|abstract|async|inline|open|sealed|data|case|local)\s+)*)(?P<kind>function|func|fun|fn|def|defp|defmodule|sub|proc|class|struct|interface|trait|enum|module|object|record|protocol|namespace)\s+(?:self\.)?(?P<name>[A-Za-z_]\w*)",
+    )
+    .expect("Invalid generic item pattern")
+});
+
+/// A method declared with modifiers and a return type, as in Java and C#.
+static GENERIC_METHOD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^\s*(?P<modifiers>(?:(?:public|private|protected|internal|static|final|abstract|virtual|override|synchronized|native|extern|async)\s+)+)(?:[\w<>\[\],.?]+\s+)+(?P<name>[A-Za-z_]\w*)\s*\(",
+    )
+    .expect("Invalid generic method pattern")
+});
+
+/// A top-level C-style function definition such as `static int parse(char *s) {`.
+static C_FUNCTION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<type>[A-Za-z_][\w\s*]*?)[\s*]+(?P<name>[A-Za-z_]\w*)\s*\([^;]*$")
+        .expect("Invalid C function pattern")
+});
+
+/// Words that start statements rather than return types of C-style functions.
+const C_STATEMENTS: [&str; 8] = [
+    "return", "if", "else", "while", "for", "switch", "case", "do",
+];
+
+static IDENTIFIER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[A-Za-z_$][\w$]*").expect("Invalid identifier pattern"));
+
+/// Symbols defined under a directory and the places identifiers are mentioned.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SymbolIndex {
+    /// Every definition, in file and line order.
+    symbols: Vec<Symbol>,
+    /// Mentions of each identifier, definitions included.
+    mentions: HashMap<String, Vec<Reference>>,
+}
+
+impl SymbolIndex {
+    /// Indexes the source files under `root`, skipping ignored and forbidden files.
+    pub fn build(root: &Path) -> Self {
+        let ignore = find_gitignore_patterns(root).unwrap_or_default();
+        let mut files = Vec::new();
+        collect_files(root, root, &ignore, &mut files);
+        Self::from_files(root, &files)
+    }
+
+    /// Indexes the given source files; paths are recorded relative to `root`.
+    ///
+    /// Files the organization policy forbids are skipped.
+    pub fn from_files(root: &Path, files: &[PathBuf]) -> Self {
+        let mut index = Self::default();
+        let policy = org_policy::global();
+        let files: Vec<&PathBuf> = files
+            .iter()
+            .filter(|path| !policy.is_forbidden(path.strip_prefix(root).unwrap_or(path)))
+            .collect();
+        let fallback: Vec<&PathBuf> = files
+            .iter()
+            .copied()
+            .filter(|path| is_fallback_source(path))
+            .collect();
+        let tags = if fallback.is_empty() {
+            None
+        } else {
+            ctags(root, &fallback)
+        };
+        for path in files {
+            let language = Language::of(path);
+            if language.is_none() && !is_fallback_source(path) {
+                continue;
+            }
+            let Ok(content) = fs::read_to_string(path) else {
+                continue;
+            };
+            let rel_path = path.strip_prefix(root).unwrap_or(path).to_path_buf();
+            match (language, &tags) {
+                (Some(language), _) => index.add_file(&rel_path, &content, |_, line| {
+                    parse_definition(language, line)
+                }),
+                (None, Some(tags)) => {
+                    let tags = tags.get(&rel_path);
+                    index.add_file(&rel_path, &content, |line_number, _| {
+                        tags?.get(&line_number).cloned()
+                    });
+                }
+                (None, None) => {
+                    index.add_file(&rel_path, &content, |_, line| {
+                        parse_generic_definition(line)
+                    });
+                }
+            }
+        }
+        index
+    }
+
+    /// Returns every definition, in file and line order.
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Returns the definitions called `name`.
+    pub fn definitions(&self, name: &str) -> Vec<&Symbol> {
+        self.symbols
+            .iter()
+            .filter(|symbol| symbol.name == name)
+            .collect()
+    }
+
+    /// Returns every mention of `name` other than its definitions.
+    pub fn references(&self, name: &str) -> Vec<Reference> {
+        let definitions = self.definitions(name);
+        self.mentions(name)
+            .iter()
+            .filter(|mention| {
+                !definitions
+                    .iter()
+                    .any(|symbol| symbol.path == mention.path && symbol.line == mention.line)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns every mention of the identifier `name`, definitions included.
+    pub fn mentions(&self, name: &str) -> &[Reference] {
+        self.mentions.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    /// Adds the mentions of one file and the definitions `definition` finds on its lines,
+    /// given their number and content.
+    fn add_file(
+        &mut self,
+        path: &Path,
+        content: &str,
+        definition: impl Fn(usize, &str) -> Option<Symbol>,
+    ) {
+        for (index, line) in content.lines().enumerate() {
+            let line_number = index + 1;
+            if let Some(symbol) = definition(line_number, line) {
+                self.symbols.push(Symbol {
+                    path: path.to_path_buf(),
+                    line: line_number,
+                    ..symbol
+                });
+            }
+
+            let mut seen: Vec<&str> = Vec::new();
+            for identifier in IDENTIFIER.find_iter(line) {
+                let identifier = identifier.as_str();
+                if seen.contains(&identifier) {
+                    continue;
+                }
+                seen.push(identifier);
+                self.mentions
+                    .entry(identifier.to_string())
+                    .or_default()
+                    .push(Reference {
+                        path: path.to_path_buf(),
+                        line: line_number,
+                    });
+            }
+        }
+    }
+}
+
+/// Most lines a signature may span, for parameters listed over several lines.
+const MAX_SIGNATURE_LINES: usize = 8;
+
+/// Renders the signatures of the public definitions in `content` with the doc comments
+/// above them, each line prefixed with its number.
+///
+/// Files without public definitions, such as binaries, show all of their definitions.
+/// Bodies are omitted; Rust module docs and Python docstrings are kept.
+pub fn signatures(language: Language, content: &str) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let definitions: Vec<(usize, Symbol)> = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(index, line)| Some((index, parse_definition(language, line)?)))
+        .collect();
+    let any_public = definitions.iter().any(|(_, symbol)| symbol.public);
+
+    let mut shown: Vec<usize> = Vec::new();
+    if language == Language::Rust {
+        shown.extend(
+            (0..lines.len()).take_while(|&index| lines[index].trim_start().starts_with("//!")),
+        );
+    }
+    for (index, symbol) in &definitions {
+        if any_public && !symbol.public {
+            continue;
+        }
+        let mut start = *index;
+        while start > 0 && is_doc_line(language, lines[start - 1]) {
+            start -= 1;
+        }
+        shown.extend(start..=*index);
+
+        // Parameters may continue on the following lines
+        let mut depth = paren_depth(lines[*index]);
+        let mut end = *index;
+        while depth > 0 && end + 1 < lines.len() && end - index < MAX_SIGNATURE_LINES {
+            end += 1;
+            depth += paren_depth(lines[end]);
+            shown.push(end);
+        }
+        if language == Language::Python
+            && let Some(next) = lines.get(end + 1)
+            && next.trim_start().starts_with(['"', '\''])
+        {
+            shown.push(end + 1);
+        }
+    }
+    render_lines(&lines, shown)
+}
+
+/// Renders the definitions the generic patterns find in `content`, each prefixed with its
+/// line number, as the outline of the file at `path` in a language without specific patterns.
+///
+/// Returns `None` for files that are not source files in a known language.
+pub fn outline(path: &Path, content: &str) -> Option<String> {
+    if !is_fallback_source(path) {
+        return None;
+    }
+    let lines: Vec<&str> = content.lines().collect();
+    let shown = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| parse_generic_definition(line).is_some())
+        .map(|(index, _)| index)
+        .collect();
+    Some(render_lines(&lines, shown))
+}
+
+/// Renders the `shown` lines with their numbers, marking the gaps between them.
+fn render_lines(lines: &[&str], mut shown: Vec<usize>) -> String {
+    shown.sort_unstable();
+    shown.dedup();
+
+    let mut output = String::new();
+    let mut previous = None;
+    for index in shown {
+        if previous.is_some_and(|previous| previous + 1 != index) {
+            output.push_str("      ...\n");
+        }
+        let line = lines[index].trim_end();
+        let line = line.strip_suffix('{').map_or(line, str::trim_end);
+        output.push_str(&format!("{:>5}  {line}\n", index + 1));
+        previous = Some(index);
+    }
+    output
+}
+
+/// Returns whether `line` documents or annotates the definition below it.
+fn is_doc_line(language: Language, line: &str) -> bool {
+    let line = line.trim_start();
+    match language {
+        Language::Rust => line.starts_with("///") || line.starts_with("#["),
+        Language::Python => line.starts_with('#') || line.starts_with('@'),
+        Language::JavaScript => {
+            line.starts_with("/**") || line.starts_with('*') || line.starts_with("//")
+        }
+        Language::Go => line.starts_with("//"),
+    }
+}
+
+/// Returns how many more parentheses `line` opens than it closes.
+fn paren_depth(line: &str) -> isize {
+    line.chars().fold(0, |depth, c| match c {
+        '(' => depth + 1,
+        ')' => depth - 1,
+        _ => depth,
+    })
+}
+
+/// Recognizes a definition on `line`; the returned symbol has no location yet.
+fn parse_definition(language: Language, line: &str) -> Option<Symbol> {
+    let symbol = |name: &str, kind, public| Symbol {
+        name: name.to_string(),
+        kind,
+        path: PathBuf::new(),
+        line: 0,
+        public,
+    };
+
+    match language {
+        Language::Rust => {
+            if let Some(captures) = RUST_MACRO.captures(line) {
+                return Some(symbol(
+                    &captures["name"],
+                    SymbolKind::Macro,
+                    captures.name("export").is_some(),
+                ));
+            }
+            let captures = RUST_ITEM.captures(line)?;
+            let kind = match &captures["kind"] {
+                "fn" => SymbolKind::Function,
+                "trait" => SymbolKind::Trait,
+                "const" | "static" => SymbolKind::Constant,
+                "mod" => SymbolKind::Module,
+                _ => SymbolKind::Type,
+            };
+            let public = captures
+                .name("vis")
+                .is_some_and(|vis| vis.as_str().trim() == "pub");
+            Some(symbol(&captures["name"], kind, public))
+        }
+        Language::Python => {
+            let captures = PYTHON_ITEM.captures(line)?;
+            let kind = if &captures["kind"] == "def" {
+                SymbolKind::Function
+            } else {
+                SymbolKind::Type
+            };
+            let name = &captures["name"];
+            Some(symbol(name, kind, !name.starts_with('_')))
+        }
+        Language::JavaScript => {
+            let captures = JS_ITEM.captures(line)?;
+            let kind = match &captures["kind"] {
+                "function" | "function*" => SymbolKind::Function,
+                "const" | "let" | "var" => SymbolKind::Constant,
+                _ => SymbolKind::Type,
+            };
+            Some(symbol(
+                &captures["name"],
+                kind,
+                captures.name("export").is_some(),
+            ))
+        }
+        Language::Go => {
+            let captures = GO_ITEM.captures(line)?;
+            let kind = if &captures["kind"] == "func" {
+                SymbolKind::Function
+            } else {
+                SymbolKind::Type
+            };
+            let name = &captures["name"];
+            let public = name.starts_with(|c: char| c.is_ascii_uppercase());
+            Some(symbol(name, kind, public))
+        }
+    }
+}
+
+/// Returns whether `path` is a source file indexed without language-specific patterns.
+pub fn is_fallback_source(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| FALLBACK_EXTENSIONS.contains(&extension))
+}
+
+/// Recognizes a definition on `line` with the generic patterns; the returned symbol has no
+/// location yet.
+fn parse_generic_definition(line: &str) -> Option<Symbol> {
+    let symbol = |captures: &regex::Captures, kind, public| Symbol {
+        name: captures["name"].to_string(),
+        kind,
+        path: PathBuf::new(),
+        line: 0,
+        public,
+    };
+    let private = |captures: &regex::Captures| {
+        captures.name("modifiers").is_some_and(|modifiers| {
+            modifiers
+                .as_str()
+                .split_whitespace()
+                .any(|modifier| matches!(modifier, "private" | "protected" | "local"))
+        })
+    };
+
+    if let Some(captures) = GENERIC_ITEM.captures(line) {
+        let kind = match &captures["kind"] {
+            "function" | "func" | "fun" | "fn" | "def" | "defp" | "sub" | "proc" => {
+                SymbolKind::Function
+            }
+            "trait" => SymbolKind::Trait,
+            "module" | "defmodule" | "namespace" => SymbolKind::Module,
+            _ => SymbolKind::Type,
+        };
+        let public = !private(&captures) && &captures["kind"] != "defp";
+        return Some(symbol(&captures, kind, public));
+    }
+    if let Some(captures) = GENERIC_METHOD.captures(line) {
+        // Methods without an access modifier are package-private or private by default
+        let public = captures["modifiers"]
+            .split_whitespace()
+            .any(|m| m == "public");
+        return Some(symbol(&captures, SymbolKind::Function, public));
+    }
+    let captures = C_FUNCTION.captures(line)?;
+    let first = captures["type"]
+        .split_whitespace()
+        .next()
+        .unwrap_or_default();
+    if C_STATEMENTS.contains(&first) || C_STATEMENTS.contains(&&captures["name"]) {
+        return None;
+    }
+    let public = first != "static";
+    Some(symbol(&captures, SymbolKind::Function, public))
+}
+
+/// Indexes `files` with universal-ctags, returning the definitions of each file (relative
+/// to `root`) by line.
+///
+/// Returns `None` if ctags is not installed or fails, for example because it is Exuberant
+/// ctags, which cannot write JSON.
+fn ctags(root: &Path, files: &[&PathBuf]) -> Option<HashMap<PathBuf, HashMap<usize, Symbol>>> {
+    let mut tags: HashMap<PathBuf, HashMap<usize, Symbol>> = HashMap::new();
+    for batch in files.chunks(CTAGS_BATCH) {
+        let _permit = scheduler::global().acquire(Resource::Subprocess);
+        let output = Command::new("ctags")
+            .args(["--output-format=json", "--fields=+nKa", "-f", "-", "--"])
+            .args(
+                batch
+                    .iter()
+                    .map(|path| path.strip_prefix(root).unwrap_or(path)),
+            )
+            .current_dir(root)
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if let Some(symbol) = parse_tag(line) {
+                tags.entry(symbol.path.clone())
+                    .or_default()
+                    .entry(symbol.line)
+                    .or_insert(symbol);
+            }
+        }
+    }
+    Some(tags)
+}
+
+/// Parses one line of ctags JSON output, skipping kinds that are not definitions such as
+/// fields and local variables.
+fn parse_tag(line: &str) -> Option<Symbol> {
+    let tag: Value = serde_json::from_str(line).ok()?;
+    if tag["_type"] != "tag" {
+        return None;
+    }
+    let kind = match tag["kind"].as_str()? {
+        "function" | "method" | "func" | "subroutine" | "procedure" | "singletonMethod"
+        | "constructor" => SymbolKind::Function,
+        "class" | "struct" | "interface" | "enum" | "union" | "typedef" | "type" | "record"
+        | "protocol" | "object" => SymbolKind::Type,
+        "trait" => SymbolKind::Trait,
+        "module" | "namespace" | "package" => SymbolKind::Module,
+        "constant" | "define" => SymbolKind::Constant,
+        "macro" => SymbolKind::Macro,
+        _ => return None,
+    };
+    Some(Symbol {
+        name: tag["name"].as_str()?.to_string(),
+        kind,
+        path: PathBuf::from(tag["path"].as_str()?),
+        line: usize::try_from(tag["line"].as_u64()?).ok()?,
+        public: !matches!(tag["access"].as_str(), Some("private" | "protected")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_definitions() {
+        let parse = |language, line| {
+            parse_definition(language, line).map(|symbol| (symbol.name, symbol.kind, symbol.public))
+        };
+        assert_eq!(
+            parse(Language::Rust, "    pub(crate) async fn load() {"),
+            Some(("load".to_string(), SymbolKind::Function, false))
+        );
+        assert_eq!(
+            parse(Language::Rust, "pub const fn limit() -> usize {"),
+            Some(("limit".to_string(), SymbolKind::Function, true))
+        );
+        assert_eq!(
+            parse(Language::Rust, "const MAX: usize = 3;"),
+            Some(("MAX".to_string(), SymbolKind::Constant, false))
+        );
+        assert_eq!(
+            parse(Language::Rust, "#[macro_export] macro_rules! ensure {"),
+            Some(("ensure".to_string(), SymbolKind::Macro, true))
+        );
+        assert_eq!(parse(Language::Rust, "impl Display for Plan {"), None);
+        assert_eq!(
+            parse(Language::Python, "    def _helper(self):"),
+            Some(("_helper".to_string(), SymbolKind::Function, false))
+        );
+        assert_eq!(
+            parse(
+                Language::JavaScript,
+                "export default async function main() {"
+            ),
+            Some(("main".to_string(), SymbolKind::Function, true))
+        );
+        assert_eq!(
+            parse(Language::Go, "func (s *Server) Serve() error {"),
+            Some(("Serve".to_string(), SymbolKind::Function, true))
+        );
+    }
+
+    #[test]
+    fn test_signatures() {
+        let source = "//! Sessions.\n\nuse std::fs;\n\n/// Saves a session.\n#[must_use]\npub fn save(\n    id: &str,\n    data: &[u8],\n) -> bool {\n    fs::write(id, data).is_ok()\n}\n\nfn helper() {}\n";
+        assert_eq!(
+            signatures(Language::Rust, source),
+            "    1  //! Sessions.\n      ...\n    5  /// Saves a session.\n    6  #[must_use]\n    7  pub fn save(\n    8      id: &str,\n    9      data: &[u8],\n   10  ) -> bool\n"
+        );
+
+        let source = "def _private():\n    pass\n\ndef load(path):\n    \"\"\"Loads a session.\"\"\"\n    return open(path).read()\n";
+        assert_eq!(
+            signatures(Language::Python, source),
+            "    4  def load(path):\n    5      \"\"\"Loads a session.\"\"\"\n"
+        );
+        // Without public definitions, every definition is shown
+        assert_eq!(
+            signatures(Language::Rust, "fn main() {\n}\n"),
+            "    1  fn main()\n"
+        );
+    }
+
+    #[test]
+    fn test_generic_definitions() {
+        let parse = |line| {
+            parse_generic_definition(line).map(|symbol| (symbol.name, symbol.kind, symbol.public))
+        };
+        assert_eq!(
+            parse("  private def reset!"),
+            Some(("reset".to_string(), SymbolKind::Function, false))
+        );
+        assert_eq!(
+            parse("public final class Parser {"),
+            Some(("Parser".to_string(), SymbolKind::Type, true))
+        );
+        assert_eq!(
+            parse("    public static List<String> split(String text) {"),
+            Some(("split".to_string(), SymbolKind::Function, true))
+        );
+        assert_eq!(
+            parse("static int count_lines(const char *path) {"),
+            Some(("count_lines".to_string(), SymbolKind::Function, false))
+        );
+        assert_eq!(parse("    return compute(x);"), None);
+        assert_eq!(parse("int total = sum(values);"), None);
+        assert_eq!(
+            outline(
+                Path::new("greeter.rb"),
+                "class Greeter\n  def greet(name)\n    puts name\n  end\nend\n"
+            )
+            .as_deref(),
+            Some("    1  class Greeter\n    2    def greet(name)\n")
+        );
+        assert_eq!(outline(Path::new("notes.txt"), "class Notes\n"), None);
+        assert_eq!(
+            parse_tag(r#"{"_type": "tag", "name": "Greeter", "path": "lib/greeter.rb", "line": 1, "kind": "class"}"#)
+                .map(|symbol| (symbol.name, symbol.line)),
+            Some(("Greeter".to_string(), 1))
+        );
+    }
+
+    #[test]
+    fn test_references_exclude_definitions() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        fs::write(
+            root.join("lib.rs"),
+            "pub fn used() {}\nfn unused() {}\nfn caller() {\n    used();\n}\n",
+        )
+        .expect("Failed to write file");
+        fs::write(root.join("notes.txt"), "unused\n").expect("Failed to write file");
+
+        let index = SymbolIndex::build(root);
+        assert_eq!(index.symbols().len(), 3);
+        assert_eq!(
+            index.references("used"),
+            vec![Reference {
+                path: PathBuf::from("lib.rs"),
+                line: 4,
+            }]
+        );
+        // Only source files are indexed
+        assert!(index.references("unused").is_empty());
+    }
+}
@@ -0,0 +1,88 @@
+//! # Question Templates
+//!
+//! This module defines canned questions that teams use for recurring analyses. A template
+//! expands into a detailed, multi-part question and can add instructions to every prompt
+//! and name the commands the planner should prefer:
+//!
+//! ```toml
+//! [templates.security-audit]
+//! description = "Review the code for common security problems"
+//! question = """
+//! Audit {input} for security problems:
+//! 1. Where is untrusted input parsed?
+//! 2. Are there shell or SQL injection risks?
+//! 3. Are secrets hard-coded anywhere?
+//! """
+//! prompt = "Cite every finding with file and line numbers and rate its severity."
+//! tools = ["search", "show_file"]
+//! ```
+//!
+//! Templates are used with `nishiogi ask --template security-audit [question]`. The
+//! optional question replaces `{input}` in the template, or is appended to it when the
+//! template has no placeholder.
+
+use serde::Deserialize;
+
+/// Placeholder replaced by the question given on the command line.
+const INPUT_PLACEHOLDER: &str = "{input}";
+
+/// Text used for the placeholder when no question is given.
+const DEFAULT_INPUT: &str = "the repository";
+
+/// A `[templates.<name>]` section of the configuration file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct QuestionTemplate {
+    /// A short summary shown when listing templates.
+    pub description: String,
+    /// The question the template expands to.
+    pub question: String,
+    /// Additional instructions for every step of the agent.
+    pub prompt: Option<String>,
+    /// Commands the planner should prefer.
+    pub tools: Vec<String>,
+}
+
+impl QuestionTemplate {
+    /// Expands the template with the question given on the command line.
+    pub fn expand(&self, input: Option<&str>) -> String {
+        let question = self.question.trim();
+        if question.contains(INPUT_PLACEHOLDER) {
+            return question.replace(INPUT_PLACEHOLDER, input.unwrap_or(DEFAULT_INPUT));
+        }
+        match input {
+            Some(input) => format!("{question}\n\nFocus on: {input}"),
+            None => question.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let template = QuestionTemplate {
+            question: "Audit {input} for injection risks.\n".to_string(),
+            ..QuestionTemplate::default()
+        };
+        assert_eq!(
+            template.expand(Some("src/db")),
+            "Audit src/db for injection risks."
+        );
+        assert_eq!(
+            template.expand(None),
+            "Audit the repository for injection risks."
+        );
+
+        let template = QuestionTemplate {
+            question: "List the public API.".to_string(),
+            ..QuestionTemplate::default()
+        };
+        assert_eq!(
+            template.expand(Some("the parser")),
+            "List the public API.\n\nFocus on: the parser"
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! # Server Tenants
+//!
+//! This module decides which clients may use a shared `nishiogi serve` deployment and how
+//! much. Each client gets its own API key, configured in the `[server]` section with:
+//!
+//! - a rate limit, in requests per minute
+//! - a daily token budget, reset at midnight UTC
+//! - the repositories it may ask about
+//!
+//! Only the SHA-256 of each key is configured, so the configuration file can be shared
+//! without leaking the keys. Clients send the key as `Authorization: Bearer <key>`. When
+//! no key is configured, the server is open to everyone without limits.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    error::Error,
+    fmt,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+
+use crate::session::sha256_hex;
+
+/// Name under which requests are counted when no keys are configured.
+pub const ANONYMOUS: &str = "anonymous";
+
+/// Name of the repository the server was started in.
+pub const DEFAULT_REPO: &str = "default";
+
+/// Window over which the request rate is measured.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// The `[server]` section of the configuration file.
+///
+/// ```toml
+/// [server.repos]
+/// api = "/srv/checkouts/api"
+/// web = "/srv/checkouts/web"
+///
+/// [[server.keys]]
+/// name = "payments-team"
+/// # printf %s "$KEY" | sha256sum
+/// sha256 = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+/// requests_per_minute = 30
+/// daily_tokens = 2000000
+/// repos = ["api"]
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Repositories served besides the one the server was started in (named `default`),
+    /// by name.
+    pub repos: BTreeMap<String, PathBuf>,
+    /// Client API keys; without any, no key is required.
+    pub keys: Vec<ApiKeyConfig>,
+}
+
+/// A client API key and its limits.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKeyConfig {
+    /// Name of the client, used in logs and metrics.
+    pub name: String,
+    /// Hex-encoded SHA-256 of the key.
+    pub sha256: String,
+    /// Most requests per minute, if limited.
+    #[serde(default)]
+    pub requests_per_minute: Option<usize>,
+    /// Most tokens per day (UTC), if limited.
+    #[serde(default)]
+    pub daily_tokens: Option<u64>,
+    /// Repositories the key may ask about; all of them when empty.
+    #[serde(default)]
+    pub repos: Vec<String>,
+}
+
+/// Why a request was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Denial {
+    /// No key or an unknown key was sent.
+    Unauthenticated,
+    /// The key may not ask about the repository.
+    RepoNotAllowed(String),
+    /// The key sent too many requests in the last minute.
+    RateLimited,
+    /// The key used up its tokens for the day.
+    BudgetExhausted,
+}
+
+impl Denial {
+    /// Returns the HTTP status code for the denial.
+    pub fn status(&self) -> u16 {
+        match self {
+            Denial::Unauthenticated => 401,
+            Denial::RepoNotAllowed(_) => 403,
+            Denial::RateLimited | Denial::BudgetExhausted => 429,
+        }
+    }
+}
+
+impl fmt::Display for Denial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Denial::Unauthenticated => write!(f, "Missing or unknown API key"),
+            Denial::RepoNotAllowed(repo) => {
+                write!(f, "This API key may not ask about repository {repo}")
+            }
+            Denial::RateLimited => write!(f, "Rate limit exceeded; retry in a minute"),
+            Denial::BudgetExhausted => {
+                write!(f, "Daily token budget exhausted; it resets at midnight UTC")
+            }
+        }
+    }
+}
+
+impl Error for Denial {}
+
+/// Usage of one key.
+#[derive(Debug, Default)]
+struct Usage {
+    /// When the requests of the current window were admitted.
+    requests: VecDeque<Instant>,
+    /// The day `tokens` counts.
+    day: Option<NaiveDate>,
+    /// Tokens used on `day`.
+    tokens: u64,
+}
+
+/// The configured keys and their usage.
+#[derive(Debug)]
+pub struct Tenants {
+    keys: Vec<ApiKeyConfig>,
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+impl Tenants {
+    /// Creates the tenants of `keys`.
+    pub fn new(keys: Vec<ApiKeyConfig>) -> Self {
+        Self {
+            keys,
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Admits a request with the `Authorization` header `authorization` asking about
+    /// `repo`, counting it against the key's rate limit.
+    ///
+    /// Returns the name of the key, or [`ANONYMOUS`] when no keys are configured.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Denial` if the key is missing or unknown, may not ask about `repo`, or
+    /// exceeded its rate limit or token budget.
+    pub fn admit(&self, authorization: Option<&str>, repo: &str) -> Result<String, Denial> {
+        self.admit_at(authorization, repo, Instant::now(), Utc::now().date_naive())
+    }
+
+    fn admit_at(
+        &self,
+        authorization: Option<&str>,
+        repo: &str,
+        now: Instant,
+        today: NaiveDate,
+    ) -> Result<String, Denial> {
+        if self.keys.is_empty() {
+            return Ok(ANONYMOUS.to_string());
+        }
+        let key = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|key| sha256_hex(key.trim().as_bytes()))
+            .and_then(|hash| {
+                self.keys
+                    .iter()
+                    .find(|key| key.sha256.eq_ignore_ascii_case(&hash))
+            })
+            .ok_or(Denial::Unauthenticated)?;
+        if !key.repos.is_empty() && !key.repos.iter().any(|allowed| allowed == repo) {
+            return Err(Denial::RepoNotAllowed(repo.to_string()));
+        }
+
+        let mut usage = self.usage.lock().unwrap_or_else(|err| err.into_inner());
+        let usage = usage.entry(key.name.clone()).or_default();
+        if usage.day != Some(today) {
+            usage.day = Some(today);
+            usage.tokens = 0;
+        }
+        if key
+            .daily_tokens
+            .is_some_and(|budget| usage.tokens >= budget)
+        {
+            return Err(Denial::BudgetExhausted);
+        }
+        while usage
+            .requests
+            .front()
+            .is_some_and(|admitted| now.duration_since(*admitted) >= RATE_WINDOW)
+        {
+            usage.requests.pop_front();
+        }
+        if key
+            .requests_per_minute
+            .is_some_and(|limit| usage.requests.len() >= limit)
+        {
+            return Err(Denial::RateLimited);
+        }
+        usage.requests.push_back(now);
+        Ok(key.name.clone())
+    }
+
+    /// Counts `tokens` used by a request admitted for `name` against its daily budget.
+    pub fn record_tokens(&self, name: &str, tokens: u64) {
+        let mut usage = self.usage.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(usage) = usage.get_mut(name) {
+            usage.tokens += tokens;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tenants() -> Tenants {
+        let config: ServerConfig = toml::from_str(&format!(
+            "[[keys]]\nname = \"payments\"\nsha256 = \"{}\"\nrequests_per_minute = 2\ndaily_tokens = 100\nrepos = [\"api\"]\n\n[[keys]]\nname = \"ops\"\nsha256 = \"{}\"\n",
+            sha256_hex(b"pay-key"),
+            sha256_hex(b"ops-key")
+        ))
+        .expect("Failed to parse server config");
+        Tenants::new(config.keys)
+    }
+
+    #[test]
+    fn test_keys_and_scopes() {
+        let tenants = tenants();
+        let now = Instant::now();
+        let today = NaiveDate::from_ymd_opt(2026, 10, 16).expect("Invalid date");
+        assert_eq!(
+            tenants.admit_at(Some("Bearer ops-key"), "web", now, today),
+            Ok("ops".to_string())
+        );
+        assert_eq!(
+            tenants.admit_at(Some("Bearer wrong"), "web", now, today),
+            Err(Denial::Unauthenticated)
+        );
+        assert_eq!(
+            tenants.admit_at(None, "web", now, today),
+            Err(Denial::Unauthenticated)
+        );
+        assert_eq!(
+            tenants.admit_at(Some("Bearer pay-key"), "web", now, today),
+            Err(Denial::RepoNotAllowed("web".to_string()))
+        );
+        assert_eq!(
+            Tenants::new(Vec::new()).admit_at(None, "web", now, today),
+            Ok(ANONYMOUS.to_string())
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_and_budget() {
+        let tenants = tenants();
+        let start = Instant::now();
+        let today = NaiveDate::from_ymd_opt(2026, 10, 16).expect("Invalid date");
+        let admit = |now: Instant, day: NaiveDate| {
+            tenants.admit_at(Some("Bearer pay-key"), "api", now, day)
+        };
+
+        assert!(admit(start, today).is_ok());
+        assert!(admit(start + Duration::from_secs(1), today).is_ok());
+        assert_eq!(
+            admit(start + Duration::from_secs(2), today),
+            Err(Denial::RateLimited)
+        );
+        assert!(admit(start + Duration::from_secs(61), today).is_ok());
+
+        tenants.record_tokens("payments", 100);
+        let later = start + Duration::from_secs(300);
+        assert_eq!(admit(later, today), Err(Denial::BudgetExhausted));
+        assert!(admit(later, today.succ_opt().expect("Invalid date")).is_ok());
+    }
+}
@@ -0,0 +1,135 @@
+//! # Token Estimation
+//!
+//! This module estimates how many tokens a piece of text occupies in a model prompt,
+//! without shipping a tokenizer:
+//!
+//! - [`estimate_tokens`] uses the common rule of thumb of roughly four characters per
+//!   token, which is close enough for reporting and ranking.
+//! - [`count_tokens`] splits the text the way byte-pair encoders such as tiktoken split it
+//!   before merging (words with their leading space, runs of digits, runs of punctuation
+//!   and runs of whitespace) and charges each piece for the tokens it usually merges into.
+//!   It follows source code, which is full of punctuation and short identifiers, much more
+//!   closely, and is used to fit prompts in a model's context window, which is
+//!   [`DEFAULT_CONTEXT_WINDOW`] when the provider does not report it.
+//! - [`prompt_cost`] turns a token count into dollars at a configured price, for the
+//!   estimate printed when a question is answered by a more expensive model.
+
+/// Context window assumed for models whose provider does not report one.
+pub const DEFAULT_CONTEXT_WINDOW: usize = 32_768;
+
+/// Average number of characters per token assumed by [`estimate_tokens`].
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Letters merged into one token, on average.
+const LETTERS_PER_TOKEN: usize = 6;
+
+/// Digits merged into one token; encoders split numbers into groups of three.
+const DIGITS_PER_TOKEN: usize = 3;
+
+/// Punctuation characters merged into one token, on average, as in `::` or `);`.
+const PUNCTUATION_PER_TOKEN: usize = 2;
+
+/// The kinds of pieces text is split into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Piece {
+    Letters,
+    Digits,
+    Punctuation,
+    Whitespace,
+    /// A character outside ASCII, such as CJK text or emoji, counted as a token each.
+    Other,
+}
+
+impl Piece {
+    /// Returns the kind of piece `c` belongs to.
+    fn of(c: char) -> Self {
+        if c.is_ascii_alphabetic() {
+            Piece::Letters
+        } else if c.is_ascii_digit() {
+            Piece::Digits
+        } else if c.is_ascii_whitespace() {
+            Piece::Whitespace
+        } else if c.is_ascii() {
+            Piece::Punctuation
+        } else {
+            Piece::Other
+        }
+    }
+
+    /// Returns the tokens of a piece of `len` characters.
+    fn tokens(self, len: usize) -> usize {
+        match self {
+            Piece::Letters => len.div_ceil(LETTERS_PER_TOKEN),
+            Piece::Digits => len.div_ceil(DIGITS_PER_TOKEN),
+            Piece::Punctuation => len.div_ceil(PUNCTUATION_PER_TOKEN),
+            Piece::Whitespace => 1,
+            Piece::Other => len,
+        }
+    }
+}
+
+/// Estimates the number of tokens in `text`.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Counts the tokens of `text` like a byte-pair encoder would, approximately.
+pub fn count_tokens(text: &str) -> usize {
+    let chars: Vec<char> = text.chars().collect();
+    let starts_word = |index: usize| chars.get(index).is_some_and(char::is_ascii_alphanumeric);
+    let mut tokens = 0;
+    let mut index = 0;
+    while index < chars.len() {
+        // A single space is merged into the word it precedes
+        if chars[index] == ' ' && starts_word(index + 1) {
+            index += 1;
+            continue;
+        }
+        let piece = Piece::of(chars[index]);
+        let start = index;
+        index += 1;
+        while piece != Piece::Other
+            && index < chars.len()
+            && Piece::of(chars[index]) == piece
+            // The last space of indentation goes to the word that follows
+            && !(chars[index] == ' ' && starts_word(index + 1))
+        {
+            index += 1;
+        }
+        tokens += piece.tokens(index - start);
+    }
+    tokens
+}
+
+/// Returns the cost in dollars of `tokens` prompt tokens at `dollars_per_million` tokens.
+pub fn prompt_cost(tokens: usize, dollars_per_million: f64) -> f64 {
+    tokens as f64 * dollars_per_million / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abc"), 1);
+        assert_eq!(estimate_tokens("abcdefgh"), 2);
+        assert_eq!(estimate_tokens("日本語のテキスト"), 2);
+    }
+
+    #[test]
+    fn test_count_tokens() {
+        assert_eq!(count_tokens(""), 0);
+        assert_eq!(count_tokens("The quick brown fox"), 4);
+        assert_eq!(count_tokens("    let x = 12345;\n"), 9);
+        assert_eq!(count_tokens("ToolRegistry::builtin()"), 6);
+        assert_eq!(count_tokens("日本語のテキスト"), 8);
+    }
+
+    #[test]
+    fn test_prompt_cost() {
+        assert_eq!(prompt_cost(0, 2.5), 0.0);
+        assert!((prompt_cost(200_000, 2.5) - 0.5).abs() < 1e-9);
+    }
+}
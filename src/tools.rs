@@ -0,0 +1,1317 @@
+//! # Tool Registry
+//!
+//! This module defines the commands a plan can run. Every command is a [`Tool`] with a
+//! name, a description for the planner, an argument schema and an `execute` method, and
+//! the agent runs them through a [`ToolRegistry`]:
+//!
+//! - [`ToolRegistry::run`] splits a planned command such as `search fn\s+main src` into
+//!   the tool name and its arguments, checks them against the tool's schema and executes
+//!   it.
+//! - [`ToolRegistry::planner_prompt`] describes the registered tools to the planner, so a
+//!   tool added with [`ToolRegistry::register`] (or
+//!   [`Agent::with_tool`](crate::agent::Agent::with_tool)) becomes usable without touching
+//!   the prompts.
+//!
+//! Arguments are separated by whitespace, except that the last argument of tools taking
+//! free text (paths, code, shell commands) is the rest of the command. Tools unknown to
+//! the [tool policy](crate::policy) are treated as `exec` tools unless their class is
+//! configured in `[policy.classes]`.
+//!
+//! A registry given a [`CommandCache`] serves repeated file-reading commands from it, so
+//! agents answering the same question with different models read the repository once.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+
+use crate::{
+    agent::AgentError,
+    blame::run_blame,
+    build_config::{run_makefiles, run_workflows},
+    config::repo_root,
+    containers::run_containers,
+    coverage::{find_report, missing_report_message, render_coverage},
+    licenses::{find_licenses, render_licenses},
+    org_policy::{self, OrgPolicy},
+    overrides::Exclusions,
+    owners::run_owners,
+    pattern_check::{glob_expand, regex_test},
+    plan::PLANNER_PROMPT,
+    root_sandbox::RootSandbox,
+    routes::{find_routes, render_routes},
+    sandbox::{output_within, Sandbox},
+    scheduler::{self, Resource},
+    search::{grep, render_matches, render_snippets, search, SearchOptions, MAX_LINE_MATCHES},
+    session::{sha256_hex, FileProvenance, LineRange},
+    show_file::{number_lines, read_file_content, split_range, FileReadError},
+    snippet::{render_outcome, run_snippet},
+    symbols::{outline, signatures, Language},
+    tree::{generate_tree, generate_tree_hiding},
+};
+
+/// Time a `run` command may take before it is killed.
+const RUN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How an argument of a tool is read from the command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgumentKind {
+    /// One word, which must be given.
+    Word,
+    /// One word, which may be left out.
+    OptionalWord,
+    /// The rest of the command, which must not be empty.
+    Rest,
+    /// The rest of the command, which may be empty.
+    OptionalRest,
+}
+
+/// An argument in a tool's schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Argument {
+    /// The name shown to the planner, such as `path`.
+    pub name: &'static str,
+    /// How the argument is read.
+    pub kind: ArgumentKind,
+}
+
+impl Argument {
+    /// A word that must be given.
+    pub const fn word(name: &'static str) -> Self {
+        Self {
+            name,
+            kind: ArgumentKind::Word,
+        }
+    }
+
+    /// A word that may be left out.
+    pub const fn optional_word(name: &'static str) -> Self {
+        Self {
+            name,
+            kind: ArgumentKind::OptionalWord,
+        }
+    }
+
+    /// The rest of the command, which must not be empty.
+    pub const fn rest(name: &'static str) -> Self {
+        Self {
+            name,
+            kind: ArgumentKind::Rest,
+        }
+    }
+
+    /// The rest of the command, which may be empty.
+    pub const fn optional_rest(name: &'static str) -> Self {
+        Self {
+            name,
+            kind: ArgumentKind::OptionalRest,
+        }
+    }
+
+    /// Returns whether the argument must be given.
+    fn is_required(self) -> bool {
+        matches!(self.kind, ArgumentKind::Word | ArgumentKind::Rest)
+    }
+}
+
+impl fmt::Display for Argument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_required() {
+            write!(f, "<{}>", self.name)
+        } else {
+            write!(f, "[{}]", self.name)
+        }
+    }
+}
+
+/// What a tool returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolResult {
+    /// The text shown to the model.
+    pub text: String,
+    /// The file that was read, if the tool read one.
+    pub file: Option<FileProvenance>,
+}
+
+impl From<String> for ToolResult {
+    fn from(text: String) -> Self {
+        Self { text, file: None }
+    }
+}
+
+/// A command the planner can use.
+pub trait Tool: Send + Sync {
+    /// The name commands start with, such as `show_file`.
+    fn name(&self) -> &'static str;
+
+    /// What the tool does and when to use it, completing "'<usage>' to ...".
+    fn description(&self) -> &'static str;
+
+    /// The arguments, in order. Optional arguments and the rest of the command come last.
+    fn arguments(&self) -> &'static [Argument];
+
+    /// Whether the tool runs under a file read permit of the [scheduler](crate::scheduler).
+    ///
+    /// Tools starting processes return `false` and limit themselves as subprocesses.
+    fn reads_files(&self) -> bool {
+        true
+    }
+
+    /// Runs the tool with the arguments read according to [`Tool::arguments`], leaving out
+    /// optional arguments that were not given, and relative paths resolved by `context`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `AgentError` if the tool cannot produce a result.
+    fn execute(&self, args: &[&str], context: &ToolContext<'_>) -> Result<ToolResult, AgentError>;
+
+    /// Returns how the tool is invoked, such as `search <regex> [dir]`.
+    fn usage(&self) -> String {
+        let mut usage = self.name().to_string();
+        for argument in self.arguments() {
+            usage.push_str(&format!(" {argument}"));
+        }
+        usage
+    }
+}
+
+/// What the tools see of the repository during one query.
+#[derive(Debug, Clone, Copy)]
+pub struct ToolContext<'a> {
+    /// The directory relative paths are resolved against, or `None` for the current
+    /// directory.
+    pub base: Option<&'a Path>,
+    /// The paths hidden from the tools for the query.
+    pub excluded: &'a Exclusions,
+    /// The directory tree paths must stay in, or `None` to allow any path.
+    pub sandbox: Option<&'a RootSandbox>,
+}
+
+impl ToolContext<'_> {
+    /// Resolves a path from a command against the base directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AgentError::PathOutsideSandbox` if the path leads outside the sandbox.
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, AgentError> {
+        let path = resolve_path(self.base, path);
+        if let Some(sandbox) = self.sandbox {
+            sandbox.check(&path)?;
+        }
+        Ok(path)
+    }
+
+    /// Returns whether `path` is excluded from the query.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        self.excluded.excludes(path)
+    }
+
+    /// Returns whether listings leave `path` out, because the organization policy forbids it
+    /// or it is excluded from the query.
+    pub fn is_hidden(&self, path: &Path) -> bool {
+        org_policy::global().is_forbidden(path) || self.is_excluded(path)
+    }
+}
+
+/// Output of a single tool execution.
+pub(crate) struct ToolOutput {
+    /// The text returned by the tool.
+    pub(crate) text: String,
+    /// The file that was read, if the tool read one.
+    pub(crate) file: Option<FileProvenance>,
+    /// Wall-clock time the tool took, in milliseconds.
+    pub(crate) duration_ms: u64,
+}
+
+/// Results of file-reading commands, shared by the registries of several agents.
+///
+/// Commands starting processes are never cached, since their output may change between
+/// runs. Clones share the same results.
+#[derive(Debug, Clone, Default)]
+pub struct CommandCache {
+    results: Arc<Mutex<HashMap<String, ToolResult>>>,
+    hits: Arc<AtomicUsize>,
+}
+
+impl CommandCache {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns how many commands were served from the cache.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the cached result of `key`, counting a hit.
+    fn get(&self, key: &str) -> Option<ToolResult> {
+        let result = self
+            .results
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(key)
+            .cloned();
+        if result.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Stores the result of `key`.
+    fn insert(&self, key: String, result: ToolResult) {
+        self.results
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(key, result);
+    }
+}
+
+/// The tools available to plans, by name.
+pub struct ToolRegistry {
+    tools: Vec<Box<dyn Tool>>,
+    cache: Option<CommandCache>,
+}
+
+impl Default for ToolRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl ToolRegistry {
+    /// Creates a registry without tools.
+    pub fn empty() -> Self {
+        Self {
+            tools: Vec::new(),
+            cache: None,
+        }
+    }
+
+    /// Creates a registry of the built-in tools.
+    pub fn builtin() -> Self {
+        let registry = Self::empty()
+            .register(TreeTool)
+            .register(ShowFileTool(FileView::Whole))
+            .register(ShowFileTool(FileView::Signatures));
+        #[cfg(feature = "outline")]
+        let registry = registry.register(ShowFileTool(FileView::Outline));
+        registry
+            .register(SearchTool)
+            .register(GrepTool)
+            .register(CoverageTool)
+            .register(BlameTool)
+            .register(RoutesTool)
+            .register(OwnersTool)
+            .register(LicensesTool)
+            .register(BuildConfigTool::Workflows)
+            .register(BuildConfigTool::Containers)
+            .register(BuildConfigTool::Makefiles)
+            .register(RegexTestTool)
+            .register(GlobExpandTool)
+            .register(RunTool)
+            .register(RunRustTool)
+    }
+
+    /// Adds `tool`, replacing a registered tool of the same name.
+    #[must_use]
+    pub fn register(mut self, tool: impl Tool + 'static) -> Self {
+        self.tools.retain(|known| known.name() != tool.name());
+        self.tools.push(Box::new(tool));
+        self
+    }
+
+    /// Serves file-reading commands from `cache`, storing the results of those that miss.
+    #[must_use]
+    pub fn with_cache(mut self, cache: CommandCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Returns the tool named `name`, if registered.
+    pub fn get(&self, name: &str) -> Option<&dyn Tool> {
+        self.tools
+            .iter()
+            .find(|tool| tool.name() == name)
+            .map(AsRef::as_ref)
+    }
+
+    /// Returns the names of the registered tools, in registration order.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.tools.iter().map(|tool| tool.name()).collect()
+    }
+
+    /// Returns the system prompt of the planning steps, describing the tools `permitted`
+    /// accepts.
+    pub fn planner_prompt(&self, permitted: impl Fn(&str) -> bool) -> String {
+        let mut prompt = PLANNER_PROMPT.to_string();
+        for tool in self.tools.iter().filter(|tool| permitted(tool.name())) {
+            prompt.push_str(&format!("\n- '{}' to {}", tool.usage(), tool.description()));
+        }
+        prompt
+    }
+
+    /// Runs a planned `command` in `context`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AgentError::UnknownCommand` if no tool has the command's name,
+    /// `AgentError::InvalidArguments` if the arguments do not match the tool's schema, and
+    /// the tool's error if it fails.
+    pub(crate) fn run(
+        &self,
+        command: &str,
+        context: &ToolContext<'_>,
+    ) -> Result<ToolOutput, AgentError> {
+        let started = Instant::now();
+        let command = command.trim_start();
+        let (name, rest) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        let tool = self
+            .get(name)
+            .ok_or_else(|| AgentError::UnknownCommand(command.to_string()))?;
+        let args = parse_arguments(tool.arguments(), rest).ok_or_else(|| {
+            AgentError::InvalidArguments {
+                command: command.to_string(),
+                usage: tool.usage(),
+            }
+        })?;
+
+        // The same command may read different files in another package or with exclusions
+        let key = format!(
+            "{}\n{}\n{command}",
+            context
+                .base
+                .map(Path::display)
+                .map(|base| base.to_string())
+                .unwrap_or_default(),
+            context.excluded.globs().join(" ")
+        );
+        let cache = self.cache.as_ref().filter(|_| tool.reads_files());
+        let result = match cache.and_then(|cache| cache.get(&key)) {
+            Some(result) => result,
+            None => {
+                let _permit = tool
+                    .reads_files()
+                    .then(|| scheduler::global().acquire(Resource::FileRead));
+                let result = tool.execute(&args, context)?;
+                if let Some(cache) = cache {
+                    cache.insert(key, result.clone());
+                }
+                result
+            }
+        };
+        Ok(ToolOutput {
+            text: result.text,
+            file: result.file,
+            duration_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+        })
+    }
+}
+
+/// Reads the arguments of `rest` according to `schema`, or returns `None` if a required
+/// argument is missing.
+///
+/// Words beyond the schema are ignored.
+fn parse_arguments<'a>(schema: &[Argument], rest: &'a str) -> Option<Vec<&'a str>> {
+    let mut args = Vec::with_capacity(schema.len());
+    let mut rest = rest.trim_start();
+    for argument in schema {
+        let value = match argument.kind {
+            ArgumentKind::Word | ArgumentKind::OptionalWord => {
+                let (word, remainder) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                rest = remainder.trim_start();
+                word
+            }
+            ArgumentKind::Rest | ArgumentKind::OptionalRest => std::mem::take(&mut rest),
+        };
+        if value.is_empty() {
+            if argument.is_required() {
+                return None;
+            }
+            break;
+        }
+        args.push(value);
+    }
+    Some(args)
+}
+
+/// Resolve a path from the plan against the package directory, if any
+pub(crate) fn resolve_path(base: Option<&Path>, path: &str) -> PathBuf {
+    match base {
+        Some(dir) => dir.join(path),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Resolves the directory argument at `index` of `args`, the current directory if it is
+/// left out, and checks that it exists.
+fn existing_dir(
+    args: &[&str],
+    index: usize,
+    context: &ToolContext<'_>,
+) -> Result<PathBuf, AgentError> {
+    let dir = context.resolve(args.get(index).copied().unwrap_or("."))?;
+    if dir.exists() {
+        Ok(dir)
+    } else {
+        Err(AgentError::PathNotFound(dir))
+    }
+}
+
+/// The result of reading an excluded `path`
+///
+/// Planners may still name excluded paths, which must not fail the whole query.
+fn excluded(path: &Path) -> ToolResult {
+    format!("{} is excluded from this question", path.display()).into()
+}
+
+/// Renders the tree of `path`, reusing the one computed by `nishiogi warm` if the files
+/// have not changed since
+fn directory_tree(path: &Path) -> String {
+    #[cfg(feature = "sessions")]
+    if let Some(tree) = crate::warm::cached_tree(path) {
+        return tree;
+    }
+    generate_tree(path, "", None, None)
+}
+
+/// `tree <dir>`
+struct TreeTool;
+
+impl Tool for TreeTool {
+    fn name(&self) -> &'static str {
+        "tree"
+    }
+
+    fn description(&self) -> &'static str {
+        "show directory structure"
+    }
+
+    fn arguments(&self) -> &'static [Argument] {
+        const ARGUMENTS: &[Argument] = &[Argument::rest("dir")];
+        ARGUMENTS
+    }
+
+    fn execute(&self, args: &[&str], context: &ToolContext<'_>) -> Result<ToolResult, AgentError> {
+        let dir = existing_dir(args, 0, context)?;
+        if org_policy::global().is_forbidden(&dir) {
+            return Err(AgentError::PathForbidden(dir));
+        }
+        if context.is_excluded(&dir) {
+            return Ok(excluded(&dir));
+        }
+        if context.excluded.is_empty() && !org_policy::global().forbids_paths() {
+            Ok(directory_tree(&dir).into())
+        } else {
+            Ok(generate_tree_hiding(&dir, &|path| context.is_hidden(path)).into())
+        }
+    }
+}
+
+/// What of a file a [`ShowFileTool`] displays
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileView {
+    /// The contents, or a numbered line range of them
+    Whole,
+    /// The signatures and doc comments of its definitions
+    Signatures,
+    /// The signatures of its definitions, nested and numbered, from its syntax tree
+    #[cfg(feature = "outline")]
+    Outline,
+}
+
+/// `show_file <path>[:<start>-<end>]`, `show_signatures <path>` and `outline <path>`
+struct ShowFileTool(FileView);
+
+impl Tool for ShowFileTool {
+    fn name(&self) -> &'static str {
+        match self.0 {
+            FileView::Whole => "show_file",
+            FileView::Signatures => "show_signatures",
+            #[cfg(feature = "outline")]
+            FileView::Outline => "outline",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self.0 {
+            FileView::Whole => "display file contents; append a line range to the path, as in src/main.rs:100-180, to display only those lines, numbered",
+            FileView::Signatures => "display only the signatures and doc comments of a file's definitions, without their bodies",
+            #[cfg(feature = "outline")]
+            FileView::Outline => "list the functions, types and impl blocks of a Rust, Python or TypeScript file with their line numbers, to pick a range for show_file",
+        }
+    }
+
+    fn arguments(&self) -> &'static [Argument] {
+        const ARGUMENTS: &[Argument] = &[Argument::rest("path")];
+        ARGUMENTS
+    }
+
+    fn execute(&self, args: &[&str], context: &ToolContext<'_>) -> Result<ToolResult, AgentError> {
+        let (arg, range) = if self.0 == FileView::Whole {
+            split_range(args[0]).map_err(AgentError::Other)?
+        } else {
+            (args[0], None)
+        };
+        let path = context.resolve(arg)?;
+        if org_policy::global().is_forbidden(&path) {
+            return Err(AgentError::PathForbidden(path));
+        }
+        if context.is_excluded(&path) {
+            return Ok(excluded(&path));
+        }
+
+        let content = match read_file_content(&path) {
+            Ok(content) => content,
+            Err(FileReadError::NotFound) => return Err(AgentError::PathNotFound(path)),
+            Err(FileReadError::IsDirectory) => return Err(AgentError::PathIsDirectory(path)),
+            Err(FileReadError::Io(io_err)) => return Err(AgentError::IoError(io_err)),
+        };
+        let total = content.lines().count();
+        if let Some(range) = range
+            && range.start > total
+        {
+            return Ok(format!("{} has only {total} lines", path.display()).into());
+        }
+        let range = range.map(|range| LineRange {
+            start: range.start,
+            end: range.end.min(total),
+        });
+        let text = match (self.0, range) {
+            (_, Some(range)) => format!(
+                "Lines {}-{} of {} ({total} lines):\n{}",
+                range.start,
+                range.end,
+                path.display(),
+                number_lines(&content, range)
+            ),
+            (FileView::Whole, None) => content.clone(),
+            (FileView::Signatures, None) => signature_view(&path, &content, total),
+            // Files without a grammar are outlined by their signatures instead
+            #[cfg(feature = "outline")]
+            (FileView::Outline, None) => crate::outline::outline(&path, &content).map_or_else(
+                || signature_view(&path, &content, total),
+                |outline| format!("Outline of {} ({total} lines):\n{outline}", path.display()),
+            ),
+        };
+        Ok(ToolResult {
+            text,
+            file: Some(FileProvenance {
+                sha256: sha256_hex(content.as_bytes()),
+                path,
+                range,
+            }),
+        })
+    }
+}
+
+/// Renders the signatures and doc comments of `content`, the `total` lines of `path`
+fn signature_view(path: &Path, content: &str, total: usize) -> String {
+    match Language::of(path) {
+        Some(language) => format!(
+            "Signatures and doc comments of {} ({} lines; bodies omitted):\n{}",
+            path.display(),
+            total,
+            signatures(language, content)
+        ),
+        None => outline(path, content).map_or_else(
+            || content.to_string(),
+            |outline| {
+                format!(
+                    "Definitions found by pattern in {} ({} lines; bodies and doc comments omitted):\n{outline}",
+                    path.display(),
+                    total
+                )
+            },
+        ),
+    }
+}
+
+/// Compiles a pattern argument of `tool`
+fn pattern(tool: &str, pattern: &str) -> Result<Regex, AgentError> {
+    Regex::new(pattern)
+        .map_err(|e| AgentError::Other(format!("Invalid {tool} pattern {pattern}: {e}")))
+}
+
+/// `search <regex> [dir]`
+struct SearchTool;
+
+impl Tool for SearchTool {
+    fn name(&self) -> &'static str {
+        "search"
+    }
+
+    fn description(&self) -> &'static str {
+        "find ranked snippets of matching code (the regex must not contain spaces; use \\s instead)"
+    }
+
+    fn arguments(&self) -> &'static [Argument] {
+        const ARGUMENTS: &[Argument] = &[Argument::word("regex"), Argument::optional_word("dir")];
+        ARGUMENTS
+    }
+
+    fn execute(&self, args: &[&str], context: &ToolContext<'_>) -> Result<ToolResult, AgentError> {
+        let dir = existing_dir(args, 1, context)?;
+        let pattern = pattern("search", args[0])?;
+        let mut snippets = search(&dir, &pattern, SearchOptions::default());
+        snippets.retain(|snippet| !context.is_hidden(&snippet.path));
+        Ok(render_snippets(&snippets).into())
+    }
+}
+
+/// `grep <regex> [path]`
+struct GrepTool;
+
+impl Tool for GrepTool {
+    fn name(&self) -> &'static str {
+        "grep"
+    }
+
+    fn description(&self) -> &'static str {
+        "list every matching line of a file or directory with its line number, for finding where something is defined or used (the regex must not contain spaces)"
+    }
+
+    fn arguments(&self) -> &'static [Argument] {
+        const ARGUMENTS: &[Argument] = &[Argument::word("regex"), Argument::optional_word("path")];
+        ARGUMENTS
+    }
+
+    fn execute(&self, args: &[&str], context: &ToolContext<'_>) -> Result<ToolResult, AgentError> {
+        let path = existing_dir(args, 1, context)?;
+        let pattern = pattern("grep", args[0])?;
+        let (mut matches, mut total) = grep(&path, &pattern, MAX_LINE_MATCHES);
+        let before = matches.len();
+        matches.retain(|found| !context.is_hidden(&found.path));
+        total -= before - matches.len();
+        Ok(render_matches(&matches, total).into())
+    }
+}
+
+/// `coverage [path]`
+struct CoverageTool;
+
+impl Tool for CoverageTool {
+    fn name(&self) -> &'static str {
+        "coverage"
+    }
+
+    fn description(&self) -> &'static str {
+        "show measured test coverage of the files under a path from the project's coverage report"
+    }
+
+    fn arguments(&self) -> &'static [Argument] {
+        const ARGUMENTS: &[Argument] = &[Argument::optional_rest("path")];
+        ARGUMENTS
+    }
+
+    fn execute(&self, args: &[&str], context: &ToolContext<'_>) -> Result<ToolResult, AgentError> {
+        let root = repo_root();
+        let filter = context.resolve(args.first().map_or("", |path| path.trim()))?;
+        let filter = filter.strip_prefix(&root).unwrap_or(&filter);
+        let text = match find_report(&root) {
+            Some(mut report) => {
+                report.files.retain(|file| !context.is_hidden(&file.path));
+                render_coverage(&report, &root, &filter.to_string_lossy())
+            }
+            None => missing_report_message(),
+        };
+        Ok(text.into())
+    }
+}
+
+/// `blame <path> [start-end]`
+struct BlameTool;
+
+impl Tool for BlameTool {
+    fn name(&self) -> &'static str {
+        "blame"
+    }
+
+    fn description(&self) -> &'static str {
+        "show the commits (with their messages and pull request references) that last changed lines of a file, or the file's latest commits without a range; use it for questions about why code exists or how it came to be"
+    }
+
+    fn arguments(&self) -> &'static [Argument] {
+        const ARGUMENTS: &[Argument] =
+            &[Argument::word("path"), Argument::optional_word("start-end")];
+        ARGUMENTS
+    }
+
+    fn reads_files(&self) -> bool {
+        false
+    }
+
+    fn execute(&self, args: &[&str], context: &ToolContext<'_>) -> Result<ToolResult, AgentError> {
+        let path = context.resolve(args[0])?;
+        if !path.is_file() {
+            return Err(AgentError::PathNotFound(path));
+        }
+        if org_policy::global().is_forbidden(&path) {
+            return Err(AgentError::PathForbidden(path));
+        }
+        if context.is_excluded(&path) {
+            return Ok(excluded(&path));
+        }
+        let range = args.get(1).and_then(|range| {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            Some((start.parse().ok()?, end.parse().ok()?))
+        });
+        // An untracked file or a missing git is a result, not a reason to abort the question
+        let text =
+            run_blame(&path, range).unwrap_or_else(|err| format!("No history available: {err}"));
+        Ok(text.into())
+    }
+}
+
+/// `routes [dir]`
+struct RoutesTool;
+
+impl Tool for RoutesTool {
+    fn name(&self) -> &'static str {
+        "routes"
+    }
+
+    fn description(&self) -> &'static str {
+        "list the HTTP endpoints declared with axum, actix-web, Express or FastAPI and where their handlers are defined; use it for questions about the API a service exposes"
+    }
+
+    fn arguments(&self) -> &'static [Argument] {
+        const ARGUMENTS: &[Argument] = &[Argument::optional_rest("dir")];
+        ARGUMENTS
+    }
+
+    fn execute(&self, args: &[&str], context: &ToolContext<'_>) -> Result<ToolResult, AgentError> {
+        let dir = existing_dir(args, 0, context)?;
+        let mut routes = find_routes(&dir);
+        routes.retain(|route| !context.is_hidden(&dir.join(&route.file)));
+        Ok(render_routes(&routes).into())
+    }
+}
+
+/// `owners <path>`
+struct OwnersTool;
+
+impl Tool for OwnersTool {
+    fn name(&self) -> &'static str {
+        "owners"
+    }
+
+    fn description(&self) -> &'static str {
+        "list the code owners of a file or directory from the CODEOWNERS file and its top committers; use it for questions about who maintains code or who to ask about it"
+    }
+
+    fn arguments(&self) -> &'static [Argument] {
+        const ARGUMENTS: &[Argument] = &[Argument::rest("path")];
+        ARGUMENTS
+    }
+
+    fn reads_files(&self) -> bool {
+        false
+    }
+
+    fn execute(&self, args: &[&str], context: &ToolContext<'_>) -> Result<ToolResult, AgentError> {
+        let path = context.resolve(args[0].trim())?;
+        if !path.exists() {
+            return Err(AgentError::PathNotFound(path));
+        }
+        if org_policy::global().is_forbidden(&path) {
+            return Err(AgentError::PathForbidden(path));
+        }
+        if context.is_excluded(&path) {
+            return Ok(excluded(&path));
+        }
+        let root = repo_root();
+        let path = path.canonicalize()?;
+        Ok(run_owners(&root.canonicalize()?, &path).into())
+    }
+}
+
+/// `licenses [dir]`
+struct LicensesTool;
+
+impl Tool for LicensesTool {
+    fn name(&self) -> &'static str {
+        "licenses"
+    }
+
+    fn description(&self) -> &'static str {
+        "list the licenses declared by manifests and LICENSE files, vendored dependencies included, grouped by license with copyleft licenses first; use it for questions about licensing instead of reading manifests"
+    }
+
+    fn arguments(&self) -> &'static [Argument] {
+        const ARGUMENTS: &[Argument] = &[Argument::optional_rest("dir")];
+        ARGUMENTS
+    }
+
+    fn execute(&self, args: &[&str], context: &ToolContext<'_>) -> Result<ToolResult, AgentError> {
+        let dir = existing_dir(args, 0, context)?;
+        let mut sources = find_licenses(&dir);
+        sources.retain(|source| !context.is_hidden(&dir.join(&source.path)));
+        Ok(render_licenses(&sources).into())
+    }
+}
+
+/// `workflows [dir]`, `containers [dir]` and `makefiles [dir]`
+enum BuildConfigTool {
+    /// GitHub Actions workflows
+    Workflows,
+    /// Dockerfiles and compose files
+    Containers,
+    /// Makefiles
+    Makefiles,
+}
+
+impl Tool for BuildConfigTool {
+    fn name(&self) -> &'static str {
+        match self {
+            BuildConfigTool::Workflows => "workflows",
+            BuildConfigTool::Containers => "containers",
+            BuildConfigTool::Makefiles => "makefiles",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            BuildConfigTool::Workflows => {
+                "outline the GitHub Actions workflows (triggers, jobs, job dependencies, matrices, steps); use it for questions about how the project is built, tested, or deployed before reading workflow files whole"
+            }
+            BuildConfigTool::Containers => {
+                "summarize the Dockerfiles (stages, base images, exposed ports, environment variables, copied paths) and compose services and flag issues such as secrets baked into image layers; use it before reading those files whole"
+            }
+            BuildConfigTool::Makefiles => {
+                "list the Makefile targets with their prerequisites and recipes; use it before reading Makefiles whole"
+            }
+        }
+    }
+
+    fn arguments(&self) -> &'static [Argument] {
+        const ARGUMENTS: &[Argument] = &[Argument::optional_rest("dir")];
+        ARGUMENTS
+    }
+
+    fn execute(&self, args: &[&str], context: &ToolContext<'_>) -> Result<ToolResult, AgentError> {
+        let dir = existing_dir(args, 0, context)?;
+        let hidden = &|path: &Path| context.is_hidden(path);
+        let text = match self {
+            BuildConfigTool::Workflows => run_workflows(&dir, hidden),
+            BuildConfigTool::Containers => run_containers(&dir, hidden),
+            BuildConfigTool::Makefiles => run_makefiles(&dir, hidden),
+        };
+        Ok(text.into())
+    }
+}
+
+/// `regex_test <regex> <sample>`
+struct RegexTestTool;
+
+impl Tool for RegexTestTool {
+    fn name(&self) -> &'static str {
+        "regex_test"
+    }
+
+    fn description(&self) -> &'static str {
+        "check whether a regex matches a sample text and what it captures instead of guessing (the regex must not contain spaces; the sample is the rest of the command)"
+    }
+
+    fn arguments(&self) -> &'static [Argument] {
+        const ARGUMENTS: &[Argument] =
+            &[Argument::word("regex"), Argument::optional_rest("sample")];
+        ARGUMENTS
+    }
+
+    fn execute(&self, args: &[&str], _context: &ToolContext<'_>) -> Result<ToolResult, AgentError> {
+        Ok(regex_test(args[0], args.get(1).copied().unwrap_or_default()).into())
+    }
+}
+
+/// `glob_expand <glob>`
+struct GlobExpandTool;
+
+impl Tool for GlobExpandTool {
+    fn name(&self) -> &'static str {
+        "glob_expand"
+    }
+
+    fn description(&self) -> &'static str {
+        "list the files a glob pattern such as `src/**/*.rs` matches, to check an assumption about which files exist"
+    }
+
+    fn arguments(&self) -> &'static [Argument] {
+        const ARGUMENTS: &[Argument] = &[Argument::rest("glob")];
+        ARGUMENTS
+    }
+
+    fn execute(&self, args: &[&str], context: &ToolContext<'_>) -> Result<ToolResult, AgentError> {
+        Ok(
+            glob_expand(&context.resolve(".")?, args[0].trim(), &|path| {
+                context.is_hidden(path)
+            })
+            .into(),
+        )
+    }
+}
+
+/// `run <shell command>`
+struct RunTool;
+
+impl Tool for RunTool {
+    fn name(&self) -> &'static str {
+        "run"
+    }
+
+    fn description(&self) -> &'static str {
+        "run a command such as a test or a build in the repository root; it runs in a sandbox without network access that can only write inside the repository, and is killed after 5 minutes"
+    }
+
+    fn arguments(&self) -> &'static [Argument] {
+        const ARGUMENTS: &[Argument] = &[Argument::rest("shell command")];
+        ARGUMENTS
+    }
+
+    fn reads_files(&self) -> bool {
+        false
+    }
+
+    fn execute(&self, args: &[&str], context: &ToolContext<'_>) -> Result<ToolResult, AgentError> {
+        // Shell commands only run confined to the repository
+        let sandbox = Sandbox::detect().ok_or(AgentError::SandboxUnavailable)?;
+        let root = context.base.unwrap_or(Path::new(".")).canonicalize()?;
+        if let Some(path) = forbidden_operand(org_policy::global(), &root, args[0]) {
+            return Err(AgentError::PathForbidden(path));
+        }
+        // Wildcards and scripts can still reach forbidden paths, so the sandbox hides them
+        let hidden = org_policy::global().forbidden_in(&root);
+        let _permit = scheduler::global().acquire(Resource::Subprocess);
+        let command = sandbox.command_hiding(&root, args[0], &hidden);
+        let (status, output) = output_within(command, RUN_TIMEOUT)?;
+        let status = match status.map(|status| status.code()) {
+            Some(Some(code)) => format!("exit status {code}"),
+            Some(None) => "terminated by a signal".to_string(),
+            None => format!("killed after {} s", RUN_TIMEOUT.as_secs()),
+        };
+        Ok(format!(
+            "{status} (sandboxed with {sandbox})\n{}",
+            String::from_utf8_lossy(&output)
+        )
+        .into())
+    }
+}
+
+/// Returns the first operand of the shell `command` naming a path that `policy` forbids,
+/// relative to `root`
+fn forbidden_operand(policy: &OrgPolicy, root: &Path, command: &str) -> Option<PathBuf> {
+    if !policy.forbids_paths() {
+        return None;
+    }
+    command
+        .split(|c: char| c.is_whitespace() || "'\"`;|&<>()=".contains(c))
+        .filter(|word| !word.is_empty() && !word.starts_with('-'))
+        .map(|word| root.join(word))
+        .find(|path| policy.is_forbidden(path))
+}
+
+/// `run_rust <code>`
+struct RunRustTool;
+
+impl Tool for RunRustTool {
+    fn name(&self) -> &'static str {
+        "run_rust"
+    }
+
+    fn description(&self) -> &'static str {
+        "compile and run a small self-contained Rust program that uses only the standard library (statements without `fn main` are wrapped in one) and see its output; use it to verify a claim about behavior, such as what a standard library function returns, before asserting it"
+    }
+
+    fn arguments(&self) -> &'static [Argument] {
+        const ARGUMENTS: &[Argument] = &[Argument::rest("code")];
+        ARGUMENTS
+    }
+
+    fn reads_files(&self) -> bool {
+        false
+    }
+
+    fn execute(&self, args: &[&str], _context: &ToolContext<'_>) -> Result<ToolResult, AgentError> {
+        // Snippets only run confined to their temporary directory
+        let sandbox = Sandbox::detect().ok_or(AgentError::SandboxUnavailable)?;
+        let _permit = scheduler::global().acquire(Resource::Subprocess);
+        Ok(render_outcome(&run_snippet(&sandbox, args[0])?, &sandbox).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::plan::COMMANDS;
+
+    /// A tool added by an embedder
+    struct Echo;
+
+    impl Tool for Echo {
+        fn name(&self) -> &'static str {
+            "echo"
+        }
+
+        fn description(&self) -> &'static str {
+            "repeat a text"
+        }
+
+        fn arguments(&self) -> &'static [Argument] {
+            const ARGUMENTS: &[Argument] =
+                &[Argument::word("times"), Argument::optional_rest("text")];
+            ARGUMENTS
+        }
+
+        fn execute(
+            &self,
+            args: &[&str],
+            _context: &ToolContext<'_>,
+        ) -> Result<ToolResult, AgentError> {
+            let times: usize = args[0].parse().unwrap_or(1);
+            Ok(args
+                .get(1)
+                .copied()
+                .unwrap_or_default()
+                .repeat(times)
+                .into())
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut commands = COMMANDS.to_vec();
+        // The outline tool needs the tree-sitter grammars
+        if cfg!(not(feature = "outline")) {
+            commands.retain(|&command| command != "outline");
+        }
+        assert_eq!(ToolRegistry::builtin().names(), commands);
+
+        let excluded = Exclusions::default();
+        let context = ToolContext {
+            base: None,
+            excluded: &excluded,
+            sandbox: None,
+        };
+        let registry = ToolRegistry::builtin().register(Echo);
+        assert_eq!(
+            registry
+                .run("echo 2  a b", &context)
+                .expect("Echo runs")
+                .text,
+            "a ba b"
+        );
+        assert!(matches!(
+            registry.run("echo", &context),
+            Err(AgentError::InvalidArguments { .. })
+        ));
+        assert!(matches!(
+            registry.run("cat src/main.rs", &context),
+            Err(AgentError::UnknownCommand(_))
+        ));
+        assert_eq!(
+            registry.get("search").expect("search is built in").usage(),
+            "search <regex> [dir]"
+        );
+
+        let prompt = registry.planner_prompt(|tool| tool == "tree" || tool == "echo");
+        assert!(prompt.starts_with(PLANNER_PROMPT));
+        assert!(prompt.ends_with(
+            "\n- 'tree <dir>' to show directory structure\n- 'echo <times> [text]' to repeat a text"
+        ));
+    }
+
+    #[test]
+    fn test_exclusions() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        for path in ["src/lib.rs", "tests/api.rs"] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().expect("Has a parent")).expect("Failed to create dir");
+            fs::write(&path, "fn api() {}\n").expect("Failed to write file");
+        }
+        let excluded = Exclusions::new(vec!["tests/".to_string()]);
+        let sandbox = RootSandbox::new(dir.path());
+        let context = ToolContext {
+            base: Some(dir.path()),
+            excluded: &excluded,
+            sandbox: Some(&sandbox),
+        };
+        let registry = ToolRegistry::builtin();
+
+        let tree = registry.run("tree .", &context).expect("tree runs").text;
+        assert!(tree.contains("lib.rs") && !tree.contains("tests"));
+        let grep = registry.run("grep api", &context).expect("grep runs").text;
+        assert!(grep.contains("lib.rs") && !grep.contains("api.rs"));
+        let read = registry
+            .run("show_file tests/api.rs", &context)
+            .expect("Excluded files do not fail the query");
+        assert!(read.text.ends_with("is excluded from this question"));
+        assert_eq!(read.file, None);
+        assert!(matches!(
+            registry.run("show_file src/../../secret", &context),
+            Err(AgentError::PathOutsideSandbox(_))
+        ));
+    }
+
+    #[test]
+    fn test_show_file_range() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let content: String = (1..=200).map(|n| format!("line {n}\n")).collect();
+        fs::write(dir.path().join("big.txt"), &content).expect("Failed to write file");
+        let context = ToolContext {
+            base: Some(dir.path()),
+            excluded: &Exclusions::default(),
+            sandbox: None,
+        };
+        let registry = ToolRegistry::builtin();
+
+        let read = registry
+            .run("show_file big.txt:100-102", &context)
+            .expect("show_file runs");
+        assert_eq!(
+            read.text,
+            "Lines 100-102 of big.txt (200 lines):\n  100 | line 100\n  101 | line 101\n  102 | line 102\n"
+                .replace("big.txt", &dir.path().join("big.txt").display().to_string())
+        );
+        let file = read.file.expect("Reads are recorded");
+        assert_eq!(
+            file.range,
+            Some(LineRange {
+                start: 100,
+                end: 102
+            })
+        );
+        assert_eq!(file.sha256, sha256_hex(content.as_bytes()));
+
+        let tail = registry
+            .run("show_file big.txt:199-250", &context)
+            .expect("show_file runs");
+        assert!(tail.text.ends_with("  199 | line 199\n  200 | line 200\n"));
+        let past = registry
+            .run("show_file big.txt:300-310", &context)
+            .expect("show_file runs");
+        assert!(past.text.ends_with("has only 200 lines"));
+        assert!(registry.run("show_file big.txt:9-1", &context).is_err());
+    }
+
+    #[cfg(feature = "outline")]
+    #[test]
+    fn test_outline() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        fs::write(
+            dir.path().join("lib.rs"),
+            "struct Cache;\n\nimpl Cache {\n    fn get(&self) -> u8 {\n        0\n    }\n}\n",
+        )
+        .expect("Failed to write file");
+        fs::write(dir.path().join("main.go"), "func main() {\n}\n").expect("Failed to write file");
+        let context = ToolContext {
+            base: Some(dir.path()),
+            excluded: &Exclusions::default(),
+            sandbox: None,
+        };
+        let registry = ToolRegistry::builtin();
+
+        let outline = registry
+            .run("outline lib.rs", &context)
+            .expect("outline runs");
+        assert!(outline.text.ends_with(
+            "(7 lines):\n    1  struct Cache\n    3  impl Cache\n    4    fn get(&self) -> u8\n"
+        ));
+        assert!(outline.file.is_some());
+        let fallback = registry
+            .run("outline main.go", &context)
+            .expect("outline runs");
+        assert!(fallback.text.starts_with("Signatures and doc comments"));
+    }
+
+    #[test]
+    fn test_command_cache() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        fs::write(dir.path().join("lib.rs"), "fn api() {}\n").expect("Failed to write file");
+        let excluded = Exclusions::default();
+        let context = ToolContext {
+            base: Some(dir.path()),
+            excluded: &excluded,
+            sandbox: None,
+        };
+        let cache = CommandCache::new();
+        let first = ToolRegistry::builtin().with_cache(cache.clone());
+        let second = ToolRegistry::builtin().with_cache(cache.clone());
+
+        let read = first
+            .run("show_file lib.rs", &context)
+            .expect("show_file runs");
+        fs::write(dir.path().join("lib.rs"), "fn changed() {}\n").expect("Failed to write file");
+        let cached = second
+            .run("show_file lib.rs", &context)
+            .expect("show_file runs");
+        assert_eq!(cached.text, read.text);
+        assert_eq!(cached.file, read.file);
+        assert_eq!(cache.hits(), 1);
+
+        let other = ToolContext {
+            base: Some(dir.path()),
+            excluded: &Exclusions::new(vec!["*.md".to_string()]),
+            sandbox: None,
+        };
+        let fresh = second
+            .run("show_file lib.rs", &other)
+            .expect("show_file runs");
+        assert!(fresh.text.contains("changed"));
+        assert_eq!(cache.hits(), 1);
+    }
+
+    #[test]
+    fn test_forbidden_operand() {
+        let policy = OrgPolicy::new(crate::org_policy::OrgPolicyConfig {
+            forbidden_paths: vec!["secrets/**".to_string(), ".env".to_string()],
+            ..Default::default()
+        })
+        .expect("Failed to compile policy");
+        let root = Path::new("/repo");
+        assert_eq!(
+            forbidden_operand(&policy, root, "cat secrets/x"),
+            Some(PathBuf::from("/repo/secrets/x"))
+        );
+        assert_eq!(
+            forbidden_operand(&policy, root, "cd src && grep -r KEY ../.env"),
+            Some(PathBuf::from("/repo/../.env"))
+        );
+        assert_eq!(
+            forbidden_operand(&policy, root, "cargo test -- secrets_are_hidden"),
+            None
+        );
+        assert_eq!(
+            forbidden_operand(&OrgPolicy::default(), root, "cat secrets/x"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_arguments() {
+        let schema = [Argument::word("regex"), Argument::optional_word("dir")];
+        assert_eq!(
+            parse_arguments(&schema, " fn\\s+main  src extra"),
+            Some(vec!["fn\\s+main", "src"])
+        );
+        assert_eq!(parse_arguments(&schema, "main"), Some(vec!["main"]));
+        assert_eq!(parse_arguments(&schema, ""), None);
+        assert_eq!(
+            parse_arguments(&[Argument::rest("path")], "src/my file.rs"),
+            Some(vec!["src/my file.rs"])
+        );
+    }
+}
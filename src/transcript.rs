@@ -0,0 +1,582 @@
+//! # Session Transcripts
+//!
+//! This module converts sessions to and from files that can be shared outside the local
+//! database: attached to an issue, pasted into a pull request, or collected as fine-tuning
+//! and evaluation data. Three formats are supported:
+//!
+//! - Markdown, for people. Each answer is preceded by an HTML comment carrying its
+//!   provenance, so an imported transcript can still be checked for staleness.
+//! - JSON, the session record exactly as it is stored.
+//! - ShareGPT, the conversation format most fine-tuning tools read. It keeps only the
+//!   questions and answers.
+//!
+//! Two sessions can also be rendered side by side with [`compare`], e.g. a session and its
+//! fork asked with another model or other overrides, to see how their answers differ.
+
+use std::{error::Error, fmt, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::session::{Provenance, SessionEntry, SessionRecord, SESSION_VERSION};
+
+/// Start of the comment carrying the session metadata in Markdown transcripts.
+const SESSION_MARKER: &str = "<!-- nishiogi:session ";
+
+/// Start of the comment carrying an entry's metadata in Markdown transcripts.
+const ENTRY_MARKER: &str = "<!-- nishiogi:entry ";
+
+/// End of the metadata comments.
+const MARKER_END: &str = " -->";
+
+/// Heading preceding the question of an entry.
+const QUESTION_HEADING: &str = "### Question\n";
+
+/// Heading preceding the answer of an entry.
+const ANSWER_HEADING: &str = "### Answer\n";
+
+/// A file format for sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Readable Markdown with the provenance in comments.
+    Markdown,
+    /// The stored session record.
+    Json,
+    /// ShareGPT conversations.
+    ShareGpt,
+}
+
+/// Errors that can occur while importing a transcript.
+#[derive(Debug)]
+pub enum TranscriptError {
+    /// The transcript is not valid JSON of the expected shape.
+    Serde(serde_json::Error),
+    /// The transcript was exported by a newer version of nishiogi.
+    UnsupportedVersion(usize),
+    /// The transcript is not in the expected format.
+    Malformed(String),
+}
+
+impl fmt::Display for TranscriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscriptError::Serde(err) => write!(f, "Malformed transcript: {err}"),
+            TranscriptError::UnsupportedVersion(version) => write!(
+                f,
+                "Transcript uses session format version {version}, but this nishiogi supports up to {SESSION_VERSION}; upgrade nishiogi"
+            ),
+            TranscriptError::Malformed(reason) => write!(f, "Malformed transcript: {reason}"),
+        }
+    }
+}
+
+impl Error for TranscriptError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TranscriptError::Serde(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for TranscriptError {
+    fn from(error: serde_json::Error) -> Self {
+        TranscriptError::Serde(error)
+    }
+}
+
+/// Session metadata carried by a Markdown transcript.
+#[derive(Serialize, Deserialize)]
+struct SessionHeader {
+    version: usize,
+    id: String,
+    created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    working_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    forked_from: Option<String>,
+}
+
+/// Entry metadata carried by a Markdown transcript; the question and answer are the text.
+#[derive(Serialize, Deserialize)]
+struct EntryHeader {
+    answered_at: DateTime<Utc>,
+    provenance: Provenance,
+    #[serde(default)]
+    truncated: bool,
+}
+
+/// A ShareGPT conversation.
+#[derive(Serialize, Deserialize)]
+struct Conversation {
+    #[serde(default)]
+    id: Option<String>,
+    conversations: Vec<Turn>,
+}
+
+/// A turn of a ShareGPT conversation.
+#[derive(Serialize, Deserialize)]
+struct Turn {
+    from: String,
+    value: String,
+}
+
+/// Renders `record` in `format`.
+pub fn export(record: &SessionRecord, format: Format) -> String {
+    match format {
+        Format::Markdown => export_markdown(record),
+        Format::Json => serde_json::to_string_pretty(record).unwrap_or_default(),
+        Format::ShareGpt => {
+            let mut conversations = Vec::new();
+            for entry in &record.entries {
+                conversations.push(Turn {
+                    from: "human".to_string(),
+                    value: entry.question.clone(),
+                });
+                conversations.push(Turn {
+                    from: "gpt".to_string(),
+                    value: entry.answer.clone(),
+                });
+            }
+            let conversation = Conversation {
+                id: Some(record.id.clone()),
+                conversations,
+            };
+            serde_json::to_string_pretty(&[conversation]).unwrap_or_default()
+        }
+    }
+}
+
+/// Parses the sessions in `data`, written in `format`.
+///
+/// Markdown and JSON transcripts hold one session; ShareGPT files may hold several
+/// conversations, each imported as its own session. Embeddings are not exported, so
+/// imported entries are not offered as reusable answers until asked again.
+///
+/// # Errors
+///
+/// Returns a `TranscriptError` if `data` is not a valid transcript in `format`.
+pub fn import(data: &str, format: Format) -> Result<Vec<SessionRecord>, TranscriptError> {
+    match format {
+        Format::Markdown => import_markdown(data).map(|record| vec![record]),
+        Format::Json => {
+            let record: SessionRecord = serde_json::from_str(data)?;
+            if record.version > SESSION_VERSION {
+                return Err(TranscriptError::UnsupportedVersion(record.version));
+            }
+            Ok(vec![record])
+        }
+        Format::ShareGpt => import_sharegpt(data),
+    }
+}
+
+/// Renders the answers of `left` and `right` side by side, in columns fitting `width`
+/// characters.
+///
+/// Questions asked in both sessions are paired by their text, in the order of `left`;
+/// questions asked in only one of them are shown next to an empty column.
+pub fn compare(left: &SessionRecord, right: &SessionRecord, width: usize) -> String {
+    let column = (width.saturating_sub(3) / 2).max(20);
+    let key = |question: &str| question.trim().to_lowercase();
+    let mut pairs: Vec<(Option<&SessionEntry>, Option<&SessionEntry>)> = Vec::new();
+    let mut unpaired: Vec<&SessionEntry> = right.entries.iter().collect();
+    for entry in &left.entries {
+        let matched = unpaired
+            .iter()
+            .position(|other| key(&other.question) == key(&entry.question))
+            .map(|index| unpaired.remove(index));
+        pairs.push((Some(entry), matched));
+    }
+    pairs.extend(unpaired.into_iter().map(|entry| (None, Some(entry))));
+
+    let label = |record: &SessionRecord| {
+        let models: Vec<&str> = record
+            .entries
+            .iter()
+            .map(|entry| entry.provenance.model.as_str())
+            .filter(|model| !model.is_empty())
+            .fold(Vec::new(), |mut models, model| {
+                if !models.contains(&model) {
+                    models.push(model);
+                }
+                models
+            });
+        if models.is_empty() {
+            record.id.clone()
+        } else {
+            format!("{} ({})", record.id, models.join(", "))
+        }
+    };
+    let mut out = columns(&[&label(left), &label(right)], column);
+    out.push_str(&format!(
+        "{}-+-{}\n",
+        "-".repeat(column),
+        "-".repeat(column)
+    ));
+    for (index, (left, right)) in pairs.into_iter().enumerate() {
+        let question = left.or(right).map_or("", |entry| entry.question.trim());
+        out.push_str(&format!("\n{}. {question}\n", index + 1));
+        let answer = |entry: Option<&SessionEntry>| {
+            entry.map_or_else(|| "(not asked)".to_string(), |entry| entry.answer.clone())
+        };
+        let (left, right) = (answer(left), answer(right));
+        if left == right {
+            out.push_str("(same answer)\n");
+        }
+        out.push_str(&columns(&[&left, &right], column));
+    }
+    out
+}
+
+/// Lays out `cells` side by side as columns of `width` characters.
+pub(crate) fn columns(cells: &[&str], width: usize) -> String {
+    let cells: Vec<Vec<String>> = cells.iter().map(|cell| wrap(cell, width)).collect();
+    let height = cells.iter().map(Vec::len).max().unwrap_or(0);
+    let mut out = String::new();
+    for index in 0..height {
+        let line = cells
+            .iter()
+            .map(|lines| {
+                let line = lines.get(index).map_or("", String::as_str);
+                format!("{line:<width$}")
+            })
+            .collect::<Vec<_>>()
+            .join(" | ");
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Wraps `text` at word boundaries into lines of at most `width` characters, splitting
+/// longer words.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            let used = line.chars().count();
+            if used > 0 && used + 1 + word.len() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            while word.len() > width {
+                lines.push(word.drain(..width).collect());
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.extend(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Serializes `value` for an HTML comment, which must not contain `-->`.
+fn comment_json<T: Serialize>(value: &T) -> String {
+    // `-->` can only occur inside JSON strings, where `>` may be escaped
+    serde_json::to_string(value)
+        .unwrap_or_default()
+        .replace("-->", "--\\u003e")
+}
+
+fn export_markdown(record: &SessionRecord) -> String {
+    let mut out = markdown_header(record);
+    for (index, entry) in record.entries.iter().enumerate() {
+        out.push_str(&markdown_entry(index + 1, entry));
+    }
+    out
+}
+
+/// Renders the start of a Markdown transcript of `record`, without its entries.
+///
+/// Together with [`markdown_entry`], this writes a transcript as the session goes; the
+/// result is the same as exporting the finished session.
+pub fn markdown_header(record: &SessionRecord) -> String {
+    let header = SessionHeader {
+        version: record.version,
+        id: record.id.clone(),
+        created_at: record.created_at,
+        working_dir: record.working_dir.clone(),
+        forked_from: record.forked_from.clone(),
+    };
+    let mut out = format!(
+        "# Session {}\n\n{SESSION_MARKER}{}{MARKER_END}\n\nStarted {}",
+        record.id,
+        comment_json(&header),
+        record.created_at.format("%Y-%m-%d %H:%M UTC")
+    );
+    if let Some(dir) = &record.working_dir {
+        out.push_str(&format!(" in `{}`", dir.display()));
+    }
+    if let Some(parent) = &record.forked_from {
+        out.push_str(&format!(", forked from session {parent}"));
+    }
+    out.push_str(".\n");
+    out
+}
+
+/// Renders `entry` as the `number`th entry of a Markdown transcript.
+pub fn markdown_entry(number: usize, entry: &SessionEntry) -> String {
+    let header = EntryHeader {
+        answered_at: entry.answered_at,
+        provenance: entry.provenance.clone(),
+        truncated: entry.truncated,
+    };
+    let mut out = format!(
+        "\n## {number}. {}\n\n{ENTRY_MARKER}{}{MARKER_END}\n\n{QUESTION_HEADING}\n{}\n\n{ANSWER_HEADING}\n{}\n",
+        entry.question.lines().next().unwrap_or_default(),
+        comment_json(&header),
+        entry.question.trim(),
+        entry.answer.trim()
+    );
+    if entry.truncated {
+        out.push_str("\n_The answer was interrupted before it was final._\n");
+    }
+    if !entry.provenance.files.is_empty() {
+        out.push_str(&format!(
+            "\nAnswered by `{}` from:\n\n",
+            entry.provenance.model
+        ));
+        for file in &entry.provenance.files {
+            match file.range {
+                Some(range) => out.push_str(&format!(
+                    "- `{}` lines {}-{}\n",
+                    file.path.display(),
+                    range.start,
+                    range.end
+                )),
+                None => out.push_str(&format!("- `{}`\n", file.path.display())),
+            }
+        }
+    }
+    out
+}
+
+/// Returns the number of entries in a Markdown transcript.
+pub fn markdown_entry_count(data: &str) -> usize {
+    data.matches(ENTRY_MARKER).count()
+}
+
+/// Returns the JSON of the metadata comment starting `text`, and the text after it.
+fn split_comment(text: &str) -> Option<(&str, &str)> {
+    let end = text.find(MARKER_END)?;
+    Some((&text[..end], &text[end + MARKER_END.len()..]))
+}
+
+fn import_markdown(data: &str) -> Result<SessionRecord, TranscriptError> {
+    let start = data
+        .find(SESSION_MARKER)
+        .ok_or_else(|| TranscriptError::Malformed("no nishiogi session comment".to_string()))?;
+    let (header, mut rest) = split_comment(&data[start + SESSION_MARKER.len()..])
+        .ok_or_else(|| TranscriptError::Malformed("unterminated session comment".to_string()))?;
+    let header: SessionHeader = serde_json::from_str(header)?;
+    if header.version > SESSION_VERSION {
+        return Err(TranscriptError::UnsupportedVersion(header.version));
+    }
+
+    let mut entries = Vec::new();
+    while let Some(start) = rest.find(ENTRY_MARKER) {
+        let (meta, body) = split_comment(&rest[start + ENTRY_MARKER.len()..])
+            .ok_or_else(|| TranscriptError::Malformed("unterminated entry comment".to_string()))?;
+        let meta: EntryHeader = serde_json::from_str(meta)?;
+        // The heading before the next entry's comment belongs to that entry
+        let end = body.find(ENTRY_MARKER).map_or(body.len(), |next| {
+            body[..next].rfind("\n## ").unwrap_or(next)
+        });
+        let text = &body[..end];
+        rest = &body[end..];
+
+        let number = entries.len() + 1;
+        let (question, answer) = text
+            .find(QUESTION_HEADING)
+            .and_then(|start| {
+                let text = &text[start + QUESTION_HEADING.len()..];
+                let split = text.find(ANSWER_HEADING)?;
+                Some((&text[..split], &text[split + ANSWER_HEADING.len()..]))
+            })
+            .ok_or_else(|| {
+                TranscriptError::Malformed(format!("entry {number} lacks a question or answer"))
+            })?;
+        let mut answer = answer.trim();
+        if meta.truncated {
+            answer = answer
+                .split("\n_The answer was interrupted")
+                .next()
+                .unwrap_or_default()
+                .trim_end();
+        }
+        if !meta.provenance.files.is_empty() {
+            answer = answer
+                .rsplit_once("\nAnswered by `")
+                .map_or(answer, |(answer, _)| answer.trim_end());
+        }
+        entries.push(SessionEntry {
+            question: question.trim().to_string(),
+            answer: answer.to_string(),
+            answered_at: meta.answered_at,
+            provenance: meta.provenance,
+            question_embedding: None,
+            truncated: meta.truncated,
+        });
+    }
+
+    Ok(SessionRecord {
+        version: SESSION_VERSION,
+        id: header.id,
+        created_at: header.created_at,
+        working_dir: header.working_dir,
+        entries,
+        forked_from: header.forked_from,
+    })
+}
+
+fn import_sharegpt(data: &str) -> Result<Vec<SessionRecord>, TranscriptError> {
+    let value: serde_json::Value = serde_json::from_str(data)?;
+    let conversations: Vec<Conversation> = if value.is_array() {
+        serde_json::from_value(value)?
+    } else {
+        vec![serde_json::from_value(value)?]
+    };
+
+    let mut records = Vec::new();
+    for (index, conversation) in conversations.into_iter().enumerate() {
+        let mut record = SessionRecord::new(None);
+        record.id = conversation
+            .id
+            .unwrap_or_else(|| format!("{}-{}", record.id, index + 1));
+        let mut question: Option<String> = None;
+        for turn in conversation.conversations {
+            match turn.from.as_str() {
+                "human" | "user" => question = Some(turn.value),
+                "gpt" | "assistant" => {
+                    let question = question.take().ok_or_else(|| {
+                        TranscriptError::Malformed(format!(
+                            "conversation {} has an answer without a question",
+                            index + 1
+                        ))
+                    })?;
+                    record.entries.push(SessionEntry {
+                        question,
+                        answer: turn.value,
+                        answered_at: record.created_at,
+                        provenance: Provenance::default(),
+                        question_embedding: None,
+                        truncated: false,
+                    });
+                }
+                // System prompts and tool turns have no place in a session
+                _ => {}
+            }
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provenance::{FileProvenance, LineRange};
+
+    fn record() -> SessionRecord {
+        let mut record = SessionRecord::new(Some(PathBuf::from("/work/repo")));
+        record.entries.push(SessionEntry {
+            question: "How are sessions saved?".to_string(),
+            answer: "### Summary\n\nAs JSON in SQLite. <!-- not a marker -->".to_string(),
+            answered_at: Utc::now(),
+            provenance: Provenance {
+                model: "gpt-4o".to_string(),
+                files: vec![FileProvenance {
+                    path: PathBuf::from("src/session.rs"),
+                    range: Some(LineRange { start: 1, end: 40 }),
+                    sha256: "abc".to_string(),
+                }],
+                ..Provenance::default()
+            },
+            question_embedding: Some(vec![0.5]),
+            truncated: false,
+        });
+        record.entries.push(SessionEntry {
+            question: "And loaded?".to_string(),
+            answer: "With `load` -->".to_string(),
+            answered_at: Utc::now(),
+            provenance: Provenance::default(),
+            question_embedding: None,
+            truncated: true,
+        });
+        record
+    }
+
+    #[test]
+    fn test_markdown_round_trip() {
+        let record = record();
+        let markdown = export(&record, Format::Markdown);
+        assert!(markdown.contains("## 1. How are sessions saved?"));
+        assert!(markdown.contains("- `src/session.rs` lines 1-40"));
+        assert_eq!(markdown_entry_count(&markdown), 2);
+
+        let imported = import(&markdown, Format::Markdown).expect("Failed to import");
+        let [imported] = imported.as_slice() else {
+            panic!("Expected one session");
+        };
+        assert_eq!(imported.id, record.id);
+        assert_eq!(imported.working_dir, record.working_dir);
+        assert_eq!(imported.entries.len(), 2);
+        for (imported, entry) in imported.entries.iter().zip(&record.entries) {
+            assert_eq!(imported.question, entry.question);
+            assert_eq!(imported.answer, entry.answer);
+            assert_eq!(imported.provenance.files, entry.provenance.files);
+            assert_eq!(imported.truncated, entry.truncated);
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let left = record();
+        let mut right = left.fork(Some(1));
+        right.entries[0].answer = "In a SQLite table of JSON records".to_string();
+        right.entries.push(SessionEntry {
+            question: "Who deletes them?".to_string(),
+            answer: "`clean`".to_string(),
+            ..right.entries[0].clone()
+        });
+        assert_eq!(right.forked_from.as_deref(), Some(left.id.as_str()));
+        assert!(
+            export(&right, Format::Markdown).contains(&format!("forked from session {}", left.id))
+        );
+
+        let rendered = compare(&left, &right, 63);
+        assert!(rendered.starts_with(&format!("{} (gpt-4o)", left.id)));
+        assert!(rendered.contains("\n1. How are sessions saved?\n"));
+        assert!(rendered.contains("### Summary                    | In a SQLite table of JSON\n"));
+        assert!(rendered.contains("With `load` -->                | (not asked)\n"));
+        assert!(rendered.contains("\n3. Who deletes them?\n(not asked)"));
+        assert!(rendered.lines().all(|line| line.chars().count() <= 63));
+
+        assert_eq!(wrap("one two three", 7), ["one two", "three"]);
+        assert_eq!(wrap("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+    }
+
+    #[test]
+    fn test_sharegpt() {
+        let sharegpt = export(&record(), Format::ShareGpt);
+        let imported = import(&sharegpt, Format::ShareGpt).expect("Failed to import");
+        assert_eq!(imported[0].entries[1].answer, "With `load` -->");
+
+        let data = r#"{"conversations": [
+            {"from": "system", "value": "Be brief."},
+            {"from": "human", "value": "Why?"},
+            {"from": "gpt", "value": "Because."}
+        ]}"#;
+        let imported = import(data, Format::ShareGpt).expect("Failed to import");
+        assert_eq!(imported[0].entries.len(), 1);
+        assert_eq!(imported[0].entries[0].question, "Why?");
+        assert!(import(
+            r#"[{"conversations": [{"from": "gpt", "value": "Hi"}]}]"#,
+            Format::ShareGpt
+        )
+        .is_err());
+    }
+}
@@ -47,7 +47,14 @@ pub fn generate_tree(
         None => find_gitignore_patterns(path).unwrap_or_default(),
     };
 
-    generate_tree_with_patterns(path, prefix, &patterns, depth)
+    generate_tree_with_patterns(path, prefix, &patterns, &|_| false, depth)
+}
+
+/// Generates the tree of `path` like [`generate_tree`], leaving out the entries `hidden`
+/// accepts along with everything below them.
+pub(crate) fn generate_tree_hiding(path: &Path, hidden: &dyn Fn(&Path) -> bool) -> String {
+    let patterns = find_gitignore_patterns(path).unwrap_or_default();
+    generate_tree_with_patterns(path, "", &patterns, hidden, None)
 }
 
 /// Internal function that does the actual tree generation with the provided ignore patterns
@@ -55,6 +62,7 @@ fn generate_tree_with_patterns(
     path: &Path,
     prefix: &str,
     ignore: &[Regex],
+    hidden: &dyn Fn(&Path) -> bool,
     depth: Option<usize>,
 ) -> String {
     if let Some(0) = depth {
@@ -82,6 +90,7 @@ fn generate_tree_with_patterns(
             !ignore
                 .iter()
                 .any(|r| r.is_match(&file_name) || r.is_match(&rel_path_str))
+                && !hidden(&entry_path)
         })
         .collect();
     entries.sort_by_key(std::fs::DirEntry::file_name);
@@ -105,6 +114,7 @@ fn generate_tree_with_patterns(
                     &new_path,
                     &new_prefix,
                     ignore,
+                    hidden,
                     new_depth,
                 ));
             }
@@ -114,7 +124,7 @@ fn generate_tree_with_patterns(
 }
 
 /// Finds the repository root by looking for a .git directory
-fn find_repo_root(start_path: &Path) -> Option<PathBuf> {
+pub(crate) fn find_repo_root(start_path: &Path) -> Option<PathBuf> {
     let mut current = start_path.to_path_buf();
 
     loop {
@@ -131,7 +141,7 @@ fn find_repo_root(start_path: &Path) -> Option<PathBuf> {
 }
 
 /// Collects gitignore patterns from all .gitignore files
-fn find_gitignore_patterns(start_path: &Path) -> io::Result<Vec<Regex>> {
+pub(crate) fn find_gitignore_patterns(start_path: &Path) -> io::Result<Vec<Regex>> {
     let repo_root = find_repo_root(start_path).unwrap_or_default();
 
     let mut patterns = Vec::new();
@@ -411,7 +421,7 @@ mod tests {
         let mut gitignore = File::create(&gitignore_path).expect("Failed to create .gitignore");
         use std::io::Write;
         writeln!(gitignore, "# Comment line").expect("Failed to write to .gitignore");
-        writeln!(gitignore, "").expect("Failed to write to .gitignore"); // Empty line
+        writeln!(gitignore).expect("Failed to write to .gitignore"); // Empty line
         writeln!(gitignore, "node_modules/").expect("Failed to write to .gitignore");
         writeln!(gitignore, "*.log").expect("Failed to write to .gitignore");
         writeln!(gitignore, "!important.log").expect("Failed to write to .gitignore"); // Negation
@@ -0,0 +1,142 @@
+//! # Unknown Answers
+//!
+//! When the gathered evidence does not answer a question, the agent must say so in a fixed
+//! form instead of guessing:
+//!
+//! ```text
+//! Cannot determine from the repository.
+//! Missing: where `RETRY_LIMIT` is set in production
+//! Suggest checking: the deployment manifests, which are not in this repository
+//! ```
+//!
+//! The answer prompt asks for this form with [`UNKNOWN_PROMPT`]. The reviewers accept such
+//! an answer only when both fields are filled in (see [`crate::review::precheck`]), and
+//! [`AnswerStatus`] reports it in machine-readable output.
+
+use serde::Serialize;
+
+/// Instructs the model to use the unknown form when the evidence is insufficient.
+pub const UNKNOWN_PROMPT: &str = "If the command results do not contain enough evidence to answer the question, do not guess. Instead answer in exactly this form: a first line 'Cannot determine from the repository.', then a line 'Missing: <the information that is missing>', then a line 'Suggest checking: <where the information could be found or what to run>'.";
+
+/// The first line of an unknown answer, compared case-insensitively.
+const UNKNOWN_OPENING: &str = "cannot determine from the repository";
+
+/// An answer stating that the repository does not answer the question.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Unknown {
+    /// What information would be needed to answer.
+    pub missing: String,
+    /// Where that information could be found.
+    pub suggest_checking: String,
+}
+
+impl Unknown {
+    /// Returns whether both fields are filled in.
+    pub fn is_complete(&self) -> bool {
+        !self.missing.is_empty() && !self.suggest_checking.is_empty()
+    }
+}
+
+/// Whether an answer answers its question.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnswerStatus {
+    /// The answer addresses the question.
+    Answered,
+    /// The answer states that the repository does not answer the question.
+    Unknown,
+}
+
+impl AnswerStatus {
+    /// Returns the status of `answer`.
+    pub fn of(answer: &str) -> Self {
+        if parse(answer).is_some() {
+            AnswerStatus::Unknown
+        } else {
+            AnswerStatus::Answered
+        }
+    }
+}
+
+/// Parses `answer` as an unknown answer.
+///
+/// Returns `None` if the answer does not open with "Cannot determine from the repository".
+/// Fields the answer leaves out are empty. A field may continue on bulleted or indented
+/// lines; any other line ends it, so notes appended to the answer are not included.
+pub fn parse(answer: &str) -> Option<Unknown> {
+    let mut lines = answer.lines().filter(|line| !line.trim().is_empty());
+    let opening = plain(lines.next()?).to_lowercase();
+    if !opening.starts_with(UNKNOWN_OPENING) {
+        return None;
+    }
+
+    let mut unknown = Unknown {
+        missing: String::new(),
+        suggest_checking: String::new(),
+    };
+    let mut field = None;
+    for line in lines {
+        let text = plain(line);
+        if let Some(value) = strip_label(text, "missing:") {
+            unknown.missing = value.to_string();
+            field = Some(&mut unknown.missing);
+        } else if let Some(value) = strip_label(text, "suggest checking:") {
+            unknown.suggest_checking = value.to_string();
+            field = Some(&mut unknown.suggest_checking);
+        } else if let Some(value) = field
+            .as_mut()
+            .filter(|_| line.starts_with([' ', '\t', '-', '*']))
+        {
+            if !value.is_empty() {
+                value.push_str("; ");
+            }
+            value.push_str(text);
+        } else {
+            field = None;
+        }
+    }
+    Some(unknown)
+}
+
+/// Returns the rest of `text` after `label`, matched case-insensitively.
+fn strip_label<'a>(text: &'a str, label: &str) -> Option<&'a str> {
+    let prefix = text.get(..label.len())?;
+    prefix
+        .eq_ignore_ascii_case(label)
+        .then(|| text[label.len()..].trim_start_matches('*').trim())
+}
+
+/// Strips list markers and Markdown emphasis around a line.
+fn plain(line: &str) -> &str {
+    line.trim()
+        .trim_start_matches(['-', '*', '#', '>', ' '])
+        .trim_end()
+        .trim_matches('*')
+        .trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let answer = "**Cannot determine from the repository.**\n\n**Missing:** the production value of `RETRY_LIMIT`\nSuggest checking:\n- the deployment manifests\n- `kubectl get configmap`\n\n(Note: This answer was provided after reaching the maximum number of iteration attempts.)";
+        assert_eq!(
+            parse(answer),
+            Some(Unknown {
+                missing: "the production value of `RETRY_LIMIT`".to_string(),
+                suggest_checking: "the deployment manifests; `kubectl get configmap`".to_string(),
+            })
+        );
+        assert_eq!(AnswerStatus::of(answer), AnswerStatus::Unknown);
+
+        let incomplete = parse("Cannot determine from the repository.\nMissing: the schema")
+            .expect("Answer is an unknown answer");
+        assert!(!incomplete.is_complete());
+
+        let answered = "The retry limit is set in `src/config.rs`. It cannot determine from the repository which value production uses.";
+        assert_eq!(parse(answered), None);
+        assert_eq!(AnswerStatus::of(answered), AnswerStatus::Answered);
+    }
+}
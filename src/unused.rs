@@ -0,0 +1,435 @@
+//! # Unused Code Analysis
+//!
+//! This module implements `nishiogi unused`, which lists candidates for dead code and
+//! unused dependencies and has the model sanity-check them.
+//!
+//! Candidates come from local analysis rather than from the model, which tends to invent
+//! answers to this kind of question:
+//!
+//! - **Dead code**: symbols from the [`SymbolIndex`] whose name is never mentioned outside
+//!   their definitions. Entry points and tests are skipped.
+//! - **Unused dependencies**: dependencies declared in `Cargo.toml` or `package.json` (at the
+//!   root and in workspace members) that no source file refers to.
+//!
+//! The model then sees each candidate with its context and judges whether it is likely
+//! unused, e.g. spotting trait methods or public API that is used from outside the
+//! repository.
+
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    agent::AgentError,
+    github_copilot_client::Message,
+    review::ReviewModel,
+    search::collect_files,
+    symbols::{Symbol, SymbolIndex},
+    tree::find_gitignore_patterns,
+    workspace::{detect_packages, PackageKind},
+};
+
+/// Upper bound on the candidates sent to the model.
+pub const MAX_CANDIDATES: usize = 100;
+
+/// Symbol names that are used without being mentioned, such as entry points.
+const IMPLICITLY_USED: [&str; 3] = ["main", "__init__", "init"];
+
+/// Dependency tables of a Cargo manifest.
+const CARGO_SECTIONS: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// Dependency objects of a `package.json` manifest.
+const NODE_SECTIONS: [&str; 2] = ["dependencies", "devDependencies"];
+
+/// A dependency no source file refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnusedDependency {
+    /// The dependency's name as declared.
+    pub name: String,
+    /// The manifest declaring it, relative to the analyzed root.
+    pub manifest: PathBuf,
+    /// The manifest section, e.g. `dev-dependencies`.
+    pub section: String,
+}
+
+/// Something that may be unused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Candidate {
+    /// A symbol that is never mentioned outside its definitions.
+    Symbol(Symbol),
+    /// A dependency no source file refers to.
+    Dependency(UnusedDependency),
+}
+
+impl Candidate {
+    /// Describes the candidate in one line.
+    pub fn describe(&self) -> String {
+        match self {
+            Candidate::Symbol(symbol) => format!(
+                "{} {} `{}` at {}:{}",
+                if symbol.public { "public" } else { "private" },
+                symbol.kind,
+                symbol.name,
+                symbol.path.display(),
+                symbol.line
+            ),
+            Candidate::Dependency(dependency) => format!(
+                "dependency `{}` in [{}] of {}",
+                dependency.name,
+                dependency.section,
+                dependency.manifest.display()
+            ),
+        }
+    }
+}
+
+/// The model's judgement of a candidate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    /// Safe to remove as far as the model can tell.
+    Unused,
+    /// Needs a human to decide.
+    Unsure,
+    /// Used in a way the local analysis cannot see.
+    Used,
+}
+
+/// A candidate with the model's judgement.
+#[derive(Debug, Clone)]
+pub struct Assessment {
+    /// The candidate that was judged.
+    pub candidate: Candidate,
+    /// The model's verdict.
+    pub verdict: Verdict,
+    /// Why the model reached its verdict.
+    pub reason: String,
+}
+
+/// One item of the model's reply.
+#[derive(Deserialize)]
+struct RawAssessment {
+    id: usize,
+    verdict: Verdict,
+    #[serde(default)]
+    reason: String,
+}
+
+/// Finds dead code and unused dependency candidates under `root`.
+///
+/// At most [`MAX_CANDIDATES`] candidates are returned; dependencies come first because
+/// there are few of them.
+pub fn find_candidates(root: &Path) -> Vec<Candidate> {
+    let index = SymbolIndex::build(root);
+    let mut candidates: Vec<Candidate> = unused_dependencies(root, &index)
+        .into_iter()
+        .map(Candidate::Dependency)
+        .collect();
+    candidates.extend(
+        dead_symbols(&index)
+            .into_iter()
+            .map(|symbol| Candidate::Symbol(symbol.clone())),
+    );
+    candidates.truncate(MAX_CANDIDATES);
+    candidates
+}
+
+/// Returns the symbols that are never mentioned outside their definitions.
+fn dead_symbols(index: &SymbolIndex) -> Vec<&Symbol> {
+    index
+        .symbols()
+        .iter()
+        .filter(|symbol| {
+            !IMPLICITLY_USED.contains(&symbol.name.as_str()) && !symbol.name.starts_with("test")
+        })
+        .filter(|symbol| index.references(&symbol.name).is_empty())
+        .collect()
+}
+
+/// Returns the declared dependencies that no source file refers to.
+fn unused_dependencies(root: &Path, index: &SymbolIndex) -> Vec<UnusedDependency> {
+    let mut dirs = vec![PathBuf::new()];
+    dirs.extend(
+        detect_packages(root)
+            .into_iter()
+            .filter(|package| matches!(package.kind, PackageKind::Cargo | PackageKind::Node))
+            .map(|package| package.path),
+    );
+
+    let mut unused = Vec::new();
+    let mut node_sources: Option<Vec<String>> = None;
+    for dir in dirs {
+        let cargo = dir.join("Cargo.toml");
+        for (section, name) in cargo_dependencies(&root.join(&cargo)) {
+            // Crates are referred to by their name with dashes replaced
+            if index.mentions(&name.replace('-', "_")).is_empty() {
+                unused.push(UnusedDependency {
+                    name,
+                    manifest: cargo.clone(),
+                    section,
+                });
+            }
+        }
+
+        let package_json = dir.join("package.json");
+        let dependencies = node_dependencies(&root.join(&package_json));
+        if dependencies.is_empty() {
+            continue;
+        }
+        let sources = node_sources.get_or_insert_with(|| read_node_sources(root));
+        for (section, name) in dependencies {
+            let imported = sources.iter().any(|source| {
+                ['"', '\''].iter().any(|quote| {
+                    source.contains(&format!("{quote}{name}{quote}"))
+                        || source.contains(&format!("{quote}{name}/"))
+                })
+            });
+            if !imported {
+                unused.push(UnusedDependency {
+                    name,
+                    manifest: package_json.clone(),
+                    section,
+                });
+            }
+        }
+    }
+    unused
+}
+
+/// Lists the `(section, name)` pairs declared in a Cargo manifest.
+fn cargo_dependencies(path: &Path) -> Vec<(String, String)> {
+    let Some(manifest) = fs::read_to_string(path)
+        .ok()
+        .and_then(|content| content.parse::<toml::Table>().ok())
+    else {
+        return Vec::new();
+    };
+    CARGO_SECTIONS
+        .iter()
+        .filter_map(|section| Some((*section, manifest.get(*section)?.as_table()?)))
+        .flat_map(|(section, table)| {
+            table
+                .keys()
+                .map(move |name| (section.to_string(), name.clone()))
+        })
+        .collect()
+}
+
+/// Lists the `(section, name)` pairs declared in a `package.json` manifest.
+fn node_dependencies(path: &Path) -> Vec<(String, String)> {
+    let Some(manifest) = fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<Value>(&content).ok())
+    else {
+        return Vec::new();
+    };
+    let scripts = manifest
+        .get("scripts")
+        .map(Value::to_string)
+        .unwrap_or_default();
+    NODE_SECTIONS
+        .iter()
+        .filter_map(|section| Some((*section, manifest.get(*section)?.as_object()?)))
+        .flat_map(|(section, object)| {
+            object
+                .keys()
+                .map(move |name| (section.to_string(), name.clone()))
+        })
+        // Tools run from npm scripts are used even though no source imports them
+        .filter(|(_, name)| !scripts.contains(name.as_str()))
+        .collect()
+}
+
+/// Reads the JavaScript and TypeScript sources under `root`.
+fn read_node_sources(root: &Path) -> Vec<String> {
+    let ignore = find_gitignore_patterns(root).unwrap_or_default();
+    let mut files = Vec::new();
+    collect_files(root, root, &ignore, &mut files);
+    files
+        .iter()
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| {
+                    matches!(
+                        ext,
+                        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "vue" | "svelte"
+                    )
+                })
+        })
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .collect()
+}
+
+/// Asks the model to judge every candidate.
+///
+/// Candidates the model does not judge are kept as `Verdict::Unsure`.
+///
+/// # Errors
+///
+/// Returns an `AgentError` if the request to the model fails.
+pub async fn sanity_check<M: ReviewModel>(
+    model: &mut M,
+    root: &Path,
+    candidates: Vec<Candidate>,
+) -> Result<Vec<Assessment>, AgentError> {
+    let reply = model
+        .complete(sanity_check_messages(root, &candidates))
+        .await?;
+    let raw = parse_assessments(&reply);
+
+    let mut assessments: Vec<Assessment> = candidates
+        .into_iter()
+        .enumerate()
+        .map(|(index, candidate)| {
+            let (verdict, reason) = raw
+                .iter()
+                .find(|assessment| assessment.id == index + 1)
+                .map_or(
+                    (Verdict::Unsure, "Not assessed by the model".to_string()),
+                    |a| (a.verdict, a.reason.clone()),
+                );
+            Assessment {
+                candidate,
+                verdict,
+                reason,
+            }
+        })
+        .collect();
+    assessments.sort_by_key(|assessment| match assessment.verdict {
+        Verdict::Unused => 0,
+        Verdict::Unsure => 1,
+        Verdict::Used => 2,
+    });
+    Ok(assessments)
+}
+
+/// Renders the assessments grouped by verdict.
+pub fn render_report(assessments: &[Assessment]) -> String {
+    let mut report = String::new();
+    for (verdict, heading) in [
+        (Verdict::Unused, "Likely unused"),
+        (Verdict::Unsure, "Needs a closer look"),
+        (Verdict::Used, "Probably used"),
+    ] {
+        let group: Vec<&Assessment> = assessments
+            .iter()
+            .filter(|assessment| assessment.verdict == verdict)
+            .collect();
+        if group.is_empty() {
+            continue;
+        }
+        let _ = writeln!(report, "{heading} ({}):", group.len());
+        for assessment in group {
+            let _ = writeln!(report, "  - {}", assessment.candidate.describe());
+            if !assessment.reason.is_empty() {
+                let _ = writeln!(report, "    {}", assessment.reason.trim());
+            }
+        }
+        report.push('\n');
+    }
+    report
+}
+
+/// Builds the request asking the model to judge the candidates.
+fn sanity_check_messages(root: &Path, candidates: &[Candidate]) -> Vec<Message> {
+    let mut listing = String::new();
+    for (index, candidate) in candidates.iter().enumerate() {
+        let _ = writeln!(listing, "{}. {}", index + 1, candidate.describe());
+        if let Candidate::Symbol(symbol) = candidate
+            && let Some(line) = fs::read_to_string(root.join(&symbol.path))
+                .ok()
+                .and_then(|content| content.lines().nth(symbol.line - 1).map(str::to_string))
+        {
+            let _ = writeln!(listing, "   {}", line.trim());
+        }
+    }
+
+    vec![
+        Message {
+            role: "system".to_string(),
+            content: "You are a code reviewer checking the results of a static analysis for unused code and dependencies. The analysis only matches names, so it misses uses through trait implementations, macros, reflection, FFI, configuration files, and callers outside the repository.".to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: format!(
+                "No other mention of these items was found in the repository:\n\n{listing}\nFor each item, judge whether it is really unused. Respond with JSON only, in this format:\n\n[{{\"id\": 1, \"verdict\": \"unused|unsure|used\", \"reason\": \"one sentence\"}}]"
+            ),
+        },
+    ]
+}
+
+/// Extracts the assessments from the model's reply, tolerating text around the JSON.
+fn parse_assessments(reply: &str) -> Vec<RawAssessment> {
+    let (Some(start), Some(end)) = (reply.find('['), reply.rfind(']')) else {
+        return Vec::new();
+    };
+    reply
+        .get(start..=end)
+        .and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    struct ScriptedModel(&'static str);
+
+    #[async_trait]
+    impl ReviewModel for ScriptedModel {
+        async fn complete(&mut self, _messages: Vec<Message>) -> Result<String, AgentError> {
+            Ok(self.0.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_candidates_are_found_and_checked() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[package]\nname = \"demo\"\n\n[dependencies]\nserde-json = \"1\"\nregex = \"1\"\n",
+        )
+        .expect("Failed to write manifest");
+        fs::write(
+            root.join("main.rs"),
+            "fn main() {\n    helper();\n    let _ = serde_json::json!({});\n}\nfn helper() {}\nfn orphan() {}\n#[test]\nfn test_orphan() {}\n",
+        )
+        .expect("Failed to write source");
+
+        let candidates = find_candidates(root);
+        assert_eq!(candidates.len(), 2);
+        assert!(matches!(
+            &candidates[0],
+            Candidate::Dependency(dependency) if dependency.name == "regex"
+        ));
+        assert!(matches!(
+            &candidates[1],
+            Candidate::Symbol(symbol) if symbol.name == "orphan"
+        ));
+
+        let mut model = ScriptedModel(r#"[{"id": 2, "verdict": "unused", "reason": "Dead."}]"#);
+        let assessments = sanity_check(&mut model, root, candidates)
+            .await
+            .expect("Failed to check candidates");
+        assert_eq!(assessments[0].verdict, Verdict::Unused);
+        assert_eq!(assessments[1].verdict, Verdict::Unsure);
+
+        let report = render_report(&assessments);
+        assert!(report.starts_with(
+            "Likely unused (1):\n  - private function `orphan` at main.rs:6\n    Dead.\n"
+        ));
+        assert!(report.contains(
+            "Needs a closer look (1):\n  - dependency `regex` in [dependencies] of Cargo.toml\n"
+        ));
+    }
+}
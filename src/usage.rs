@@ -0,0 +1,155 @@
+//! # Usage Examples
+//!
+//! This module finds tests and examples for "how do I use X" questions. Code that calls
+//! `X` the way it is meant to be called usually answers such a question better than the
+//! implementation of `X`, so the files under test and example directories that mention it
+//! are read first (see [`usage_subject`] and [`find_usage_examples`]).
+//!
+//! Test and example files are recognized by their path only: Rust unit tests living next to
+//! the code they test are not found this way, but the defining file is usually read anyway.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use regex::Regex;
+
+use crate::symbols::SymbolIndex;
+
+/// Most test and example files read for a usage question.
+pub const MAX_USAGE_EXAMPLES: usize = 3;
+
+/// A usage question, capturing what it asks about.
+static USAGE_QUESTION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)(?:how\s+(?:do\s+(?:i|we|you)\s+|should\s+(?:i|we)\s+|can\s+(?:i|we)\s+|to\s+)(?:use|call|invoke|instantiate|construct|create|configure)|(?:examples?|usage)\s+(?:of|for)|example\s+using)\s+(?:the\s+|an?\s+)?`?([A-Za-z_][\w:.]*)",
+    )
+    .expect("Invalid usage question pattern")
+});
+
+/// Directories holding tests and examples.
+const EXAMPLE_DIRS: [&str; 7] = [
+    "tests",
+    "test",
+    "examples",
+    "example",
+    "__tests__",
+    "spec",
+    "benches",
+];
+
+/// Returns the identifier a "how do I use X" question asks about, if it is one.
+///
+/// For paths such as `Agent::new` or `client.fetch`, the last segment is returned since
+/// that is the name the index knows.
+pub fn usage_subject(question: &str) -> Option<String> {
+    let captures = USAGE_QUESTION.captures(question)?;
+    let subject = captures[1]
+        .trim_end_matches(['.', ':'])
+        .rsplit(['.', ':'])
+        .next()?;
+    (!subject.is_empty()).then(|| subject.to_string())
+}
+
+/// Returns whether `path` is a test or an example.
+pub fn is_example_path(path: &Path) -> bool {
+    let in_example_dir = path.parent().is_some_and(|dir| {
+        dir.components().any(|component| {
+            EXAMPLE_DIRS.contains(&component.as_os_str().to_string_lossy().as_ref())
+        })
+    });
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stem = name.split('.').next().unwrap_or_default();
+    in_example_dir
+        || stem.starts_with("test_")
+        || stem.ends_with("_test")
+        || stem.ends_with("_tests")
+        || name.contains(".test.")
+        || name.contains(".spec.")
+}
+
+/// Returns up to `limit` test and example files mentioning `name`, those mentioning it
+/// most first.
+pub fn find_usage_examples(index: &SymbolIndex, name: &str, limit: usize) -> Vec<PathBuf> {
+    let mut counts: HashMap<&Path, usize> = HashMap::new();
+    for reference in index.mentions(name) {
+        if is_example_path(&reference.path) {
+            *counts.entry(reference.path.as_path()).or_default() += 1;
+        }
+    }
+    let mut files: Vec<(&Path, usize)> = counts.into_iter().collect();
+    files.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    files
+        .into_iter()
+        .take(limit)
+        .map(|(path, _)| path.to_path_buf())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_usage_subject() {
+        assert_eq!(
+            usage_subject("How do I use SymbolIndex to find references?"),
+            Some("SymbolIndex".to_string())
+        );
+        assert_eq!(
+            usage_subject("how to call `Agent::with_planner`?"),
+            Some("with_planner".to_string())
+        );
+        assert_eq!(
+            usage_subject("Is there an example of parse_plan"),
+            Some("parse_plan".to_string())
+        );
+        assert_eq!(usage_subject("Why does parse_plan fail?"), None);
+    }
+
+    #[test]
+    fn test_find_usage_examples() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let root = dir.path();
+        fs::create_dir_all(root.join("src")).expect("Failed to create src");
+        fs::create_dir_all(root.join("tests")).expect("Failed to create tests");
+        fs::create_dir_all(root.join("examples")).expect("Failed to create examples");
+        fs::write(
+            root.join("src/lib.rs"),
+            "pub fn connect() {}\nfn retry() { connect(); }\n",
+        )
+        .expect("Failed to write lib");
+        fs::write(
+            root.join("tests/client.rs"),
+            "fn test() {\n    connect();\n}\n",
+        )
+        .expect("Failed to write test");
+        fs::write(
+            root.join("examples/demo.rs"),
+            "fn main() {\n    connect();\n    connect();\n}\n",
+        )
+        .expect("Failed to write example");
+
+        let index = SymbolIndex::build(root);
+        assert_eq!(
+            find_usage_examples(&index, "connect", MAX_USAGE_EXAMPLES),
+            vec![
+                PathBuf::from("examples/demo.rs"),
+                PathBuf::from("tests/client.rs")
+            ]
+        );
+        assert_eq!(find_usage_examples(&index, "connect", 1).len(), 1);
+        assert!(is_example_path(Path::new("pkg/client_test.go")));
+        assert!(is_example_path(Path::new("web/button.spec.tsx")));
+        assert!(!is_example_path(Path::new("src/testing.rs")));
+    }
+}
@@ -0,0 +1,230 @@
+//! # Cache Warm-up
+//!
+//! This module implements `nishiogi warm`, which precomputes what the first question in a
+//! repository would otherwise compute on the spot: the directory tree and the symbol index.
+//! Both are stored in the command cache of the repository database together with a
+//! fingerprint of the files they were computed from, and [`cached_tree`] and
+//! [`cached_symbol_index`] return them as long as the fingerprint still matches.
+//!
+//! Warming is cheap when nothing changed, so it can run from `post-checkout` and
+//! `post-merge` hooks.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::PROJECT_DIR,
+    db::{CacheKind, Database, DbError, DB_FILE, INDEX_META_PREFIX},
+    search::collect_files,
+    symbols::SymbolIndex,
+    tree::{find_gitignore_patterns, find_repo_root, generate_tree},
+};
+
+/// Prefix of the cache key of a directory's tree.
+const TREE_KEY: &str = "warm.tree:";
+
+/// Prefix of the cache key of a directory's symbol index.
+const SYMBOLS_KEY: &str = "warm.symbols:";
+
+/// A cached value and the fingerprint of the files it was computed from.
+#[derive(Serialize, Deserialize)]
+struct Warmed<T> {
+    fingerprint: String,
+    value: T,
+}
+
+/// What [`warm`] computed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WarmReport {
+    /// Fingerprint of the files under the warmed directory.
+    pub fingerprint: String,
+    /// Number of files under the directory.
+    pub files: usize,
+    /// Number of indexed definitions.
+    pub symbols: usize,
+    /// Whether the cache was already up to date.
+    pub up_to_date: bool,
+}
+
+/// Returns a fingerprint of the files under `root`, changing whenever a file is added,
+/// removed, resized, or modified.
+///
+/// Also returns the number of files. Ignored files and nishiogi's own project directory
+/// are skipped.
+pub fn fingerprint(root: &Path) -> (String, usize) {
+    let ignore = find_gitignore_patterns(root).unwrap_or_default();
+    let mut files = Vec::new();
+    collect_files(root, root, &ignore, &mut files);
+    // The database itself changes whenever the cache is written
+    files.retain(|file| !file.starts_with(root.join(PROJECT_DIR)));
+    let mut hasher = Sha256::new();
+    for file in &files {
+        let Ok(metadata) = fs::metadata(file) else {
+            continue;
+        };
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |duration| duration.as_nanos());
+        let path = file.strip_prefix(root).unwrap_or(file);
+        hasher.update(format!(
+            "{}\0{}\0{modified}\n",
+            path.display(),
+            metadata.len()
+        ));
+    }
+    (format!("{:x}", hasher.finalize()), files.len())
+}
+
+/// Computes the tree and the symbol index of `root` and stores them in `db`, unless the
+/// cached ones are still current.
+///
+/// # Errors
+///
+/// Returns a `DbError` if the cache cannot be read or written.
+pub fn warm(root: &Path, db: &Database) -> Result<WarmReport, DbError> {
+    let root = canonical(root);
+    let (fingerprint, files) = fingerprint(&root);
+    if let Some(index) = load::<SymbolIndex>(db, SYMBOLS_KEY, &root, &fingerprint)?
+        && load::<String>(db, TREE_KEY, &root, &fingerprint)?.is_some()
+    {
+        return Ok(WarmReport {
+            fingerprint,
+            files,
+            symbols: index.symbols().len(),
+            up_to_date: true,
+        });
+    }
+
+    let tree = generate_tree(&root, "", None, None);
+    store(db, TREE_KEY, &root, &fingerprint, &tree)?;
+    let index = SymbolIndex::build(&root);
+    store(db, SYMBOLS_KEY, &root, &fingerprint, &index)?;
+    db.set_meta(&format!("{INDEX_META_PREFIX}fingerprint"), &fingerprint)?;
+    db.set_meta(
+        &format!("{INDEX_META_PREFIX}updated_at"),
+        &Utc::now().to_rfc3339(),
+    )?;
+    Ok(WarmReport {
+        fingerprint,
+        files,
+        symbols: index.symbols().len(),
+        up_to_date: false,
+    })
+}
+
+/// Returns the tree of `dir` computed by [`warm`], if the files have not changed since.
+pub fn cached_tree(dir: &Path) -> Option<String> {
+    cached(TREE_KEY, dir)
+}
+
+/// Returns the symbol index of `dir` computed by [`warm`], if the files have not changed
+/// since.
+pub fn cached_symbol_index(dir: &Path) -> Option<SymbolIndex> {
+    cached(SYMBOLS_KEY, dir)
+}
+
+/// Looks up a warmed value of `dir` in the database of its repository, without creating
+/// the database if there is none.
+fn cached<T: for<'de> Deserialize<'de>>(prefix: &str, dir: &Path) -> Option<T> {
+    let dir = canonical(dir);
+    let repo = find_repo_root(&dir).unwrap_or_else(|| dir.clone());
+    let path = repo.join(PROJECT_DIR).join(DB_FILE);
+    if !path.is_file() {
+        return None;
+    }
+    let db = Database::open(&path).ok()?;
+    // Skip fingerprinting the files when nothing was warmed
+    db.cache_get(CacheKind::Command, &key(prefix, &dir))
+        .ok()
+        .flatten()?;
+    let (fingerprint, _) = fingerprint(&dir);
+    load(&db, prefix, &dir, &fingerprint).ok()?
+}
+
+/// Reads the value stored under `prefix` for `root` if it has the given fingerprint.
+fn load<T: for<'de> Deserialize<'de>>(
+    db: &Database,
+    prefix: &str,
+    root: &Path,
+    fingerprint: &str,
+) -> Result<Option<T>, DbError> {
+    let Some(text) = db.cache_get(CacheKind::Command, &key(prefix, root))? else {
+        return Ok(None);
+    };
+    Ok(serde_json::from_str::<Warmed<T>>(&text)
+        .ok()
+        .filter(|warmed| warmed.fingerprint == fingerprint)
+        .map(|warmed| warmed.value))
+}
+
+/// Stores `value` under `prefix` for `root`, replacing the previous value.
+fn store<T: Serialize>(
+    db: &Database,
+    prefix: &str,
+    root: &Path,
+    fingerprint: &str,
+    value: &T,
+) -> Result<(), DbError> {
+    let warmed = Warmed {
+        fingerprint: fingerprint.to_string(),
+        value,
+    };
+    let text = serde_json::to_string(&warmed).unwrap_or_default();
+    db.cache_put(CacheKind::Command, &key(prefix, root), &text)
+}
+
+fn key(prefix: &str, root: &Path) -> String {
+    format!("{prefix}{}", root.display())
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_warm() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let root = dir.path();
+        fs::create_dir_all(root.join("src")).expect("Failed to create src");
+        fs::write(root.join("src/lib.rs"), "pub fn connect() {}\n").expect("Failed to write lib");
+        let db = Database::open_in_memory().expect("Failed to open database");
+
+        let report = warm(root, &db).expect("Failed to warm");
+        assert_eq!((report.files, report.symbols), (1, 1));
+        assert!(!report.up_to_date);
+        assert!(warm(root, &db).expect("Failed to warm again").up_to_date);
+
+        // A changed file invalidates the cached values
+        fs::write(
+            root.join("src/lib.rs"),
+            "pub fn connect() {}\npub fn close() {}\n",
+        )
+        .expect("Failed to rewrite lib");
+        let (fingerprint, _) = fingerprint(&canonical(root));
+        assert_ne!(fingerprint, report.fingerprint);
+        let root = canonical(root);
+        assert!(load::<String>(&db, TREE_KEY, &root, &fingerprint)
+            .expect("Failed to read cache")
+            .is_none());
+        let report = warm(&root, &db).expect("Failed to rewarm");
+        assert_eq!(report.symbols, 2);
+        let tree =
+            load::<String>(&db, TREE_KEY, &root, &fingerprint).expect("Failed to read cache");
+        assert!(tree.is_some_and(|tree| tree.contains("lib.rs")));
+    }
+}
@@ -0,0 +1,360 @@
+//! # Workspace Detection
+//!
+//! This module finds the member packages of a monorepo so questions can be scoped to one of
+//! them. The following workspace layouts are recognized at the repository root:
+//!
+//! - Cargo workspaces (`[workspace] members` in `Cargo.toml`)
+//! - npm/yarn workspaces (`workspaces` in `package.json`)
+//! - pnpm workspaces (`packages` in `pnpm-workspace.yaml`)
+//! - Go workspaces (`use` directives in `go.work`)
+//!
+//! Member patterns may contain `*` wildcards within a path segment, e.g. `crates/*`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use serde_json::Value;
+
+/// The kind of workspace a package was found in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageKind {
+    /// A Cargo workspace member.
+    Cargo,
+    /// An npm, yarn, or pnpm workspace member.
+    Node,
+    /// A Go workspace module.
+    Go,
+}
+
+/// A member package of a monorepo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    /// The package name from its manifest, or its directory name if the manifest has none.
+    pub name: String,
+    /// Path of the package directory relative to the repository root.
+    pub path: PathBuf,
+    /// The workspace the package belongs to.
+    pub kind: PackageKind,
+}
+
+/// Detects all workspace member packages under `root`.
+pub fn detect_packages(root: &Path) -> Vec<Package> {
+    let mut packages = Vec::new();
+    packages.extend(cargo_members(root));
+    packages.extend(node_members(root));
+    packages.extend(go_members(root));
+    packages
+}
+
+/// Finds the package called `name`, also accepting its directory path.
+pub fn find_package<'a>(packages: &'a [Package], name: &str) -> Option<&'a Package> {
+    packages
+        .iter()
+        .find(|package| package.name == name)
+        .or_else(|| {
+            let wanted = Path::new(name.trim_end_matches('/'));
+            packages.iter().find(|package| package.path == wanted)
+        })
+}
+
+fn cargo_members(root: &Path) -> Vec<Package> {
+    let Some(manifest) = read_toml(&root.join("Cargo.toml")) else {
+        return Vec::new();
+    };
+    let patterns = string_array(manifest.get("workspace").and_then(|w| w.get("members")));
+    expand_members(root, &patterns, "Cargo.toml")
+        .into_iter()
+        .map(|path| {
+            let name = read_toml(&root.join(&path).join("Cargo.toml"))
+                .and_then(|m| {
+                    m.get("package")?
+                        .get("name")?
+                        .as_str()
+                        .map(ToString::to_string)
+                })
+                .unwrap_or_else(|| dir_name(&path));
+            Package {
+                name,
+                path,
+                kind: PackageKind::Cargo,
+            }
+        })
+        .collect()
+}
+
+fn node_members(root: &Path) -> Vec<Package> {
+    let mut patterns = Vec::new();
+    if let Some(manifest) = read_json(&root.join("package.json")) {
+        let workspaces = manifest.get("workspaces");
+        let list = workspaces
+            .and_then(|w| w.get("packages"))
+            .or(workspaces)
+            .and_then(Value::as_array);
+        patterns.extend(
+            list.into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(ToString::to_string),
+        );
+    }
+    if let Ok(content) = fs::read_to_string(root.join("pnpm-workspace.yaml")) {
+        patterns.extend(pnpm_patterns(&content));
+    }
+
+    expand_members(root, &patterns, "package.json")
+        .into_iter()
+        .map(|path| {
+            let name = read_json(&root.join(&path).join("package.json"))
+                .and_then(|m| m.get("name")?.as_str().map(ToString::to_string))
+                .unwrap_or_else(|| dir_name(&path));
+            Package {
+                name,
+                path,
+                kind: PackageKind::Node,
+            }
+        })
+        .collect()
+}
+
+fn go_members(root: &Path) -> Vec<Package> {
+    let Ok(content) = fs::read_to_string(root.join("go.work")) else {
+        return Vec::new();
+    };
+    go_work_dirs(&content)
+        .into_iter()
+        .map(|dir| PathBuf::from(dir.trim_start_matches("./")))
+        .filter(|path| root.join(path).join("go.mod").is_file())
+        .map(|path| {
+            let name = fs::read_to_string(root.join(&path).join("go.mod"))
+                .ok()
+                .and_then(|content| {
+                    content.lines().find_map(|line| {
+                        line.trim()
+                            .strip_prefix("module ")
+                            .map(|module| module.trim().to_string())
+                    })
+                })
+                .unwrap_or_else(|| dir_name(&path));
+            Package {
+                name,
+                path,
+                kind: PackageKind::Go,
+            }
+        })
+        .collect()
+}
+
+/// Extracts the package patterns from a `pnpm-workspace.yaml` file.
+fn pnpm_patterns(content: &str) -> Vec<String> {
+    let mut patterns = Vec::new();
+    let mut in_packages = false;
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if !line.starts_with([' ', '\t', '-']) && !trimmed.is_empty() {
+            in_packages = trimmed == "packages:";
+            continue;
+        }
+        if in_packages && let Some(item) = trimmed.strip_prefix('-') {
+            let item = item.trim().trim_matches(|c| c == '\'' || c == '"');
+            if !item.is_empty() && !item.starts_with('!') {
+                patterns.push(item.to_string());
+            }
+        }
+    }
+    patterns
+}
+
+/// Extracts the module directories from the `use` directives of a `go.work` file.
+fn go_work_dirs(content: &str) -> Vec<String> {
+    let mut dirs = Vec::new();
+    let mut in_block = false;
+    for line in content.lines() {
+        let line = line.split("//").next().unwrap_or("").trim();
+        if in_block {
+            if line == ")" {
+                in_block = false;
+            } else if !line.is_empty() {
+                dirs.push(line.to_string());
+            }
+        } else if let Some(rest) = line.strip_prefix("use") {
+            let rest = rest.trim();
+            if rest == "(" {
+                in_block = true;
+            } else if !rest.is_empty() {
+                dirs.push(rest.to_string());
+            }
+        }
+    }
+    dirs
+}
+
+/// Expands member patterns to directories (relative to `root`) containing `manifest`.
+fn expand_members(root: &Path, patterns: &[String], manifest: &str) -> Vec<PathBuf> {
+    let mut members = Vec::new();
+    for pattern in patterns {
+        let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+        let mut candidates = vec![PathBuf::new()];
+        for segment in pattern.split('/') {
+            candidates = candidates
+                .iter()
+                .flat_map(|dir| expand_segment(root, dir, segment))
+                .collect();
+        }
+        for candidate in candidates {
+            if root.join(&candidate).join(manifest).is_file() && !members.contains(&candidate) {
+                members.push(candidate);
+            }
+        }
+    }
+    members.sort();
+    members
+}
+
+/// Expands one pattern segment within `dir`, matching `*` against directory names.
+fn expand_segment(root: &Path, dir: &Path, segment: &str) -> Vec<PathBuf> {
+    if !segment.contains('*') {
+        return vec![dir.join(segment)];
+    }
+    let pattern = format!("^{}$", regex::escape(segment).replace(r"\*", ".*"));
+    let Ok(regex) = Regex::new(&pattern) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(root.join(dir)) else {
+        return Vec::new();
+    };
+    let mut matches: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| regex.is_match(name))
+        .map(|name| dir.join(name))
+        .collect();
+    matches.sort();
+    matches
+}
+
+fn read_toml(path: &Path) -> Option<toml::Table> {
+    fs::read_to_string(path).ok()?.parse().ok()
+}
+
+fn read_json(path: &Path) -> Option<Value> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+fn string_array(value: Option<&toml::Value>) -> Vec<String> {
+    value
+        .and_then(toml::Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(toml::Value::as_str)
+        .map(ToString::to_string)
+        .collect()
+}
+
+fn dir_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().expect("Path has a parent"))
+            .expect("Failed to create directory");
+        fs::write(path, content).expect("Failed to write file");
+    }
+
+    #[test]
+    fn test_cargo_workspace() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        write(
+            root,
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\", \"tools/cli\"]\n",
+        );
+        write(
+            root,
+            "crates/core/Cargo.toml",
+            "[package]\nname = \"app-core\"\n",
+        );
+        write(
+            root,
+            "crates/web/Cargo.toml",
+            "[package]\nname = \"app-web\"\n",
+        );
+        write(root, "crates/notes/README.md", "not a crate");
+        write(
+            root,
+            "tools/cli/Cargo.toml",
+            "[package]\nname = \"app-cli\"\n",
+        );
+
+        let packages = detect_packages(root);
+        let names: Vec<&str> = packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["app-core", "app-web", "app-cli"]);
+        assert_eq!(packages[0].path, PathBuf::from("crates/core"));
+        assert_eq!(packages[0].kind, PackageKind::Cargo);
+    }
+
+    #[test]
+    fn test_node_and_pnpm_workspaces() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        write(
+            root,
+            "package.json",
+            r#"{"workspaces": {"packages": ["apps/*"]}}"#,
+        );
+        write(root, "apps/site/package.json", r#"{"name": "@acme/site"}"#);
+        write(
+            root,
+            "pnpm-workspace.yaml",
+            "packages:\n  - 'libs/*'\n  - '!libs/ignored'\ncatalog:\n  - nope\n",
+        );
+        write(root, "libs/ui/package.json", r#"{"name": "@acme/ui"}"#);
+
+        let packages = detect_packages(root);
+        let names: Vec<&str> = packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["@acme/site", "@acme/ui"]);
+    }
+
+    #[test]
+    fn test_go_workspace() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        write(
+            root,
+            "go.work",
+            "go 1.22\n\nuse (\n\t./api // server\n\t./worker\n)\n",
+        );
+        write(root, "api/go.mod", "module example.com/api\n");
+        write(root, "worker/go.mod", "module example.com/worker\n");
+
+        let packages = detect_packages(root);
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].name, "example.com/api");
+        assert_eq!(packages[1].path, PathBuf::from("worker"));
+    }
+
+    #[test]
+    fn test_find_package_by_name_or_path() {
+        let packages = vec![Package {
+            name: "app-core".to_string(),
+            path: PathBuf::from("crates/core"),
+            kind: PackageKind::Cargo,
+        }];
+        assert!(find_package(&packages, "app-core").is_some());
+        assert!(find_package(&packages, "crates/core/").is_some());
+        assert!(find_package(&packages, "missing").is_none());
+    }
+}